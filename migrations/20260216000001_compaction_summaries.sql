@@ -0,0 +1,12 @@
+-- Persisted compaction summaries. The compactor swaps summaries into the
+-- in-memory history; these rows let context survive a restart and give us a
+-- bounded record of what has been compacted per channel.
+CREATE TABLE IF NOT EXISTS compaction_summaries (
+    id TEXT PRIMARY KEY,
+    channel_id TEXT NOT NULL,
+    summary TEXT NOT NULL,
+    turns_covered INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_compaction_summaries_channel ON compaction_summaries(channel_id, created_at);
@@ -0,0 +1,7 @@
+-- Active OpenCode session per channel, so a restart can resume the
+-- conversation instead of starting a fresh session.
+CREATE TABLE IF NOT EXISTS channel_sessions (
+    channel_id TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,16 @@
+-- Every answer given to an OpenCode permission request, and who gave it
+-- (a user reply, a timeout, or auto-approval).
+CREATE TABLE IF NOT EXISTS opencode_permission_audit (
+    id TEXT PRIMARY KEY,
+    channel_id TEXT,
+    worker_id TEXT NOT NULL,
+    permission_id TEXT NOT NULL,
+    permission TEXT,
+    patterns TEXT NOT NULL,
+    reply TEXT NOT NULL,
+    source TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_opencode_permission_audit_channel
+    ON opencode_permission_audit(channel_id, created_at);
@@ -0,0 +1,2 @@
+-- Index on sender for per-user history lookups (load_by_sender).
+CREATE INDEX IF NOT EXISTS idx_messages_sender_time ON conversation_messages(sender_id, created_at);
@@ -0,0 +1,17 @@
+-- OpenCode tool calls, one row per call. Upserted as the call moves through
+-- pending -> running -> completed/error.
+CREATE TABLE IF NOT EXISTS tool_invocations (
+    id TEXT PRIMARY KEY,
+    session_id TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    call_id TEXT NOT NULL,
+    tool TEXT NOT NULL,
+    status TEXT NOT NULL,
+    input TEXT,                      -- JSON tool arguments
+    output TEXT,                     -- tool output, or the error message
+    started_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    completed_at TIMESTAMP,
+    UNIQUE (session_id, call_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_tool_invocations_channel ON tool_invocations(channel_id, started_at);
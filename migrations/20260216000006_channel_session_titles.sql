@@ -0,0 +1,3 @@
+-- Title of the channel's active OpenCode session, mirrored locally so it can
+-- be shown without asking the server.
+ALTER TABLE channel_sessions ADD COLUMN title TEXT;
@@ -0,0 +1,4 @@
+-- Token count of each message's content: reported by OpenCode when it
+-- provides one, otherwise estimated with the configured tokenizer. NULL for
+-- rows written before this column existed.
+ALTER TABLE conversation_messages ADD COLUMN token_count INTEGER;
@@ -0,0 +1,4 @@
+-- Marks messages Spacebot injected (context, compaction notices) rather than
+-- ones a user or the model wrote. Hidden from the visible transcript and
+-- exports. Existing rows are all real messages.
+ALTER TABLE conversation_messages ADD COLUMN is_synthetic INTEGER NOT NULL DEFAULT 0;
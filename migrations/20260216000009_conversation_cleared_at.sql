@@ -0,0 +1,4 @@
+-- Set when a channel reset soft-deletes rows: they're kept for auditing but
+-- left out of the live context, transcripts, and exports.
+ALTER TABLE conversation_messages ADD COLUMN cleared_at TIMESTAMP;
+ALTER TABLE compaction_summaries ADD COLUMN cleared_at TIMESTAMP;
@@ -0,0 +1,9 @@
+-- "Always" answers to OpenCode permission requests, kept per channel so they
+-- outlive the session they were given in.
+CREATE TABLE IF NOT EXISTS permission_grants (
+    channel_id TEXT NOT NULL,
+    permission TEXT NOT NULL,
+    pattern TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (channel_id, permission, pattern)
+);
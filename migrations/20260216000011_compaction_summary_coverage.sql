@@ -0,0 +1,6 @@
+-- The exact messages a compaction summary replaces, by message id. A count
+-- alone is ambiguous once messages land in the same second as the summary or
+-- summaries are merged. NULL for summaries of in-memory history, which has
+-- no stored message ids.
+ALTER TABLE compaction_summaries ADD COLUMN covered_from TEXT;
+ALTER TABLE compaction_summaries ADD COLUMN covered_to TEXT;
@@ -0,0 +1,17 @@
+-- Message metadata is queried with SQLite's JSON functions, so reject writes
+-- that aren't JSON. Encrypted metadata (`enc:` prefix) is opaque to SQL and
+-- allowed through. The filtered key varies per query, so there's no fixed
+-- expression to index; lookups go through the (channel_id, created_at) index.
+CREATE TRIGGER conversation_messages_metadata_json_insert
+BEFORE INSERT ON conversation_messages
+WHEN NEW.metadata IS NOT NULL AND NOT json_valid(NEW.metadata) AND NEW.metadata NOT LIKE 'enc:%'
+BEGIN
+    SELECT RAISE(ABORT, 'conversation_messages.metadata must be JSON');
+END;
+
+CREATE TRIGGER conversation_messages_metadata_json_update
+BEFORE UPDATE OF metadata ON conversation_messages
+WHEN NEW.metadata IS NOT NULL AND NOT json_valid(NEW.metadata) AND NEW.metadata NOT LIKE 'enc:%'
+BEGIN
+    SELECT RAISE(ABORT, 'conversation_messages.metadata must be JSON');
+END;
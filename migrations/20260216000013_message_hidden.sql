@@ -0,0 +1,3 @@
+-- Moderator-hidden messages. Hidden rows stay for audit and export but are
+-- left out of context. Existing rows are visible.
+ALTER TABLE conversation_messages ADD COLUMN is_hidden INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- The OpenCode agent each channel's prompts are sent to. Channels without a
+-- row use the server's default agent.
+CREATE TABLE IF NOT EXISTS channel_agents (
+    channel_id TEXT PRIMARY KEY,
+    agent TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,3 @@
+-- Replies replaced by a regenerated one. They are hidden from context like
+-- moderator-hidden rows, but keep their content for audit and export.
+ALTER TABLE conversation_messages ADD COLUMN superseded_at TIMESTAMP;
@@ -0,0 +1,7 @@
+-- Runtime overrides of which channels the bot engages in. Channels without a
+-- row follow the configured allowlist, denylist, and default.
+CREATE TABLE IF NOT EXISTS channel_access (
+    channel_id TEXT PRIMARY KEY,
+    rule TEXT NOT NULL CHECK (rule IN ('allow', 'deny')),
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
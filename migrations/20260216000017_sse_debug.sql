@@ -0,0 +1,11 @@
+-- Raw OpenCode SSE envelopes, written from the in-memory debug buffer when a
+-- turn fails, for reproducing what the server sent.
+CREATE TABLE IF NOT EXISTS sse_debug (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    channel_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    properties TEXT NOT NULL,
+    received_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sse_debug_channel ON sse_debug(channel_id, id);
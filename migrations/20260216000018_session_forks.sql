@@ -0,0 +1,12 @@
+-- Forks of a channel's OpenCode session. Each fork is a child session mapped
+-- to a sub-channel (its own row in channel_sessions), so it can be continued,
+-- abandoned, or reaped without touching the main thread.
+CREATE TABLE IF NOT EXISTS session_forks (
+    fork_channel_id TEXT PRIMARY KEY,
+    parent_channel_id TEXT NOT NULL,
+    parent_session_id TEXT NOT NULL,
+    session_id TEXT NOT NULL,
+    created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_forks_parent ON session_forks(parent_channel_id);
@@ -0,0 +1,14 @@
+-- Chat attachments cached on disk by content hash, so a file sent again is
+-- reused instead of downloaded and inlined again. `source_url` is the
+-- platform URL it was last downloaded from.
+CREATE TABLE IF NOT EXISTS attachment_cache (
+    hash TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    mime TEXT NOT NULL,
+    size_bytes INTEGER NOT NULL,
+    source_url TEXT,
+    created_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_attachment_cache_source ON attachment_cache(source_url);
@@ -0,0 +1,7 @@
+-- Per-channel system prompt templates for OpenCode workers, set with
+-- `!persona`. Channels without a row use `default_system_prompt`.
+CREATE TABLE IF NOT EXISTS channel_system_prompts (
+    channel_id TEXT PRIMARY KEY,
+    prompt TEXT NOT NULL,
+    updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
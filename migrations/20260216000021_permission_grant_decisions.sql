@@ -0,0 +1,4 @@
+-- Remembered rejections alongside "always" grants. A rejected pattern is
+-- answered with `reject` instead of asking again.
+ALTER TABLE permission_grants
+    ADD COLUMN decision TEXT NOT NULL DEFAULT 'always' CHECK (decision IN ('always', 'reject'));
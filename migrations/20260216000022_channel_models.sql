@@ -0,0 +1,8 @@
+-- The model each channel's OpenCode prompts run on, set with `!model`.
+-- Channels without a row use `default_model`, or the server's default.
+CREATE TABLE IF NOT EXISTS channel_models (
+    channel_id TEXT PRIMARY KEY,
+    provider_id TEXT NOT NULL,
+    model_id TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,12 @@
+-- Prompts sent to OpenCode sessions, keyed by the idempotency key generated
+-- for each logical prompt. A send whose key, or whose session and content
+-- within the dedup window, is already here is skipped instead of repeated.
+CREATE TABLE IF NOT EXISTS prompt_sends (
+    idempotency_key TEXT PRIMARY KEY,
+    channel_id TEXT,
+    session_id TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    sent_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_prompt_sends_fingerprint ON prompt_sends(session_id, fingerprint, sent_at);
@@ -0,0 +1,9 @@
+-- Per-channel settings as JSON values, one row per channel and key. New
+-- per-channel features store their state here instead of adding a table.
+CREATE TABLE IF NOT EXISTS channel_settings (
+    channel_id TEXT NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL CHECK (json_valid(value)),
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (channel_id, key)
+);
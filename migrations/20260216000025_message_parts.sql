@@ -0,0 +1,20 @@
+-- Every part of an OpenCode assistant message, in the order it arrived, so a
+-- turn's text and tool steps can be reconstructed without OpenCode. Only
+-- written when `persist_message_parts` is on. Upserted as a part streams in;
+-- `revision` keeps a late write of an older state from overwriting a newer one.
+CREATE TABLE IF NOT EXISTS message_parts (
+    message_id TEXT NOT NULL,        -- OpenCode message ID
+    part_id TEXT NOT NULL,           -- OpenCode part ID
+    session_id TEXT NOT NULL,
+    channel_id TEXT NOT NULL,
+    ordinal INTEGER NOT NULL,        -- position within the message, from 0
+    part_type TEXT NOT NULL,         -- text, tool, step-start, step-finish
+    content TEXT NOT NULL,           -- JSON part body
+    started_ms REAL,                 -- OpenCode timing, epoch milliseconds
+    ended_ms REAL,
+    revision INTEGER NOT NULL DEFAULT 0,
+    updated_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (message_id, part_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_message_parts_channel ON message_parts(channel_id, updated_at);
@@ -0,0 +1,9 @@
+-- Assistant replies persisted while they stream. A streamed reply's row is
+-- written as 'pending' when its text starts arriving and rewritten as it
+-- grows; it becomes 'complete' when the turn ends, or 'interrupted' if the
+-- turn was cut short. A row still 'pending' at startup was left by a crash.
+-- NULL for every other message.
+ALTER TABLE conversation_messages ADD COLUMN status TEXT
+    CHECK (status IN ('pending', 'complete', 'interrupted'));
+
+CREATE INDEX IF NOT EXISTS idx_messages_pending ON conversation_messages(status) WHERE status = 'pending';
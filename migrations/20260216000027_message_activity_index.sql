@@ -0,0 +1,5 @@
+-- A channel's last activity is the newest message not cleared by a reset.
+-- Covering it with a partial index lets MAX(created_at), per channel or
+-- grouped across channels, be read from the index without touching rows.
+CREATE INDEX IF NOT EXISTS idx_messages_live_channel_time
+    ON conversation_messages(channel_id, created_at) WHERE cleared_at IS NULL;
@@ -0,0 +1,9 @@
+-- Idempotency key of a user message, derived from the channel and the
+-- platform's message ID. Prompts sent to OpenCode on the message's behalf
+-- derive their keys from it, so a redelivered message maps to the same
+-- prompt keys while a user repeating themselves gets new ones.
+-- NULL for every other message.
+ALTER TABLE conversation_messages ADD COLUMN idempotency_key TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_messages_idempotency_key
+    ON conversation_messages(idempotency_key) WHERE idempotency_key IS NOT NULL;
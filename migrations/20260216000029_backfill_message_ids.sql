@@ -0,0 +1,7 @@
+-- OpenCode's ID for messages backfilled from a session, so a message is
+-- only ever backfilled once per channel, even by two reconciles racing on
+-- the same cutoff. NULL for messages Spacebot logged itself.
+ALTER TABLE conversation_messages ADD COLUMN opencode_message_id TEXT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_messages_opencode_message_id
+    ON conversation_messages(channel_id, opencode_message_id) WHERE opencode_message_id IS NOT NULL;
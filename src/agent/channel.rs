@@ -34,7 +34,7 @@ pub struct ChannelState {
     pub worker_handles: Arc<RwLock<HashMap<WorkerId, tokio::task::JoinHandle<()>>>>,
     /// Input senders for interactive workers, keyed by worker ID.
     /// Used by the route tool to deliver follow-up messages.
-    pub worker_inputs: Arc<RwLock<HashMap<WorkerId, tokio::sync::mpsc::Sender<String>>>>,
+    pub worker_inputs: Arc<RwLock<HashMap<WorkerId, tokio::sync::mpsc::Sender<crate::WorkerInput>>>>,
     pub status_block: Arc<RwLock<StatusBlock>>,
     pub deps: AgentDeps,
     pub conversation_logger: ConversationLogger,
@@ -42,6 +42,24 @@ pub struct ChannelState {
     pub channel_store: ChannelStore,
     pub screenshot_dir: std::path::PathBuf,
     pub logs_dir: std::path::PathBuf,
+    /// Outbound messages to the channel, for workers that stream replies.
+    pub response_tx: mpsc::Sender<OutboundResponse>,
+    /// Attachments on the message being handled. Taken by the first OpenCode
+    /// worker spawned for it.
+    pub pending_attachments: Arc<RwLock<Vec<crate::Attachment>>>,
+    /// Display name of whoever sent the message being handled, for sender
+    /// attribution of OpenCode prompts. `None` for system messages and for
+    /// coalesced batches from more than one sender.
+    pub pending_sender: Arc<RwLock<Option<String>>>,
+    /// Idempotency key of the message being handled (joined keys for a
+    /// coalesced batch), from which OpenCode prompts sent on its behalf
+    /// derive theirs. `None` for system messages.
+    pub pending_message_key: Arc<RwLock<Option<String>>>,
+    /// Per-channel settings, cached for the life of the channel.
+    pub channel_settings: crate::settings::ChannelSettings,
+    /// Background compaction after OpenCode turns. `None` when
+    /// `compact_after_turns` is 0.
+    pub compaction_scheduler: Option<Arc<crate::opencode::compaction::CompactionScheduler>>,
 }
 
 impl ChannelState {
@@ -74,6 +92,27 @@ impl ChannelState {
             Err(format!("Branch {branch_id} not found"))
         }
     }
+
+    /// Clear the channel's context so the next message starts fresh: archive
+    /// and clear its stored history (per the configured `reset_mode`), drop
+    /// its OpenCode session mapping, and empty the in-memory history.
+    pub async fn reset_context(&self) -> crate::error::Result<crate::conversation::ChannelReset> {
+        let rc = &self.deps.runtime_config;
+        let reset = self
+            .conversation_logger
+            .reset_channel(&self.channel_id, &rc.archives_dir, rc.reset_mode)
+            .await?;
+        self.history.write().await.clear();
+
+        tracing::info!(
+            channel_id = %self.channel_id,
+            messages = reset.messages_cleared,
+            summaries = reset.summaries_cleared,
+            session_cleared = reset.session_cleared,
+            "channel context reset"
+        );
+        Ok(reset)
+    }
 }
 
 impl std::fmt::Debug for ChannelState {
@@ -115,6 +154,15 @@ pub struct Channel {
     coalesce_buffer: Vec<InboundMessage>,
     /// Deadline for flushing the coalesce buffer.
     coalesce_deadline: Option<tokio::time::Instant>,
+    /// Whether the channel was already told the bot doesn't engage here.
+    access_notice_sent: bool,
+    /// Messages held during quiet hours in queue mode.
+    quiet_queue: Vec<InboundMessage>,
+    /// When the held messages are handled: the end of the quiet period.
+    quiet_release_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// End of the quiet period the channel was last told about, so the
+    /// notice goes out once per period.
+    quiet_notice_until: Option<chrono::DateTime<chrono::Utc>>,
 }
 
 impl Channel {
@@ -139,8 +187,16 @@ impl Channel {
         let active_workers = Arc::new(RwLock::new(HashMap::new()));
         let (message_tx, message_rx) = mpsc::channel(64);
 
-        let conversation_logger = ConversationLogger::new(deps.sqlite_pool.clone());
-        let process_run_logger = ProcessRunLogger::new(deps.sqlite_pool.clone());
+        let mut conversation_logger = ConversationLogger::new(deps.sqlite_pool.clone())
+            .with_cipher(deps.runtime_config.history_cipher.clone())
+            .with_tokenizer(deps.runtime_config.tokenizer.clone())
+            .with_recent_cache(deps.runtime_config.history_cache.clone())
+            .with_write_limiter(deps.runtime_config.history_writes.clone());
+        if let Some(redactor) = &deps.runtime_config.redactor {
+            conversation_logger = conversation_logger.with_redactor(redactor.clone());
+        }
+        let process_run_logger = ProcessRunLogger::new(deps.sqlite_pool.clone())
+            .with_cipher(deps.runtime_config.history_cipher.clone());
         let channel_store = ChannelStore::new(deps.sqlite_pool.clone());
 
         let compactor = Compactor::new(
@@ -149,6 +205,17 @@ impl Channel {
             history.clone(),
         );
 
+        let opencode_config = deps.runtime_config.opencode.load();
+        let compaction_scheduler = (opencode_config.compact_after_turns > 0).then(|| {
+            Arc::new(
+                crate::opencode::compaction::CompactionScheduler::new(
+                    opencode_config.compact_after_turns,
+                    deps.runtime_config.archives_dir.clone(),
+                )
+                .with_summary_limits(opencode_config.summary_limits()),
+            )
+        });
+
         let state = ChannelState {
             channel_id: id.clone(),
             history: history.clone(),
@@ -163,6 +230,12 @@ impl Channel {
             channel_store,
             screenshot_dir,
             logs_dir,
+            response_tx: response_tx.clone(),
+            pending_attachments: Arc::new(RwLock::new(Vec::new())),
+            pending_sender: Arc::new(RwLock::new(None)),
+            pending_message_key: Arc::new(RwLock::new(None)),
+            channel_settings: crate::settings::ChannelSettings::new(deps.sqlite_pool.clone()),
+            compaction_scheduler,
         };
 
         // Each channel gets its own isolated tool server to avoid races between
@@ -188,6 +261,10 @@ impl Channel {
             memory_persistence_branches: HashSet::new(),
             coalesce_buffer: Vec::new(),
             coalesce_deadline: None,
+            access_notice_sent: false,
+            quiet_queue: Vec::new(),
+            quiet_release_at: None,
+            quiet_notice_until: None,
         };
         
         (channel, message_tx)
@@ -210,9 +287,90 @@ impl Channel {
                     }
                 })
                 .unwrap_or(std::time::Duration::from_secs(3600)); // Default long timeout if no deadline
+            let quiet_wait = self
+                .quiet_release_at
+                .and_then(|release_at| (release_at - chrono::Utc::now()).to_std().ok())
+                .unwrap_or(std::time::Duration::from_millis(1));
 
             tokio::select! {
                 Some(message) = self.message_rx.recv() => {
+                    // `!access` from an admin skips the access check, so a
+                    // denied channel can be allowed again.
+                    if let crate::MessageContent::Text(text) = &message.content {
+                        if let Some(command) = crate::opencode::access::AccessCommand::parse(text) {
+                            if self.is_admin(&message.sender_id) {
+                                self.handle_access_command(command).await;
+                                continue;
+                            }
+                        }
+                    }
+                    if message.source != "system"
+                        && !(self.check_channel_access().await && self.check_flood_guard(&message).await)
+                    {
+                        continue;
+                    }
+                    // Commands are handled directly, never coalesced or sent to the LLM.
+                    if let crate::MessageContent::Text(text) = &message.content {
+                        if let Some(ungrant) = crate::opencode::grants::Ungrant::parse(text) {
+                            self.handle_ungrant(ungrant).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::access::AccessCommand::parse(text) {
+                            if command == crate::opencode::access::AccessCommand::Show {
+                                self.handle_access_command(command).await;
+                            } else {
+                                let reply = "Only admins can change where I reply.".to_string();
+                                let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+                            }
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::agents::AgentCommand::parse(text) {
+                            self.handle_agent_command(command).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::models::ModelCommand::parse(text) {
+                            self.handle_model_command(command).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::forks::ForkCommand::parse(text) {
+                            self.handle_fork_command(command).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::personas::PersonaCommand::parse(text) {
+                            self.handle_persona_command(command).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::output::OutputCommand::parse(text) {
+                            self.handle_output_command(command).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::quiet::QuietCommand::parse(text) {
+                            self.handle_quiet_command(command, &message.sender_id).await;
+                            continue;
+                        }
+                        if let Some(command) = crate::opencode::summarize::SummarizeCommand::parse(text) {
+                            self.handle_summarize_command(command).await;
+                            continue;
+                        }
+                        if text.trim() == "!pending" {
+                            let pending = self.deps.runtime_config.pending_interactions.pending_interactions(&self.id);
+                            let _ = self.response_tx.send(OutboundResponse::Text(pending.render())).await;
+                            continue;
+                        }
+                        if let Some(regenerate) = crate::conversation::regenerate::Regenerate::parse(text) {
+                            if let Err(error) = self.flush_coalesce_buffer().await {
+                                tracing::error!(%error, channel_id = %self.id, "error flushing coalesce buffer");
+                            }
+                            if let Err(error) = self.handle_regenerate(regenerate, &message.conversation_id).await {
+                                tracing::error!(%error, channel_id = %self.id, "error regenerating reply");
+                            }
+                            continue;
+                        }
+                    }
+                    // Quiet hours are checked before anything reaches a prompt.
+                    if !self.check_quiet_hours(&message).await {
+                        continue;
+                    }
                     let config = self.deps.runtime_config.coalesce.load();
                     if self.should_coalesce(&message, &config) {
                         self.coalesce_buffer.push(message);
@@ -242,6 +400,9 @@ impl Channel {
                         tracing::error!(%error, channel_id = %self.id, "error flushing coalesce buffer on deadline");
                     }
                 }
+                _ = tokio::time::sleep(quiet_wait), if self.quiet_release_at.is_some() => {
+                    self.release_quiet_queue().await;
+                }
                 else => break,
             }
         }
@@ -255,6 +416,690 @@ impl Channel {
         Ok(())
     }
 
+    /// Handle `!ungrant`: list the channel's stored "always" permission
+    /// grants, or revoke some of them, and reply with the result.
+    async fn handle_ungrant(&self, ungrant: crate::opencode::grants::Ungrant) {
+        use crate::opencode::grants::Ungrant;
+
+        let grants = crate::opencode::grants::PermissionGrants::new(self.deps.sqlite_pool.clone());
+        let reply = match &ungrant {
+            Ungrant::List => match grants.list(&self.id).await {
+                Ok(list) if list.is_empty() => "No remembered permission choices in this channel.".to_string(),
+                Ok(list) => {
+                    let lines: Vec<String> = list
+                        .iter()
+                        .map(|grant| format!("- {} `{}` ({})", grant.permission, grant.pattern, grant.decision.as_str()))
+                        .collect();
+                    format!(
+                        "Remembered permission choices:\n{}\n\nForget them with `!ungrant <permission> [pattern]` or `!ungrant all`.",
+                        lines.join("\n")
+                    )
+                }
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to list permission grants");
+                    "Couldn't load permission grants.".to_string()
+                }
+            },
+            _ => match grants.revoke(&self.id, &ungrant).await {
+                Ok(0) => "No matching permission grants.".to_string(),
+                Ok(1) => "Revoked 1 permission grant.".to_string(),
+                Ok(count) => format!("Revoked {count} permission grants."),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to revoke permission grants");
+                    "Couldn't revoke permission grants.".to_string()
+                }
+            },
+        };
+
+        if let Err(error) = self.response_tx.send(OutboundResponse::Text(reply)).await {
+            tracing::warn!(%error, channel_id = %self.id, "failed to send ungrant reply");
+        }
+    }
+
+    /// Handle `!regenerate`: supersede the replies to the channel's last user
+    /// message and run that turn again, on `regenerate.model` if given.
+    async fn handle_regenerate(
+        &mut self,
+        regenerate: crate::conversation::regenerate::Regenerate,
+        conversation_id: &str,
+    ) -> Result<()> {
+        let logger = &self.state.conversation_logger;
+        let Some(last) = logger.last_user_message(&self.state.channel_id).await? else {
+            let reply = "There's no message to regenerate a reply to.".to_string();
+            let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+            return Ok(());
+        };
+        let superseded = logger.supersede_replies(&self.state.channel_id, &last).await?;
+        tracing::info!(
+            channel_id = %self.id,
+            message_id = %last.id,
+            superseded,
+            model = ?regenerate.model,
+            "regenerating reply"
+        );
+
+        {
+            let mut history = self.state.history.write().await;
+            if !crate::conversation::regenerate::truncate_to_last_user_turn(&mut history) {
+                tracing::debug!(channel_id = %self.id, "no user turn in memory to roll back");
+            }
+        }
+
+        if self.conversation_id.is_none() {
+            self.conversation_id = Some(conversation_id.to_string());
+        }
+        let sender = last.sender_name.as_deref().or(last.sender_id.as_deref()).unwrap_or("user");
+        let user_text = format!("[{sender}]: {}", last.content);
+        let system_prompt = self.build_system_prompt().await;
+        let (result, skip_flag) = self.run_agent_turn(
+            &user_text,
+            &system_prompt,
+            conversation_id,
+            Vec::new(),
+            regenerate.model.as_deref(),
+        ).await?;
+
+        self.handle_agent_result(result, &skip_flag).await;
+        Ok(())
+    }
+
+    /// Whether the channel access policy lets the bot engage here. The first
+    /// turned-away message gets a notice; later ones are dropped silently.
+    async fn check_channel_access(&mut self) -> bool {
+        use crate::opencode::access::{AccessRule, ChannelAccess};
+
+        let policy = self.deps.runtime_config.opencode.load().channel_access_policy();
+        let access = ChannelAccess::new(self.deps.sqlite_pool.clone(), policy.clone());
+        let rule = access.check(&self.id).await.unwrap_or_else(|error| {
+            tracing::warn!(%error, channel_id = %self.id, "failed to load channel access override");
+            policy.check(&self.id)
+        });
+        if rule == AccessRule::Allow {
+            self.access_notice_sent = false;
+            return true;
+        }
+
+        tracing::debug!(channel_id = %self.id, "ignoring message in denied channel");
+        if !self.access_notice_sent {
+            self.access_notice_sent = true;
+            let notice = "I'm not enabled in this channel, so I won't reply here.".to_string();
+            let _ = self.response_tx.send(OutboundResponse::Text(notice)).await;
+        }
+        false
+    }
+
+    /// Whether the sender may change the channel's reply rule (`!access`) or
+    /// quiet hours (`!quiet`). Only senders listed in `admin_users` may.
+    fn is_admin(&self, sender_id: &str) -> bool {
+        self.deps.runtime_config.opencode.load().admin_users.iter().any(|user| user == sender_id)
+    }
+
+    /// Whether the sender is under the flood limit. Over it, the message is
+    /// dropped, with a notice on the first drop of each cooldown.
+    async fn check_flood_guard(&self, message: &InboundMessage) -> bool {
+        use crate::opencode::FloodVerdict;
+
+        let limit = self.deps.runtime_config.opencode.load().flood_limit();
+        let verdict = self.deps.runtime_config.flood_guard.check(
+            &message.sender_id,
+            limit,
+            tokio::time::Instant::now(),
+        );
+        let FloodVerdict::Drop { notify, retry_after } = verdict else {
+            return true;
+        };
+
+        tracing::debug!(channel_id = %self.id, sender_id = %message.sender_id, "dropping message over flood limit");
+        if notify {
+            let notice = format!(
+                "You're sending messages too fast. I'll skip them for the next {} seconds.",
+                retry_after.as_secs().max(1)
+            );
+            let _ = self.response_tx.send(OutboundResponse::Text(notice)).await;
+        }
+        false
+    }
+
+    /// Handle `!access`: show the channel's access rule, or override it.
+    /// Callers check that only admins override; an admin's command runs
+    /// before the access check, so a denied channel can be allowed again.
+    async fn handle_access_command(&mut self, command: crate::opencode::access::AccessCommand) {
+        use crate::opencode::access::{AccessCommand, AccessRule, ChannelAccess};
+
+        let policy = self.deps.runtime_config.opencode.load().channel_access_policy();
+        let access = ChannelAccess::new(self.deps.sqlite_pool.clone(), policy.clone());
+        let result = match command {
+            AccessCommand::Show => access.stored_rule(&self.id).await.map(|stored| match stored {
+                Some(rule) => format!("Access here is `{rule}`, overriding the config (`{}`).", policy.check(&self.id)),
+                None => format!("Access here is `{}`, from the config.", policy.check(&self.id)),
+            }),
+            AccessCommand::Allow => access.allow_channel(&self.id).await.map(|()| "I'll reply in this channel.".to_string()),
+            AccessCommand::Deny => access
+                .deny_channel(&self.id)
+                .await
+                .map(|()| "I won't reply in this channel anymore.".to_string()),
+            AccessCommand::Reset => access.clear_override(&self.id).await.map(|cleared| match (cleared, policy.check(&self.id)) {
+                (false, _) => "This channel has no access override.".to_string(),
+                (true, AccessRule::Allow) => "Override removed. The config allows this channel.".to_string(),
+                (true, AccessRule::Deny) => "Override removed. The config denies this channel.".to_string(),
+            }),
+        };
+        let reply = result.unwrap_or_else(|error| {
+            tracing::warn!(%error, channel_id = %self.id, "failed to update channel access");
+            "Couldn't update this channel's access.".to_string()
+        });
+        // The next turned-away message gets a fresh notice.
+        self.access_notice_sent = false;
+        let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+    }
+
+    /// Handle `!agents` and `!agent`: list the OpenCode agents, show the
+    /// channel's, or switch it. A new agent is only saved once the server
+    /// confirms it exists.
+    async fn handle_agent_command(&self, command: crate::opencode::agents::AgentCommand) {
+        use crate::opencode::agents::{AgentCommand, AgentSelectionError, ChannelAgents, validate_agent};
+
+        let agents = ChannelAgents::new(self.deps.sqlite_pool.clone());
+        let reply = match command {
+            AgentCommand::Show => match agents.get(&self.id).await {
+                Ok(Some(agent)) => format!("This channel uses the `{agent}` agent."),
+                Ok(None) => "This channel uses OpenCode's default agent.".to_string(),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to load channel agent");
+                    "Couldn't load this channel's agent.".to_string()
+                }
+            },
+            AgentCommand::Reset => match agents.clear(&self.id).await {
+                Ok(_) => "This channel now uses OpenCode's default agent.".to_string(),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to clear channel agent");
+                    "Couldn't reset this channel's agent.".to_string()
+                }
+            },
+            AgentCommand::List => match self.list_opencode_agents().await {
+                Ok(available) => {
+                    let lines: Vec<String> = available
+                        .iter()
+                        .filter(|agent| agent.is_selectable())
+                        .map(|agent| match &agent.description {
+                            Some(description) => format!("- `{}`: {description}", agent.name),
+                            None => format!("- `{}`", agent.name),
+                        })
+                        .collect();
+                    format!("OpenCode agents:\n{}\n\nSwitch with `!agent <name>`.", lines.join("\n"))
+                }
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to list OpenCode agents");
+                    "Couldn't load the OpenCode agents.".to_string()
+                }
+            },
+            AgentCommand::Select(name) => {
+                let validated = self
+                    .list_opencode_agents()
+                    .await
+                    .map(|available| validate_agent(&name, &available));
+                match validated {
+                    Ok(Ok(())) => match agents.set(&self.id, &name).await {
+                        Ok(()) => format!("This channel now uses the `{name}` agent."),
+                        Err(error) => {
+                            tracing::warn!(%error, channel_id = %self.id, "failed to save channel agent");
+                            "Couldn't save this channel's agent.".to_string()
+                        }
+                    },
+                    Ok(Err(AgentSelectionError::Subagent)) => {
+                        format!("`{name}` only runs as a sub-agent. Pick one from `!agents`.")
+                    }
+                    Ok(Err(AgentSelectionError::Unknown { available })) => {
+                        let names: Vec<String> = available.iter().map(|name| format!("`{name}`")).collect();
+                        format!("There's no `{name}` agent. Available: {}", names.join(", "))
+                    }
+                    Err(error) => {
+                        tracing::warn!(%error, channel_id = %self.id, "failed to list OpenCode agents");
+                        "Couldn't check the OpenCode agents, so the agent wasn't changed.".to_string()
+                    }
+                }
+            }
+        };
+
+        if let Err(error) = self.response_tx.send(OutboundResponse::Text(reply)).await {
+            tracing::warn!(%error, channel_id = %self.id, "failed to send agent reply");
+        }
+    }
+
+    /// Handle `!models`, `!model`, `!model default`, and `!model <provider/model>`.
+    async fn handle_model_command(&self, command: crate::opencode::models::ModelCommand) {
+        use crate::opencode::models::{ChannelModels, ModelCommand, ModelSelectionError, validate_model};
+
+        let models = ChannelModels::new(self.deps.sqlite_pool.clone());
+        let default_model = self.deps.runtime_config.opencode.load().default_model.clone();
+        let reply = match command {
+            ModelCommand::Show => match models.get(&self.id).await {
+                Ok(Some(model)) => format!("This channel uses `{model}`."),
+                Ok(None) => match default_model {
+                    Some(model) => format!("This channel uses the default model, `{model}`."),
+                    None => "This channel uses OpenCode's default model.".to_string(),
+                },
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to load channel model");
+                    "Couldn't load this channel's model.".to_string()
+                }
+            },
+            ModelCommand::Reset => match models.clear(&self.id).await {
+                Ok(_) => "This channel now uses the default model.".to_string(),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to clear channel model");
+                    "Couldn't reset this channel's model.".to_string()
+                }
+            },
+            ModelCommand::List => match self.list_opencode_providers().await {
+                Ok(providers) => {
+                    let mut lines: Vec<String> = providers
+                        .iter()
+                        .flat_map(|provider| {
+                            provider.models.keys().map(move |model| format!("- `{}/{model}`", provider.id))
+                        })
+                        .collect();
+                    lines.sort();
+                    format!("OpenCode models:\n{}\n\nSwitch with `!model <provider/model>`.", lines.join("\n"))
+                }
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to list OpenCode providers");
+                    "Couldn't load the OpenCode models.".to_string()
+                }
+            },
+            ModelCommand::Select(name) => {
+                let validated = self
+                    .list_opencode_providers()
+                    .await
+                    .map(|providers| validate_model(&name, &providers));
+                match validated {
+                    Ok(Ok(model)) => match models.set(&self.id, &model).await {
+                        Ok(()) => format!("This channel now uses `{model}`."),
+                        Err(error) => {
+                            tracing::warn!(%error, channel_id = %self.id, "failed to save channel model");
+                            "Couldn't save this channel's model.".to_string()
+                        }
+                    },
+                    Ok(Err(ModelSelectionError::Malformed)) => {
+                        format!("`{name}` isn't a model. Use `provider/model`, e.g. `anthropic/claude-sonnet-4-5`.")
+                    }
+                    Ok(Err(ModelSelectionError::UnknownProvider { available })) => {
+                        let names: Vec<String> = available.iter().map(|name| format!("`{name}`")).collect();
+                        format!("There's no provider for `{name}`. Available: {}", names.join(", "))
+                    }
+                    Ok(Err(ModelSelectionError::UnknownModel { provider_id, .. })) => {
+                        format!("`{provider_id}` has no model `{name}`. See `!models`.")
+                    }
+                    Err(error) => {
+                        tracing::warn!(%error, channel_id = %self.id, "failed to list OpenCode providers");
+                        "Couldn't check the OpenCode models, so the model wasn't changed.".to_string()
+                    }
+                }
+            }
+        };
+        let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+    }
+
+    /// Handle `!fork`, `!forks`, and `!fork abandon <sub-channel>`.
+    async fn handle_fork_command(&self, command: crate::opencode::forks::ForkCommand) {
+        use crate::opencode::forks::{ForkCommand, SessionForks};
+
+        let forks = SessionForks::new(self.deps.sqlite_pool.clone());
+        let reply = match command {
+            ForkCommand::Create => match self.fork_active_session(&forks).await {
+                Ok(Some(fork)) => format!(
+                    "Forked this conversation into `{}` (session `{}`). The main thread is unchanged; \
+                     abandon the fork with `!fork abandon {}`.",
+                    fork.fork_channel_id, fork.session_id, fork.fork_channel_id
+                ),
+                Ok(None) => "There's no OpenCode session in this channel to fork yet.".to_string(),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to fork session");
+                    "Couldn't fork this channel's session.".to_string()
+                }
+            },
+            ForkCommand::List => match forks.list(&self.id).await {
+                Ok(list) if list.is_empty() => "This channel has no forks.".to_string(),
+                Ok(list) => {
+                    let lines: Vec<String> = list
+                        .iter()
+                        .map(|fork| format!("- `{}` (session `{}`)", fork.fork_channel_id, fork.session_id))
+                        .collect();
+                    format!("Forks of this channel:\n{}", lines.join("\n"))
+                }
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to list forks");
+                    "Couldn't load this channel's forks.".to_string()
+                }
+            },
+            ForkCommand::Abandon(fork_channel_id) => match forks.get(&fork_channel_id).await {
+                Ok(Some(fork)) if fork.parent_channel_id == self.id.as_ref() => {
+                    match forks.abandon(&fork_channel_id).await {
+                        Ok(_) => {
+                            if let Err(error) = self.deps.runtime_config.opencode_server_pool.delete_session(&fork.session_id).await {
+                                tracing::warn!(%error, session_id = %fork.session_id, "failed to delete abandoned fork session");
+                            }
+                            format!("Abandoned fork `{fork_channel_id}`.")
+                        }
+                        Err(error) => {
+                            tracing::warn!(%error, channel_id = %self.id, "failed to abandon fork");
+                            "Couldn't abandon that fork.".to_string()
+                        }
+                    }
+                }
+                Ok(_) => format!("This channel has no fork `{fork_channel_id}`."),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to load fork");
+                    "Couldn't load that fork.".to_string()
+                }
+            },
+        };
+        let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+    }
+
+    /// Handle `!persona`, `!persona reset`, and `!persona <prompt>`.
+    async fn handle_persona_command(&self, command: crate::opencode::personas::PersonaCommand) {
+        use crate::opencode::personas::{ChannelPrompts, PersonaCommand};
+
+        let opencode_config = self.deps.runtime_config.opencode.load();
+        let prompts = ChannelPrompts::new(self.deps.sqlite_pool.clone(), opencode_config.system_prompt_max_chars);
+        let reply = match command {
+            PersonaCommand::Show => match prompts.get_system_prompt(&self.id).await {
+                Ok(Some(prompt)) => format!("This channel's system prompt:\n> {prompt}"),
+                Ok(None) => match &opencode_config.default_system_prompt {
+                    Some(prompt) => format!("This channel uses the default system prompt:\n> {prompt}"),
+                    None => "This channel has no system prompt.".to_string(),
+                },
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to load channel system prompt");
+                    "Couldn't load this channel's system prompt.".to_string()
+                }
+            },
+            PersonaCommand::Reset => match prompts.clear_system_prompt(&self.id).await {
+                Ok(_) => "This channel now uses the default system prompt.".to_string(),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to clear channel system prompt");
+                    "Couldn't reset this channel's system prompt.".to_string()
+                }
+            },
+            PersonaCommand::Set(prompt) => match prompts.set_system_prompt(&self.id, &prompt).await {
+                Ok(()) => "Updated this channel's system prompt. It applies from the next task.".to_string(),
+                Err(error @ crate::opencode::personas::PersonaError::TooLong { .. }) => format!("Couldn't set it: {error}."),
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to save channel system prompt");
+                    "Couldn't save this channel's system prompt.".to_string()
+                }
+            },
+        };
+        let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+    }
+
+    /// The channel's quiet-hours schedule, if it has one. A schedule that
+    /// can't be loaded is logged and treated as none.
+    async fn quiet_hours(&self) -> Option<crate::opencode::quiet::QuietHours> {
+        use crate::opencode::quiet::QUIET_HOURS_KEY;
+
+        self.state
+            .channel_settings
+            .get_setting(&self.id, QUIET_HOURS_KEY)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::warn!(%error, channel_id = %self.id, "failed to load quiet hours");
+                None
+            })
+    }
+
+    /// Whether the message should be handled now. During quiet hours it's
+    /// answered with an "offline until" notice, or held until they end,
+    /// unless the sender bypasses quiet hours.
+    async fn check_quiet_hours(&mut self, message: &InboundMessage) -> bool {
+        use crate::opencode::quiet::QuietMode;
+
+        if message.source == "system" {
+            return true;
+        }
+        let bypass_users = self.deps.runtime_config.opencode.load().quiet_hours_bypass_users.clone();
+        if bypass_users.contains(&message.sender_id) {
+            return true;
+        }
+        let Some(schedule) = self.quiet_hours().await else {
+            return true;
+        };
+        let Some(until) = schedule.quiet_until(chrono::Utc::now()) else {
+            return true;
+        };
+
+        let back = schedule.format_local(until);
+        let notice = match schedule.mode {
+            QuietMode::Notice => format!("I'm offline until {back}."),
+            QuietMode::Queue if self.quiet_queue.len() >= QUIET_QUEUE_MAX => {
+                tracing::debug!(channel_id = %self.id, "quiet-hours queue full, dropping message");
+                let notice = format!("I'm offline until {back}, and already have too many messages waiting. Please resend this then.");
+                let _ = self.response_tx.send(OutboundResponse::Text(notice)).await;
+                return false;
+            }
+            QuietMode::Queue => {
+                self.quiet_queue.push(message.clone());
+                self.quiet_release_at = Some(self.quiet_release_at.map_or(until, |release_at| release_at.max(until)));
+                format!("I'm offline until {back}. I'll get to this then.")
+            }
+        };
+        tracing::debug!(channel_id = %self.id, mode = schedule.mode.as_str(), %until, "message arrived during quiet hours");
+        if self.quiet_notice_until != Some(until) {
+            self.quiet_notice_until = Some(until);
+            let _ = self.response_tx.send(OutboundResponse::Text(notice)).await;
+        }
+        false
+    }
+
+    /// Handle the messages held during quiet hours, as one batch, once they
+    /// end. If the schedule was extended meanwhile, wait for the new end.
+    async fn release_quiet_queue(&mut self) {
+        self.quiet_release_at = None;
+        let still_quiet = self
+            .quiet_hours()
+            .await
+            .and_then(|schedule| schedule.quiet_until(chrono::Utc::now()));
+        if let Some(until) = still_quiet.filter(|_| !self.quiet_queue.is_empty()) {
+            self.quiet_release_at = Some(until);
+            return;
+        }
+
+        let queued = std::mem::take(&mut self.quiet_queue);
+        if queued.is_empty() {
+            return;
+        }
+        tracing::info!(channel_id = %self.id, count = queued.len(), "quiet hours over, handling held messages");
+        if let Err(error) = self.flush_coalesce_buffer().await {
+            tracing::error!(%error, channel_id = %self.id, "error flushing coalesce buffer");
+        }
+        self.coalesce_buffer = queued;
+        if let Err(error) = self.flush_coalesce_buffer().await {
+            tracing::error!(%error, channel_id = %self.id, "error handling held messages");
+        }
+    }
+
+    /// Handle `!quiet`: show, set, or remove the channel's quiet hours.
+    /// Anyone can show the schedule; only admins (see `is_admin`) can change it.
+    async fn handle_quiet_command(
+        &mut self,
+        command: Result<crate::opencode::quiet::QuietCommand, crate::opencode::quiet::QuietHoursError>,
+        sender_id: &str,
+    ) {
+        use crate::opencode::quiet::{QUIET_HOURS_KEY, QuietCommand};
+
+        let may_change = self.is_admin(sender_id);
+        let settings = self.state.channel_settings.clone();
+        let reply = match command {
+            Err(error) => format!("{error}."),
+            Ok(QuietCommand::Show) => match self.quiet_hours().await {
+                Some(schedule) => match schedule.quiet_until(chrono::Utc::now()) {
+                    Some(until) => format!("Quiet hours: {schedule}. Quiet now, until {}.", schedule.format_local(until)),
+                    None => format!("Quiet hours: {schedule}."),
+                },
+                None => "This channel has no quiet hours.".to_string(),
+            },
+            Ok(_) if !may_change => "Only admins can change this channel's quiet hours.".to_string(),
+            Ok(QuietCommand::Off) => match settings.clear_setting(&self.id, QUIET_HOURS_KEY).await {
+                Ok(_) => {
+                    self.quiet_notice_until = None;
+                    if !self.quiet_queue.is_empty() {
+                        self.quiet_release_at = Some(chrono::Utc::now());
+                    }
+                    "Quiet hours are off for this channel.".to_string()
+                }
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to clear quiet hours");
+                    "Couldn't turn off quiet hours.".to_string()
+                }
+            },
+            Ok(QuietCommand::Set(schedule)) => match settings.set_setting(&self.id, QUIET_HOURS_KEY, &schedule).await {
+                Ok(()) => {
+                    self.quiet_notice_until = None;
+                    if !self.quiet_queue.is_empty() {
+                        self.quiet_release_at = Some(chrono::Utc::now());
+                    }
+                    format!("Quiet hours set: {schedule}.")
+                }
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %self.id, "failed to save quiet hours");
+                    "Couldn't save quiet hours.".to_string()
+                }
+            },
+        };
+        let _ = self.response_tx.send(OutboundResponse::Text(reply)).await;
+    }
+
+    /// Handle `!summarize`. The summary is produced in the background, so
+    /// the channel keeps handling messages meanwhile, and posted when ready.
+    async fn handle_summarize_command(
+        &self,
+        command: Result<crate::opencode::summarize::SummarizeCommand, crate::opencode::summarize::SummarizeError>,
+    ) {
+        let command = match command {
+            Ok(command) => command,
+            Err(error) => {
+                let _ = self.response_tx.send(OutboundResponse::Text(format!("{error}."))).await;
+                return;
+            }
+        };
+        let rc = &self.deps.runtime_config;
+        if !rc.opencode.load().enabled {
+            let _ = self
+                .response_tx
+                .send(OutboundResponse::Text("Summaries need OpenCode workers, which aren't enabled.".to_string()))
+                .await;
+            return;
+        }
+
+        let server_pool = rc.opencode_server_pool.clone();
+        let workspace_dir = rc.workspace_dir.clone();
+        let logger = self.state.conversation_logger.clone();
+        let response_tx = self.response_tx.clone();
+        let channel_id = self.id.clone();
+        tokio::spawn(async move {
+            let summary = async {
+                let server = server_pool.get_or_create_for_channel(&workspace_dir, Some(&channel_id)).await?;
+                let handle = server.lock().await.handle();
+                crate::opencode::summarize::summarize_channel(&handle, &logger, &channel_id, &command).await
+            };
+            let reply = match summary.await {
+                Ok(Some(summary)) => format!("Summary of the last {} messages:\n{summary}", command.messages),
+                Ok(None) => "There's nothing in this channel to summarize yet.".to_string(),
+                Err(error) => {
+                    tracing::warn!(%error, %channel_id, "failed to summarize channel");
+                    "Couldn't summarize this channel.".to_string()
+                }
+            };
+            let _ = response_tx.send(OutboundResponse::Text(reply)).await;
+        });
+    }
+
+    /// Handle `!output` and `!output <call_id>`.
+    async fn handle_output_command(&self, command: crate::opencode::output::OutputCommand) {
+        use crate::opencode::output::{ToolOutputReply, tool_output_reply};
+
+        let logger = &self.state.conversation_logger;
+        let found = match &command.call_id {
+            Some(call_id) => logger.find_tool_invocation(&self.id, call_id).await,
+            None => logger
+                .load_tool_invocations(&self.id, 1)
+                .await
+                .map(|invocations| invocations.into_iter().next()),
+        };
+        let response = match found {
+            Ok(Some(invocation)) => {
+                let max_bytes = self.deps.runtime_config.opencode.load().tool_output_max_bytes;
+                match tool_output_reply(&invocation, max_bytes) {
+                    ToolOutputReply::Text(text) => OutboundResponse::Text(text),
+                    ToolOutputReply::File { filename, data, caption } => OutboundResponse::File {
+                        filename,
+                        data,
+                        mime_type: "text/plain".to_string(),
+                        caption: Some(caption),
+                    },
+                }
+            }
+            Ok(None) => OutboundResponse::Text(match &command.call_id {
+                Some(call_id) => format!("There's no tool call `{call_id}` in this channel."),
+                None => "No tool calls have been recorded in this channel yet.".to_string(),
+            }),
+            Err(error) => {
+                tracing::warn!(%error, channel_id = %self.id, "failed to load tool invocation");
+                OutboundResponse::Text("Couldn't load the tool output.".to_string())
+            }
+        };
+        let _ = self.response_tx.send(response).await;
+    }
+
+    /// Fork the channel's active session. `None` if it has none.
+    async fn fork_active_session(
+        &self,
+        forks: &crate::opencode::forks::SessionForks,
+    ) -> anyhow::Result<Option<crate::opencode::forks::SessionFork>> {
+        let rc = &self.deps.runtime_config;
+        if !rc.opencode.load().enabled {
+            anyhow::bail!("OpenCode workers are not enabled in config");
+        }
+        let Some(session_id) = self.state.channel_store.get_active_session(&self.id).await? else {
+            return Ok(None);
+        };
+        let server = rc
+            .opencode_server_pool
+            .get_or_create_for_channel(&rc.workspace_dir, Some(&self.id))
+            .await?;
+        let handle = server.lock().await.handle();
+        Ok(Some(forks.fork_session(&handle, &self.id, &session_id).await?))
+    }
+
+    /// The agents the channel's OpenCode server can run.
+    async fn list_opencode_agents(&self) -> anyhow::Result<Vec<crate::opencode::types::AgentInfo>> {
+        let rc = &self.deps.runtime_config;
+        if !rc.opencode.load().enabled {
+            anyhow::bail!("OpenCode workers are not enabled in config");
+        }
+        let server = rc
+            .opencode_server_pool
+            .get_or_create_for_channel(&rc.workspace_dir, Some(&self.id))
+            .await?;
+        let handle = server.lock().await.handle();
+        handle.list_agents().await
+    }
+
+    /// The providers, and their models, the channel's OpenCode server has.
+    async fn list_opencode_providers(&self) -> anyhow::Result<Vec<crate::opencode::types::ProviderInfo>> {
+        let rc = &self.deps.runtime_config;
+        if !rc.opencode.load().enabled {
+            anyhow::bail!("OpenCode workers are not enabled in config");
+        }
+        let server = rc
+            .opencode_server_pool
+            .get_or_create_for_channel(&rc.workspace_dir, Some(&self.id))
+            .await?;
+        let handle = server.lock().await.handle();
+        Ok(handle.list_providers().await?.providers)
+    }
+
     /// Determine if a message should be coalesced (batched with other messages).
     ///
     /// Returns false for:
@@ -392,13 +1237,12 @@ impl Channel {
         // Persist each message to conversation log (individual audit trail)
         let mut user_contents: Vec<UserContent> = Vec::new();
         let mut conversation_id = String::new();
+        let mut all_attachments = Vec::new();
+        let mut message_keys = Vec::new();
         
         for message in &messages {
             if message.source != "system" {
-                let sender_name = message.metadata
-                    .get("sender_display_name")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or(&message.sender_id);
+                let sender_name = sender_display_name(message);
                 
                 let (raw_text, attachments) = match &message.content {
                     crate::MessageContent::Text(text) => (text.clone(), Vec::new()),
@@ -407,13 +1251,16 @@ impl Channel {
                     }
                 };
                 
+                let message_key = crate::opencode::idempotency::message_key(&self.state.channel_id, &message.id);
                 self.state.conversation_logger.log_user_message(
                     &self.state.channel_id,
                     sender_name,
                     &message.sender_id,
                     &raw_text,
                     &message.metadata,
+                    &message_key,
                 );
+                message_keys.push(message_key);
                 self.state.channel_store.upsert(
                     &message.conversation_id,
                     &message.metadata,
@@ -431,10 +1278,7 @@ impl Channel {
                     format!("{}m ago", relative_secs / 60)
                 };
                 
-                let display_name = message.metadata
-                    .get("sender_display_name")
-                    .and_then(|v| v.as_str())
-                    .unwrap_or(&message.sender_id);
+                let display_name = sender_display_name(message);
                 
                 let formatted_text = format!("[{}] ({}): {}", display_name, relative_text, raw_text);
                 
@@ -444,6 +1288,7 @@ impl Channel {
                     for content in attachment_content {
                         user_contents.push(content);
                     }
+                    all_attachments.extend(attachments);
                 }
                 
                 user_contents.push(UserContent::text(formatted_text));
@@ -463,6 +1308,17 @@ impl Channel {
                 .join("\n")
         );
         
+        *self.state.pending_attachments.write().await = all_attachments;
+        let mut senders = messages
+            .iter()
+            .filter(|message| message.source != "system")
+            .map(sender_display_name);
+        let first_sender = senders.next();
+        *self.state.pending_sender.write().await =
+            first_sender.filter(|first| senders.all(|sender| sender == *first)).map(str::to_string);
+        *self.state.pending_message_key.write().await =
+            (!message_keys.is_empty()).then(|| message_keys.join("+"));
+
         // Build system prompt with coalesce hint
         let system_prompt = self.build_system_prompt_with_coalesce(
             message_count,
@@ -476,6 +1332,7 @@ impl Channel {
             &system_prompt,
             &conversation_id,
             Vec::new(), // Attachments already formatted into text
+            None,
         ).await?;
         
         self.handle_agent_result(result, &skip_flag).await;
@@ -571,19 +1428,23 @@ impl Channel {
         } else {
             Vec::new()
         };
+        *self.state.pending_attachments.write().await = attachments;
+        *self.state.pending_sender.write().await =
+            (message.source != "system").then(|| sender_display_name(&message).to_string());
+        let message_key = (message.source != "system")
+            .then(|| crate::opencode::idempotency::message_key(&self.state.channel_id, &message.id));
+        *self.state.pending_message_key.write().await = message_key.clone();
 
         // Persist user messages (skip system re-triggers)
-        if message.source != "system" {
-            let sender_name = message.metadata
-                .get("sender_display_name")
-                .and_then(|v| v.as_str())
-                .unwrap_or(&message.sender_id);
+        if let Some(message_key) = &message_key {
+            let sender_name = sender_display_name(&message);
             self.state.conversation_logger.log_user_message(
                 &self.state.channel_id,
                 sender_name,
                 &message.sender_id,
                 &raw_text,
                 &message.metadata,
+                message_key,
             );
             self.state.channel_store.upsert(
                 &message.conversation_id,
@@ -610,6 +1471,7 @@ impl Channel {
             &system_prompt,
             &message.conversation_id,
             attachment_content,
+            None,
         ).await?;
 
         self.handle_agent_result(result, &skip_flag).await;
@@ -668,12 +1530,14 @@ impl Channel {
     /// Register per-turn tools, run the LLM agentic loop, and clean up.
     ///
     /// Returns the prompt result and skip flag for the caller to dispatch.
+    /// `model_override` replaces the routed channel model for this turn.
     async fn run_agent_turn(
         &self,
         user_text: &str,
         system_prompt: &str,
         conversation_id: &str,
         attachment_content: Vec<UserContent>,
+        model_override: Option<&str>,
     ) -> Result<(std::result::Result<String, rig::completion::PromptError>, crate::tools::SkipFlag)> {
         let skip_flag = crate::tools::new_skip_flag();
 
@@ -692,7 +1556,7 @@ impl Channel {
         let rc = &self.deps.runtime_config;
         let routing = rc.routing.load();
         let max_turns = **rc.max_turns.load();
-        let model_name = routing.resolve(ProcessType::Channel, None);
+        let model_name = model_override.unwrap_or_else(|| routing.resolve(ProcessType::Channel, None));
         let model = SpacebotModel::make(&self.deps.llm_manager, model_name)
             .with_routing((**routing).clone());
 
@@ -847,6 +1711,68 @@ impl Channel {
                 
                 tracing::info!(worker_id = %worker_id, "worker completed");
             }
+            ProcessEvent::WorkerPermissionBatch { worker_id, permissions, .. } => {
+                let mut message = format!("[Worker {worker_id} needs permission]:");
+                for (index, permission) in permissions.iter().enumerate() {
+                    message.push_str(&format!("\n{}. {}", index + 1, permission.description));
+                }
+                message.push_str(
+                    "\nRoute the user's decision to the worker, e.g. \"allow 1 2, reject 3\" \
+                     (allow, always, or reject; a bare verb or \"all\" covers every item).",
+                );
+                self.state.history.write().await.push(rig::message::Message::from(message));
+                should_retrigger = true;
+            }
+            ProcessEvent::WorkerQuestion { worker_id, questions, awaiting_reply: true, .. } => {
+                let mut message = format!("[Worker {worker_id} is asking]:");
+                for (index, question) in questions.iter().enumerate() {
+                    let text = question.question.as_deref().or(question.header.as_deref()).unwrap_or("(no text)");
+                    message.push_str(&format!("\n{}. {text}", index + 1));
+                    for (number, option) in question.options.iter().enumerate() {
+                        message.push_str(&format!("\n   {}) {}", number + 1, option.label));
+                    }
+                }
+                message.push_str(
+                    "\nRoute the user's answer to the worker: an option number, an option label, \
+                     or free text; one line per question.",
+                );
+                self.state.history.write().await.push(rig::message::Message::from(message));
+                should_retrigger = true;
+            }
+            ProcessEvent::WorkerQuestionAnswered { worker_id, answers, .. } => {
+                tracing::debug!(worker_id = %worker_id, "posting chosen question answers");
+                let labels: Vec<&str> = answers.iter().map(|answer| answer.label.as_str()).collect();
+                let message = format!("✅ You chose: {}", labels.join(", "));
+                let _ = self.response_tx.send(OutboundResponse::Text(message)).await;
+            }
+            ProcessEvent::WorkerToolError { worker_id, tool, error, .. } => {
+                tracing::debug!(worker_id = %worker_id, %tool, "posting worker tool error");
+                let message = format!("❌ {tool} failed: {}", tool_error_summary(error));
+                let _ = self.response_tx.send(OutboundResponse::Text(message)).await;
+            }
+            ProcessEvent::WorkerTurnOutcome { worker_id, outcome, .. } => {
+                if let Some(warning) = outcome.warning() {
+                    tracing::info!(worker_id = %worker_id, finish_reason = ?outcome.finish_reason, duration_ms = ?outcome.duration_ms, "worker turn ended early");
+                    let _ = self.response_tx.send(OutboundResponse::Text(warning.to_string())).await;
+                }
+            }
+            ProcessEvent::WorkerRetryWarning { worker_id, attempt, message, .. } => {
+                tracing::debug!(worker_id = %worker_id, attempt, "posting worker retry warning");
+                let reason = message.as_deref().map(|message| format!(" ({message})")).unwrap_or_default();
+                let warning = format!(
+                    "⚠️ OpenCode has retried the model {attempt} times{reason}; this turn may fail."
+                );
+                let _ = self.response_tx.send(OutboundResponse::Text(warning)).await;
+            }
+            ProcessEvent::WorkerIndicator { worker_id, state, .. } => {
+                tracing::trace!(worker_id = %worker_id, state = %state.label(), "worker indicator");
+                let status = if state.is_active() {
+                    crate::StatusUpdate::Thinking
+                } else {
+                    crate::StatusUpdate::StopTyping
+                };
+                let _ = self.response_tx.send(OutboundResponse::Status(status)).await;
+            }
             _ => {}
         }
 
@@ -1148,7 +2074,7 @@ pub async fn spawn_opencode_worker_from_state(
     interactive: bool,
 ) -> std::result::Result<crate::WorkerId, AgentError> {
     check_worker_limit(state).await?;
-    let task = task.into();
+    let task = attribute_prompt(state, task.into()).await;
     let directory = std::path::PathBuf::from(directory);
 
     let rc = &state.deps.runtime_config;
@@ -1161,6 +2087,15 @@ pub async fn spawn_opencode_worker_from_state(
     }
 
     let server_pool = rc.opencode_server_pool.clone();
+    let breaker_state = server_pool.circuit_breaker().state(tokio::time::Instant::now());
+    if let crate::opencode::breaker::BreakerState::Open { retry_after } = breaker_state {
+        let unavailable = crate::opencode::breaker::CircuitOpen { retry_after };
+        let _ = state
+            .response_tx
+            .send(OutboundResponse::Text(format!("⚠️ {unavailable}. Try again shortly.")))
+            .await;
+        return Err(AgentError::Other(unavailable.into()));
+    }
 
     let worker = if interactive {
         let (worker, input_tx) = crate::opencode::OpenCodeWorker::new_interactive(
@@ -1171,6 +2106,36 @@ pub async fn spawn_opencode_worker_from_state(
             server_pool,
             state.deps.event_tx.clone(),
         );
+        let worker = worker
+            .with_follow_up_mode(opencode_config.follow_up_mode)
+            .with_prompt_coalescing(
+                std::time::Duration::from_millis(opencode_config.prompt_coalesce_window_ms),
+                opencode_config.prompt_coalesce_max_chars,
+            )
+            .with_indicator_debounce(std::time::Duration::from_millis(
+                opencode_config.typing_indicator_debounce_ms,
+            ))
+            .with_retry_warning(opencode_config.retry_warning_attempts)
+            .with_permission_mode(
+                opencode_config.permission_mode,
+                std::time::Duration::from_millis(opencode_config.permission_batch_window_ms),
+            )
+            .with_permission_timeouts(opencode_config.permission_timeouts())
+            .with_conversation_logger(state.conversation_logger.clone())
+            .with_message_parts(opencode_config.persist_message_parts)
+            .with_reply_checkpoints(std::time::Duration::from_millis(
+                opencode_config.reply_checkpoint_interval_ms,
+            ))
+            .with_channel_store(state.channel_store.clone())
+            .with_auto_compaction(
+                rc.archives_dir.clone(),
+                state.response_tx.clone(),
+                opencode_config.summary_limits(),
+            );
+        let worker = match &state.compaction_scheduler {
+            Some(scheduler) => worker.with_compaction_scheduler(scheduler.clone()),
+            None => worker,
+        };
         let worker_id = worker.id;
         state.worker_inputs.write().await.insert(worker_id, input_tx);
         worker
@@ -1184,6 +2149,85 @@ pub async fn spawn_opencode_worker_from_state(
             state.deps.event_tx.clone(),
         )
     };
+    let question_timeout = (opencode_config.question_timeout_secs > 0)
+        .then(|| std::time::Duration::from_secs(opencode_config.question_timeout_secs));
+    let mut worker = worker
+        .with_question_defaults(opencode_config.question_default.clone(), question_timeout)
+        .with_permission_audit(crate::opencode::audit::PermissionAuditLog::new(
+            state.deps.sqlite_pool.clone(),
+        ))
+        .with_permission_grants(crate::opencode::grants::PermissionGrants::new(
+            state.deps.sqlite_pool.clone(),
+        ))
+        .with_prompt_ledger(crate::opencode::idempotency::PromptLedger::new(
+            state.deps.sqlite_pool.clone(),
+            std::time::Duration::from_secs(opencode_config.prompt_dedup_window_secs),
+        ))
+        .with_message_key(state.pending_message_key.read().await.clone())
+        .with_active_turns(rc.active_turns.clone())
+        .with_pending_registry(rc.pending_interactions.clone());
+    if rc.sse_recorder.is_enabled() {
+        let persist_to = opencode_config.sse_debug_persist.then(|| state.deps.sqlite_pool.clone());
+        worker = worker.with_sse_recorder(rc.sse_recorder.clone(), persist_to);
+    }
+    if let Some(limit) = opencode_config.prompt_limit(&state.channel_id) {
+        worker = worker.with_prompt_limit(limit, state.response_tx.clone());
+    }
+    if opencode_config.stream_replies {
+        worker = worker.with_reply_stream(state.response_tx.clone());
+    }
+    if opencode_config.dry_run {
+        worker = worker
+            .with_dry_run(true)
+            .with_reply_stream(state.response_tx.clone());
+    }
+    if let Some(url) = &opencode_config.error_webhook_url {
+        worker = worker.with_error_notifier(crate::opencode::webhook::SessionErrorNotifier::new(url));
+    }
+    if !opencode_config.reply_webhook_urls.is_empty() {
+        let sinks = opencode_config
+            .reply_webhook_urls
+            .iter()
+            .fold(crate::opencode::ReplySinks::new(), |sinks, url| {
+                sinks.with_sink(Arc::new(crate::opencode::WebhookReplySink::new(url)))
+            });
+        worker = worker.with_reply_sinks(sinks);
+    }
+    let channel_name = state.channel_store.resolve_name(&state.channel_id).await;
+    let preferences = crate::opencode::prompt::ChannelPreferences::resolve(
+        &state.deps.sqlite_pool,
+        &opencode_config,
+        &state.channel_id,
+        channel_name.as_deref(),
+    )
+    .await;
+    let participants = recent_participants(state).await;
+    worker = worker
+        .with_preferences(preferences)
+        .with_channel_context(crate::opencode::ChannelContext::new(channel_name, participants));
+
+    let attachments = std::mem::take(&mut *state.pending_attachments.write().await);
+    if !attachments.is_empty() {
+        let cache = (opencode_config.attachment_cache_max_bytes > 0).then(|| {
+            crate::opencode::attachment_cache::AttachmentCache::new(
+                state.deps.sqlite_pool.clone(),
+                rc.attachment_cache_dir.clone(),
+                opencode_config.attachment_cache_max_bytes,
+                std::time::Duration::from_secs(opencode_config.attachment_cache_max_age_secs),
+            )
+        });
+        let attached = crate::opencode::attachments::attachment_parts(
+            state.deps.llm_manager.http_client(),
+            &attachments,
+            &opencode_config.attachment_policy(),
+            cache.as_ref(),
+        )
+        .await;
+        for rejection in attached.rejected {
+            let _ = state.response_tx.send(OutboundResponse::Text(rejection)).await;
+        }
+        worker = worker.with_files(attached.parts);
+    }
 
     let worker_id = worker.id;
 
@@ -1252,6 +2296,57 @@ where
     })
 }
 
+/// The sender's display name, falling back to their platform ID.
+fn sender_display_name(message: &InboundMessage) -> &str {
+    message.metadata
+        .get("sender_display_name")
+        .and_then(|v| v.as_str())
+        .unwrap_or(&message.sender_id)
+}
+
+/// `text` attributed to the sender of the message being handled, if the
+/// channel has a sender attribution template. Used for prompts handed to
+/// OpenCode workers; the logged message keeps its clean content.
+pub async fn attribute_prompt(state: &ChannelState, text: String) -> String {
+    let attribution = state
+        .deps
+        .runtime_config
+        .opencode
+        .load()
+        .sender_attribution(&state.channel_id);
+    let attribution = match attribution {
+        Ok(Some(attribution)) => attribution,
+        Ok(None) => return text,
+        Err(error) => {
+            tracing::warn!(%error, channel_id = %state.channel_id, "invalid sender attribution template");
+            return text;
+        }
+    };
+    match state.pending_sender.read().await.as_deref() {
+        Some(sender) => attribution.apply(sender, &text),
+        None => text,
+    }
+}
+
+/// Names of the users who wrote the channel's recent messages, in order of
+/// first appearance. Sent to workers as part of the channel context.
+async fn recent_participants(state: &ChannelState) -> Vec<String> {
+    let messages = match state.conversation_logger.load_recent(&state.channel_id, 50, false).await {
+        Ok(messages) => messages,
+        Err(error) => {
+            tracing::warn!(%error, channel_id = %state.channel_id, "failed to load recent participants");
+            return Vec::new();
+        }
+    };
+    let mut participants: Vec<String> = Vec::new();
+    for name in messages.into_iter().filter_map(|message| message.sender_name) {
+        if !participants.contains(&name) {
+            participants.push(name);
+        }
+    }
+    participants
+}
+
 /// Format a user message with sender attribution from message metadata.
 ///
 /// In multi-user channels, this lets the LLM distinguish who said what.
@@ -1261,10 +2356,7 @@ fn format_user_message(raw_text: &str, message: &InboundMessage) -> String {
         return raw_text.to_string();
     }
 
-    let display_name = message.metadata
-        .get("sender_display_name")
-        .and_then(|v| v.as_str())
-        .unwrap_or(&message.sender_id);
+    let display_name = sender_display_name(message);
 
     let bot_tag = if message.metadata.get("sender_is_bot").and_then(|v| v.as_bool()).unwrap_or(false) {
         " (bot)"
@@ -1307,12 +2399,51 @@ fn event_is_for_channel(event: &ProcessEvent, channel_id: &ChannelId) -> bool {
         ProcessEvent::WorkerStatus { channel_id: event_channel, .. } => {
             event_channel.as_ref() == Some(channel_id)
         }
+        ProcessEvent::WorkerPermissionBatch { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
+        ProcessEvent::WorkerQuestion { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
+        ProcessEvent::WorkerQuestionAnswered { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
+        ProcessEvent::WorkerToolError { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
+        ProcessEvent::WorkerTurnOutcome { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
+        ProcessEvent::WorkerRetryWarning { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
+        ProcessEvent::WorkerIndicator { channel_id: event_channel, .. } => {
+            event_channel.as_ref() == Some(channel_id)
+        }
         // Status block updates, tool events, etc. — match on agent_id which
         // is already filtered by the event bus subscription. Let them through.
         _ => true,
     }
 }
 
+/// Most messages held per channel during quiet hours. Later ones are
+/// turned away with a notice.
+const QUIET_QUEUE_MAX: usize = 50;
+
+/// Longest tool error posted to the channel, in characters.
+const TOOL_ERROR_MAX_CHARS: usize = 300;
+
+/// The first line of a tool error, shortened for a chat message.
+fn tool_error_summary(error: &str) -> String {
+    let line = error.lines().find(|line| !line.trim().is_empty()).unwrap_or("unknown error").trim();
+    if line.chars().count() <= TOOL_ERROR_MAX_CHARS {
+        return line.to_string();
+    }
+    let mut summary: String = line.chars().take(TOOL_ERROR_MAX_CHARS - 1).collect();
+    summary.push('…');
+    summary
+}
+
 /// Image MIME types we support for vision.
 const IMAGE_MIME_PREFIXES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
 
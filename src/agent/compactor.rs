@@ -4,6 +4,7 @@
 //! spawns compaction workers when thresholds are crossed. The LLM work (summarization
 //! + memory extraction) happens in the spawned worker, not here.
 
+use crate::conversation::history::{ConversationLogger, SummaryOverflow};
 use crate::error::Result;
 use crate::llm::SpacebotModel;
 use crate::{AgentDeps, ChannelId, ProcessType};
@@ -119,6 +120,7 @@ impl Compactor {
         tokio::spawn(async move {
             let result = run_compaction(
                 &deps,
+                &channel_id,
                 &compactor_prompt,
                 &history,
                 fraction,
@@ -183,6 +185,7 @@ impl Compactor {
 /// Run the actual compaction: summarize via LLM, extract memories, swap summary into history.
 async fn run_compaction(
     deps: &AgentDeps,
+    channel_id: &ChannelId,
     compactor_prompt: &str,
     history: &Arc<RwLock<Vec<Message>>>,
     fraction: f32,
@@ -239,6 +242,23 @@ async fn run_compaction(
         hist.insert(0, Message::from(summary_message));
     }
 
+    // 5. Persist the summary and keep the stored set bounded. The in-memory
+    // swap already happened, so a failed write only loses durability.
+    let logger = ConversationLogger::new(deps.sqlite_pool.clone())
+        .with_cipher(deps.runtime_config.history_cipher.clone());
+    let max_summaries = deps.runtime_config.compaction.load().max_summaries_per_channel;
+    if let Err(error) = logger
+        .save_compaction_summary(channel_id, &summary, remove_count as i64)
+        .await
+    {
+        tracing::warn!(%error, %channel_id, "failed to persist compaction summary");
+    } else if let Err(error) = logger
+        .cap_summaries_per_channel(channel_id, max_summaries, SummaryOverflow::Merge)
+        .await
+    {
+        tracing::warn!(%error, %channel_id, "failed to cap compaction summaries");
+    }
+
     Ok(remove_count)
 }
 
@@ -345,7 +345,8 @@ impl CortexChatSession {
 
     /// Load the last 50 messages from a channel as a formatted transcript.
     async fn load_channel_transcript(&self, channel_id: &str) -> Option<String> {
-        let logger = ProcessRunLogger::new(self.deps.sqlite_pool.clone());
+        let logger = ProcessRunLogger::new(self.deps.sqlite_pool.clone())
+            .with_cipher(self.deps.runtime_config.history_cipher.clone());
 
         match logger.load_channel_timeline(channel_id, 50, None).await {
             Ok(items) if !items.is_empty() => {
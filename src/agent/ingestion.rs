@@ -420,7 +420,8 @@ async fn process_chunk(
     let model = SpacebotModel::make(&deps.llm_manager, &model_name)
         .with_routing((**routing).clone());
 
-    let conversation_logger = crate::conversation::history::ConversationLogger::new(deps.sqlite_pool.clone());
+    let conversation_logger = crate::conversation::history::ConversationLogger::new(deps.sqlite_pool.clone())
+        .with_cipher(deps.runtime_config.history_cipher.clone());
     let channel_store = crate::conversation::ChannelStore::new(deps.sqlite_pool.clone());
     let tool_server: ToolServerHandle =
         crate::tools::create_branch_tool_server(deps.memory_search.clone(), conversation_logger, channel_store);
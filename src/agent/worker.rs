@@ -5,7 +5,7 @@ use crate::config::BrowserConfig;
 use crate::error::Result;
 use crate::llm::routing::is_context_overflow_error;
 use crate::llm::SpacebotModel;
-use crate::{WorkerId, ChannelId, ProcessId, ProcessType, AgentDeps};
+use crate::{WorkerId, WorkerInput, ChannelId, ProcessId, ProcessType, AgentDeps};
 use crate::hooks::SpacebotHook;
 use rig::agent::AgentBuilder;
 use rig::completion::{CompletionModel, Prompt};
@@ -45,7 +45,7 @@ pub struct Worker {
     /// System prompt loaded from prompts/WORKER.md.
     pub system_prompt: String,
     /// Input channel for interactive workers.
-    pub input_rx: Option<mpsc::Receiver<String>>,
+    pub input_rx: Option<mpsc::Receiver<WorkerInput>>,
     /// Browser automation config.
     pub browser_config: BrowserConfig,
     /// Directory for browser screenshots.
@@ -104,7 +104,7 @@ impl Worker {
         screenshot_dir: PathBuf,
         brave_search_key: Option<String>,
         logs_dir: PathBuf,
-    ) -> (Self, mpsc::Sender<String>) {
+    ) -> (Self, mpsc::Sender<WorkerInput>) {
         let id = Uuid::new_v4();
         let process_id = ProcessId::Worker(id);
         let hook = SpacebotHook::new(deps.agent_id.clone(), process_id, ProcessType::Worker, channel_id.clone(), deps.event_tx.clone());
@@ -276,6 +276,7 @@ impl Worker {
                 // Compact before follow-up if needed
                 self.maybe_compact_history(&mut history).await;
 
+                let follow_up = follow_up.text;
                 let mut follow_up_prompt = follow_up.clone();
                 let mut follow_up_overflow_retries = 0;
 
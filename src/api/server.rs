@@ -434,6 +434,7 @@ pub async fn start_http_server(
 
     let api_routes = Router::new()
         .route("/health", get(health))
+        .route("/metrics", get(metrics))
         .route("/status", get(status))
         .route("/overview", get(instance_overview))
         .route("/events", get(events_sse))
@@ -498,6 +499,25 @@ async fn health() -> Json<HealthResponse> {
     Json(HealthResponse { status: "ok" })
 }
 
+/// Prometheus scrape endpoint for OpenCode traffic, history cache lookups,
+/// and history write queues.
+async fn metrics(State(state): State<Arc<ApiState>>) -> impl IntoResponse {
+    let runtime_configs = state.runtime_configs.load();
+    let caches = runtime_configs.iter().filter_map(|(agent_id, runtime_config)| {
+        runtime_config.history_cache.as_deref().map(|cache| (agent_id.as_str(), cache))
+    });
+    let limiters = runtime_configs.iter().filter_map(|(agent_id, runtime_config)| {
+        runtime_config.history_writes.as_deref().map(|limiter| (agent_id.as_str(), limiter))
+    });
+    let mut body = crate::opencode::metrics::global().render();
+    body.push_str(&crate::conversation::cache::render_metrics(caches));
+    body.push_str(&crate::conversation::writes::render_metrics(limiters));
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        body,
+    )
+}
+
 async fn status(State(state): State<Arc<ApiState>>) -> Json<StatusResponse> {
     let uptime = state.started_at.elapsed();
     Json(StatusResponse {
@@ -875,8 +895,12 @@ async fn channel_messages(
     // Fetch one extra to determine if there are more pages
     let fetch_limit = limit + 1;
 
-    for (_agent_id, pool) in pools.iter() {
-        let logger = ProcessRunLogger::new(pool.clone());
+    let runtime_configs = state.runtime_configs.load();
+    for (agent_id, pool) in pools.iter() {
+        let cipher = runtime_configs
+            .get(agent_id)
+            .and_then(|rc| rc.history_cipher.clone());
+        let logger = ProcessRunLogger::new(pool.clone()).with_cipher(cipher);
         match logger.load_channel_timeline(&query.channel_id, fetch_limit, query.before.as_deref()).await {
             Ok(items) if !items.is_empty() => {
                 let has_more = items.len() as i64 > limit;
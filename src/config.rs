@@ -104,6 +104,69 @@ pub struct DefaultsConfig {
     pub opencode: OpenCodeConfig,
     /// Worker log mode: "errors_only", "all_separate", or "all_combined".
     pub worker_log_mode: crate::settings::WorkerLogMode,
+    pub redaction: RedactionConfig,
+    /// Base64-encoded 32-byte key for encrypting conversation content at rest.
+    /// Supports "env:VAR_NAME" references. Unset stores plaintext.
+    pub history_encryption_key: Option<String>,
+    /// Hugging Face `tokenizer.json` for exact token counts. Requires the
+    /// `bpe-tokenizer` feature; unset uses a character-based estimate.
+    pub tokenizer_path: Option<PathBuf>,
+    /// What resetting a channel does with its stored history.
+    pub reset_mode: crate::conversation::ResetMode,
+    /// Channels whose recent messages are cached in memory. 0 disables the
+    /// cache.
+    pub history_cache_channels: usize,
+    /// Recent messages cached per channel.
+    pub history_cache_messages: usize,
+    /// Fire-and-forget history writes allowed to run at once; the rest
+    /// queue. 0 removes the cap.
+    pub history_write_concurrency: usize,
+}
+
+impl DefaultsConfig {
+    /// Build the recent history cache, or `None` if it's disabled.
+    pub fn history_cache(&self) -> Option<crate::conversation::RecentHistoryCache> {
+        if self.history_cache_channels == 0 || self.history_cache_messages == 0 {
+            return None;
+        }
+        Some(crate::conversation::RecentHistoryCache::new(
+            self.history_cache_channels,
+            self.history_cache_messages,
+        ))
+    }
+
+    /// Build the history write limiter, or `None` if writes are uncapped.
+    pub fn history_write_limiter(&self) -> Option<crate::conversation::WriteLimiter> {
+        (self.history_write_concurrency > 0)
+            .then(|| crate::conversation::WriteLimiter::new(self.history_write_concurrency))
+    }
+
+    /// Build the conversation content cipher, or `None` if no key is set.
+    pub fn history_cipher(&self) -> anyhow::Result<Option<crate::conversation::ContentCipher>> {
+        self.history_encryption_key
+            .as_deref()
+            .map(crate::conversation::ContentCipher::from_base64_key)
+            .transpose()
+    }
+
+    /// Build the tokenizer used for token counts and budgets.
+    pub fn tokenizer(&self) -> anyhow::Result<Arc<dyn crate::conversation::Tokenizer>> {
+        let Some(path) = &self.tokenizer_path else {
+            return Ok(Arc::new(crate::conversation::CharTokenizer));
+        };
+
+        #[cfg(feature = "bpe-tokenizer")]
+        {
+            Ok(Arc::new(crate::conversation::tokenizer::HuggingFaceTokenizer::from_file(path)?))
+        }
+        #[cfg(not(feature = "bpe-tokenizer"))]
+        {
+            anyhow::bail!(
+                "tokenizer_path is set to {} but spacebot was built without the `bpe-tokenizer` feature",
+                path.display()
+            )
+        }
+    }
 }
 
 /// Compaction threshold configuration.
@@ -112,6 +175,9 @@ pub struct CompactionConfig {
     pub background_threshold: f32,
     pub aggressive_threshold: f32,
     pub emergency_threshold: f32,
+    /// Maximum persisted compaction summaries per channel. Older summaries
+    /// beyond the cap are merged into the oldest surviving one.
+    pub max_summaries_per_channel: usize,
 }
 
 /// Auto-branching memory persistence configuration.
@@ -142,10 +208,50 @@ impl Default for CompactionConfig {
             background_threshold: 0.80,
             aggressive_threshold: 0.85,
             emergency_threshold: 0.95,
+            max_summaries_per_channel: 10,
         }
     }
 }
 
+/// Secret redaction applied to message content before it's persisted.
+#[derive(Debug, Clone)]
+pub struct RedactionConfig {
+    /// Whether redaction runs at all.
+    pub enabled: bool,
+    /// Built-in rules to apply, by name (see `redact::BUILTIN_RULES`).
+    pub rules: Vec<String>,
+    /// Additional regex patterns. A `secret` capture group limits the
+    /// replacement to that group.
+    pub patterns: Vec<String>,
+    /// Channel IDs whose messages are stored verbatim.
+    pub disabled_channels: Vec<String>,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            rules: crate::conversation::redact::BUILTIN_RULES
+                .iter()
+                .map(|rule| rule.to_string())
+                .collect(),
+            patterns: Vec::new(),
+            disabled_channels: Vec::new(),
+        }
+    }
+}
+
+impl RedactionConfig {
+    /// Compile the configured rules, or `None` if redaction is disabled.
+    pub fn redactor(&self) -> anyhow::Result<Option<crate::conversation::Redactor>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        crate::conversation::Redactor::new(&self.rules, &self.patterns, &self.disabled_channels)
+            .map(Some)
+    }
+}
+
 /// Message coalescing configuration for handling rapid-fire messages.
 ///
 /// When enabled, messages arriving in quick succession are accumulated and
@@ -246,6 +352,239 @@ pub struct OpenCodeConfig {
     pub max_restart_retries: u32,
     /// Permission settings passed to OpenCode's config.
     pub permissions: crate::opencode::OpenCodePermissions,
+    /// Named permission sets that channels can opt into (e.g. "readonly").
+    pub permission_profiles: HashMap<String, crate::opencode::OpenCodePermissions>,
+    /// Channel ID → permission profile name. Unlisted channels use `permissions`.
+    pub channel_permissions: HashMap<String, String>,
+    /// Provider ID → credentials passed to every OpenCode server.
+    pub providers: HashMap<String, crate::opencode::OpenCodeProvider>,
+    /// What interactive workers do when a new message arrives mid-turn.
+    pub follow_up_mode: crate::opencode::FollowUpMode,
+    /// How long an interactive worker waits for more follow-ups to merge
+    /// into one prompt, in milliseconds. 0 sends each follow-up on its own.
+    pub prompt_coalesce_window_ms: u64,
+    /// Largest merged follow-up prompt, in characters.
+    pub prompt_coalesce_max_chars: usize,
+    /// Shortest gap between two refreshes of the typing indicator while a
+    /// turn runs, in milliseconds. State changes are always sent at once.
+    pub typing_indicator_debounce_ms: u64,
+    /// Provider retries in one turn before the channel is warned the turn
+    /// may fail. 0 never warns.
+    pub retry_warning_attempts: u32,
+    /// Whether permission prompts are auto-approved or asked in the channel.
+    pub permission_mode: crate::opencode::PermissionMode,
+    /// How long to collect permission requests into one prompt, in milliseconds.
+    pub permission_batch_window_ms: u64,
+    /// Seconds an asked permission waits for a reply before it's rejected.
+    /// 0 waits forever.
+    pub permission_timeout_secs: u64,
+    /// Per-permission-type overrides of `permission_timeout_secs` (e.g. "bash").
+    pub permission_timeouts: HashMap<String, u64>,
+    /// Seconds an asked question waits for a reply before the default answer
+    /// is sent. 0 waits forever.
+    pub question_timeout_secs: u64,
+    /// How default answers to questions are picked.
+    pub question_default: crate::opencode::questions::QuestionDefault,
+    /// Stream worker replies into the channel as an edited message.
+    pub stream_replies: bool,
+    /// URL that receives a JSON POST on every OpenCode session error.
+    /// Supports "env:VAR_NAME" references.
+    pub error_webhook_url: Option<String>,
+    /// URLs that each receive a JSON POST with every finished worker reply.
+    /// Supports "env:VAR_NAME" references.
+    pub reply_webhook_urls: Vec<String>,
+    /// Build prompts and echo them to the channel instead of sending them.
+    pub dry_run: bool,
+    /// Largest chat attachment passed to OpenCode, in bytes. Attachments are
+    /// inlined as `data:` URLs, so this also caps inline file parts.
+    pub attachment_max_bytes: u64,
+    /// MIME types of chat attachments passed to OpenCode. `type/*` matches a
+    /// whole family.
+    pub attachment_mime_types: Vec<String>,
+    /// Cache attachments on disk by content hash, up to this many bytes in
+    /// total, and pass them as `file://` URLs. 0 disables.
+    pub attachment_cache_max_bytes: u64,
+    /// Evict cached attachments unused for this long. 0 keeps them until the
+    /// size limit pushes them out.
+    pub attachment_cache_max_age_secs: u64,
+    /// Semver requirement for the OpenCode version (e.g. ">=1.1, <2").
+    /// Servers outside it are logged as unsupported.
+    pub supported_versions: String,
+    /// Compact a channel's history in the background once this many messages
+    /// have been logged since its last compaction. 0 disables.
+    pub compact_after_turns: usize,
+    /// Shortest compaction summary that's saved, in characters. Shorter
+    /// summaries are rejected and the raw turns kept.
+    pub compaction_summary_min_chars: usize,
+    /// Largest fraction of transcript lines a compaction summary may copy
+    /// verbatim (0.0–1.0) before it's rejected as an echo.
+    pub compaction_summary_max_copied: f64,
+    /// Longest message a prompt carries inline, in characters. 0 disables.
+    pub max_prompt_chars: usize,
+    /// What happens to messages over `max_prompt_chars`.
+    pub prompt_overflow: crate::opencode::limits::PromptOverflow,
+    /// Channel ID → prompt limit overriding `max_prompt_chars` and
+    /// `prompt_overflow`.
+    pub channel_prompt_limits: HashMap<String, crate::opencode::limits::PromptLimit>,
+    /// Channel ID → template attributing each prompt to its sender, e.g.
+    /// `"[{sender_name}]: {content}"`. Unlisted channels aren't attributed.
+    pub channel_sender_attribution: HashMap<String, String>,
+    /// Forget a channel's session once no message has been logged for this
+    /// long, archiving its transcript first. 0 disables.
+    pub session_idle_timeout_secs: u64,
+    /// How often the reaper looks for idle sessions.
+    pub session_reap_interval_secs: u64,
+    /// Also delete reaped sessions from the OpenCode server.
+    pub delete_idle_sessions: bool,
+    /// Whether the bot engages in channels on neither access list.
+    pub channel_access_default: crate::opencode::access::AccessRule,
+    /// Channel IDs the bot engages in.
+    pub allowed_channels: Vec<String>,
+    /// Channel IDs the bot stays silent in. Wins over `allowed_channels`.
+    pub denied_channels: Vec<String>,
+    /// Messages one sender may send across all channels per
+    /// `flood_window_secs` before the rest are dropped. 0 disables.
+    pub flood_max_messages: usize,
+    /// Sliding window for `flood_max_messages`, in seconds.
+    pub flood_window_secs: u64,
+    /// Sender IDs served during quiet hours (`!quiet`).
+    pub quiet_hours_bypass_users: Vec<String>,
+    /// Sender IDs allowed to change a channel's reply rule (`!access`) and
+    /// quiet hours (`!quiet`). Empty means nobody can.
+    pub admin_users: Vec<String>,
+    /// Raw SSE envelopes kept in memory per channel for debugging. 0
+    /// disables. Read at startup.
+    pub sse_debug_buffer_size: usize,
+    /// Write a channel's buffered envelopes to the `sse_debug` table when a
+    /// turn fails.
+    pub sse_debug_persist: bool,
+    /// Record every part of OpenCode's replies (text, tool steps, step
+    /// boundaries) in order to the `message_parts` table.
+    pub persist_message_parts: bool,
+    /// How often a streamed reply is written to history while it grows, in
+    /// milliseconds, so a crash mid-turn doesn't lose it. 0 logs it only
+    /// when the turn ends.
+    pub reply_checkpoint_interval_ms: u64,
+    /// System prompt for channels without one of their own (`!persona`).
+    /// `{channel}`, `{channel_id}`, and `{date}` are filled in.
+    pub default_system_prompt: Option<String>,
+    /// Longest system prompt `!persona` accepts, in characters.
+    pub system_prompt_max_chars: usize,
+    /// Model (`provider/model`) for channels that haven't picked one with
+    /// `!model`. Unset leaves it to the OpenCode server.
+    pub default_model: Option<String>,
+    /// Consecutive failed OpenCode calls, within `breaker_window_secs`, that
+    /// open the circuit breaker. 0 disables. Read at startup.
+    pub breaker_failure_threshold: u32,
+    /// Window for `breaker_failure_threshold`, in seconds.
+    pub breaker_window_secs: u64,
+    /// How long the breaker stays open before probing again, in seconds.
+    pub breaker_cooldown_secs: u64,
+    /// How long the same prompt text to the same session counts as a
+    /// duplicate send, in seconds, whatever its key. Zero (the default)
+    /// deduplicates by idempotency key only.
+    pub prompt_dedup_window_secs: u64,
+    /// Most bytes of tool output `!output` posts.
+    pub tool_output_max_bytes: usize,
+}
+
+impl OpenCodeConfig {
+    /// The default permissions plus per-channel profiles, as the server pool uses them.
+    pub fn permission_profiles(&self) -> crate::opencode::PermissionProfiles {
+        crate::opencode::PermissionProfiles {
+            default: self.permissions.clone(),
+            profiles: self.permission_profiles.clone(),
+            channels: self.channel_permissions.clone(),
+        }
+    }
+
+    /// Permission reply timeouts, as the worker uses them.
+    pub fn permission_timeouts(&self) -> crate::opencode::permissions::PermissionTimeouts {
+        let timeout = |secs: u64| (secs > 0).then(|| std::time::Duration::from_secs(secs));
+        crate::opencode::permissions::PermissionTimeouts {
+            default: timeout(self.permission_timeout_secs),
+            per_type: self
+                .permission_timeouts
+                .iter()
+                .filter_map(|(permission, secs)| Some((permission.clone(), timeout(*secs)?)))
+                .collect(),
+        }
+    }
+
+    /// Parsed `supported_versions`, as the server pool uses it.
+    pub fn version_requirement(&self) -> anyhow::Result<semver::VersionReq> {
+        semver::VersionReq::parse(&self.supported_versions).with_context(|| {
+            format!("invalid opencode supported_versions '{}'", self.supported_versions)
+        })
+    }
+
+    /// The prompt limit for a channel, or `None` if its messages aren't limited.
+    pub fn prompt_limit(&self, channel_id: &str) -> Option<crate::opencode::limits::PromptLimit> {
+        let limit = self.channel_prompt_limits.get(channel_id).copied().unwrap_or(
+            crate::opencode::limits::PromptLimit {
+                max_chars: self.max_prompt_chars,
+                overflow: self.prompt_overflow,
+            },
+        );
+        (limit.max_chars > 0).then_some(limit)
+    }
+
+    /// The sender attribution template for a channel, or `None` if its
+    /// prompts aren't attributed.
+    pub fn sender_attribution(
+        &self,
+        channel_id: &str,
+    ) -> anyhow::Result<Option<crate::opencode::attribution::SenderAttribution>> {
+        self.channel_sender_attribution
+            .get(channel_id)
+            .map(|template| {
+                template
+                    .parse()
+                    .with_context(|| format!("invalid sender attribution template for channel '{channel_id}'"))
+            })
+            .transpose()
+    }
+
+    /// The thresholds compaction summaries are checked against.
+    pub fn summary_limits(&self) -> crate::opencode::compaction::SummaryLimits {
+        crate::opencode::compaction::SummaryLimits {
+            min_chars: self.compaction_summary_min_chars,
+            max_copied: self.compaction_summary_max_copied,
+        }
+    }
+
+    /// Which chat attachments are passed to OpenCode, as the channel uses it.
+    pub fn attachment_policy(&self) -> crate::opencode::attachments::AttachmentPolicy {
+        crate::opencode::attachments::AttachmentPolicy {
+            max_bytes: self.attachment_max_bytes,
+            allowed_mime_types: self.attachment_mime_types.clone(),
+        }
+    }
+
+    /// The configured channel access lists, as the channel checks them.
+    pub fn channel_access_policy(&self) -> crate::opencode::access::ChannelAccessPolicy {
+        crate::opencode::access::ChannelAccessPolicy {
+            default: self.channel_access_default,
+            allowed: self.allowed_channels.iter().cloned().collect(),
+            denied: self.denied_channels.iter().cloned().collect(),
+        }
+    }
+
+    /// The per-sender message limit, as the channel checks it.
+    pub fn flood_limit(&self) -> crate::opencode::flood::FloodLimit {
+        crate::opencode::flood::FloodLimit {
+            max_messages: self.flood_max_messages,
+            window: std::time::Duration::from_secs(self.flood_window_secs.max(1)),
+        }
+    }
+
+    pub fn breaker_config(&self) -> crate::opencode::breaker::BreakerConfig {
+        crate::opencode::breaker::BreakerConfig {
+            failure_threshold: self.breaker_failure_threshold,
+            window: std::time::Duration::from_secs(self.breaker_window_secs.max(1)),
+            cooldown: std::time::Duration::from_secs(self.breaker_cooldown_secs),
+        }
+    }
 }
 
 impl Default for OpenCodeConfig {
@@ -257,6 +596,63 @@ impl Default for OpenCodeConfig {
             server_startup_timeout_secs: 30,
             max_restart_retries: 5,
             permissions: crate::opencode::OpenCodePermissions::default(),
+            permission_profiles: HashMap::new(),
+            providers: HashMap::new(),
+            channel_permissions: HashMap::new(),
+            follow_up_mode: crate::opencode::FollowUpMode::default(),
+            prompt_coalesce_window_ms: 0,
+            prompt_coalesce_max_chars: 4000,
+            typing_indicator_debounce_ms: 5000,
+            retry_warning_attempts: 3,
+            permission_mode: crate::opencode::PermissionMode::default(),
+            permission_batch_window_ms: 300,
+            permission_timeout_secs: 300,
+            permission_timeouts: HashMap::new(),
+            question_timeout_secs: 300,
+            question_default: crate::opencode::questions::QuestionDefault::default(),
+            stream_replies: false,
+            error_webhook_url: None,
+            reply_webhook_urls: Vec::new(),
+            dry_run: false,
+            attachment_max_bytes: 10 * 1024 * 1024,
+            attachment_mime_types: vec![
+                "image/*".to_string(),
+                "text/*".to_string(),
+                "application/json".to_string(),
+                "application/pdf".to_string(),
+            ],
+            attachment_cache_max_bytes: 0,
+            attachment_cache_max_age_secs: 7 * 24 * 60 * 60,
+            supported_versions: ">=1.0.0".to_string(),
+            compact_after_turns: 0,
+            compaction_summary_min_chars: 40,
+            compaction_summary_max_copied: 0.5,
+            max_prompt_chars: 0,
+            prompt_overflow: crate::opencode::limits::PromptOverflow::default(),
+            channel_prompt_limits: HashMap::new(),
+            channel_sender_attribution: HashMap::new(),
+            session_idle_timeout_secs: 0,
+            session_reap_interval_secs: 300,
+            delete_idle_sessions: false,
+            channel_access_default: crate::opencode::access::AccessRule::default(),
+            allowed_channels: Vec::new(),
+            denied_channels: Vec::new(),
+            flood_max_messages: 0,
+            flood_window_secs: 60,
+            quiet_hours_bypass_users: Vec::new(),
+            admin_users: Vec::new(),
+            sse_debug_buffer_size: 0,
+            sse_debug_persist: false,
+            persist_message_parts: false,
+            reply_checkpoint_interval_ms: 2000,
+            default_system_prompt: None,
+            system_prompt_max_chars: 4000,
+            default_model: None,
+            breaker_failure_threshold: 5,
+            breaker_window_secs: 60,
+            breaker_cooldown_secs: 30,
+            prompt_dedup_window_secs: 0,
+            tool_output_max_bytes: 1024 * 1024,
         }
     }
 }
@@ -386,6 +782,13 @@ impl Default for DefaultsConfig {
             cron: Vec::new(),
             opencode: OpenCodeConfig::default(),
             worker_log_mode: crate::settings::WorkerLogMode::default(),
+            redaction: RedactionConfig::default(),
+            history_encryption_key: None,
+            tokenizer_path: None,
+            reset_mode: crate::conversation::ResetMode::default(),
+            history_cache_channels: 256,
+            history_cache_messages: 100,
+            history_write_concurrency: 16,
         }
     }
 }
@@ -896,6 +1299,23 @@ struct TomlDefaultsConfig {
     brave_search_key: Option<String>,
     opencode: Option<TomlOpenCodeConfig>,
     worker_log_mode: Option<String>,
+    redaction: Option<TomlRedactionConfig>,
+    history_encryption_key: Option<String>,
+    tokenizer_path: Option<String>,
+    reset_mode: Option<String>,
+    history_cache_channels: Option<usize>,
+    history_cache_messages: Option<usize>,
+    history_write_concurrency: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct TomlRedactionConfig {
+    enabled: Option<bool>,
+    rules: Option<Vec<String>>,
+    #[serde(default)]
+    patterns: Vec<String>,
+    #[serde(default)]
+    disabled_channels: Vec<String>,
 }
 
 #[derive(Deserialize, Default)]
@@ -938,6 +1358,7 @@ struct TomlCompactionConfig {
     background_threshold: Option<f32>,
     aggressive_threshold: Option<f32>,
     emergency_threshold: Option<f32>,
+    max_summaries_per_channel: Option<usize>,
 }
 
 #[derive(Deserialize)]
@@ -972,6 +1393,75 @@ struct TomlOpenCodeConfig {
     server_startup_timeout_secs: Option<u64>,
     max_restart_retries: Option<u32>,
     permissions: Option<TomlOpenCodePermissions>,
+    #[serde(default)]
+    permission_profiles: HashMap<String, TomlOpenCodePermissions>,
+    #[serde(default)]
+    channel_permissions: HashMap<String, String>,
+    providers: Option<HashMap<String, TomlOpenCodeProvider>>,
+    follow_up_mode: Option<String>,
+    prompt_coalesce_window_ms: Option<u64>,
+    prompt_coalesce_max_chars: Option<usize>,
+    typing_indicator_debounce_ms: Option<u64>,
+    retry_warning_attempts: Option<u32>,
+    permission_mode: Option<String>,
+    permission_batch_window_ms: Option<u64>,
+    permission_timeout_secs: Option<u64>,
+    #[serde(default)]
+    permission_timeouts: HashMap<String, u64>,
+    question_timeout_secs: Option<u64>,
+    question_default: Option<String>,
+    stream_replies: Option<bool>,
+    error_webhook_url: Option<String>,
+    reply_webhook_urls: Option<Vec<String>>,
+    dry_run: Option<bool>,
+    attachment_max_bytes: Option<u64>,
+    attachment_mime_types: Option<Vec<String>>,
+    attachment_cache_max_bytes: Option<u64>,
+    attachment_cache_max_age_secs: Option<u64>,
+    supported_versions: Option<String>,
+    compact_after_turns: Option<usize>,
+    compaction_summary_min_chars: Option<usize>,
+    compaction_summary_max_copied: Option<f64>,
+    max_prompt_chars: Option<usize>,
+    prompt_overflow: Option<String>,
+    #[serde(default)]
+    channel_prompt_limits: HashMap<String, TomlPromptLimit>,
+    #[serde(default)]
+    channel_sender_attribution: HashMap<String, String>,
+    session_idle_timeout_secs: Option<u64>,
+    session_reap_interval_secs: Option<u64>,
+    delete_idle_sessions: Option<bool>,
+    channel_access_default: Option<String>,
+    allowed_channels: Option<Vec<String>>,
+    denied_channels: Option<Vec<String>>,
+    flood_max_messages: Option<usize>,
+    flood_window_secs: Option<u64>,
+    quiet_hours_bypass_users: Option<Vec<String>>,
+    admin_users: Option<Vec<String>>,
+    sse_debug_buffer_size: Option<usize>,
+    sse_debug_persist: Option<bool>,
+    persist_message_parts: Option<bool>,
+    reply_checkpoint_interval_ms: Option<u64>,
+    default_system_prompt: Option<String>,
+    system_prompt_max_chars: Option<usize>,
+    default_model: Option<String>,
+    breaker_failure_threshold: Option<u32>,
+    breaker_window_secs: Option<u64>,
+    breaker_cooldown_secs: Option<u64>,
+    prompt_dedup_window_secs: Option<u64>,
+    tool_output_max_bytes: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct TomlPromptLimit {
+    max_chars: Option<usize>,
+    overflow: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TomlOpenCodeProvider {
+    api_key: Option<String>,
+    base_url: Option<String>,
 }
 
 #[derive(Deserialize)]
@@ -979,6 +1469,8 @@ struct TomlOpenCodePermissions {
     edit: Option<String>,
     bash: Option<String>,
     webfetch: Option<String>,
+    #[serde(default)]
+    tools: HashMap<String, String>,
 }
 
 #[derive(Deserialize)]
@@ -1225,10 +1717,18 @@ impl Config {
             cron: Vec::new(),
         }];
 
+        let defaults = DefaultsConfig {
+            history_encryption_key: std::env::var("SPACEBOT_HISTORY_ENCRYPTION_KEY").ok(),
+            ..DefaultsConfig::default()
+        };
+        defaults
+            .history_cipher()
+            .map_err(|error| ConfigError::Invalid(error.to_string()))?;
+
         Ok(Self {
             instance_dir: instance_dir.to_path_buf(),
             llm,
-            defaults: DefaultsConfig::default(),
+            defaults,
             agents,
             messaging: MessagingConfig::default(),
             bindings: Vec::new(),
@@ -1342,6 +1842,9 @@ impl Config {
                     emergency_threshold: c
                         .emergency_threshold
                         .unwrap_or(base_defaults.compaction.emergency_threshold),
+                    max_summaries_per_channel: c
+                        .max_summaries_per_channel
+                        .unwrap_or(base_defaults.compaction.max_summaries_per_channel),
                 })
                 .unwrap_or(base_defaults.compaction),
             memory_persistence: toml
@@ -1449,12 +1952,64 @@ impl Config {
             opencode: toml
                 .defaults
                 .opencode
-                .map(|oc| {
+                .map(|oc| -> Result<OpenCodeConfig> {
                     let base = &base_defaults.opencode;
                     let path_raw = oc.path.unwrap_or_else(|| base.path.clone());
                     let resolved_path =
                         resolve_env_value(&path_raw).unwrap_or_else(|| base.path.clone());
-                    OpenCodeConfig {
+                    let permissions = oc
+                        .permissions
+                        .map(|p| crate::opencode::OpenCodePermissions {
+                            edit: p.edit.unwrap_or_else(|| base.permissions.edit.clone()),
+                            bash: p.bash.unwrap_or_else(|| base.permissions.bash.clone()),
+                            webfetch: p
+                                .webfetch
+                                .unwrap_or_else(|| base.permissions.webfetch.clone()),
+                            tools: base.permissions.tools.clone(),
+                        }
+                        .with_tools(p.tools))
+                        .unwrap_or_else(|| base.permissions.clone());
+                    // Profiles fill unset keys from the instance-wide permissions,
+                    // and their per-tool entries replace the instance-wide ones.
+                    let permission_profiles = oc
+                        .permission_profiles
+                        .into_iter()
+                        .map(|(name, p)| {
+                            let profile = crate::opencode::OpenCodePermissions {
+                                edit: p.edit.unwrap_or_else(|| permissions.edit.clone()),
+                                bash: p.bash.unwrap_or_else(|| permissions.bash.clone()),
+                                webfetch: p
+                                    .webfetch
+                                    .unwrap_or_else(|| permissions.webfetch.clone()),
+                                tools: permissions.tools.clone(),
+                            }
+                            .with_tools(p.tools);
+                            (name, profile)
+                        })
+                        .collect();
+                    let max_prompt_chars = oc.max_prompt_chars.unwrap_or(base.max_prompt_chars);
+                    let prompt_overflow = oc
+                        .prompt_overflow
+                        .as_deref()
+                        .and_then(|s| s.parse().ok())
+                        .unwrap_or(base.prompt_overflow);
+                    // Unset keys in a channel's limit fall back to the defaults.
+                    let channel_prompt_limits = oc
+                        .channel_prompt_limits
+                        .into_iter()
+                        .map(|(channel_id, limit)| {
+                            let limit = crate::opencode::limits::PromptLimit {
+                                max_chars: limit.max_chars.unwrap_or(max_prompt_chars),
+                                overflow: limit
+                                    .overflow
+                                    .as_deref()
+                                    .and_then(|s| s.parse().ok())
+                                    .unwrap_or(prompt_overflow),
+                            };
+                            (channel_id, limit)
+                        })
+                        .collect();
+                    Ok(OpenCodeConfig {
                         enabled: oc.enabled.unwrap_or(base.enabled),
                         path: resolved_path,
                         max_servers: oc.max_servers.unwrap_or(base.max_servers),
@@ -1464,18 +2019,154 @@ impl Config {
                         max_restart_retries: oc
                             .max_restart_retries
                             .unwrap_or(base.max_restart_retries),
-                        permissions: oc
-                            .permissions
-                            .map(|p| crate::opencode::OpenCodePermissions {
-                                edit: p.edit.unwrap_or_else(|| base.permissions.edit.clone()),
-                                bash: p.bash.unwrap_or_else(|| base.permissions.bash.clone()),
-                                webfetch: p
-                                    .webfetch
-                                    .unwrap_or_else(|| base.permissions.webfetch.clone()),
+                        permissions,
+                        permission_profiles,
+                        channel_permissions: oc.channel_permissions,
+                        providers: oc
+                            .providers
+                            .map(|providers| {
+                                providers
+                                    .into_iter()
+                                    .map(|(id, provider)| {
+                                        let provider = crate::opencode::OpenCodeProvider {
+                                            api_key: provider.api_key.as_deref().and_then(resolve_env_value),
+                                            base_url: provider.base_url,
+                                        };
+                                        (id, provider)
+                                    })
+                                    .collect()
                             })
-                            .unwrap_or_else(|| base.permissions.clone()),
-                    }
+                            .unwrap_or_else(|| base.providers.clone()),
+                        follow_up_mode: oc
+                            .follow_up_mode
+                            .as_deref()
+                            .map(str::parse)
+                            .transpose()
+                            .map_err(ConfigError::Invalid)?
+                            .unwrap_or(base.follow_up_mode),
+                        prompt_coalesce_window_ms: oc
+                            .prompt_coalesce_window_ms
+                            .unwrap_or(base.prompt_coalesce_window_ms),
+                        prompt_coalesce_max_chars: oc
+                            .prompt_coalesce_max_chars
+                            .unwrap_or(base.prompt_coalesce_max_chars),
+                        typing_indicator_debounce_ms: oc
+                            .typing_indicator_debounce_ms
+                            .unwrap_or(base.typing_indicator_debounce_ms),
+                        retry_warning_attempts: oc
+                            .retry_warning_attempts
+                            .unwrap_or(base.retry_warning_attempts),
+                        // A typo here must not fall back to auto-approving.
+                        permission_mode: oc
+                            .permission_mode
+                            .as_deref()
+                            .map(str::parse)
+                            .transpose()
+                            .map_err(ConfigError::Invalid)?
+                            .unwrap_or(base.permission_mode),
+                        permission_batch_window_ms: oc
+                            .permission_batch_window_ms
+                            .unwrap_or(base.permission_batch_window_ms),
+                        permission_timeout_secs: oc
+                            .permission_timeout_secs
+                            .unwrap_or(base.permission_timeout_secs),
+                        permission_timeouts: oc.permission_timeouts,
+                        question_timeout_secs: oc
+                            .question_timeout_secs
+                            .unwrap_or(base.question_timeout_secs),
+                        question_default: oc
+                            .question_default
+                            .as_deref()
+                            .map(str::parse)
+                            .transpose()
+                            .map_err(ConfigError::Invalid)?
+                            .unwrap_or_else(|| base.question_default.clone()),
+                        stream_replies: oc.stream_replies.unwrap_or(base.stream_replies),
+                        error_webhook_url: oc
+                            .error_webhook_url
+                            .as_deref()
+                            .and_then(resolve_env_value)
+                            .or_else(|| base.error_webhook_url.clone()),
+                        reply_webhook_urls: oc
+                            .reply_webhook_urls
+                            .map(|urls| urls.iter().filter_map(|url| resolve_env_value(url)).collect())
+                            .unwrap_or_else(|| base.reply_webhook_urls.clone()),
+                        dry_run: oc.dry_run.unwrap_or(base.dry_run),
+                        attachment_max_bytes: oc
+                            .attachment_max_bytes
+                            .unwrap_or(base.attachment_max_bytes),
+                        attachment_mime_types: oc
+                            .attachment_mime_types
+                            .unwrap_or_else(|| base.attachment_mime_types.clone()),
+                        attachment_cache_max_bytes: oc
+                            .attachment_cache_max_bytes
+                            .unwrap_or(base.attachment_cache_max_bytes),
+                        attachment_cache_max_age_secs: oc
+                            .attachment_cache_max_age_secs
+                            .unwrap_or(base.attachment_cache_max_age_secs),
+                        supported_versions: oc
+                            .supported_versions
+                            .unwrap_or_else(|| base.supported_versions.clone()),
+                        compact_after_turns: oc.compact_after_turns.unwrap_or(base.compact_after_turns),
+                        compaction_summary_min_chars: oc
+                            .compaction_summary_min_chars
+                            .unwrap_or(base.compaction_summary_min_chars),
+                        compaction_summary_max_copied: oc
+                            .compaction_summary_max_copied
+                            .unwrap_or(base.compaction_summary_max_copied),
+                        max_prompt_chars,
+                        prompt_overflow,
+                        channel_prompt_limits,
+                        channel_sender_attribution: oc.channel_sender_attribution,
+                        session_idle_timeout_secs: oc
+                            .session_idle_timeout_secs
+                            .unwrap_or(base.session_idle_timeout_secs),
+                        session_reap_interval_secs: oc
+                            .session_reap_interval_secs
+                            .unwrap_or(base.session_reap_interval_secs),
+                        delete_idle_sessions: oc.delete_idle_sessions.unwrap_or(base.delete_idle_sessions),
+                        channel_access_default: oc
+                            .channel_access_default
+                            .as_deref()
+                            .and_then(|s| s.parse().ok())
+                            .unwrap_or(base.channel_access_default),
+                        allowed_channels: oc
+                            .allowed_channels
+                            .unwrap_or_else(|| base.allowed_channels.clone()),
+                        denied_channels: oc
+                            .denied_channels
+                            .unwrap_or_else(|| base.denied_channels.clone()),
+                        flood_max_messages: oc.flood_max_messages.unwrap_or(base.flood_max_messages),
+                        flood_window_secs: oc.flood_window_secs.unwrap_or(base.flood_window_secs),
+                        quiet_hours_bypass_users: oc
+                            .quiet_hours_bypass_users
+                            .unwrap_or_else(|| base.quiet_hours_bypass_users.clone()),
+                        admin_users: oc.admin_users.unwrap_or_else(|| base.admin_users.clone()),
+                        sse_debug_buffer_size: oc.sse_debug_buffer_size.unwrap_or(base.sse_debug_buffer_size),
+                        sse_debug_persist: oc.sse_debug_persist.unwrap_or(base.sse_debug_persist),
+                        persist_message_parts: oc.persist_message_parts.unwrap_or(base.persist_message_parts),
+                        reply_checkpoint_interval_ms: oc
+                            .reply_checkpoint_interval_ms
+                            .unwrap_or(base.reply_checkpoint_interval_ms),
+                        default_system_prompt: oc
+                            .default_system_prompt
+                            .or_else(|| base.default_system_prompt.clone()),
+                        system_prompt_max_chars: oc
+                            .system_prompt_max_chars
+                            .unwrap_or(base.system_prompt_max_chars),
+                        default_model: oc.default_model.or_else(|| base.default_model.clone()),
+                        breaker_failure_threshold: oc
+                            .breaker_failure_threshold
+                            .unwrap_or(base.breaker_failure_threshold),
+                        breaker_window_secs: oc.breaker_window_secs.unwrap_or(base.breaker_window_secs),
+                        breaker_cooldown_secs: oc.breaker_cooldown_secs.unwrap_or(base.breaker_cooldown_secs),
+                        prompt_dedup_window_secs: oc
+                            .prompt_dedup_window_secs
+                            .unwrap_or(base.prompt_dedup_window_secs),
+                        tool_output_max_bytes: oc.tool_output_max_bytes.unwrap_or(base.tool_output_max_bytes),
+                    })
                 })
+                .transpose()?
                 .unwrap_or_else(|| base_defaults.opencode.clone()),
             worker_log_mode: toml
                 .defaults
@@ -1483,8 +2174,83 @@ impl Config {
                 .as_deref()
                 .and_then(|s| s.parse().ok())
                 .unwrap_or(base_defaults.worker_log_mode),
+            redaction: toml
+                .defaults
+                .redaction
+                .map(|r| RedactionConfig {
+                    enabled: r.enabled.unwrap_or(base_defaults.redaction.enabled),
+                    rules: r.rules.unwrap_or_else(|| base_defaults.redaction.rules.clone()),
+                    patterns: r.patterns,
+                    disabled_channels: r.disabled_channels,
+                })
+                .unwrap_or_else(|| base_defaults.redaction.clone()),
+            history_encryption_key: toml
+                .defaults
+                .history_encryption_key
+                .as_deref()
+                .and_then(resolve_env_value)
+                .or_else(|| std::env::var("SPACEBOT_HISTORY_ENCRYPTION_KEY").ok()),
+            tokenizer_path: toml
+                .defaults
+                .tokenizer_path
+                .as_deref()
+                .and_then(resolve_env_value)
+                .map(PathBuf::from),
+            reset_mode: toml
+                .defaults
+                .reset_mode
+                .as_deref()
+                .map(str::parse)
+                .transpose()
+                .map_err(ConfigError::Invalid)?
+                .unwrap_or(base_defaults.reset_mode),
+            history_cache_channels: toml
+                .defaults
+                .history_cache_channels
+                .unwrap_or(base_defaults.history_cache_channels),
+            history_cache_messages: toml
+                .defaults
+                .history_cache_messages
+                .unwrap_or(base_defaults.history_cache_messages),
+            history_write_concurrency: toml
+                .defaults
+                .history_write_concurrency
+                .unwrap_or(base_defaults.history_write_concurrency),
         };
 
+        defaults
+            .opencode
+            .permission_profiles()
+            .validate()
+            .map_err(|error| ConfigError::Invalid(error.to_string()))?;
+        crate::opencode::types::validate_providers(&defaults.opencode.providers)
+            .map_err(|error| ConfigError::Invalid(error.to_string()))?;
+        defaults
+            .opencode
+            .version_requirement()
+            .map_err(|error| ConfigError::Invalid(format!("{error:#}")))?;
+        defaults
+            .opencode
+            .summary_limits()
+            .validate()
+            .map_err(|error| ConfigError::Invalid(error.to_string()))?;
+        for channel_id in defaults.opencode.channel_sender_attribution.keys() {
+            defaults
+                .opencode
+                .sender_attribution(channel_id)
+                .map_err(|error| ConfigError::Invalid(format!("{error:#}")))?;
+        }
+        defaults
+            .redaction
+            .redactor()
+            .map_err(|error| ConfigError::Invalid(error.to_string()))?;
+        defaults
+            .history_cipher()
+            .map_err(|error| ConfigError::Invalid(error.to_string()))?;
+        defaults
+            .tokenizer()
+            .map_err(|error| ConfigError::Invalid(format!("{error:#}")))?;
+
         let mut agents: Vec<AgentConfig> = toml
             .agents
             .into_iter()
@@ -1530,6 +2296,9 @@ impl Config {
                         emergency_threshold: c
                             .emergency_threshold
                             .unwrap_or(defaults.compaction.emergency_threshold),
+                        max_summaries_per_channel: c
+                            .max_summaries_per_channel
+                            .unwrap_or(defaults.compaction.max_summaries_per_channel),
                     }),
                     memory_persistence: a.memory_persistence.map(|mp| MemoryPersistenceConfig {
                         enabled: mp.enabled.unwrap_or(defaults.memory_persistence.enabled),
@@ -1608,6 +2377,14 @@ impl Config {
             })
             .collect();
 
+        for agent in &agents {
+            for job in &agent.cron {
+                crate::cron::scheduler::cron_channel_id(&job.id).map_err(|error| {
+                    ConfigError::Invalid(format!("agent {}: invalid cron job ID {:?}: {error}", agent.id, job.id))
+                })?;
+            }
+        }
+
         if agents.is_empty() {
             agents.push(AgentConfig {
                 id: "main".into(),
@@ -1751,6 +2528,10 @@ pub struct RuntimeConfig {
     pub instance_dir: PathBuf,
     /// Agent workspace directory (e.g., ~/.spacebot/agents/{id}/workspace). Immutable after startup.
     pub workspace_dir: PathBuf,
+    /// Where compacted transcripts are archived (e.g., ~/.spacebot/agents/{id}/archives). Immutable after startup.
+    pub archives_dir: PathBuf,
+    /// Where cached chat attachments are stored.
+    pub attachment_cache_dir: PathBuf,
     pub routing: ArcSwap<RoutingConfig>,
     pub compaction: ArcSwap<CompactionConfig>,
     pub memory_persistence: ArcSwap<MemoryPersistenceConfig>,
@@ -1774,6 +2555,28 @@ pub struct RuntimeConfig {
     pub opencode: ArcSwap<OpenCodeConfig>,
     /// Shared pool of OpenCode server processes. Lazily initialized on first use.
     pub opencode_server_pool: Arc<crate::opencode::OpenCodeServerPool>,
+    /// In-flight OpenCode turns, one per channel.
+    pub active_turns: Arc<crate::opencode::ActiveTurns>,
+    /// Permission requests and questions waiting on a reply, by request id.
+    pub pending_interactions: Arc<crate::opencode::PendingRegistry>,
+    /// Per-sender message counts, shared by every channel of the agent.
+    pub flood_guard: Arc<crate::opencode::FloodGuard>,
+    /// Each channel's most recent raw SSE envelopes, for debugging.
+    pub sse_recorder: Arc<crate::opencode::SseRecorder>,
+    /// Secret redaction for persisted messages. `None` when disabled.
+    pub redactor: Option<Arc<crate::conversation::Redactor>>,
+    /// At-rest encryption for conversation content. `None` stores plaintext.
+    pub history_cipher: Option<Arc<crate::conversation::ContentCipher>>,
+    /// Token counter for persisted messages and context budgets.
+    pub tokenizer: Arc<dyn crate::conversation::Tokenizer>,
+    /// What resetting a channel does with its stored history.
+    pub reset_mode: crate::conversation::ResetMode,
+    /// Recent messages kept in memory, shared by the agent's loggers. `None`
+    /// when disabled.
+    pub history_cache: Option<Arc<crate::conversation::RecentHistoryCache>>,
+    /// Caps concurrent history writes, shared by the agent's loggers. `None`
+    /// when uncapped.
+    pub history_writes: Option<Arc<crate::conversation::WriteLimiter>>,
     /// Cron store, set after agent initialization.
     pub cron_store: ArcSwap<Option<Arc<crate::cron::CronStore>>>,
     /// Cron scheduler, set after agent initialization.
@@ -1793,15 +2596,25 @@ impl RuntimeConfig {
         skills: crate::skills::SkillSet,
     ) -> Self {
         let opencode_config = &defaults.opencode;
-        let server_pool = crate::opencode::OpenCodeServerPool::new(
+        let mut server_pool = crate::opencode::OpenCodeServerPool::new(
             opencode_config.path.clone(),
-            opencode_config.permissions.clone(),
+            opencode_config.permission_profiles(),
             opencode_config.max_servers,
         );
+        // Validated when the config was loaded.
+        if let Ok(requirement) = opencode_config.version_requirement() {
+            server_pool = server_pool.with_supported_versions(requirement);
+        }
+        if !opencode_config.providers.is_empty() {
+            server_pool = server_pool.with_providers(opencode_config.providers.clone());
+        }
+        server_pool = server_pool.with_circuit_breaker(opencode_config.breaker_config());
 
         Self {
             instance_dir: instance_dir.to_path_buf(),
             workspace_dir: agent_config.workspace.clone(),
+            archives_dir: agent_config.archives_dir.clone(),
+            attachment_cache_dir: agent_config.data_dir.join("attachments"),
             routing: ArcSwap::from_pointee(agent_config.routing.clone()),
             compaction: ArcSwap::from_pointee(agent_config.compaction),
             memory_persistence: ArcSwap::from_pointee(agent_config.memory_persistence),
@@ -1822,6 +2635,27 @@ impl RuntimeConfig {
             skills: ArcSwap::from_pointee(skills),
             opencode: ArcSwap::from_pointee(defaults.opencode.clone()),
             opencode_server_pool: Arc::new(server_pool),
+            active_turns: Arc::new(crate::opencode::ActiveTurns::new()),
+            pending_interactions: Arc::new(crate::opencode::PendingRegistry::new()),
+            flood_guard: Arc::new(crate::opencode::FloodGuard::new()),
+            sse_recorder: Arc::new(crate::opencode::SseRecorder::new(opencode_config.sse_debug_buffer_size)),
+            redactor: defaults
+                .redaction
+                .redactor()
+                .unwrap_or_else(|error| {
+                    tracing::warn!(%error, "invalid redaction config, storing messages verbatim");
+                    None
+                })
+                .map(Arc::new),
+            // Validated when the config was loaded.
+            history_cipher: defaults
+                .history_cipher()
+                .expect("history encryption key validated at config load")
+                .map(Arc::new),
+            tokenizer: defaults.tokenizer().expect("tokenizer validated at config load"),
+            reset_mode: defaults.reset_mode,
+            history_cache: defaults.history_cache().map(Arc::new),
+            history_writes: defaults.history_write_limiter().map(Arc::new),
             cron_store: ArcSwap::from_pointee(None),
             cron_scheduler: ArcSwap::from_pointee(None),
             settings: ArcSwap::from_pointee(None),
@@ -2433,3 +3267,59 @@ pub fn run_onboarding() -> anyhow::Result<Option<PathBuf>> {
 
     Ok(Some(config_path))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(toml: &str) -> Result<Config> {
+        let toml_config: TomlConfig = toml::from_str(toml).expect("valid TOML");
+        Config::from_toml(toml_config, PathBuf::from("."))
+    }
+
+    #[test]
+    fn test_unknown_permission_mode_is_rejected() {
+        let config = load("[defaults.opencode]\npermission_mode = \"ask\"\n").unwrap();
+        assert_eq!(config.defaults.opencode.permission_mode, crate::opencode::PermissionMode::Ask);
+
+        let error = load("[defaults.opencode]\npermission_mode = \"aks\"\n").unwrap_err();
+        assert!(error.to_string().contains("unknown permission mode: aks"), "{error}");
+    }
+
+    #[test]
+    fn test_unknown_follow_up_mode_is_rejected() {
+        let config = load("[defaults.opencode]\nfollow_up_mode = \"abort\"\n").unwrap();
+        assert_eq!(config.defaults.opencode.follow_up_mode, crate::opencode::FollowUpMode::Abort);
+
+        let error = load("[defaults.opencode]\nfollow_up_mode = \"interrupt\"\n").unwrap_err();
+        assert!(error.to_string().contains("unknown follow-up mode: interrupt"), "{error}");
+    }
+
+    #[test]
+    fn test_unknown_question_default_is_rejected() {
+        let config = load("[defaults.opencode]\nquestion_default = \"label:Yes\"\n").unwrap();
+        assert_eq!(
+            config.defaults.opencode.question_default,
+            crate::opencode::questions::QuestionDefault::Label("Yes".into())
+        );
+
+        let error = load("[defaults.opencode]\nquestion_default = \"label:\"\n").unwrap_err();
+        assert!(error.to_string().contains("unknown question default: label:"), "{error}");
+    }
+
+    #[test]
+    fn test_cron_job_ids_must_fit_a_channel_id() {
+        let cron = |id: &str| {
+            format!(
+                "[[agents]]\nid = \"main\"\n\n[[agents.cron]]\nid = \"{id}\"\nprompt = \"check the inbox\"\ndelivery_target = \"discord:123\"\n"
+            )
+        };
+        let config = load(&cron("check-email")).unwrap();
+        assert_eq!(config.agents[0].cron[0].id, "check-email");
+
+        for id in ["check email", "reports/daily", "r\u{e9}sum\u{e9}"] {
+            let error = load(&cron(id)).unwrap_err();
+            assert!(error.to_string().contains("invalid cron job ID"), "{error}");
+        }
+    }
+}
@@ -1,8 +1,24 @@
 //! Conversation history and context management.
 
+pub mod budget;
+pub mod cache;
 pub mod channels;
+pub mod crypto;
 pub mod history;
+pub mod redact;
+pub mod regenerate;
+pub mod tokenizer;
+pub mod writes;
 pub mod context;
 
+pub use budget::{BudgetEstimate, BudgetRecommendation, ContextBudget};
+pub use cache::RecentHistoryCache;
 pub use channels::ChannelStore;
-pub use history::{ConversationLogger, ProcessRunLogger, TimelineItem};
+pub use crypto::ContentCipher;
+pub use redact::Redactor;
+pub use tokenizer::{CharTokenizer, Tokenizer};
+pub use writes::WriteLimiter;
+pub use history::{
+    ChannelReset, CompactionSummary, ConversationLogger, ConversationMessage, ProcessRunLogger,
+    ResetMode, RollingContext, SummaryOverflow, TimelineItem, merge_consecutive_turns,
+};
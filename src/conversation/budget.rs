@@ -0,0 +1,200 @@
+//! Context-window budget for persisted channel history.
+//!
+//! Estimates how many tokens a channel's uncompacted turns plus its latest
+//! compaction summary will cost once assembled into a prompt, so compaction
+//! can run before the model rejects the prompt instead of after.
+
+use crate::config::CompactionConfig;
+use crate::conversation::history::{CompactionSummary, ConversationLogger, ConversationMessage};
+use crate::conversation::tokenizer::{CharTokenizer, Tokenizer};
+use crate::ChannelId;
+
+use std::sync::Arc;
+
+/// Tokens added per message for role and sender framing.
+const MESSAGE_OVERHEAD_TOKENS: usize = 4;
+
+/// What to do about a channel's history before the next prompt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BudgetRecommendation {
+    /// Comfortably within the window.
+    Ok,
+    /// Past the compaction threshold; compact in the background.
+    CompactNeeded,
+    /// Close to (or over) the limit; compact before sending.
+    MustCompact,
+}
+
+/// Token estimate for a channel's history against a model limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BudgetEstimate {
+    pub tokens: usize,
+    pub limit: usize,
+    pub recommendation: BudgetRecommendation,
+}
+
+impl BudgetEstimate {
+    /// Fraction of the limit used.
+    pub fn usage(&self) -> f32 {
+        self.tokens as f32 / self.limit.max(1) as f32
+    }
+
+    /// Whether the estimate is over the model's limit outright.
+    pub fn exceeds_limit(&self) -> bool {
+        self.tokens > self.limit
+    }
+}
+
+/// Estimates history size against a context window.
+#[derive(Debug, Clone)]
+pub struct ContextBudget {
+    /// The model's context window, in tokens.
+    pub limit: usize,
+    /// Usage at which compaction is recommended.
+    pub compact_threshold: f32,
+    /// Usage at which compaction must happen before sending.
+    pub must_compact_threshold: f32,
+    /// Counts text with no stored token count.
+    pub tokenizer: Arc<dyn Tokenizer>,
+}
+
+impl ContextBudget {
+    /// Budget for `limit` tokens, using the compaction config's background
+    /// and emergency thresholds.
+    pub fn new(limit: usize, compaction: &CompactionConfig) -> Self {
+        Self {
+            limit,
+            compact_threshold: compaction.background_threshold,
+            must_compact_threshold: compaction.emergency_threshold,
+            tokenizer: Arc::new(CharTokenizer),
+        }
+    }
+
+    /// Count with this tokenizer instead of the character heuristic.
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Estimate `messages` plus the latest of `summaries` (the only one a
+    /// prompt includes). A message's stored token count is used when it has
+    /// one; otherwise its content is counted with the tokenizer.
+    pub fn estimate(
+        &self,
+        messages: &[ConversationMessage],
+        summaries: &[CompactionSummary],
+    ) -> BudgetEstimate {
+        let summary_tokens = summaries
+            .last()
+            .map_or(0, |summary| self.tokenizer.count(&summary.summary) + MESSAGE_OVERHEAD_TOKENS);
+        let message_tokens: usize = messages
+            .iter()
+            .map(|message| {
+                let sender = message.sender_name.as_deref().unwrap_or_default();
+                let content = match message.token_count {
+                    Some(count) => count.max(0) as usize,
+                    None => self.tokenizer.count(&message.content),
+                };
+                self.tokenizer.count(sender) + content + MESSAGE_OVERHEAD_TOKENS
+            })
+            .sum();
+
+        let tokens = summary_tokens + message_tokens;
+        let usage = tokens as f32 / self.limit.max(1) as f32;
+        let recommendation = if usage >= self.must_compact_threshold {
+            BudgetRecommendation::MustCompact
+        } else if usage >= self.compact_threshold {
+            BudgetRecommendation::CompactNeeded
+        } else {
+            BudgetRecommendation::Ok
+        };
+
+        BudgetEstimate { tokens, limit: self.limit, recommendation }
+    }
+
+    /// Estimate a channel's turns since its last compaction, plus its latest
+    /// summary, as loaded from the database.
+    pub async fn estimate_channel(
+        &self,
+        logger: &ConversationLogger,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<BudgetEstimate> {
+        let turns = logger.turns_since_last_compaction(channel_id).await?;
+        let messages = logger.load_recent(channel_id, turns as i64, false).await?;
+        let summaries = logger.load_compaction_summaries(channel_id).await?;
+        Ok(self.estimate(&messages, &summaries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &str) -> ConversationMessage {
+        ConversationMessage {
+            id: "m".into(),
+            channel_id: "discord:1:2".into(),
+            role: "user".into(),
+            sender_name: Some("alice".into()),
+            sender_id: None,
+            content: content.into(),
+            metadata: None,
+            token_count: None,
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    fn summary(text: &str) -> CompactionSummary {
+        CompactionSummary {
+            id: "s".into(),
+            channel_id: "discord:1:2".into(),
+            summary: text.into(),
+            turns_covered: 10,
+            covered_from: None,
+            covered_to: None,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_estimate_recommendations() {
+        let budget = ContextBudget::new(100, &CompactionConfig::default());
+
+        // "alice" (2) + 40 chars (10) + overhead (4) = 16 tokens.
+        let small = budget.estimate(&[message(&"x".repeat(40))], &[]);
+        assert_eq!(small.tokens, 16);
+        assert_eq!(small.recommendation, BudgetRecommendation::Ok);
+
+        // Only the latest summary counts: 300 chars (75) + overhead (4) + 16.
+        let summaries = [summary(&"y".repeat(4000)), summary(&"y".repeat(300))];
+        let compact = budget.estimate(&[message(&"x".repeat(40))], &summaries);
+        assert_eq!(compact.tokens, 95);
+        assert_eq!(compact.recommendation, BudgetRecommendation::MustCompact);
+        assert!(!compact.exceeds_limit());
+
+        let background = budget.estimate(&[message(&"x".repeat(40))], &[summary(&"y".repeat(250))]);
+        assert_eq!(background.tokens, 83);
+        assert_eq!(background.recommendation, BudgetRecommendation::CompactNeeded);
+    }
+
+    #[test]
+    fn test_estimate_prefers_stored_counts() {
+        #[derive(Debug)]
+        struct OnePerWord;
+        impl Tokenizer for OnePerWord {
+            fn count(&self, text: &str) -> usize {
+                text.split_whitespace().count()
+            }
+        }
+
+        let budget = ContextBudget::new(100, &CompactionConfig::default()).with_tokenizer(Arc::new(OnePerWord));
+        let mut counted = message("two words");
+        counted.token_count = Some(30);
+
+        // "alice" (1) + "two words" (2) + overhead (4), then 1 + 30 + 4.
+        let estimate = budget.estimate(&[message("two words"), counted], &[]);
+        assert_eq!(estimate.tokens, 42);
+    }
+}
@@ -0,0 +1,312 @@
+//! In-memory cache of each channel's most recent messages.
+//!
+//! `load_recent` runs on every turn, so a busy channel re-reads the same rows
+//! from SQLite over and over. `RecentHistoryCache` keeps the last
+//! `messages_per_channel` messages of up to `max_channels` channels, evicting
+//! the least recently used channel first. The logger appends each message once
+//! its write lands and drops a channel's entry when its history changes some
+//! other way (a reset, a backfill, a failed write).
+
+use crate::conversation::history::ConversationMessage;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bounded LRU cache of recent messages, keyed by channel.
+#[derive(Debug)]
+pub struct RecentHistoryCache {
+    max_channels: usize,
+    messages_per_channel: usize,
+    state: Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<String, CachedChannel>,
+    /// Bumped on every change, so a fill that raced with one is dropped.
+    generation: u64,
+    /// Logical clock for least-recently-used eviction.
+    tick: u64,
+}
+
+#[derive(Debug)]
+struct CachedChannel {
+    /// Oldest first, synthetic and hidden messages included.
+    messages: VecDeque<ConversationMessage>,
+    /// Whether `messages` is the channel's whole live history, so a request
+    /// for more than it holds can still be answered.
+    complete: bool,
+    last_used: u64,
+}
+
+/// Lookup counts, for tuning the cache size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Channels currently cached.
+    pub channels: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups served from memory. `0.0` before any lookup.
+    pub fn hit_rate(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f64 / total as f64
+        }
+    }
+}
+
+impl RecentHistoryCache {
+    /// Cache the last `messages_per_channel` messages of up to
+    /// `max_channels` channels.
+    pub fn new(max_channels: usize, messages_per_channel: usize) -> Self {
+        Self {
+            max_channels: max_channels.max(1),
+            messages_per_channel: messages_per_channel.max(1),
+            state: Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// How many messages a fill should load.
+    pub fn messages_per_channel(&self) -> usize {
+        self.messages_per_channel
+    }
+
+    /// The newest `limit` messages matching the filters, oldest first, or
+    /// `None` if the cache can't answer (channel not cached, or it holds
+    /// fewer matches than asked for and isn't the whole history). A negative
+    /// `limit` means no limit.
+    pub fn get(
+        &self,
+        channel_id: &str,
+        limit: i64,
+        include_synthetic: bool,
+        include_hidden: bool,
+    ) -> Option<Vec<ConversationMessage>> {
+        let result = self.peek(channel_id, limit, include_synthetic, include_hidden);
+        let counter = if result.is_some() { &self.hits } else { &self.misses };
+        counter.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    /// Like `get`, without counting toward the hit and miss metrics.
+    pub fn peek(
+        &self,
+        channel_id: &str,
+        limit: i64,
+        include_synthetic: bool,
+        include_hidden: bool,
+    ) -> Option<Vec<ConversationMessage>> {
+        let mut state = self.lock();
+        state.tick += 1;
+        let tick = state.tick;
+        let entry = state.entries.get_mut(channel_id)?;
+        entry.last_used = tick;
+
+        let matching: Vec<&ConversationMessage> = entry
+            .messages
+            .iter()
+            .filter(|message| include_synthetic || !message.is_synthetic)
+            .filter(|message| include_hidden || !message.is_hidden)
+            .collect();
+        let take = match usize::try_from(limit) {
+            Ok(limit) if limit <= matching.len() => limit,
+            _ if entry.complete => matching.len(),
+            _ => return None,
+        };
+        Some(matching[matching.len() - take..].iter().map(|message| (*message).clone()).collect())
+    }
+
+    /// The generation to pass to `fill`. Take it before reading the
+    /// database, so a change that lands during the read voids the fill.
+    pub fn generation(&self) -> u64 {
+        self.lock().generation
+    }
+
+    /// Cache `messages` (oldest first, loaded with synthetic and hidden
+    /// messages, limited to `messages_per_channel`) for a channel. Dropped if
+    /// anything changed since `generation` was taken.
+    pub fn fill(&self, channel_id: &str, generation: u64, messages: Vec<ConversationMessage>) {
+        let mut state = self.lock();
+        if state.generation != generation {
+            return;
+        }
+        let complete = messages.len() < self.messages_per_channel;
+        state.tick += 1;
+        let entry = CachedChannel {
+            messages: messages.into(),
+            complete,
+            last_used: state.tick,
+        };
+        state.entries.insert(channel_id.to_string(), entry);
+
+        while state.entries.len() > self.max_channels {
+            let oldest = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(channel_id, _)| channel_id.clone());
+            match oldest {
+                Some(oldest) => state.entries.remove(&oldest),
+                None => break,
+            };
+        }
+    }
+
+    /// Append a message that was just written. Ignored if the channel isn't
+    /// cached or already has it.
+    pub fn push(&self, message: ConversationMessage) {
+        let mut state = self.lock();
+        state.generation += 1;
+        let Some(entry) = state.entries.get_mut(&message.channel_id) else {
+            return;
+        };
+        if entry.messages.iter().rev().any(|cached| cached.id == message.id) {
+            return;
+        }
+        entry.messages.push_back(message);
+        while entry.messages.len() > self.messages_per_channel {
+            entry.messages.pop_front();
+            entry.complete = false;
+        }
+    }
+
+    /// Update the hidden flag of a cached message.
+    pub fn set_hidden(&self, id: &str, hidden: bool) {
+        let mut state = self.lock();
+        state.generation += 1;
+        for entry in state.entries.values_mut() {
+            if let Some(message) = entry.messages.iter_mut().find(|message| message.id == id) {
+                message.is_hidden = hidden;
+            }
+        }
+    }
+
+    /// Forget a channel, so the next lookup reloads it.
+    pub fn invalidate(&self, channel_id: &str) {
+        let mut state = self.lock();
+        state.generation += 1;
+        state.entries.remove(channel_id);
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            channels: self.lock().entries.len(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CacheState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Render each agent's cache counters in the Prometheus text exposition
+/// format.
+pub fn render_metrics<'a>(caches: impl IntoIterator<Item = (&'a str, &'a RecentHistoryCache)>) -> String {
+    let stats: Vec<(&str, CacheStats)> = caches.into_iter().map(|(agent_id, cache)| (agent_id, cache.stats())).collect();
+    if stats.is_empty() {
+        return String::new();
+    }
+
+    let mut output = String::new();
+    let series: [(&str, &str, fn(&CacheStats) -> u64); 3] = [
+        ("spacebot_history_cache_hits_total", "counter", |stats| stats.hits),
+        ("spacebot_history_cache_misses_total", "counter", |stats| stats.misses),
+        ("spacebot_history_cache_channels", "gauge", |stats| stats.channels as u64),
+    ];
+    for (name, kind, value) in series {
+        let _ = writeln!(output, "# TYPE {name} {kind}");
+        for (agent_id, stats) in &stats {
+            let _ = writeln!(output, "{name}{{agent=\"{agent_id}\"}} {}", value(stats));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, is_synthetic: bool) -> ConversationMessage {
+        ConversationMessage {
+            id: id.into(),
+            channel_id: "discord:1:2".into(),
+            role: "user".into(),
+            sender_name: None,
+            sender_id: None,
+            content: id.into(),
+            metadata: None,
+            token_count: None,
+            is_synthetic,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    fn ids(messages: &[ConversationMessage]) -> Vec<&str> {
+        messages.iter().map(|message| message.id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_recent_history_cache() {
+        let cache = RecentHistoryCache::new(2, 3);
+        assert!(cache.get("discord:1:2", 2, false, false).is_none());
+
+        let generation = cache.generation();
+        cache.fill("discord:1:2", generation, vec![message("a", false), message("b", true)]);
+        let recent = cache.get("discord:1:2", 5, false, false).unwrap();
+        assert_eq!(ids(&recent), ["a"]);
+
+        cache.push(message("c", false));
+        cache.push(message("c", false));
+        cache.push(message("d", false));
+        // "a" was trimmed, so the cache no longer holds the whole history.
+        assert_eq!(ids(&cache.get("discord:1:2", 2, false, false).unwrap()), ["c", "d"]);
+        assert!(cache.get("discord:1:2", 3, false, false).is_none());
+        assert_eq!(ids(&cache.get("discord:1:2", 3, true, false).unwrap()), ["b", "c", "d"]);
+
+        cache.set_hidden("c", true);
+        assert_eq!(ids(&cache.get("discord:1:2", 1, false, false).unwrap()), ["d"]);
+        assert!(cache.get("discord:1:2", 2, false, false).is_none());
+
+        // A change during the read voids the fill.
+        let generation = cache.generation();
+        cache.invalidate("discord:1:2");
+        cache.fill("discord:1:2", generation, vec![message("a", false)]);
+        assert!(cache.get("discord:1:2", 1, false, false).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.channels), (4, 4, 0));
+        assert_eq!(stats.hit_rate(), 0.5);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let cache = RecentHistoryCache::new(2, 10);
+        for channel_id in ["one", "two"] {
+            cache.fill(channel_id, cache.generation(), Vec::new());
+        }
+        assert!(cache.get("one", 1, false, false).is_some());
+        cache.fill("three", cache.generation(), Vec::new());
+
+        assert!(cache.get("two", 1, false, false).is_none());
+        assert!(cache.get("one", 1, false, false).is_some());
+        assert!(cache.get("three", 1, false, false).is_some());
+
+        let rendered = render_metrics([("main", &cache)]);
+        assert!(rendered.contains("spacebot_history_cache_hits_total{agent=\"main\"} 3"));
+        assert!(rendered.contains("spacebot_history_cache_channels{agent=\"main\"} 2"));
+    }
+}
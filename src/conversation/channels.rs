@@ -1,13 +1,16 @@
 //! Channel tracking and metadata (SQLite).
 
+use crate::error::HistoryError;
+
 use sqlx::{Row as _, SqlitePool};
 use std::collections::HashMap;
 
 /// Tracks known channels in SQLite.
 ///
 /// Handles upsert on channel open, activity timestamps, and channel lookups.
-/// All write methods are fire-and-forget — they spawn a tokio task and return
-/// immediately so the caller never blocks on a DB write.
+/// Metadata writes are fire-and-forget — they spawn a tokio task and return
+/// immediately so the caller never blocks on a DB write. Active session
+/// writes are awaited, since losing one would orphan the session on restart.
 #[derive(Debug, Clone)]
 pub struct ChannelStore {
     pool: SqlitePool,
@@ -95,7 +98,7 @@ impl ChannelStore {
         )
         .fetch_all(&self.pool)
         .await
-        .map_err(|e| anyhow::anyhow!(e))?;
+        .map_err(HistoryError::Database)?;
 
         Ok(rows.into_iter().map(row_to_channel_info).collect())
     }
@@ -146,7 +149,7 @@ impl ChannelStore {
         .bind(channel_id)
         .fetch_optional(&self.pool)
         .await
-        .map_err(|e| anyhow::anyhow!(e))?;
+        .map_err(HistoryError::Database)?;
 
         Ok(row.map(row_to_channel_info))
     }
@@ -155,6 +158,147 @@ impl ChannelStore {
     pub async fn resolve_name(&self, channel_id: &str) -> Option<String> {
         self.get(channel_id).await.ok().flatten().and_then(|c| c.display_name)
     }
+
+    /// Record the OpenCode session currently serving a channel, replacing any
+    /// previous one. The stored title is kept only if the session is unchanged.
+    pub async fn set_active_session(
+        &self,
+        channel_id: &str,
+        session_id: &str,
+    ) -> crate::error::Result<()> {
+        sqlx::query(
+            "INSERT INTO channel_sessions (channel_id, session_id, updated_at) \
+             VALUES (?, ?, CURRENT_TIMESTAMP) \
+             ON CONFLICT(channel_id) DO UPDATE SET \
+                 title = CASE WHEN channel_sessions.session_id = excluded.session_id \
+                     THEN channel_sessions.title END, \
+                 session_id = excluded.session_id, \
+                 updated_at = CURRENT_TIMESTAMP"
+        )
+        .bind(channel_id)
+        .bind(session_id)
+        .execute(&self.pool)
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(())
+    }
+
+    /// Get the OpenCode session ID last recorded for a channel.
+    pub async fn get_active_session(&self, channel_id: &str) -> crate::error::Result<Option<String>> {
+        let session_id = sqlx::query_scalar::<_, String>(
+            "SELECT session_id FROM channel_sessions WHERE channel_id = ?"
+        )
+        .bind(channel_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(session_id)
+    }
+
+    /// Record a new title for the channel's active session. No-op if
+    /// `session_id` is no longer the channel's active session.
+    pub async fn set_session_title(
+        &self,
+        channel_id: &str,
+        session_id: &str,
+        title: &str,
+    ) -> crate::error::Result<()> {
+        sqlx::query(
+            "UPDATE channel_sessions SET title = ?, updated_at = CURRENT_TIMESTAMP \
+             WHERE channel_id = ? AND session_id = ?"
+        )
+        .bind(title)
+        .bind(channel_id)
+        .bind(session_id)
+        .execute(&self.pool)
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(())
+    }
+
+    /// Get the title recorded for a channel's active session.
+    pub async fn get_session_title(&self, channel_id: &str) -> crate::error::Result<Option<String>> {
+        let title = sqlx::query_scalar::<_, Option<String>>(
+            "SELECT title FROM channel_sessions WHERE channel_id = ?"
+        )
+        .bind(channel_id)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(HistoryError::Database)?
+        .flatten();
+
+        Ok(title)
+    }
+
+    /// Forget a channel's active session (e.g. on `!reset`). The next prompt
+    /// starts a fresh session.
+    pub async fn clear_active_session(&self, channel_id: &str) -> crate::error::Result<()> {
+        sqlx::query("DELETE FROM channel_sessions WHERE channel_id = ?")
+            .bind(channel_id)
+            .execute(&self.pool)
+            .await
+            .map_err(HistoryError::Database)?;
+
+        Ok(())
+    }
+
+    /// Forget a channel's active session only if it's still `session_id`.
+    /// Returns whether it was cleared.
+    pub async fn clear_session_if_active(&self, channel_id: &str, session_id: &str) -> crate::error::Result<bool> {
+        let result = sqlx::query("DELETE FROM channel_sessions WHERE channel_id = ? AND session_id = ?")
+            .bind(channel_id)
+            .bind(session_id)
+            .execute(&self.pool)
+            .await
+            .map_err(HistoryError::Database)?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Channels with an active session and no message logged since `before`.
+    /// A channel with no live messages counts from when its session was
+    /// recorded.
+    pub async fn idle_sessions(
+        &self,
+        before: chrono::DateTime<chrono::Utc>,
+    ) -> crate::error::Result<Vec<IdleSession>> {
+        let rows = sqlx::query(
+            "SELECT channel_id, session_id, last_activity_at FROM ( \
+                 SELECT session.channel_id, session.session_id, \
+                     COALESCE( \
+                         (SELECT MAX(message.created_at) FROM conversation_messages message \
+                          WHERE message.channel_id = session.channel_id AND message.cleared_at IS NULL), \
+                         session.updated_at \
+                     ) AS last_activity_at \
+                 FROM channel_sessions session \
+             ) WHERE last_activity_at < ? \
+             ORDER BY last_activity_at ASC"
+        )
+        .bind(before.format("%Y-%m-%d %H:%M:%S").to_string())
+        .fetch_all(&self.pool)
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| IdleSession {
+                channel_id: row.try_get("channel_id").unwrap_or_default(),
+                session_id: row.try_get("session_id").unwrap_or_default(),
+                last_activity_at: row.try_get("last_activity_at").unwrap_or_else(|_| chrono::Utc::now()),
+            })
+            .collect())
+    }
+}
+
+/// A channel session with no recent activity, from `ChannelStore::idle_sessions`.
+#[derive(Debug, Clone)]
+pub struct IdleSession {
+    pub channel_id: String,
+    pub session_id: String,
+    pub last_activity_at: chrono::DateTime<chrono::Utc>,
 }
 
 fn row_to_channel_info(row: sqlx::sqlite::SqliteRow) -> ChannelInfo {
@@ -243,3 +387,48 @@ fn extract_platform_meta(
         serde_json::to_string(&serde_json::Value::Object(meta)).ok()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[tokio::test]
+    async fn test_active_session_round_trip() {
+        let store = ChannelStore::new(connect_in_memory().await);
+        let channel_id = "discord:1:2";
+
+        assert_eq!(store.get_active_session(channel_id).await.unwrap(), None);
+
+        store.set_active_session(channel_id, "ses_a").await.unwrap();
+        store.set_active_session(channel_id, "ses_b").await.unwrap();
+        assert_eq!(
+            store.get_active_session(channel_id).await.unwrap().as_deref(),
+            Some("ses_b")
+        );
+        assert_eq!(store.get_active_session("discord:1:3").await.unwrap(), None);
+
+        store.clear_active_session(channel_id).await.unwrap();
+        assert_eq!(store.get_active_session(channel_id).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_session_title_follows_active_session() {
+        let store = ChannelStore::new(connect_in_memory().await);
+        let channel_id = "discord:1:2";
+
+        store.set_active_session(channel_id, "ses_a").await.unwrap();
+        store.set_session_title(channel_id, "ses_a", "Fix flaky CI").await.unwrap();
+        // A stale session's title doesn't overwrite the active one.
+        store.set_session_title(channel_id, "ses_old", "Old").await.unwrap();
+        assert_eq!(
+            store.get_session_title(channel_id).await.unwrap().as_deref(),
+            Some("Fix flaky CI")
+        );
+
+        store.set_active_session(channel_id, "ses_a").await.unwrap();
+        assert!(store.get_session_title(channel_id).await.unwrap().is_some());
+        store.set_active_session(channel_id, "ses_b").await.unwrap();
+        assert_eq!(store.get_session_title(channel_id).await.unwrap(), None);
+    }
+}
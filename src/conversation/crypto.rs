@@ -0,0 +1,194 @@
+//! At-rest encryption for conversation content (SQLite).
+//!
+//! When a key is configured, `ConversationLogger` seals message `content`,
+//! `metadata`, and compaction `summary` values before writing them and opens
+//! them on read. Values stay TEXT so the schema and the rest of the queries
+//! are unchanged:
+//!
+//! ```text
+//! enc:<base64(version || nonce || ciphertext || tag)>
+//! ```
+//!
+//! Version `1` is AES-256-GCM with a fresh random 96-bit nonce per value, drawn
+//! from the OS RNG. Random nonces are safe for well over 2^32 values under one
+//! key, far beyond a single agent's history. The version byte leaves room to
+//! move to another AEAD later; readers dispatch on it.
+//!
+//! Values without the `enc:` prefix are plaintext written before encryption
+//! was enabled, and are returned as-is. `encrypt_plaintext_rows` converts them
+//! in place.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::Engine as _;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use sqlx::{Row as _, SqlitePool};
+
+/// Marks an encrypted column value.
+const PREFIX: &str = "enc:";
+
+/// AES-256-GCM with a random 96-bit nonce.
+const VERSION_AES_256_GCM: u8 = 1;
+
+const NONCE_LEN: usize = 12;
+
+/// Seals and opens conversation content with a single 256-bit key.
+pub struct ContentCipher {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for ContentCipher {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContentCipher").finish_non_exhaustive()
+    }
+}
+
+impl ContentCipher {
+    /// Build from a base64-encoded 32-byte key (e.g. `openssl rand -base64 32`).
+    pub fn from_base64_key(key: &str) -> anyhow::Result<Self> {
+        let bytes = BASE64
+            .decode(key.trim())
+            .map_err(|error| anyhow::anyhow!("encryption key is not valid base64: {error}"))?;
+        let cipher = Aes256Gcm::new_from_slice(&bytes).map_err(|_| {
+            anyhow::anyhow!("encryption key must be 32 bytes, got {}", bytes.len())
+        })?;
+        Ok(Self { cipher })
+    }
+
+    /// Encrypt a value for storage.
+    pub fn seal(&self, plaintext: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("AES-GCM encryption only fails on oversized input");
+
+        let mut envelope = Vec::with_capacity(1 + NONCE_LEN + ciphertext.len());
+        envelope.push(VERSION_AES_256_GCM);
+        envelope.extend_from_slice(&nonce);
+        envelope.extend_from_slice(&ciphertext);
+        format!("{PREFIX}{}", BASE64.encode(envelope))
+    }
+
+    /// Decrypt a stored value. Plaintext values pass through unchanged.
+    pub fn open(&self, stored: &str) -> anyhow::Result<String> {
+        let Some(encoded) = stored.strip_prefix(PREFIX) else {
+            return Ok(stored.to_string());
+        };
+        let envelope = BASE64
+            .decode(encoded)
+            .map_err(|error| anyhow::anyhow!("malformed encrypted value: {error}"))?;
+
+        match envelope.split_first() {
+            Some((&VERSION_AES_256_GCM, rest)) if rest.len() >= NONCE_LEN => {
+                let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+                let plaintext = self
+                    .cipher
+                    .decrypt(Nonce::from_slice(nonce), ciphertext)
+                    .map_err(|_| anyhow::anyhow!("failed to decrypt value (wrong key or corrupted)"))?;
+                Ok(String::from_utf8(plaintext)?)
+            }
+            Some((version, _)) => anyhow::bail!("unsupported encryption version {version}"),
+            None => anyhow::bail!("empty encrypted value"),
+        }
+    }
+}
+
+/// Whether a stored value is already encrypted.
+pub fn is_sealed(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// Rows converted by `encrypt_plaintext_rows`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EncryptionReport {
+    pub messages: u64,
+    pub summaries: u64,
+}
+
+/// Encrypt every plaintext message and summary in place.
+///
+/// Idempotent: already-encrypted values are skipped, so this can run on every
+/// startup once a key is configured. Runs in a single transaction.
+pub async fn encrypt_plaintext_rows(
+    pool: &SqlitePool,
+    cipher: &ContentCipher,
+) -> crate::error::Result<EncryptionReport> {
+    let mut transaction = pool.begin().await.map_err(|e| anyhow::anyhow!(e))?;
+    let mut report = EncryptionReport::default();
+
+    let rows = sqlx::query(
+        "SELECT id, content, metadata FROM conversation_messages \
+         WHERE content NOT LIKE 'enc:%' OR (metadata IS NOT NULL AND metadata NOT LIKE 'enc:%')",
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .map_err(|e| anyhow::anyhow!(e))?;
+
+    for row in rows {
+        let id: String = row.try_get("id").unwrap_or_default();
+        let content: String = row.try_get("content").unwrap_or_default();
+        let metadata: Option<String> = row.try_get("metadata").ok().flatten();
+        let seal = |value: &str| {
+            if is_sealed(value) { value.to_string() } else { cipher.seal(value) }
+        };
+
+        sqlx::query("UPDATE conversation_messages SET content = ?, metadata = ? WHERE id = ?")
+            .bind(seal(&content))
+            .bind(metadata.as_deref().map(seal))
+            .bind(&id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        report.messages += 1;
+    }
+
+    let rows = sqlx::query("SELECT id, summary FROM compaction_summaries WHERE summary NOT LIKE 'enc:%'")
+        .fetch_all(&mut *transaction)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))?;
+
+    for row in rows {
+        let id: String = row.try_get("id").unwrap_or_default();
+        let summary: String = row.try_get("summary").unwrap_or_default();
+
+        sqlx::query("UPDATE compaction_summaries SET summary = ? WHERE id = ?")
+            .bind(cipher.seal(&summary))
+            .bind(&id)
+            .execute(&mut *transaction)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        report.summaries += 1;
+    }
+
+    transaction.commit().await.map_err(|e| anyhow::anyhow!(e))?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_cipher() -> ContentCipher {
+        ContentCipher::from_base64_key(&BASE64.encode([7u8; 32])).unwrap()
+    }
+
+    #[test]
+    fn test_seal_and_open() {
+        let cipher = test_cipher();
+
+        let sealed = cipher.seal("hello");
+        assert!(is_sealed(&sealed));
+        assert_eq!(cipher.open(&sealed).unwrap(), "hello");
+        // Fresh nonce every time.
+        assert_ne!(cipher.seal("hello"), sealed);
+
+        // Plaintext written before encryption was enabled.
+        assert_eq!(cipher.open("legacy text").unwrap(), "legacy text");
+
+        let other = ContentCipher::from_base64_key(&BASE64.encode([8u8; 32])).unwrap();
+        assert!(other.open(&sealed).is_err());
+
+        assert!(ContentCipher::from_base64_key(&BASE64.encode([7u8; 16])).is_err());
+    }
+}
@@ -1,18 +1,43 @@
 //! Conversation message persistence (SQLite).
 
+use crate::conversation::cache::RecentHistoryCache;
+use crate::conversation::crypto::ContentCipher;
+use crate::conversation::redact::Redactor;
+use crate::conversation::tokenizer::{CharTokenizer, Tokenizer};
+use crate::conversation::writes::WriteLimiter;
+use crate::db::with_retry;
+use crate::error::HistoryError;
+use crate::opencode::prompt::format_turn;
+use crate::opencode::types::{MessageInfo, Part, PartInput, ToolState};
 use crate::{BranchId, ChannelId, WorkerId};
 
 use serde::Serialize;
 use sqlx::{Row as _, SqlitePool};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Persists conversation messages (user and assistant) to SQLite.
 ///
-/// All write methods are fire-and-forget — they spawn a tokio task and return
-/// immediately so the caller never blocks on a DB write.
+/// Message write methods are fire-and-forget — they spawn a tokio task and
+/// return immediately so the caller never blocks on a DB write. Compaction
+/// writes are awaited, since the compactor needs to know they landed.
+/// Message content passes through the `Redactor`, if one is set, before insert.
+/// With a `ContentCipher`, message content, metadata, and summaries are
+/// encrypted on write and decrypted on read. Each message's token count is
+/// estimated with the `Tokenizer` and stored alongside it. With a
+/// `RecentHistoryCache`, `load_recent` is served from memory when it can be.
+/// With a `WriteLimiter`, fire-and-forget writes past its cap wait for a
+/// slot instead of all hitting the pool at once.
 #[derive(Debug, Clone)]
 pub struct ConversationLogger {
     pool: SqlitePool,
+    redactor: Option<Arc<Redactor>>,
+    cipher: Option<Arc<ContentCipher>>,
+    tokenizer: Arc<dyn Tokenizer>,
+    include_hidden: bool,
+    merge_turns: bool,
+    recent_cache: Option<Arc<RecentHistoryCache>>,
+    write_limiter: Option<Arc<WriteLimiter>>,
 }
 
 /// A persisted conversation message.
@@ -25,15 +50,310 @@ pub struct ConversationMessage {
     pub sender_id: Option<String>,
     pub content: String,
     pub metadata: Option<String>,
+    /// Tokens in `content`. `None` for messages stored before counts were kept.
+    pub token_count: Option<i64>,
+    /// Injected by Spacebot (context, notices) rather than written by a user
+    /// or the model. Hidden from the visible transcript and exports.
+    pub is_synthetic: bool,
+    /// Hidden by a moderator. Kept for audit and export, but left out of
+    /// context unless the logger includes hidden messages.
+    pub is_hidden: bool,
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
+impl ConversationMessage {
+    /// `provider/model` that produced this message, for assistant replies
+    /// mirrored from OpenCode.
+    pub fn model(&self) -> Option<String> {
+        let metadata: serde_json::Value = serde_json::from_str(self.metadata.as_deref()?).ok()?;
+        Some(format!("{}/{}", metadata["provider_id"].as_str()?, metadata["model_id"].as_str()?))
+    }
+}
+
+/// A persisted compaction summary.
+#[derive(Debug, Clone)]
+pub struct CompactionSummary {
+    pub id: String,
+    pub channel_id: String,
+    pub summary: String,
+    pub turns_covered: i64,
+    /// ID of the first stored message the summary replaces. `None` for
+    /// summaries of in-memory history.
+    pub covered_from: Option<String>,
+    /// ID of the last stored message the summary replaces. Context assembly
+    /// starts right after it.
+    pub covered_to: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A channel's history ready to prefix a prompt: every compaction summary
+/// (oldest first) and the most recent raw turns after the newest one.
+#[derive(Debug, Clone, Default)]
+pub struct RollingContext {
+    pub summaries: Vec<CompactionSummary>,
+    pub turns: Vec<ConversationMessage>,
+}
+
+impl RollingContext {
+    pub fn is_empty(&self) -> bool {
+        self.summaries.is_empty() && self.turns.is_empty()
+    }
+
+    /// The summaries and turns as one block, in order, for
+    /// `SendPromptRequest.system`. `None` if there is no history.
+    pub fn render(&self) -> Option<String> {
+        let sections: Vec<String> = self.summary_text().into_iter().chain(self.transcript()).collect();
+        (!sections.is_empty()).then(|| sections.join("\n\n"))
+    }
+
+    /// The same history as synthetic text parts: one for the summaries, one
+    /// for the recent turns.
+    pub fn parts(&self) -> Vec<PartInput> {
+        self.summary_text()
+            .into_iter()
+            .chain(self.transcript())
+            .map(|text| PartInput::Text { text, synthetic: Some(true) })
+            .collect()
+    }
+
+    fn summary_text(&self) -> Option<String> {
+        Self::summary_section(self.summaries.iter().map(|summary| summary.summary.as_str()))
+    }
+
+    fn transcript(&self) -> Option<String> {
+        Self::transcript_section(&self.turns)
+    }
+
+    /// The `[Summary of earlier conversation]` block for `summaries`, oldest
+    /// first. `None` if they are all blank.
+    pub fn summary_section<'a>(summaries: impl IntoIterator<Item = &'a str>) -> Option<String> {
+        let summaries: Vec<&str> = summaries
+            .into_iter()
+            .map(str::trim)
+            .filter(|summary| !summary.is_empty())
+            .collect();
+        (!summaries.is_empty()).then(|| format!("[Summary of earlier conversation]\n{}", summaries.join("\n\n")))
+    }
+
+    /// The `[Recent conversation]` block for `turns`, one line per turn.
+    /// `None` if there are none.
+    pub fn transcript_section(turns: &[ConversationMessage]) -> Option<String> {
+        if turns.is_empty() {
+            return None;
+        }
+        let mut transcript = String::from("[Recent conversation]\n");
+        for turn in turns {
+            transcript.push_str(&format_turn(turn));
+            transcript.push('\n');
+        }
+        Some(transcript)
+    }
+}
+
+/// A persisted OpenCode tool call.
+#[derive(Debug, Clone)]
+pub struct ToolInvocation {
+    pub id: String,
+    pub session_id: String,
+    pub channel_id: String,
+    pub call_id: String,
+    pub tool: String,
+    pub status: String,
+    pub input: Option<serde_json::Value>,
+    pub output: Option<String>,
+    pub started_at: chrono::DateTime<chrono::Utc>,
+    pub completed_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A persisted part of an OpenCode assistant message.
+#[derive(Debug, Clone)]
+pub struct MessagePart {
+    pub message_id: String,
+    pub part_id: String,
+    pub session_id: String,
+    pub channel_id: String,
+    /// Position within the message, in arrival order from 0.
+    pub ordinal: i64,
+    /// `text`, `tool`, `step-start`, or `step-finish`.
+    pub part_type: String,
+    /// The part's body: `text` for text, the call and its latest state for
+    /// tools, `reason` and `tokens` for step ends.
+    pub content: serde_json::Value,
+    /// OpenCode's timing for the part, in epoch milliseconds.
+    pub started_ms: Option<f64>,
+    pub ended_ms: Option<f64>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// One line of `ConversationLogger::export_jsonl` output.
+#[derive(Serialize)]
+struct ExportConversation {
+    messages: Vec<ExportMessage>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct ExportMessage {
+    role: String,
+    content: String,
+}
+
+/// What to do with the oldest summaries once a channel exceeds its cap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SummaryOverflow {
+    /// Delete the overflowing summaries outright.
+    #[default]
+    Prune,
+    /// Fold the overflowing summaries into the oldest surviving one, so the
+    /// row count is bounded but no summarized context is dropped.
+    Merge,
+}
+
+/// What a channel reset does with the channel's stored messages and summaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ResetMode {
+    /// Mark the rows cleared: hidden from live context but kept (default).
+    #[default]
+    SoftDelete,
+    /// Delete the rows outright. Only the transcript archive remains.
+    HardDelete,
+}
+
+impl std::str::FromStr for ResetMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "soft_delete" => Ok(Self::SoftDelete),
+            "hard_delete" => Ok(Self::HardDelete),
+            _ => Err(format!("unknown reset mode: {s}")),
+        }
+    }
+}
+
+/// What `ConversationLogger::reset_channel` cleared.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelReset {
+    /// The archived transcript, or `None` if the channel had no messages.
+    pub archive_path: Option<std::path::PathBuf>,
+    pub messages_cleared: u64,
+    pub summaries_cleared: u64,
+    /// Whether an active OpenCode session mapping was removed.
+    pub session_cleared: bool,
+}
+
 impl ConversationLogger {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            redactor: None,
+            cipher: None,
+            tokenizer: Arc::new(CharTokenizer),
+            include_hidden: false,
+            merge_turns: false,
+            recent_cache: None,
+            write_limiter: None,
+        }
+    }
+
+    /// Count stored token sizes with this tokenizer instead of the
+    /// character heuristic.
+    pub fn with_tokenizer(mut self, tokenizer: Arc<dyn Tokenizer>) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Encrypt content at rest with this cipher. `None` stores plaintext.
+    pub fn with_cipher(mut self, cipher: Option<Arc<ContentCipher>>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Include messages hidden with `hide_message` in recent history and
+    /// context assembly. They're left out by default.
+    pub fn with_hidden_messages(mut self, include: bool) -> Self {
+        self.include_hidden = include;
+        self
+    }
+
+    /// Merge consecutive same-role messages into one turn in context
+    /// assembly (see `merge_consecutive_turns`). Off by default. The stored
+    /// rows are never changed.
+    pub fn with_merged_turns(mut self, merge: bool) -> Self {
+        self.merge_turns = merge;
+        self
+    }
+
+    /// Serve `load_recent` from this cache when it can, keeping it updated
+    /// as messages are logged. Share one cache between every logger of an
+    /// agent, so changes made through any of them reach it.
+    pub fn with_recent_cache(mut self, cache: Option<Arc<RecentHistoryCache>>) -> Self {
+        self.recent_cache = cache;
+        self
+    }
+
+    /// Run fire-and-forget writes through this limiter. Share one between
+    /// every logger of an agent, like the recent cache. `None` spawns each
+    /// write directly.
+    pub fn with_write_limiter(mut self, limiter: Option<Arc<WriteLimiter>>) -> Self {
+        self.write_limiter = limiter;
+        self
+    }
+
+    /// Spawn a fire-and-forget write, through the limiter if there is one.
+    fn spawn_write<F>(&self, write: F)
+    where
+        F: std::future::Future<Output = ()> + Send + 'static,
+    {
+        match &self.write_limiter {
+            Some(limiter) => limiter.spawn(write),
+            None => {
+                tokio::spawn(write);
+            }
+        }
+    }
+
+    /// Pair a message about to be written with the cache it goes into once
+    /// the write lands. `None` without a cache.
+    fn cache_entry(
+        &self,
+        message: impl FnOnce() -> ConversationMessage,
+    ) -> Option<(Arc<RecentHistoryCache>, ConversationMessage)> {
+        self.recent_cache.clone().map(|cache| (cache, message()))
+    }
+
+    fn seal(&self, value: String) -> String {
+        match &self.cipher {
+            Some(cipher) => cipher.seal(&value),
+            None => value,
+        }
+    }
+
+    fn open_message(&self, mut message: ConversationMessage) -> ConversationMessage {
+        message.content = open_value(self.cipher.as_deref(), message.content);
+        message.metadata = message.metadata.map(|metadata| open_value(self.cipher.as_deref(), metadata));
+        message
+    }
+
+    fn open_summary(&self, mut summary: CompactionSummary) -> CompactionSummary {
+        summary.summary = open_value(self.cipher.as_deref(), summary.summary);
+        summary
+    }
+
+    /// Redact secrets from message content before it's persisted.
+    pub fn with_redactor(mut self, redactor: Arc<Redactor>) -> Self {
+        self.redactor = Some(redactor);
+        self
+    }
+
+    fn redact(&self, channel_id: &ChannelId, content: &str) -> String {
+        match &self.redactor {
+            Some(redactor) => redactor.redact(channel_id, content).into_owned(),
+            None => content.to_string(),
+        }
     }
 
-    /// Log a user message. Fire-and-forget.
+    /// Log a user message under its idempotency key (see
+    /// `opencode::idempotency::message_key`). Fire-and-forget.
     pub fn log_user_message(
         &self,
         channel_id: &ChannelId,
@@ -41,19 +361,37 @@ impl ConversationLogger {
         sender_id: &str,
         content: &str,
         metadata: &HashMap<String, serde_json::Value>,
+        idempotency_key: &str,
     ) {
         let pool = self.pool.clone();
         let id = uuid::Uuid::new_v4().to_string();
+        let content = self.redact(channel_id, content);
+        let token_count = self.tokenizer.count(&content) as i64;
+        let metadata_json = serde_json::to_string(metadata).ok();
+        let cached = self.cache_entry(|| ConversationMessage {
+            id: id.clone(),
+            channel_id: channel_id.to_string(),
+            role: "user".into(),
+            sender_name: Some(sender_name.to_string()),
+            sender_id: Some(sender_id.to_string()),
+            content: content.clone(),
+            metadata: metadata_json.clone(),
+            token_count: Some(token_count),
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        });
+        let content = self.seal(content);
         let channel_id = channel_id.to_string();
         let sender_name = sender_name.to_string();
         let sender_id = sender_id.to_string();
-        let content = content.to_string();
-        let metadata_json = serde_json::to_string(metadata).ok();
+        let metadata_json = metadata_json.map(|json| self.seal(json));
+        let idempotency_key = idempotency_key.to_string();
 
-        tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
-                "INSERT INTO conversation_messages (id, channel_id, role, sender_name, sender_id, content, metadata) \
-                 VALUES (?, ?, 'user', ?, ?, ?, ?)"
+        self.spawn_write(async move {
+            let result = with_retry(|| sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, sender_name, sender_id, content, metadata, token_count, idempotency_key) \
+                 VALUES (?, ?, 'user', ?, ?, ?, ?, ?, ?)"
             )
             .bind(&id)
             .bind(&channel_id)
@@ -61,11 +399,14 @@ impl ConversationLogger {
             .bind(&sender_id)
             .bind(&content)
             .bind(&metadata_json)
-            .execute(&pool)
-            .await
-            {
+            .bind(token_count)
+            .bind(&idempotency_key)
+            .execute(&pool))
+            .await;
+            if let Err(error) = &result {
                 tracing::warn!(%error, "failed to persist user message");
             }
+            update_recent_cache(cached, result.is_ok());
         });
     }
 
@@ -73,56 +414,413 @@ impl ConversationLogger {
     pub fn log_bot_message(&self, channel_id: &ChannelId, content: &str) {
         let pool = self.pool.clone();
         let id = uuid::Uuid::new_v4().to_string();
+        let content = self.redact(channel_id, content);
+        let token_count = self.tokenizer.count(&content) as i64;
+        let cached = self.cache_entry(|| ConversationMessage {
+            id: id.clone(),
+            channel_id: channel_id.to_string(),
+            role: "assistant".into(),
+            sender_name: None,
+            sender_id: None,
+            content: content.clone(),
+            metadata: None,
+            token_count: Some(token_count),
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        });
+        let content = self.seal(content);
         let channel_id = channel_id.to_string();
-        let content = content.to_string();
 
-        tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
-                "INSERT INTO conversation_messages (id, channel_id, role, content) \
-                 VALUES (?, ?, 'assistant', ?)"
+        self.spawn_write(async move {
+            let result = with_retry(|| sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, content, token_count) \
+                 VALUES (?, ?, 'assistant', ?, ?)"
             )
             .bind(&id)
             .bind(&channel_id)
             .bind(&content)
-            .execute(&pool)
-            .await
-            {
+            .bind(token_count)
+            .execute(&pool))
+            .await;
+            if let Err(error) = &result {
                 tracing::warn!(%error, "failed to persist bot message");
             }
+            update_recent_cache(cached, result.is_ok());
+        });
+    }
+
+    /// Log a message Spacebot injected rather than one a user or the model
+    /// wrote, such as a notice posted to the channel. Stored with
+    /// `is_synthetic` set. Fire-and-forget.
+    pub fn log_synthetic_message(&self, channel_id: &ChannelId, role: &str, content: &str) {
+        let pool = self.pool.clone();
+        let id = uuid::Uuid::new_v4().to_string();
+        let content = self.redact(channel_id, content);
+        let token_count = self.tokenizer.count(&content) as i64;
+        let cached = self.cache_entry(|| ConversationMessage {
+            id: id.clone(),
+            channel_id: channel_id.to_string(),
+            role: role.to_string(),
+            sender_name: None,
+            sender_id: None,
+            content: content.clone(),
+            metadata: None,
+            token_count: Some(token_count),
+            is_synthetic: true,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        });
+        let content = self.seal(content);
+        let channel_id = channel_id.to_string();
+        let role = role.to_string();
+
+        self.spawn_write(async move {
+            let result = with_retry(|| sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, content, token_count, is_synthetic) \
+                 VALUES (?, ?, ?, ?, ?, 1)"
+            )
+            .bind(&id)
+            .bind(&channel_id)
+            .bind(&role)
+            .bind(&content)
+            .bind(token_count)
+            .execute(&pool))
+            .await;
+            if let Err(error) = &result {
+                tracing::warn!(%error, "failed to persist synthetic message");
+            }
+            update_recent_cache(cached, result.is_ok());
+        });
+    }
+
+    /// Log a partial assistant reply from a turn that was cut short by a newer
+    /// message. Stored with `{"interrupted": true}` metadata. Fire-and-forget.
+    pub fn log_interrupted_bot_message(&self, channel_id: &ChannelId, content: &str) {
+        let pool = self.pool.clone();
+        let id = uuid::Uuid::new_v4().to_string();
+        let content = self.redact(channel_id, content);
+        let token_count = self.tokenizer.count(&content) as i64;
+        let cached = self.cache_entry(|| ConversationMessage {
+            id: id.clone(),
+            channel_id: channel_id.to_string(),
+            role: "assistant".into(),
+            sender_name: None,
+            sender_id: None,
+            content: content.clone(),
+            metadata: Some(serde_json::json!({ "interrupted": true }).to_string()),
+            token_count: Some(token_count),
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        });
+        let content = self.seal(content);
+        let channel_id = channel_id.to_string();
+        let metadata_json = self.seal(serde_json::json!({ "interrupted": true }).to_string());
+
+        self.spawn_write(async move {
+            let result = with_retry(|| sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, content, metadata, token_count) \
+                 VALUES (?, ?, 'assistant', ?, ?, ?)"
+            )
+            .bind(&id)
+            .bind(&channel_id)
+            .bind(&content)
+            .bind(&metadata_json)
+            .bind(token_count)
+            .execute(&pool))
+            .await;
+            if let Err(error) = &result {
+                tracing::warn!(%error, "failed to persist interrupted bot message");
+            }
+            update_recent_cache(cached, result.is_ok());
         });
     }
 
-    /// Load recent messages for a channel (oldest first).
+    /// Write a streaming assistant reply's content so far under `id`,
+    /// creating its row on the first call. The row stays `pending` until
+    /// `finalize_bot_message`, so a crash mid-turn leaves the partial reply
+    /// behind rather than nothing. Waits for the write, so checkpoints land
+    /// in order.
+    pub async fn checkpoint_bot_message(
+        &self,
+        channel_id: &ChannelId,
+        id: &str,
+        content: &str,
+    ) -> crate::error::Result<()> {
+        self.upsert_bot_message(channel_id, id, content, "pending", None).await
+    }
+
+    /// Write a streamed reply's final content and mark it `complete`, or
+    /// `interrupted` (with `{"interrupted": true}` metadata, like
+    /// `log_interrupted_bot_message`) if the turn was cut short. The reply
+    /// moves to the end of the channel's history, where a reply logged in
+    /// one piece would have landed.
+    pub async fn finalize_bot_message(
+        &self,
+        channel_id: &ChannelId,
+        id: &str,
+        content: &str,
+        interrupted: bool,
+    ) -> crate::error::Result<()> {
+        if interrupted {
+            let metadata_json = self.seal(serde_json::json!({ "interrupted": true }).to_string());
+            self.upsert_bot_message(channel_id, id, content, "interrupted", Some(metadata_json)).await
+        } else {
+            self.upsert_bot_message(channel_id, id, content, "complete", None).await
+        }
+    }
+
+    /// Mark every reply still `pending` as interrupted. At startup these are
+    /// replies whose turn died with the previous process. Returns how many
+    /// were marked.
+    pub async fn recover_pending_messages(&self) -> crate::error::Result<u64> {
+        let channels: Vec<String> = with_retry(|| sqlx::query_scalar(
+            "SELECT DISTINCT channel_id FROM conversation_messages WHERE status = 'pending'"
+        )
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+        if channels.is_empty() {
+            return Ok(0);
+        }
+
+        let metadata_json = self.seal(serde_json::json!({ "interrupted": true }).to_string());
+        let result = with_retry(|| sqlx::query(
+            "UPDATE conversation_messages SET status = 'interrupted', metadata = COALESCE(metadata, ?) \
+             WHERE status = 'pending'"
+        )
+        .bind(&metadata_json)
+        .execute(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        if let Some(cache) = &self.recent_cache {
+            for channel_id in &channels {
+                cache.invalidate(channel_id);
+            }
+        }
+        Ok(result.rows_affected())
+    }
+
+    /// Insert or rewrite a streamed reply. A row that already left `pending`
+    /// is never rewritten.
+    async fn upsert_bot_message(
+        &self,
+        channel_id: &ChannelId,
+        id: &str,
+        content: &str,
+        status: &str,
+        metadata_json: Option<String>,
+    ) -> crate::error::Result<()> {
+        let content = self.redact(channel_id, content);
+        let token_count = self.tokenizer.count(&content) as i64;
+        let content = self.seal(content);
+        // Finalizing stamps the reply with the time it finished.
+        let finished = status != "pending";
+
+        with_retry(|| sqlx::query(
+            "INSERT INTO conversation_messages (id, channel_id, role, content, metadata, token_count, status) \
+             VALUES (?1, ?2, 'assistant', ?3, ?4, ?5, ?6) \
+             ON CONFLICT(id) DO UPDATE SET \
+                 content = excluded.content, \
+                 metadata = COALESCE(excluded.metadata, conversation_messages.metadata), \
+                 token_count = excluded.token_count, \
+                 status = excluded.status, \
+                 created_at = CASE WHEN ?7 THEN CURRENT_TIMESTAMP ELSE conversation_messages.created_at END \
+             WHERE conversation_messages.status = 'pending'"
+        )
+        .bind(id)
+        .bind(channel_id.as_ref())
+        .bind(&content)
+        .bind(&metadata_json)
+        .bind(token_count)
+        .bind(status)
+        .bind(finished)
+        .execute(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        // The row changes under any cached copy of it.
+        if let Some(cache) = &self.recent_cache {
+            cache.invalidate(channel_id);
+        }
+        Ok(())
+    }
+
+    /// Insert a message recovered from OpenCode (e.g. sent while the bot was
+    /// down), keeping its original timestamp. Unlike the `log_*` methods this
+    /// waits for the write, so callers can count what was backfilled.
+    /// Returns `false` if the channel already has the message, by OpenCode
+    /// message ID.
+    ///
+    /// The OpenCode message ID and, for assistant replies, the provider and
+    /// model are kept in the metadata. OpenCode's output token count is used
+    /// when it reported one (it covers the whole reply, tool calls included);
+    /// otherwise the content is counted with the tokenizer. `is_synthetic`
+    /// marks content Spacebot injected into the session.
+    pub async fn backfill_message(
+        &self,
+        channel_id: &ChannelId,
+        info: &MessageInfo,
+        content: &str,
+        is_synthetic: bool,
+        created_at: chrono::DateTime<chrono::Utc>,
+    ) -> crate::error::Result<bool> {
+        let content = self.redact(channel_id, content);
+        let token_count = info
+            .tokens
+            .map(|tokens| tokens.output)
+            .filter(|output| *output > 0)
+            .map_or_else(|| self.tokenizer.count(&content) as i64, |output| output as i64);
+        let content = self.seal(content);
+        let mut metadata = serde_json::json!({ "backfilled": true, "opencode_message_id": info.id });
+        if let (Some(provider_id), Some(model_id)) = (&info.provider_id, &info.model_id) {
+            metadata["provider_id"] = provider_id.as_str().into();
+            metadata["model_id"] = model_id.as_str().into();
+        }
+        let metadata_json = self.seal(metadata.to_string());
+
+        let result = with_retry(|| sqlx::query(
+            "INSERT INTO conversation_messages \
+             (id, channel_id, role, content, metadata, token_count, is_synthetic, created_at, opencode_message_id) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT DO NOTHING"
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(channel_id.as_ref())
+        .bind(&info.role)
+        .bind(&content)
+        .bind(&metadata_json)
+        .bind(token_count)
+        .bind(is_synthetic)
+        .bind(sqlite_timestamp(created_at))
+        .bind(&info.id)
+        .execute(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        // Backfilled messages land in the past, not at the end.
+        if let Some(cache) = &self.recent_cache {
+            cache.invalidate(channel_id);
+        }
+        Ok(true)
+    }
+
+    /// Timestamp of the newest message stored for a channel.
+    pub async fn latest_message_time(
+        &self,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let latest = with_retry(|| sqlx::query_scalar::<_, Option<chrono::DateTime<chrono::Utc>>>(
+            "SELECT MAX(created_at) FROM conversation_messages WHERE channel_id = ?"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_one(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(latest)
+    }
+
+    /// When the channel last had activity: its newest message since the last
+    /// reset. `None` if it has no messages left. Unlike
+    /// `latest_message_time`, cleared messages don't count.
+    pub async fn last_activity(
+        &self,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<Option<chrono::DateTime<chrono::Utc>>> {
+        let last_activity = with_retry(|| sqlx::query_scalar::<_, Option<chrono::DateTime<chrono::Utc>>>(
+            "SELECT MAX(created_at) FROM conversation_messages WHERE channel_id = ? AND cleared_at IS NULL"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_one(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(last_activity)
+    }
+
+    /// Channel IDs with their last activity (see `last_activity`), most
+    /// recently active first. Channels with no messages left are omitted.
+    pub async fn channels_ordered_by_activity(
+        &self,
+        limit: i64,
+    ) -> crate::error::Result<Vec<(String, chrono::DateTime<chrono::Utc>)>> {
+        let channels = with_retry(|| sqlx::query_as::<_, (String, chrono::DateTime<chrono::Utc>)>(
+            "SELECT channel_id, MAX(created_at) AS last_activity_at \
+             FROM conversation_messages \
+             WHERE cleared_at IS NULL \
+             GROUP BY channel_id \
+             ORDER BY last_activity_at DESC \
+             LIMIT ?"
+        )
+        .bind(limit)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(channels)
+    }
+
+    /// Load recent messages for a channel (oldest first). Synthetic messages
+    /// are left out (and don't count toward `limit`) unless
+    /// `include_synthetic` is set; hidden ones unless the logger includes them.
     pub async fn load_recent(
         &self,
         channel_id: &ChannelId,
         limit: i64,
+        include_synthetic: bool,
     ) -> crate::error::Result<Vec<ConversationMessage>> {
-        let rows = sqlx::query(
-            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, created_at \
+        let Some(cache) = &self.recent_cache else {
+            return self.query_recent(channel_id, limit, include_synthetic, self.include_hidden).await;
+        };
+        if let Some(messages) = cache.get(channel_id, limit, include_synthetic, self.include_hidden) {
+            return Ok(messages);
+        }
+
+        // Fill the cache with everything recent, then answer from it. Falls
+        // back to the exact query if the fill can't answer (the channel has
+        // more history than the cache holds and the filters left too few).
+        let generation = cache.generation();
+        let recent = self
+            .query_recent(channel_id, cache.messages_per_channel() as i64, true, true)
+            .await?;
+        cache.fill(channel_id, generation, recent);
+        match cache.peek(channel_id, limit, include_synthetic, self.include_hidden) {
+            Some(messages) => Ok(messages),
+            None => self.query_recent(channel_id, limit, include_synthetic, self.include_hidden).await,
+        }
+    }
+
+    async fn query_recent(
+        &self,
+        channel_id: &ChannelId,
+        limit: i64,
+        include_synthetic: bool,
+        include_hidden: bool,
+    ) -> crate::error::Result<Vec<ConversationMessage>> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
              FROM conversation_messages \
-             WHERE channel_id = ? \
+             WHERE channel_id = ?1 AND (?3 OR is_synthetic = 0) AND (?4 OR is_hidden = 0) AND cleared_at IS NULL \
              ORDER BY created_at DESC \
-             LIMIT ?"
+             LIMIT ?2"
         )
         .bind(channel_id.as_ref())
         .bind(limit)
-        .fetch_all(&self.pool)
+        .bind(include_synthetic)
+        .bind(include_hidden)
+        .fetch_all(&self.pool))
         .await
-        .map_err(|e| anyhow::anyhow!(e))?;
+        .map_err(HistoryError::Database)?;
 
         let mut messages: Vec<ConversationMessage> = rows
             .into_iter()
-            .map(|row| ConversationMessage {
-                id: row.try_get("id").unwrap_or_default(),
-                channel_id: row.try_get("channel_id").unwrap_or_default(),
-                role: row.try_get("role").unwrap_or_default(),
-                sender_name: row.try_get("sender_name").ok(),
-                sender_id: row.try_get("sender_id").ok(),
-                content: row.try_get("content").unwrap_or_default(),
-                metadata: row.try_get("metadata").ok(),
-                created_at: row.try_get("created_at").unwrap_or_else(|_| chrono::Utc::now()),
-            })
+            .map(|row| self.open_message(row_to_message(row)))
             .collect();
 
         // Reverse to chronological order
@@ -132,42 +830,1148 @@ impl ConversationLogger {
     }
 
     /// Load recent messages from any channel (not just the current one).
+    /// Synthetic messages are left out, and so are hidden ones unless the
+    /// logger includes them.
     pub async fn load_channel_transcript(
         &self,
         channel_id: &str,
         limit: i64,
     ) -> crate::error::Result<Vec<ConversationMessage>> {
-        let rows = sqlx::query(
-            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, created_at \
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
              FROM conversation_messages \
-             WHERE channel_id = ? \
+             WHERE channel_id = ? AND is_synthetic = 0 AND (? OR is_hidden = 0) AND cleared_at IS NULL \
              ORDER BY created_at DESC \
              LIMIT ?"
         )
         .bind(channel_id)
+        .bind(self.include_hidden)
+        .bind(limit)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        let mut messages: Vec<ConversationMessage> = rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect();
+
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Load a channel's messages created in `[start, end)` (oldest first).
+    ///
+    /// With a `limit`, the earliest `limit` messages in the window are returned.
+    /// Served by the `(channel_id, created_at)` index.
+    pub async fn load_between(
+        &self,
+        channel_id: &ChannelId,
+        start: chrono::DateTime<chrono::Utc>,
+        end: chrono::DateTime<chrono::Utc>,
+        limit: Option<i64>,
+    ) -> crate::error::Result<Vec<ConversationMessage>> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ? AND created_at >= ? AND created_at < ? AND cleared_at IS NULL \
+             ORDER BY created_at ASC, rowid ASC \
+             LIMIT ?"
+        )
+        .bind(channel_id.as_ref())
+        .bind(sqlite_timestamp(start))
+        .bind(sqlite_timestamp(end))
+        // SQLite treats a negative LIMIT as unbounded.
+        .bind(limit.unwrap_or(-1))
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect())
+    }
+
+    /// Load the most recent messages from one sender (oldest first).
+    ///
+    /// With no `channel_id`, spans every channel the sender posted in.
+    pub async fn load_by_sender(
+        &self,
+        sender_id: &str,
+        channel_id: Option<&ChannelId>,
+        limit: i64,
+    ) -> crate::error::Result<Vec<ConversationMessage>> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE sender_id = ?1 AND (?2 IS NULL OR channel_id = ?2) AND cleared_at IS NULL \
+             ORDER BY created_at DESC, rowid DESC \
+             LIMIT ?3"
+        )
+        .bind(sender_id)
+        .bind(channel_id.map(|id| id.as_ref()))
         .bind(limit)
-        .fetch_all(&self.pool)
+        .fetch_all(&self.pool))
         .await
-        .map_err(|e| anyhow::anyhow!(e))?;
+        .map_err(HistoryError::Database)?;
+
+        let mut messages: Vec<ConversationMessage> = rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Load a channel's most recent messages whose metadata has `value` at
+    /// the top-level `key` (oldest first), e.g. every message tagged with a
+    /// command source. Numbers and booleans match their SQL text form (`42`,
+    /// `1`/`0`). Synthetic messages are included.
+    ///
+    /// Filtered with `json_extract` in SQLite. With a `ContentCipher` the
+    /// stored metadata is opaque, so the channel's messages are decrypted and
+    /// filtered here instead.
+    pub async fn load_where_metadata(
+        &self,
+        channel_id: &ChannelId,
+        key: &str,
+        value: &str,
+        limit: i64,
+    ) -> crate::error::Result<Vec<ConversationMessage>> {
+        if self.cipher.is_some() {
+            let rows = with_retry(|| sqlx::query(
+                "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+                 FROM conversation_messages \
+                 WHERE channel_id = ? AND metadata IS NOT NULL AND cleared_at IS NULL \
+                 ORDER BY created_at DESC, rowid DESC"
+            )
+            .bind(channel_id.as_ref())
+            .fetch_all(&self.pool))
+            .await
+            .map_err(HistoryError::Database)?;
+
+            let mut messages: Vec<ConversationMessage> = rows
+                .into_iter()
+                .map(|row| self.open_message(row_to_message(row)))
+                .filter(|message| {
+                    message.metadata.as_deref().is_some_and(|metadata| metadata_matches(metadata, key, value))
+                })
+                .take(limit.max(0) as usize)
+                .collect();
+            messages.reverse();
+            return Ok(messages);
+        }
+
+        // Quoted so keys containing `.` or `[` aren't read as a path. JSON
+        // paths have no escape for a quote inside the label.
+        if key.contains('"') {
+            return Err(HistoryError::InvalidQuery(format!("metadata key can't contain a double quote: {key}")).into());
+        }
+        let path = format!("$.\"{key}\"");
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ?1 AND cleared_at IS NULL \
+             AND CAST(CASE WHEN json_valid(metadata) THEN json_extract(metadata, ?2) END AS TEXT) = ?3 \
+             ORDER BY created_at DESC, rowid DESC \
+             LIMIT ?4"
+        )
+        .bind(channel_id.as_ref())
+        .bind(&path)
+        .bind(value)
+        .bind(limit)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        let mut messages: Vec<ConversationMessage> = rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect();
+        messages.reverse();
+        Ok(messages)
+    }
+
+    /// Hide a message from context without deleting it. The row keeps its
+    /// content and metadata for audit and export. Returns `false` if no
+    /// message has that id.
+    pub async fn hide_message(&self, id: &str) -> crate::error::Result<bool> {
+        self.set_hidden(id, true).await
+    }
+
+    /// Undo `hide_message`. Returns `false` if no message has that id.
+    pub async fn unhide_message(&self, id: &str) -> crate::error::Result<bool> {
+        self.set_hidden(id, false).await
+    }
+
+    /// The channel's latest visible message from a user, e.g. the prompt to
+    /// regenerate a reply to. Synthetic and hidden messages are skipped.
+    pub async fn last_user_message(
+        &self,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<Option<ConversationMessage>> {
+        let row = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ? AND role = 'user' AND is_synthetic = 0 AND is_hidden = 0 AND cleared_at IS NULL \
+             ORDER BY created_at DESC, rowid DESC \
+             LIMIT 1"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_optional(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(row.map(|row| self.open_message(row_to_message(row))))
+    }
+
+    /// Mark the replies logged after `message` as superseded, ahead of
+    /// regenerating them. They are hidden from context but not deleted.
+    /// Returns how many replies were superseded.
+    pub async fn supersede_replies(
+        &self,
+        channel_id: &ChannelId,
+        message: &ConversationMessage,
+    ) -> crate::error::Result<u64> {
+        let result = with_retry(|| sqlx::query(
+            "UPDATE conversation_messages SET is_hidden = 1, superseded_at = CURRENT_TIMESTAMP \
+             WHERE channel_id = ?1 AND role = 'assistant' AND is_hidden = 0 AND cleared_at IS NULL \
+             AND rowid > (SELECT rowid FROM conversation_messages WHERE id = ?2)"
+        )
+        .bind(channel_id.as_ref())
+        .bind(&message.id)
+        .execute(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        if let Some(cache) = &self.recent_cache {
+            cache.invalidate(channel_id);
+        }
+        Ok(result.rows_affected())
+    }
+
+    async fn set_hidden(&self, id: &str, hidden: bool) -> crate::error::Result<bool> {
+        let result = with_retry(|| sqlx::query("UPDATE conversation_messages SET is_hidden = ? WHERE id = ?")
+            .bind(hidden)
+            .bind(id)
+            .execute(&self.pool))
+            .await
+            .map_err(HistoryError::Database)?;
+        if let Some(cache) = &self.recent_cache {
+            cache.set_hidden(id, hidden);
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Persist a compaction summary for a channel. Returns the new summary ID.
+    ///
+    /// Records no covered range, so the summary's boundary is its creation
+    /// time. Use `save_compaction_summary_through` when the summarized
+    /// messages are stored.
+    pub async fn save_compaction_summary(
+        &self,
+        channel_id: &ChannelId,
+        summary: &str,
+        turns_covered: i64,
+    ) -> crate::error::Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+
+        with_retry(|| sqlx::query(
+            "INSERT INTO compaction_summaries (id, channel_id, summary, turns_covered) \
+             VALUES (?, ?, ?, ?)"
+        )
+        .bind(&id)
+        .bind(channel_id.as_ref())
+        .bind(self.seal(summary.to_string()))
+        .bind(turns_covered)
+        .execute(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(id)
+    }
+
+    /// Persist a compaction summary that replaces exactly the `covered`
+    /// messages (oldest first).
+    ///
+    /// The first and last message IDs are stored as the covered range, so
+    /// messages logged while the summary was being generated stay on the
+    /// uncompacted side of the boundary, even within the same second. The
+    /// summary is timestamped with the last covered message.
+    pub async fn save_compaction_summary_through(
+        &self,
+        channel_id: &ChannelId,
+        summary: &str,
+        covered: &[ConversationMessage],
+    ) -> crate::error::Result<String> {
+        let id = uuid::Uuid::new_v4().to_string();
+        let covered_from = covered.first().map(|message| message.id.as_str());
+        let covered_to = covered.last().map(|message| message.id.as_str());
+        let created_at = covered.last().map(|message| message.created_at).unwrap_or_else(chrono::Utc::now);
+
+        with_retry(|| sqlx::query(
+            "INSERT INTO compaction_summaries (id, channel_id, summary, turns_covered, covered_from, covered_to, created_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?)"
+        )
+        .bind(&id)
+        .bind(channel_id.as_ref())
+        .bind(self.seal(summary.to_string()))
+        .bind(covered.len() as i64)
+        .bind(covered_from)
+        .bind(covered_to)
+        .bind(sqlite_timestamp(created_at))
+        .execute(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(id)
+    }
+
+    /// Load every non-synthetic message since the channel's latest compaction
+    /// summary (oldest first): the messages `turns_since_last_compaction`
+    /// counts. Hidden messages are left out unless the logger includes them.
+    pub async fn load_since_last_compaction(
+        &self,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<Vec<ConversationMessage>> {
+        let query = format!(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ?1 AND is_synthetic = 0 AND (?2 OR is_hidden = 0) AND cleared_at IS NULL \
+             AND {AFTER_LATEST_SUMMARY} \
+             ORDER BY created_at ASC, rowid ASC"
+        );
+        let rows = with_retry(|| sqlx::query(&query)
+        .bind(channel_id.as_ref())
+        .bind(self.include_hidden)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect())
+    }
+
+    /// Load all compaction summaries for a channel (oldest first).
+    pub async fn load_compaction_summaries(
+        &self,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<Vec<CompactionSummary>> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, summary, turns_covered, covered_from, covered_to, created_at \
+             FROM compaction_summaries \
+             WHERE channel_id = ? AND cleared_at IS NULL \
+             ORDER BY created_at ASC, rowid ASC"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| self.open_summary(row_to_compaction_summary(row)))
+            .collect())
+    }
+
+    /// Load a channel's rolling context: all compaction summaries (oldest
+    /// first) plus the last `recent_turns` non-synthetic messages.
+    ///
+    /// Only messages after the newest summary's boundary are included, so a
+    /// turn is never both summarized and repeated verbatim. Hidden messages
+    /// are left out unless the logger includes them. With merged turns,
+    /// `recent_turns` counts messages before merging.
+    pub async fn assemble_context(
+        &self,
+        channel_id: &ChannelId,
+        recent_turns: i64,
+    ) -> crate::error::Result<RollingContext> {
+        let summaries = self.load_compaction_summaries(channel_id).await?;
+
+        let query = format!(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ?1 AND is_synthetic = 0 AND (?3 OR is_hidden = 0) AND cleared_at IS NULL \
+             AND {AFTER_LATEST_SUMMARY} \
+             ORDER BY created_at DESC, rowid DESC \
+             LIMIT ?2"
+        );
+        let rows = with_retry(|| sqlx::query(&query)
+        .bind(channel_id.as_ref())
+        .bind(recent_turns)
+        .bind(self.include_hidden)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        let mut turns: Vec<ConversationMessage> = rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect();
+        turns.reverse();
+        if self.merge_turns {
+            turns = merge_consecutive_turns(turns);
+        }
+
+        Ok(RollingContext { summaries, turns })
+    }
+
+    /// Keep at most `max` compaction summaries for a channel.
+    ///
+    /// The oldest summaries beyond the cap are either deleted or folded into
+    /// the oldest surviving summary, depending on `overflow`. Returns the
+    /// number of rows affected (deleted plus updated).
+    pub async fn cap_summaries_per_channel(
+        &self,
+        channel_id: &ChannelId,
+        max: usize,
+        overflow: SummaryOverflow,
+    ) -> crate::error::Result<u64> {
+        let mut transaction = self.pool.begin().await.map_err(HistoryError::Database)?;
+
+        let rows = sqlx::query(
+            "SELECT id, channel_id, summary, turns_covered, covered_from, covered_to, created_at \
+             FROM compaction_summaries \
+             WHERE channel_id = ? AND cleared_at IS NULL \
+             ORDER BY created_at ASC, rowid ASC"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_all(&mut *transaction)
+        .await
+        .map_err(HistoryError::Database)?;
+
+        let summaries: Vec<CompactionSummary> = rows
+            .into_iter()
+            .map(|row| self.open_summary(row_to_compaction_summary(row)))
+            .collect();
+        if summaries.len() <= max {
+            return Ok(0);
+        }
+
+        let (overflowing, kept) = summaries.split_at(summaries.len() - max);
+        let mut rows_affected = 0;
+
+        if overflow == SummaryOverflow::Merge {
+            if let Some(survivor) = kept.first() {
+                let merged: Vec<&CompactionSummary> =
+                    overflowing.iter().chain(std::iter::once(survivor)).collect();
+                let summary = merged
+                    .iter()
+                    .map(|s| s.summary.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n");
+                let turns_covered: i64 = merged.iter().map(|s| s.turns_covered).sum();
+                // The survivor now also replaces the overflowing summaries'
+                // messages, so its range starts where the oldest one did.
+                let covered_from = merged.first().and_then(|s| s.covered_from.as_deref());
+
+                rows_affected += sqlx::query(
+                    "UPDATE compaction_summaries SET summary = ?, turns_covered = ?, covered_from = ? WHERE id = ?"
+                )
+                .bind(self.seal(summary))
+                .bind(turns_covered)
+                .bind(covered_from)
+                .bind(&survivor.id)
+                .execute(&mut *transaction)
+                .await
+                .map_err(HistoryError::Database)?
+                .rows_affected();
+            }
+        }
+
+        for summary in overflowing {
+            rows_affected += sqlx::query("DELETE FROM compaction_summaries WHERE id = ?")
+                .bind(&summary.id)
+                .execute(&mut *transaction)
+                .await
+                .map_err(HistoryError::Database)?
+                .rows_affected();
+        }
+
+        transaction.commit().await.map_err(HistoryError::Database)?;
+
+        Ok(rows_affected)
+    }
+
+    /// Write the raw transcript of compacted messages to `archives_dir`, so
+    /// the full text survives after the summary replaces it in prompts.
+    ///
+    /// One file per compaction, named after the channel and time. Sealed with
+    /// the cipher, if one is set. Returns the path written, or
+    /// `HistoryError::NotFound` if there are no messages to archive.
+    pub async fn archive_transcript(
+        &self,
+        channel_id: &ChannelId,
+        messages: &[ConversationMessage],
+        archives_dir: &std::path::Path,
+    ) -> crate::error::Result<std::path::PathBuf> {
+        if messages.is_empty() {
+            return Err(HistoryError::NotFound { channel_id: channel_id.to_string() }.into());
+        }
+
+        let mut transcript = String::new();
+        for message in messages {
+            let timestamp = sqlite_timestamp(message.created_at);
+            let line = match message.sender_name.as_deref().filter(|name| !name.is_empty()) {
+                Some(name) => format!("[{timestamp}] {name} ({}): {}\n", message.role, message.content),
+                None => format!("[{timestamp}] {}: {}\n", message.role, message.content),
+            };
+            transcript.push_str(&line);
+        }
+
+        let channel: String = channel_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+            .collect();
+        let path = archives_dir.join(format!(
+            "{channel}-{}.txt",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S%.3f")
+        ));
+
+        let archive_error = |source: std::io::Error| HistoryError::Archive {
+            path: path.display().to_string(),
+            source: Arc::new(source),
+        };
+        tokio::fs::create_dir_all(archives_dir).await.map_err(archive_error)?;
+        tokio::fs::write(&path, self.seal(transcript)).await.map_err(archive_error)?;
+
+        Ok(path)
+    }
+
+    /// Wipe a channel's live context so its next message starts fresh.
+    ///
+    /// Archives the transcript of every live message to `archives_dir`,
+    /// then clears the channel's messages and summaries (per `mode`) and its
+    /// active OpenCode session mapping in one transaction.
+    pub async fn reset_channel(
+        &self,
+        channel_id: &ChannelId,
+        archives_dir: &std::path::Path,
+        mode: ResetMode,
+    ) -> crate::error::Result<ChannelReset> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ? AND cleared_at IS NULL \
+             ORDER BY created_at ASC, rowid ASC"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+        let messages: Vec<ConversationMessage> = rows
+            .into_iter()
+            .map(|row| self.open_message(row_to_message(row)))
+            .collect();
+
+        let archive_path = if messages.is_empty() {
+            None
+        } else {
+            Some(self.archive_transcript(channel_id, &messages, archives_dir).await?)
+        };
+
+        let (clear_messages, clear_summaries) = match mode {
+            ResetMode::SoftDelete => (
+                "UPDATE conversation_messages SET cleared_at = CURRENT_TIMESTAMP \
+                 WHERE channel_id = ? AND cleared_at IS NULL",
+                "UPDATE compaction_summaries SET cleared_at = CURRENT_TIMESTAMP \
+                 WHERE channel_id = ? AND cleared_at IS NULL",
+            ),
+            ResetMode::HardDelete => (
+                "DELETE FROM conversation_messages WHERE channel_id = ?",
+                "DELETE FROM compaction_summaries WHERE channel_id = ?",
+            ),
+        };
+
+        let mut transaction = self.pool.begin().await.map_err(HistoryError::Database)?;
+        let messages_cleared = sqlx::query(clear_messages)
+            .bind(channel_id.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .map_err(HistoryError::Database)?
+            .rows_affected();
+        let summaries_cleared = sqlx::query(clear_summaries)
+            .bind(channel_id.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .map_err(HistoryError::Database)?
+            .rows_affected();
+        let session_cleared = sqlx::query("DELETE FROM channel_sessions WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .execute(&mut *transaction)
+            .await
+            .map_err(HistoryError::Database)?
+            .rows_affected()
+            > 0;
+        transaction.commit().await.map_err(HistoryError::Database)?;
+        if let Some(cache) = &self.recent_cache {
+            cache.invalidate(channel_id);
+        }
+
+        Ok(ChannelReset {
+            archive_path,
+            messages_cleared,
+            summaries_cleared,
+            session_cleared,
+        })
+    }
+
+    /// Export a channel's history as OpenAI-style chat JSONL, one
+    /// `{"messages": [{"role", "content"}, ...]}` object per line.
+    ///
+    /// Each compaction starts a new conversation; conversations after the
+    /// first open with the preceding summary as a `system` message. Only
+    /// non-synthetic user and assistant messages with content are exported.
+    pub async fn export_jsonl(&self, channel_id: &ChannelId) -> crate::error::Result<String> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, channel_id, role, sender_name, sender_id, content, metadata, token_count, is_synthetic, is_hidden, created_at \
+             FROM conversation_messages \
+             WHERE channel_id = ? AND is_synthetic = 0 AND cleared_at IS NULL \
+             ORDER BY created_at ASC, rowid ASC"
+        )
+        .bind(channel_id.as_ref())
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+        let summaries = self.load_compaction_summaries(channel_id).await?;
+
+        let messages: Vec<ConversationMessage> =
+            rows.into_iter().map(|row| self.open_message(row_to_message(row))).collect();
+        // Where each summary's covered range ends in `messages`, if recorded.
+        let covered_to: Vec<Option<usize>> = summaries
+            .iter()
+            .map(|summary| {
+                let last = summary.covered_to.as_deref()?;
+                messages.iter().position(|message| message.id == last)
+            })
+            .collect();
+
+        // Conversation `i` holds messages after summary `i - 1` and up to
+        // summary `i`, the same boundary `turns_since_last_compaction` uses.
+        let mut conversations: Vec<Vec<ExportMessage>> = vec![Vec::new(); summaries.len() + 1];
+        for (position, message) in messages.into_iter().enumerate() {
+            if !matches!(message.role.as_str(), "user" | "assistant") || message.content.trim().is_empty() {
+                continue;
+            }
+            let index = summaries
+                .iter()
+                .zip(&covered_to)
+                .take_while(|(summary, covered_to)| match covered_to {
+                    Some(last) => *last < position,
+                    None => summary.created_at < message.created_at,
+                })
+                .count();
+            conversations[index].push(ExportMessage { role: message.role, content: message.content });
+        }
+
+        let mut jsonl = String::new();
+        for (index, messages) in conversations.into_iter().enumerate() {
+            if messages.is_empty() {
+                continue;
+            }
+            let context = index.checked_sub(1).map(|previous| ExportMessage {
+                role: "system".into(),
+                content: format!("Summary of the earlier conversation:\n{}", summaries[previous].summary),
+            });
+            let conversation = ExportConversation { messages: context.into_iter().chain(messages).collect() };
+            jsonl.push_str(&serde_json::to_string(&conversation).map_err(HistoryError::Serialization)?);
+            jsonl.push('\n');
+        }
+
+        Ok(jsonl)
+    }
+
+    /// Record a tool call's latest state. Fire-and-forget.
+    ///
+    /// OpenCode reports each call several times as it progresses, so rows are
+    /// upserted on `(session_id, call_id)`. `completed_at` is set once the call
+    /// completes or errors, and the row is final from then on: writes run
+    /// concurrently, so a `running` update can land after the result and must
+    /// not overwrite it. Input and output are redacted and sealed like
+    /// message content.
+    pub fn log_tool_invocation(
+        &self,
+        channel_id: &ChannelId,
+        session_id: &str,
+        call_id: &str,
+        tool: &str,
+        state: &ToolState,
+    ) {
+        let logger = self.clone();
+        let channel_id = channel_id.clone();
+        let session_id = session_id.to_string();
+        let call_id = call_id.to_string();
+        let tool = tool.to_string();
+        let state = state.clone();
+
+        self.spawn_write(async move {
+            if let Err(error) = logger
+                .upsert_tool_invocation(&channel_id, &session_id, &call_id, &tool, &state)
+                .await
+            {
+                tracing::warn!(%error, %call_id, "failed to persist tool invocation");
+            }
+        });
+    }
+
+    async fn upsert_tool_invocation(
+        &self,
+        channel_id: &ChannelId,
+        session_id: &str,
+        call_id: &str,
+        tool: &str,
+        state: &ToolState,
+    ) -> std::result::Result<(), sqlx::Error> {
+        let input = state
+            .input()
+            .map(|input| self.seal(self.redact(channel_id, &input.to_string())));
+        let output = state
+            .output()
+            .map(|output| self.seal(self.redact(channel_id, output)));
+        let finished = state.is_completed() || state.is_error();
+
+        with_retry(|| sqlx::query(
+            "INSERT INTO tool_invocations \
+             (id, session_id, channel_id, call_id, tool, status, input, output, completed_at) \
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, CASE WHEN ?9 THEN CURRENT_TIMESTAMP END) \
+             ON CONFLICT (session_id, call_id) DO UPDATE SET \
+                 status = excluded.status, \
+                 input = COALESCE(excluded.input, tool_invocations.input), \
+                 output = COALESCE(excluded.output, tool_invocations.output), \
+                 completed_at = COALESCE(tool_invocations.completed_at, excluded.completed_at) \
+             WHERE tool_invocations.status NOT IN ('completed', 'error')"
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(session_id)
+        .bind(channel_id.as_ref())
+        .bind(call_id)
+        .bind(tool)
+        .bind(state.status_str())
+        .bind(&input)
+        .bind(&output)
+        .bind(finished)
+        .execute(&self.pool))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Full output of one tool call, for fetching what a chat preview left out.
+    pub async fn load_tool_output(
+        &self,
+        session_id: &str,
+        call_id: &str,
+    ) -> crate::error::Result<Option<String>> {
+        let output: Option<String> = with_retry(|| sqlx::query_scalar::<_, Option<String>>(
+            "SELECT output FROM tool_invocations WHERE session_id = ? AND call_id = ?"
+        )
+        .bind(session_id)
+        .bind(call_id)
+        .fetch_optional(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?
+        .flatten();
+
+        Ok(output.map(|output| open_value(self.cipher.as_deref(), output)))
+    }
+
+    /// Load the most recent tool calls for a channel, newest first.
+    pub async fn load_tool_invocations(
+        &self,
+        channel_id: &ChannelId,
+        limit: i64,
+    ) -> crate::error::Result<Vec<ToolInvocation>> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT id, session_id, channel_id, call_id, tool, status, input, output, started_at, completed_at \
+             FROM tool_invocations \
+             WHERE channel_id = ? \
+             ORDER BY started_at DESC, rowid DESC \
+             LIMIT ?"
+        )
+        .bind(channel_id.as_ref())
+        .bind(limit)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(rows.iter().map(|row| self.row_to_tool_invocation(row)).collect())
+    }
+
+    /// Load one of a channel's tool calls by its call ID. The most recent
+    /// wins if a call ID was reused across sessions.
+    pub async fn find_tool_invocation(
+        &self,
+        channel_id: &ChannelId,
+        call_id: &str,
+    ) -> crate::error::Result<Option<ToolInvocation>> {
+        let row = with_retry(|| sqlx::query(
+            "SELECT id, session_id, channel_id, call_id, tool, status, input, output, started_at, completed_at \
+             FROM tool_invocations \
+             WHERE channel_id = ? AND call_id = ? \
+             ORDER BY started_at DESC, rowid DESC \
+             LIMIT 1"
+        )
+        .bind(channel_id.as_ref())
+        .bind(call_id)
+        .fetch_optional(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(row.map(|row| self.row_to_tool_invocation(&row)))
+    }
+
+    fn row_to_tool_invocation(&self, row: &sqlx::sqlite::SqliteRow) -> ToolInvocation {
+        let input: Option<String> = row.try_get("input").ok().flatten();
+        let output: Option<String> = row.try_get("output").ok().flatten();
+        ToolInvocation {
+            id: row.try_get("id").unwrap_or_default(),
+            session_id: row.try_get("session_id").unwrap_or_default(),
+            channel_id: row.try_get("channel_id").unwrap_or_default(),
+            call_id: row.try_get("call_id").unwrap_or_default(),
+            tool: row.try_get("tool").unwrap_or_default(),
+            status: row.try_get("status").unwrap_or_default(),
+            input: input
+                .map(|input| open_value(self.cipher.as_deref(), input))
+                .and_then(|input| serde_json::from_str(&input).ok()),
+            output: output.map(|output| open_value(self.cipher.as_deref(), output)),
+            started_at: row
+                .try_get("started_at")
+                .unwrap_or_else(|_| chrono::Utc::now()),
+            completed_at: row.try_get("completed_at").ok().flatten(),
+        }
+    }
+
+    /// Record a message part's latest state. Fire-and-forget.
+    ///
+    /// Parts are upserted on `(message_id, part_id)` as they stream in.
+    /// `ordinal` is the part's position in its message, and `revision` orders
+    /// updates to it: a write carrying an older revision than the stored row
+    /// is dropped, so updates racing to the database can't regress a part.
+    /// Content is redacted and sealed like message content. Parts without a
+    /// message ID, and `Part::Other`, aren't recorded.
+    pub fn log_message_part(
+        &self,
+        channel_id: &ChannelId,
+        session_id: &str,
+        ordinal: i64,
+        revision: i64,
+        part: &Part,
+    ) {
+        let logger = self.clone();
+        let channel_id = channel_id.clone();
+        let session_id = session_id.to_string();
+        let part = part.clone();
+
+        self.spawn_write(async move {
+            if let Err(error) = logger
+                .upsert_message_part(&channel_id, &session_id, ordinal, revision, &part)
+                .await
+            {
+                tracing::warn!(%error, part_id = ?part.id(), "failed to persist message part");
+            }
+        });
+    }
+
+    async fn upsert_message_part(
+        &self,
+        channel_id: &ChannelId,
+        session_id: &str,
+        ordinal: i64,
+        revision: i64,
+        part: &Part,
+    ) -> std::result::Result<(), sqlx::Error> {
+        let (Some(part_id), Some(message_id)) = (part.id(), part.message_id()) else {
+            return Ok(());
+        };
+        let Some((part_type, content, started_ms, ended_ms)) = part_record(part) else {
+            return Ok(());
+        };
+        let content = self.seal(self.redact(channel_id, &content.to_string()));
+
+        with_retry(|| sqlx::query(
+            "INSERT INTO message_parts \
+             (message_id, part_id, session_id, channel_id, ordinal, part_type, content, started_ms, ended_ms, revision) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT (message_id, part_id) DO UPDATE SET \
+                 content = excluded.content, \
+                 started_ms = COALESCE(excluded.started_ms, message_parts.started_ms), \
+                 ended_ms = COALESCE(excluded.ended_ms, message_parts.ended_ms), \
+                 revision = excluded.revision, \
+                 updated_at = CURRENT_TIMESTAMP \
+             WHERE excluded.revision > message_parts.revision"
+        )
+        .bind(message_id)
+        .bind(part_id)
+        .bind(session_id)
+        .bind(channel_id.as_ref())
+        .bind(ordinal)
+        .bind(part_type)
+        .bind(&content)
+        .bind(started_ms)
+        .bind(ended_ms)
+        .bind(revision)
+        .execute(&self.pool))
+        .await?;
+
+        Ok(())
+    }
+
+    /// Every recorded part of an OpenCode message, in order.
+    pub async fn load_message_parts(&self, message_id: &str) -> crate::error::Result<Vec<MessagePart>> {
+        let rows = with_retry(|| sqlx::query(
+            "SELECT message_id, part_id, session_id, channel_id, ordinal, part_type, content, \
+                    started_ms, ended_ms, updated_at \
+             FROM message_parts \
+             WHERE message_id = ? \
+             ORDER BY ordinal, rowid"
+        )
+        .bind(message_id)
+        .fetch_all(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(rows
+            .iter()
+            .map(|row| {
+                let content: String = row.try_get("content").unwrap_or_default();
+                MessagePart {
+                    message_id: row.try_get("message_id").unwrap_or_default(),
+                    part_id: row.try_get("part_id").unwrap_or_default(),
+                    session_id: row.try_get("session_id").unwrap_or_default(),
+                    channel_id: row.try_get("channel_id").unwrap_or_default(),
+                    ordinal: row.try_get("ordinal").unwrap_or_default(),
+                    part_type: row.try_get("part_type").unwrap_or_default(),
+                    content: serde_json::from_str(&open_value(self.cipher.as_deref(), content))
+                        .unwrap_or(serde_json::Value::Null),
+                    started_ms: row.try_get("started_ms").ok().flatten(),
+                    ended_ms: row.try_get("ended_ms").ok().flatten(),
+                    updated_at: row
+                        .try_get("updated_at")
+                        .unwrap_or_else(|_| chrono::Utc::now()),
+                }
+            })
+            .collect())
+    }
+
+    /// Count messages logged since the channel's latest compaction summary.
+    ///
+    /// Counts every message in the channel if it has never been compacted.
+    /// Synthetic messages don't count, nor do hidden ones unless the logger
+    /// includes them. This is the trigger signal for automatic compaction.
+    pub async fn turns_since_last_compaction(
+        &self,
+        channel_id: &ChannelId,
+    ) -> crate::error::Result<usize> {
+        let query = format!(
+            "SELECT COUNT(*) FROM conversation_messages \
+             WHERE channel_id = ?1 AND is_synthetic = 0 AND (?2 OR is_hidden = 0) AND cleared_at IS NULL \
+             AND {AFTER_LATEST_SUMMARY}"
+        );
+        let count: i64 = with_retry(|| sqlx::query_scalar(&query)
+        .bind(channel_id.as_ref())
+        .bind(self.include_hidden)
+        .fetch_one(&self.pool))
+        .await
+        .map_err(HistoryError::Database)?;
+
+        Ok(count as usize)
+    }
+}
+
+/// A part's type, JSON body, and start and end times, as `message_parts`
+/// stores them. `None` for parts we don't model.
+fn part_record(part: &Part) -> Option<(&'static str, serde_json::Value, Option<f64>, Option<f64>)> {
+    let record = match part {
+        Part::Text { text, time, synthetic, .. } => (
+            "text",
+            serde_json::json!({ "text": text, "synthetic": synthetic }),
+            time.as_ref().and_then(|time| time.start),
+            time.as_ref().and_then(|time| time.end),
+        ),
+        Part::Tool { call_id, tool, state, .. } => {
+            let time = state.as_ref().and_then(ToolState::time);
+            (
+                "tool",
+                serde_json::json!({
+                    "callID": call_id,
+                    "tool": tool,
+                    "status": state.as_ref().map(ToolState::status_str),
+                    "input": state.as_ref().and_then(ToolState::input),
+                    "title": state.as_ref().and_then(ToolState::title),
+                    "output": state.as_ref().and_then(ToolState::output),
+                }),
+                time.and_then(|time| time.start),
+                time.and_then(|time| time.end),
+            )
+        }
+        Part::StepStart { .. } => ("step-start", serde_json::json!({}), None, None),
+        Part::StepFinish { reason, tokens, .. } => (
+            "step-finish",
+            serde_json::json!({ "reason": reason, "tokens": tokens }),
+            None,
+            None,
+        ),
+        Part::Other => return None,
+    };
+    Some(record)
+}
+
+/// Decrypt a stored value if a cipher is configured. Unreadable values are
+/// replaced with a marker rather than failing the whole load.
+fn open_value(cipher: Option<&ContentCipher>, value: String) -> String {
+    let Some(cipher) = cipher else {
+        return value;
+    };
+    cipher.open(&value).unwrap_or_else(|error| {
+        tracing::warn!(%error, "failed to decrypt stored conversation content");
+        "[unreadable encrypted content]".to_string()
+    })
+}
 
-        let mut messages: Vec<ConversationMessage> = rows
-            .into_iter()
-            .map(|row| ConversationMessage {
-                id: row.try_get("id").unwrap_or_default(),
-                channel_id: row.try_get("channel_id").unwrap_or_default(),
-                role: row.try_get("role").unwrap_or_default(),
-                sender_name: row.try_get("sender_name").ok(),
-                sender_id: row.try_get("sender_id").ok(),
-                content: row.try_get("content").unwrap_or_default(),
-                metadata: row.try_get("metadata").ok(),
-                created_at: row.try_get("created_at").unwrap_or_else(|_| chrono::Utc::now()),
+/// `load_where_metadata`'s filter, for metadata SQLite can't read. Mirrors
+/// `CAST(json_extract(..) AS TEXT)`.
+fn metadata_matches(metadata: &str, key: &str, value: &str) -> bool {
+    let Ok(serde_json::Value::Object(metadata)) = serde_json::from_str::<serde_json::Value>(metadata) else {
+        return false;
+    };
+    match metadata.get(key) {
+        Some(serde_json::Value::String(text)) => text == value,
+        Some(serde_json::Value::Number(number)) => number.to_string() == value,
+        Some(serde_json::Value::Bool(flag)) => value == if *flag { "1" } else { "0" },
+        _ => false,
+    }
+}
+
+/// Merge runs of consecutive same-role messages into one turn each, so
+/// several user messages logged back to back reach the model as a single
+/// user turn rather than as turns with no reply between them.
+///
+/// A merged turn keeps the first message's id and timestamp and joins the
+/// contents with newlines. If the run has more than one sender, each line is
+/// attributed (`name: content`) and the turn itself has no sender. Token
+/// counts are summed when every message has one, and metadata, which can't
+/// be combined, is dropped. The role is kept, so a merged turn still maps to
+/// a single OpenCode message of that role.
+pub fn merge_consecutive_turns(turns: Vec<ConversationMessage>) -> Vec<ConversationMessage> {
+    let mut merged: Vec<Vec<ConversationMessage>> = Vec::new();
+    for turn in turns {
+        match merged.last_mut() {
+            Some(run) if run[0].role == turn.role => run.push(turn),
+            _ => merged.push(vec![turn]),
+        }
+    }
+    merged.into_iter().map(merge_run).collect()
+}
+
+fn merge_run(mut run: Vec<ConversationMessage>) -> ConversationMessage {
+    if run.len() == 1 {
+        return run.remove(0);
+    }
+    let single_sender = run
+        .iter()
+        .all(|turn| turn.sender_id == run[0].sender_id && turn.sender_name == run[0].sender_name);
+    let content = if single_sender {
+        run.iter().map(|turn| turn.content.as_str()).collect::<Vec<_>>().join("\n")
+    } else {
+        run.iter()
+            .map(|turn| {
+                let name = turn
+                    .sender_name
+                    .as_deref()
+                    .filter(|name| !name.is_empty())
+                    .or(turn.sender_id.as_deref())
+                    .unwrap_or(&turn.role);
+                format!("{name}: {}", turn.content)
             })
-            .collect();
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    let token_count = run.iter().map(|turn| turn.token_count).sum::<Option<i64>>();
+    let is_synthetic = run.iter().any(|turn| turn.is_synthetic);
+    let is_hidden = run.iter().any(|turn| turn.is_hidden);
 
-        messages.reverse();
-        Ok(messages)
+    let first = run.swap_remove(0);
+    ConversationMessage {
+        sender_name: if single_sender { first.sender_name } else { None },
+        sender_id: if single_sender { first.sender_id } else { None },
+        content,
+        metadata: None,
+        token_count,
+        is_synthetic,
+        is_hidden,
+        ..first
+    }
+}
+
+fn row_to_message(row: sqlx::sqlite::SqliteRow) -> ConversationMessage {
+    ConversationMessage {
+        id: row.try_get("id").unwrap_or_default(),
+        channel_id: row.try_get("channel_id").unwrap_or_default(),
+        role: row.try_get("role").unwrap_or_default(),
+        sender_name: row.try_get("sender_name").ok(),
+        sender_id: row.try_get("sender_id").ok(),
+        content: row.try_get("content").unwrap_or_default(),
+        metadata: row.try_get("metadata").ok(),
+        token_count: row.try_get("token_count").ok().flatten(),
+        is_synthetic: row.try_get("is_synthetic").unwrap_or(false),
+        is_hidden: row.try_get("is_hidden").unwrap_or(false),
+        created_at: row.try_get("created_at").unwrap_or_else(|_| chrono::Utc::now()),
+    }
+}
+
+/// SQL condition matching `conversation_messages` rows after the latest
+/// compaction summary of channel `?1`. A summary with a covered range ends
+/// right after its `covered_to` message; one without ends at its
+/// `created_at`. Matches every row if the channel has no summary.
+const AFTER_LATEST_SUMMARY: &str = "NOT EXISTS ( \
+    SELECT 1 FROM ( \
+        SELECT summary.created_at AS summary_at, covered.created_at AS covered_at, covered.rowid AS covered_rowid \
+        FROM compaction_summaries summary \
+        LEFT JOIN conversation_messages covered ON covered.id = summary.covered_to \
+        WHERE summary.channel_id = ?1 AND summary.cleared_at IS NULL \
+        ORDER BY summary.created_at DESC, summary.rowid DESC \
+        LIMIT 1 \
+    ) latest \
+    WHERE CASE WHEN latest.covered_at IS NULL \
+        THEN conversation_messages.created_at <= latest.summary_at \
+        ELSE conversation_messages.created_at < latest.covered_at \
+            OR (conversation_messages.created_at = latest.covered_at \
+                AND conversation_messages.rowid <= latest.covered_rowid) \
+    END \
+)";
+
+/// Format a timestamp the way SQLite's `CURRENT_TIMESTAMP` stores it, so
+/// string comparisons against `created_at` columns order correctly.
+/// Add a logged message to the recent cache once its write landed. A failed
+/// write drops the channel instead, so the cache never holds a message the
+/// database doesn't.
+fn update_recent_cache(cached: Option<(Arc<RecentHistoryCache>, ConversationMessage)>, written: bool) {
+    if let Some((cache, message)) = cached {
+        if written {
+            cache.push(message);
+        } else {
+            cache.invalidate(&message.channel_id);
+        }
     }
+}
 
+fn sqlite_timestamp(time: chrono::DateTime<chrono::Utc>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+fn row_to_compaction_summary(row: sqlx::sqlite::SqliteRow) -> CompactionSummary {
+    CompactionSummary {
+        id: row.try_get("id").unwrap_or_default(),
+        channel_id: row.try_get("channel_id").unwrap_or_default(),
+        summary: row.try_get("summary").unwrap_or_default(),
+        turns_covered: row.try_get("turns_covered").unwrap_or(0),
+        covered_from: row.try_get("covered_from").ok().flatten(),
+        covered_to: row.try_get("covered_to").ok().flatten(),
+        created_at: row.try_get("created_at").unwrap_or_else(|_| chrono::Utc::now()),
+    }
 }
 
 /// A unified timeline item combining messages, branch runs, and worker runs.
@@ -205,11 +2009,19 @@ pub enum TimelineItem {
 #[derive(Debug, Clone)]
 pub struct ProcessRunLogger {
     pool: SqlitePool,
+    /// Decrypts message content in the timeline, matching `ConversationLogger`.
+    cipher: Option<Arc<ContentCipher>>,
 }
 
 impl ProcessRunLogger {
     pub fn new(pool: SqlitePool) -> Self {
-        Self { pool }
+        Self { pool, cipher: None }
+    }
+
+    /// Decrypt message content encrypted by a `ConversationLogger` with this cipher.
+    pub fn with_cipher(mut self, cipher: Option<Arc<ContentCipher>>) -> Self {
+        self.cipher = cipher;
+        self
     }
 
     /// Record a branch starting. Fire-and-forget.
@@ -220,13 +2032,13 @@ impl ProcessRunLogger {
         let description = description.to_string();
 
         tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
+            if let Err(error) = with_retry(|| sqlx::query(
                 "INSERT INTO branch_runs (id, channel_id, description) VALUES (?, ?, ?)"
             )
             .bind(&id)
             .bind(&channel_id)
             .bind(&description)
-            .execute(&pool)
+            .execute(&pool))
             .await
             {
                 tracing::warn!(%error, branch_id = %id, "failed to persist branch start");
@@ -241,12 +2053,12 @@ impl ProcessRunLogger {
         let conclusion = conclusion.to_string();
 
         tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
+            if let Err(error) = with_retry(|| sqlx::query(
                 "UPDATE branch_runs SET conclusion = ?, completed_at = CURRENT_TIMESTAMP WHERE id = ?"
             )
             .bind(&conclusion)
             .bind(&id)
-            .execute(&pool)
+            .execute(&pool))
             .await
             {
                 tracing::warn!(%error, branch_id = %id, "failed to persist branch completion");
@@ -262,13 +2074,13 @@ impl ProcessRunLogger {
         let task = task.to_string();
 
         tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
+            if let Err(error) = with_retry(|| sqlx::query(
                 "INSERT INTO worker_runs (id, channel_id, task) VALUES (?, ?, ?)"
             )
             .bind(&id)
             .bind(&channel_id)
             .bind(&task)
-            .execute(&pool)
+            .execute(&pool))
             .await
             {
                 tracing::warn!(%error, worker_id = %id, "failed to persist worker start");
@@ -283,12 +2095,12 @@ impl ProcessRunLogger {
         let status = status.to_string();
 
         tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
+            if let Err(error) = with_retry(|| sqlx::query(
                 "UPDATE worker_runs SET status = ? WHERE id = ?"
             )
             .bind(&status)
             .bind(&id)
-            .execute(&pool)
+            .execute(&pool))
             .await
             {
                 tracing::warn!(%error, worker_id = %id, "failed to persist worker status");
@@ -303,12 +2115,12 @@ impl ProcessRunLogger {
         let result = result.to_string();
 
         tokio::spawn(async move {
-            if let Err(error) = sqlx::query(
+            if let Err(error) = with_retry(|| sqlx::query(
                 "UPDATE worker_runs SET result = ?, status = 'done', completed_at = CURRENT_TIMESTAMP WHERE id = ?"
             )
             .bind(&result)
             .bind(&id)
-            .execute(&pool)
+            .execute(&pool))
             .await
             {
                 tracing::warn!(%error, worker_id = %id, "failed to persist worker completion");
@@ -334,7 +2146,7 @@ impl ProcessRunLogger {
                 SELECT 'message' AS item_type, id, role, sender_name, sender_id, content, \
                        NULL AS description, NULL AS conclusion, NULL AS task, NULL AS result, NULL AS status, \
                        created_at AS timestamp, NULL AS completed_at \
-                FROM conversation_messages WHERE channel_id = ?1 \
+                FROM conversation_messages WHERE channel_id = ?1 AND is_synthetic = 0 AND cleared_at IS NULL \
                 UNION ALL \
                 SELECT 'branch_run' AS item_type, id, NULL, NULL, NULL, NULL, \
                        description, conclusion, NULL, NULL, NULL, \
@@ -348,18 +2160,19 @@ impl ProcessRunLogger {
             ) WHERE 1=1 {before_clause} ORDER BY timestamp DESC LIMIT ?2"
         );
 
-        let mut query = sqlx::query(&query_str)
-            .bind(channel_id)
-            .bind(limit);
+        let rows = with_retry(|| {
+            let mut query = sqlx::query(&query_str)
+                .bind(channel_id)
+                .bind(limit);
 
-        if let Some(before_ts) = before {
-            query = query.bind(before_ts);
-        }
+            if let Some(before_ts) = before {
+                query = query.bind(before_ts);
+            }
 
-        let rows = query
-            .fetch_all(&self.pool)
-            .await
-            .map_err(|e| anyhow::anyhow!(e))?;
+            query.fetch_all(&self.pool)
+        })
+        .await
+        .map_err(HistoryError::Database)?;
 
         let mut items: Vec<TimelineItem> = rows
             .into_iter()
@@ -371,7 +2184,10 @@ impl ProcessRunLogger {
                         role: row.try_get("role").unwrap_or_default(),
                         sender_name: row.try_get("sender_name").ok(),
                         sender_id: row.try_get("sender_id").ok(),
-                        content: row.try_get("content").unwrap_or_default(),
+                        content: open_value(
+                            self.cipher.as_deref(),
+                            row.try_get("content").unwrap_or_default(),
+                        ),
                         created_at: row.try_get::<chrono::DateTime<chrono::Utc>, _>("timestamp")
                             .map(|t| t.to_rfc3339())
                             .unwrap_or_default(),
@@ -409,3 +2225,977 @@ impl ProcessRunLogger {
         Ok(items)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{connect_in_memory, insert_message_at};
+    use std::sync::Arc;
+
+    async fn seed_summaries(logger: &ConversationLogger, channel_id: &ChannelId, count: usize) {
+        for index in 0..count {
+            logger
+                .save_compaction_summary(channel_id, &format!("summary {index}"), 10)
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cap_summaries_prunes_oldest() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        seed_summaries(&logger, &channel_id, 5).await;
+
+        let affected = logger
+            .cap_summaries_per_channel(&channel_id, 2, SummaryOverflow::Prune)
+            .await
+            .unwrap();
+        assert_eq!(affected, 3);
+
+        let remaining = logger.load_compaction_summaries(&channel_id).await.unwrap();
+        let texts: Vec<_> = remaining.iter().map(|s| s.summary.as_str()).collect();
+        assert_eq!(texts, ["summary 3", "summary 4"]);
+    }
+
+    #[tokio::test]
+    async fn test_cap_summaries_merges_overflow() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+        seed_summaries(&logger, &channel_id, 4).await;
+        seed_summaries(&logger, &other_channel, 4).await;
+
+        let affected = logger
+            .cap_summaries_per_channel(&channel_id, 2, SummaryOverflow::Merge)
+            .await
+            .unwrap();
+        // Two deletes plus one update of the surviving summary.
+        assert_eq!(affected, 3);
+
+        let remaining = logger.load_compaction_summaries(&channel_id).await.unwrap();
+        assert_eq!(remaining.len(), 2);
+        assert_eq!(remaining[0].summary, "summary 0\n\nsummary 1\n\nsummary 2");
+        assert_eq!(remaining[0].turns_covered, 30);
+        assert_eq!(remaining[1].summary, "summary 3");
+
+        // Other channels are untouched.
+        let untouched = logger.load_compaction_summaries(&other_channel).await.unwrap();
+        assert_eq!(untouched.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_cap_summaries_under_limit_is_noop() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        seed_summaries(&logger, &channel_id, 2).await;
+
+        let affected = logger
+            .cap_summaries_per_channel(&channel_id, 5, SummaryOverflow::Prune)
+            .await
+            .unwrap();
+        assert_eq!(affected, 0);
+        assert_eq!(logger.load_compaction_summaries(&channel_id).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_last_activity() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let busy: ChannelId = "discord:1:2".parse().unwrap();
+        let quiet: ChannelId = "discord:1:3".parse().unwrap();
+        let reset: ChannelId = "discord:1:4".parse().unwrap();
+        assert_eq!(logger.last_activity(&busy).await.unwrap(), None);
+
+        insert_message_at(&pool, &busy, "2026-01-01 10:00:00").await;
+        insert_message_at(&pool, &busy, "2026-01-01 12:00:00").await;
+        insert_message_at(&pool, &quiet, "2026-01-01 11:00:00").await;
+        insert_message_at(&pool, &reset, "2026-01-01 13:00:00").await;
+        sqlx::query("UPDATE conversation_messages SET cleared_at = CURRENT_TIMESTAMP WHERE channel_id = ?")
+            .bind(reset.as_ref())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let noon: chrono::DateTime<chrono::Utc> = "2026-01-01T12:00:00Z".parse().unwrap();
+        assert_eq!(logger.last_activity(&busy).await.unwrap(), Some(noon));
+        // Cleared messages aren't activity.
+        assert_eq!(logger.last_activity(&reset).await.unwrap(), None);
+        assert!(logger.latest_message_time(&reset).await.unwrap().is_some());
+
+        let channels = logger.channels_ordered_by_activity(10).await.unwrap();
+        let ids: Vec<&str> = channels.iter().map(|(channel_id, _)| channel_id.as_str()).collect();
+        assert_eq!(ids, ["discord:1:2", "discord:1:3"]);
+        assert_eq!(channels[0].1, noon);
+        assert_eq!(logger.channels_ordered_by_activity(1).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_turns_since_last_compaction() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+
+        for created_at in ["2026-01-01 10:00:00", "2026-01-01 10:01:00", "2026-01-01 10:02:00"] {
+            insert_message_at(&pool, &channel_id, created_at).await;
+        }
+        insert_message_at(&pool, &other_channel, "2026-01-01 10:05:00").await;
+
+        // Never compacted: every message counts.
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 3);
+
+        sqlx::query(
+            "INSERT INTO compaction_summaries (id, channel_id, summary, created_at) \
+             VALUES ('s1', ?, 'summary', '2026-01-01 10:01:30')",
+        )
+        .bind(channel_id.as_ref())
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 1);
+        assert_eq!(logger.turns_since_last_compaction(&other_channel).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_load_between() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+
+        for created_at in [
+            "2026-01-01 09:59:59",
+            "2026-01-01 10:00:00",
+            "2026-01-01 10:30:00",
+            "2026-01-01 11:00:00",
+        ] {
+            insert_message_at(&pool, &channel_id, created_at).await;
+        }
+        insert_message_at(&pool, &other_channel, "2026-01-01 10:15:00").await;
+
+        let at = |time: &str| {
+            chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+                .unwrap()
+                .and_utc()
+        };
+        let start = at("2026-01-01 10:00:00");
+        let end = at("2026-01-01 11:00:00");
+
+        let messages = logger.load_between(&channel_id, start, end, None).await.unwrap();
+        let times: Vec<String> = messages
+            .iter()
+            .map(|message| sqlite_timestamp(message.created_at))
+            .collect();
+        assert_eq!(times, ["2026-01-01 10:00:00", "2026-01-01 10:30:00"]);
+
+        let limited = logger.load_between(&channel_id, start, end, Some(1)).await.unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].created_at, start);
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_history_round_trip() {
+        use crate::conversation::crypto::{self, ContentCipher};
+        use base64::Engine as _;
+
+        let pool = connect_in_memory().await;
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let key = base64::engine::general_purpose::STANDARD.encode([3u8; 32]);
+        let cipher = Arc::new(ContentCipher::from_base64_key(&key).unwrap());
+
+        // Plaintext written before a key was configured.
+        insert_message_at(&pool, &channel_id, "2026-01-01 10:00:00").await;
+        let plain = ConversationLogger::new(pool.clone());
+        plain.save_compaction_summary(&channel_id, "old summary", 5).await.unwrap();
+
+        let report = crypto::encrypt_plaintext_rows(&pool, &cipher).await.unwrap();
+        assert_eq!(report, crypto::EncryptionReport { messages: 1, summaries: 1 });
+        let again = crypto::encrypt_plaintext_rows(&pool, &cipher).await.unwrap();
+        assert_eq!(again, crypto::EncryptionReport::default());
+
+        let stored: String = sqlx::query_scalar("SELECT content FROM conversation_messages")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert!(crypto::is_sealed(&stored));
+
+        let logger = ConversationLogger::new(pool.clone()).with_cipher(Some(cipher));
+        logger.save_compaction_summary(&channel_id, "new summary", 5).await.unwrap();
+        logger
+            .cap_summaries_per_channel(&channel_id, 1, SummaryOverflow::Merge)
+            .await
+            .unwrap();
+
+        let messages = logger.load_recent(&channel_id, 10, true).await.unwrap();
+        assert_eq!(messages[0].content, "hi");
+        let summaries = logger.load_compaction_summaries(&channel_id).await.unwrap();
+        assert_eq!(summaries[0].summary, "old summary\n\nnew summary");
+    }
+
+    #[tokio::test]
+    async fn test_load_by_sender() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+
+        for (channel, sender_id, content, created_at) in [
+            (&channel_id, "u1", "first", "2026-01-01 10:00:00"),
+            (&other_channel, "u1", "second", "2026-01-01 10:01:00"),
+            (&channel_id, "u2", "someone else", "2026-01-01 10:02:00"),
+            (&channel_id, "u1", "third", "2026-01-01 10:03:00"),
+        ] {
+            sqlx::query(
+                "INSERT INTO conversation_messages \
+                 (id, channel_id, role, sender_name, sender_id, content, created_at) \
+                 VALUES (?, ?, 'user', 'Alice', ?, ?, ?)",
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(channel.as_ref())
+            .bind(sender_id)
+            .bind(content)
+            .bind(created_at)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let everywhere = logger.load_by_sender("u1", None, 10).await.unwrap();
+        let contents: Vec<&str> = everywhere.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["first", "second", "third"]);
+        assert_eq!(everywhere[0].sender_name.as_deref(), Some("Alice"));
+        assert_eq!(everywhere[0].sender_id.as_deref(), Some("u1"));
+
+        let in_channel = logger.load_by_sender("u1", Some(&channel_id), 10).await.unwrap();
+        let contents: Vec<&str> = in_channel.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["first", "third"]);
+
+        // The limit keeps the most recent messages.
+        let latest = logger.load_by_sender("u1", None, 2).await.unwrap();
+        let contents: Vec<&str> = latest.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn test_load_where_metadata() {
+        let pool = connect_in_memory().await;
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        for (content, metadata, created_at) in [
+            ("first", r#"{"source":"command","priority":2}"#, "2026-01-01 10:00:00"),
+            ("chat", r#"{"source":"chat"}"#, "2026-01-01 10:01:00"),
+            ("second", r#"{"source":"command","pinned":true}"#, "2026-01-01 10:02:00"),
+        ] {
+            sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, content, metadata, created_at) \
+                 VALUES (?, ?, 'user', ?, ?, ?)",
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(channel_id.as_ref())
+            .bind(content)
+            .bind(metadata)
+            .bind(created_at)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let rejected = sqlx::query(
+            "INSERT INTO conversation_messages (id, channel_id, role, content, metadata) \
+             VALUES ('bad', 'discord:1:2', 'user', 'x', 'not json')",
+        )
+        .execute(&pool)
+        .await;
+        assert!(rejected.is_err());
+
+        let logger = ConversationLogger::new(pool.clone());
+        let commands = logger.load_where_metadata(&channel_id, "source", "command", 10).await.unwrap();
+        let contents: Vec<&str> = commands.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["first", "second"]);
+        let latest = logger.load_where_metadata(&channel_id, "source", "command", 1).await.unwrap();
+        assert_eq!(latest[0].content, "second");
+        let priority = logger.load_where_metadata(&channel_id, "priority", "2", 10).await.unwrap();
+        assert_eq!(priority[0].content, "first");
+        let pinned = logger.load_where_metadata(&channel_id, "pinned", "1", 10).await.unwrap();
+        assert_eq!(pinned[0].content, "second");
+        assert!(logger.load_where_metadata(&channel_id, "source", "cron", 10).await.unwrap().is_empty());
+
+        // Encrypted metadata is filtered after decryption, with the same matching.
+        use base64::Engine as _;
+        let key = base64::engine::general_purpose::STANDARD.encode([7u8; 32]);
+        let cipher = Arc::new(ContentCipher::from_base64_key(&key).unwrap());
+        crate::conversation::crypto::encrypt_plaintext_rows(&pool, &cipher).await.unwrap();
+        let logger = logger.with_cipher(Some(cipher));
+        let commands = logger.load_where_metadata(&channel_id, "source", "command", 10).await.unwrap();
+        let contents: Vec<&str> = commands.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["first", "second"]);
+        let pinned = logger.load_where_metadata(&channel_id, "pinned", "1", 10).await.unwrap();
+        assert_eq!(pinned[0].content, "second");
+    }
+
+    #[tokio::test]
+    async fn test_tool_invocation_upserts_by_call() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let input = serde_json::json!({ "command": "cargo test" });
+
+        let running = ToolState::Running {
+            input: Some(input.clone()),
+            title: None,
+            metadata: None,
+        };
+        logger
+            .upsert_tool_invocation(&channel_id, "ses_1", "call_1", "bash", &running)
+            .await
+            .unwrap();
+        let completed = ToolState::Completed {
+            input: None,
+            output: Some("ok".into()),
+            title: None,
+            metadata: None,
+            time: None,
+        };
+        logger
+            .upsert_tool_invocation(&channel_id, "ses_1", "call_1", "bash", &completed)
+            .await
+            .unwrap();
+
+        let invocations = logger.load_tool_invocations(&channel_id, 10).await.unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].tool, "bash");
+        assert_eq!(invocations[0].status, "completed");
+        assert_eq!(invocations[0].input, Some(input));
+        assert_eq!(invocations[0].output.as_deref(), Some("ok"));
+        assert!(invocations[0].completed_at.is_some());
+
+        let output = logger.load_tool_output("ses_1", "call_1").await.unwrap();
+        assert_eq!(output.as_deref(), Some("ok"));
+        assert_eq!(logger.load_tool_output("ses_1", "call_2").await.unwrap(), None);
+
+        let found = logger.find_tool_invocation(&channel_id, "call_1").await.unwrap().unwrap();
+        assert_eq!(found.output.as_deref(), Some("ok"));
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+        assert!(logger.find_tool_invocation(&other_channel, "call_1").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_late_tool_update_keeps_the_result() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let error = ToolState::Error {
+            input: None,
+            error: Some("exit status 101".into()),
+            time: None,
+        };
+        let running = ToolState::Running {
+            input: Some(serde_json::json!({ "command": "cargo test" })),
+            title: None,
+            metadata: None,
+        };
+
+        // The result is written before an earlier update that was held up.
+        logger
+            .upsert_tool_invocation(&channel_id, "ses_1", "call_1", "bash", &error)
+            .await
+            .unwrap();
+        logger
+            .upsert_tool_invocation(&channel_id, "ses_1", "call_1", "bash", &running)
+            .await
+            .unwrap();
+
+        let invocations = logger.load_tool_invocations(&channel_id, 10).await.unwrap();
+        assert_eq!(invocations.len(), 1);
+        assert_eq!(invocations[0].status, "error");
+        assert_eq!(invocations[0].output.as_deref(), Some("exit status 101"));
+        assert!(invocations[0].completed_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_message_parts_keep_order_and_latest_state() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let part = |value: serde_json::Value| -> Part { serde_json::from_value(value).unwrap() };
+        let text = |text: &str| {
+            part(serde_json::json!({
+                "type": "text", "id": "prt_2", "sessionID": "ses_1", "messageID": "msg_1",
+                "text": text, "time": { "start": 1000.0 }
+            }))
+        };
+        let tool = part(serde_json::json!({
+            "type": "tool", "id": "prt_1", "sessionID": "ses_1", "messageID": "msg_1",
+            "callID": "call_1", "tool": "bash",
+            "state": { "status": "completed", "input": { "command": "cargo test" }, "output": "ok",
+                       "time": { "start": 500.0, "end": 900.0 } }
+        }));
+        let step = part(serde_json::json!({ "type": "step-start", "id": "prt_0", "sessionID": "ses_1", "messageID": "msg_1" }));
+
+        for (ordinal, revision, part) in [(0, 0, &step), (1, 0, &tool), (2, 0, &text("The tests")), (2, 2, &text("The tests pass."))] {
+            logger.upsert_message_part(&channel_id, "ses_1", ordinal, revision, part).await.unwrap();
+        }
+        // A stale update landing late doesn't regress the part.
+        logger.upsert_message_part(&channel_id, "ses_1", 2, 1, &text("The tests pass")).await.unwrap();
+        // Parts we don't model, or without a message, aren't recorded.
+        logger.upsert_message_part(&channel_id, "ses_1", 3, 0, &Part::Other).await.unwrap();
+
+        let parts = logger.load_message_parts("msg_1").await.unwrap();
+        let types: Vec<&str> = parts.iter().map(|part| part.part_type.as_str()).collect();
+        assert_eq!(types, ["step-start", "tool", "text"]);
+        assert_eq!(parts[1].content["status"], "completed");
+        assert_eq!(parts[1].content["output"], "ok");
+        assert_eq!(parts[1].content["input"]["command"], "cargo test");
+        assert_eq!((parts[1].started_ms, parts[1].ended_ms), (Some(500.0), Some(900.0)));
+        assert_eq!(parts[2].content["text"], "The tests pass.");
+        assert_eq!(parts[2].started_ms, Some(1000.0));
+        assert!(logger.load_message_parts("msg_2").await.unwrap().is_empty());
+    }
+
+    fn opencode_info(role: &str, id: &str, output_tokens: Option<u64>) -> MessageInfo {
+        MessageInfo {
+            id: id.into(),
+            role: role.into(),
+            session_id: Some("ses_1".into()),
+            time: None,
+            tokens: output_tokens.map(|output| crate::opencode::types::TokenUsage { output, ..Default::default() }),
+            provider_id: (role == "assistant").then(|| "anthropic".into()),
+            model_id: (role == "assistant").then(|| "claude-sonnet-4-5".into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_backfill_message_keeps_timestamp() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        assert_eq!(logger.latest_message_time(&channel_id).await.unwrap(), None);
+
+        let created_at = chrono::DateTime::from_timestamp(1_770_927_523, 0).unwrap();
+        logger
+            .backfill_message(&channel_id, &opencode_info("user", "msg_1", None), "fix the tests", false, created_at)
+            .await
+            .unwrap();
+        let replied_at = created_at + chrono::Duration::seconds(5);
+        logger
+            .backfill_message(&channel_id, &opencode_info("assistant", "msg_2", Some(42)), "done", false, replied_at)
+            .await
+            .unwrap();
+
+        assert_eq!(logger.latest_message_time(&channel_id).await.unwrap(), Some(replied_at));
+        // The same OpenCode message isn't backfilled twice.
+        let again = logger
+            .backfill_message(&channel_id, &opencode_info("user", "msg_1", None), "fix the tests", false, created_at)
+            .await
+            .unwrap();
+        assert!(!again);
+        let messages = logger.load_recent(&channel_id, 10, true).await.unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(messages[0].metadata.as_deref().unwrap().contains("msg_1"));
+        // No reported count: estimated with the tokenizer.
+        assert_eq!(messages[0].token_count, Some(4));
+        assert_eq!(messages[0].model(), None);
+        assert_eq!(messages[1].role, "assistant");
+        assert_eq!(messages[1].token_count, Some(42));
+        assert_eq!(messages[1].model().as_deref(), Some("anthropic/claude-sonnet-4-5"));
+    }
+
+    #[tokio::test]
+    async fn test_summary_through_keeps_later_messages_uncompacted() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        for created_at in ["2026-01-01 10:00:00", "2026-01-01 10:01:00"] {
+            insert_message_at(&pool, &channel_id, created_at).await;
+        }
+        let window = logger.load_since_last_compaction(&channel_id).await.unwrap();
+        assert_eq!(window.len(), 2);
+
+        // A message logged while the summary is being generated.
+        insert_message_at(&pool, &channel_id, "2026-01-01 10:02:00").await;
+        logger
+            .save_compaction_summary_through(&channel_id, "summary", &window)
+            .await
+            .unwrap();
+
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 1);
+        let remaining = logger.load_since_last_compaction(&channel_id).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(sqlite_timestamp(remaining[0].created_at), "2026-01-01 10:02:00");
+    }
+
+    #[tokio::test]
+    async fn test_assemble_context() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        assert!(logger.assemble_context(&channel_id, 10).await.unwrap().render().is_none());
+
+        for minute in 0..5 {
+            insert_message_at(&pool, &channel_id, &format!("2026-01-01 10:0{minute}:00")).await;
+        }
+        let window = logger.load_since_last_compaction(&channel_id).await.unwrap();
+        logger.save_compaction_summary_through(&channel_id, "first", &window[..1]).await.unwrap();
+        logger.save_compaction_summary_through(&channel_id, "second", &window[1..3]).await.unwrap();
+
+        let context = logger.assemble_context(&channel_id, 10).await.unwrap();
+        let summaries: Vec<_> = context.summaries.iter().map(|s| s.summary.as_str()).collect();
+        assert_eq!(summaries, ["first", "second"]);
+        let turn_ids: Vec<_> = context.turns.iter().map(|turn| turn.id.as_str()).collect();
+        assert_eq!(turn_ids, [window[3].id.as_str(), window[4].id.as_str()]);
+
+        let recent = logger.assemble_context(&channel_id, 1).await.unwrap();
+        assert_eq!(recent.turns.len(), 1);
+        assert_eq!(recent.turns[0].id, window[4].id);
+
+        let rendered = context.render().unwrap();
+        assert!(rendered.starts_with("[Summary of earlier conversation]\nfirst\n\nsecond"));
+        assert!(rendered.contains("[Recent conversation]\nuser: hi\nuser: hi\n"));
+        assert_eq!(context.parts().len(), 2);
+
+        // Merging folds the two user messages into one turn; the rows stay.
+        let merged = logger.clone().with_merged_turns(true).assemble_context(&channel_id, 10).await.unwrap();
+        assert_eq!(merged.turns.len(), 1);
+        assert_eq!(merged.turns[0].id, window[3].id);
+        assert_eq!(merged.turns[0].content, "hi\nhi");
+        assert_eq!(logger.load_recent(&channel_id, 10, false).await.unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_merge_consecutive_turns() {
+        let turn = |role: &str, sender: Option<&str>, content: &str| ConversationMessage {
+            id: content.into(),
+            channel_id: "discord:1:2".into(),
+            role: role.into(),
+            sender_name: sender.map(Into::into),
+            sender_id: sender.map(Into::into),
+            content: content.into(),
+            metadata: Some("{}".into()),
+            token_count: Some(1),
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        };
+        let merged = merge_consecutive_turns(vec![
+            turn("user", Some("alice"), "hi"),
+            turn("user", Some("alice"), "are you there?"),
+            turn("assistant", None, "yes"),
+            turn("user", Some("alice"), "fix the build"),
+            turn("user", Some("bob"), "and the tests"),
+        ]);
+
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[0].id, "hi");
+        assert_eq!(merged[0].content, "hi\nare you there?");
+        assert_eq!(merged[0].sender_name.as_deref(), Some("alice"));
+        assert_eq!(merged[0].token_count, Some(2));
+        assert!(merged[0].metadata.is_none());
+        assert_eq!(merged[1].metadata.as_deref(), Some("{}"));
+        assert_eq!(merged[2].content, "alice: fix the build\nbob: and the tests");
+        assert_eq!(merged[2].sender_name, None);
+        assert_eq!(merged[2].role, "user");
+    }
+
+    #[tokio::test]
+    async fn test_hidden_messages_leave_context() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        for minute in 0..3 {
+            insert_message_at(&pool, &channel_id, &format!("2026-01-01 10:0{minute}:00")).await;
+        }
+        let messages = logger.load_recent(&channel_id, 10, false).await.unwrap();
+        assert!(logger.hide_message(&messages[1].id).await.unwrap());
+        assert!(!logger.hide_message("missing").await.unwrap());
+
+        let visible = logger.load_recent(&channel_id, 10, false).await.unwrap();
+        let ids: Vec<&str> = visible.iter().map(|m| m.id.as_str()).collect();
+        assert_eq!(ids, [messages[0].id.as_str(), messages[2].id.as_str()]);
+        assert_eq!(logger.assemble_context(&channel_id, 10).await.unwrap().turns.len(), 2);
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 2);
+
+        // The row is still there for audit, flagged.
+        let audit = logger.clone().with_hidden_messages(true);
+        let everything = audit.load_recent(&channel_id, 10, false).await.unwrap();
+        assert_eq!(everything.len(), 3);
+        assert!(everything[1].is_hidden);
+        assert!(!everything[0].is_hidden);
+
+        assert!(logger.unhide_message(&messages[1].id).await.unwrap());
+        assert_eq!(logger.load_recent(&channel_id, 10, false).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_supersede_replies_to_last_user_message() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        assert!(logger.last_user_message(&channel_id).await.unwrap().is_none());
+
+        for (role, content) in [("user", "first"), ("assistant", "one"), ("user", "second"), ("assistant", "two")] {
+            sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, content, created_at) \
+                 VALUES (?, ?, ?, ?, '2026-01-01 10:00:00')",
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(channel_id.as_ref())
+            .bind(role)
+            .bind(content)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+
+        let last = logger.last_user_message(&channel_id).await.unwrap().unwrap();
+        assert_eq!(last.content, "second");
+        assert_eq!(logger.supersede_replies(&channel_id, &last).await.unwrap(), 1);
+        assert_eq!(logger.supersede_replies(&channel_id, &last).await.unwrap(), 0);
+
+        let visible: Vec<String> = logger
+            .load_recent(&channel_id, 10, false)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|message| message.content)
+            .collect();
+        assert_eq!(visible, ["first", "one", "second"]);
+        let superseded: i64 = sqlx::query_scalar(
+            "SELECT COUNT(*) FROM conversation_messages WHERE superseded_at IS NOT NULL AND content = 'two'",
+        )
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert_eq!(superseded, 1);
+    }
+
+    #[tokio::test]
+    async fn test_load_recent_uses_cache() {
+        let pool = connect_in_memory().await;
+        let cache = Arc::new(RecentHistoryCache::new(8, 10));
+        let logger = ConversationLogger::new(pool.clone()).with_recent_cache(Some(cache.clone()));
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        for minute in 0..3 {
+            insert_message_at(&pool, &channel_id, &format!("2026-01-01 10:0{minute}:00")).await;
+        }
+        let messages = logger.load_recent(&channel_id, 10, false).await.unwrap();
+        assert_eq!(messages.len(), 3);
+
+        // A row written behind the logger's back isn't seen: the cache answered.
+        insert_message_at(&pool, &channel_id, "2026-01-01 10:05:00").await;
+        assert_eq!(logger.load_recent(&channel_id, 10, false).await.unwrap().len(), 3);
+        assert_eq!(logger.load_recent(&channel_id, 2, false).await.unwrap()[1].id, messages[2].id);
+
+        assert!(logger.hide_message(&messages[0].id).await.unwrap());
+        assert_eq!(logger.load_recent(&channel_id, 10, false).await.unwrap().len(), 2);
+
+        logger.log_bot_message(&channel_id, "hello");
+        let mut latest = Vec::new();
+        for _ in 0..50 {
+            latest = logger.load_recent(&channel_id, 10, false).await.unwrap();
+            if latest.len() == 3 {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        assert_eq!(latest.last().map(|message| message.content.as_str()), Some("hello"));
+
+        // A backfill drops the channel, so the next load sees every row.
+        logger
+            .backfill_message(&channel_id, &opencode_info("user", "msg_1", None), "earlier", false, chrono::Utc::now())
+            .await
+            .unwrap();
+        assert_eq!(logger.load_recent(&channel_id, 10, false).await.unwrap().len(), 5);
+
+        let stats = cache.stats();
+        assert_eq!(stats.misses, 2);
+        assert!(stats.hits >= 4);
+    }
+
+    #[tokio::test]
+    async fn test_summary_ranges_split_messages_within_a_second() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        for _ in 0..3 {
+            insert_message_at(&pool, &channel_id, "2026-01-01 10:00:00").await;
+        }
+        let window = logger.load_since_last_compaction(&channel_id).await.unwrap();
+        logger
+            .save_compaction_summary_through(&channel_id, "first", &window[..2])
+            .await
+            .unwrap();
+
+        let remaining = logger.load_since_last_compaction(&channel_id).await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].id, window[2].id);
+
+        // The next summary covers only what the first one didn't.
+        logger
+            .save_compaction_summary_through(&channel_id, "second", &remaining)
+            .await
+            .unwrap();
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 0);
+
+        let summaries = logger.load_compaction_summaries(&channel_id).await.unwrap();
+        assert_eq!(summaries[0].covered_from.as_deref(), Some(window[0].id.as_str()));
+        assert_eq!(summaries[0].covered_to.as_deref(), Some(window[1].id.as_str()));
+        assert_eq!(summaries[0].turns_covered, 2);
+        assert_eq!(summaries[1].covered_from.as_deref(), Some(window[2].id.as_str()));
+
+        let jsonl = logger.export_jsonl(&channel_id).await.unwrap();
+        let conversations: Vec<serde_json::Value> =
+            jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0]["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(conversations[1]["messages"][0]["content"], "Summary of the earlier conversation:\nfirst");
+    }
+
+    #[tokio::test]
+    async fn test_synthetic_messages_are_hidden() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let at = |second: i64| chrono::DateTime::from_timestamp(1_770_000_000 + second, 0).unwrap();
+
+        logger
+            .backfill_message(&channel_id, &opencode_info("user", "msg_1", None), "is CI green?", false, at(0))
+            .await
+            .unwrap();
+        logger
+            .backfill_message(&channel_id, &opencode_info("user", "msg_2", None), "[injected context]", true, at(1))
+            .await
+            .unwrap();
+        logger
+            .backfill_message(&channel_id, &opencode_info("assistant", "msg_3", None), "yes", false, at(2))
+            .await
+            .unwrap();
+
+        let visible = logger.load_recent(&channel_id, 10, false).await.unwrap();
+        let contents: Vec<_> = visible.iter().map(|message| message.content.as_str()).collect();
+        assert_eq!(contents, ["is CI green?", "yes"]);
+
+        let all = logger.load_recent(&channel_id, 10, true).await.unwrap();
+        assert_eq!(all.len(), 3);
+        assert!(all[1].is_synthetic);
+        assert!(!all[0].is_synthetic);
+
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 2);
+        assert_eq!(logger.load_channel_transcript("discord:1:2", 10).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_reply_survives_a_crash() {
+        let pool = connect_in_memory().await;
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let status = |id: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query_scalar::<_, Option<String>>("SELECT status FROM conversation_messages WHERE id = ?")
+                    .bind(id)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // A reply that finishes is one complete row.
+        let logger = ConversationLogger::new(pool.clone());
+        logger.checkpoint_bot_message(&channel_id, "reply_1", "Looking").await.unwrap();
+        logger.checkpoint_bot_message(&channel_id, "reply_1", "Looking at the build").await.unwrap();
+        logger.finalize_bot_message(&channel_id, "reply_1", "Looking at the build. Fixed.", false).await.unwrap();
+        assert_eq!(status("reply_1").await.as_deref(), Some("complete"));
+        // Finished rows aren't rewritten by a late checkpoint.
+        logger.checkpoint_bot_message(&channel_id, "reply_1", "Looking").await.unwrap();
+
+        // The process dies mid-stream, leaving the last checkpoint behind.
+        logger.checkpoint_bot_message(&channel_id, "reply_2", "Running the tests").await.unwrap();
+        drop(logger);
+
+        let restarted = ConversationLogger::new(pool.clone());
+        assert_eq!(restarted.recover_pending_messages().await.unwrap(), 1);
+        assert_eq!(restarted.recover_pending_messages().await.unwrap(), 0);
+        assert_eq!(status("reply_2").await.as_deref(), Some("interrupted"));
+
+        let messages = restarted.load_recent(&channel_id, 10, false).await.unwrap();
+        let metadata = |content: &str| {
+            let message = messages.iter().find(|message| message.content == content).expect("message kept");
+            message.metadata.clone()
+        };
+        assert_eq!(messages.len(), 2);
+        assert_eq!(metadata("Looking at the build. Fixed."), None);
+        assert_eq!(metadata("Running the tests").as_deref(), Some(r#"{"interrupted":true}"#));
+
+        // A turn that ended early finalizes its own reply as interrupted.
+        restarted.checkpoint_bot_message(&channel_id, "reply_3", "Half").await.unwrap();
+        restarted.finalize_bot_message(&channel_id, "reply_3", "Half a reply", true).await.unwrap();
+        assert_eq!(status("reply_3").await.as_deref(), Some("interrupted"));
+        // Plain messages have no status.
+        restarted.log_bot_message(&channel_id, "hello");
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let plain: Option<String> = sqlx::query_scalar("SELECT status FROM conversation_messages WHERE content = 'hello'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(plain, None);
+    }
+
+    #[tokio::test]
+    async fn test_reset_channel() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let store = crate::conversation::ChannelStore::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+
+        for created_at in ["2026-01-01 10:00:00", "2026-01-01 10:01:00"] {
+            insert_message_at(&pool, &channel_id, created_at).await;
+        }
+        insert_message_at(&pool, &other_channel, "2026-01-01 10:00:00").await;
+        seed_summaries(&logger, &channel_id, 1).await;
+        store.set_active_session(&channel_id, "ses_1").await.unwrap();
+
+        let reset = logger
+            .reset_channel(&channel_id, directory.path(), ResetMode::SoftDelete)
+            .await
+            .unwrap();
+        assert_eq!((reset.messages_cleared, reset.summaries_cleared), (2, 1));
+        assert!(reset.session_cleared);
+        assert!(reset.archive_path.unwrap().exists());
+
+        assert!(logger.load_recent(&channel_id, 10, true).await.unwrap().is_empty());
+        assert!(logger.load_compaction_summaries(&channel_id).await.unwrap().is_empty());
+        assert_eq!(logger.turns_since_last_compaction(&channel_id).await.unwrap(), 0);
+        assert_eq!(store.get_active_session(&channel_id).await.unwrap(), None);
+        assert_eq!(logger.load_recent(&other_channel, 10, true).await.unwrap().len(), 1);
+
+        // Soft-deleted rows are kept until a hard reset removes them.
+        let kept: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM conversation_messages WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(kept, 2);
+
+        let reset = logger
+            .reset_channel(&channel_id, directory.path(), ResetMode::HardDelete)
+            .await
+            .unwrap();
+        assert_eq!((reset.messages_cleared, reset.summaries_cleared), (2, 1));
+        assert!(reset.archive_path.is_none(), "nothing live left to archive");
+        assert!(!reset.session_cleared);
+    }
+
+    #[tokio::test]
+    async fn test_export_jsonl_splits_on_compaction() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let at = |minute: u32| chrono::DateTime::from_timestamp(1_770_000_000 + i64::from(minute) * 60, 0).unwrap();
+
+        for (minute, role, content) in [
+            (0, "user", "is CI green?"),
+            (1, "assistant", "yes"),
+            (3, "user", "ship it"),
+            (4, "assistant", "   "),
+            (5, "assistant", "shipped"),
+        ] {
+            let id = format!("msg_{minute}");
+            logger
+                .backfill_message(&channel_id, &opencode_info(role, &id, None), content, false, at(minute))
+                .await
+                .unwrap();
+        }
+        logger
+            .backfill_message(&channel_id, &opencode_info("user", "msg_context", None), "[channel context]", true, at(3))
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO compaction_summaries (id, channel_id, summary, created_at) VALUES ('s1', ?, 'CI is green.', ?)",
+        )
+        .bind(channel_id.as_ref())
+        .bind(sqlite_timestamp(at(2)))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let jsonl = logger.export_jsonl(&channel_id).await.unwrap();
+        let lines: Vec<serde_json::Value> =
+            jsonl.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            serde_json::json!({ "messages": [
+                { "role": "user", "content": "is CI green?" },
+                { "role": "assistant", "content": "yes" },
+            ]})
+        );
+        assert_eq!(
+            lines[1],
+            serde_json::json!({ "messages": [
+                { "role": "system", "content": "Summary of the earlier conversation:\nCI is green." },
+                { "role": "user", "content": "ship it" },
+                { "role": "assistant", "content": "shipped" },
+            ]})
+        );
+        assert!(logger.export_jsonl(&ChannelId::new("discord:9:9").unwrap()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_archive_transcript() {
+        let logger = ConversationLogger::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+        let message = |sender_name: Option<&str>, role: &str, content: &str| ConversationMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            channel_id: channel_id.to_string(),
+            role: role.into(),
+            sender_name: sender_name.map(Into::into),
+            sender_id: None,
+            content: content.into(),
+            metadata: None,
+            token_count: None,
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        };
+        let messages = [
+            message(Some("alice"), "user", "is CI green?"),
+            message(None, "assistant", "not yet"),
+        ];
+
+        let path = logger
+            .archive_transcript(&channel_id, &messages, directory.path())
+            .await
+            .unwrap();
+
+        assert!(path.file_name().unwrap().to_str().unwrap().starts_with("discord_1_2-"));
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = transcript.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with("alice (user): is CI green?"));
+        assert!(lines[1].ends_with("assistant: not yet"));
+    }
+
+    #[tokio::test]
+    async fn test_history_errors_keep_their_kind() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let directory = tempfile::tempdir().unwrap();
+
+        let error = logger.archive_transcript(&channel_id, &[], directory.path()).await.unwrap_err();
+        assert!(matches!(error, crate::error::Error::History(HistoryError::NotFound { .. })));
+
+        let error = logger.load_where_metadata(&channel_id, "a\"b", "x", 10).await.unwrap_err();
+        assert!(matches!(error, crate::error::Error::History(HistoryError::InvalidQuery(_))));
+
+        pool.close().await;
+        match logger.load_recent(&channel_id, 10, false).await.unwrap_err() {
+            crate::error::Error::History(error) => assert!(error.is_unavailable()),
+            other => panic!("expected a history error, got {other:?}"),
+        }
+    }
+}
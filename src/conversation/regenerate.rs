@@ -0,0 +1,78 @@
+//! Re-rolling the bot's last reply, behind the `!regenerate` command.
+//!
+//! Regenerating finds the channel's last user message, marks the replies to
+//! it superseded (hidden, but kept for audit), rolls the in-memory history
+//! back to before that message, and runs the turn again, optionally on a
+//! different model. The new reply is logged as a message of its own.
+
+use rig::message::{Message, UserContent};
+
+/// A `!regenerate` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Regenerate {
+    /// Model to run the turn on instead of the channel's routed one.
+    pub model: Option<String>,
+}
+
+impl Regenerate {
+    /// Parse `!regenerate` or `!regenerate <provider/model>`. Returns `None`
+    /// for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.trim().strip_prefix("!regenerate")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let model = Some(rest.trim()).filter(|model| !model.is_empty()).map(str::to_string);
+        Some(Self { model })
+    }
+}
+
+/// Drop the last user turn and everything after it from `history`. Tool
+/// results ride in user messages too, so only a user message without any
+/// counts as a turn. Returns `false`, leaving `history` alone, if there is
+/// no user turn.
+pub fn truncate_to_last_user_turn(history: &mut Vec<Message>) -> bool {
+    let last_turn = history.iter().rposition(|message| match message {
+        Message::User { content } => !content.iter().any(|item| matches!(item, UserContent::ToolResult(_))),
+        _ => false,
+    });
+    match last_turn {
+        Some(index) => {
+            history.truncate(index);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Regenerate::parse("!regenerate"), Some(Regenerate { model: None }));
+        assert_eq!(
+            Regenerate::parse(" !regenerate anthropic/claude-sonnet-4 "),
+            Some(Regenerate { model: Some("anthropic/claude-sonnet-4".into()) })
+        );
+        assert_eq!(Regenerate::parse("!regenerated"), None);
+        assert_eq!(Regenerate::parse("please !regenerate"), None);
+    }
+
+    #[test]
+    fn test_truncate_to_last_user_turn() {
+        let mut history = vec![
+            Message::user("first"),
+            Message::assistant("one"),
+            Message::user("second"),
+            Message::assistant("two"),
+        ];
+        assert!(truncate_to_last_user_turn(&mut history));
+        assert_eq!(history.len(), 2);
+
+        let mut empty = vec![Message::assistant("hello")];
+        assert!(!truncate_to_last_user_turn(&mut empty));
+        assert_eq!(empty.len(), 1);
+    }
+}
@@ -0,0 +1,77 @@
+//! Token counting for budgets and persisted message sizes.
+//!
+//! The default `CharTokenizer` is a heuristic. Deployments that need exact
+//! counts can build with the `bpe-tokenizer` feature and point
+//! `tokenizer_path` at a Hugging Face `tokenizer.json` for their model.
+
+use std::fmt::Debug;
+
+/// Counts the tokens a piece of text costs. Object-safe, so it can be shared
+/// as `Arc<dyn Tokenizer>`.
+pub trait Tokenizer: Send + Sync + Debug {
+    fn count(&self, text: &str) -> usize;
+}
+
+/// Rough characters per token for English text.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Heuristic fallback: characters / 4 rounded up, but never fewer than the
+/// number of whitespace-separated words.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CharTokenizer;
+
+impl Tokenizer for CharTokenizer {
+    fn count(&self, text: &str) -> usize {
+        let chars = text.chars().count().div_ceil(CHARS_PER_TOKEN);
+        chars.max(text.split_whitespace().count())
+    }
+}
+
+/// A BPE tokenizer loaded from a Hugging Face `tokenizer.json`.
+#[cfg(feature = "bpe-tokenizer")]
+pub struct HuggingFaceTokenizer {
+    inner: tokenizers::Tokenizer,
+}
+
+#[cfg(feature = "bpe-tokenizer")]
+impl HuggingFaceTokenizer {
+    pub fn from_file(path: &std::path::Path) -> anyhow::Result<Self> {
+        let inner = tokenizers::Tokenizer::from_file(path)
+            .map_err(|error| anyhow::anyhow!("failed to load tokenizer {}: {error}", path.display()))?;
+        Ok(Self { inner })
+    }
+}
+
+#[cfg(feature = "bpe-tokenizer")]
+impl Debug for HuggingFaceTokenizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HuggingFaceTokenizer").finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "bpe-tokenizer")]
+impl Tokenizer for HuggingFaceTokenizer {
+    fn count(&self, text: &str) -> usize {
+        match self.inner.encode(text, false) {
+            Ok(encoding) => encoding.len(),
+            Err(error) => {
+                tracing::debug!(%error, "tokenizer failed, using character estimate");
+                CharTokenizer.count(text)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_tokenizer() {
+        assert_eq!(CharTokenizer.count(""), 0);
+        assert_eq!(CharTokenizer.count("alice"), 2);
+        assert_eq!(CharTokenizer.count(&"x".repeat(40)), 10);
+        // Many short words outnumber characters / 4.
+        assert_eq!(CharTokenizer.count("a b c d e f"), 6);
+    }
+}
@@ -0,0 +1,128 @@
+//! Bounded concurrency for fire-and-forget history writes.
+//!
+//! `ConversationLogger` writes messages, tool calls, and message parts on
+//! spawned tasks so callers never wait on SQLite. A burst of messages would
+//! otherwise start as many writes at once as there are messages, all
+//! competing for the pool's connections. `WriteLimiter` still spawns every
+//! write immediately, but lets at most `max_in_flight` of them run; the rest
+//! wait their turn, in arrival order.
+
+use std::fmt::Write as _;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tokio::sync::Semaphore;
+
+/// Caps the history writes running at once. Share one between every logger
+/// of an agent, so the cap covers all of them.
+#[derive(Debug)]
+pub struct WriteLimiter {
+    max_in_flight: usize,
+    permits: Arc<Semaphore>,
+    queued: Arc<AtomicUsize>,
+}
+
+/// A snapshot of the limiter, for spotting saturation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteStats {
+    /// Writes waiting for a slot.
+    pub queued: usize,
+    /// Writes running now.
+    pub in_flight: usize,
+}
+
+impl WriteLimiter {
+    /// Allow at most `max_in_flight` writes at once (at least one).
+    pub fn new(max_in_flight: usize) -> Self {
+        let max_in_flight = max_in_flight.max(1);
+        Self {
+            max_in_flight,
+            permits: Arc::new(Semaphore::new(max_in_flight)),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Spawn `write`, running it once a slot is free. Returns immediately.
+    pub fn spawn<F>(&self, write: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let permits = self.permits.clone();
+        let queued = self.queued.clone();
+        queued.fetch_add(1, Ordering::Relaxed);
+        tokio::spawn(async move {
+            let permit = permits.acquire_owned().await;
+            queued.fetch_sub(1, Ordering::Relaxed);
+            // The semaphore is never closed, so the permit is always granted.
+            let _permit = permit.ok();
+            write.await;
+        });
+    }
+
+    pub fn stats(&self) -> WriteStats {
+        WriteStats {
+            queued: self.queued.load(Ordering::Relaxed),
+            in_flight: self.max_in_flight - self.permits.available_permits(),
+        }
+    }
+}
+
+/// Render each agent's write queue in the Prometheus text exposition format.
+pub fn render_metrics<'a>(limiters: impl IntoIterator<Item = (&'a str, &'a WriteLimiter)>) -> String {
+    let stats: Vec<(&str, WriteStats)> =
+        limiters.into_iter().map(|(agent_id, limiter)| (agent_id, limiter.stats())).collect();
+    if stats.is_empty() {
+        return String::new();
+    }
+
+    let mut output = String::new();
+    let series: [(&str, fn(&WriteStats) -> usize); 2] = [
+        ("spacebot_history_writes_queued", |stats| stats.queued),
+        ("spacebot_history_writes_in_flight", |stats| stats.in_flight),
+    ];
+    for (name, value) in series {
+        let _ = writeln!(output, "# TYPE {name} gauge");
+        for (agent_id, stats) in &stats {
+            let _ = writeln!(output, "{name}{{agent=\"{agent_id}\"}} {}", value(stats));
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_caps_writes_in_flight() {
+        let limiter = WriteLimiter::new(2);
+        let (release_tx, _) = tokio::sync::broadcast::channel::<()>(1);
+        let (done_tx, mut done_rx) = tokio::sync::mpsc::unbounded_channel();
+        for _ in 0..5 {
+            let mut release = release_tx.subscribe();
+            let done = done_tx.clone();
+            limiter.spawn(async move {
+                let _ = release.recv().await;
+                let _ = done.send(());
+            });
+        }
+
+        // Let the first two start; the other three wait for a slot.
+        while limiter.stats().in_flight < 2 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(limiter.stats(), WriteStats { queued: 3, in_flight: 2 });
+        let rendered = render_metrics([("main", &limiter)]);
+        assert!(rendered.contains("spacebot_history_writes_queued{agent=\"main\"} 3"));
+
+        // Every write still runs once slots free up.
+        drop(release_tx);
+        for _ in 0..5 {
+            done_rx.recv().await.unwrap();
+        }
+        while limiter.stats() != (WriteStats { queued: 0, in_flight: 0 }) {
+            tokio::task::yield_now().await;
+        }
+    }
+}
@@ -368,9 +368,17 @@ impl Scheduler {
     }
 }
 
+/// The channel a cron job runs in. Job IDs are checked against this when a
+/// job is created or loaded from config, so a job that can't run is refused
+/// up front.
+pub fn cron_channel_id(job_id: &str) -> std::result::Result<crate::ChannelId, crate::ChannelIdError> {
+    crate::ChannelId::new(format!("cron:{job_id}"))
+}
+
 /// Execute a single cron job: create a fresh channel, run the prompt, deliver the result.
 async fn run_cron_job(job: &CronJob, context: &CronContext) -> Result<()> {
-    let channel_id: crate::ChannelId = Arc::from(format!("cron:{}", job.id).as_str());
+    let channel_id = cron_channel_id(&job.id)
+        .map_err(|error| anyhow::anyhow!("cron job {} has no valid channel ID: {error}", job.id))?;
 
     // Create the outbound response channel to collect whatever the channel produces
     let (response_tx, mut response_rx) = tokio::sync::mpsc::channel::<OutboundResponse>(32);
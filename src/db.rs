@@ -3,7 +3,31 @@
 use crate::error::{DbError, Result};
 use anyhow::Context as _;
 use sqlx::SqlitePool;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use std::future::Future;
 use std::path::Path;
+use std::time::Duration;
+
+/// How long a connection waits on a locked database before returning
+/// `SQLITE_BUSY`.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Attempts made by `with_retry` before giving up on a locked database.
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first retry. Doubles on each attempt.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Outcome of a `Db::maintenance` run.
+#[derive(Debug, Clone, Default)]
+pub struct MaintenanceReport {
+    /// SQLite database size in bytes before `VACUUM`.
+    pub size_before: u64,
+    /// SQLite database size in bytes after `VACUUM`.
+    pub size_after: u64,
+    /// Problems reported by `PRAGMA integrity_check`. Empty when the database is healthy.
+    pub integrity_issues: Vec<String>,
+}
 
 /// Database connections bundle.
 pub struct Db {
@@ -23,16 +47,19 @@ impl Db {
     /// Connect to all databases and run migrations.
     pub async fn connect(data_dir: &Path) -> Result<Self> {
         // SQLite
-        let sqlite_url = format!("sqlite:{}?mode=rwc", data_dir.join("spacebot.db").display());
-        let sqlite = SqlitePool::connect(&sqlite_url)
+        // WAL lets reads proceed alongside the fire-and-forget writers, and the
+        // busy timeout makes writers queue on the lock instead of failing fast.
+        let options = SqliteConnectOptions::new()
+            .filename(data_dir.join("spacebot.db"))
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .synchronous(SqliteSynchronous::Normal)
+            .busy_timeout(BUSY_TIMEOUT);
+        let sqlite = SqlitePool::connect_with(options)
             .await
             .with_context(|| "failed to connect to SQLite")?;
         
-        // Run migrations
-        sqlx::migrate!("./migrations")
-            .run(&sqlite)
-            .await
-            .with_context(|| "failed to run database migrations")?;
+        migrate(&sqlite).await?;
         
         // LanceDB
         let lance_path = data_dir.join("lancedb");
@@ -56,9 +83,279 @@ impl Db {
         })
     }
     
+    /// Check integrity, then reclaim free pages and refresh query planner stats.
+    ///
+    /// `VACUUM` rewrites the whole file and holds the write lock while it does,
+    /// so run this during quiet periods. Concurrent writers wait on the busy
+    /// timeout rather than failing. Vacuum is skipped if the integrity check
+    /// finds problems, since rewriting a corrupt database can make it worse.
+    pub async fn maintenance(&self) -> Result<MaintenanceReport> {
+        let integrity_issues: Vec<String> = sqlx::query_scalar("PRAGMA integrity_check")
+            .fetch_all(&self.sqlite)
+            .await
+            .with_context(|| "integrity check failed")?
+            .into_iter()
+            .filter(|line: &String| line != "ok")
+            .collect();
+
+        let size_before = database_size(&self.sqlite).await?;
+        if !integrity_issues.is_empty() {
+            tracing::warn!(issues = integrity_issues.len(), "SQLite integrity check failed, skipping vacuum");
+            return Ok(MaintenanceReport {
+                size_before,
+                size_after: size_before,
+                integrity_issues,
+            });
+        }
+
+        with_retry(|| sqlx::query("VACUUM").execute(&self.sqlite))
+            .await
+            .with_context(|| "VACUUM failed")?;
+        sqlx::query("PRAGMA optimize")
+            .execute(&self.sqlite)
+            .await
+            .with_context(|| "PRAGMA optimize failed")?;
+
+        let size_after = database_size(&self.sqlite).await?;
+        tracing::info!(size_before, size_after, "SQLite maintenance complete");
+
+        Ok(MaintenanceReport {
+            size_before,
+            size_after,
+            integrity_issues,
+        })
+    }
+
     /// Close all database connections gracefully.
     pub async fn close(self) {
         self.sqlite.close().await;
         // LanceDB and redb close automatically when dropped
     }
 }
+
+/// Apply the numbered migrations in `migrations/` that haven't run yet.
+///
+/// sqlx records each applied migration (with a checksum) in
+/// `_sqlx_migrations`, so running this on every startup is a no-op once the
+/// schema is current. New tables and columns ship as a new
+/// `<timestamp>_<name>.sql` file; applied files must never be edited.
+pub async fn migrate(pool: &SqlitePool) -> Result<()> {
+    sqlx::migrate!("./migrations")
+        .run(pool)
+        .await
+        .with_context(|| "failed to run database migrations")?;
+    Ok(())
+}
+
+/// A fresh in-memory database with every migration applied, for tests.
+#[cfg(test)]
+pub async fn connect_in_memory() -> SqlitePool {
+    let options = SqliteConnectOptions::new()
+        .in_memory(true)
+        .create_if_missing(true);
+
+    // Single-connection pool: each pool gets its own private in-memory db.
+    let pool = sqlx::pool::PoolOptions::<sqlx::Sqlite>::new()
+        .max_connections(1)
+        .connect_with(options)
+        .await
+        .expect("in-memory SQLite");
+    migrate(&pool).await.expect("migrations");
+    pool
+}
+
+/// Insert a user message into `channel_id` with a fixed `created_at`
+/// ("YYYY-MM-DD HH:MM:SS"), for tests that depend on message times.
+#[cfg(test)]
+pub async fn insert_message_at(pool: &SqlitePool, channel_id: &str, created_at: &str) {
+    sqlx::query(
+        "INSERT INTO conversation_messages (id, channel_id, role, content, created_at) \
+         VALUES (?, ?, 'user', 'hi', ?)",
+    )
+    .bind(uuid::Uuid::new_v4().to_string())
+    .bind(channel_id)
+    .bind(created_at)
+    .execute(pool)
+    .await
+    .unwrap();
+}
+
+/// Size of the main database file in bytes, excluding the WAL.
+async fn database_size(pool: &SqlitePool) -> Result<u64> {
+    let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+        .fetch_one(pool)
+        .await
+        .with_context(|| "failed to read page count")?;
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+        .fetch_one(pool)
+        .await
+        .with_context(|| "failed to read page size")?;
+    Ok((page_count * page_size) as u64)
+}
+
+/// Whether an error means the database was locked by another connection and
+/// the operation can simply be tried again.
+pub fn is_retryable(error: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(error) = error else {
+        return false;
+    };
+    // SQLITE_BUSY (5) and SQLITE_LOCKED (6), including their extended codes
+    // (e.g. 517 SQLITE_BUSY_SNAPSHOT), which keep the primary code in the low byte.
+    let primary_code = error
+        .code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .map(|code| code & 0xff);
+    matches!(primary_code, Some(5 | 6))
+        || error.message().contains("database is locked")
+        || error.message().contains("database table is locked")
+}
+
+/// Run a query, retrying with exponential backoff while the database is locked.
+///
+/// `operation` builds and runs the query fresh on each attempt. Errors other
+/// than lock contention are returned immediately.
+pub async fn with_retry<T, F, Fut>(mut operation: F) -> std::result::Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = std::result::Result<T, sqlx::Error>>,
+{
+    let mut backoff = INITIAL_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        match operation().await {
+            Err(error) if attempt < MAX_ATTEMPTS && is_retryable(&error) => {
+                tracing::debug!(%error, attempt, "database locked, retrying");
+                tokio::time::sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn connect(path: &Path) -> SqlitePool {
+        // No busy timeout, so contention surfaces as an error immediately.
+        let options = SqliteConnectOptions::new()
+            .filename(path)
+            .create_if_missing(true)
+            .busy_timeout(Duration::ZERO);
+        sqlx::pool::PoolOptions::<sqlx::Sqlite>::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .expect("SQLite")
+    }
+
+    async fn columns(pool: &SqlitePool, table: &str) -> Vec<String> {
+        sqlx::query_scalar("SELECT name FROM pragma_table_info(?) ORDER BY cid")
+            .bind(table)
+            .fetch_all(pool)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_migrations_build_schema() {
+        let options = SqliteConnectOptions::new().in_memory(true);
+        let pool = sqlx::pool::PoolOptions::<sqlx::Sqlite>::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .expect("in-memory SQLite");
+
+        migrate(&pool).await.unwrap();
+        // A second run on startup finds nothing to do.
+        migrate(&pool).await.unwrap();
+
+        let applied: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM _sqlx_migrations WHERE success = 1")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(applied as usize, sqlx::migrate!("./migrations").migrations.len());
+
+        assert_eq!(
+            columns(&pool, "conversation_messages").await,
+            [
+                "id", "channel_id", "role", "sender_name", "sender_id", "content", "metadata", "created_at",
+                "token_count", "is_synthetic", "cleared_at", "is_hidden", "superseded_at", "idempotency_key",
+                "opencode_message_id",
+            ]
+        );
+        let summaries = columns(&pool, "compaction_summaries").await;
+        for column in ["channel_id", "summary", "turns_covered", "covered_from", "covered_to"] {
+            assert!(summaries.iter().any(|name| name == column), "compaction_summaries.{column}");
+        }
+        for table in ["attachment_cache", "channel_sessions", "channel_agents", "channel_access", "channel_models", "channel_settings", "channel_system_prompts", "message_parts", "permission_grants", "prompt_sends", "session_forks", "sse_debug"] {
+            assert!(!columns(&pool, table).await.is_empty(), "{table} missing");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_reclaims_pruned_rows() {
+        let directory = tempfile::tempdir().unwrap();
+        let db = Db::connect(directory.path()).await.unwrap();
+        sqlx::query("CREATE TABLE items (id INTEGER PRIMARY KEY, body TEXT)").execute(&db.sqlite).await.unwrap();
+        for id in 0..200 {
+            sqlx::query("INSERT INTO items (id, body) VALUES (?, ?)")
+                .bind(id)
+                .bind("x".repeat(4096))
+                .execute(&db.sqlite)
+                .await
+                .unwrap();
+        }
+        // Prune all but the newest rows; the freed pages stay in the file until vacuumed.
+        sqlx::query("DELETE FROM items WHERE id < 190").execute(&db.sqlite).await.unwrap();
+        sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)").execute(&db.sqlite).await.unwrap();
+
+        let report = db.maintenance().await.unwrap();
+        assert!(report.integrity_issues.is_empty(), "{:?}", report.integrity_issues);
+        assert!(report.size_after < report.size_before, "{report:?}");
+
+        // The rows kept by the prune survive the vacuum.
+        let kept: Vec<i64> = sqlx::query_scalar("SELECT id FROM items ORDER BY id")
+            .fetch_all(&db.sqlite)
+            .await
+            .unwrap();
+        assert_eq!(kept, (190..200).collect::<Vec<_>>());
+        db.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_retries_while_database_is_locked() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("test.db");
+        let holder = connect(&path).await;
+        let writer = connect(&path).await;
+        sqlx::query("CREATE TABLE items (id INTEGER)").execute(&holder).await.unwrap();
+
+        let mut lock = holder.begin().await.unwrap();
+        sqlx::query("INSERT INTO items VALUES (1)").execute(&mut *lock).await.unwrap();
+
+        let error = sqlx::query("INSERT INTO items VALUES (2)")
+            .execute(&writer)
+            .await
+            .unwrap_err();
+        assert!(is_retryable(&error));
+        assert!(!is_retryable(&sqlx::Error::RowNotFound));
+
+        let release = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(120)).await;
+            lock.commit().await.unwrap();
+        });
+        with_retry(|| sqlx::query("INSERT INTO items VALUES (2)").execute(&writer))
+            .await
+            .unwrap();
+        release.await.unwrap();
+
+        let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM items")
+            .fetch_one(&writer)
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+}
@@ -29,6 +29,9 @@ pub enum Error {
     #[error(transparent)]
     Settings(#[from] SettingsError),
 
+    #[error(transparent)]
+    History(#[from] HistoryError),
+
     #[error("database error: {0}")]
     Sqlx(#[from] sqlx::Error),
 
@@ -164,6 +167,47 @@ pub enum AgentError {
     Other(#[from] anyhow::Error),
 }
 
+/// Conversation history and channel storage errors.
+#[derive(Debug, thiserror::Error)]
+pub enum HistoryError {
+    #[error("no history for channel {channel_id}")]
+    NotFound { channel_id: String },
+
+    #[error("history database error: {0}")]
+    Database(#[from] sqlx::Error),
+
+    #[error("failed to serialize history: {0}")]
+    Serialization(#[from] serde_json::Error),
+
+    #[error("invalid history query: {0}")]
+    InvalidQuery(String),
+
+    #[error("failed to write transcript archive {path}: {source}")]
+    Archive {
+        path: String,
+        source: Arc<std::io::Error>,
+    },
+
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl HistoryError {
+    /// Whether the database couldn't be reached at all, as opposed to a
+    /// query failing against a working one.
+    pub fn is_unavailable(&self) -> bool {
+        matches!(
+            self,
+            Self::Database(
+                sqlx::Error::Io(_)
+                    | sqlx::Error::PoolTimedOut
+                    | sqlx::Error::PoolClosed
+                    | sqlx::Error::WorkerCrashed
+            )
+        )
+    }
+}
+
 /// Secrets and credential errors.
 #[derive(Debug, thiserror::Error)]
 pub enum SecretsError {
@@ -37,12 +37,152 @@ pub enum ProviderSetupEvent {
 /// Agent identifier type.
 pub type AgentId = Arc<str>;
 
-/// Channel identifier type.
-pub type ChannelId = Arc<str>;
+/// Channel identifier: the conversation ID an adapter assigns, such as
+/// `discord:123:456` or `slack:T1:C1:1700000000.000100`.
+///
+/// IDs end up in database keys, log lines and archive file names, so they
+/// are validated when built: non-empty, at most `ChannelId::MAX_LEN` bytes,
+/// and only ASCII letters, digits and `:_-.@`. Build one from untrusted
+/// input with `ChannelId::new` or `parse`.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ChannelId(Arc<str>);
+
+/// Why a string isn't a valid channel ID.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ChannelIdError {
+    #[error("channel ID is empty")]
+    Empty,
+    #[error("channel ID is {0} bytes, over the limit of {max}", max = ChannelId::MAX_LEN)]
+    TooLong(usize),
+    #[error("channel ID contains {0:?}; only ASCII letters, digits and ':_-.@' are allowed")]
+    InvalidChar(char),
+}
+
+impl ChannelId {
+    /// Longest ID accepted, in bytes.
+    pub const MAX_LEN: usize = 256;
+
+    pub fn new(id: impl AsRef<str>) -> std::result::Result<Self, ChannelIdError> {
+        let id = id.as_ref();
+        if id.is_empty() {
+            return Err(ChannelIdError::Empty);
+        }
+        if id.len() > Self::MAX_LEN {
+            return Err(ChannelIdError::TooLong(id.len()));
+        }
+        if let Some(c) = id
+            .chars()
+            .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, ':' | '_' | '-' | '.' | '@')))
+        {
+            return Err(ChannelIdError::InvalidChar(c));
+        }
+        Ok(Self(Arc::from(id)))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::str::FromStr for ChannelId {
+    type Err = ChannelIdError;
+
+    fn from_str(id: &str) -> std::result::Result<Self, Self::Err> {
+        Self::new(id)
+    }
+}
+
+impl TryFrom<String> for ChannelId {
+    type Error = ChannelIdError;
+
+    fn try_from(id: String) -> std::result::Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl TryFrom<&str> for ChannelId {
+    type Error = ChannelIdError;
+
+    fn try_from(id: &str) -> std::result::Result<Self, Self::Error> {
+        Self::new(id)
+    }
+}
+
+impl From<ChannelId> for String {
+    fn from(id: ChannelId) -> Self {
+        id.0.to_string()
+    }
+}
+
+impl std::ops::Deref for ChannelId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for ChannelId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::borrow::Borrow<str> for ChannelId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Display for ChannelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::fmt::Debug for ChannelId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl PartialEq<str> for ChannelId {
+    fn eq(&self, other: &str) -> bool {
+        &*self.0 == other
+    }
+}
+
+impl PartialEq<&str> for ChannelId {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.0 == *other
+    }
+}
 
 /// Worker identifier type.
 pub type WorkerId = uuid::Uuid;
 
+/// A follow-up message routed to an interactive worker.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkerInput {
+    pub text: String,
+    /// Idempotency key of the user message being handled when the follow-up
+    /// was routed. OpenCode workers derive the prompt's key from it.
+    pub message_key: Option<String>,
+}
+
+impl From<String> for WorkerInput {
+    fn from(text: String) -> Self {
+        Self { text, message_key: None }
+    }
+}
+
+impl From<&str> for WorkerInput {
+    fn from(text: &str) -> Self {
+        text.to_string().into()
+    }
+}
+
 /// Branch identifier type.
 pub type BranchId = uuid::Uuid;
 
@@ -158,12 +298,59 @@ pub enum ProcessEvent {
         description: String,
         patterns: Vec<String>,
     },
+    WorkerPermissionBatch {
+        agent_id: AgentId,
+        worker_id: WorkerId,
+        channel_id: Option<ChannelId>,
+        permissions: Vec<opencode::PermissionPrompt>,
+    },
     WorkerQuestion {
         agent_id: AgentId,
         worker_id: WorkerId,
         channel_id: Option<ChannelId>,
         question_id: String,
         questions: Vec<opencode::QuestionInfo>,
+        awaiting_reply: bool,
+    },
+    /// A question the channel was asked got its answers.
+    WorkerQuestionAnswered {
+        agent_id: AgentId,
+        worker_id: WorkerId,
+        channel_id: Option<ChannelId>,
+        question_id: String,
+        answers: Vec<opencode::QuestionAnswer>,
+    },
+    /// An OpenCode worker's tool call failed. Sent once per call.
+    WorkerToolError {
+        agent_id: AgentId,
+        worker_id: WorkerId,
+        channel_id: Option<ChannelId>,
+        tool: String,
+        call_id: String,
+        error: String,
+    },
+    /// An OpenCode worker's turn finished (the session went idle).
+    WorkerTurnOutcome {
+        agent_id: AgentId,
+        worker_id: WorkerId,
+        channel_id: Option<ChannelId>,
+        outcome: opencode::TurnOutcome,
+    },
+    /// An OpenCode worker's turn has retried its provider call more times
+    /// than `retry_warning_attempts`. Sent once per turn.
+    WorkerRetryWarning {
+        agent_id: AgentId,
+        worker_id: WorkerId,
+        channel_id: Option<ChannelId>,
+        attempt: u32,
+        message: Option<String>,
+    },
+    /// An OpenCode worker's typing indicator should change, or be refreshed.
+    WorkerIndicator {
+        agent_id: AgentId,
+        worker_id: WorkerId,
+        channel_id: Option<ChannelId>,
+        state: opencode::IndicatorState,
     },
 }
 
@@ -303,3 +490,38 @@ pub enum StatusUpdate {
     WorkerStarted { worker_id: WorkerId, task: String },
     WorkerCompleted { worker_id: WorkerId, result: String },
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_channel_id_validation() {
+        for id in ["discord:123:456", "discord:dm:9", "slack:T1:C1:1700000000.000100", "telegram:-100123", "cron:daily_digest", "webhook:ops@example.com"] {
+            let parsed: ChannelId = id.parse().unwrap();
+            assert_eq!(parsed.to_string(), id);
+            assert_eq!(parsed, id);
+        }
+
+        assert_eq!(ChannelId::new(""), Err(ChannelIdError::Empty));
+        assert_eq!(ChannelId::new("webhook:../../etc"), Err(ChannelIdError::InvalidChar('/')));
+        assert_eq!(ChannelId::new("webhook:a b"), Err(ChannelIdError::InvalidChar(' ')));
+        assert_eq!(ChannelId::new("webhook:\nforged"), Err(ChannelIdError::InvalidChar('\n')));
+        assert_eq!(ChannelId::new("webhook:caf\u{e9}"), Err(ChannelIdError::InvalidChar('\u{e9}')));
+        let long = format!("webhook:{}", "a".repeat(ChannelId::MAX_LEN));
+        assert_eq!(ChannelId::new(&long), Err(ChannelIdError::TooLong(long.len())));
+    }
+
+    #[test]
+    fn test_channel_id_serde() {
+        let id = ChannelId::new("discord:1:2").unwrap();
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, "\"discord:1:2\"");
+        assert_eq!(serde_json::from_str::<ChannelId>(&json).unwrap(), id);
+        assert!(serde_json::from_str::<ChannelId>("\"bad id\"").is_err());
+
+        // Maps keyed by ID can be looked up with a plain string.
+        let map = HashMap::from([(id.clone(), 1)]);
+        assert_eq!(map.get("discord:1:2"), Some(&1));
+    }
+}
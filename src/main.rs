@@ -430,7 +430,17 @@ async fn run(
                     // Subscribe to the agent's event bus
                     let event_rx = agent.deps.event_tx.subscribe();
 
-                    let channel_id: spacebot::ChannelId = Arc::from(conversation_id.as_str());
+                    let channel_id = match spacebot::ChannelId::new(&conversation_id) {
+                        Ok(channel_id) => channel_id,
+                        Err(error) => {
+                            tracing::warn!(
+                                %error,
+                                conversation_id = %conversation_id,
+                                "message has an invalid conversation ID, dropping"
+                            );
+                            continue;
+                        }
+                    };
 
                     let (channel, channel_tx) = spacebot::agent::channel::Channel::new(
                         channel_id,
@@ -798,6 +808,18 @@ async fn initialize_agents(
 
         // Set the settings store in RuntimeConfig and apply config-driven defaults
         runtime_config.set_settings(settings_store.clone());
+        if let Some(cipher) = &runtime_config.history_cipher {
+            match spacebot::conversation::crypto::encrypt_plaintext_rows(&db.sqlite, cipher).await {
+                Ok(report) if report.messages + report.summaries > 0 => tracing::info!(
+                    agent = %agent_config.id,
+                    messages = report.messages,
+                    summaries = report.summaries,
+                    "encrypted plaintext conversation history"
+                ),
+                Ok(_) => {}
+                Err(error) => tracing::warn!(%error, agent = %agent_config.id, "failed to encrypt existing conversation history"),
+            }
+        }
         if let Err(error) = settings_store.set_worker_log_mode(config.defaults.worker_log_mode) {
             tracing::warn!(%error, agent = %agent_config.id, "failed to set worker_log_mode from config");
         }
@@ -1025,6 +1047,24 @@ async fn initialize_agents(
         }
     }
 
+    // Start idle-session reapers for each agent
+    for (agent_id, agent) in agents.iter() {
+        let handle = spacebot::opencode::reaper::spawn_session_reaper(agent.deps.clone());
+        cortex_handles.push(handle);
+        tracing::info!(agent_id = %agent_id, "session reaper started");
+    }
+
+    // Replies still pending were streaming when the previous process died
+    for (agent_id, agent) in agents.iter() {
+        let conversation_logger = spacebot::conversation::history::ConversationLogger::new(agent.db.sqlite.clone())
+            .with_cipher(agent.deps.runtime_config.history_cipher.clone());
+        match conversation_logger.recover_pending_messages().await {
+            Ok(0) => {}
+            Ok(count) => tracing::info!(agent_id = %agent_id, count, "marked unfinished streamed replies as interrupted"),
+            Err(error) => tracing::warn!(agent_id = %agent_id, %error, "failed to recover unfinished streamed replies"),
+        }
+    }
+
     // Start cortex bulletin loops and association loops for each agent
     for (agent_id, agent) in agents.iter() {
         let cortex_logger = spacebot::agent::cortex::CortexLogger::new(agent.db.sqlite.clone());
@@ -1043,7 +1083,10 @@ async fn initialize_agents(
         for (agent_id, agent) in agents.iter() {
             let browser_config = (**agent.deps.runtime_config.browser_config.load()).clone();
             let brave_search_key = (**agent.deps.runtime_config.brave_search_key.load()).clone();
-            let conversation_logger = spacebot::conversation::history::ConversationLogger::new(agent.db.sqlite.clone());
+            let conversation_logger = spacebot::conversation::history::ConversationLogger::new(agent.db.sqlite.clone())
+                .with_cipher(agent.deps.runtime_config.history_cipher.clone())
+                .with_recent_cache(agent.deps.runtime_config.history_cache.clone())
+                .with_write_limiter(agent.deps.runtime_config.history_writes.clone());
             let channel_store = spacebot::conversation::ChannelStore::new(agent.db.sqlite.clone());
             let tool_server = spacebot::tools::create_cortex_chat_tool_server(
                 agent.deps.memory_search.clone(),
@@ -435,7 +435,7 @@ fn row_to_memory(row: &sqlx::sqlite::SqliteRow) -> Memory {
         last_accessed_at: row.try_get("last_accessed_at").unwrap_or_else(|_| chrono::Utc::now()),
         access_count: row.try_get("access_count").unwrap_or(0),
         source: row.try_get("source").ok(),
-        channel_id: channel_id.map(|id| Arc::from(id) as crate::ChannelId),
+        channel_id: channel_id.and_then(|id| crate::ChannelId::new(id).ok()),
         forgotten: row.try_get::<bool, _>("forgotten").unwrap_or(false),
     }
 }
@@ -204,6 +204,11 @@ async fn handle_send(
     State(state): State<AppState>,
     Json(request): Json<WebhookRequest>,
 ) -> Result<StatusCode, (StatusCode, String)> {
+    let conversation_id = format!("webhook:{}", request.conversation_id);
+    if let Err(error) = crate::ChannelId::new(&conversation_id) {
+        return Err((StatusCode::BAD_REQUEST, format!("invalid conversation_id: {error}")));
+    }
+
     let tx = state.inbound_tx.read().await;
     let Some(tx) = tx.as_ref() else {
         return Err((
@@ -222,8 +227,6 @@ async fn handle_send(
         serde_json::Value::String(request.sender_id.clone()),
     );
 
-    let conversation_id = format!("webhook:{}", request.conversation_id);
-
     let inbound = InboundMessage {
         id: uuid::Uuid::new_v4().to_string(),
         source: "webhook".into(),
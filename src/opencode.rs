@@ -5,10 +5,68 @@
 //! alternative worker backend that delegates to OpenCode's full agent
 //! capabilities instead of running a Rig agent loop with basic tools.
 
+pub mod access;
+pub mod agents;
+pub mod attachment_cache;
+pub mod attachments;
+pub mod attribution;
+pub mod audit;
+pub mod breaker;
+pub mod compaction;
+pub mod flood;
+pub mod forks;
+pub mod grants;
+pub mod handler;
+pub mod idempotency;
+pub mod indicator;
+pub mod limits;
+pub mod metrics;
+pub mod models;
+pub mod output;
+pub mod pending;
+pub mod permissions;
+pub mod personas;
+pub mod prompt;
+pub mod questions;
+pub mod quiet;
+pub mod queue;
+pub mod reaper;
+pub mod recorder;
+pub mod render;
+pub mod replay;
+pub mod retries;
 pub mod server;
+pub mod sessions;
+pub mod sinks;
+pub mod status;
+pub mod summarize;
+pub mod stream;
+pub mod turns;
 pub mod types;
+pub mod webhook;
 pub mod worker;
 
+pub use breaker::{BreakerConfig, BreakerState, CircuitBreaker, CircuitOpen};
+pub use flood::{FloodGuard, FloodLimit, FloodVerdict};
+pub use forks::{SessionFork, SessionForks};
+pub use handler::{DefaultEventHandler, EventContext, EventHandler};
+pub use indicator::{IndicatorState, TypingIndicator};
+pub use pending::{PendingInteractions, PendingRegistry};
+pub use personas::{ChannelPrompts, PersonaCommand};
+pub use recorder::SseRecorder;
+pub use render::render_final_message;
+pub use retries::{RetryTracker, RetryUpdate};
+pub use questions::{QuestionReplyBuilder, QuestionReplyError};
+pub use prompt::{ChannelContext, ChannelPreferences, TurnPromptBuilder, build_compaction_prompt};
 pub use server::{OpenCodeServer, OpenCodeServerPool};
-pub use types::{OpenCodePermissions, QuestionAnswer, QuestionInfo, QuestionOption};
-pub use worker::{OpenCodeWorker, OpenCodeWorkerResult};
+pub use sessions::{apply_session_title, ensure_session, resume_active_session};
+pub use sinks::{ReplySink, ReplySinks, WebhookReplySink};
+pub use status::{StatusReport, status_report};
+pub use stream::{MarkdownStreamGuard, StreamCoordinator, TextAccumulator};
+pub use turns::{ActiveTurns, TurnHandle};
+pub use types::{
+    EditToolMetadata, FinishReason, FollowUpMode, OpenCodePermissions, OpenCodeProvider, PermissionMode, PermissionProfiles,
+    PermissionPrompt, ProviderInfo, ProviderList, QuestionAnswer, QuestionInfo, QuestionOption, SessionErrorKind, TokenUsage, TurnOutcome,
+    classify_session_error,
+};
+pub use worker::{AutoCompaction, OpenCodeWorker, OpenCodeWorkerResult};
@@ -0,0 +1,201 @@
+//! Which channels the bot engages in (SQLite).
+//!
+//! `ChannelAccess` decides whether the bot answers in a channel at all,
+//! before any command or prompt is built from a message. Config sets an
+//! allowlist, a denylist, and the rule for unlisted channels;
+//! `allow_channel` and `deny_channel` record overrides in SQLite that win
+//! over config and survive restarts. Admins set them with `!access`. This
+//! is separate from permission policy, which decides what a worker may do
+//! once the bot does engage.
+
+use crate::ChannelId;
+
+use sqlx::SqlitePool;
+use std::collections::HashSet;
+
+/// Whether the bot engages in a channel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccessRule {
+    #[default]
+    Allow,
+    Deny,
+}
+
+impl std::fmt::Display for AccessRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Allow => write!(f, "allow"),
+            Self::Deny => write!(f, "deny"),
+        }
+    }
+}
+
+impl std::str::FromStr for AccessRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "deny" => Ok(Self::Deny),
+            other => Err(format!("unknown channel access rule '{other}' (expected allow or deny)")),
+        }
+    }
+}
+
+/// An `!access` command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessCommand {
+    /// `!access`: show the channel's rule.
+    Show,
+    /// `!access allow`
+    Allow,
+    /// `!access deny`
+    Deny,
+    /// `!access reset`: drop the override, going back to config.
+    Reset,
+}
+
+impl AccessCommand {
+    /// Parse an access command. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.trim().strip_prefix("!access")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        match rest.trim() {
+            "" => Some(Self::Show),
+            "allow" => Some(Self::Allow),
+            "deny" => Some(Self::Deny),
+            "reset" => Some(Self::Reset),
+            _ => None,
+        }
+    }
+}
+
+/// The configured lists. The denylist wins over the allowlist.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelAccessPolicy {
+    /// Rule for channels on neither list.
+    pub default: AccessRule,
+    pub allowed: HashSet<String>,
+    pub denied: HashSet<String>,
+}
+
+impl ChannelAccessPolicy {
+    pub fn check(&self, channel_id: &str) -> AccessRule {
+        if self.denied.contains(channel_id) {
+            AccessRule::Deny
+        } else if self.allowed.contains(channel_id) {
+            AccessRule::Allow
+        } else {
+            self.default
+        }
+    }
+}
+
+/// The configured policy plus the overrides stored in SQLite.
+#[derive(Debug, Clone)]
+pub struct ChannelAccess {
+    pool: SqlitePool,
+    policy: ChannelAccessPolicy,
+}
+
+impl ChannelAccess {
+    pub fn new(pool: SqlitePool, policy: ChannelAccessPolicy) -> Self {
+        Self { pool, policy }
+    }
+
+    /// The rule for a channel: its stored override, or else the policy's.
+    pub async fn check(&self, channel_id: &ChannelId) -> anyhow::Result<AccessRule> {
+        match self.stored_rule(channel_id).await? {
+            Some(rule) => Ok(rule),
+            None => Ok(self.policy.check(channel_id)),
+        }
+    }
+
+    /// Engage in the channel from now on, whatever the config says.
+    pub async fn allow_channel(&self, channel_id: &ChannelId) -> anyhow::Result<()> {
+        self.store_rule(channel_id, AccessRule::Allow).await
+    }
+
+    /// Stop engaging in the channel from now on, whatever the config says.
+    pub async fn deny_channel(&self, channel_id: &ChannelId) -> anyhow::Result<()> {
+        self.store_rule(channel_id, AccessRule::Deny).await
+    }
+
+    /// Drop the channel's override, going back to the configured rule.
+    /// Returns whether there was one.
+    pub async fn clear_override(&self, channel_id: &ChannelId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM channel_access WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The channel's override, if it has one.
+    pub async fn stored_rule(&self, channel_id: &ChannelId) -> anyhow::Result<Option<AccessRule>> {
+        let rule: Option<String> = sqlx::query_scalar("SELECT rule FROM channel_access WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .fetch_optional(&self.pool)
+            .await?;
+        rule.map(|rule| rule.parse().map_err(anyhow::Error::msg)).transpose()
+    }
+
+    async fn store_rule(&self, channel_id: &ChannelId, rule: AccessRule) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO channel_access (channel_id, rule, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP) \
+             ON CONFLICT(channel_id) DO UPDATE SET rule = excluded.rule, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(channel_id.as_ref())
+        .bind(rule.to_string())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(AccessCommand::parse("!access"), Some(AccessCommand::Show));
+        assert_eq!(AccessCommand::parse(" !access deny "), Some(AccessCommand::Deny));
+        assert_eq!(AccessCommand::parse("!access reset"), Some(AccessCommand::Reset));
+        assert_eq!(AccessCommand::parse("!access everyone"), None);
+        assert_eq!(AccessCommand::parse("!accessible"), None);
+    }
+
+    #[tokio::test]
+    async fn test_channel_access() {
+        let policy = ChannelAccessPolicy {
+            default: AccessRule::Deny,
+            allowed: HashSet::from(["discord:1:2".to_string(), "discord:1:3".to_string()]),
+            denied: HashSet::from(["discord:1:3".to_string()]),
+        };
+        let pool = connect_in_memory().await;
+        let access = ChannelAccess::new(pool.clone(), policy.clone());
+        let allowed: ChannelId = "discord:1:2".parse().unwrap();
+        let listed_twice: ChannelId = "discord:1:3".parse().unwrap();
+        let unlisted: ChannelId = "slack:T1:C1".parse().unwrap();
+
+        assert_eq!(access.check(&allowed).await.unwrap(), AccessRule::Allow);
+        assert_eq!(access.check(&listed_twice).await.unwrap(), AccessRule::Deny);
+        assert_eq!(access.check(&unlisted).await.unwrap(), AccessRule::Deny);
+
+        access.allow_channel(&unlisted).await.unwrap();
+        access.deny_channel(&allowed).await.unwrap();
+
+        // Overrides live in SQLite, so a fresh instance sees them.
+        let access = ChannelAccess::new(pool, policy);
+        assert_eq!(access.check(&unlisted).await.unwrap(), AccessRule::Allow);
+        assert_eq!(access.check(&allowed).await.unwrap(), AccessRule::Deny);
+
+        assert!(access.clear_override(&allowed).await.unwrap());
+        assert!(!access.clear_override(&allowed).await.unwrap());
+        assert_eq!(access.check(&allowed).await.unwrap(), AccessRule::Allow);
+    }
+}
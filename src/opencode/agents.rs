@@ -0,0 +1,153 @@
+//! Per-channel OpenCode agent selection (SQLite).
+//!
+//! OpenCode runs each prompt with an agent (`build`, `plan`, or a custom
+//! one). `ChannelAgents` records which agent a channel's prompts go to, set
+//! with `!agent <name>` after checking the server actually has it, so a typo
+//! doesn't silently fall back to the default agent.
+
+use crate::opencode::types::AgentInfo;
+use crate::ChannelId;
+
+use sqlx::SqlitePool;
+
+/// An `!agents` or `!agent` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentCommand {
+    /// `!agents`: list the agents the channel can switch to.
+    List,
+    /// `!agent` with no name: show the channel's agent.
+    Show,
+    /// `!agent default`: go back to the server's default agent.
+    Reset,
+    /// `!agent <name>`
+    Select(String),
+}
+
+impl AgentCommand {
+    /// Parse an agent command. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text == "!agents" {
+            return Some(Self::List);
+        }
+        let rest = text.strip_prefix("!agent")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let command = match rest.trim() {
+            "" => Self::Show,
+            "default" => Self::Reset,
+            name => Self::Select(name.to_string()),
+        };
+        Some(command)
+    }
+}
+
+/// Why a selection was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AgentSelectionError {
+    /// The server has no agent by that name. Carries the selectable names.
+    Unknown { available: Vec<String> },
+    /// The agent only runs as a sub-agent, not for a prompt.
+    Subagent,
+}
+
+/// Check `name` against the agents the server reported.
+pub fn validate_agent(name: &str, agents: &[AgentInfo]) -> Result<(), AgentSelectionError> {
+    match agents.iter().find(|agent| agent.name == name) {
+        Some(agent) if agent.is_selectable() => Ok(()),
+        Some(_) => Err(AgentSelectionError::Subagent),
+        None => Err(AgentSelectionError::Unknown {
+            available: agents
+                .iter()
+                .filter(|agent| agent.is_selectable())
+                .map(|agent| agent.name.clone())
+                .collect(),
+        }),
+    }
+}
+
+/// Reads and writes the agent selected for each channel.
+#[derive(Debug, Clone)]
+pub struct ChannelAgents {
+    pool: SqlitePool,
+}
+
+impl ChannelAgents {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// The agent selected for the channel, or `None` for the server default.
+    pub async fn get(&self, channel_id: &ChannelId) -> anyhow::Result<Option<String>> {
+        Ok(sqlx::query_scalar("SELECT agent FROM channel_agents WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Select `agent` for the channel. Check it with `validate_agent` first.
+    pub async fn set(&self, channel_id: &ChannelId, agent: &str) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO channel_agents (channel_id, agent, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP) \
+             ON CONFLICT(channel_id) DO UPDATE SET agent = excluded.agent, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(channel_id.as_ref())
+        .bind(agent)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Go back to the server's default agent. Returns whether one was selected.
+    pub async fn clear(&self, channel_id: &ChannelId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM channel_agents WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    fn agent(name: &str, mode: &str) -> AgentInfo {
+        AgentInfo { name: name.into(), description: None, mode: Some(mode.into()) }
+    }
+
+    #[test]
+    fn test_parse_and_validate() {
+        assert_eq!(AgentCommand::parse("!agents"), Some(AgentCommand::List));
+        assert_eq!(AgentCommand::parse(" !agent "), Some(AgentCommand::Show));
+        assert_eq!(AgentCommand::parse("!agent default"), Some(AgentCommand::Reset));
+        assert_eq!(AgentCommand::parse("!agent plan"), Some(AgentCommand::Select("plan".into())));
+        assert_eq!(AgentCommand::parse("!agentic"), None);
+        assert_eq!(AgentCommand::parse("use the plan agent"), None);
+
+        let agents = [agent("build", "primary"), agent("plan", "all"), agent("general", "subagent")];
+        assert_eq!(validate_agent("plan", &agents), Ok(()));
+        assert_eq!(validate_agent("general", &agents), Err(AgentSelectionError::Subagent));
+        assert_eq!(
+            validate_agent("pln", &agents),
+            Err(AgentSelectionError::Unknown { available: vec!["build".into(), "plan".into()] })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_agents() {
+        let agents = ChannelAgents::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        assert_eq!(agents.get(&channel_id).await.unwrap(), None);
+
+        agents.set(&channel_id, "plan").await.unwrap();
+        agents.set(&channel_id, "build").await.unwrap();
+        assert_eq!(agents.get(&channel_id).await.unwrap().as_deref(), Some("build"));
+
+        assert!(agents.clear(&channel_id).await.unwrap());
+        assert!(!agents.clear(&channel_id).await.unwrap());
+        assert_eq!(agents.get(&channel_id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Content-addressed cache of chat attachments (SQLite plus files on disk).
+//!
+//! Without it, every attachment is downloaded and inlined as a `data:` URL
+//! each time it's sent. `AttachmentCache` stores each distinct file once,
+//! named by the SHA-256 of its bytes, and hands OpenCode a stable `file://`
+//! URL for it, so re-sending the same file reuses the same part (and keeps
+//! the model's file cache warm). A platform URL seen before skips the
+//! download entirely. Entries unused for `max_age`, or beyond `max_bytes` in
+//! total (least recently used first), are evicted.
+
+use sha2::{Digest, Sha256};
+use sqlx::{Row as _, SqlitePool};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A file in the cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedFile {
+    /// SHA-256 of the content, hex encoded.
+    pub hash: String,
+    pub path: PathBuf,
+    pub mime: String,
+    pub size_bytes: u64,
+}
+
+impl CachedFile {
+    /// The URL OpenCode reads the file from.
+    pub fn url(&self) -> String {
+        format!("file://{}", self.path.display())
+    }
+}
+
+/// Stores attachments by content hash under `dir`.
+#[derive(Debug, Clone)]
+pub struct AttachmentCache {
+    pool: SqlitePool,
+    dir: PathBuf,
+    max_bytes: u64,
+    max_age: Duration,
+}
+
+impl AttachmentCache {
+    /// Keep at most `max_bytes` of files in `dir`, each for at most
+    /// `max_age` since it was last used. A zero `max_age` keeps files until
+    /// the size limit pushes them out.
+    pub fn new(pool: SqlitePool, dir: PathBuf, max_bytes: u64, max_age: Duration) -> Self {
+        Self { pool, dir, max_bytes, max_age }
+    }
+
+    /// The cached file last downloaded from `source_url`, if it's still on
+    /// disk. Marks it used.
+    pub async fn lookup_source(&self, source_url: &str) -> anyhow::Result<Option<CachedFile>> {
+        let row = sqlx::query("SELECT hash, path, mime, size_bytes FROM attachment_cache WHERE source_url = ?")
+            .bind(source_url)
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some(file) = row.as_ref().map(file_from_row) else {
+            return Ok(None);
+        };
+        if !tokio::fs::try_exists(&file.path).await.unwrap_or(false) {
+            self.remove(&file.hash).await?;
+            return Ok(None);
+        }
+        self.touch(&file.hash, Some(source_url)).await?;
+        Ok(Some(file))
+    }
+
+    /// Cache downloaded bytes. Identical content already cached is reused,
+    /// and remembered as coming from `source_url` too.
+    pub async fn store(&self, source_url: &str, mime: &str, bytes: &[u8]) -> anyhow::Result<CachedFile> {
+        let hash = content_hash(bytes);
+        let path = self.dir.join(&hash);
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            tokio::fs::write(&path, bytes).await?;
+        }
+
+        let now = chrono::Utc::now();
+        sqlx::query(
+            "INSERT INTO attachment_cache (hash, path, mime, size_bytes, source_url, created_at, last_used_at) \
+             VALUES (?, ?, ?, ?, ?, ?, ?) \
+             ON CONFLICT(hash) DO UPDATE SET source_url = excluded.source_url, last_used_at = excluded.last_used_at",
+        )
+        .bind(&hash)
+        .bind(path.to_string_lossy().as_ref())
+        .bind(mime)
+        .bind(bytes.len() as i64)
+        .bind(source_url)
+        .bind(now)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(CachedFile {
+            hash,
+            path,
+            mime: mime.to_string(),
+            size_bytes: bytes.len() as u64,
+        })
+    }
+
+    /// Drop entries past `max_age`, then the least recently used until the
+    /// total fits in `max_bytes`. Returns how many were evicted.
+    pub async fn evict(&self) -> anyhow::Result<usize> {
+        let rows = sqlx::query("SELECT hash, path, size_bytes, last_used_at FROM attachment_cache ORDER BY last_used_at DESC, rowid DESC")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let cutoff = (!self.max_age.is_zero())
+            .then(|| chrono::Duration::from_std(self.max_age).ok())
+            .flatten()
+            .map(|max_age| chrono::Utc::now() - max_age);
+        let mut kept_bytes = 0u64;
+        let mut evicted = 0;
+        for row in rows {
+            let hash: String = row.try_get("hash")?;
+            let size_bytes = row.try_get::<i64, _>("size_bytes")? as u64;
+            let last_used_at: chrono::DateTime<chrono::Utc> = row.try_get("last_used_at")?;
+            let expired = cutoff.is_some_and(|cutoff| last_used_at < cutoff);
+            if !expired && kept_bytes + size_bytes <= self.max_bytes {
+                kept_bytes += size_bytes;
+                continue;
+            }
+            let path: String = row.try_get("path")?;
+            if let Err(error) = tokio::fs::remove_file(&path).await {
+                if error.kind() != std::io::ErrorKind::NotFound {
+                    tracing::warn!(%error, path, "failed to delete cached attachment");
+                }
+            }
+            self.remove(&hash).await?;
+            evicted += 1;
+        }
+        Ok(evicted)
+    }
+
+    async fn touch(&self, hash: &str, source_url: Option<&str>) -> anyhow::Result<()> {
+        sqlx::query(
+            "UPDATE attachment_cache SET last_used_at = ?, source_url = COALESCE(?, source_url) WHERE hash = ?",
+        )
+        .bind(chrono::Utc::now())
+        .bind(source_url)
+        .bind(hash)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn remove(&self, hash: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM attachment_cache WHERE hash = ?")
+            .bind(hash)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+/// SHA-256 hex digest of attachment content.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(bytes);
+    format!("{:x}", hasher.finalize())
+}
+
+fn file_from_row(row: &sqlx::sqlite::SqliteRow) -> CachedFile {
+    CachedFile {
+        hash: row.try_get("hash").unwrap_or_default(),
+        path: PathBuf::from(row.try_get::<String, _>("path").unwrap_or_default()),
+        mime: row.try_get("mime").unwrap_or_default(),
+        size_bytes: row.try_get::<i64, _>("size_bytes").unwrap_or_default() as u64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[tokio::test]
+    async fn test_dedupes_by_content_and_evicts_least_recently_used() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = AttachmentCache::new(connect_in_memory().await, dir.path().to_path_buf(), 8, Duration::ZERO);
+
+        let first = cache.store("https://cdn/a?v=1", "text/plain", b"hello").await.unwrap();
+        let again = cache.store("https://cdn/a?v=2", "text/plain", b"hello").await.unwrap();
+        assert_eq!(first, again);
+        assert_eq!(first.hash, content_hash(b"hello"));
+        assert!(first.url().starts_with("file://"));
+        assert_eq!(std::fs::read(&first.path).unwrap(), b"hello");
+
+        assert_eq!(cache.lookup_source("https://cdn/a?v=2").await.unwrap(), Some(first.clone()));
+        assert_eq!(cache.lookup_source("https://cdn/missing").await.unwrap(), None);
+
+        let second = cache.store("https://cdn/b", "text/plain", b"world").await.unwrap();
+        assert_eq!(cache.evict().await.unwrap(), 1);
+        assert!(!first.path.exists());
+        assert_eq!(cache.lookup_source("https://cdn/a?v=2").await.unwrap(), None);
+        assert_eq!(cache.lookup_source("https://cdn/b").await.unwrap(), Some(second));
+    }
+}
@@ -0,0 +1,304 @@
+//! Chat attachments as OpenCode file parts.
+//!
+//! Attachments are downloaded and inlined as `data:` URLs, since platform CDN
+//! links (Discord's in particular) expire and need no auth OpenCode would have.
+//! With an `AttachmentCache`, they're stored on disk by content hash and
+//! passed as `file://` URLs instead.
+
+use crate::opencode::attachment_cache::{AttachmentCache, CachedFile};
+use crate::opencode::types::PartInput;
+use crate::Attachment;
+
+/// Which attachments may be passed to OpenCode.
+#[derive(Debug, Clone)]
+pub struct AttachmentPolicy {
+    /// Largest attachment accepted, in bytes.
+    pub max_bytes: u64,
+    /// Accepted MIME types. `type/*` matches a whole family (e.g. `image/*`).
+    pub allowed_mime_types: Vec<String>,
+}
+
+impl AttachmentPolicy {
+    pub fn allows(&self, mime: &str) -> bool {
+        self.allowed_mime_types.iter().any(|allowed| match allowed.strip_suffix('*') {
+            Some(prefix) => mime.starts_with(prefix),
+            None => allowed == mime,
+        })
+    }
+}
+
+/// File parts built from a message's attachments, plus a friendly message for
+/// each attachment that was left out.
+#[derive(Debug, Default)]
+pub struct AttachmentParts {
+    pub parts: Vec<PartInput>,
+    pub rejected: Vec<String>,
+}
+
+/// Download each allowed attachment and turn it into a `PartInput::File`.
+///
+/// Attachments are checked against the policy before downloading (using the
+/// platform-reported size, when there is one), against the response's
+/// `Content-Length`, and as the body streams in, so an oversized file is
+/// never held in memory whole. With a
+/// `cache`, an attachment already cached from the same URL isn't downloaded
+/// again, and a new one is cached; if caching fails it's inlined as usual.
+pub async fn attachment_parts(
+    http: &reqwest::Client,
+    attachments: &[Attachment],
+    policy: &AttachmentPolicy,
+    cache: Option<&AttachmentCache>,
+) -> AttachmentParts {
+    let mut result = AttachmentParts::default();
+
+    for attachment in attachments {
+        let mime = infer_mime(attachment);
+        if !policy.allows(&mime) {
+            result.rejected.push(format!(
+                "Couldn't pass `{}` to OpenCode: `{mime}` files aren't supported.",
+                attachment.filename
+            ));
+            continue;
+        }
+        if let Some(size) = attachment.size_bytes.filter(|size| *size > policy.max_bytes) {
+            result.rejected.push(too_large(&attachment.filename, size, policy.max_bytes));
+            continue;
+        }
+
+        if let Some(cache) = cache {
+            match cache.lookup_source(&attachment.url).await {
+                Ok(Some(cached)) => {
+                    tracing::debug!(filename = %attachment.filename, hash = %cached.hash, "reusing cached attachment");
+                    result.parts.push(cached_part(&cached, &attachment.filename));
+                    continue;
+                }
+                Ok(None) => {}
+                Err(error) => tracing::warn!(%error, "failed to look up cached attachment"),
+            }
+        }
+
+        let bytes = match download(http, &attachment.url, policy.max_bytes).await {
+            Ok(Download::Complete(bytes)) => bytes,
+            Ok(Download::TooLarge { size }) => {
+                result.rejected.push(match size {
+                    Some(size) => too_large(&attachment.filename, size, policy.max_bytes),
+                    None => format!(
+                        "Couldn't pass `{}` to OpenCode: it's over the {:.1} MB limit.",
+                        attachment.filename,
+                        policy.max_bytes as f64 / 1_048_576.0
+                    ),
+                });
+                continue;
+            }
+            Err(error) => {
+                tracing::warn!(%error, filename = %attachment.filename, "failed to download attachment");
+                result.rejected.push(format!(
+                    "Couldn't pass `{}` to OpenCode: the download failed.",
+                    attachment.filename
+                ));
+                continue;
+            }
+        };
+        if let Some(cache) = cache {
+            match cache.store(&attachment.url, &mime, &bytes).await {
+                Ok(cached) => {
+                    tracing::info!(
+                        filename = %attachment.filename,
+                        %mime,
+                        size = bytes.len(),
+                        hash = %cached.hash,
+                        "attaching cached file to OpenCode prompt"
+                    );
+                    result.parts.push(cached_part(&cached, &attachment.filename));
+                    continue;
+                }
+                Err(error) => tracing::warn!(%error, "failed to cache attachment, inlining it"),
+            }
+        }
+
+        let part = match PartInput::file_from_bytes_with_limit(
+            &bytes,
+            &mime,
+            Some(attachment.filename.clone()),
+            policy.max_bytes,
+        ) {
+            Ok(part) => part,
+            Err(error) => {
+                result.rejected.push(too_large(&attachment.filename, error.size, error.max_bytes));
+                continue;
+            }
+        };
+
+        tracing::info!(
+            filename = %attachment.filename,
+            %mime,
+            size = bytes.len(),
+            "attaching file to OpenCode prompt"
+        );
+        result.parts.push(part);
+    }
+
+    if let Some(cache) = cache {
+        if let Err(error) = cache.evict().await {
+            tracing::warn!(%error, "failed to evict cached attachments");
+        }
+    }
+
+    result
+}
+
+fn cached_part(cached: &CachedFile, filename: &str) -> PartInput {
+    PartInput::File {
+        mime: cached.mime.clone(),
+        url: cached.url(),
+        filename: Some(filename.to_string()),
+    }
+}
+
+/// The attachment's MIME type, falling back to a guess from the file
+/// extension when the platform reports none or a generic binary type.
+pub fn infer_mime(attachment: &Attachment) -> String {
+    let reported = attachment.mime_type.split(';').next().unwrap_or_default().trim();
+    if !reported.is_empty() && reported != "application/octet-stream" {
+        return reported.to_string();
+    }
+
+    let extension = attachment
+        .filename
+        .rsplit_once('.')
+        .map(|(_, extension)| extension.to_ascii_lowercase())
+        .unwrap_or_default();
+    let mime = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        "json" => "application/json",
+        "md" | "markdown" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "txt" | "log" | "rs" | "py" | "js" | "ts" | "tsx" | "go" | "toml" | "yaml" | "yml" | "sh" | "diff"
+        | "patch" => "text/plain",
+        _ => "application/octet-stream",
+    };
+    mime.to_string()
+}
+
+/// A finished download, or why it was cut short.
+#[derive(Debug, PartialEq, Eq)]
+enum Download {
+    Complete(Vec<u8>),
+    /// Over the limit. `size` is the declared `Content-Length`; `None` when
+    /// the body ran past the limit without declaring one.
+    TooLarge { size: Option<u64> },
+}
+
+/// Download `url`, giving up as soon as it's known to exceed `max_bytes`.
+async fn download(http: &reqwest::Client, url: &str, max_bytes: u64) -> anyhow::Result<Download> {
+    let mut response = http.get(url).send().await?.error_for_status()?;
+    if let Some(size) = response.content_length().filter(|size| *size > max_bytes) {
+        return Ok(Download::TooLarge { size: Some(size) });
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await? {
+        if (bytes.len() + chunk.len()) as u64 > max_bytes {
+            return Ok(Download::TooLarge { size: None });
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(Download::Complete(bytes))
+}
+
+fn too_large(filename: &str, size: u64, max_bytes: u64) -> String {
+    format!(
+        "Couldn't pass `{filename}` to OpenCode: it's {:.1} MB, over the {:.1} MB limit.",
+        size as f64 / 1_048_576.0,
+        max_bytes as f64 / 1_048_576.0
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attachment(filename: &str, mime_type: &str, size_bytes: Option<u64>) -> Attachment {
+        Attachment {
+            filename: filename.into(),
+            mime_type: mime_type.into(),
+            // Nothing listens here, so any attempted download fails.
+            url: "http://127.0.0.1:9/file".into(),
+            size_bytes,
+        }
+    }
+
+    fn policy() -> AttachmentPolicy {
+        AttachmentPolicy {
+            max_bytes: 1024,
+            allowed_mime_types: vec!["image/*".into(), "text/plain".into()],
+        }
+    }
+
+    #[test]
+    fn test_policy_and_mime_inference() {
+        let policy = policy();
+        assert!(policy.allows("image/png"));
+        assert!(policy.allows("text/plain"));
+        assert!(!policy.allows("text/html"));
+        assert!(!policy.allows("application/zip"));
+
+        assert_eq!(infer_mime(&attachment("a.png", "image/png", None)), "image/png");
+        assert_eq!(infer_mime(&attachment("main.rs", "", None)), "text/plain");
+        assert_eq!(infer_mime(&attachment("notes.txt", "text/plain; charset=utf-8", None)), "text/plain");
+        assert_eq!(infer_mime(&attachment("photo.JPG", "application/octet-stream", None)), "image/jpeg");
+        assert_eq!(infer_mime(&attachment("blob", "", None)), "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_and_oversized_attachments_are_rejected() {
+        let attachments = [
+            attachment("archive.zip", "application/zip", Some(10)),
+            attachment("huge.png", "image/png", Some(4096)),
+        ];
+
+        let result = attachment_parts(&reqwest::Client::new(), &attachments, &policy(), None).await;
+
+        assert!(result.parts.is_empty());
+        assert_eq!(result.rejected.len(), 2);
+        assert!(result.rejected[0].contains("`application/zip` files aren't supported"));
+        assert!(result.rejected[1].contains("over the"));
+    }
+
+    /// Serve one response with `headers` and a body of `body_len` bytes.
+    async fn serve_once(headers: &'static str, body_len: usize) -> String {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let _ = socket.write_all(format!("HTTP/1.1 200 OK\r\n{headers}\r\n").as_bytes()).await;
+            let _ = socket.write_all(&vec![b'x'; body_len]).await;
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_download_stops_at_the_limit() {
+        let http = reqwest::Client::new();
+
+        let url = serve_once("Content-Length: 512\r\nConnection: close\r\n", 512).await;
+        assert_eq!(download(&http, &url, 1024).await.unwrap(), Download::Complete(vec![b'x'; 512]));
+
+        // Refused from the declared length, before any of the body is read.
+        let url = serve_once("Content-Length: 4096\r\nConnection: close\r\n", 0).await;
+        assert_eq!(download(&http, &url, 1024).await.unwrap(), Download::TooLarge { size: Some(4096) });
+
+        // No declared length: cut off once the body passes the limit.
+        let url = serve_once("Connection: close\r\n", 4096).await;
+        assert_eq!(download(&http, &url, 1024).await.unwrap(), Download::TooLarge { size: None });
+    }
+}
@@ -0,0 +1,187 @@
+//! Sender attribution for prompts in shared channels.
+//!
+//! When several people talk to the same worker, the model can't tell them
+//! apart from the prompt text alone. A channel can opt into a
+//! `SenderAttribution` template such as `"[{sender_name}]: {content}"`,
+//! applied to each message as it's handed to the worker. Only the prompt
+//! is decorated; the message logged to the channel's history keeps its
+//! clean content.
+//!
+//! Templates are substituted in a single pass, so a sender name or message
+//! containing `{content}` is inserted literally rather than expanded. `{{`
+//! and `}}` write literal braces.
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    SenderName,
+    Content,
+}
+
+/// A parsed attribution template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SenderAttribution {
+    /// Source text, for display.
+    template: String,
+    segments: Vec<Segment>,
+}
+
+/// Why a template was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum TemplateError {
+    #[error("unknown placeholder {{{0}}}; use {{sender_name}} or {{content}}")]
+    UnknownPlaceholder(String),
+    #[error("unmatched '{0}'; write '{0}{0}' for a literal brace")]
+    UnmatchedBrace(char),
+    #[error("template must contain {{content}} exactly once")]
+    Content,
+}
+
+impl FromStr for SenderAttribution {
+    type Err = TemplateError;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(TemplateError::UnmatchedBrace('{')),
+                        }
+                    }
+                    let segment = match name.as_str() {
+                        "sender_name" => Segment::SenderName,
+                        "content" => Segment::Content,
+                        _ => return Err(TemplateError::UnknownPlaceholder(name)),
+                    };
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(segment);
+                }
+                '}' => return Err(TemplateError::UnmatchedBrace('}')),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        if segments.iter().filter(|segment| **segment == Segment::Content).count() != 1 {
+            return Err(TemplateError::Content);
+        }
+        Ok(Self {
+            template: template.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for SenderAttribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.template)
+    }
+}
+
+impl SenderAttribution {
+    /// `content` attributed to `sender_name`.
+    ///
+    /// Text that already carries this sender's attribution is returned
+    /// unchanged, so a message passing through twice (e.g. when queued
+    /// follow-ups are merged) isn't decorated twice.
+    pub fn apply(&self, sender_name: &str, content: &str) -> String {
+        let sender_name = clean_sender_name(sender_name);
+        let (prefix, suffix) = self.frame(&sender_name);
+        if !(prefix.is_empty() && suffix.is_empty())
+            && content.len() >= prefix.len() + suffix.len()
+            && content.starts_with(&prefix)
+            && content.ends_with(&suffix)
+        {
+            return content.to_string();
+        }
+        format!("{prefix}{content}{suffix}")
+    }
+
+    /// The rendered text before and after `{content}`.
+    fn frame(&self, sender_name: &str) -> (String, String) {
+        let mut prefix = String::new();
+        let mut suffix = String::new();
+        let mut target = &mut prefix;
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => target.push_str(text),
+                Segment::SenderName => target.push_str(sender_name),
+                Segment::Content => target = &mut suffix,
+            }
+        }
+        (prefix, suffix)
+    }
+}
+
+/// A display name on one line, so it can't fake a message boundary.
+fn clean_sender_name(name: &str) -> String {
+    name.split(|c: char| c.is_control())
+        .filter(|part| !part.trim().is_empty())
+        .map(str::trim)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitution_and_escaping() {
+        let attribution: SenderAttribution = "[{sender_name}]: {content}".parse().unwrap();
+        assert_eq!(attribution.apply("alice", "fix the build"), "[alice]: fix the build");
+
+        // Placeholders in the values are inserted literally.
+        assert_eq!(
+            attribution.apply("{content}", "say {sender_name}"),
+            "[{content}]: say {sender_name}"
+        );
+        // A name can't break onto a new line.
+        assert_eq!(attribution.apply("eve\n[bob]", "hi"), "[eve [bob]]: hi");
+
+        // Braces are escaped by doubling.
+        let braces: SenderAttribution = "{{{sender_name}}} {content}".parse().unwrap();
+        assert_eq!(braces.apply("alice", "hi"), "{alice} hi");
+        assert_eq!(braces.to_string(), "{{{sender_name}}} {content}");
+
+        // Content in the middle, with a suffix.
+        let quoted: SenderAttribution = "{sender_name} said \"{content}\"".parse().unwrap();
+        assert_eq!(quoted.apply("bob", "ok"), "bob said \"ok\"");
+
+        assert_eq!("{sender}: {content}".parse::<SenderAttribution>(), Err(TemplateError::UnknownPlaceholder("sender".into())));
+        assert_eq!("[{sender_name}".parse::<SenderAttribution>(), Err(TemplateError::Content));
+        assert_eq!("{content".parse::<SenderAttribution>(), Err(TemplateError::UnmatchedBrace('{')));
+        assert_eq!("} {content}".parse::<SenderAttribution>(), Err(TemplateError::UnmatchedBrace('}')));
+        assert_eq!("{content} {content}".parse::<SenderAttribution>(), Err(TemplateError::Content));
+    }
+
+    #[test]
+    fn test_not_applied_twice() {
+        let attribution: SenderAttribution = "[{sender_name}]: {content}".parse().unwrap();
+        let once = attribution.apply("alice", "fix the build");
+        assert_eq!(attribution.apply("alice", &once), once);
+        // Someone else quoting it is still attributed to them.
+        assert_eq!(attribution.apply("bob", &once), "[bob]: [alice]: fix the build");
+    }
+}
@@ -0,0 +1,185 @@
+//! Audit trail of OpenCode permission replies (SQLite).
+
+use crate::opencode::types::{PermissionReply, PermissionRequest};
+use crate::{ChannelId, WorkerId};
+
+use sqlx::{Row as _, SqlitePool};
+
+/// Who decided a permission reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplySource {
+    /// A user answered in the channel.
+    User,
+    /// Nobody answered before the timeout.
+    Timeout,
+    /// Approved without asking (`PermissionMode::Auto`).
+    Auto,
+    /// Covered by a stored "always" grant from an earlier session.
+    Grant,
+    /// Answered by a custom `EventHandler`.
+    Handler,
+}
+
+impl ReplySource {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::User => "user",
+            Self::Timeout => "timeout",
+            Self::Auto => "auto",
+            Self::Grant => "grant",
+            Self::Handler => "handler",
+        }
+    }
+}
+
+/// A persisted permission reply.
+#[derive(Debug, Clone)]
+pub struct PermissionAuditRecord {
+    pub id: String,
+    pub channel_id: Option<String>,
+    pub worker_id: String,
+    pub permission_id: String,
+    pub permission: Option<String>,
+    pub patterns: Vec<String>,
+    pub reply: String,
+    pub source: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Records every permission reply a worker sends.
+///
+/// `record` is fire-and-forget, same pattern as `ConversationLogger`.
+#[derive(Debug, Clone)]
+pub struct PermissionAuditLog {
+    pool: SqlitePool,
+}
+
+impl PermissionAuditLog {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Record a reply. Fire-and-forget.
+    pub fn record(
+        &self,
+        worker_id: WorkerId,
+        channel_id: Option<&ChannelId>,
+        request: &PermissionRequest,
+        reply: &PermissionReply,
+        source: ReplySource,
+    ) {
+        let log = self.clone();
+        let channel_id = channel_id.map(|id| id.to_string());
+        let request = request.clone();
+        let reply = reply.clone();
+
+        tokio::spawn(async move {
+            if let Err(error) = log
+                .insert(worker_id, channel_id.as_deref(), &request, &reply, source)
+                .await
+            {
+                tracing::warn!(%error, "failed to persist permission audit record");
+            }
+        });
+    }
+
+    async fn insert(
+        &self,
+        worker_id: WorkerId,
+        channel_id: Option<&str>,
+        request: &PermissionRequest,
+        reply: &PermissionReply,
+        source: ReplySource,
+    ) -> anyhow::Result<()> {
+        let reply = serde_json::to_value(reply)?;
+        sqlx::query(
+            "INSERT INTO opencode_permission_audit \
+             (id, channel_id, worker_id, permission_id, permission, patterns, reply, source) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(channel_id)
+        .bind(worker_id.to_string())
+        .bind(&request.id)
+        .bind(&request.permission)
+        .bind(serde_json::to_string(&request.patterns)?)
+        .bind(reply.as_str().unwrap_or_default())
+        .bind(source.as_str())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Load the most recent replies for a channel, newest first.
+    pub async fn load_recent(
+        &self,
+        channel_id: &ChannelId,
+        limit: i64,
+    ) -> anyhow::Result<Vec<PermissionAuditRecord>> {
+        let rows = sqlx::query(
+            "SELECT id, channel_id, worker_id, permission_id, permission, patterns, reply, source, created_at \
+             FROM opencode_permission_audit \
+             WHERE channel_id = ? \
+             ORDER BY created_at DESC, rowid DESC \
+             LIMIT ?",
+        )
+        .bind(channel_id.as_ref())
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| PermissionAuditRecord {
+                id: row.try_get("id").unwrap_or_default(),
+                channel_id: row.try_get("channel_id").ok(),
+                worker_id: row.try_get("worker_id").unwrap_or_default(),
+                permission_id: row.try_get("permission_id").unwrap_or_default(),
+                permission: row.try_get("permission").ok(),
+                patterns: row
+                    .try_get::<String, _>("patterns")
+                    .ok()
+                    .and_then(|patterns| serde_json::from_str(&patterns).ok())
+                    .unwrap_or_default(),
+                reply: row.try_get("reply").unwrap_or_default(),
+                source: row.try_get("source").unwrap_or_default(),
+                created_at: row
+                    .try_get("created_at")
+                    .unwrap_or_else(|_| chrono::Utc::now()),
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[tokio::test]
+    async fn test_record_and_load() {
+        let log = PermissionAuditLog::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let worker_id = uuid::Uuid::new_v4();
+        let request = PermissionRequest {
+            id: "per_1".into(),
+            session_id: "ses_1".into(),
+            permission: Some("bash".into()),
+            patterns: vec!["git push".into()],
+            always: Vec::new(),
+            metadata: Default::default(),
+        };
+
+        log.insert(worker_id, Some(channel_id.as_ref()), &request, &PermissionReply::Reject, ReplySource::Timeout)
+            .await
+            .unwrap();
+
+        let records = log.load_recent(&channel_id, 10).await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].permission_id, "per_1");
+        assert_eq!(records[0].patterns, ["git push"]);
+        assert_eq!(records[0].reply, "reject");
+        assert_eq!(records[0].source, "timeout");
+        assert_eq!(records[0].worker_id, worker_id.to_string());
+    }
+}
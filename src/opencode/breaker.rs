@@ -0,0 +1,306 @@
+//! Circuit breaker for OpenCode HTTP calls.
+//!
+//! When OpenCode keeps failing, every new prompt would otherwise wait out its
+//! own timeout and post its own error. After `failure_threshold` consecutive
+//! failures within `window`, the breaker opens: calls fail at once with
+//! `CircuitOpen`, and new prompts get a "temporarily unavailable" notice
+//! instead. Once `cooldown` has passed it half-opens and lets a single call
+//! through as a probe. A success closes it again; a failure reopens it for
+//! another cooldown. A probe dropped without a result (its call was
+//! cancelled) frees the slot for the next caller.
+//!
+//! Only transport errors and 5xx responses count as failures. A 4xx means
+//! the server is up and answering, so it counts as a success.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// When the breaker opens, and for how long.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BreakerConfig {
+    /// Consecutive failures that open the breaker. Zero turns it off.
+    pub failure_threshold: u32,
+    /// Failures older than this don't count toward the threshold.
+    pub window: Duration,
+    /// How long the breaker stays open before letting a probe through.
+    pub cooldown: Duration,
+}
+
+impl BreakerConfig {
+    pub fn disabled() -> Self {
+        Self {
+            failure_threshold: 0,
+            window: Duration::ZERO,
+            cooldown: Duration::ZERO,
+        }
+    }
+}
+
+/// The breaker's state, as reported by `!status` and metrics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakerState {
+    Closed,
+    /// Rejecting calls for another `retry_after`.
+    Open { retry_after: Duration },
+    /// Cooled down; the next call is a probe.
+    HalfOpen,
+}
+
+impl BreakerState {
+    /// 0 closed, 1 half-open, 2 open, for the metrics gauge.
+    pub fn gauge_value(&self) -> u8 {
+        match self {
+            Self::Closed => 0,
+            Self::HalfOpen => 1,
+            Self::Open { .. } => 2,
+        }
+    }
+}
+
+impl std::fmt::Display for BreakerState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Closed => f.write_str("closed"),
+            Self::Open { retry_after } => write!(f, "open (retrying in {}s)", retry_after.as_secs().max(1)),
+            Self::HalfOpen => f.write_str("half-open"),
+        }
+    }
+}
+
+/// A call refused because the breaker is open.
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("OpenCode is temporarily unavailable after repeated failures; retrying in {}s", retry_after.as_secs().max(1))]
+pub struct CircuitOpen {
+    pub retry_after: Duration,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    /// Consecutive failures still inside the window, oldest first.
+    failures: VecDeque<Instant>,
+    /// Set while open: when the cooldown ends.
+    open_until: Option<Instant>,
+    /// The half-open probe in flight, by ID.
+    probing: Option<u64>,
+    /// ID for the next probe.
+    next_probe: u64,
+}
+
+/// Permission to make a call, returned by `CircuitBreaker::check`. Hold it
+/// until the call is recorded: if it was the half-open probe and is dropped
+/// first, the probe slot is released so a later call can probe instead.
+#[must_use = "dropping the permit releases a half-open probe"]
+#[derive(Debug)]
+pub struct Permit<'a> {
+    /// Set when this call is the half-open probe.
+    probe: Option<(&'a CircuitBreaker, u64)>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        if let Some((breaker, id)) = self.probe {
+            let mut inner = breaker.lock();
+            if inner.probing == Some(id) {
+                inner.probing = None;
+                tracing::debug!("OpenCode circuit breaker probe abandoned");
+            }
+        }
+    }
+}
+
+/// Shared by every server in a pool, since they fail together when OpenCode
+/// itself (or the model provider behind it) is down.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: BreakerConfig,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: BreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config.failure_threshold > 0
+    }
+
+    /// Ask to make a call. While half-open, only the first caller gets
+    /// through, as the probe, until its permit is dropped.
+    pub fn check(&self, now: Instant) -> Result<Permit<'_>, CircuitOpen> {
+        let permit = Permit { probe: None };
+        if !self.is_enabled() {
+            return Ok(permit);
+        }
+        let mut inner = self.lock();
+        let Some(open_until) = inner.open_until else {
+            return Ok(permit);
+        };
+        if now < open_until {
+            return Err(CircuitOpen { retry_after: open_until - now });
+        }
+        if inner.probing.is_some() {
+            return Err(CircuitOpen { retry_after: self.config.cooldown });
+        }
+        let id = inner.next_probe;
+        inner.next_probe += 1;
+        inner.probing = Some(id);
+        tracing::info!("OpenCode circuit breaker half-open, probing");
+        Ok(Permit { probe: Some((self, id)) })
+    }
+
+    /// A call got an answer. Closes the breaker.
+    pub fn record_success(&self) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.open_until.is_some() {
+            tracing::info!("OpenCode circuit breaker closed");
+            crate::opencode::metrics::global().record_breaker_state(BreakerState::Closed);
+        }
+        inner.failures.clear();
+        inner.open_until = None;
+        inner.probing = None;
+    }
+
+    /// A call failed. Opens the breaker once the threshold is reached, or
+    /// right away if it was a half-open probe.
+    pub fn record_failure(&self, now: Instant) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut inner = self.lock();
+        if inner.open_until.is_some() {
+            if inner.probing.is_some() {
+                inner.probing = None;
+                inner.open_until = Some(now + self.config.cooldown);
+                tracing::warn!("OpenCode circuit breaker probe failed, reopening");
+            }
+            return;
+        }
+
+        while inner.failures.front().is_some_and(|failed| now.duration_since(*failed) >= self.config.window) {
+            inner.failures.pop_front();
+        }
+        inner.failures.push_back(now);
+        if inner.failures.len() >= self.config.failure_threshold as usize {
+            inner.failures.clear();
+            inner.open_until = Some(now + self.config.cooldown);
+            tracing::warn!(
+                threshold = self.config.failure_threshold,
+                cooldown_secs = self.config.cooldown.as_secs(),
+                "OpenCode circuit breaker opened"
+            );
+            let metrics = crate::opencode::metrics::global();
+            metrics.record_breaker_trip();
+            metrics.record_breaker_state(BreakerState::Open { retry_after: self.config.cooldown });
+        }
+    }
+
+    pub fn state(&self, now: Instant) -> BreakerState {
+        let inner = self.lock();
+        match inner.open_until {
+            None => BreakerState::Closed,
+            Some(open_until) if now < open_until => BreakerState::Open { retry_after: open_until - now },
+            Some(_) => BreakerState::HalfOpen,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Inner> {
+        self.inner.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(BreakerConfig::disabled())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_half_opens_and_closes() {
+        let breaker = CircuitBreaker::new(BreakerConfig {
+            failure_threshold: 3,
+            window: Duration::from_secs(60),
+            cooldown: Duration::from_secs(30),
+        });
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Failures outside the window, or broken up by a success, don't add up.
+        breaker.record_failure(at(0));
+        breaker.record_failure(at(1));
+        breaker.record_success();
+        breaker.record_failure(at(2));
+        breaker.record_failure(at(3));
+        breaker.record_failure(at(70));
+        assert_eq!(breaker.state(at(70)), BreakerState::Closed);
+        assert!(breaker.check(at(70)).is_ok());
+
+        breaker.record_failure(at(71));
+        breaker.record_failure(at(72));
+        assert_eq!(breaker.state(at(72)), BreakerState::Open { retry_after: Duration::from_secs(30) });
+        assert_eq!(breaker.check(at(92)).unwrap_err().retry_after, Duration::from_secs(10));
+
+        // Cooled down: one probe goes through, and its failure reopens.
+        assert_eq!(breaker.state(at(102)), BreakerState::HalfOpen);
+        let probe = breaker.check(at(102)).unwrap();
+        assert!(breaker.check(at(102)).is_err());
+        breaker.record_failure(at(103));
+        drop(probe);
+        assert!(matches!(breaker.state(at(103)), BreakerState::Open { .. }));
+
+        let probe = breaker.check(at(133)).unwrap();
+        breaker.record_success();
+        drop(probe);
+        assert_eq!(breaker.state(at(133)), BreakerState::Closed);
+        assert!(breaker.check(at(133)).is_ok());
+
+        let disabled = CircuitBreaker::default();
+        for secs in 0..10 {
+            disabled.record_failure(at(secs));
+        }
+        assert!(disabled.check(at(10)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_probe_releases_the_slot() {
+        let breaker = std::sync::Arc::new(CircuitBreaker::new(BreakerConfig {
+            failure_threshold: 1,
+            window: Duration::from_secs(60),
+            cooldown: Duration::ZERO,
+        }));
+        breaker.record_failure(Instant::now());
+
+        // A probe whose call never finishes, cancelled mid-flight.
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let probe = tokio::spawn({
+            let breaker = breaker.clone();
+            async move {
+                let _permit = breaker.check(Instant::now()).unwrap();
+                let _ = started_tx.send(());
+                std::future::pending::<()>().await;
+            }
+        });
+        started_rx.await.unwrap();
+        assert!(breaker.check(Instant::now()).is_err());
+        probe.abort();
+        assert!(probe.await.unwrap_err().is_cancelled());
+
+        // The next call probes instead, and can still close the breaker.
+        let permit = breaker.check(Instant::now()).unwrap();
+        breaker.record_success();
+        drop(permit);
+        assert_eq!(breaker.state(Instant::now()), BreakerState::Closed);
+    }
+}
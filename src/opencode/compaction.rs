@@ -0,0 +1,444 @@
+//! Channel history compaction through OpenCode.
+//!
+//! Used when a prompt overflows the model's context window, and by the
+//! `CompactionScheduler` once enough turns pile up: the channel's uncompacted
+//! turns are summarized in a throwaway session, the summary is persisted, and
+//! the raw turns are archived to disk.
+//!
+//! Summaries are checked before they're saved. One that's too short, parrots
+//! the prompt back, or reads as a refusal would replace real history with
+//! noise, so it's rejected and the raw turns stay in the window instead.
+
+use crate::conversation::history::ConversationLogger;
+use crate::opencode::prompt::build_compaction_prompt;
+use crate::opencode::server::OpenCodeServer;
+use crate::opencode::types::{Part, PartInput};
+use crate::ChannelId;
+
+use anyhow::{Context as _, bail};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Backoff after the first failed scheduled compaction. Doubles per failure.
+const RETRY_BASE: Duration = Duration::from_secs(30);
+/// Longest wait between scheduled compaction attempts after failures.
+const RETRY_MAX: Duration = Duration::from_secs(30 * 60);
+
+/// Openings of replies where the model declined instead of summarizing.
+const REFUSAL_OPENINGS: &[&str] = &["i'm sorry", "i am sorry", "i can't", "i cannot", "i'm unable", "i am unable", "as an ai"];
+
+/// Transcript lines shorter than this aren't counted when checking whether a
+/// summary copies the conversation; short lines repeat by coincidence.
+const MIN_COPIED_LINE_CHARS: usize = 20;
+
+/// Thresholds a compaction summary has to meet to be saved.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SummaryLimits {
+    /// Shortest acceptable summary, in characters.
+    pub min_chars: usize,
+    /// Largest fraction of the transcript's lines a summary may copy
+    /// verbatim before it counts as an echo rather than a summary.
+    pub max_copied: f64,
+}
+
+impl Default for SummaryLimits {
+    fn default() -> Self {
+        Self {
+            min_chars: 40,
+            max_copied: 0.5,
+        }
+    }
+}
+
+impl SummaryLimits {
+    /// Check the thresholds make sense.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if !(0.0..=1.0).contains(&self.max_copied) {
+            bail!("compaction_summary_max_copied must be between 0.0 and 1.0, got {}", self.max_copied);
+        }
+        Ok(())
+    }
+}
+
+/// Why a compaction summary wasn't saved.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SummaryRejection {
+    #[error("summary is {len} characters, under the minimum of {min}")]
+    TooShort { len: usize, min: usize },
+    #[error("summary repeats the compaction prompt")]
+    EchoesPrompt,
+    #[error("summary copies {copied} of {lines} transcript lines verbatim")]
+    CopiesTranscript { copied: usize, lines: usize },
+    #[error("summary is no shorter than the prompt it summarizes")]
+    NotShorter,
+    #[error("summary reads as a refusal")]
+    Refusal,
+}
+
+/// Check a summary against the compaction prompt it answers.
+pub fn validate_summary(summary: &str, prompt: &str, limits: &SummaryLimits) -> Result<(), SummaryRejection> {
+    let summary = summary.trim();
+    let len = summary.chars().count();
+    if len < limits.min_chars {
+        return Err(SummaryRejection::TooShort { len, min: limits.min_chars });
+    }
+
+    let lowered = summary.to_lowercase();
+    if REFUSAL_OPENINGS.iter().any(|opening| lowered.starts_with(opening)) {
+        return Err(SummaryRejection::Refusal);
+    }
+
+    // The instructions' first sentence, or the section headings, only show
+    // up in a reply that repeats the prompt.
+    let instructions = prompt.split_once('.').map_or(prompt, |(first, _)| first).trim();
+    if (!instructions.is_empty() && summary.contains(instructions))
+        || summary.contains("## Conversation")
+        || summary.contains("## Previous summary")
+    {
+        return Err(SummaryRejection::EchoesPrompt);
+    }
+
+    let transcript: Vec<&str> = prompt
+        .split_once("## Conversation\n")
+        .map_or("", |(_, transcript)| transcript)
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.chars().count() >= MIN_COPIED_LINE_CHARS)
+        .collect();
+    let copied = transcript.iter().filter(|line| summary.contains(**line)).count();
+    if transcript.len() >= 2 && copied as f64 / transcript.len() as f64 > limits.max_copied {
+        return Err(SummaryRejection::CopiesTranscript { copied, lines: transcript.len() });
+    }
+
+    if len >= prompt.chars().count() {
+        return Err(SummaryRejection::NotShorter);
+    }
+    Ok(())
+}
+
+/// Result of compacting a channel's history.
+#[derive(Debug, Clone)]
+pub struct ChannelCompaction {
+    /// The new rolling summary, already saved as the latest compaction summary.
+    pub summary: String,
+    /// How many messages the summary covers.
+    pub turns_covered: usize,
+    /// Where the raw transcript of those messages was written.
+    pub archive_path: PathBuf,
+}
+
+/// Summarize every message since the channel's last compaction, save the
+/// summary, and archive the raw transcript.
+///
+/// The summary is produced in a fresh session so it doesn't inherit the
+/// overflowing context, deleted once it has replied. It's saved as covering only the messages it was built
+/// from; anything logged meanwhile waits for the next compaction. Errors if
+/// there is nothing to compact or the summary fails `validate_summary`, in
+/// which case nothing is saved or archived.
+pub async fn compact_channel(
+    server: &OpenCodeServer,
+    logger: &ConversationLogger,
+    channel_id: &ChannelId,
+    archives_dir: &Path,
+    limits: &SummaryLimits,
+) -> anyhow::Result<ChannelCompaction> {
+    let messages = logger.load_since_last_compaction(channel_id).await?;
+    let prior_summary = logger
+        .load_compaction_summaries(channel_id)
+        .await?
+        .into_iter()
+        .last()
+        .map(|summary| summary.summary);
+    let request = build_compaction_prompt(&messages, prior_summary.as_deref())?;
+
+    let session = server
+        .create_session(Some(format!("spacebot-compaction-{channel_id}")))
+        .await?;
+    let response = server.send_prompt(&session.id, &request).await;
+    if let Err(error) = server.delete_session(&session.id).await {
+        tracing::warn!(%channel_id, session_id = %session.id, %error, "failed to delete compaction session");
+    }
+    let summary = response_text(&response?);
+    if summary.is_empty() {
+        bail!("compaction prompt returned no text");
+    }
+    let prompt = request
+        .parts
+        .iter()
+        .filter_map(|part| match part {
+            PartInput::Text { text, .. } => Some(text.as_str()),
+            PartInput::File { .. } => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if let Err(rejection) = validate_summary(&summary, &prompt, limits) {
+        tracing::warn!(
+            %channel_id,
+            %rejection,
+            turns = messages.len(),
+            "rejected compaction summary, keeping the raw turns"
+        );
+        return Err(rejection).context("compaction summary rejected");
+    }
+
+    logger
+        .save_compaction_summary_through(channel_id, &summary, &messages)
+        .await?;
+    let archive_path = logger
+        .archive_transcript(channel_id, &messages, archives_dir)
+        .await
+        .context("summary saved, but archiving the transcript failed")?;
+
+    Ok(ChannelCompaction {
+        summary,
+        turns_covered: messages.len(),
+        archive_path,
+    })
+}
+
+/// Compacts a channel's history in the background once the turns since its
+/// last compaction reach a threshold.
+///
+/// Shared by every worker in a channel. At most one compaction runs per
+/// channel at a time; turns that finish meanwhile are left for the next
+/// check. A failed compaction saves no summary, so its turns stay in the
+/// window, and the channel backs off before trying again.
+#[derive(Debug)]
+pub struct CompactionScheduler {
+    threshold: usize,
+    archives_dir: PathBuf,
+    summary_limits: SummaryLimits,
+    channels: Mutex<HashMap<ChannelId, ScheduleState>>,
+}
+
+#[derive(Debug, Default)]
+struct ScheduleState {
+    running: bool,
+    failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl CompactionScheduler {
+    /// Compact once at least `threshold` messages have been logged since the
+    /// last compaction. Transcripts are archived to `archives_dir`.
+    pub fn new(threshold: usize, archives_dir: PathBuf) -> Self {
+        Self {
+            threshold,
+            archives_dir,
+            summary_limits: SummaryLimits::default(),
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Check summaries against `limits` instead of the defaults.
+    pub fn with_summary_limits(mut self, limits: SummaryLimits) -> Self {
+        self.summary_limits = limits;
+        self
+    }
+
+    /// Check the channel after an assistant turn and compact it in the
+    /// background if it's due. Returns immediately.
+    ///
+    /// `server` should be a `OpenCodeServer::handle`, so the compaction
+    /// prompt doesn't hold the pool's lock while the model runs.
+    pub fn after_turn(
+        self: &Arc<Self>,
+        server: OpenCodeServer,
+        logger: ConversationLogger,
+        channel_id: ChannelId,
+    ) {
+        if !self.try_begin(&channel_id) {
+            return;
+        }
+
+        let scheduler = self.clone();
+        tokio::spawn(async move {
+            let result = scheduler.compact_if_due(&server, &logger, &channel_id).await;
+            scheduler.finish(&channel_id, &result);
+        });
+    }
+
+    async fn compact_if_due(
+        &self,
+        server: &OpenCodeServer,
+        logger: &ConversationLogger,
+        channel_id: &ChannelId,
+    ) -> anyhow::Result<Option<ChannelCompaction>> {
+        let turns = logger.turns_since_last_compaction(channel_id).await?;
+        if turns < self.threshold {
+            return Ok(None);
+        }
+
+        tracing::info!(%channel_id, turns, threshold = self.threshold, "compacting channel history");
+        compact_channel(server, logger, channel_id, &self.archives_dir, &self.summary_limits)
+            .await
+            .map(Some)
+    }
+
+    /// Claim the channel, unless a compaction is already running for it or
+    /// it's backing off after a failure.
+    fn try_begin(&self, channel_id: &ChannelId) -> bool {
+        let mut channels = self.channels.lock().expect("compaction schedule lock poisoned");
+        let state = channels.entry(channel_id.clone()).or_default();
+        if state.running || state.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+            return false;
+        }
+        state.running = true;
+        true
+    }
+
+    fn finish(&self, channel_id: &ChannelId, result: &anyhow::Result<Option<ChannelCompaction>>) {
+        let mut channels = self.channels.lock().expect("compaction schedule lock poisoned");
+        let state = channels.entry(channel_id.clone()).or_default();
+        state.running = false;
+
+        match result {
+            Ok(compaction) => {
+                if let Some(compaction) = compaction {
+                    tracing::info!(
+                        %channel_id,
+                        turns_covered = compaction.turns_covered,
+                        archive = %compaction.archive_path.display(),
+                        "scheduled compaction finished"
+                    );
+                }
+                state.failures = 0;
+                state.retry_at = None;
+            }
+            Err(error) => {
+                state.failures += 1;
+                let backoff = retry_backoff(state.failures);
+                state.retry_at = Some(Instant::now() + backoff);
+                tracing::warn!(
+                    %channel_id,
+                    %error,
+                    failures = state.failures,
+                    retry_in_secs = backoff.as_secs(),
+                    "scheduled compaction failed, turns stay uncompacted"
+                );
+            }
+        }
+    }
+}
+
+/// Wait after `failures` consecutive failed compactions.
+fn retry_backoff(failures: u32) -> Duration {
+    RETRY_BASE
+        .saturating_mul(2u32.saturating_pow(failures.saturating_sub(1)))
+        .min(RETRY_MAX)
+}
+
+/// Concatenated text parts of a blocking `send_prompt` response
+/// (`{ "info": {...}, "parts": [...] }`).
+pub(crate) fn response_text(response: &serde_json::Value) -> String {
+    let parts: Vec<Part> = response
+        .get("parts")
+        .cloned()
+        .and_then(|parts| serde_json::from_value(parts).ok())
+        .unwrap_or_default();
+    parts
+        .iter()
+        .filter_map(|part| match part {
+            Part::Text { text, .. } => Some(text.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_text_joins_text_parts() {
+        let response = serde_json::json!({
+            "info": { "id": "msg_1", "role": "assistant" },
+            "parts": [
+                { "type": "step-start", "id": "prt_1" },
+                { "type": "text", "id": "prt_2", "text": "alice is debugging CI." },
+                { "type": "reasoning", "id": "prt_3", "text": "thinking" },
+                { "type": "text", "id": "prt_4", "text": "bob owns the deploy.\n" },
+            ]
+        });
+        assert_eq!(response_text(&response), "alice is debugging CI.\nbob owns the deploy.");
+        assert_eq!(response_text(&serde_json::json!({})), "");
+    }
+
+    #[test]
+    fn test_validate_summary() {
+        let messages = [
+            ("alice", "the deploy is failing on the migration step"),
+            ("bob", "I'll roll back the schema change tonight"),
+            ("alice", "thanks, ping me when the rollback is done"),
+        ];
+        let mut prompt = String::from("Summarize the conversation below. Reply with the summary text only.\n\n## Conversation\n");
+        for (name, content) in messages {
+            prompt.push_str(&format!("{name} (user): {content}\n"));
+        }
+        let limits = SummaryLimits::default();
+
+        let good = "The deploy fails at the migration step; bob will roll back the schema change tonight and tell alice.";
+        assert_eq!(validate_summary(good, &prompt, &limits), Ok(()));
+
+        assert_eq!(
+            validate_summary("  Deploy broken.  ", &prompt, &limits),
+            Err(SummaryRejection::TooShort { len: 14, min: 40 })
+        );
+        assert_eq!(
+            validate_summary("I'm sorry, but I can't summarize a conversation I haven't seen.", &prompt, &limits),
+            Err(SummaryRejection::Refusal)
+        );
+        assert_eq!(
+            validate_summary(&format!("Summarize the conversation below: {good}"), &prompt, &limits),
+            Err(SummaryRejection::EchoesPrompt)
+        );
+        let copied = "alice (user): the deploy is failing on the migration step\nbob (user): I'll roll back the schema change tonight";
+        assert_eq!(
+            validate_summary(copied, &prompt, &limits),
+            Err(SummaryRejection::CopiesTranscript { copied: 2, lines: 3 })
+        );
+        // Quoting one line is fine.
+        let quoting = format!("{good} Alice's words: \"thanks, ping me when the rollback is done\"");
+        assert_eq!(validate_summary(&quoting, &prompt, &limits), Ok(()));
+        assert_eq!(
+            validate_summary(&good.repeat(4), &prompt, &limits),
+            Err(SummaryRejection::NotShorter)
+        );
+
+        let lenient = SummaryLimits { min_chars: 10, max_copied: 1.0 };
+        assert_eq!(validate_summary("Deploy broken.", &prompt, &lenient), Ok(()));
+        assert_eq!(validate_summary(copied, &prompt, &lenient), Ok(()));
+    }
+
+    #[tokio::test]
+    async fn test_scheduler_debounces_and_backs_off() {
+        let scheduler = CompactionScheduler::new(10, PathBuf::from("/tmp"));
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        assert!(scheduler.try_begin(&channel_id));
+        // A second turn while the first check is running is skipped.
+        assert!(!scheduler.try_begin(&channel_id));
+        assert!(scheduler.try_begin(&ChannelId::new("discord:1:3").unwrap()));
+
+        scheduler.finish(&channel_id, &Err(anyhow::anyhow!("model unavailable")));
+        assert!(!scheduler.try_begin(&channel_id), "backing off after a failure");
+
+        scheduler.channels.lock().unwrap().get_mut(&channel_id).unwrap().retry_at = Some(Instant::now());
+        assert!(scheduler.try_begin(&channel_id));
+        scheduler.finish(&channel_id, &Ok(None));
+        assert!(scheduler.try_begin(&channel_id));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(1), Duration::from_secs(30));
+        assert_eq!(retry_backoff(3), Duration::from_secs(120));
+        assert_eq!(retry_backoff(40), RETRY_MAX);
+    }
+}
@@ -0,0 +1,145 @@
+//! Per-sender flood guard.
+//!
+//! `FloodGuard` counts each sender's messages over a sliding window, across
+//! every channel of an agent, so one user spamming several channels at once
+//! is caught too. Messages over the limit are dropped before the channel does
+//! anything with them. The first dropped message gets a cooldown notice; the
+//! rest are dropped silently until the window has passed. Dropped messages
+//! don't count toward the window, so a sender is let back in as soon as their
+//! earlier messages age out.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Senders tracked before stale entries are swept out.
+const SWEEP_THRESHOLD: usize = 1024;
+
+/// How many messages a sender may send per window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodLimit {
+    /// Zero turns the guard off.
+    pub max_messages: usize,
+    pub window: Duration,
+}
+
+/// What to do with a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodVerdict {
+    Allow,
+    /// Drop the message. `notify` is set on the first drop of a cooldown.
+    Drop { notify: bool, retry_after: Duration },
+}
+
+#[derive(Debug, Default)]
+struct SenderWindow {
+    /// Accepted messages still inside the window, oldest first.
+    sent: VecDeque<Instant>,
+    /// When the last cooldown notice's cooldown ends.
+    notified_until: Option<Instant>,
+}
+
+impl SenderWindow {
+    fn prune(&mut self, now: Instant, window: Duration) {
+        while self.sent.front().is_some_and(|sent| now.duration_since(*sent) >= window) {
+            self.sent.pop_front();
+        }
+        if self.notified_until.is_some_and(|until| now >= until) {
+            self.notified_until = None;
+        }
+    }
+
+    fn is_stale(&self) -> bool {
+        self.sent.is_empty() && self.notified_until.is_none()
+    }
+}
+
+/// Sliding-window message counts, keyed by sender id.
+#[derive(Debug, Default)]
+pub struct FloodGuard {
+    senders: Mutex<HashMap<String, SenderWindow>>,
+}
+
+impl FloodGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count a message from `sender_id` against `limit`.
+    pub fn check(&self, sender_id: &str, limit: FloodLimit, now: Instant) -> FloodVerdict {
+        if limit.max_messages == 0 {
+            return FloodVerdict::Allow;
+        }
+
+        let mut senders = self.senders.lock().unwrap_or_else(|e| e.into_inner());
+        if senders.len() >= SWEEP_THRESHOLD {
+            senders.retain(|_, sender| {
+                sender.prune(now, limit.window);
+                !sender.is_stale()
+            });
+        }
+
+        let sender = senders.entry(sender_id.to_string()).or_default();
+        sender.prune(now, limit.window);
+        if sender.sent.len() < limit.max_messages {
+            sender.sent.push_back(now);
+            return FloodVerdict::Allow;
+        }
+
+        let retry_after = sender
+            .sent
+            .front()
+            .map(|oldest| limit.window.saturating_sub(now.duration_since(*oldest)))
+            .unwrap_or_default();
+        let notify = sender.notified_until.is_none();
+        if notify {
+            sender.notified_until = Some(now + limit.window);
+        }
+        FloodVerdict::Drop { notify, retry_after }
+    }
+
+    /// Senders currently tracked.
+    pub fn len(&self) -> usize {
+        self.senders.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flood_guard() {
+        let guard = FloodGuard::new();
+        let limit = FloodLimit { max_messages: 2, window: Duration::from_secs(10) };
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        assert_eq!(guard.check("alice", limit, at(0)), FloodVerdict::Allow);
+        assert_eq!(guard.check("alice", limit, at(1)), FloodVerdict::Allow);
+        assert_eq!(guard.check("bob", limit, at(1)), FloodVerdict::Allow);
+        assert_eq!(
+            guard.check("alice", limit, at(2)),
+            FloodVerdict::Drop { notify: true, retry_after: Duration::from_secs(8) }
+        );
+        assert_eq!(
+            guard.check("alice", limit, at(3)),
+            FloodVerdict::Drop { notify: false, retry_after: Duration::from_secs(7) }
+        );
+
+        // The first message aged out, so one more fits.
+        assert_eq!(guard.check("alice", limit, at(10)), FloodVerdict::Allow);
+        assert!(matches!(guard.check("alice", limit, at(10)), FloodVerdict::Drop { notify: false, .. }));
+        // Once the cooldown is over, the next drop gets a new notice.
+        assert_eq!(guard.check("alice", limit, at(12)), FloodVerdict::Allow);
+        assert!(matches!(guard.check("alice", limit, at(12)), FloodVerdict::Drop { notify: true, .. }));
+
+        let off = FloodLimit { max_messages: 0, ..limit };
+        assert_eq!(guard.check("alice", off, at(12)), FloodVerdict::Allow);
+    }
+}
@@ -0,0 +1,270 @@
+//! Forked channel sessions (SQLite).
+//!
+//! A fork branches a channel's conversation at the current point, so an
+//! alternative can be explored without losing the main thread. The fork is
+//! an OpenCode child session (`parent_id` set to the channel's session),
+//! mapped to a sub-channel `<channel>:fork-<id>` with its own row in
+//! `channel_sessions`. The parent's live history is copied to the
+//! sub-channel, so prompts built for the fork start from the same context.
+//!
+//! A platform adapter routes a thread (or anything else) to a fork by using
+//! its sub-channel ID. Forks are abandoned explicitly, or reaped like any
+//! other idle channel session; `prune_reaped` then drops the leftover rows.
+
+use crate::conversation::channels::ChannelStore;
+use crate::opencode::server::OpenCodeServer;
+use crate::ChannelId;
+
+use sqlx::{Row as _, SqlitePool};
+
+/// A `!fork` or `!forks` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ForkCommand {
+    /// `!fork`: fork the channel's session.
+    Create,
+    /// `!forks`: list the channel's forks.
+    List,
+    /// `!fork abandon <sub-channel>`
+    Abandon(String),
+}
+
+impl ForkCommand {
+    /// Parse a fork command. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text == "!forks" {
+            return Some(Self::List);
+        }
+        let rest = text.strip_prefix("!fork")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let mut words = rest.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (None, _, _) => Some(Self::Create),
+            (Some("abandon"), Some(fork_channel_id), None) => Some(Self::Abandon(fork_channel_id.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// A fork of a channel's session.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionFork {
+    /// The sub-channel the fork's session is mapped to.
+    pub fork_channel_id: String,
+    pub parent_channel_id: String,
+    pub parent_session_id: String,
+    pub session_id: String,
+}
+
+/// Creates, lists, and cleans up session forks.
+#[derive(Debug, Clone)]
+pub struct SessionForks {
+    pool: SqlitePool,
+    store: ChannelStore,
+}
+
+impl SessionForks {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            store: ChannelStore::new(pool.clone()),
+            pool,
+        }
+    }
+
+    /// Fork `from_session_id`, the session of `channel_id`, into a new child
+    /// session mapped to a fresh sub-channel.
+    pub async fn fork_session(
+        &self,
+        server: &OpenCodeServer,
+        channel_id: &ChannelId,
+        from_session_id: &str,
+    ) -> anyhow::Result<SessionFork> {
+        let title = Some(format!("Fork of {from_session_id}"));
+        let session = server.create_child_session(from_session_id, title).await?;
+        let fork = self.record_fork(channel_id, from_session_id, &session.id).await?;
+        tracing::info!(
+            %channel_id,
+            fork_channel_id = %fork.fork_channel_id,
+            session_id = %fork.session_id,
+            "forked OpenCode session"
+        );
+        Ok(fork)
+    }
+
+    /// Map an already created child session to a new sub-channel, copy the
+    /// parent's live history to it, and record the fork.
+    pub async fn record_fork(
+        &self,
+        channel_id: &ChannelId,
+        parent_session_id: &str,
+        session_id: &str,
+    ) -> anyhow::Result<SessionFork> {
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let fork = SessionFork {
+            fork_channel_id: format!("{channel_id}:fork-{}", &suffix[..8]),
+            parent_channel_id: channel_id.to_string(),
+            parent_session_id: parent_session_id.to_string(),
+            session_id: session_id.to_string(),
+        };
+
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO conversation_messages \
+                 (id, channel_id, role, sender_name, sender_id, content, metadata, created_at, \
+                  token_count, is_synthetic, is_hidden) \
+             SELECT lower(hex(randomblob(16))), ?, role, sender_name, sender_id, content, metadata, created_at, \
+                  token_count, is_synthetic, is_hidden \
+             FROM conversation_messages \
+             WHERE channel_id = ? AND cleared_at IS NULL AND superseded_at IS NULL \
+             ORDER BY rowid",
+        )
+        .bind(&fork.fork_channel_id)
+        .bind(&fork.parent_channel_id)
+        .execute(&mut *transaction)
+        .await?;
+        sqlx::query(
+            "INSERT INTO session_forks (fork_channel_id, parent_channel_id, parent_session_id, session_id) \
+             VALUES (?, ?, ?, ?)",
+        )
+        .bind(&fork.fork_channel_id)
+        .bind(&fork.parent_channel_id)
+        .bind(&fork.parent_session_id)
+        .bind(&fork.session_id)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        self.store.set_active_session(&fork.fork_channel_id, &fork.session_id).await?;
+        Ok(fork)
+    }
+
+    /// A channel's forks, oldest first.
+    pub async fn list(&self, parent_channel_id: &str) -> anyhow::Result<Vec<SessionFork>> {
+        let rows = sqlx::query(
+            "SELECT fork_channel_id, parent_channel_id, parent_session_id, session_id \
+             FROM session_forks WHERE parent_channel_id = ? ORDER BY created_at, rowid",
+        )
+        .bind(parent_channel_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.iter().map(fork_from_row).collect())
+    }
+
+    /// The fork mapped to a sub-channel, if it is one.
+    pub async fn get(&self, fork_channel_id: &str) -> anyhow::Result<Option<SessionFork>> {
+        let row = sqlx::query(
+            "SELECT fork_channel_id, parent_channel_id, parent_session_id, session_id \
+             FROM session_forks WHERE fork_channel_id = ?",
+        )
+        .bind(fork_channel_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.as_ref().map(fork_from_row))
+    }
+
+    /// Forget a fork and its sub-channel's session mapping. The copied
+    /// history stays, like any other channel's. Returns the fork, so the
+    /// caller can delete its session from the server.
+    pub async fn abandon(&self, fork_channel_id: &str) -> anyhow::Result<Option<SessionFork>> {
+        let Some(fork) = self.get(fork_channel_id).await? else {
+            return Ok(None);
+        };
+        self.store.clear_session_if_active(&fork.fork_channel_id, &fork.session_id).await?;
+        sqlx::query("DELETE FROM session_forks WHERE fork_channel_id = ?")
+            .bind(fork_channel_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(Some(fork))
+    }
+
+    /// Drop forks whose session is no longer mapped to their sub-channel,
+    /// e.g. because the reaper retired it. Returns how many were dropped.
+    pub async fn prune_reaped(&self) -> anyhow::Result<u64> {
+        let result = sqlx::query(
+            "DELETE FROM session_forks WHERE NOT EXISTS ( \
+                 SELECT 1 FROM channel_sessions session \
+                 WHERE session.channel_id = session_forks.fork_channel_id \
+                   AND session.session_id = session_forks.session_id)",
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+fn fork_from_row(row: &sqlx::sqlite::SqliteRow) -> SessionFork {
+    SessionFork {
+        fork_channel_id: row.try_get("fork_channel_id").unwrap_or_default(),
+        parent_channel_id: row.try_get("parent_channel_id").unwrap_or_default(),
+        parent_session_id: row.try_get("parent_session_id").unwrap_or_default(),
+        session_id: row.try_get("session_id").unwrap_or_default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    async fn insert_message(pool: &SqlitePool, channel_id: &str, content: &str, cleared: bool) {
+        sqlx::query(
+            "INSERT INTO conversation_messages (id, channel_id, role, content, cleared_at) \
+             VALUES (?, ?, 'user', ?, CASE WHEN ? THEN CURRENT_TIMESTAMP END)",
+        )
+        .bind(uuid::Uuid::new_v4().to_string())
+        .bind(channel_id)
+        .bind(content)
+        .bind(cleared)
+        .execute(pool)
+        .await
+        .unwrap();
+    }
+
+    #[test]
+    fn test_parse_fork_command() {
+        assert_eq!(ForkCommand::parse(" !fork "), Some(ForkCommand::Create));
+        assert_eq!(ForkCommand::parse("!forks"), Some(ForkCommand::List));
+        assert_eq!(
+            ForkCommand::parse("!fork abandon discord:1:2:fork-ab12cd34"),
+            Some(ForkCommand::Abandon("discord:1:2:fork-ab12cd34".into()))
+        );
+        assert_eq!(ForkCommand::parse("!fork abandon"), None);
+        assert_eq!(ForkCommand::parse("!forklift"), None);
+    }
+
+    #[tokio::test]
+    async fn test_fork_copies_context_and_cleans_up() {
+        let pool = connect_in_memory().await;
+        let forks = SessionForks::new(pool.clone());
+        let store = ChannelStore::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        store.set_active_session(&channel_id, "ses_main").await.unwrap();
+        insert_message(&pool, "discord:1:2", "before reset", true).await;
+        insert_message(&pool, "discord:1:2", "fix the build", false).await;
+
+        let fork = forks.record_fork(&channel_id, "ses_main", "ses_child").await.unwrap();
+        assert!(fork.fork_channel_id.starts_with("discord:1:2:fork-"));
+        assert_eq!(store.get_active_session(&fork.fork_channel_id).await.unwrap().as_deref(), Some("ses_child"));
+        assert_eq!(store.get_active_session(&channel_id).await.unwrap().as_deref(), Some("ses_main"));
+        let copied: Vec<String> = sqlx::query_scalar("SELECT content FROM conversation_messages WHERE channel_id = ?")
+            .bind(&fork.fork_channel_id)
+            .fetch_all(&pool)
+            .await
+            .unwrap();
+        assert_eq!(copied, ["fix the build"]);
+
+        let second = forks.record_fork(&channel_id, "ses_main", "ses_other").await.unwrap();
+        assert_eq!(forks.list("discord:1:2").await.unwrap(), [fork.clone(), second.clone()]);
+
+        assert_eq!(forks.abandon(&fork.fork_channel_id).await.unwrap(), Some(fork.clone()));
+        assert_eq!(forks.abandon(&fork.fork_channel_id).await.unwrap(), None);
+        assert_eq!(store.get_active_session(&fork.fork_channel_id).await.unwrap(), None);
+
+        // The reaper retired the other fork's session.
+        store.clear_active_session(&second.fork_channel_id).await.unwrap();
+        assert_eq!(forks.prune_reaped().await.unwrap(), 1);
+        assert!(forks.list("discord:1:2").await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,365 @@
+//! Persisted permission choices (SQLite).
+//!
+//! OpenCode remembers an `Always` reply only for the session it was given in.
+//! Grants are stored per channel so later sessions in the same channel get
+//! the request approved without asking again, until revoked with `!ungrant`.
+//! A `Reject` reply is remembered the same way, for the exact patterns it was
+//! given to, and a matching request is rejected without asking. Rejections
+//! win: a request is approved only if grants cover every pattern and none of
+//! its patterns was rejected.
+
+use crate::opencode::types::{PermissionReply, PermissionRequest};
+use crate::ChannelId;
+
+use sqlx::{Row as _, SqlitePool};
+
+/// A remembered answer to permission requests.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantDecision {
+    Always,
+    Reject,
+}
+
+impl GrantDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::Reject => "reject",
+        }
+    }
+
+    /// The decision a reply is remembered as. `Once` isn't remembered.
+    pub fn from_reply(reply: &PermissionReply) -> Option<Self> {
+        match reply {
+            PermissionReply::Once => None,
+            PermissionReply::Always => Some(Self::Always),
+            PermissionReply::Reject => Some(Self::Reject),
+        }
+    }
+
+    /// The reply sent for a request this decision matches.
+    pub fn reply(&self) -> PermissionReply {
+        match self {
+            Self::Always => PermissionReply::Always,
+            Self::Reject => PermissionReply::Reject,
+        }
+    }
+}
+
+impl std::str::FromStr for GrantDecision {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always" => Ok(Self::Always),
+            "reject" => Ok(Self::Reject),
+            other => Err(format!("unknown grant decision: {other}")),
+        }
+    }
+}
+
+/// A stored grant: `pattern` (a wildcard, as OpenCode reports in a request's
+/// `always` list) approved or rejected for `permission` in a channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PermissionGrant {
+    pub permission: String,
+    pub pattern: String,
+    pub decision: GrantDecision,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Which grants an `!ungrant` command revokes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ungrant {
+    /// No arguments: list the channel's grants.
+    List,
+    /// `!ungrant all`
+    All,
+    /// `!ungrant <permission>`
+    Permission(String),
+    /// `!ungrant <permission> <pattern>`
+    Pattern { permission: String, pattern: String },
+}
+
+impl Ungrant {
+    /// Parse an `!ungrant` command. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.trim().strip_prefix("!ungrant")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let rest = rest.trim();
+        let command = match rest.split_once(char::is_whitespace) {
+            None if rest.is_empty() => Self::List,
+            None if rest.eq_ignore_ascii_case("all") => Self::All,
+            None => Self::Permission(rest.to_string()),
+            Some((permission, pattern)) => Self::Pattern {
+                permission: permission.to_string(),
+                pattern: pattern.trim().to_string(),
+            },
+        };
+        Some(command)
+    }
+}
+
+/// Reads and writes a channel's persisted permission grants.
+#[derive(Debug, Clone)]
+pub struct PermissionGrants {
+    pool: SqlitePool,
+}
+
+impl PermissionGrants {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// Remember a reply to `request`. `Always` stores the request's `always`
+    /// patterns, or its own patterns if OpenCode sent none; `Reject` stores
+    /// only its own patterns, so a narrow refusal doesn't block the whole
+    /// tool. A pattern remembered with the other decision is switched.
+    /// Returns how many grants were added or changed.
+    pub async fn remember(
+        &self,
+        channel_id: &ChannelId,
+        request: &PermissionRequest,
+        decision: GrantDecision,
+    ) -> anyhow::Result<u64> {
+        let Some(permission) = &request.permission else {
+            return Ok(0);
+        };
+        let patterns = match decision {
+            GrantDecision::Always if !request.always.is_empty() => &request.always,
+            _ => &request.patterns,
+        };
+
+        let mut changed = 0;
+        for pattern in patterns {
+            changed += sqlx::query(
+                "INSERT INTO permission_grants (channel_id, permission, pattern, decision) VALUES (?, ?, ?, ?) \
+                 ON CONFLICT(channel_id, permission, pattern) DO UPDATE SET \
+                     decision = excluded.decision, created_at = CURRENT_TIMESTAMP \
+                 WHERE permission_grants.decision != excluded.decision",
+            )
+            .bind(channel_id.as_ref())
+            .bind(permission)
+            .bind(pattern)
+            .bind(decision.as_str())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        }
+        Ok(changed)
+    }
+
+    /// The remembered answer to `request`, if any: `Reject` if any of its
+    /// patterns matches a rejection, `Always` if grants cover every pattern,
+    /// otherwise `None` and the request needs asking.
+    pub async fn decide(
+        &self,
+        channel_id: &ChannelId,
+        request: &PermissionRequest,
+    ) -> anyhow::Result<Option<GrantDecision>> {
+        let Some(permission) = &request.permission else {
+            return Ok(None);
+        };
+        if request.patterns.is_empty() {
+            return Ok(None);
+        }
+
+        let rows = sqlx::query(
+            "SELECT pattern, decision FROM permission_grants WHERE channel_id = ? AND permission = ?",
+        )
+        .bind(channel_id.as_ref())
+        .bind(permission)
+        .fetch_all(&self.pool)
+        .await?;
+        let (mut granted, mut rejected) = (Vec::new(), Vec::new());
+        for row in &rows {
+            let pattern: String = row.try_get("pattern")?;
+            match row.try_get::<String, _>("decision")?.parse() {
+                Ok(GrantDecision::Always) => granted.push(pattern),
+                Ok(GrantDecision::Reject) => rejected.push(pattern),
+                Err(error) => tracing::warn!(%error, "skipping permission grant"),
+            }
+        }
+
+        let matches_any = |stored: &[String], pattern: &str| {
+            stored.iter().any(|grant| wildcard_match(grant.trim(), pattern.trim()))
+        };
+        if request.patterns.iter().any(|pattern| matches_any(&rejected, pattern)) {
+            return Ok(Some(GrantDecision::Reject));
+        }
+        if request.patterns.iter().all(|pattern| matches_any(&granted, pattern)) {
+            return Ok(Some(GrantDecision::Always));
+        }
+        Ok(None)
+    }
+
+    /// The channel's grants, oldest first.
+    pub async fn list(&self, channel_id: &ChannelId) -> anyhow::Result<Vec<PermissionGrant>> {
+        let rows = sqlx::query(
+            "SELECT permission, pattern, decision, created_at FROM permission_grants \
+             WHERE channel_id = ? \
+             ORDER BY created_at, rowid",
+        )
+        .bind(channel_id.as_ref())
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| PermissionGrant {
+                permission: row.try_get("permission").unwrap_or_default(),
+                pattern: row.try_get("pattern").unwrap_or_default(),
+                decision: row
+                    .try_get::<String, _>("decision")
+                    .ok()
+                    .and_then(|decision| decision.parse().ok())
+                    .unwrap_or(GrantDecision::Always),
+                created_at: row
+                    .try_get("created_at")
+                    .unwrap_or_else(|_| chrono::Utc::now()),
+            })
+            .collect())
+    }
+
+    /// Remove the grants `ungrant` selects. Returns how many were removed;
+    /// `Ungrant::List` removes nothing.
+    pub async fn revoke(&self, channel_id: &ChannelId, ungrant: &Ungrant) -> anyhow::Result<u64> {
+        let query = match ungrant {
+            Ungrant::List => return Ok(0),
+            Ungrant::All => sqlx::query("DELETE FROM permission_grants WHERE channel_id = ?")
+                .bind(channel_id.as_ref()),
+            Ungrant::Permission(permission) => {
+                sqlx::query("DELETE FROM permission_grants WHERE channel_id = ? AND permission = ?")
+                    .bind(channel_id.as_ref())
+                    .bind(permission)
+            }
+            Ungrant::Pattern { permission, pattern } => sqlx::query(
+                "DELETE FROM permission_grants WHERE channel_id = ? AND permission = ? AND pattern = ?",
+            )
+            .bind(channel_id.as_ref())
+            .bind(permission)
+            .bind(pattern),
+        };
+        Ok(query.execute(&self.pool).await?.rows_affected())
+    }
+}
+
+/// Match `text` against an OpenCode-style wildcard: `*` matches any run of
+/// characters, `?` any single character.
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text index it was tried at.
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match star {
+                Some((star_p, star_t)) => {
+                    p = star_p + 1;
+                    t = star_t + 1;
+                    star = Some((star_p, star_t + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    fn request(patterns: &[&str], always: &[&str]) -> PermissionRequest {
+        PermissionRequest {
+            id: "per_1".into(),
+            session_id: "ses_1".into(),
+            permission: Some("bash".into()),
+            patterns: patterns.iter().map(|pattern| pattern.to_string()).collect(),
+            always: always.iter().map(|pattern| pattern.to_string()).collect(),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("rm *", "rm -rf target"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("git ?ush", "git push"));
+        assert!(wildcard_match("cargo * --release", "cargo build --release"));
+        assert!(!wildcard_match("rm *", "git rm x"));
+        assert!(!wildcard_match("git push", "git push --force"));
+    }
+
+    #[test]
+    fn test_parse_ungrant() {
+        assert_eq!(Ungrant::parse("!ungrant"), Some(Ungrant::List));
+        assert_eq!(Ungrant::parse(" !ungrant ALL "), Some(Ungrant::All));
+        assert_eq!(Ungrant::parse("!ungrant bash"), Some(Ungrant::Permission("bash".into())));
+        assert_eq!(
+            Ungrant::parse("!ungrant bash rm *"),
+            Some(Ungrant::Pattern { permission: "bash".into(), pattern: "rm *".into() })
+        );
+        assert_eq!(Ungrant::parse("!ungranted"), None);
+        assert_eq!(Ungrant::parse("please !ungrant"), None);
+    }
+
+    #[tokio::test]
+    async fn test_grants_cover_later_requests_until_revoked() {
+        let grants = PermissionGrants::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other_channel: ChannelId = "discord:1:3".parse().unwrap();
+        let always = GrantDecision::Always;
+
+        assert_eq!(grants.remember(&channel_id, &request(&["rm -rf target"], &["rm *"]), always).await.unwrap(), 1);
+        // Without `always` patterns, the request's own patterns are stored.
+        assert_eq!(grants.remember(&channel_id, &request(&["ls"], &[]), always).await.unwrap(), 1);
+        assert_eq!(grants.remember(&channel_id, &request(&["rm -r x"], &["rm *"]), always).await.unwrap(), 0);
+
+        let decide = |patterns: &'static [&'static str], channel_id: &ChannelId| {
+            let (grants, channel_id) = (grants.clone(), channel_id.clone());
+            async move { grants.decide(&channel_id, &request(patterns, &[])).await.unwrap() }
+        };
+        assert_eq!(decide(&["rm -rf node_modules", "ls"], &channel_id).await, Some(always));
+        assert_eq!(decide(&["rm x", "git push"], &channel_id).await, None);
+        assert_eq!(decide(&["ls"], &other_channel).await, None);
+        assert_eq!(grants.list(&channel_id).await.unwrap().len(), 2);
+
+        // A remembered rejection of the exact command wins over the wildcard grant.
+        let reject = GrantDecision::Reject;
+        assert_eq!(grants.remember(&channel_id, &request(&["rm -rf /"], &["rm *"]), reject).await.unwrap(), 1);
+        assert_eq!(decide(&["ls", "rm -rf /"], &channel_id).await, Some(reject));
+        assert_eq!(decide(&["rm -rf target"], &channel_id).await, Some(always));
+        // Remembering the other decision switches it.
+        assert_eq!(grants.remember(&channel_id, &request(&["rm -rf /"], &[]), always).await.unwrap(), 1);
+        assert_eq!(decide(&["rm -rf /"], &channel_id).await, Some(always));
+        assert!(grants.list(&channel_id).await.unwrap().iter().all(|grant| grant.decision == always));
+        grants
+            .revoke(&channel_id, &Ungrant::Pattern { permission: "bash".into(), pattern: "rm -rf /".into() })
+            .await
+            .unwrap();
+
+        let revoked = grants
+            .revoke(&channel_id, &Ungrant::Pattern { permission: "bash".into(), pattern: "rm *".into() })
+            .await
+            .unwrap();
+        assert_eq!(revoked, 1);
+        assert_eq!(decide(&["rm x"], &channel_id).await, None);
+
+        assert_eq!(grants.revoke(&channel_id, &Ungrant::All).await.unwrap(), 1);
+        assert!(grants.list(&channel_id).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,101 @@
+//! Hooks for customizing how a worker reacts to OpenCode events.
+//!
+//! An `EventHandler` sees the events of a worker's session as the event loop
+//! processes them, before the worker's own handling. It is an override
+//! layer, not a replacement: posting to the channel, stored grants, asking
+//! in the channel and the configured defaults stay in `OpenCodeWorker`. The
+//! decision hooks (`on_permission`, `on_question`) return `None` to leave
+//! the request to that built-in flow, or an answer to skip it entirely.
+//! Every method defaults to doing nothing, so an integrator embedding
+//! Spacebot overrides only the reactions they care about.
+//!
+//! Hooks run inline in the event loop, so a slow hook holds up the turn.
+//! Spawn a task for anything that doesn't need to answer.
+
+use crate::opencode::types::{Part, PermissionReply, PermissionRequest, QuestionAnswer, QuestionRequest, TurnOutcome};
+use crate::{ChannelId, WorkerId};
+
+use async_trait::async_trait;
+
+/// Where an event came from.
+#[derive(Debug, Clone, Copy)]
+pub struct EventContext<'a> {
+    pub worker_id: WorkerId,
+    pub channel_id: Option<&'a ChannelId>,
+    /// The worker's session. Sub-agent sessions report through their parent.
+    pub session_id: &'a str,
+}
+
+/// Reactions to a worker's session events.
+#[async_trait]
+pub trait EventHandler: Send + Sync + std::fmt::Debug {
+    /// A text or tool part of the session was created or updated. `delta` is
+    /// the appended text, when OpenCode sends one.
+    async fn on_message_part(&self, _context: &EventContext<'_>, _part: &Part, _delta: Option<&str>) {}
+
+    /// OpenCode asks for permission. Return a reply to answer it here,
+    /// skipping grants and the channel.
+    async fn on_permission(&self, _context: &EventContext<'_>, _request: &PermissionRequest) -> Option<PermissionReply> {
+        None
+    }
+
+    /// OpenCode asks a question. Return one answer per question, in order,
+    /// to answer it here instead of in the channel.
+    async fn on_question(&self, _context: &EventContext<'_>, _question: &QuestionRequest) -> Option<Vec<QuestionAnswer>> {
+        None
+    }
+
+    /// The session went idle and the turn completed.
+    async fn on_session_idle(&self, _context: &EventContext<'_>, _outcome: &TurnOutcome) {}
+}
+
+/// Overrides nothing, leaving every event to the worker's built-in flow.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultEventHandler;
+
+impl EventHandler for DefaultEventHandler {}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::opencode::types::QuestionInfo;
+
+    /// Rejects every permission and leaves questions to the chat.
+    #[derive(Debug)]
+    pub(crate) struct Locked;
+
+    #[async_trait]
+    impl EventHandler for Locked {
+        async fn on_permission(&self, _context: &EventContext<'_>, _request: &PermissionRequest) -> Option<PermissionReply> {
+            Some(PermissionReply::Reject)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_defaults_decline() {
+        let context = EventContext {
+            worker_id: uuid::Uuid::new_v4(),
+            channel_id: None,
+            session_id: "ses_1",
+        };
+        let request: PermissionRequest = serde_json::from_value(serde_json::json!({
+            "id": "per_1",
+            "sessionID": "ses_1",
+            "permission": "bash",
+            "patterns": ["rm -rf build"],
+        }))
+        .unwrap();
+        let question = QuestionRequest {
+            id: "que_1".into(),
+            session_id: "ses_1".into(),
+            questions: vec![QuestionInfo { question: None, header: None, options: Vec::new() }],
+        };
+
+        assert!(DefaultEventHandler.on_permission(&context, &request).await.is_none());
+        assert!(DefaultEventHandler.on_question(&context, &question).await.is_none());
+
+        let handler: &dyn EventHandler = &Locked;
+        assert!(matches!(handler.on_permission(&context, &request).await, Some(PermissionReply::Reject)));
+        assert!(handler.on_question(&context, &question).await.is_none());
+    }
+}
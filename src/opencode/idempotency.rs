@@ -0,0 +1,235 @@
+//! Deduplicating prompt sends (SQLite).
+//!
+//! Each logical prompt has an idempotency key, kept through any resend.
+//! OpenCode has no server-side idempotency, so the key goes out as an
+//! `Idempotency-Key` header for a future server (or a proxy) to honor, and is
+//! enforced here: `PromptLedger` records every send before it happens, and a
+//! send whose key is already recorded is skipped.
+//!
+//! A prompt's key is derived from the user message it was sent for, whose
+//! own key (`message_key`) is persisted with the message. A prompt rebuilt
+//! after a crash-and-restart, or for a message the platform redelivered,
+//! gets the same key; a user repeating themselves sends a new message, and
+//! so a new key. Keys outlive the process, so a restart doesn't forget what
+//! was sent.
+//!
+//! The ledger can also match on content: with a nonzero dedup window, the
+//! same text to the same session within it counts as a repeat under any key.
+//! That catches a user repeating a message word for word too, so it's off
+//! unless configured.
+
+use crate::ChannelId;
+
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+use std::time::Duration;
+
+/// How long sends are kept. Resends of a logical prompt happen within
+/// minutes, so a day is plenty; older rows are pruned as new ones arrive.
+const KEY_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A fresh idempotency key, for a prompt with no user message behind it.
+pub fn idempotency_key() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// The idempotency key of a user message: stable for a given platform
+/// message in a given channel.
+pub fn message_key(channel_id: &ChannelId, message_id: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(channel_id.as_ref().as_bytes());
+    hasher.update([0]);
+    hasher.update(message_id.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// The idempotency key of a prompt sent to `session_id` for the user message
+/// keyed `message_key`. Workers that get several prompts for one message (a
+/// task and a routed follow-up, or a compaction retry in a new session) get
+/// distinct keys. Without a message key, a fresh one.
+pub fn prompt_key(message_key: Option<&str>, session_id: &str, text: &str) -> String {
+    let Some(message_key) = message_key else {
+        return idempotency_key();
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(message_key.as_bytes());
+    hasher.update([0]);
+    hasher.update(prompt_fingerprint(session_id, text).as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// SHA-256 hex digest identifying a prompt's content within a session.
+pub fn prompt_fingerprint(session_id: &str, text: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(session_id.as_bytes());
+    hasher.update([0]);
+    hasher.update(text.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Whether a send should go ahead.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Claim {
+    /// Recorded; send it.
+    New,
+    /// Already sent under this key.
+    SameKey,
+    /// The same content went to the session within the window, under
+    /// another key.
+    SameContent { key: String },
+}
+
+/// Records prompt sends so repeats can be skipped.
+#[derive(Debug, Clone)]
+pub struct PromptLedger {
+    pool: SqlitePool,
+    /// How long identical content to the same session counts as a repeat.
+    window: Duration,
+}
+
+impl PromptLedger {
+    pub fn new(pool: SqlitePool, window: Duration) -> Self {
+        Self { pool, window }
+    }
+
+    /// Record a send of `text` to `session_id` under `key`, unless it's a
+    /// repeat.
+    pub async fn claim(
+        &self,
+        key: &str,
+        channel_id: Option<&ChannelId>,
+        session_id: &str,
+        text: &str,
+    ) -> anyhow::Result<Claim> {
+        let now = chrono::Utc::now();
+        let fingerprint = prompt_fingerprint(session_id, text);
+
+        let known: Option<(String,)> = sqlx::query_as("SELECT idempotency_key FROM prompt_sends WHERE idempotency_key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+        if known.is_some() {
+            return Ok(Claim::SameKey);
+        }
+
+        if let Some(since) = (!self.window.is_zero())
+            .then(|| chrono::Duration::from_std(self.window).ok())
+            .flatten()
+            .map(|window| now - window)
+        {
+            let repeat: Option<(String,)> = sqlx::query_as(
+                "SELECT idempotency_key FROM prompt_sends \
+                 WHERE session_id = ? AND fingerprint = ? AND sent_at >= ? \
+                 ORDER BY sent_at DESC LIMIT 1",
+            )
+            .bind(session_id)
+            .bind(&fingerprint)
+            .bind(since)
+            .fetch_optional(&self.pool)
+            .await?;
+            if let Some((key,)) = repeat {
+                return Ok(Claim::SameContent { key });
+            }
+        }
+
+        sqlx::query(
+            "INSERT INTO prompt_sends (idempotency_key, channel_id, session_id, fingerprint, sent_at) \
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(key)
+        .bind(channel_id.map(|channel_id| channel_id.as_ref()))
+        .bind(session_id)
+        .bind(&fingerprint)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        self.prune(KEY_RETENTION.max(self.window)).await?;
+        Ok(Claim::New)
+    }
+
+    /// Forget a send the server definitely refused, so it can be retried.
+    pub async fn release(&self, key: &str) -> anyhow::Result<()> {
+        sqlx::query("DELETE FROM prompt_sends WHERE idempotency_key = ?")
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Delete sends older than `max_age`. Returns how many were removed.
+    pub async fn prune(&self, max_age: Duration) -> anyhow::Result<u64> {
+        let cutoff = chrono::Utc::now() - chrono::Duration::from_std(max_age)?;
+        let result = sqlx::query("DELETE FROM prompt_sends WHERE sent_at < ?")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[test]
+    fn test_prompt_keys_follow_the_user_message() {
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let first = message_key(&channel_id, "1001");
+        assert_eq!(first, message_key(&channel_id, "1001"));
+        let second = message_key(&channel_id, "1002");
+        assert_ne!(first, second);
+
+        // A resend of a message's prompt keeps its key; the user saying the
+        // same thing again doesn't.
+        let key = prompt_key(Some(&first), "ses_1", "yes");
+        assert_eq!(key, prompt_key(Some(&first), "ses_1", "yes"));
+        assert_ne!(key, prompt_key(Some(&second), "ses_1", "yes"));
+        // Distinct prompts for one message get distinct keys.
+        assert_ne!(key, prompt_key(Some(&first), "ses_2", "yes"));
+        assert_ne!(key, prompt_key(Some(&first), "ses_1", "no"));
+        // Without a message, every prompt is new.
+        assert_ne!(prompt_key(None, "ses_1", "yes"), prompt_key(None, "ses_1", "yes"));
+    }
+
+    #[tokio::test]
+    async fn test_repeated_text_goes_through_by_default() {
+        let pool = connect_in_memory().await;
+        let ledger = PromptLedger::new(pool, Duration::ZERO);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let first = prompt_key(Some(&message_key(&channel_id, "1001")), "ses_1", "yes");
+        let second = prompt_key(Some(&message_key(&channel_id, "1002")), "ses_1", "yes");
+
+        assert_eq!(ledger.claim(&first, Some(&channel_id), "ses_1", "yes").await.unwrap(), Claim::New);
+        assert_eq!(ledger.claim(&second, Some(&channel_id), "ses_1", "yes").await.unwrap(), Claim::New);
+        assert_eq!(ledger.claim(&first, Some(&channel_id), "ses_1", "yes").await.unwrap(), Claim::SameKey);
+    }
+
+    #[tokio::test]
+    async fn test_claims_dedupe_by_key_and_content() {
+        let pool = connect_in_memory().await;
+        let ledger = PromptLedger::new(pool.clone(), Duration::from_secs(60));
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        assert_eq!(ledger.claim("key-1", Some(&channel_id), "ses_1", "fix the build").await.unwrap(), Claim::New);
+        // A resend of the same logical prompt.
+        assert_eq!(ledger.claim("key-1", Some(&channel_id), "ses_1", "fix the build").await.unwrap(), Claim::SameKey);
+        // The same text under another key, with a content window set.
+        assert_eq!(
+            ledger.claim("key-2", Some(&channel_id), "ses_1", "fix the build").await.unwrap(),
+            Claim::SameContent { key: "key-1".into() }
+        );
+        // Other sessions and other text are unaffected.
+        assert_eq!(ledger.claim("key-3", Some(&channel_id), "ses_2", "fix the build").await.unwrap(), Claim::New);
+        assert_eq!(ledger.claim("key-4", Some(&channel_id), "ses_1", "and the tests").await.unwrap(), Claim::New);
+
+        // A refused send can go again.
+        ledger.release("key-4").await.unwrap();
+        assert_eq!(ledger.claim("key-4", Some(&channel_id), "ses_1", "and the tests").await.unwrap(), Claim::New);
+
+        // Without a window only the key counts.
+        let keys_only = PromptLedger::new(pool, Duration::ZERO);
+        assert_eq!(keys_only.claim("key-5", None, "ses_1", "fix the build").await.unwrap(), Claim::New);
+        assert_eq!(keys_only.prune(Duration::from_secs(3600)).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,141 @@
+//! Typing indicator for a running turn.
+//!
+//! `TypingIndicator` follows a session's `session.status` and
+//! `message.part.updated` events and reports when the indicator the chat
+//! layer shows should change: `Thinking` while the model works, `Retrying`
+//! while OpenCode backs off from a provider error, and `Idle` once the session
+//! goes idle. Parts keep arriving for as long as the model writes, so repeats
+//! of the current state are debounced to spare the platform's typing API.
+
+use crate::opencode::types::{SessionStatusPayload, SseEvent};
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// What the typing indicator should show.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndicatorState {
+    Idle,
+    Thinking,
+    Retrying { attempt: u32 },
+}
+
+impl IndicatorState {
+    /// Whether the indicator should be visible.
+    pub fn is_active(&self) -> bool {
+        !matches!(self, Self::Idle)
+    }
+
+    /// Short label for status lines, e.g. "retrying (attempt 2)…".
+    pub fn label(&self) -> String {
+        match self {
+            Self::Idle => "idle".to_string(),
+            Self::Thinking => "thinking".to_string(),
+            Self::Retrying { attempt } => format!("retrying (attempt {attempt})…"),
+        }
+    }
+}
+
+/// Indicator state machine for one session.
+#[derive(Debug, Clone)]
+pub struct TypingIndicator {
+    debounce: Duration,
+    state: IndicatorState,
+    last_emitted: Option<Instant>,
+}
+
+impl TypingIndicator {
+    /// Re-emit an unchanged state at most once per `debounce`.
+    pub fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            state: IndicatorState::Idle,
+            last_emitted: None,
+        }
+    }
+
+    pub fn state(&self) -> IndicatorState {
+        self.state
+    }
+
+    /// Feed an event. Returns the state to show if the chat layer should act:
+    /// on every change, and on activity in an unchanged active state once
+    /// `debounce` has passed since the last emission.
+    pub fn observe(&mut self, event: &SseEvent, session_id: &str, now: Instant) -> Option<IndicatorState> {
+        let next = match event {
+            SseEvent::SessionStatus { session_id: event_session, status } if event_session == session_id => {
+                match status {
+                    SessionStatusPayload::Busy => IndicatorState::Thinking,
+                    SessionStatusPayload::Retry { attempt, .. } => IndicatorState::Retrying { attempt: *attempt },
+                    SessionStatusPayload::Idle => IndicatorState::Idle,
+                }
+            }
+            SseEvent::SessionIdle { session_id: event_session } if event_session == session_id => IndicatorState::Idle,
+            // Output flowing means any retry went through.
+            SseEvent::MessagePartUpdated { part, .. } if part.session_id() == Some(session_id) => {
+                IndicatorState::Thinking
+            }
+            _ => return None,
+        };
+        self.transition(next, now)
+    }
+
+    fn transition(&mut self, next: IndicatorState, now: Instant) -> Option<IndicatorState> {
+        if next == self.state {
+            let due = self
+                .last_emitted
+                .is_none_or(|emitted| now.duration_since(emitted) >= self.debounce);
+            if !next.is_active() || !due {
+                return None;
+            }
+        }
+        self.state = next;
+        self.last_emitted = Some(now);
+        Some(next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: SessionStatusPayload) -> SseEvent {
+        SseEvent::SessionStatus { session_id: "ses_1".into(), status }
+    }
+
+    fn part(session_id: &str) -> SseEvent {
+        serde_json::from_value(serde_json::json!({
+            "type": "message.part.updated",
+            "properties": { "part": { "type": "text", "id": "prt_1", "sessionID": session_id, "text": "hi" } }
+        }))
+        .map(SseEvent::from_envelope)
+        .unwrap()
+    }
+
+    #[test]
+    fn test_indicator_transitions_and_debounce() {
+        let mut indicator = TypingIndicator::new(Duration::from_secs(5));
+        let start = Instant::now();
+
+        assert_eq!(indicator.observe(&status(SessionStatusPayload::Busy), "ses_1", start), Some(IndicatorState::Thinking));
+        assert_eq!(indicator.observe(&part("ses_1"), "ses_1", start + Duration::from_secs(1)), None);
+        assert_eq!(indicator.observe(&part("ses_2"), "ses_1", start + Duration::from_secs(6)), None);
+        assert_eq!(
+            indicator.observe(&part("ses_1"), "ses_1", start + Duration::from_secs(6)),
+            Some(IndicatorState::Thinking)
+        );
+
+        let retry = status(SessionStatusPayload::Retry { attempt: 2, message: None });
+        let retrying = indicator.observe(&retry, "ses_1", start + Duration::from_secs(7));
+        assert_eq!(retrying, Some(IndicatorState::Retrying { attempt: 2 }));
+        assert_eq!(retrying.unwrap().label(), "retrying (attempt 2)…");
+        assert_eq!(
+            indicator.observe(&part("ses_1"), "ses_1", start + Duration::from_secs(8)),
+            Some(IndicatorState::Thinking)
+        );
+
+        let idle = SseEvent::SessionIdle { session_id: "ses_1".into() };
+        assert_eq!(indicator.observe(&idle, "ses_1", start + Duration::from_secs(9)), Some(IndicatorState::Idle));
+        assert_eq!(indicator.observe(&idle, "ses_1", start + Duration::from_secs(20)), None);
+    }
+}
@@ -0,0 +1,139 @@
+//! Pre-send limit on how long a prompt's message may be.
+//!
+//! A pasted blob past the model's context fails the turn outright, so
+//! oversized messages are truncated, rejected, or sent as a file part instead,
+//! and the user is told which.
+
+use crate::opencode::types::PartInput;
+
+use base64::Engine as _;
+
+/// What to do with a message over the limit.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PromptOverflow {
+    /// Keep the start of the message and mark where it was cut.
+    #[default]
+    Truncate,
+    /// Don't send it; tell the user how to shorten it.
+    Reject,
+    /// Send the whole message as a text file part, with a short note inline.
+    Attach,
+}
+
+impl std::str::FromStr for PromptOverflow {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(Self::Truncate),
+            "reject" => Ok(Self::Reject),
+            "attach" => Ok(Self::Attach),
+            _ => Err(format!("unknown prompt overflow mode: {s}")),
+        }
+    }
+}
+
+/// The longest message a prompt may carry inline, in characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptLimit {
+    pub max_chars: usize,
+    pub overflow: PromptOverflow,
+}
+
+/// A message after the limit was applied.
+#[derive(Debug, Clone)]
+pub struct FittedPrompt {
+    /// Text to send in place of the message.
+    pub text: String,
+    /// File parts to send along with it.
+    pub files: Vec<PartInput>,
+    /// What to tell the user, if the message was changed.
+    pub notice: Option<String>,
+}
+
+/// Name of the file an attached message is sent as.
+const ATTACHED_FILENAME: &str = "message.txt";
+
+impl PromptLimit {
+    /// Apply the limit to `text`. `Err` carries the guidance to post when the
+    /// message is rejected.
+    pub fn fit(&self, text: &str) -> Result<FittedPrompt, String> {
+        let chars = text.chars().count();
+        if chars <= self.max_chars {
+            return Ok(FittedPrompt { text: text.to_string(), files: Vec::new(), notice: None });
+        }
+
+        match self.overflow {
+            PromptOverflow::Truncate => {
+                let omitted = chars - self.max_chars;
+                let kept: String = text.chars().take(self.max_chars).collect();
+                Ok(FittedPrompt {
+                    text: format!("{kept}\n\n[… truncated: {omitted} of {chars} characters omitted to fit the context …]"),
+                    files: Vec::new(),
+                    notice: Some(format!(
+                        "⚠️ Your message was truncated to fit the context ({} of {chars} characters kept).",
+                        self.max_chars
+                    )),
+                })
+            }
+            PromptOverflow::Reject => Err(format!(
+                "⚠️ Your message is {chars} characters, over the {} allowed here, so it wasn't sent. \
+                 Trim it down, or attach large content (logs, files) as a file instead of pasting it.",
+                self.max_chars
+            )),
+            PromptOverflow::Attach => {
+                let encoded = base64::engine::general_purpose::STANDARD.encode(text);
+                Ok(FittedPrompt {
+                    text: format!(
+                        "The message was too long to include inline ({chars} characters), so it's attached as \
+                         `{ATTACHED_FILENAME}`. Read it and treat its contents as the message."
+                    ),
+                    files: vec![PartInput::File {
+                        mime: "text/plain".to_string(),
+                        url: format!("data:text/plain;base64,{encoded}"),
+                        filename: Some(ATTACHED_FILENAME.to_string()),
+                    }],
+                    notice: Some("📎 Your message was too long to send inline, so it was attached as a file.".to_string()),
+                })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(overflow: PromptOverflow) -> PromptLimit {
+        PromptLimit { max_chars: 10, overflow }
+    }
+
+    #[test]
+    fn test_fit() {
+        let fitted = limit(PromptOverflow::Reject).fit("short").unwrap();
+        assert_eq!(fitted.text, "short");
+        assert!(fitted.files.is_empty() && fitted.notice.is_none());
+
+        let long = "é".repeat(25);
+        let truncated = limit(PromptOverflow::Truncate).fit(&long).unwrap();
+        assert!(truncated.text.starts_with(&"é".repeat(10)));
+        assert!(truncated.text.contains("15 of 25 characters omitted"));
+        assert!(truncated.notice.unwrap().contains("truncated"));
+
+        let rejected = limit(PromptOverflow::Reject).fit(&long).unwrap_err();
+        assert!(rejected.contains("25 characters"));
+
+        let attached = limit(PromptOverflow::Attach).fit(&long).unwrap();
+        assert!(attached.text.contains(ATTACHED_FILENAME));
+        let [PartInput::File { mime, url, .. }] = attached.files.as_slice() else {
+            panic!("expected one file part");
+        };
+        assert_eq!(mime, "text/plain");
+        let encoded = url.strip_prefix("data:text/plain;base64,").unwrap();
+        let decoded = base64::engine::general_purpose::STANDARD.decode(encoded).unwrap();
+        assert_eq!(String::from_utf8(decoded).unwrap(), long);
+
+        assert_eq!("attach".parse(), Ok(PromptOverflow::Attach));
+        assert!("summarize".parse::<PromptOverflow>().is_err());
+    }
+}
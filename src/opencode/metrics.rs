@@ -0,0 +1,260 @@
+//! Prometheus metrics for OpenCode traffic.
+//!
+//! A small process-wide registry of counters, gauges, and a prompt latency
+//! histogram, rendered in the Prometheus text exposition format by
+//! `GET /api/metrics`. Events and prompt sends are labeled by channel;
+//! channels are bounded by the bot's bindings, so the cardinality stays
+//! manageable.
+
+use crate::opencode::breaker::BreakerState;
+use crate::opencode::types::{MessageInfo, PermissionReply, SseEvent, TimeSpan};
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::{LazyLock, Mutex};
+
+/// Upper bounds (seconds) of the prompt latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 10] = [0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+static METRICS: LazyLock<OpenCodeMetrics> = LazyLock::new(OpenCodeMetrics::default);
+
+/// The process-wide registry.
+pub fn global() -> &'static OpenCodeMetrics {
+    &METRICS
+}
+
+/// Counters and histograms for OpenCode events, replies, and prompts.
+#[derive(Debug, Default)]
+pub struct OpenCodeMetrics {
+    /// Keyed by metric name, then by rendered label set.
+    counters: Mutex<BTreeMap<&'static str, BTreeMap<String, u64>>>,
+    /// Keyed by metric name, then by rendered label set.
+    gauges: Mutex<BTreeMap<&'static str, BTreeMap<String, f64>>>,
+    /// Prompt round-trip latency, keyed by rendered label set.
+    latency: Mutex<BTreeMap<String, Histogram>>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if value <= bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl OpenCodeMetrics {
+    /// Count an SSE event processed for a channel.
+    pub fn record_event(&self, event: &SseEvent, channel_id: Option<&str>) {
+        let labels = format_labels(&[
+            ("event", event.event_type()),
+            ("channel", channel_id.unwrap_or("none")),
+        ]);
+        self.increment("spacebot_opencode_events_total", labels);
+    }
+
+    /// Count a prompt sent to OpenCode for a channel.
+    pub fn record_prompt_sent(&self, channel_id: Option<&str>) {
+        let labels = format_labels(&[("channel", channel_id.unwrap_or("none"))]);
+        self.increment("spacebot_opencode_prompts_total", labels);
+    }
+
+    /// Count a permission reply sent to OpenCode.
+    pub fn record_permission_reply(&self, reply: &PermissionReply) {
+        let reply = match reply {
+            PermissionReply::Once => "once",
+            PermissionReply::Always => "always",
+            PermissionReply::Reject => "reject",
+        };
+        self.increment("spacebot_opencode_permission_replies_total", format_labels(&[("reply", reply)]));
+    }
+
+    /// Count a question reply sent to OpenCode.
+    pub fn record_question_reply(&self) {
+        self.increment("spacebot_opencode_question_replies_total", String::new());
+    }
+
+    /// Count the circuit breaker opening.
+    pub fn record_breaker_trip(&self) {
+        self.increment("spacebot_opencode_circuit_breaker_trips_total", String::new());
+    }
+
+    /// Set the circuit breaker gauge: 0 closed, 1 half-open, 2 open.
+    pub fn record_breaker_state(&self, state: BreakerState) {
+        let mut gauges = self.gauges.lock().unwrap_or_else(|e| e.into_inner());
+        gauges
+            .entry("spacebot_opencode_circuit_breaker_state")
+            .or_default()
+            .insert(String::new(), f64::from(state.gauge_value()));
+    }
+
+    /// Record prompt round-trip latency from a finished assistant message.
+    ///
+    /// Uses the message's `time.start`/`time.end` (epoch milliseconds).
+    /// Messages that aren't from the assistant or haven't finished are
+    /// ignored. Returns whether a sample was recorded, so callers can avoid
+    /// counting the same message twice across repeated updates.
+    pub fn observe_assistant_message(&self, info: &MessageInfo, channel_id: Option<&str>) -> bool {
+        if info.role != "assistant" {
+            return false;
+        }
+        let Some(duration_ms) = info.time.as_ref().and_then(TimeSpan::duration_ms) else {
+            return false;
+        };
+        if duration_ms < 0.0 {
+            return false;
+        }
+
+        let labels = format_labels(&[("channel", channel_id.unwrap_or("none"))]);
+        let mut latency = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        latency.entry(labels).or_default().observe(duration_ms / 1000.0);
+        true
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        let counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, series) in counters.iter() {
+            let _ = writeln!(output, "# TYPE {name} counter");
+            for (labels, value) in series {
+                let _ = writeln!(output, "{name}{} {value}", wrap_labels(labels));
+            }
+        }
+        drop(counters);
+
+        let gauges = self.gauges.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, series) in gauges.iter() {
+            let _ = writeln!(output, "# TYPE {name} gauge");
+            for (labels, value) in series {
+                let _ = writeln!(output, "{name}{} {value}", wrap_labels(labels));
+            }
+        }
+        drop(gauges);
+
+        let latency = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        if !latency.is_empty() {
+            let name = "spacebot_opencode_prompt_latency_seconds";
+            let _ = writeln!(output, "# TYPE {name} histogram");
+            for (labels, histogram) in latency.iter() {
+                for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                    let le = join_labels(labels, &format!("le=\"{bound}\""));
+                    let _ = writeln!(output, "{name}_bucket{{{le}}} {count}");
+                }
+                let le = join_labels(labels, "le=\"+Inf\"");
+                let _ = writeln!(output, "{name}_bucket{{{le}}} {}", histogram.count);
+                let _ = writeln!(output, "{name}_sum{} {}", wrap_labels(labels), histogram.sum);
+                let _ = writeln!(output, "{name}_count{} {}", wrap_labels(labels), histogram.count);
+            }
+        }
+
+        output
+    }
+
+    fn increment(&self, name: &'static str, labels: String) {
+        let mut counters = self.counters.lock().unwrap_or_else(|e| e.into_inner());
+        *counters.entry(name).or_default().entry(labels).or_default() += 1;
+    }
+}
+
+/// Render `key="value"` pairs, escaping values per the exposition format.
+fn format_labels(labels: &[(&str, &str)]) -> String {
+    labels
+        .iter()
+        .map(|(key, value)| {
+            let value = value
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{key}=\"{value}\"")
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn wrap_labels(labels: &str) -> String {
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{labels}}}")
+    }
+}
+
+fn join_labels(labels: &str, extra: &str) -> String {
+    if labels.is_empty() {
+        extra.to_string()
+    } else {
+        format!("{labels},{extra}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_counters_and_latency() {
+        let metrics = OpenCodeMetrics::default();
+        let idle = SseEvent::SessionIdle { session_id: "ses_1".into() };
+        metrics.record_event(&idle, Some("discord:1:2"));
+        metrics.record_event(&idle, Some("discord:1:2"));
+        metrics.record_prompt_sent(None);
+        metrics.record_permission_reply(&PermissionReply::Once);
+        metrics.record_breaker_trip();
+        metrics.record_breaker_state(BreakerState::Open { retry_after: std::time::Duration::from_secs(30) });
+
+        let info = MessageInfo {
+            id: "msg_1".into(),
+            role: "assistant".into(),
+            session_id: Some("ses_1".into()),
+            time: Some(TimeSpan { start: Some(1_000.0), end: Some(4_000.0) }),
+            tokens: None,
+            provider_id: None,
+            model_id: None,
+        };
+        assert!(metrics.observe_assistant_message(&info, Some("discord:1:2")));
+
+        let output = metrics.render();
+        assert!(output.contains(
+            "spacebot_opencode_events_total{event=\"session.idle\",channel=\"discord:1:2\"} 2"
+        ));
+        assert!(output.contains("spacebot_opencode_prompts_total{channel=\"none\"} 1"));
+        assert!(output.contains("spacebot_opencode_permission_replies_total{reply=\"once\"} 1"));
+        assert!(output.contains("spacebot_opencode_circuit_breaker_trips_total 1"));
+        assert!(output.contains("# TYPE spacebot_opencode_circuit_breaker_state gauge\nspacebot_opencode_circuit_breaker_state 2"));
+        assert!(output.contains(
+            "spacebot_opencode_prompt_latency_seconds_bucket{channel=\"discord:1:2\",le=\"2.5\"} 0"
+        ));
+        assert!(output.contains(
+            "spacebot_opencode_prompt_latency_seconds_bucket{channel=\"discord:1:2\",le=\"5\"} 1"
+        ));
+        assert!(output.contains("spacebot_opencode_prompt_latency_seconds_sum{channel=\"discord:1:2\"} 3"));
+    }
+
+    #[test]
+    fn test_unfinished_message_is_not_observed() {
+        let metrics = OpenCodeMetrics::default();
+        let info = MessageInfo {
+            id: "msg_1".into(),
+            role: "assistant".into(),
+            session_id: None,
+            time: Some(TimeSpan { start: Some(1_000.0), end: None }),
+            tokens: None,
+            provider_id: None,
+            model_id: None,
+        };
+        assert!(!metrics.observe_assistant_message(&info, None));
+        assert!(metrics.render().is_empty());
+    }
+}
@@ -0,0 +1,205 @@
+//! Per-channel model override (SQLite).
+//!
+//! `ChannelModels` records the model a channel's prompts run on, set with
+//! `!model provider/model`. The choice is checked against the providers the
+//! OpenCode server reports, so a typo is refused instead of failing every
+//! later prompt. Channels without one use `default_model`, or whatever the
+//! server picks.
+
+use crate::opencode::types::{ModelParam, ProviderInfo};
+use crate::ChannelId;
+
+use sqlx::SqlitePool;
+
+/// A `!models` or `!model` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelCommand {
+    /// `!models`: list the models the channel can switch to.
+    List,
+    /// `!model` with no argument: show the channel's model.
+    Show,
+    /// `!model default`: go back to the default model.
+    Reset,
+    /// `!model <provider/model>`
+    Select(String),
+}
+
+impl ModelCommand {
+    /// Parse a model command. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        if text == "!models" {
+            return Some(Self::List);
+        }
+        let rest = text.strip_prefix("!model")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let command = match rest.trim() {
+            "" => Self::Show,
+            "default" => Self::Reset,
+            model => Self::Select(model.to_string()),
+        };
+        Some(command)
+    }
+}
+
+/// Why a selection was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ModelSelectionError {
+    /// Not in `provider/model` form.
+    Malformed,
+    /// The server has no such provider. Carries the provider IDs.
+    UnknownProvider { available: Vec<String> },
+    /// The provider has no such model. Carries its model IDs.
+    UnknownModel { provider_id: String, available: Vec<String> },
+}
+
+/// Check `model` (`provider/model`) against the providers the server
+/// reported, returning it parsed.
+pub fn validate_model(model: &str, providers: &[ProviderInfo]) -> Result<ModelParam, ModelSelectionError> {
+    let param: ModelParam = model.parse().map_err(|_| ModelSelectionError::Malformed)?;
+    let Some(provider) = providers.iter().find(|provider| provider.id == param.provider_id) else {
+        let mut available: Vec<String> = providers.iter().map(|provider| provider.id.clone()).collect();
+        available.sort();
+        return Err(ModelSelectionError::UnknownProvider { available });
+    };
+    if !provider.models.contains_key(&param.model_id) {
+        let mut available: Vec<String> = provider.models.keys().cloned().collect();
+        available.sort();
+        return Err(ModelSelectionError::UnknownModel {
+            provider_id: provider.id.clone(),
+            available,
+        });
+    }
+    Ok(param)
+}
+
+/// Reads and writes the model selected for each channel.
+#[derive(Debug, Clone)]
+pub struct ChannelModels {
+    pool: SqlitePool,
+}
+
+impl ChannelModels {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+
+    /// The model selected for the channel, or `None` for the default.
+    pub async fn get(&self, channel_id: &ChannelId) -> anyhow::Result<Option<ModelParam>> {
+        let row: Option<(String, String)> =
+            sqlx::query_as("SELECT provider_id, model_id FROM channel_models WHERE channel_id = ?")
+                .bind(channel_id.as_ref())
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(row.map(|(provider_id, model_id)| ModelParam { provider_id, model_id }))
+    }
+
+    /// Select `model` for the channel. Check it with `validate_model` first.
+    pub async fn set(&self, channel_id: &ChannelId, model: &ModelParam) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO channel_models (channel_id, provider_id, model_id, updated_at) \
+             VALUES (?, ?, ?, CURRENT_TIMESTAMP) \
+             ON CONFLICT(channel_id) DO UPDATE SET \
+                 provider_id = excluded.provider_id, model_id = excluded.model_id, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(channel_id.as_ref())
+        .bind(&model.provider_id)
+        .bind(&model.model_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Go back to the default model. Returns whether one was selected.
+    pub async fn clear(&self, channel_id: &ChannelId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM channel_models WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opencode::types::ProviderModel;
+    use crate::db::connect_in_memory;
+
+    fn provider(id: &str, models: &[&str]) -> ProviderInfo {
+        ProviderInfo {
+            id: id.into(),
+            name: None,
+            models: models
+                .iter()
+                .map(|model| (model.to_string(), ProviderModel { id: model.to_string(), name: None }))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_parse_and_validate() {
+        assert_eq!(ModelCommand::parse("!models"), Some(ModelCommand::List));
+        assert_eq!(ModelCommand::parse(" !model "), Some(ModelCommand::Show));
+        assert_eq!(ModelCommand::parse("!model default"), Some(ModelCommand::Reset));
+        assert_eq!(
+            ModelCommand::parse("!model anthropic/claude-sonnet-4-5"),
+            Some(ModelCommand::Select("anthropic/claude-sonnet-4-5".into()))
+        );
+        assert_eq!(ModelCommand::parse("!modeling"), None);
+
+        let providers = [
+            provider("anthropic", &["claude-sonnet-4-5", "claude-haiku-4-5"]),
+            provider("openrouter", &["anthropic/claude-sonnet-4"]),
+        ];
+        let selected = validate_model("openrouter/anthropic/claude-sonnet-4", &providers).unwrap();
+        assert_eq!(selected.provider_id, "openrouter");
+        assert_eq!(selected.model_id, "anthropic/claude-sonnet-4");
+        assert_eq!(validate_model("sonnet", &providers), Err(ModelSelectionError::Malformed));
+        assert_eq!(
+            validate_model("openai/gpt-5", &providers),
+            Err(ModelSelectionError::UnknownProvider { available: vec!["anthropic".into(), "openrouter".into()] })
+        );
+        assert_eq!(
+            validate_model("anthropic/claude-opus", &providers),
+            Err(ModelSelectionError::UnknownModel {
+                provider_id: "anthropic".into(),
+                available: vec!["claude-haiku-4-5".into(), "claude-sonnet-4-5".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_model_param_round_trip() {
+        let param: ModelParam = "anthropic/claude-sonnet-4-5".parse().unwrap();
+        assert_eq!(param.to_string(), "anthropic/claude-sonnet-4-5");
+
+        let json = serde_json::to_value(&param).unwrap();
+        assert_eq!(json, serde_json::json!({ "providerId": "anthropic", "modelId": "claude-sonnet-4-5" }));
+        let back: ModelParam = serde_json::from_value(json).unwrap();
+        assert_eq!(back.to_string(), "anthropic/claude-sonnet-4-5");
+        // OpenCode's own spelling is accepted too.
+        let from_server: ModelParam =
+            serde_json::from_value(serde_json::json!({ "providerID": "anthropic", "modelID": "claude-sonnet-4-5" }))
+                .unwrap();
+        assert_eq!(from_server.to_string(), "anthropic/claude-sonnet-4-5");
+    }
+
+    #[tokio::test]
+    async fn test_channel_models() {
+        let models = ChannelModels::new(connect_in_memory().await);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        assert!(models.get(&channel_id).await.unwrap().is_none());
+
+        models.set(&channel_id, &"anthropic/claude-sonnet-4-5".parse().unwrap()).await.unwrap();
+        models.set(&channel_id, &"anthropic/claude-haiku-4-5".parse().unwrap()).await.unwrap();
+        let selected = models.get(&channel_id).await.unwrap().unwrap();
+        assert_eq!(selected.to_string(), "anthropic/claude-haiku-4-5");
+
+        assert!(models.clear(&channel_id).await.unwrap());
+        assert!(!models.clear(&channel_id).await.unwrap());
+        assert!(models.get(&channel_id).await.unwrap().is_none());
+    }
+}
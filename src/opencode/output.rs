@@ -0,0 +1,156 @@
+//! `!output`: fetching a tool call's full output.
+//!
+//! Tool output is cut short wherever it's shown in chat. The full output is
+//! kept in `tool_invocations`, and `!output` posts it back: the channel's
+//! most recent call, or the one named by call ID. Short output comes back
+//! inline; anything longer as a text file, capped at `tool_output_max_bytes`.
+
+use crate::conversation::history::ToolInvocation;
+
+/// Longest output posted inline rather than as a file, leaving room in a
+/// 2000-character message for the header and code fence.
+const INLINE_MAX_CHARS: usize = 1800;
+
+/// An `!output` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputCommand {
+    /// The call to show. `None` for the channel's most recent one.
+    pub call_id: Option<String>,
+}
+
+impl OutputCommand {
+    /// Parse `!output` or `!output <call_id>`. Returns `None` for any other
+    /// message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.trim().strip_prefix("!output")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let call_id = rest.split_whitespace().next().map(str::to_string);
+        Some(Self { call_id })
+    }
+}
+
+/// What `!output` posts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolOutputReply {
+    /// A message: short output in a code block, or why there is none.
+    Text(String),
+    /// Output too long to post inline.
+    File {
+        filename: String,
+        data: Vec<u8>,
+        caption: String,
+    },
+}
+
+/// The reply to `!output` for `invocation`, with the output cut to
+/// `max_bytes`.
+pub fn tool_output_reply(invocation: &ToolInvocation, max_bytes: usize) -> ToolOutputReply {
+    let label = format!("`{}` (`{}`)", invocation.tool, invocation.call_id);
+    let Some(output) = invocation.output.as_deref() else {
+        let reason = match invocation.status.as_str() {
+            "pending" | "running" => "is still running, so it has no output yet",
+            _ => "has no stored output",
+        };
+        return ToolOutputReply::Text(format!("{label} {reason}."));
+    };
+    if output.trim().is_empty() {
+        return ToolOutputReply::Text(format!("{label} finished with empty output."));
+    }
+
+    let heading = match invocation.status.as_str() {
+        "error" => format!("{label} failed"),
+        _ => format!("Output of {label}"),
+    };
+    let shown = truncate_bytes(output, max_bytes);
+    if shown.len() == output.len() && output.chars().count() <= INLINE_MAX_CHARS && !output.contains("```") {
+        return ToolOutputReply::Text(format!("{heading}:\n```\n{}\n```", output.trim_end()));
+    }
+
+    let caption = if shown.len() < output.len() {
+        format!("{heading} (first {} of {} bytes)", shown.len(), output.len())
+    } else {
+        heading
+    };
+    ToolOutputReply::File {
+        filename: format!("{}-{}.txt", sanitize_filename(&invocation.tool), sanitize_filename(&invocation.call_id)),
+        data: shown.as_bytes().to_vec(),
+        caption,
+    }
+}
+
+/// The longest prefix of `text` within `max_bytes`, cut on a char boundary.
+fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(status: &str, output: Option<&str>) -> ToolInvocation {
+        ToolInvocation {
+            id: "1".into(),
+            session_id: "ses_1".into(),
+            channel_id: "discord:1:2".into(),
+            call_id: "call_1".into(),
+            tool: "bash".into(),
+            status: status.into(),
+            input: None,
+            output: output.map(str::to_string),
+            started_at: chrono::Utc::now(),
+            completed_at: None,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(OutputCommand::parse("!output"), Some(OutputCommand { call_id: None }));
+        assert_eq!(
+            OutputCommand::parse(" !output call_abc "),
+            Some(OutputCommand { call_id: Some("call_abc".into()) })
+        );
+        assert_eq!(OutputCommand::parse("!outputs"), None);
+        assert_eq!(OutputCommand::parse("show !output"), None);
+    }
+
+    #[test]
+    fn test_replies() {
+        assert_eq!(
+            tool_output_reply(&invocation("completed", Some("ok\n")), 1024),
+            ToolOutputReply::Text("Output of `bash` (`call_1`):\n```\nok\n```".into())
+        );
+        assert_eq!(
+            tool_output_reply(&invocation("running", None), 1024),
+            ToolOutputReply::Text("`bash` (`call_1`) is still running, so it has no output yet.".into())
+        );
+        assert_eq!(
+            tool_output_reply(&invocation("completed", None), 1024),
+            ToolOutputReply::Text("`bash` (`call_1`) has no stored output.".into())
+        );
+
+        let long = "é".repeat(2000);
+        let ToolOutputReply::File { filename, data, caption } = tool_output_reply(&invocation("error", Some(&long)), 1001)
+        else {
+            panic!("expected a file");
+        };
+        assert_eq!(filename, "bash-call_1.txt");
+        // Cut on a char boundary.
+        assert_eq!(data.len(), 1000);
+        assert_eq!(caption, "`bash` (`call_1`) failed (first 1000 of 4000 bytes)");
+    }
+}
@@ -0,0 +1,226 @@
+//! Permission requests and questions waiting on a human, behind `!pending`.
+//!
+//! Workers record each `permission.asked` and `question.asked` for their
+//! session in `PendingRegistry` and drop it again on the matching reply. A
+//! session that errors or a turn that ends some other way clears whatever
+//! its session still had outstanding, so the registry never lists a request
+//! nobody can answer anymore.
+
+use crate::opencode::types::{PermissionRequest, QuestionRequest, SseEvent};
+use crate::ChannelId;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// A request waiting on a reply.
+#[derive(Debug, Clone)]
+pub enum PendingRequest {
+    Permission(PermissionRequest),
+    Question(QuestionRequest),
+}
+
+impl PendingRequest {
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Permission(permission) => &permission.id,
+            Self::Question(question) => &question.id,
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        match self {
+            Self::Permission(permission) => &permission.session_id,
+            Self::Question(question) => &question.session_id,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PendingEntry {
+    channel_id: ChannelId,
+    request: PendingRequest,
+    asked_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Everything one channel is waiting on, oldest first.
+#[derive(Debug, Clone, Default)]
+pub struct PendingInteractions {
+    pub permissions: Vec<(PermissionRequest, chrono::DateTime<chrono::Utc>)>,
+    pub questions: Vec<(QuestionRequest, chrono::DateTime<chrono::Utc>)>,
+}
+
+impl PendingInteractions {
+    pub fn is_empty(&self) -> bool {
+        self.permissions.is_empty() && self.questions.is_empty()
+    }
+
+    /// Render the list as a chat message.
+    pub fn render(&self) -> String {
+        if self.is_empty() {
+            return "Nothing is waiting on a reply.".to_string();
+        }
+        let now = chrono::Utc::now();
+        let mut lines = Vec::new();
+        if !self.permissions.is_empty() {
+            lines.push("**Permissions:**".to_string());
+            for (permission, asked_at) in &self.permissions {
+                lines.push(format!(
+                    "- `{}` {}: {} ({})",
+                    permission.id,
+                    permission.permission.as_deref().unwrap_or("unknown"),
+                    permission.patterns.join(", "),
+                    waiting_for(now, *asked_at),
+                ));
+            }
+        }
+        if !self.questions.is_empty() {
+            lines.push("**Questions:**".to_string());
+            for (question, asked_at) in &self.questions {
+                let text: Vec<&str> = question
+                    .questions
+                    .iter()
+                    .filter_map(|info| info.question.as_deref().or(info.header.as_deref()))
+                    .collect();
+                lines.push(format!(
+                    "- `{}` {} ({})",
+                    question.id,
+                    if text.is_empty() { "(no text)".to_string() } else { text.join(" / ") },
+                    waiting_for(now, *asked_at),
+                ));
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+fn waiting_for(now: chrono::DateTime<chrono::Utc>, asked_at: chrono::DateTime<chrono::Utc>) -> String {
+    let secs = (now - asked_at).num_seconds().max(0);
+    if secs < 60 {
+        format!("waiting {secs}s")
+    } else {
+        format!("waiting {}m", secs / 60)
+    }
+}
+
+/// Outstanding permission requests and questions by request id, shared by
+/// every worker.
+#[derive(Debug, Default)]
+pub struct PendingRegistry {
+    entries: RwLock<HashMap<String, PendingEntry>>,
+}
+
+impl PendingRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Track an event from `session_id`, the session `channel_id`'s worker
+    /// is driving. Requests from other sessions are ignored; replies and
+    /// errors are matched by id and session.
+    pub fn observe(&self, channel_id: &ChannelId, session_id: &str, event: &SseEvent) {
+        match event {
+            SseEvent::PermissionAsked(permission) if permission.session_id == session_id => {
+                self.insert(channel_id, PendingRequest::Permission(permission.clone()));
+            }
+            SseEvent::QuestionAsked(question) if question.session_id == session_id => {
+                self.insert(channel_id, PendingRequest::Question(question.clone()));
+            }
+            SseEvent::PermissionReplied { request_id, .. } | SseEvent::QuestionReplied { request_id, .. } => {
+                self.write().remove(request_id);
+            }
+            SseEvent::SessionError { session_id: Some(errored), .. } if errored == session_id => {
+                self.clear_session(session_id);
+            }
+            _ => {}
+        }
+    }
+
+    fn insert(&self, channel_id: &ChannelId, request: PendingRequest) {
+        let entry = PendingEntry {
+            channel_id: channel_id.clone(),
+            request,
+            asked_at: chrono::Utc::now(),
+        };
+        self.write().insert(entry.request.id().to_string(), entry);
+    }
+
+    /// Forget every request from `session_id`, e.g. once its turn ended
+    /// without them being answered. Returns how many were dropped.
+    pub fn clear_session(&self, session_id: &str) -> usize {
+        let mut entries = self.write();
+        let before = entries.len();
+        entries.retain(|_, entry| entry.request.session_id() != session_id);
+        before - entries.len()
+    }
+
+    /// What `channel_id` is waiting on.
+    pub fn pending_interactions(&self, channel_id: &ChannelId) -> PendingInteractions {
+        let entries = self.entries.read().unwrap_or_else(|e| e.into_inner());
+        let mut matching: Vec<&PendingEntry> = entries.values().filter(|entry| &entry.channel_id == channel_id).collect();
+        matching.sort_by_key(|entry| entry.asked_at);
+
+        let mut pending = PendingInteractions::default();
+        for entry in matching {
+            match &entry.request {
+                PendingRequest::Permission(permission) => pending.permissions.push((permission.clone(), entry.asked_at)),
+                PendingRequest::Question(question) => pending.questions.push((question.clone(), entry.asked_at)),
+            }
+        }
+        pending
+    }
+
+    fn write(&self) -> std::sync::RwLockWriteGuard<'_, HashMap<String, PendingEntry>> {
+        self.entries.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: &str, properties: serde_json::Value) -> SseEvent {
+        SseEvent::from_envelope(
+            serde_json::from_value(serde_json::json!({ "type": event_type, "properties": properties })).unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_pending_registry() {
+        let registry = PendingRegistry::new();
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let other: ChannelId = "discord:1:3".parse().unwrap();
+
+        registry.observe(&channel_id, "ses_1", &event(
+            "permission.asked",
+            serde_json::json!({ "id": "per_1", "sessionID": "ses_1", "permission": "bash", "patterns": ["rm -rf target"] }),
+        ));
+        registry.observe(&channel_id, "ses_1", &event(
+            "question.asked",
+            serde_json::json!({ "id": "que_1", "sessionID": "ses_1", "questions": [{ "question": "Which branch?" }] }),
+        ));
+        // Another session's request isn't this worker's to track.
+        registry.observe(&channel_id, "ses_1", &event(
+            "permission.asked",
+            serde_json::json!({ "id": "per_2", "sessionID": "ses_2", "permission": "edit" }),
+        ));
+
+        let pending = registry.pending_interactions(&channel_id);
+        assert_eq!(pending.permissions.len(), 1);
+        assert_eq!(pending.questions.len(), 1);
+        let rendered = pending.render();
+        assert!(rendered.contains("`per_1` bash: rm -rf target"));
+        assert!(rendered.contains("`que_1` Which branch?"));
+        assert!(registry.pending_interactions(&other).is_empty());
+
+        registry.observe(&channel_id, "ses_1", &event(
+            "permission.replied",
+            serde_json::json!({ "sessionID": "ses_1", "requestID": "per_1", "reply": "once" }),
+        ));
+        assert!(registry.pending_interactions(&channel_id).permissions.is_empty());
+
+        // The session erroring clears what it was still waiting on.
+        registry.observe(&channel_id, "ses_1", &event("session.error", serde_json::json!({ "sessionID": "ses_1" })));
+        assert!(registry.pending_interactions(&channel_id).is_empty());
+        assert_eq!(registry.pending_interactions(&channel_id).render(), "Nothing is waiting on a reply.");
+    }
+}
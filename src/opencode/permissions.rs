@@ -0,0 +1,274 @@
+//! Interactive permission prompts for `PermissionMode::Ask`.
+//!
+//! A single prompt often makes OpenCode ask for several permissions at once
+//! (e.g. five bash commands). `PermissionBatcher` collects requests that land
+//! within a short window and releases them as one numbered prompt. Replies
+//! come back as text through the worker's input channel ("allow 1 3, reject 2")
+//! and resolve to one `PermissionReply` per request ID. Requests nobody
+//! answers expire after a configurable timeout and get rejected.
+
+use crate::opencode::types::{PermissionPrompt, PermissionReply, PermissionRequest};
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long an asked permission may go unanswered before it's rejected.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionTimeouts {
+    /// Applies to permission types without an override. `None` waits forever.
+    pub default: Option<Duration>,
+    /// Overrides keyed by permission type ("bash", "edit", ...).
+    pub per_type: HashMap<String, Duration>,
+}
+
+impl PermissionTimeouts {
+    pub fn for_permission(&self, permission: Option<&str>) -> Option<Duration> {
+        permission
+            .and_then(|permission| self.per_type.get(permission).copied())
+            .or(self.default)
+    }
+}
+
+/// Collects permission requests and tracks which ones still need a reply.
+#[derive(Debug)]
+pub struct PermissionBatcher {
+    window: Duration,
+    timeouts: PermissionTimeouts,
+    /// Requests waiting for the batch window to close.
+    collecting: Vec<PermissionRequest>,
+    deadline: Option<Instant>,
+    /// Requests shown to the channel that nobody has answered yet, with the
+    /// time each one expires.
+    awaiting: Vec<(PermissionRequest, Option<Instant>)>,
+}
+
+impl PermissionBatcher {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            timeouts: PermissionTimeouts::default(),
+            collecting: Vec::new(),
+            deadline: None,
+            awaiting: Vec::new(),
+        }
+    }
+
+    /// Reject requests left unanswered for longer than these timeouts.
+    pub fn with_timeouts(mut self, timeouts: PermissionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Add a request. The first request of a batch starts the window.
+    pub fn push(&mut self, request: PermissionRequest) {
+        if self.collecting.is_empty() {
+            self.deadline = Some(Instant::now() + self.window);
+        }
+        self.collecting.push(request);
+    }
+
+    /// When the current batch should be released, if one is collecting.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Close the current batch and start each request's timeout. Returns
+    /// every unanswered request, numbered from 1 in the order replies refer to them.
+    pub fn flush(&mut self) -> Vec<PermissionPrompt> {
+        self.deadline = None;
+        let now = Instant::now();
+        for request in self.collecting.drain(..) {
+            let expires_at = self
+                .timeouts
+                .for_permission(request.permission.as_deref())
+                .map(|timeout| now + timeout);
+            self.awaiting.push((request, expires_at));
+        }
+        self.awaiting
+            .iter()
+            .map(|(request, _)| PermissionPrompt::from(request))
+            .collect()
+    }
+
+    /// When the next unanswered request expires.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.awaiting.iter().filter_map(|(_, expires_at)| *expires_at).min()
+    }
+
+    /// Remove and return requests whose timeout has passed.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<PermissionRequest> {
+        let (expired, awaiting) = self
+            .awaiting
+            .drain(..)
+            .partition(|(_, expires_at)| expires_at.is_some_and(|at| at <= now));
+        self.awaiting = awaiting;
+        expired.into_iter().map(|(request, _)| request).collect()
+    }
+
+    pub fn has_awaiting(&self) -> bool {
+        !self.awaiting.is_empty()
+    }
+
+    /// Resolve a text reply against the awaiting requests.
+    ///
+    /// Returns `None` if the text isn't a permission reply, leaving every
+    /// request pending. Requests the reply doesn't mention stay pending, and
+    /// answered ones no longer time out.
+    pub fn apply_reply(&mut self, text: &str) -> Option<Vec<(PermissionRequest, PermissionReply)>> {
+        let selections = parse_permission_reply(text, self.awaiting.len())?;
+
+        let mut replies: Vec<Option<PermissionReply>> = vec![None; self.awaiting.len()];
+        for (index, reply) in selections {
+            replies[index] = Some(reply);
+        }
+
+        let mut resolved = Vec::new();
+        let mut still_awaiting = Vec::new();
+        for (entry, reply) in self.awaiting.drain(..).zip(replies) {
+            match reply {
+                Some(reply) => resolved.push((entry.0, reply)),
+                None => still_awaiting.push(entry),
+            }
+        }
+        self.awaiting = still_awaiting;
+        Some(resolved)
+    }
+
+    /// Drop a request answered out of band (e.g. from the OpenCode TUI).
+    pub fn remove(&mut self, request_id: &str) {
+        self.collecting.retain(|request| request.id != request_id);
+        self.awaiting.retain(|(request, _)| request.id != request_id);
+        if self.collecting.is_empty() {
+            self.deadline = None;
+        }
+    }
+}
+
+/// Parse a reply like `allow 1 2, always 3, reject 4` or `reject all`.
+///
+/// Clauses are separated by commas, semicolons, or newlines. Each starts with
+/// a verb (`allow`/`once`/`yes`, `always`, `reject`/`deny`/`no`) followed by
+/// 1-based item numbers or `all`; a bare verb applies to every item. Later
+/// clauses win. Returns 0-based indices, or `None` if anything doesn't parse.
+pub fn parse_permission_reply(text: &str, count: usize) -> Option<Vec<(usize, PermissionReply)>> {
+    let mut selections = Vec::new();
+
+    for clause in text.split([',', ';', '\n']) {
+        let mut words = clause.split_whitespace();
+        let Some(verb) = words.next() else {
+            continue;
+        };
+        let reply = match verb.to_lowercase().as_str() {
+            "allow" | "approve" | "once" | "yes" => PermissionReply::Once,
+            "always" => PermissionReply::Always,
+            "reject" | "deny" | "no" => PermissionReply::Reject,
+            _ => return None,
+        };
+
+        let targets: Vec<&str> = words.collect();
+        if targets.is_empty() || targets.iter().any(|t| t.eq_ignore_ascii_case("all")) {
+            selections.extend((0..count).map(|index| (index, reply.clone())));
+            continue;
+        }
+        for target in targets {
+            let number: usize = target.trim_start_matches('#').parse().ok()?;
+            if number == 0 || number > count {
+                return None;
+            }
+            selections.push((number - 1, reply.clone()));
+        }
+    }
+
+    (!selections.is_empty()).then_some(selections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(id: &str) -> PermissionRequest {
+        PermissionRequest {
+            id: id.into(),
+            session_id: "ses_1".into(),
+            permission: Some("bash".into()),
+            patterns: vec![format!("echo {id}")],
+            always: Vec::new(),
+            metadata: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_parse_permission_reply() {
+        let parsed = parse_permission_reply("allow 1 3, reject 2", 3).unwrap();
+        assert!(matches!(parsed[0], (0, PermissionReply::Once)));
+        assert!(matches!(parsed[1], (2, PermissionReply::Once)));
+        assert!(matches!(parsed[2], (1, PermissionReply::Reject)));
+
+        let parsed = parse_permission_reply("Always all", 2).unwrap();
+        assert_eq!(parsed.len(), 2);
+        assert!(parsed.iter().all(|(_, reply)| matches!(reply, PermissionReply::Always)));
+
+        assert!(parse_permission_reply("allow 4", 3).is_none());
+        assert!(parse_permission_reply("sure, go ahead", 3).is_none());
+        assert!(parse_permission_reply("", 3).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_and_partial_reply() {
+        let mut batcher = PermissionBatcher::new(Duration::from_millis(300));
+        assert!(batcher.deadline().is_none());
+
+        batcher.push(request("per_1"));
+        let deadline = batcher.deadline().unwrap();
+        batcher.push(request("per_2"));
+        batcher.push(request("per_3"));
+        assert_eq!(batcher.deadline(), Some(deadline));
+
+        let prompts = batcher.flush();
+        assert_eq!(prompts.len(), 3);
+        assert_eq!(prompts[1].permission_id, "per_2");
+        assert!(batcher.deadline().is_none());
+
+        let resolved = batcher.apply_reply("allow 1, reject 3").unwrap();
+        let ids: Vec<&str> = resolved.iter().map(|(request, _)| request.id.as_str()).collect();
+        assert_eq!(ids, ["per_1", "per_3"]);
+        assert!(batcher.has_awaiting());
+
+        // The remaining request is renumbered as item 1.
+        assert!(batcher.apply_reply("what?").is_none());
+        let resolved = batcher.apply_reply("always 1").unwrap();
+        assert_eq!(resolved[0].0.id, "per_2");
+        assert!(!batcher.has_awaiting());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unanswered_requests_expire() {
+        let timeouts = PermissionTimeouts {
+            default: Some(Duration::from_secs(60)),
+            per_type: HashMap::from([("edit".to_string(), Duration::from_secs(10))]),
+        };
+        let mut batcher = PermissionBatcher::new(Duration::from_millis(300)).with_timeouts(timeouts);
+
+        let mut edit = request("per_edit");
+        edit.permission = Some("edit".into());
+        batcher.push(request("per_bash"));
+        batcher.push(edit);
+        batcher.push(request("per_answered"));
+        let asked_at = Instant::now();
+        batcher.flush();
+        assert_eq!(batcher.next_expiry(), Some(asked_at + Duration::from_secs(10)));
+
+        // A human answer cancels that request's timer.
+        batcher.apply_reply("allow 3").unwrap();
+
+        let expired = batcher.take_expired(asked_at + Duration::from_secs(10));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, "per_edit");
+        assert_eq!(batcher.next_expiry(), Some(asked_at + Duration::from_secs(60)));
+
+        let expired = batcher.take_expired(asked_at + Duration::from_secs(60));
+        assert_eq!(expired[0].id, "per_bash");
+        assert!(!batcher.has_awaiting());
+    }
+}
@@ -0,0 +1,185 @@
+//! Per-channel system prompts for OpenCode workers (SQLite).
+//!
+//! Channels can want different personas or standing instructions.
+//! `ChannelPrompts` stores one system prompt per channel, set with
+//! `!persona <text>`, and workers send it as `SendPromptRequest.system`.
+//! Channels without one fall back to `default_system_prompt`. Prompts are
+//! templates: `{channel}`, `{channel_id}`, and `{date}` are filled in each
+//! time a worker starts.
+
+use crate::ChannelId;
+
+use sqlx::SqlitePool;
+
+/// A `!persona` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersonaCommand {
+    /// `!persona`: show the channel's system prompt.
+    Show,
+    /// `!persona reset`: go back to the default.
+    Reset,
+    /// `!persona <text>`
+    Set(String),
+}
+
+impl PersonaCommand {
+    /// Parse a persona command. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Self> {
+        let rest = text.trim().strip_prefix("!persona")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let command = match rest.trim() {
+            "" => Self::Show,
+            "reset" => Self::Reset,
+            prompt => Self::Set(prompt.to_string()),
+        };
+        Some(command)
+    }
+}
+
+/// Values substituted into a system prompt template.
+#[derive(Debug, Clone)]
+pub struct PromptVariables<'a> {
+    pub channel_id: &'a str,
+    /// The channel's display name. Falls back to the ID.
+    pub channel_name: Option<&'a str>,
+    pub date: chrono::NaiveDate,
+}
+
+/// Fill in `{channel}`, `{channel_id}`, and `{date}` (as `YYYY-MM-DD`).
+/// Other braces are left alone.
+pub fn render_system_prompt(template: &str, variables: &PromptVariables<'_>) -> String {
+    template
+        .replace("{channel_id}", variables.channel_id)
+        .replace("{channel}", variables.channel_name.unwrap_or(variables.channel_id))
+        .replace("{date}", &variables.date.format("%Y-%m-%d").to_string())
+}
+
+/// Why a system prompt wasn't saved.
+#[derive(Debug, thiserror::Error)]
+pub enum PersonaError {
+    #[error("system prompt is {chars} characters, over the {max_chars} character limit")]
+    TooLong { chars: usize, max_chars: usize },
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+/// Reads and writes each channel's system prompt.
+#[derive(Debug, Clone)]
+pub struct ChannelPrompts {
+    pool: SqlitePool,
+    max_chars: usize,
+}
+
+impl ChannelPrompts {
+    /// Refuse prompts longer than `max_chars`.
+    pub fn new(pool: SqlitePool, max_chars: usize) -> Self {
+        Self { pool, max_chars }
+    }
+
+    /// The channel's own system prompt template, if it has one.
+    pub async fn get_system_prompt(&self, channel_id: &ChannelId) -> anyhow::Result<Option<String>> {
+        Ok(sqlx::query_scalar("SELECT prompt FROM channel_system_prompts WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// Set the channel's system prompt template.
+    pub async fn set_system_prompt(&self, channel_id: &ChannelId, prompt: &str) -> Result<(), PersonaError> {
+        let chars = prompt.chars().count();
+        if chars > self.max_chars {
+            return Err(PersonaError::TooLong { chars, max_chars: self.max_chars });
+        }
+        sqlx::query(
+            "INSERT INTO channel_system_prompts (channel_id, prompt, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP) \
+             ON CONFLICT(channel_id) DO UPDATE SET prompt = excluded.prompt, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(channel_id.as_ref())
+        .bind(prompt)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Drop the channel's system prompt. Returns whether it had one.
+    pub async fn clear_system_prompt(&self, channel_id: &ChannelId) -> anyhow::Result<bool> {
+        let result = sqlx::query("DELETE FROM channel_system_prompts WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// The system prompt a worker in the channel should send: the channel's
+    /// own, or else `default`, rendered. `None` if neither is set.
+    pub async fn resolve(
+        &self,
+        channel_id: &ChannelId,
+        default: Option<&str>,
+        variables: &PromptVariables<'_>,
+    ) -> anyhow::Result<Option<String>> {
+        let template = self.get_system_prompt(channel_id).await?;
+        Ok(template
+            .as_deref()
+            .or(default)
+            .map(|template| render_system_prompt(template, variables)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[test]
+    fn test_parse_and_render() {
+        assert_eq!(PersonaCommand::parse("!persona"), Some(PersonaCommand::Show));
+        assert_eq!(PersonaCommand::parse(" !persona reset "), Some(PersonaCommand::Reset));
+        assert_eq!(
+            PersonaCommand::parse("!persona You are a pirate."),
+            Some(PersonaCommand::Set("You are a pirate.".into()))
+        );
+        assert_eq!(PersonaCommand::parse("!personal"), None);
+
+        let date = chrono::NaiveDate::from_ymd_opt(2026, 2, 16).unwrap();
+        let variables = PromptVariables { channel_id: "discord:1:2", channel_name: Some("#backend"), date };
+        assert_eq!(
+            render_system_prompt("Helping {channel} ({channel_id}) on {date}. Keep {braces}.", &variables),
+            "Helping #backend (discord:1:2) on 2026-02-16. Keep {braces}."
+        );
+        let unnamed = PromptVariables { channel_name: None, ..variables };
+        assert_eq!(render_system_prompt("In {channel}", &unnamed), "In discord:1:2");
+    }
+
+    #[tokio::test]
+    async fn test_channel_prompts() {
+        let prompts = ChannelPrompts::new(connect_in_memory().await, 20);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let variables = PromptVariables {
+            channel_id: "discord:1:2",
+            channel_name: Some("#backend"),
+            date: chrono::NaiveDate::from_ymd_opt(2026, 2, 16).unwrap(),
+        };
+
+        assert_eq!(prompts.resolve(&channel_id, None, &variables).await.unwrap(), None);
+        assert_eq!(
+            prompts.resolve(&channel_id, Some("Default for {channel}"), &variables).await.unwrap().as_deref(),
+            Some("Default for #backend")
+        );
+
+        prompts.set_system_prompt(&channel_id, "Be a pirate.").await.unwrap();
+        assert!(matches!(
+            prompts.set_system_prompt(&channel_id, &"x".repeat(21)).await,
+            Err(PersonaError::TooLong { chars: 21, max_chars: 20 })
+        ));
+        assert_eq!(
+            prompts.resolve(&channel_id, Some("Default"), &variables).await.unwrap().as_deref(),
+            Some("Be a pirate.")
+        );
+
+        assert!(prompts.clear_system_prompt(&channel_id).await.unwrap());
+        assert_eq!(prompts.get_system_prompt(&channel_id).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,487 @@
+//! Prompt construction for OpenCode sessions.
+//!
+//! Turns persisted channel state (compaction summaries, recent turns, channel
+//! metadata) plus a new inbound message into a `SendPromptRequest`, and builds
+//! the synthetic prompt used to produce compaction summaries.
+
+use crate::config::OpenCodeConfig;
+use crate::conversation::history::{ConversationLogger, ConversationMessage, RollingContext, merge_consecutive_turns};
+use crate::opencode::agents::ChannelAgents;
+use crate::opencode::models::ChannelModels;
+use crate::opencode::personas::{ChannelPrompts, PromptVariables};
+use crate::opencode::types::{PartInput, SendPromptRequest};
+use crate::opencode::worker::parse_model_param;
+use crate::ChannelId;
+
+use anyhow::bail;
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+/// Situational metadata about the channel a prompt comes from.
+///
+/// Rendered into a synthetic text part ahead of the user's message, so the
+/// model sees it without it counting as a user turn. Only the user's own
+/// message is ever logged to `conversation_messages`.
+#[derive(Debug, Clone)]
+pub struct ChannelContext {
+    pub channel_name: Option<String>,
+    pub participants: Vec<String>,
+    pub now: DateTime<Utc>,
+}
+
+impl ChannelContext {
+    pub fn new(channel_name: Option<String>, participants: Vec<String>) -> Self {
+        Self {
+            channel_name,
+            participants,
+            now: Utc::now(),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut text = String::from("[Channel context]\n");
+        if let Some(name) = &self.channel_name {
+            text.push_str(&format!("Channel: {name}\n"));
+        }
+        if !self.participants.is_empty() {
+            text.push_str(&format!("Participants: {}\n", self.participants.join(", ")));
+        }
+        text.push_str(&format!(
+            "Current time: {}\n",
+            self.now.format("%Y-%m-%d %H:%M UTC")
+        ));
+        text
+    }
+
+    /// Insert the rendered context as the first part of a request.
+    pub fn prepend_to(&self, request: &mut SendPromptRequest) {
+        request.parts.insert(
+            0,
+            PartInput::Text {
+                text: self.render(),
+                synthetic: Some(true),
+            },
+        );
+    }
+}
+
+/// The system prompt, model, and agent a channel's prompts are sent with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelPreferences {
+    pub system: Option<String>,
+    /// Model in "provider/model" form.
+    pub model: Option<String>,
+    /// OpenCode agent. `None` uses the server's default.
+    pub agent: Option<String>,
+}
+
+impl ChannelPreferences {
+    /// Resolve a channel's preferences from what was selected for it
+    /// (`ChannelAgents`, `ChannelModels`, `ChannelPrompts`), falling back to
+    /// the config's default model and system prompt. `channel_name` fills in
+    /// the system prompt template. A store that fails to load is logged and
+    /// left unset.
+    pub async fn resolve(
+        pool: &SqlitePool,
+        config: &OpenCodeConfig,
+        channel_id: &ChannelId,
+        channel_name: Option<&str>,
+    ) -> Self {
+        let agent = ChannelAgents::new(pool.clone()).get(channel_id).await.unwrap_or_else(|error| {
+            tracing::warn!(%error, %channel_id, "failed to load channel agent");
+            None
+        });
+        let model = match ChannelModels::new(pool.clone()).get(channel_id).await {
+            Ok(Some(model)) => Some(model.to_string()),
+            Ok(None) => config.default_model.clone(),
+            Err(error) => {
+                tracing::warn!(%error, %channel_id, "failed to load channel model");
+                None
+            }
+        };
+        let variables = PromptVariables {
+            channel_id,
+            channel_name,
+            date: chrono::Local::now().date_naive(),
+        };
+        let system = ChannelPrompts::new(pool.clone(), config.system_prompt_max_chars)
+            .resolve(channel_id, config.default_system_prompt.as_deref(), &variables)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::warn!(%error, %channel_id, "failed to load channel system prompt");
+                None
+            });
+        Self { system, model, agent }
+    }
+}
+
+/// Builds the full prompt for a single turn.
+///
+/// Layout, in order: the compaction summaries, the recent turns, and the
+/// channel context go in as synthetic text parts, followed by the new message as the only
+/// user-authored part. System prompt, model, and agent are carried on the
+/// request itself. Everything except the new message is optional.
+#[derive(Debug, Clone, Default)]
+pub struct TurnPromptBuilder {
+    message: String,
+    system: Option<String>,
+    summaries: Vec<String>,
+    turns: Vec<ConversationMessage>,
+    merge_turns: bool,
+    context: Option<ChannelContext>,
+    model: Option<String>,
+    agent: Option<String>,
+}
+
+impl TurnPromptBuilder {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn summary(mut self, summary: impl Into<String>) -> Self {
+        self.summaries = vec![summary.into()];
+        self
+    }
+
+    pub fn turns(mut self, turns: Vec<ConversationMessage>) -> Self {
+        self.turns = turns;
+        self
+    }
+
+    /// Merge consecutive same-role turns before rendering the transcript, so
+    /// back-to-back user messages read as one user turn.
+    pub fn merge_turns(mut self, merge: bool) -> Self {
+        self.merge_turns = merge;
+        self
+    }
+
+    pub fn context(mut self, context: ChannelContext) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Model in "provider/model" form. Strings without a provider are ignored.
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn agent(mut self, agent: impl Into<String>) -> Self {
+        self.agent = Some(agent.into());
+        self
+    }
+
+    /// Take the system prompt, model, and agent from a channel's
+    /// preferences. Ones the channel doesn't set are left as they were.
+    pub fn preferences(mut self, preferences: ChannelPreferences) -> Self {
+        self.system = preferences.system.or(self.system);
+        self.model = preferences.model.or(self.model);
+        self.agent = preferences.agent.or(self.agent);
+        self
+    }
+
+    /// Pull the channel's rolling context from the conversation store: its
+    /// compaction summaries and the last `turn_limit` messages after them
+    /// (see `ConversationLogger::assemble_context`).
+    pub async fn load_history(
+        mut self,
+        logger: &ConversationLogger,
+        channel_id: &ChannelId,
+        turn_limit: i64,
+    ) -> crate::error::Result<Self> {
+        let context = logger.assemble_context(channel_id, turn_limit).await?;
+        self.summaries = context.summaries.into_iter().map(|summary| summary.summary).collect();
+        self.turns = context.turns;
+        Ok(self)
+    }
+
+    pub fn build(self) -> SendPromptRequest {
+        let turns = if self.merge_turns {
+            merge_consecutive_turns(self.turns)
+        } else {
+            self.turns
+        };
+        let mut parts: Vec<PartInput> = RollingContext::summary_section(self.summaries.iter().map(String::as_str))
+            .into_iter()
+            .chain(RollingContext::transcript_section(&turns))
+            .map(|text| PartInput::Text { text, synthetic: Some(true) })
+            .collect();
+
+        if let Some(context) = &self.context {
+            parts.push(PartInput::Text {
+                text: context.render(),
+                synthetic: Some(true),
+            });
+        }
+
+        parts.push(PartInput::Text {
+            text: self.message,
+            synthetic: None,
+        });
+
+        SendPromptRequest {
+            parts,
+            system: self.system,
+            model: self.model.as_deref().and_then(parse_model_param),
+            agent: self.agent,
+            idempotency_key: None,
+        }
+    }
+}
+
+/// Instructions for the rolling-summary prompt sent during compaction.
+const COMPACTION_INSTRUCTIONS: &str = "\
+Summarize the conversation below into a concise rolling summary. Keep decisions, \
+open questions, commitments, and facts about the participants. Drop greetings and \
+small talk. If a previous summary is given, fold it in so the result stands on its \
+own. Reply with the summary text only.";
+
+/// Build the prompt that asks OpenCode to summarize a stretch of history.
+///
+/// The raw turns (with sender names and roles) and any prior summary go into
+/// a single synthetic text part. The reply is what gets passed to
+/// `ConversationLogger::save_compaction_summary`. Errors if `messages` is
+/// empty, since there is nothing to summarize.
+pub fn build_compaction_prompt(
+    messages: &[ConversationMessage],
+    prior_summary: Option<&str>,
+) -> anyhow::Result<SendPromptRequest> {
+    if messages.is_empty() {
+        bail!("no messages to compact");
+    }
+
+    let mut text = String::from(COMPACTION_INSTRUCTIONS);
+    if let Some(prior) = prior_summary.filter(|s| !s.trim().is_empty()) {
+        text.push_str("\n\n## Previous summary\n");
+        text.push_str(prior);
+    }
+    text.push_str("\n\n## Conversation\n");
+    for message in messages {
+        text.push_str(&format_turn(message));
+        text.push('\n');
+    }
+
+    Ok(SendPromptRequest {
+        parts: vec![PartInput::Text {
+            text,
+            synthetic: Some(true),
+        }],
+        system: None,
+        model: None,
+        agent: None,
+        idempotency_key: None,
+    })
+}
+
+/// Render one persisted message as a transcript line: `name (role): content`.
+pub(crate) fn format_turn(turn: &ConversationMessage) -> String {
+    // NULL sender names can come back as empty strings.
+    match turn.sender_name.as_deref().filter(|name| !name.is_empty()) {
+        Some(name) => format!("{name} ({}): {}", turn.role, turn.content),
+        None => format!("{}: {}", turn.role, turn.content),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::SqlitePool;
+    use crate::db::connect_in_memory;
+
+    #[tokio::test]
+    async fn test_build_full_turn_prompt() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        // Message logging is fire-and-forget, so seed rows directly.
+        for (id, role, sender, content) in [
+            ("m1", "user", Some("alice"), "can you fix the build?"),
+            ("m2", "assistant", None, "on it"),
+        ] {
+            sqlx::query(
+                "INSERT INTO conversation_messages (id, channel_id, role, sender_name, content) \
+                 VALUES (?, ?, ?, ?, ?)",
+            )
+            .bind(id)
+            .bind(channel_id.as_ref())
+            .bind(role)
+            .bind(sender)
+            .bind(content)
+            .execute(&pool)
+            .await
+            .unwrap();
+        }
+        logger
+            .save_compaction_summary(&channel_id, "alice is debugging CI", 12)
+            .await
+            .unwrap();
+
+        // The channel's own agent, model, and system prompt.
+        ChannelAgents::new(pool.clone()).set(&channel_id, "build").await.unwrap();
+        let model = crate::opencode::types::ModelParam {
+            provider_id: "anthropic".into(),
+            model_id: "claude-sonnet-4".into(),
+        };
+        ChannelModels::new(pool.clone()).set(&channel_id, &model).await.unwrap();
+        ChannelPrompts::new(pool.clone(), 200)
+            .set_system_prompt(&channel_id, "You are a helpful bot in {channel}.")
+            .await
+            .unwrap();
+        let config = OpenCodeConfig {
+            default_model: Some("openai/gpt-5".into()),
+            ..OpenCodeConfig::default()
+        };
+        let preferences = ChannelPreferences::resolve(&pool, &config, &channel_id, Some("#infra")).await;
+
+        let request = TurnPromptBuilder::new("it's still red")
+            .preferences(preferences)
+            .load_history(&logger, &channel_id, 50)
+            .await
+            .unwrap()
+            .build();
+
+        assert_eq!(request.system.as_deref(), Some("You are a helpful bot in #infra."));
+        assert_eq!(request.agent.as_deref(), Some("build"));
+        let model = request.model.expect("model");
+        assert_eq!(model.provider_id, "anthropic");
+        assert_eq!(model.model_id, "claude-sonnet-4");
+
+        let texts: Vec<(&str, Option<bool>)> = request
+            .parts
+            .iter()
+            .map(|part| match part {
+                PartInput::Text { text, synthetic } => (text.as_str(), *synthetic),
+                other => panic!("unexpected part {other:?}"),
+            })
+            .collect();
+        assert_eq!(texts.len(), 3);
+        assert!(texts[0].0.contains("alice is debugging CI"));
+        assert_eq!(texts[0].1, Some(true));
+        assert!(texts[1].0.contains("alice (user): can you fix the build?"));
+        assert!(texts[1].0.contains("assistant: on it"));
+        assert_eq!(texts[1].1, Some(true));
+        assert_eq!(texts[2], ("it's still red", None));
+
+        // A channel that chose nothing gets the config's defaults.
+        let other: ChannelId = "discord:1:3".parse().unwrap();
+        let defaults = ChannelPreferences::resolve(&pool, &config, &other, None).await;
+        assert_eq!(
+            defaults,
+            ChannelPreferences { system: None, model: Some("openai/gpt-5".into()), agent: None }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_load_history_skips_summarized_turns() {
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        for (id, content) in [("m1", "the build is red"), ("m2", "fixed the lockfile"), ("m3", "still red")] {
+            sqlx::query("INSERT INTO conversation_messages (id, channel_id, role, content) VALUES (?, ?, 'user', ?)")
+                .bind(id)
+                .bind(channel_id.as_ref())
+                .bind(content)
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+        let window = logger.load_since_last_compaction(&channel_id).await.unwrap();
+        logger
+            .save_compaction_summary_through(&channel_id, "the lockfile was fixed", &window[..2])
+            .await
+            .unwrap();
+
+        let request = TurnPromptBuilder::new("now what?")
+            .load_history(&logger, &channel_id, 50)
+            .await
+            .unwrap()
+            .build();
+
+        let PartInput::Text { text: transcript, .. } = &request.parts[1] else {
+            panic!("expected the transcript part");
+        };
+        assert!(transcript.contains("still red"));
+        assert!(!transcript.contains("fixed the lockfile"));
+    }
+
+    fn message(role: &str, sender_name: Option<&str>, content: &str) -> ConversationMessage {
+        ConversationMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            channel_id: "discord:1:2".into(),
+            role: role.into(),
+            sender_name: sender_name.map(String::from),
+            sender_id: None,
+            content: content.into(),
+            metadata: None,
+            token_count: None,
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_build_compaction_prompt() {
+        let messages = vec![
+            message("user", Some("alice"), "deploy is failing"),
+            message("assistant", None, "looking at the logs"),
+        ];
+        let request = build_compaction_prompt(&messages, Some("alice runs the infra")).unwrap();
+
+        assert_eq!(request.parts.len(), 1);
+        let PartInput::Text { text, synthetic } = &request.parts[0] else {
+            panic!("expected text part");
+        };
+        assert_eq!(*synthetic, Some(true));
+        assert!(text.contains("## Previous summary\nalice runs the infra"));
+        assert!(text.contains("alice (user): deploy is failing"));
+        assert!(text.contains("assistant: looking at the logs"));
+        assert!(text.find("Previous summary") < text.find("deploy is failing"));
+    }
+
+    #[test]
+    fn test_build_compaction_prompt_rejects_empty_history() {
+        assert!(build_compaction_prompt(&[], Some("old summary")).is_err());
+    }
+
+    #[test]
+    fn test_channel_context() {
+        let context = ChannelContext {
+            channel_name: Some("#infra".into()),
+            participants: vec!["alice".into(), "bob".into()],
+            now: "2026-02-16T09:30:00Z".parse().unwrap(),
+        };
+        let rendered = context.render();
+        assert!(rendered.contains("Channel: #infra"));
+        assert!(rendered.contains("Participants: alice, bob"));
+        assert!(rendered.contains("Current time: 2026-02-16 09:30 UTC"));
+
+        let mut request = TurnPromptBuilder::new("hi").build();
+        context.prepend_to(&mut request);
+        assert_eq!(request.parts.len(), 2);
+        assert!(matches!(&request.parts[0], PartInput::Text { synthetic: Some(true), .. }));
+        assert!(matches!(&request.parts[1], PartInput::Text { text, synthetic: None } if text == "hi"));
+
+        let request = TurnPromptBuilder::new("hi").context(context).build();
+        assert_eq!(request.parts.len(), 2);
+    }
+
+    #[test]
+    fn test_build_message_only() {
+        let request = TurnPromptBuilder::new("hello").build();
+        assert_eq!(request.parts.len(), 1);
+        assert!(request.system.is_none());
+        assert!(request.model.is_none());
+        assert!(request.agent.is_none());
+    }
+}
@@ -0,0 +1,377 @@
+//! Answering OpenCode `question.asked` requests.
+//!
+//! In `PermissionMode::Ask`, questions are posted to the channel and wait for
+//! a routed reply. Anything left unanswered past the timeout gets a default
+//! answer per question, chosen by a `QuestionDefault` strategy. Auto mode
+//! answers with the same defaults immediately.
+//!
+//! `QuestionReplyBuilder` collects answers to a multi-question request one at
+//! a time, checked against each question's options, and only produces the
+//! reply once every question has one.
+
+use crate::opencode::types::{QuestionAnswer, QuestionInfo, QuestionReplyRequest, QuestionRequest};
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How the default answer to a question is picked.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum QuestionDefault {
+    /// The first option (default).
+    #[default]
+    First,
+    /// The last option.
+    Last,
+    /// The option with this label, falling back to the first option.
+    Label(String),
+}
+
+impl QuestionDefault {
+    /// Pick the default answer for one question. Questions without options
+    /// are answered "continue".
+    pub fn select(&self, question: &QuestionInfo) -> QuestionAnswer {
+        let option = match self {
+            Self::First => question.options.first(),
+            Self::Last => question.options.last(),
+            Self::Label(label) => question
+                .options
+                .iter()
+                .find(|option| option.label.eq_ignore_ascii_case(label))
+                .or_else(|| question.options.first()),
+        };
+        match option {
+            Some(option) => QuestionAnswer {
+                label: option.label.clone(),
+                description: option.description.clone(),
+            },
+            None => QuestionAnswer {
+                label: "continue".to_string(),
+                description: None,
+            },
+        }
+    }
+
+    /// Default answers for every question in a request, in order.
+    pub fn answers(&self, questions: &[QuestionInfo]) -> Vec<QuestionAnswer> {
+        questions.iter().map(|question| self.select(question)).collect()
+    }
+}
+
+impl std::fmt::Display for QuestionDefault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::First => write!(f, "first"),
+            Self::Last => write!(f, "last"),
+            Self::Label(label) => write!(f, "label:{label}"),
+        }
+    }
+}
+
+impl std::str::FromStr for QuestionDefault {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            _ => match s.strip_prefix("label:") {
+                Some(label) if !label.is_empty() => Ok(Self::Label(label.to_string())),
+                _ => Err(format!("unknown question default: {}", s)),
+            },
+        }
+    }
+}
+
+/// Questions posted to the channel that haven't been answered yet.
+#[derive(Debug, Default)]
+pub struct PendingQuestions {
+    timeout: Option<Duration>,
+    pending: Vec<(QuestionRequest, Option<Instant>)>,
+}
+
+impl PendingQuestions {
+    /// `timeout` of `None` waits for a human forever.
+    pub fn new(timeout: Option<Duration>) -> Self {
+        Self {
+            timeout,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, request: QuestionRequest) {
+        let expires_at = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.pending.push((request, expires_at));
+    }
+
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// When the next pending question expires.
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.pending.iter().filter_map(|(_, expires_at)| *expires_at).min()
+    }
+
+    /// Remove and return questions whose timeout has passed.
+    pub fn take_expired(&mut self, now: Instant) -> Vec<QuestionRequest> {
+        let (expired, pending) = self
+            .pending
+            .drain(..)
+            .partition(|(_, expires_at)| expires_at.is_some_and(|at| at <= now));
+        self.pending = pending;
+        expired.into_iter().map(|(request, _)| request).collect()
+    }
+
+    /// Answer the oldest pending question with a text reply.
+    ///
+    /// Returns `None` (and keeps it pending) if the reply doesn't cover
+    /// every question in the request.
+    pub fn apply_reply(&mut self, text: &str) -> Option<(QuestionRequest, Vec<QuestionAnswer>)> {
+        let (request, _) = self.pending.first()?;
+        let answers = parse_question_reply(text, &request.questions)?;
+        let (request, _) = self.pending.remove(0);
+        Some((request, answers))
+    }
+
+    /// Drop a question answered out of band.
+    pub fn remove(&mut self, request_id: &str) {
+        self.pending.retain(|(request, _)| request.id != request_id);
+    }
+}
+
+/// Why an answer or a reply was refused by `QuestionReplyBuilder`.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QuestionReplyError {
+    /// The request has no question at this index.
+    #[error("there is no question {}", index + 1)]
+    NoSuchQuestion { index: usize },
+    /// The label isn't one of the question's options. Carries their labels.
+    #[error("\"{label}\" isn't an option for question {} (options: {})", index + 1, available.join(", "))]
+    UnknownOption { index: usize, label: String, available: Vec<String> },
+    /// Some questions have no answer yet. Carries their indices.
+    #[error("questions not answered yet: {}", missing.iter().map(|index| (index + 1).to_string()).collect::<Vec<_>>().join(", "))]
+    Missing { missing: Vec<usize> },
+}
+
+/// Collects one answer per question of a request, in any order, and builds
+/// the reply with answers in question order.
+#[derive(Debug, Clone)]
+pub struct QuestionReplyBuilder<'a> {
+    questions: &'a [QuestionInfo],
+    answers: Vec<Option<QuestionAnswer>>,
+}
+
+impl<'a> QuestionReplyBuilder<'a> {
+    pub fn new(questions: &'a [QuestionInfo]) -> Self {
+        Self {
+            questions,
+            answers: vec![None; questions.len()],
+        }
+    }
+
+    /// Answer question `index` with the option labelled `label`
+    /// (case-insensitive). Questions without options take any label.
+    /// Answering again replaces the earlier answer.
+    pub fn answer(&mut self, index: usize, label: &str) -> Result<&mut Self, QuestionReplyError> {
+        let question = self
+            .questions
+            .get(index)
+            .ok_or(QuestionReplyError::NoSuchQuestion { index })?;
+        let answer = if question.options.is_empty() {
+            QuestionAnswer {
+                label: label.to_string(),
+                description: None,
+            }
+        } else {
+            let option = question
+                .options
+                .iter()
+                .find(|option| option.label.eq_ignore_ascii_case(label))
+                .ok_or_else(|| QuestionReplyError::UnknownOption {
+                    index,
+                    label: label.to_string(),
+                    available: question.options.iter().map(|option| option.label.clone()).collect(),
+                })?;
+            QuestionAnswer {
+                label: option.label.clone(),
+                description: option.description.clone(),
+            }
+        };
+        self.answers[index] = Some(answer);
+        Ok(self)
+    }
+
+    /// Indices of the questions still without an answer.
+    pub fn missing(&self) -> Vec<usize> {
+        self.answers
+            .iter()
+            .enumerate()
+            .filter(|(_, answer)| answer.is_none())
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.answers.iter().all(Option::is_some)
+    }
+
+    /// The reply, once every question is answered.
+    pub fn build(&self) -> Result<QuestionReplyRequest, QuestionReplyError> {
+        let missing = self.missing();
+        if !missing.is_empty() {
+            return Err(QuestionReplyError::Missing { missing });
+        }
+        Ok(QuestionReplyRequest {
+            answers: self.answers.iter().flatten().cloned().collect(),
+        })
+    }
+}
+
+/// Parse a reply to a question request.
+///
+/// Multi-question requests take one answer per line (or `;`-separated), in
+/// order. Each answer is an option number (1-based), an option label
+/// (case-insensitive), or free text used as a custom answer.
+pub fn parse_question_reply(text: &str, questions: &[QuestionInfo]) -> Option<Vec<QuestionAnswer>> {
+    let parts: Vec<&str> = if questions.len() > 1 {
+        text.split(['\n', ';'])
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect()
+    } else {
+        vec![text.trim()]
+    };
+    if parts.len() != questions.len().max(1) || parts.iter().any(|part| part.is_empty()) {
+        return None;
+    }
+
+    let answers = parts
+        .iter()
+        .zip(questions)
+        .map(|(part, question)| {
+            let option = part
+                .parse::<usize>()
+                .ok()
+                .and_then(|number| question.options.get(number.checked_sub(1)?))
+                .or_else(|| {
+                    question
+                        .options
+                        .iter()
+                        .find(|option| option.label.eq_ignore_ascii_case(part))
+                });
+            match option {
+                Some(option) => QuestionAnswer {
+                    label: option.label.clone(),
+                    description: option.description.clone(),
+                },
+                None => QuestionAnswer {
+                    label: part.to_string(),
+                    description: None,
+                },
+            }
+        })
+        .collect();
+    Some(answers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opencode::types::QuestionOption;
+
+    fn question(labels: &[&str]) -> QuestionInfo {
+        QuestionInfo {
+            question: Some("which?".into()),
+            header: None,
+            options: labels
+                .iter()
+                .map(|label| QuestionOption {
+                    label: label.to_string(),
+                    description: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_default_strategies() {
+        let questions = [question(&["Yes", "No", "Skip"]), question(&[])];
+
+        let first: Vec<String> = QuestionDefault::First
+            .answers(&questions)
+            .into_iter()
+            .map(|answer| answer.label)
+            .collect();
+        assert_eq!(first, ["Yes", "continue"]);
+        assert_eq!(QuestionDefault::Last.select(&questions[0]).label, "Skip");
+        assert_eq!(QuestionDefault::Label("no".into()).select(&questions[0]).label, "No");
+        assert_eq!(QuestionDefault::Label("maybe".into()).select(&questions[0]).label, "Yes");
+
+        assert_eq!("label:Skip".parse(), Ok(QuestionDefault::Label("Skip".into())));
+        assert!("label:".parse::<QuestionDefault>().is_err());
+        assert!("middle".parse::<QuestionDefault>().is_err());
+    }
+
+    #[test]
+    fn test_parse_question_reply() {
+        let questions = [question(&["Yes", "No"]), question(&["sqlite", "postgres"])];
+        let answers = parse_question_reply("2\nPostgres", &questions).unwrap();
+        assert_eq!(answers[0].label, "No");
+        assert_eq!(answers[1].label, "postgres");
+
+        // Missing an answer for the second question.
+        assert!(parse_question_reply("yes", &questions).is_none());
+
+        let answers = parse_question_reply("use mysql instead", &questions[1..]).unwrap();
+        assert_eq!(answers[0].label, "use mysql instead");
+    }
+
+    #[test]
+    fn test_reply_builder() {
+        let questions = [question(&["Yes", "No"]), question(&[]), question(&["sqlite", "postgres"])];
+        let mut builder = QuestionReplyBuilder::new(&questions);
+
+        builder.answer(2, "Postgres").unwrap().answer(0, "yes").unwrap();
+        assert_eq!(builder.missing(), [1]);
+        let error = builder.build().unwrap_err();
+        assert_eq!(error, QuestionReplyError::Missing { missing: vec![1] });
+        assert_eq!(error.to_string(), "questions not answered yet: 2");
+
+        assert_eq!(
+            builder.answer(0, "maybe").unwrap_err(),
+            QuestionReplyError::UnknownOption {
+                index: 0,
+                label: "maybe".into(),
+                available: vec!["Yes".into(), "No".into()],
+            }
+        );
+        assert_eq!(builder.answer(3, "x").unwrap_err(), QuestionReplyError::NoSuchQuestion { index: 3 });
+
+        builder.answer(1, "anything goes").unwrap().answer(0, "No").unwrap();
+        assert!(builder.is_complete());
+        let labels: Vec<String> = builder.build().unwrap().answers.into_iter().map(|answer| answer.label).collect();
+        assert_eq!(labels, ["No", "anything goes", "postgres"]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pending_questions_expire_unless_answered() {
+        let request = |id: &str| QuestionRequest {
+            id: id.into(),
+            session_id: "ses_1".into(),
+            questions: vec![question(&["Yes", "No"])],
+        };
+        let mut pending = PendingQuestions::new(Some(Duration::from_secs(30)));
+        pending.push(request("que_1"));
+        pending.push(request("que_2"));
+        let asked_at = Instant::now();
+
+        let (answered, answers) = pending.apply_reply("no").unwrap();
+        assert_eq!(answered.id, "que_1");
+        assert_eq!(answers[0].label, "No");
+
+        let expired = pending.take_expired(asked_at + Duration::from_secs(30));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].id, "que_2");
+        assert!(!pending.has_pending());
+    }
+}
@@ -0,0 +1,196 @@
+//! Follow-ups waiting for an interactive worker's next turn.
+//!
+//! Messages that arrive while a turn runs, or while the worker waits out the
+//! coalescing window before starting one, queue in a `PromptQueue`. When the
+//! next turn starts, consecutive short messages are merged into one prompt
+//! with a text part each, so a user typing several lines in a row costs one
+//! turn instead of several. A message starting with `!priority` jumps ahead
+//! of everything queued and is sent on its own.
+
+use crate::opencode::types::PartInput;
+use crate::WorkerInput;
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+const PRIORITY_PREFIX: &str = "!priority";
+
+/// The text of a `!priority` message with the marker removed, or `None` if
+/// the message isn't marked.
+pub fn strip_priority(message: &str) -> Option<&str> {
+    let rest = message.trim_start().strip_prefix(PRIORITY_PREFIX)?;
+    if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+        return None;
+    }
+    Some(rest.trim_start())
+}
+
+/// Messages sent together as one turn.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PromptBatch {
+    /// Oldest first. Never empty.
+    pub messages: Vec<String>,
+    /// Keys of the user messages the batch was routed for, oldest first.
+    pub message_keys: Vec<String>,
+    pub priority: bool,
+}
+
+impl PromptBatch {
+    fn new(inputs: Vec<WorkerInput>, priority: bool) -> Self {
+        let mut messages = Vec::with_capacity(inputs.len());
+        let mut message_keys = Vec::new();
+        for input in inputs {
+            messages.push(input.text);
+            message_keys.extend(input.message_key);
+        }
+        Self { messages, message_keys, priority }
+    }
+
+    /// The first message as the prompt and the rest as extra text parts.
+    /// The prompt is keyed by every user message in the batch.
+    pub fn into_prompt(self) -> (WorkerInput, Vec<PartInput>) {
+        let mut messages = self.messages.into_iter();
+        let text = messages.next().unwrap_or_default();
+        let parts = messages
+            .map(|text| PartInput::Text { text, synthetic: None })
+            .collect();
+        let message_key = (!self.message_keys.is_empty()).then(|| self.message_keys.join("+"));
+        (WorkerInput { text, message_key }, parts)
+    }
+}
+
+/// Queued follow-ups for one channel's worker.
+#[derive(Debug, Clone, Default)]
+pub struct PromptQueue {
+    window: Duration,
+    max_chars: usize,
+    priority: VecDeque<WorkerInput>,
+    normal: VecDeque<WorkerInput>,
+}
+
+impl PromptQueue {
+    /// Wait up to `window` for more messages before a turn, and merge queued
+    /// messages into one prompt while it stays within `max_chars`. A zero
+    /// window turns coalescing off: every message is its own turn.
+    pub fn new(window: Duration, max_chars: usize) -> Self {
+        Self {
+            window,
+            max_chars,
+            ..Self::default()
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Queue a message. `!priority` messages go ahead of the others, in the
+    /// order they arrived.
+    pub fn push(&mut self, message: WorkerInput) {
+        match strip_priority(&message.text) {
+            Some(text) => self.priority.push_back(WorkerInput { text: text.to_string(), ..message }),
+            None => self.normal.push_back(message),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.priority.len() + self.normal.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.priority.is_empty() && self.normal.is_empty()
+    }
+
+    /// Whether waiting longer can't change the next batch: a priority
+    /// message is queued, coalescing is off, or the queued messages already
+    /// fill a prompt.
+    pub fn is_full(&self) -> bool {
+        if !self.priority.is_empty() || self.window.is_zero() {
+            return true;
+        }
+        let queued: usize = self.normal.iter().map(|message| message.text.chars().count()).sum();
+        queued >= self.max_chars
+    }
+
+    /// The next turn's messages: the oldest priority message alone, or else
+    /// the oldest message plus those after it that fit within `max_chars`.
+    /// A single message over the limit is still sent, alone.
+    pub fn pop_batch(&mut self) -> Option<PromptBatch> {
+        if let Some(message) = self.priority.pop_front() {
+            return Some(PromptBatch::new(vec![message], true));
+        }
+
+        let first = self.normal.pop_front()?;
+        let mut chars = first.text.chars().count();
+        let mut messages = vec![first];
+        if !self.window.is_zero() {
+            while let Some(next) = self.normal.front() {
+                let next_chars = next.text.chars().count();
+                if chars + next_chars > self.max_chars {
+                    break;
+                }
+                chars += next_chars;
+                messages.extend(self.normal.pop_front());
+            }
+        }
+        Some(PromptBatch::new(messages, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keyed(text: &str, message_key: &str) -> WorkerInput {
+        WorkerInput { text: text.into(), message_key: Some(message_key.into()) }
+    }
+
+    #[test]
+    fn test_strip_priority() {
+        assert_eq!(strip_priority("!priority the build is down"), Some("the build is down"));
+        assert_eq!(strip_priority("  !priority"), Some(""));
+        assert_eq!(strip_priority("!priorityqueue"), None);
+        assert_eq!(strip_priority("what's the !priority?"), None);
+    }
+
+    #[test]
+    fn test_coalesces_and_prioritizes() {
+        let mut queue = PromptQueue::new(Duration::from_millis(500), 25);
+        queue.push(keyed("fix the test", "m1"));
+        queue.push(keyed("in auth.rs", "m2"));
+        assert!(!queue.is_full());
+        queue.push("and run clippy".into());
+        assert!(queue.is_full());
+        queue.push(keyed("!priority stop, prod is down", "m3"));
+        assert_eq!(queue.len(), 4);
+
+        assert_eq!(
+            queue.pop_batch(),
+            Some(PromptBatch {
+                messages: vec!["stop, prod is down".into()],
+                message_keys: vec!["m3".into()],
+                priority: true,
+            })
+        );
+        let batch = queue.pop_batch().unwrap();
+        assert_eq!(batch.messages, ["fix the test", "in auth.rs"]);
+        let (prompt, parts) = batch.into_prompt();
+        assert_eq!(prompt.text, "fix the test");
+        assert_eq!(prompt.message_key.as_deref(), Some("m1+m2"));
+        assert!(matches!(&parts[..], [PartInput::Text { text, .. }] if text == "in auth.rs"));
+
+        let (prompt, _) = queue.pop_batch().unwrap().into_prompt();
+        assert_eq!(prompt, WorkerInput::from("and run clippy"));
+        assert_eq!(queue.pop_batch(), None);
+    }
+
+    #[test]
+    fn test_zero_window_sends_one_at_a_time() {
+        let mut queue = PromptQueue::new(Duration::ZERO, 1000);
+        queue.push("one".into());
+        queue.push("two".into());
+        assert!(queue.is_full());
+        assert_eq!(queue.pop_batch().unwrap().messages, ["one"]);
+        assert_eq!(queue.pop_batch().unwrap().messages, ["two"]);
+    }
+}
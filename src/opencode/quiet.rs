@@ -0,0 +1,537 @@
+//! Quiet hours: a per-channel schedule during which the bot doesn't work.
+//!
+//! A schedule is a list of daily time ranges, optionally limited to some
+//! weekdays, in a timezone: an IANA name such as `Europe/Berlin`, which
+//! follows daylight saving, or a fixed UTC offset. Ranges whose end is before
+//! their start run past midnight. During quiet hours a message either gets an
+//! "offline until" notice or is queued and handled once the quiet period
+//! ends, depending on the schedule's `QuietMode`. Users listed in
+//! `quiet_hours_bypass_users` are served as usual.
+//!
+//! Schedules are set with `!quiet` and kept in the channel settings store
+//! under `QUIET_HOURS_KEY`, so they survive restarts. Queued messages are
+//! held in memory and don't.
+
+use chrono::{DateTime, Datelike as _, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, TimeZone as _, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Channel settings key the schedule is stored under.
+pub const QUIET_HOURS_KEY: &str = "quiet_hours";
+
+/// Adjacent ranges are followed at most this far when working out when a
+/// quiet period ends, so a schedule covering the whole week terminates.
+const MAX_CHAINED_RANGES: usize = 16;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+const WEEKDAYS: [Weekday; 7] = [
+    Weekday::Mon,
+    Weekday::Tue,
+    Weekday::Wed,
+    Weekday::Thu,
+    Weekday::Fri,
+    Weekday::Sat,
+    Weekday::Sun,
+];
+
+/// What happens to messages during quiet hours.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QuietMode {
+    /// Reply that the bot is offline, and when it's back. The message is
+    /// dropped.
+    #[default]
+    Notice,
+    /// Hold the message and handle it when quiet hours end.
+    Queue,
+}
+
+impl QuietMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Notice => "notice",
+            Self::Queue => "queue",
+        }
+    }
+}
+
+/// Why a schedule didn't parse.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QuietHoursError {
+    #[error("`{0}` isn't a time range; use HH:MM-HH:MM, optionally after days, e.g. `sat,sun@00:00-24:00`")]
+    Range(String),
+    #[error("`{0}` isn't a day; use mon, tue, wed, thu, fri, sat, sun, or a range like mon-fri")]
+    Day(String),
+    #[error("`{0}` isn't a timezone; use an IANA name like Europe/Berlin, UTC, or an offset like +02:00")]
+    Timezone(String),
+    #[error("`{0}` isn't a mode; use notice or queue")]
+    Mode(String),
+    #[error("a schedule needs at least one time range")]
+    Empty,
+}
+
+/// One daily quiet range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuietRange {
+    /// Bitmask of weekdays (Monday = bit 0) the range starts on. `None` for
+    /// every day.
+    days: Option<u8>,
+    /// Minutes after local midnight, 0 to 1440.
+    start: u32,
+    end: u32,
+}
+
+impl QuietRange {
+    fn starts_on(&self, weekday: Weekday) -> bool {
+        self.days.is_none_or(|days| days & (1 << weekday.num_days_from_monday()) != 0)
+    }
+
+    /// The stretch of local time this range covers when it starts on `date`,
+    /// or `None` if it doesn't start that day.
+    fn on(&self, date: NaiveDate) -> Option<(NaiveDateTime, NaiveDateTime)> {
+        if !self.starts_on(date.weekday()) {
+            return None;
+        }
+        let midnight = date.and_hms_opt(0, 0, 0)?;
+        let start = midnight + Duration::minutes(self.start.into());
+        let mut end = midnight + Duration::minutes(self.end.into());
+        if self.end <= self.start {
+            end += Duration::days(1);
+        }
+        Some((start, end))
+    }
+}
+
+impl FromStr for QuietRange {
+    type Err = QuietHoursError;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || QuietHoursError::Range(text.to_string());
+        let (days, times) = match text.split_once('@') {
+            Some((days, times)) => (Some(parse_days(days)?), times),
+            None => (None, text),
+        };
+        let (start, end) = times.split_once('-').ok_or_else(invalid)?;
+        let start = parse_minutes(start).ok_or_else(invalid)?;
+        let end = parse_minutes(end).ok_or_else(invalid)?;
+        if start == MINUTES_PER_DAY || start == end {
+            return Err(invalid());
+        }
+        Ok(Self { days, start, end })
+    }
+}
+
+impl fmt::Display for QuietRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(days) = self.days {
+            let names: Vec<&str> = WEEKDAYS
+                .iter()
+                .filter(|day| days & (1 << day.num_days_from_monday()) != 0)
+                .map(|day| weekday_name(*day))
+                .collect();
+            write!(f, "{}@", names.join(","))?;
+        }
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02}",
+            self.start / 60,
+            self.start % 60,
+            self.end / 60,
+            self.end % 60
+        )
+    }
+}
+
+impl Serialize for QuietRange {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for QuietRange {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// The timezone a schedule's ranges are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuietTimezone {
+    /// An IANA zone, e.g. `Europe/Berlin`, with its daylight saving rules.
+    Named(Tz),
+    /// A fixed UTC offset, e.g. `+02:00`.
+    Offset(FixedOffset),
+}
+
+impl QuietTimezone {
+    fn to_local(self, at: DateTime<Utc>) -> NaiveDateTime {
+        match self {
+            Self::Named(tz) => at.with_timezone(&tz).naive_local(),
+            Self::Offset(offset) => at.with_timezone(&offset).naive_local(),
+        }
+    }
+
+    /// The instant a local time falls on. A time repeated when the clocks go
+    /// back is taken the first time round; one skipped when they go forward
+    /// is read with the offset in force before the jump.
+    fn to_utc(self, local: NaiveDateTime) -> DateTime<Utc> {
+        let tz = match self {
+            Self::Named(tz) => tz,
+            Self::Offset(offset) => {
+                return (local - Duration::seconds(offset.local_minus_utc().into())).and_utc();
+            }
+        };
+        let resolve = |local: NaiveDateTime| match tz.from_local_datetime(&local) {
+            LocalResult::Single(at) | LocalResult::Ambiguous(at, _) => Some(at.with_timezone(&Utc)),
+            LocalResult::None => None,
+        };
+        resolve(local)
+            .or_else(|| resolve(local - Duration::hours(1)).map(|at| at + Duration::hours(1)))
+            .unwrap_or_else(|| local.and_utc())
+    }
+
+    /// `at` as a weekday and time in this zone, for messages.
+    fn format(self, at: DateTime<Utc>) -> String {
+        match self {
+            Self::Named(tz) => at.with_timezone(&tz).format("%a %H:%M %Z").to_string(),
+            Self::Offset(offset) => {
+                format!("{} {}", at.with_timezone(&offset).format("%a %H:%M"), format_offset(offset))
+            }
+        }
+    }
+}
+
+impl Default for QuietTimezone {
+    fn default() -> Self {
+        Self::Named(Tz::UTC)
+    }
+}
+
+impl FromStr for QuietTimezone {
+    type Err = QuietHoursError;
+
+    /// An IANA name, or `UTC`, `Z`, or `±HH:MM`.
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        if text.eq_ignore_ascii_case("utc") || text == "Z" {
+            return Ok(Self::default());
+        }
+        if text.starts_with(['+', '-']) {
+            return parse_offset(text).map(Self::Offset);
+        }
+        text.parse::<Tz>()
+            .map(Self::Named)
+            .map_err(|_| QuietHoursError::Timezone(text.to_string()))
+    }
+}
+
+impl fmt::Display for QuietTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Named(tz) => f.write_str(tz.name()),
+            Self::Offset(offset) => f.write_str(&format_offset(*offset)),
+        }
+    }
+}
+
+impl Serialize for QuietTimezone {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for QuietTimezone {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// A channel's quiet-hours schedule.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub ranges: Vec<QuietRange>,
+    /// Timezone the ranges are in, e.g. `Europe/Berlin` or `+02:00`.
+    pub timezone: QuietTimezone,
+    #[serde(default)]
+    pub mode: QuietMode,
+}
+
+impl QuietHours {
+    /// Whether `now` falls in quiet hours.
+    pub fn is_quiet(&self, now: DateTime<Utc>) -> bool {
+        self.active_end(now).is_some()
+    }
+
+    /// When the quiet period `now` falls in ends, or `None` if it isn't
+    /// quiet. Ranges that meet or overlap count as one period.
+    pub fn quiet_until(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut until = self.active_end(now)?;
+        for _ in 0..MAX_CHAINED_RANGES {
+            match self.active_end(until) {
+                Some(next) if next > until => until = next,
+                _ => break,
+            }
+        }
+        Some(until)
+    }
+
+    /// `at` in the schedule's timezone, for messages.
+    pub fn format_local(&self, at: DateTime<Utc>) -> String {
+        self.timezone.format(at)
+    }
+
+    /// The latest end among ranges covering `now`.
+    fn active_end(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let local = self.timezone.to_local(now);
+        let today = local.date();
+        let dates = [today.pred_opt(), Some(today)];
+        self.ranges
+            .iter()
+            .flat_map(|range| dates.iter().flatten().filter_map(move |date| range.on(*date)))
+            .filter(|(start, end)| *start <= local && local < *end)
+            .map(|(_, end)| self.timezone.to_utc(end))
+            .max()
+    }
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ranges: Vec<String> = self.ranges.iter().map(ToString::to_string).collect();
+        write!(
+            f,
+            "{} ({}, {})",
+            ranges.join(" "),
+            self.timezone,
+            self.mode.as_str()
+        )
+    }
+}
+
+/// A `!quiet` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QuietCommand {
+    /// `!quiet`: show the schedule.
+    Show,
+    /// `!quiet off`: remove it.
+    Off,
+    /// `!quiet [tz=<zone>] [mode=notice|queue] <range>...`
+    Set(QuietHours),
+}
+
+impl QuietCommand {
+    /// Parse a quiet-hours command. Returns `None` for any other message,
+    /// and `Some(Err(..))` for a `!quiet` with a bad schedule.
+    pub fn parse(text: &str) -> Option<Result<Self, QuietHoursError>> {
+        let rest = text.trim().strip_prefix("!quiet")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let command = match rest.trim() {
+            "" => Ok(Self::Show),
+            "off" => Ok(Self::Off),
+            schedule => parse_schedule(schedule).map(Self::Set),
+        };
+        Some(command)
+    }
+}
+
+/// Parse whitespace-separated `tz=`, `mode=`, and range tokens.
+fn parse_schedule(text: &str) -> Result<QuietHours, QuietHoursError> {
+    let mut ranges = Vec::new();
+    let mut timezone = QuietTimezone::default();
+    let mut mode = QuietMode::default();
+    for token in text.split_whitespace() {
+        if let Some(value) = token.strip_prefix("tz=") {
+            timezone = value.parse()?;
+        } else if let Some(value) = token.strip_prefix("mode=") {
+            mode = match value {
+                "notice" => QuietMode::Notice,
+                "queue" => QuietMode::Queue,
+                _ => return Err(QuietHoursError::Mode(value.to_string())),
+            };
+        } else {
+            ranges.push(token.parse()?);
+        }
+    }
+    if ranges.is_empty() {
+        return Err(QuietHoursError::Empty);
+    }
+    Ok(QuietHours { ranges, timezone, mode })
+}
+
+/// `HH:MM` as minutes after midnight. `24:00` is allowed, as an end.
+fn parse_minutes(text: &str) -> Option<u32> {
+    let (hours, minutes) = text.split_once(':')?;
+    if hours.is_empty() || hours.len() > 2 || minutes.len() != 2 {
+        return None;
+    }
+    let hours: u32 = hours.parse().ok()?;
+    let minutes: u32 = minutes.parse().ok()?;
+    let total = hours * 60 + minutes;
+    (minutes < 60 && total <= MINUTES_PER_DAY).then_some(total)
+}
+
+/// `mon-fri`, `sat,sun`, or a mix such as `mon,wed-fri`, as a bitmask.
+fn parse_days(text: &str) -> Result<u8, QuietHoursError> {
+    let day = |name: &str| -> Result<u32, QuietHoursError> {
+        WEEKDAYS
+            .iter()
+            .find(|day| weekday_name(**day) == name.to_ascii_lowercase())
+            .map(|day| day.num_days_from_monday())
+            .ok_or_else(|| QuietHoursError::Day(name.to_string()))
+    };
+    let mut mask = 0u8;
+    for part in text.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => {
+                let (first, last) = (day(first)?, day(last)?);
+                // A range like fri-mon wraps around the weekend.
+                let mut current = first;
+                loop {
+                    mask |= 1 << current;
+                    if current == last {
+                        break;
+                    }
+                    current = (current + 1) % 7;
+                }
+            }
+            None => mask |= 1 << day(part)?,
+        }
+    }
+    Ok(mask)
+}
+
+fn weekday_name(day: Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "mon",
+        Weekday::Tue => "tue",
+        Weekday::Wed => "wed",
+        Weekday::Thu => "thu",
+        Weekday::Fri => "fri",
+        Weekday::Sat => "sat",
+        Weekday::Sun => "sun",
+    }
+}
+
+/// `±HH:MM`.
+fn parse_offset(text: &str) -> Result<FixedOffset, QuietHoursError> {
+    let invalid = || QuietHoursError::Timezone(text.to_string());
+    let (sign, rest) = match text.as_bytes().first() {
+        Some(b'+') => (1, &text[1..]),
+        Some(b'-') => (-1, &text[1..]),
+        _ => return Err(invalid()),
+    };
+    let minutes = parse_minutes(rest).filter(|minutes| *minutes < MINUTES_PER_DAY).ok_or_else(invalid)?;
+    FixedOffset::east_opt(sign * minutes as i32 * 60).ok_or_else(invalid)
+}
+
+fn format_offset(offset: FixedOffset) -> String {
+    let seconds = offset.local_minus_utc();
+    if seconds == 0 {
+        return "UTC".to_string();
+    }
+    let sign = if seconds < 0 { '-' } else { '+' };
+    let minutes = seconds.unsigned_abs() / 60;
+    format!("{sign}{:02}:{:02}", minutes / 60, minutes % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone as _;
+
+    fn at(offset: &FixedOffset, y: i32, m: u32, d: u32, h: u32, min: u32) -> DateTime<Utc> {
+        offset.with_ymd_and_hms(y, m, d, h, min, 0).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(QuietCommand::parse("!quiet"), Some(Ok(QuietCommand::Show)));
+        assert_eq!(QuietCommand::parse("!quiet off"), Some(Ok(QuietCommand::Off)));
+        assert_eq!(QuietCommand::parse("!quietly"), None);
+
+        let Some(Ok(QuietCommand::Set(schedule))) =
+            QuietCommand::parse("!quiet tz=+02:00 mode=queue 22:00-07:00 sat,sun@00:00-24:00")
+        else {
+            panic!("expected a schedule");
+        };
+        assert_eq!(schedule.to_string(), "22:00-07:00 sat,sun@00:00-24:00 (+02:00, queue)");
+        assert_eq!(schedule.mode, QuietMode::Queue);
+
+        // Round-trips through the settings store's JSON.
+        let json = serde_json::to_value(&schedule).unwrap();
+        assert_eq!(json["ranges"][1], "sat,sun@00:00-24:00");
+        assert_eq!(serde_json::from_value::<QuietHours>(json).unwrap(), schedule);
+
+        assert_eq!(QuietCommand::parse("!quiet mode=queue"), Some(Err(QuietHoursError::Empty)));
+        assert_eq!(QuietCommand::parse("!quiet 25:00-07:00"), Some(Err(QuietHoursError::Range("25:00-07:00".into()))));
+        assert_eq!(
+            QuietCommand::parse("!quiet tz=Mars/Olympus 22:00-07:00"),
+            Some(Err(QuietHoursError::Timezone("Mars/Olympus".into())))
+        );
+        assert_eq!(QuietCommand::parse("!quiet tz=+25:00 22:00-07:00"), Some(Err(QuietHoursError::Timezone("+25:00".into()))));
+        assert_eq!(QuietCommand::parse("!quiet funday@22:00-07:00"), Some(Err(QuietHoursError::Day("funday".into()))));
+        assert_eq!(parse_days("fri-mon"), Ok(0b111_0001));
+    }
+
+    #[test]
+    fn test_quiet_periods() {
+        let Some(Ok(QuietCommand::Set(schedule))) = QuietCommand::parse("!quiet tz=+02:00 22:00-07:00 sat,sun@00:00-24:00")
+        else {
+            panic!("expected a schedule");
+        };
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        assert_eq!(schedule.timezone, QuietTimezone::Offset(tz));
+
+        // 2026-10-14 is a Wednesday.
+        assert!(!schedule.is_quiet(at(&tz, 2026, 10, 14, 12, 0)));
+        assert!(schedule.is_quiet(at(&tz, 2026, 10, 14, 23, 30)));
+        // Past midnight, the range that started the evening before still applies.
+        assert_eq!(schedule.quiet_until(at(&tz, 2026, 10, 15, 3, 0)), Some(at(&tz, 2026, 10, 15, 7, 0)));
+        assert_eq!(schedule.quiet_until(at(&tz, 2026, 10, 15, 7, 0)), None);
+
+        // Friday night runs into the weekend and on to Monday morning.
+        assert_eq!(schedule.quiet_until(at(&tz, 2026, 10, 16, 23, 0)), Some(at(&tz, 2026, 10, 19, 7, 0)));
+        assert_eq!(schedule.format_local(at(&tz, 2026, 10, 19, 7, 0)), "Mon 07:00 +02:00");
+
+        // A schedule that never ends stops chaining rather than looping.
+        let always: QuietHours = parse_schedule("00:00-24:00").unwrap();
+        assert!(always.quiet_until(Utc::now()).is_some());
+    }
+
+    #[test]
+    fn test_named_timezone_follows_daylight_saving() {
+        let Some(Ok(QuietCommand::Set(schedule))) = QuietCommand::parse("!quiet tz=Europe/Berlin 22:00-07:00") else {
+            panic!("expected a schedule");
+        };
+        assert_eq!(schedule.to_string(), "22:00-07:00 (Europe/Berlin, notice)");
+        let json = serde_json::to_value(&schedule).unwrap();
+        assert_eq!(json["timezone"], "Europe/Berlin");
+        assert_eq!(serde_json::from_value::<QuietHours>(json).unwrap(), schedule);
+
+        // In summer Berlin is UTC+2.
+        assert_eq!(
+            schedule.quiet_until(Utc.with_ymd_and_hms(2026, 7, 1, 21, 0, 0).unwrap()),
+            Some(Utc.with_ymd_and_hms(2026, 7, 2, 5, 0, 0).unwrap())
+        );
+        // The clocks go back overnight on 2026-10-25, so the night ends at
+        // 07:00 UTC+1.
+        let until = schedule.quiet_until(Utc.with_ymd_and_hms(2026, 10, 24, 21, 0, 0).unwrap());
+        assert_eq!(until, Some(Utc.with_ymd_and_hms(2026, 10, 25, 6, 0, 0).unwrap()));
+        assert_eq!(schedule.format_local(until.unwrap()), "Sun 07:00 CET");
+        assert!(!schedule.is_quiet(Utc.with_ymd_and_hms(2026, 10, 25, 12, 0, 0).unwrap()));
+    }
+
+    #[test]
+    fn test_stored_offsets_still_load() {
+        let schedule: QuietHours = serde_json::from_value(serde_json::json!({
+            "ranges": ["22:00-07:00"],
+            "timezone": "+05:30",
+        }))
+        .unwrap();
+        assert_eq!(schedule.timezone, QuietTimezone::Offset(FixedOffset::east_opt(5 * 3600 + 1800).unwrap()));
+        assert_eq!(parse_schedule("22:00-07:00").unwrap().timezone.to_string(), "UTC");
+    }
+}
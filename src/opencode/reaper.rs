@@ -0,0 +1,212 @@
+//! Background reaping of idle channel sessions.
+//!
+//! A channel's OpenCode session lives until something replaces it, so quiet
+//! channels hold sessions open on the server indefinitely. `SessionReaper`
+//! finds channels with no message logged for `session_idle_timeout_secs`,
+//! archives their uncompacted transcript, and forgets the session so the next
+//! message starts a fresh one. With `delete_idle_sessions`, the session is
+//! deleted from the OpenCode server too.
+
+use crate::conversation::channels::ChannelStore;
+use crate::conversation::history::ConversationLogger;
+use crate::opencode::forks::SessionForks;
+use crate::opencode::server::OpenCodeServerPool;
+use crate::opencode::turns::ActiveTurns;
+use crate::{AgentDeps, ChannelId};
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// A session the reaper retired.
+#[derive(Debug, Clone)]
+pub struct ReapedSession {
+    pub channel_id: String,
+    pub session_id: String,
+    pub last_activity_at: chrono::DateTime<chrono::Utc>,
+    /// Where the uncompacted transcript was written, if there was one.
+    pub archive_path: Option<PathBuf>,
+    /// Whether the session was deleted from the OpenCode server.
+    pub deleted: bool,
+}
+
+/// Retires channel sessions idle for longer than `idle_after`.
+#[derive(Debug, Clone)]
+pub struct SessionReaper {
+    idle_after: Duration,
+    archives_dir: PathBuf,
+    delete_sessions: bool,
+}
+
+impl SessionReaper {
+    /// Reap sessions idle for longer than `idle_after`, archiving their
+    /// transcripts to `archives_dir`.
+    pub fn new(idle_after: Duration, archives_dir: PathBuf) -> Self {
+        Self {
+            idle_after,
+            archives_dir,
+            delete_sessions: false,
+        }
+    }
+
+    /// Also delete reaped sessions from the OpenCode server.
+    pub fn with_session_deletion(mut self, delete: bool) -> Self {
+        self.delete_sessions = delete;
+        self
+    }
+
+    /// Reap every idle session once. Channels with a turn in flight are
+    /// skipped. A channel that fails is logged and left for the next pass.
+    pub async fn reap(
+        &self,
+        store: &ChannelStore,
+        logger: &ConversationLogger,
+        active_turns: &ActiveTurns,
+        server_pool: &OpenCodeServerPool,
+    ) -> anyhow::Result<Vec<ReapedSession>> {
+        let idle_after = chrono::Duration::from_std(self.idle_after)?;
+        let idle = store.idle_sessions(chrono::Utc::now() - idle_after).await?;
+
+        let mut reaped = Vec::new();
+        for session in idle {
+            let channel_id = match ChannelId::new(&session.channel_id) {
+                Ok(channel_id) => channel_id,
+                Err(error) => {
+                    tracing::warn!(%error, channel_id = %session.channel_id, "skipping session with invalid channel ID");
+                    continue;
+                }
+            };
+            if active_turns.is_active(&channel_id) {
+                continue;
+            }
+            match self.reap_session(store, logger, server_pool, &channel_id, &session.session_id).await {
+                Ok(Some((archive_path, deleted))) => reaped.push(ReapedSession {
+                    channel_id: session.channel_id,
+                    session_id: session.session_id,
+                    last_activity_at: session.last_activity_at,
+                    archive_path,
+                    deleted,
+                }),
+                Ok(None) => {}
+                Err(error) => {
+                    tracing::warn!(%error, %channel_id, session_id = %session.session_id, "failed to reap idle session");
+                }
+            }
+        }
+        Ok(reaped)
+    }
+
+    /// Returns `None` if the channel moved to another session meanwhile.
+    async fn reap_session(
+        &self,
+        store: &ChannelStore,
+        logger: &ConversationLogger,
+        server_pool: &OpenCodeServerPool,
+        channel_id: &ChannelId,
+        session_id: &str,
+    ) -> anyhow::Result<Option<(Option<PathBuf>, bool)>> {
+        let messages = logger.load_since_last_compaction(channel_id).await?;
+        let archive_path = if messages.is_empty() {
+            None
+        } else {
+            Some(logger.archive_transcript(channel_id, &messages, &self.archives_dir).await?)
+        };
+
+        if !store.clear_session_if_active(channel_id, session_id).await? {
+            return Ok(None);
+        }
+
+        let deleted = self.delete_sessions
+            && server_pool.delete_session(session_id).await.unwrap_or_else(|error| {
+                tracing::warn!(%error, %channel_id, session_id, "failed to delete idle OpenCode session");
+                false
+            });
+        Ok(Some((archive_path, deleted)))
+    }
+}
+
+/// Spawn the idle-session reaper for an agent. Re-reads the OpenCode config
+/// every pass, so the timeout can be changed (or set to 0 to pause reaping)
+/// without a restart.
+pub fn spawn_session_reaper(deps: AgentDeps) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let rc = &deps.runtime_config;
+        let store = ChannelStore::new(deps.sqlite_pool.clone());
+        let logger = ConversationLogger::new(deps.sqlite_pool.clone()).with_cipher(rc.history_cipher.clone());
+        let forks = SessionForks::new(deps.sqlite_pool.clone());
+
+        loop {
+            let config = rc.opencode.load();
+            let interval = Duration::from_secs(config.session_reap_interval_secs.max(1));
+            if config.session_idle_timeout_secs > 0 {
+                let reaper = SessionReaper::new(
+                    Duration::from_secs(config.session_idle_timeout_secs),
+                    rc.archives_dir.clone(),
+                )
+                .with_session_deletion(config.delete_idle_sessions);
+                match reaper.reap(&store, &logger, &rc.active_turns, &rc.opencode_server_pool).await {
+                    Ok(reaped) => {
+                        for session in &reaped {
+                            tracing::info!(
+                                channel_id = %session.channel_id,
+                                session_id = %session.session_id,
+                                last_activity_at = %session.last_activity_at,
+                                archive_path = ?session.archive_path,
+                                deleted = session.deleted,
+                                "reaped idle session"
+                            );
+                        }
+                    }
+                    Err(error) => tracing::warn!(%error, "idle session reaping failed"),
+                }
+                match forks.prune_reaped().await {
+                    Ok(0) => {}
+                    Ok(pruned) => tracing::info!(pruned, "dropped forks of reaped sessions"),
+                    Err(error) => tracing::warn!(%error, "failed to prune reaped forks"),
+                }
+            }
+            drop(config);
+            tokio::time::sleep(interval).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{connect_in_memory, insert_message_at};
+
+    #[tokio::test]
+    async fn test_reap_idle_sessions() {
+        let pool = connect_in_memory().await;
+        let store = ChannelStore::new(pool.clone());
+        let logger = ConversationLogger::new(pool.clone());
+        let active_turns = ActiveTurns::new();
+        let server_pool = OpenCodeServerPool::new("opencode", Default::default(), 1);
+        let archives = tempfile::tempdir().unwrap();
+        let now = chrono::Utc::now();
+        let recent = (now - chrono::Duration::minutes(5)).format("%Y-%m-%d %H:%M:%S").to_string();
+
+        store.set_active_session("discord:1:idle", "ses_idle").await.unwrap();
+        insert_message_at(&pool, "discord:1:idle", "2026-01-01 10:00:00").await;
+        store.set_active_session("discord:1:busy", "ses_busy").await.unwrap();
+        insert_message_at(&pool, "discord:1:busy", "2026-01-01 10:00:00").await;
+        active_turns.register(&ChannelId::new("discord:1:busy").unwrap(), "ses_busy");
+        store.set_active_session("discord:1:recent", "ses_recent").await.unwrap();
+        insert_message_at(&pool, "discord:1:recent", &recent).await;
+
+        let reaper = SessionReaper::new(Duration::from_secs(3600), archives.path().to_path_buf());
+        let reaped = reaper.reap(&store, &logger, &active_turns, &server_pool).await.unwrap();
+        assert_eq!(reaped.len(), 1);
+        assert_eq!(reaped[0].channel_id, "discord:1:idle");
+        assert!(!reaped[0].deleted);
+        let archive = std::fs::read_to_string(reaped[0].archive_path.as_ref().unwrap()).unwrap();
+        assert!(archive.contains("user: hi"));
+
+        assert_eq!(store.get_active_session("discord:1:idle").await.unwrap(), None);
+        assert_eq!(store.get_active_session("discord:1:busy").await.unwrap().as_deref(), Some("ses_busy"));
+        assert_eq!(store.get_active_session("discord:1:recent").await.unwrap().as_deref(), Some("ses_recent"));
+
+        // The mapping is gone, so a second pass has nothing to do.
+        assert!(reaper.reap(&store, &logger, &active_turns, &server_pool).await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+//! Raw SSE envelopes kept for debugging.
+//!
+//! When a turn goes wrong, the parsed `SseEvent`s have already lost whatever
+//! didn't fit the model (unknown fields, payloads that failed to parse).
+//! `SseRecorder` keeps each channel's last `capacity` envelopes exactly as
+//! OpenCode sent them, so they can be dumped or written to the `sse_debug`
+//! table and replayed later. With a capacity of 0 nothing is recorded or
+//! cloned.
+
+use crate::opencode::types::SseEventEnvelope;
+
+use sqlx::SqlitePool;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone)]
+struct RecordedEnvelope {
+    received_at: chrono::DateTime<chrono::Utc>,
+    envelope: SseEventEnvelope,
+}
+
+/// Bounded per-channel ring buffers of raw envelopes.
+#[derive(Debug)]
+pub struct SseRecorder {
+    capacity: usize,
+    channels: Mutex<HashMap<String, VecDeque<RecordedEnvelope>>>,
+}
+
+impl SseRecorder {
+    /// Keep the last `capacity` envelopes of each channel. 0 disables.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            channels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    /// Keep a copy of an envelope received for a channel, dropping the
+    /// channel's oldest once the buffer is full.
+    pub fn record(&self, channel_id: &str, envelope: &SseEventEnvelope) {
+        if !self.is_enabled() {
+            return;
+        }
+        let recorded = RecordedEnvelope {
+            received_at: chrono::Utc::now(),
+            envelope: envelope.clone(),
+        };
+        let mut channels = self.lock();
+        let buffer = channels.entry(channel_id.to_string()).or_default();
+        if buffer.len() >= self.capacity {
+            buffer.pop_front();
+        }
+        buffer.push_back(recorded);
+    }
+
+    /// The channel's buffered envelopes, oldest first.
+    pub fn dump(&self, channel_id: &str) -> Vec<SseEventEnvelope> {
+        self.lock()
+            .get(channel_id)
+            .map(|buffer| buffer.iter().map(|recorded| recorded.envelope.clone()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Move the channel's buffered envelopes into the `sse_debug` table.
+    /// Returns how many were written.
+    pub async fn persist(&self, pool: &SqlitePool, channel_id: &str) -> anyhow::Result<usize> {
+        let recorded: Vec<RecordedEnvelope> = match self.lock().remove(channel_id) {
+            Some(buffer) => buffer.into(),
+            None => return Ok(0),
+        };
+
+        let mut transaction = pool.begin().await?;
+        for entry in &recorded {
+            sqlx::query(
+                "INSERT INTO sse_debug (channel_id, event_type, properties, received_at) VALUES (?, ?, ?, ?)",
+            )
+            .bind(channel_id)
+            .bind(&entry.envelope.event_type)
+            .bind(entry.envelope.properties.to_string())
+            .bind(entry.received_at)
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(recorded.len())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, VecDeque<RecordedEnvelope>>> {
+        self.channels.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    fn envelope(event_type: &str) -> SseEventEnvelope {
+        SseEventEnvelope {
+            event_type: event_type.into(),
+            properties: serde_json::json!({ "sessionID": "ses_1" }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_records_last_envelopes_per_channel() {
+        let recorder = SseRecorder::new(2);
+        for event_type in ["session.status", "message.updated", "session.idle"] {
+            recorder.record("discord:1:2", &envelope(event_type));
+        }
+        recorder.record("discord:1:3", &envelope("session.error"));
+
+        let types: Vec<String> = recorder.dump("discord:1:2").into_iter().map(|e| e.event_type).collect();
+        assert_eq!(types, ["message.updated", "session.idle"]);
+        assert_eq!(recorder.dump("discord:1:3").len(), 1);
+
+        let pool = connect_in_memory().await;
+        assert_eq!(recorder.persist(&pool, "discord:1:2").await.unwrap(), 2);
+        assert!(recorder.dump("discord:1:2").is_empty());
+        let stored: Vec<(String, String)> =
+            sqlx::query_as("SELECT event_type, properties FROM sse_debug WHERE channel_id = ? ORDER BY id")
+                .bind("discord:1:2")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(stored[0].0, "message.updated");
+        assert_eq!(stored[1].1, r#"{"sessionID":"ses_1"}"#);
+
+        let disabled = SseRecorder::new(0);
+        disabled.record("discord:1:2", &envelope("session.idle"));
+        assert!(disabled.dump("discord:1:2").is_empty());
+    }
+}
@@ -0,0 +1,181 @@
+//! Final rendering of assistant messages.
+//!
+//! An assistant message arrives as interleaved text and tool parts. For the
+//! persisted `content` (and anything else a person reads later), the raw tool
+//! JSON is noise: `render_final_message` keeps the text and replaces each tool
+//! call with a one-line summary such as `[ran bash: npm test → exit 0]`. The
+//! full tool input and output stay in `tool_invocations`.
+
+use crate::opencode::types::{Part, ToolState};
+
+/// Longest label (command, file, title) shown in a tool summary.
+const MAX_LABEL_CHARS: usize = 80;
+
+/// Render an assistant message's parts as finished text. Text parts are kept
+/// in order; each run of tool calls between them becomes one summary line per
+/// call. Synthetic text, step markers, and unknown parts are dropped.
+pub fn render_final_message(parts: &[Part]) -> String {
+    let mut blocks: Vec<String> = Vec::new();
+    let mut tools: Vec<String> = Vec::new();
+    for part in parts {
+        match part {
+            Part::Text { text, synthetic: false, .. } if !text.trim().is_empty() => {
+                if !tools.is_empty() {
+                    blocks.push(tools.join("\n"));
+                    tools.clear();
+                }
+                blocks.push(text.trim().to_string());
+            }
+            Part::Tool { tool, state: Some(state), .. } => {
+                tools.push(tool_summary(tool.as_deref().unwrap_or("tool"), state));
+            }
+            _ => {}
+        }
+    }
+    if !tools.is_empty() {
+        blocks.push(tools.join("\n"));
+    }
+    blocks.join("\n\n")
+}
+
+/// One-line summary of a tool call, e.g. `[ran bash: cargo test → exit 101]`
+/// or `[read failed: file not found]`.
+pub fn tool_summary(tool: &str, state: &ToolState) -> String {
+    let label = if tool == "bash" {
+        state.bash_command().or_else(|| state.title())
+    } else {
+        state.title().or_else(|| input_label(state))
+    }
+    .map(short_label);
+
+    match (state, label) {
+        (ToolState::Completed { .. }, label) => {
+            let exit = state.bash_exit_code().map(|code| format!(" → exit {code}")).unwrap_or_default();
+            match label {
+                Some(label) => format!("[ran {tool}: {label}{exit}]"),
+                None => format!("[ran {tool}{exit}]"),
+            }
+        }
+        (ToolState::Error { .. }, _) => match state.output().map(short_label) {
+            Some(error) if !error.is_empty() => format!("[{tool} failed: {error}]"),
+            _ => format!("[{tool} failed]"),
+        },
+        (ToolState::Pending { .. } | ToolState::Running { .. }, Some(label)) => {
+            format!("[{tool} didn't finish: {label}]")
+        }
+        (ToolState::Pending { .. } | ToolState::Running { .. }, None) => format!("[{tool} didn't finish]"),
+        (ToolState::Unknown { status, .. }, _) => format!("[{tool}: {status}]"),
+    }
+}
+
+/// A label from common tool inputs, for tools without a title.
+fn input_label(state: &ToolState) -> Option<&str> {
+    let input = state.input()?;
+    ["filePath", "path", "pattern", "url", "description"]
+        .iter()
+        .find_map(|key| input.get(*key)?.as_str())
+}
+
+/// `text` on one line, with runs of whitespace (newlines included) collapsed
+/// to a space, cut to `MAX_LABEL_CHARS` with an ellipsis.
+fn short_label(text: &str) -> String {
+    let line = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if line.chars().count() <= MAX_LABEL_CHARS {
+        return line;
+    }
+    let cut: String = line.chars().take(MAX_LABEL_CHARS - 1).collect();
+    format!("{}…", cut.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(text: &str) -> Part {
+        Part::Text {
+            id: "prt_text".into(),
+            session_id: None,
+            message_id: None,
+            text: text.into(),
+            time: None,
+            synthetic: false,
+        }
+    }
+
+    fn tool(name: &str, state: serde_json::Value) -> Part {
+        Part::Tool {
+            id: "prt_tool".into(),
+            session_id: None,
+            message_id: None,
+            call_id: None,
+            tool: Some(name.into()),
+            state: Some(ToolState::from_value(state)),
+        }
+    }
+
+    #[test]
+    fn test_render_final_message() {
+        let parts = [
+            text("Let me run the tests."),
+            tool(
+                "bash",
+                serde_json::json!({
+                    "status": "completed",
+                    "input": { "command": "npm test\n# and more" },
+                    "output": "...",
+                    "metadata": { "exit": 0 },
+                }),
+            ),
+            tool(
+                "read",
+                serde_json::json!({ "status": "completed", "input": { "filePath": "src/app.ts" }, "output": "..." }),
+            ),
+            Part::StepFinish { id: "prt_step".into(), session_id: None, message_id: None, reason: None, tokens: None },
+            text("  The tests pass.  "),
+            tool("edit", serde_json::json!({ "status": "error", "input": {}, "error": "file not found\nat ..." })),
+            tool("task", serde_json::json!({ "status": "running", "title": "Explore the repo" })),
+            Part::Text {
+                id: "prt_synthetic".into(),
+                session_id: None,
+                message_id: None,
+                text: "injected context".into(),
+                time: None,
+                synthetic: true,
+            },
+        ];
+
+        assert_eq!(
+            render_final_message(&parts),
+            "Let me run the tests.\n\n\
+             [ran bash: npm test # and more → exit 0]\n\
+             [ran read: src/app.ts]\n\n\
+             The tests pass.\n\n\
+             [edit failed: file not found at ...]\n\
+             [task didn't finish: Explore the repo]"
+        );
+        assert_eq!(render_final_message(&[]), "");
+    }
+
+    #[test]
+    fn test_tool_summary_labels() {
+        let long = "x".repeat(200);
+        let state = ToolState::from_value(serde_json::json!({
+            "status": "completed",
+            "input": { "command": long },
+            "metadata": { "exit": 1 },
+        }));
+        let summary = tool_summary("bash", &state);
+        assert!(summary.ends_with("… → exit 1]"));
+        assert_eq!(summary.chars().count(), "[ran bash: ".len() + MAX_LABEL_CHARS + " → exit 1]".chars().count());
+
+        // Multi-line labels are joined onto one line, not cut at the first newline.
+        let script = format!("set -e\n\n  cargo build\n{}", "cargo test\n".repeat(20));
+        let state = ToolState::from_value(serde_json::json!({ "status": "completed", "input": { "command": script } }));
+        let summary = tool_summary("bash", &state);
+        assert!(summary.starts_with("[ran bash: set -e cargo build cargo test cargo test"), "{summary}");
+        assert!(summary.ends_with("…]"));
+
+        let state = ToolState::from_value(serde_json::json!({ "status": "completed", "input": {} }));
+        assert_eq!(tool_summary("todowrite", &state), "[ran todowrite]");
+    }
+}
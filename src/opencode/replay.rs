@@ -0,0 +1,62 @@
+//! Recorded OpenCode event streams, for replaying a turn without a server.
+//!
+//! A recording is one `SseEventEnvelope` per line, as OpenCode sends them.
+//! Lines may keep their SSE `data:` prefix, so a raw capture of the `/event`
+//! stream can be used as is; other SSE fields and blank lines are skipped.
+//! `OpenCodeWorker::replay_from_file` drives a recording through the same
+//! handler as the live event loop.
+
+use crate::opencode::types::{SseEvent, SseEventEnvelope};
+
+use anyhow::Context as _;
+use std::path::Path;
+
+/// Read a recording into events, in order.
+pub fn read_recording(path: &Path) -> anyhow::Result<Vec<SseEvent>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read recording {}", path.display()))?;
+    parse_recording(&contents).with_context(|| format!("invalid recording {}", path.display()))
+}
+
+fn parse_recording(contents: &str) -> anyhow::Result<Vec<SseEvent>> {
+    let mut events = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        let json = match line.strip_prefix("data:") {
+            Some(data) => data.trim_start(),
+            None if line.is_empty() || line.starts_with(':') || is_sse_field(line) => continue,
+            None => line,
+        };
+        let envelope: SseEventEnvelope = serde_json::from_str(json)
+            .with_context(|| format!("line {}: not an event envelope", index + 1))?;
+        events.push(SseEvent::from_envelope(envelope));
+    }
+    Ok(events)
+}
+
+/// SSE lines other than `data:` (`event:`, `id:`, `retry:`).
+fn is_sse_field(line: &str) -> bool {
+    ["event:", "id:", "retry:"].iter().any(|field| line.starts_with(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_recording_accepts_jsonl_and_raw_sse() {
+        let contents = r#"
+{"type":"session.status","properties":{"sessionID":"ses_1","status":{"type":"busy"}}}
+event: message
+data: {"type":"session.idle","properties":{"sessionID":"ses_1"}}
+
+: keep-alive
+"#;
+        let events = parse_recording(contents).unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(matches!(events[1], SseEvent::SessionIdle { ref session_id } if session_id == "ses_1"));
+
+        let error = parse_recording("{\"type\":1}").unwrap_err();
+        assert!(format!("{error:#}").contains("line 1"));
+    }
+}
@@ -0,0 +1,115 @@
+//! Provider retries during a turn.
+//!
+//! When a provider call fails with something retryable, OpenCode backs off
+//! and reports `session.status` with `type: "retry"` and the attempt number,
+//! often several times for the same attempt. `RetryTracker` turns those into
+//! one status update per new attempt, and flags the first attempt past
+//! `warn_after` so the channel can be told the turn may fail. The count resets
+//! when the session goes idle.
+
+use crate::opencode::types::{SessionStatusPayload, SseEvent};
+
+/// A new retry attempt worth telling the channel about.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryUpdate {
+    pub attempt: u32,
+    pub message: Option<String>,
+    /// Whether this attempt crossed the warning threshold.
+    pub warn: bool,
+}
+
+impl RetryUpdate {
+    /// Status line for the worker, e.g. "retrying (attempt 2): rate limited".
+    pub fn status(&self) -> String {
+        let description = self.message.as_deref().unwrap_or("rate limited");
+        format!("retrying (attempt {}): {description}", self.attempt)
+    }
+}
+
+/// Retry attempts seen for one session.
+#[derive(Debug, Clone, Default)]
+pub struct RetryTracker {
+    warn_after: u32,
+    attempt: u32,
+    warned: bool,
+}
+
+impl RetryTracker {
+    /// Warn once retries go past `warn_after` attempts. Zero never warns.
+    pub fn new(warn_after: u32) -> Self {
+        Self {
+            warn_after,
+            ..Self::default()
+        }
+    }
+
+    /// The highest attempt seen since the session was last idle.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Follow an event. Returns an update when `session_id` reports an
+    /// attempt it hasn't reported before.
+    pub fn observe(&mut self, event: &SseEvent, session_id: &str) -> Option<RetryUpdate> {
+        let SseEvent::SessionStatus { session_id: event_session_id, status } = event else {
+            return None;
+        };
+        if event_session_id != session_id {
+            return None;
+        }
+        match status {
+            SessionStatusPayload::Retry { attempt, message } if *attempt > self.attempt => {
+                self.attempt = *attempt;
+                let warn = self.warn_after > 0 && *attempt > self.warn_after && !self.warned;
+                self.warned |= warn;
+                Some(RetryUpdate {
+                    attempt: *attempt,
+                    message: message.clone(),
+                    warn,
+                })
+            }
+            SessionStatusPayload::Idle => {
+                self.attempt = 0;
+                self.warned = false;
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(session_id: &str, status: SessionStatusPayload) -> SseEvent {
+        SseEvent::SessionStatus { session_id: session_id.into(), status }
+    }
+
+    fn retry(attempt: u32) -> SseEvent {
+        status(
+            "ses_1",
+            SessionStatusPayload::Retry { attempt, message: Some("overloaded".into()) },
+        )
+    }
+
+    #[test]
+    fn test_notifies_once_per_attempt_and_warns_past_limit() {
+        let mut tracker = RetryTracker::new(2);
+
+        let first = tracker.observe(&retry(1), "ses_1").unwrap();
+        assert_eq!(first.status(), "retrying (attempt 1): overloaded");
+        assert!(!first.warn);
+        assert_eq!(tracker.observe(&retry(1), "ses_1"), None);
+        assert_eq!(tracker.observe(&status("ses_1", SessionStatusPayload::Busy), "ses_1"), None);
+        assert_eq!(tracker.observe(&retry(4), "ses_other"), None);
+
+        assert!(!tracker.observe(&retry(2), "ses_1").unwrap().warn);
+        assert!(tracker.observe(&retry(3), "ses_1").unwrap().warn);
+        assert!(!tracker.observe(&retry(4), "ses_1").unwrap().warn);
+
+        tracker.observe(&status("ses_1", SessionStatusPayload::Idle), "ses_1");
+        assert_eq!(tracker.attempt(), 0);
+        assert_eq!(tracker.observe(&retry(1), "ses_1").map(|update| update.attempt), Some(1));
+    }
+}
@@ -1,6 +1,7 @@
 //! OpenCode server process management and HTTP client.
 //!
-//! Manages persistent OpenCode server processes (one per working directory).
+//! Manages persistent OpenCode server processes (one per working directory and
+//! permission profile).
 //! Each server is spawned as `opencode serve --port <port>` and communicated
 //! with via its HTTP API. Servers are reused across worker tasks targeting
 //! the same directory.
@@ -8,9 +9,11 @@
 //! Port mappings are persisted to disk so that after a spacebot restart, we can
 //! reattach to OpenCode servers that are still running from the previous session.
 
+use crate::opencode::breaker::{BreakerConfig, BreakerState, CircuitBreaker};
 use crate::opencode::types::*;
 
 use anyhow::{Context as _, bail};
+use futures::stream::{BoxStream, StreamExt as _, TryStreamExt as _};
 use reqwest::Client;
 use std::collections::HashMap;
 use std::collections::hash_map::DefaultHasher;
@@ -27,6 +30,9 @@ const HEALTH_CHECK_MAX_ATTEMPTS: u32 = 30;
 const HEALTH_CHECK_INTERVAL_MS: u64 = 1000;
 /// Maximum restart attempts before giving up.
 const MAX_RESTART_RETRIES: u32 = 5;
+/// Title length to fall back to when OpenCode rejects a title as too long
+/// without saying what its limit is.
+const FALLBACK_TITLE_CHARS: usize = 100;
 
 /// A running OpenCode server process bound to a specific directory.
 pub struct OpenCodeServer {
@@ -39,6 +45,9 @@ pub struct OpenCodeServer {
     restart_count: u32,
     opencode_path: String,
     permissions: OpenCodePermissions,
+    providers: HashMap<String, OpenCodeProvider>,
+    /// Shared with every server in the pool.
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl OpenCodeServer {
@@ -49,11 +58,23 @@ impl OpenCodeServer {
         directory: PathBuf,
         opencode_path: &str,
         permissions: &OpenCodePermissions,
+        providers: &HashMap<String, OpenCodeProvider>,
+    ) -> anyhow::Result<Self> {
+        let port = port_for_directory(&directory, None);
+        Self::spawn_on_port(directory, port, opencode_path, permissions, providers, Arc::default()).await
+    }
+
+    async fn spawn_on_port(
+        directory: PathBuf,
+        port: u16,
+        opencode_path: &str,
+        permissions: &OpenCodePermissions,
+        providers: &HashMap<String, OpenCodeProvider>,
+        breaker: Arc<CircuitBreaker>,
     ) -> anyhow::Result<Self> {
-        let port = port_for_directory(&directory);
         let base_url = format!("http://127.0.0.1:{port}");
 
-        let env_config = OpenCodeEnvConfig::new(permissions);
+        let env_config = OpenCodeEnvConfig::new(permissions, providers)?;
         let config_json = serde_json::to_string(&env_config)
             .context("failed to serialize OpenCode config")?;
 
@@ -92,6 +113,8 @@ impl OpenCodeServer {
             restart_count: 0,
             opencode_path: opencode_path.to_string(),
             permissions: permissions.clone(),
+            providers: providers.clone(),
+            breaker,
         };
 
         server.wait_for_health().await?;
@@ -109,10 +132,12 @@ impl OpenCodeServer {
     /// this directory. Returns None if nothing is listening.
     async fn reattach(
         directory: PathBuf,
+        port: u16,
         opencode_path: &str,
         permissions: &OpenCodePermissions,
+        providers: &HashMap<String, OpenCodeProvider>,
+        breaker: Arc<CircuitBreaker>,
     ) -> Option<Self> {
-        let port = port_for_directory(&directory);
         let base_url = format!("http://127.0.0.1:{port}");
         let client = Client::builder()
             .timeout(std::time::Duration::from_secs(300))
@@ -128,6 +153,8 @@ impl OpenCodeServer {
             restart_count: 0,
             opencode_path: opencode_path.to_string(),
             permissions: permissions.clone(),
+            providers: providers.clone(),
+            breaker,
         };
 
         // Quick health check -- if it fails, server is gone
@@ -206,6 +233,21 @@ impl OpenCodeServer {
         Ok(response.status().is_success())
     }
 
+    /// Version reported by the health endpoint, if the server reports one.
+    pub async fn version(&self) -> anyhow::Result<Option<String>> {
+        let url = format!("{}/global/health", self.base_url);
+        let health: HealthResponse = self.client
+            .get(&url)
+            .timeout(std::time::Duration::from_secs(5))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("failed to parse health response")?;
+        Ok(health.version)
+    }
+
     /// Check if the server is still alive. For spawned servers, checks the
     /// process handle. For reattached servers, does a health check.
     pub async fn is_alive(&mut self) -> bool {
@@ -221,6 +263,41 @@ impl OpenCodeServer {
         self.port
     }
 
+    /// An API-only copy of this server that doesn't own its process, for
+    /// long requests that shouldn't hold the pool's lock. Dropping it leaves
+    /// the server running, like a reattached server.
+    pub fn handle(&self) -> Self {
+        Self {
+            directory: self.directory.clone(),
+            port: self.port,
+            process: None,
+            base_url: self.base_url.clone(),
+            client: self.client.clone(),
+            restart_count: self.restart_count,
+            opencode_path: self.opencode_path.clone(),
+            permissions: self.permissions.clone(),
+            providers: self.providers.clone(),
+            breaker: self.breaker.clone(),
+        }
+    }
+
+    /// A server with no process or address behind it; every request fails.
+    /// For replaying recorded events, where replies have nowhere to go.
+    pub fn offline(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            port: 0,
+            process: None,
+            base_url: "http://127.0.0.1:0".into(),
+            client: Client::new(),
+            restart_count: 0,
+            opencode_path: "opencode".into(),
+            permissions: OpenCodePermissions::default(),
+            providers: HashMap::new(),
+            breaker: Arc::default(),
+        }
+    }
+
     /// Restart the server process. Reuses the same directory and config.
     pub async fn restart(&mut self) -> anyhow::Result<()> {
         self.restart_count += 1;
@@ -244,10 +321,10 @@ impl OpenCodeServer {
         }
 
         // Reuse the same deterministic port
-        let port = port_for_directory(&self.directory);
+        let port = self.port;
         let base_url = format!("http://127.0.0.1:{port}");
 
-        let env_config = OpenCodeEnvConfig::new(&self.permissions);
+        let env_config = OpenCodeEnvConfig::new(&self.permissions, &self.providers)?;
         let config_json = serde_json::to_string(&env_config)?;
 
         let process = Command::new(&self.opencode_path)
@@ -306,16 +383,57 @@ impl OpenCodeServer {
         &self.directory
     }
 
+    /// Send a request through the circuit breaker. Fails at once with
+    /// `CircuitOpen` while the breaker is open; transport errors and 5xx
+    /// responses count as failures. Health checks bypass this, so a restart
+    /// can still tell whether the server came back. If this call is the
+    /// half-open probe and gets cancelled, dropping the permit frees the
+    /// probe for the next call.
+    async fn send(&self, builder: reqwest::RequestBuilder) -> anyhow::Result<reqwest::Response> {
+        let _permit = self.breaker.check(tokio::time::Instant::now())?;
+        match builder.send().await {
+            Ok(response) if response.status().is_server_error() => {
+                self.breaker.record_failure(tokio::time::Instant::now());
+                Ok(response)
+            }
+            Ok(response) => {
+                self.breaker.record_success();
+                Ok(response)
+            }
+            Err(error) => {
+                self.breaker.record_failure(tokio::time::Instant::now());
+                Err(error.into())
+            }
+        }
+    }
+
+    /// The state of the circuit breaker guarding this server's calls.
+    pub fn breaker_state(&self) -> BreakerState {
+        self.breaker.state(tokio::time::Instant::now())
+    }
+
     /// Create a new session.
     pub async fn create_session(&self, title: Option<String>) -> anyhow::Result<Session> {
+        self.post_session(&CreateSessionRequest { title, parent_id: None }).await
+    }
+
+    /// Create a child session of `parent_id`, e.g. to fork a conversation.
+    pub async fn create_child_session(&self, parent_id: &str, title: Option<String>) -> anyhow::Result<Session> {
+        let body = CreateSessionRequest {
+            title,
+            parent_id: Some(parent_id.to_string()),
+        };
+        self.post_session(&body).await
+    }
+
+    async fn post_session(&self, body: &CreateSessionRequest) -> anyhow::Result<Session> {
         let url = format!("{}/session", self.base_url);
-        let body = CreateSessionRequest { title };
 
-        let response = self.client
+        let builder = self.client
             .post(&url)
             .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .json(&body)
-            .send()
+            .json(body);
+        let response = self.send(builder)
             .await
             .context("failed to create OpenCode session")?;
 
@@ -329,6 +447,144 @@ impl OpenCodeServer {
             .context("failed to parse session response")
     }
 
+    /// List the server's sessions for this directory. Child sessions (sub-agent
+    /// runs, which have a `parent_id`) are left out unless `include_children`.
+    ///
+    /// OpenCode returns every session in one response, so there is nothing to
+    /// paginate.
+    pub async fn list_sessions(&self, include_children: bool) -> anyhow::Result<Vec<Session>> {
+        let url = format!("{}/session", self.base_url);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to list OpenCode sessions")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("list sessions failed ({status}): {text}");
+        }
+
+        let sessions = response.json::<Vec<Session>>().await
+            .context("failed to parse session list")?;
+        Ok(sessions
+            .into_iter()
+            .filter(|session| include_children || session.parent_id.is_none())
+            .collect())
+    }
+
+    /// Update a session's metadata.
+    pub async fn update_session(
+        &self,
+        session_id: &str,
+        request: &UpdateSessionRequest,
+    ) -> anyhow::Result<Session> {
+        match self.try_update_session(session_id, request).await? {
+            Ok(session) => Ok(session),
+            Err((status, text)) => bail!("update session failed ({status}): {text}"),
+        }
+    }
+
+    /// Rename a session.
+    ///
+    /// If OpenCode rejects the title as too long, it's truncated to the limit
+    /// from the validation error (or `FALLBACK_TITLE_CHARS`) and sent again.
+    pub async fn set_session_title(&self, session_id: &str, title: &str) -> anyhow::Result<Session> {
+        let request = UpdateSessionRequest { title: Some(title.to_string()) };
+        let (status, text) = match self.try_update_session(session_id, &request).await? {
+            Ok(session) => return Ok(session),
+            Err(rejection) => rejection,
+        };
+        let Some(limit) = title_length_limit(status, &text) else {
+            bail!("update session failed ({status}): {text}");
+        };
+
+        let truncated = truncate_title(title, limit);
+        tracing::debug!(%session_id, limit, "session title too long, truncating");
+        self.update_session(session_id, &UpdateSessionRequest { title: Some(truncated) })
+            .await
+    }
+
+    /// PATCH the session, returning the status and body if OpenCode rejects it.
+    async fn try_update_session(
+        &self,
+        session_id: &str,
+        request: &UpdateSessionRequest,
+    ) -> anyhow::Result<Result<Session, (reqwest::StatusCode, String)>> {
+        let url = format!("{}/session/{}", self.base_url, session_id);
+
+        let builder = self.client
+            .patch(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))])
+            .json(request);
+        let response = self.send(builder)
+            .await
+            .context("failed to update OpenCode session")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Ok(Err((status, text)));
+        }
+
+        let session = response.json::<Session>().await
+            .context("failed to parse session response")?;
+        Ok(Ok(session))
+    }
+
+    /// Fetch a session by ID. Returns `None` if OpenCode doesn't know it
+    /// (e.g. the server's storage was wiped).
+    pub async fn get_session(&self, session_id: &str) -> anyhow::Result<Option<Session>> {
+        let url = format!("{}/session/{}", self.base_url, session_id);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to get OpenCode session")?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("get session failed ({status}): {text}");
+        }
+
+        response.json::<Session>().await
+            .map(Some)
+            .context("failed to parse session response")
+    }
+
+    /// Delete a session and its messages. Returns `false` if OpenCode
+    /// doesn't know it.
+    pub async fn delete_session(&self, session_id: &str) -> anyhow::Result<bool> {
+        let url = format!("{}/session/{}", self.base_url, session_id);
+
+        let builder = self.client
+            .delete(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to delete OpenCode session")?;
+
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("delete session failed ({status}): {text}");
+        }
+
+        Ok(true)
+    }
+
     /// Send a prompt to a session (blocking until complete).
     pub async fn send_prompt(
         &self,
@@ -337,11 +593,15 @@ impl OpenCodeServer {
     ) -> anyhow::Result<serde_json::Value> {
         let url = format!("{}/session/{}/message", self.base_url, session_id);
 
-        let response = self.client
+        let builder = self.client
             .post(&url)
             .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .json(request)
-            .send()
+            .json(request);
+        let builder = match &request.idempotency_key {
+            Some(key) => builder.header("Idempotency-Key", key),
+            None => builder,
+        };
+        let response = self.send(builder)
             .await
             .context("failed to send prompt to OpenCode session")?;
 
@@ -363,11 +623,15 @@ impl OpenCodeServer {
     ) -> anyhow::Result<()> {
         let url = format!("{}/session/{}/prompt_async", self.base_url, session_id);
 
-        let response = self.client
+        let builder = self.client
             .post(&url)
             .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .json(request)
-            .send()
+            .json(request);
+        let builder = match &request.idempotency_key {
+            Some(key) => builder.header("Idempotency-Key", key),
+            None => builder,
+        };
+        let response = self.send(builder)
             .await
             .context("failed to send async prompt")?;
 
@@ -384,10 +648,10 @@ impl OpenCodeServer {
     pub async fn abort_session(&self, session_id: &str) -> anyhow::Result<()> {
         let url = format!("{}/session/{}/abort", self.base_url, session_id);
 
-        let response = self.client
+        let builder = self.client
             .post(&url)
-            .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .send()
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
             .await
             .context("failed to abort OpenCode session")?;
 
@@ -405,18 +669,27 @@ impl OpenCodeServer {
         &self,
         request_id: &str,
         reply: PermissionReply,
+    ) -> anyhow::Result<()> {
+        self.reply_permission_with_message(request_id, reply, None).await
+    }
+
+    /// Reply to a permission request, with a message the model sees
+    /// (e.g. why it was rejected).
+    pub async fn reply_permission_with_message(
+        &self,
+        request_id: &str,
+        reply: PermissionReply,
+        message: Option<String>,
     ) -> anyhow::Result<()> {
         let url = format!("{}/permission/{}/reply", self.base_url, request_id);
-        let body = PermissionReplyRequest {
-            reply,
-            message: None,
-        };
+        crate::opencode::metrics::global().record_permission_reply(&reply);
+        let body = PermissionReplyRequest { reply, message };
 
-        let response = self.client
+        let builder = self.client
             .post(&url)
             .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .json(&body)
-            .send()
+            .json(&body);
+        let response = self.send(builder)
             .await
             .context("failed to reply to permission")?;
 
@@ -436,13 +709,14 @@ impl OpenCodeServer {
         answers: Vec<QuestionAnswer>,
     ) -> anyhow::Result<()> {
         let url = format!("{}/question/{}/reply", self.base_url, request_id);
+        crate::opencode::metrics::global().record_question_reply();
         let body = QuestionReplyRequest { answers };
 
-        let response = self.client
+        let builder = self.client
             .post(&url)
             .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .json(&body)
-            .send()
+            .json(&body);
+        let response = self.send(builder)
             .await
             .context("failed to reply to question")?;
 
@@ -455,17 +729,101 @@ impl OpenCodeServer {
         Ok(())
     }
 
+    /// List the agents the server can run.
+    pub async fn list_agents(&self) -> anyhow::Result<Vec<AgentInfo>> {
+        let url = format!("{}/agent", self.base_url);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to list OpenCode agents")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("list agents failed ({status}): {text}");
+        }
+
+        response.json::<Vec<AgentInfo>>().await
+            .context("failed to parse agent list")
+    }
+
+    /// List the configured providers and the models each offers.
+    pub async fn list_providers(&self) -> anyhow::Result<ProviderList> {
+        let url = format!("{}/config/providers", self.base_url);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to list OpenCode providers")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("list providers failed ({status}): {text}");
+        }
+
+        response.json::<ProviderList>().await
+            .context("failed to parse provider list")
+    }
+
+    /// List permission requests still waiting for a reply, across sessions.
+    pub async fn list_permissions(&self) -> anyhow::Result<Vec<PermissionRequest>> {
+        let url = format!("{}/permission", self.base_url);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to list pending permissions")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("list permissions failed ({status}): {text}");
+        }
+
+        response.json::<Vec<PermissionRequest>>().await
+            .context("failed to parse permission list")
+    }
+
+    /// List questions still waiting for an answer, across sessions.
+    pub async fn list_questions(&self) -> anyhow::Result<Vec<QuestionRequest>> {
+        let url = format!("{}/question", self.base_url);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to list pending questions")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("list questions failed ({status}): {text}");
+        }
+
+        response.json::<Vec<QuestionRequest>>().await
+            .context("failed to parse question list")
+    }
+
     /// Subscribe to the SSE event stream. Returns a response whose body can
     /// be read as a byte stream and parsed line-by-line for SSE events.
     pub async fn subscribe_events(&self) -> anyhow::Result<reqwest::Response> {
         let url = format!("{}/event", self.base_url);
 
-        let response = self.client
+        let builder = self.client
             .get(&url)
             .query(&[("directory", self.directory.to_str().unwrap_or("."))])
             .header("Accept", "text/event-stream")
-            .timeout(std::time::Duration::from_secs(86400)) // long-lived
-            .send()
+            .timeout(std::time::Duration::from_secs(86400)) // long-lived;
+        let response = self.send(builder)
             .await
             .context("failed to subscribe to OpenCode event stream")?;
 
@@ -485,10 +843,10 @@ impl OpenCodeServer {
     ) -> anyhow::Result<Vec<serde_json::Value>> {
         let url = format!("{}/session/{}/message", self.base_url, session_id);
 
-        let response = self.client
+        let builder = self.client
             .get(&url)
-            .query(&[("directory", self.directory.to_str().unwrap_or("."))])
-            .send()
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
             .await
             .context("failed to get session messages")?;
 
@@ -501,6 +859,112 @@ impl OpenCodeServer {
         response.json::<Vec<serde_json::Value>>().await
             .context("failed to parse messages response")
     }
+
+    /// Get every message in a session, with its parts, oldest first.
+    ///
+    /// Buffers the whole history; prefer `stream_session_messages` for
+    /// sessions that may be long.
+    pub async fn get_session_messages(&self, session_id: &str) -> anyhow::Result<Vec<SessionMessage>> {
+        self.stream_session_messages(session_id).await?.try_collect().await
+    }
+
+    /// Stream a session's messages, oldest first, parsing each as it arrives
+    /// instead of buffering the whole response.
+    pub async fn stream_session_messages(
+        &self,
+        session_id: &str,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<SessionMessage>>> {
+        let url = format!("{}/session/{}/message", self.base_url, session_id);
+
+        let builder = self.client
+            .get(&url)
+            .query(&[("directory", self.directory.to_str().unwrap_or("."))]);
+        let response = self.send(builder)
+            .await
+            .context("failed to get session messages")?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            bail!("get messages failed ({status}): {text}");
+        }
+
+        let mut bytes = response.bytes_stream();
+        Ok(async_stream::try_stream! {
+            let mut splitter = JsonArraySplitter::default();
+            while let Some(chunk) = bytes.next().await {
+                let chunk = chunk.context("failed to read session messages")?;
+                for element in splitter.push(&chunk) {
+                    let message: SessionMessage = serde_json::from_slice(&element)
+                        .context("failed to parse session message")?;
+                    yield message;
+                }
+            }
+        }
+        .boxed())
+    }
+}
+
+/// Splits a top-level JSON array arriving in chunks into its object or array
+/// elements, so each can be parsed as soon as it's complete. Scalar elements
+/// are skipped.
+#[derive(Debug, Default)]
+struct JsonArraySplitter {
+    buffer: Vec<u8>,
+    /// Offset of the element being collected, if one has started.
+    start: Option<usize>,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonArraySplitter {
+    /// Feed a chunk and return the elements it completed.
+    fn push(&mut self, chunk: &[u8]) -> Vec<Vec<u8>> {
+        let scanned = self.buffer.len();
+        self.buffer.extend_from_slice(chunk);
+
+        let mut elements = Vec::new();
+        for index in scanned..self.buffer.len() {
+            let byte = self.buffer[index];
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'[' | b'{' => {
+                    self.depth += 1;
+                    if self.depth == 2 {
+                        self.start = Some(index);
+                    }
+                }
+                b']' | b'}' => {
+                    if self.depth == 2 {
+                        if let Some(start) = self.start.take() {
+                            elements.push(self.buffer[start..=index].to_vec());
+                        }
+                    }
+                    self.depth = self.depth.saturating_sub(1);
+                }
+                _ => {}
+            }
+        }
+
+        // Keep only the unfinished element, if any.
+        let keep_from = self.start.unwrap_or(self.buffer.len());
+        self.buffer.drain(..keep_from);
+        if self.start.is_some() {
+            self.start = Some(0);
+        }
+        elements
+    }
 }
 
 impl Drop for OpenCodeServer {
@@ -516,49 +980,174 @@ impl Drop for OpenCodeServer {
     }
 }
 
-/// Pool of OpenCode server processes, one per working directory.
+/// Title length limit from a rejected session update, if the rejection was
+/// about the title being too long.
 ///
-/// Uses deterministic ports derived from directory paths so that after a
-/// spacebot restart, we can rediscover servers that are still running.
-/// No file persistence needed -- just health-check the expected port.
+/// OpenCode validates with zod, whose errors look like
+/// `{"code": "too_big", "maximum": 100, "path": ["title"], ...}`.
+fn title_length_limit(status: reqwest::StatusCode, body: &str) -> Option<usize> {
+    if status != reqwest::StatusCode::BAD_REQUEST {
+        return None;
+    }
+    let lower = body.to_lowercase();
+    if !(lower.contains("too_big") || lower.contains("too long")) {
+        return None;
+    }
+
+    fn find_maximum(value: &serde_json::Value) -> Option<u64> {
+        match value {
+            serde_json::Value::Object(map) => map
+                .get("maximum")
+                .and_then(|v| v.as_u64())
+                .or_else(|| map.values().find_map(find_maximum)),
+            serde_json::Value::Array(items) => items.iter().find_map(find_maximum),
+            _ => None,
+        }
+    }
+    let maximum = serde_json::from_str(body).ok().as_ref().and_then(find_maximum);
+    Some(maximum.map_or(FALLBACK_TITLE_CHARS, |maximum| maximum as usize))
+}
+
+/// Cut `title` to at most `limit` characters, marking the cut with "…".
+fn truncate_title(title: &str, limit: usize) -> String {
+    if title.chars().count() <= limit {
+        return title.to_string();
+    }
+    let mut truncated: String = title.chars().take(limit.saturating_sub(1)).collect();
+    truncated.truncate(truncated.trim_end().len());
+    truncated.push('…');
+    truncated
+}
+
+/// Pooled servers are keyed by canonical directory and permission profile
+/// name (None for the default permissions).
+type ServerKey = (PathBuf, Option<String>);
+
+/// Pool of OpenCode server processes, one per working directory and
+/// permission profile.
+///
+/// Uses deterministic ports derived from directory paths (and profile names)
+/// so that after a spacebot restart, we can rediscover servers that are still
+/// running. No file persistence needed -- just health-check the expected port.
 pub struct OpenCodeServerPool {
-    servers: Mutex<HashMap<PathBuf, Arc<Mutex<OpenCodeServer>>>>,
+    servers: Mutex<HashMap<ServerKey, Arc<Mutex<OpenCodeServer>>>>,
     opencode_path: String,
-    permissions: OpenCodePermissions,
+    permissions: PermissionProfiles,
+    /// Provider credentials every server is launched with.
+    providers: HashMap<String, OpenCodeProvider>,
     max_servers: usize,
+    /// OpenCode versions known to work. `None` accepts any version.
+    supported_versions: Option<semver::VersionReq>,
+    breaker: Arc<CircuitBreaker>,
 }
 
 impl OpenCodeServerPool {
     /// Create a new server pool.
     pub fn new(
         opencode_path: impl Into<String>,
-        permissions: OpenCodePermissions,
+        permissions: PermissionProfiles,
         max_servers: usize,
     ) -> Self {
         Self {
             servers: Mutex::new(HashMap::new()),
             opencode_path: opencode_path.into(),
             permissions,
+            providers: HashMap::new(),
             max_servers,
+            supported_versions: None,
+            breaker: Arc::default(),
+        }
+    }
+
+    /// Launch servers with credentials for these model providers.
+    pub fn with_providers(mut self, providers: HashMap<String, OpenCodeProvider>) -> Self {
+        self.providers = providers;
+        self
+    }
+
+    /// Short-circuit calls to every server once OpenCode keeps failing.
+    pub fn with_circuit_breaker(mut self, config: BreakerConfig) -> Self {
+        self.breaker = Arc::new(CircuitBreaker::new(config));
+        self
+    }
+
+    /// The breaker shared by the pool's servers.
+    pub fn circuit_breaker(&self) -> &Arc<CircuitBreaker> {
+        &self.breaker
+    }
+
+    /// Warn when a server's OpenCode version falls outside `requirement`.
+    pub fn with_supported_versions(mut self, requirement: semver::VersionReq) -> Self {
+        self.supported_versions = Some(requirement);
+        self
+    }
+
+    /// Whether `version` satisfies the configured requirement. Versions that
+    /// aren't valid semver never do; with no requirement, everything does.
+    pub fn is_supported_version(&self, version: &str) -> bool {
+        let Some(requirement) = &self.supported_versions else {
+            return true;
+        };
+        semver::Version::parse(version.trim().trim_start_matches('v'))
+            .is_ok_and(|version| requirement.matches(&version))
+    }
+
+    /// Log a warning if the server runs an unsupported OpenCode version.
+    async fn check_version(&self, server: &OpenCodeServer) {
+        let Some(requirement) = &self.supported_versions else {
+            return;
+        };
+        match server.version().await {
+            Ok(Some(version)) if !self.is_supported_version(&version) => {
+                tracing::warn!(
+                    directory = %server.directory().display(),
+                    %version,
+                    supported = %requirement,
+                    "OpenCode server version is outside the supported range"
+                );
+            }
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                tracing::debug!(
+                    directory = %server.directory().display(),
+                    "OpenCode server did not report a version"
+                );
+            }
+            Err(error) => {
+                tracing::debug!(%error, "failed to read OpenCode server version");
+            }
         }
     }
 
-    /// Get or create a server for the given directory.
+    /// Get or create a server for the given directory with default permissions.
+    pub async fn get_or_create(
+        &self,
+        directory: &Path,
+    ) -> anyhow::Result<Arc<Mutex<OpenCodeServer>>> {
+        self.get_or_create_for_channel(directory, None).await
+    }
+
+    /// Get or create a server for the given directory, running with the
+    /// permission profile configured for `channel_id`.
     ///
     /// On first access for a directory, checks the deterministic port for
     /// a server left over from a previous run. If one responds, reattaches.
     /// Otherwise spawns a new one. Subsequent calls reuse the pooled server.
-    pub async fn get_or_create(
+    pub async fn get_or_create_for_channel(
         &self,
         directory: &Path,
+        channel_id: Option<&str>,
     ) -> anyhow::Result<Arc<Mutex<OpenCodeServer>>> {
         let canonical = directory.canonicalize()
             .with_context(|| format!("directory '{}' does not exist", directory.display()))?;
+        let (profile, permissions) = self.permissions.resolve(channel_id);
+        let port = port_for_directory(&canonical, profile);
+        let key = (canonical.clone(), profile.map(String::from));
 
         let mut servers = self.servers.lock().await;
 
         // Check if we already have it in the pool
-        if let Some(server) = servers.get(&canonical) {
+        if let Some(server) = servers.get(&key) {
             let mut guard = server.lock().await;
             if guard.is_alive().await {
                 return Ok(Arc::clone(server));
@@ -576,11 +1165,15 @@ impl OpenCodeServerPool {
         // deterministic port (left over from a previous spacebot run).
         if let Some(reattached) = OpenCodeServer::reattach(
             canonical.clone(),
+            port,
             &self.opencode_path,
-            &self.permissions,
+            permissions,
+            &self.providers,
+            self.breaker.clone(),
         ).await {
+            self.check_version(&reattached).await;
             let server = Arc::new(Mutex::new(reattached));
-            servers.insert(canonical, Arc::clone(&server));
+            servers.insert(key, Arc::clone(&server));
             return Ok(server);
         }
 
@@ -592,14 +1185,18 @@ impl OpenCodeServerPool {
             );
         }
 
-        let server = OpenCodeServer::spawn(
-            canonical.clone(),
+        let server = OpenCodeServer::spawn_on_port(
+            canonical,
+            port,
             &self.opencode_path,
-            &self.permissions,
+            permissions,
+            &self.providers,
+            self.breaker.clone(),
         ).await?;
+        self.check_version(&server).await;
 
         let server = Arc::new(Mutex::new(server));
-        servers.insert(canonical, Arc::clone(&server));
+        servers.insert(key, Arc::clone(&server));
 
         Ok(server)
     }
@@ -607,7 +1204,7 @@ impl OpenCodeServerPool {
     /// Shut down all servers in the pool.
     pub async fn shutdown_all(&self) {
         let mut servers = self.servers.lock().await;
-        for (directory, server) in servers.drain() {
+        for ((directory, _profile), server) in servers.drain() {
             let mut guard = server.lock().await;
             guard.kill().await;
             tracing::info!(
@@ -617,6 +1214,26 @@ impl OpenCodeServerPool {
         }
     }
 
+    /// Delete a session from whichever pooled server has it. The pool
+    /// doesn't record which server a session lives on, so each is tried in
+    /// turn. Returns `false` if none of them knew it.
+    pub async fn delete_session(&self, session_id: &str) -> anyhow::Result<bool> {
+        let servers: Vec<Arc<Mutex<OpenCodeServer>>> = self.servers.lock().await.values().cloned().collect();
+        let mut last_error = None;
+        for server in servers {
+            let handle = server.lock().await.handle();
+            match handle.delete_session(session_id).await {
+                Ok(true) => return Ok(true),
+                Ok(false) => {}
+                Err(error) => last_error = Some(error),
+            }
+        }
+        match last_error {
+            Some(error) => Err(error),
+            None => Ok(false),
+        }
+    }
+
     /// Number of active servers.
     pub async fn server_count(&self) -> usize {
         self.servers.lock().await.len()
@@ -627,11 +1244,109 @@ impl OpenCodeServerPool {
 ///
 /// Uses a hash of the canonical path mapped into the range 10000-60000.
 /// This means the same directory always gets the same port, so we can
-/// rediscover servers after a restart without persisting state.
-fn port_for_directory(directory: &Path) -> u16 {
+/// rediscover servers after a restart without persisting state. Servers for a
+/// named permission profile hash the profile in too; the default profile keeps
+/// the directory-only port.
+fn port_for_directory(directory: &Path, profile: Option<&str>) -> u16 {
     let mut hasher = DefaultHasher::new();
     directory.hash(&mut hasher);
+    if let Some(profile) = profile {
+        profile.hash(&mut hasher);
+    }
     let hash = hasher.finish();
     // Map into range 10000..60000 (50000 ports)
     10000 + (hash % 50000) as u16
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// A client for a mock server; no OpenCode process behind it.
+    pub(crate) async fn mock_server(app: axum::Router) -> OpenCodeServer {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        OpenCodeServer {
+            directory: PathBuf::from("/tmp"),
+            port,
+            process: None,
+            base_url: format!("http://127.0.0.1:{port}"),
+            client: Client::new(),
+            restart_count: 0,
+            opencode_path: "opencode".into(),
+            permissions: OpenCodePermissions::default(),
+            providers: HashMap::new(),
+            breaker: Arc::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_sessions_filters_children() {
+        let app = axum::Router::new().route(
+            "/session",
+            axum::routing::get(|| async {
+                axum::Json(serde_json::json!([
+                    { "id": "ses_root", "title": "Fix CI", "version": "1.1.36", "time": { "created": 1, "updated": 2 } },
+                    { "id": "ses_child", "title": "explore", "parentID": "ses_root", "projectID": "p1" },
+                ]))
+            }),
+        );
+        let server = mock_server(app).await;
+
+        let top_level = server.list_sessions(false).await.unwrap();
+        assert_eq!(top_level.len(), 1);
+        assert_eq!(top_level[0].id, "ses_root");
+        assert_eq!(top_level[0].title.as_deref(), Some("Fix CI"));
+
+        let all = server.list_sessions(true).await.unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[1].parent_id.as_deref(), Some("ses_root"));
+    }
+
+    #[test]
+    fn test_json_array_splitter_handles_chunk_boundaries() {
+        let body = br#"[{"info":{"id":"msg_1","role":"user"},"parts":[{"type":"text","id":"p","text":"a ] } \" ["}]},
+            {"info":{"id":"msg_2","role":"assistant"},"parts":[]}, 3]"#;
+
+        for chunk_size in [1, 7, body.len()] {
+            let mut splitter = JsonArraySplitter::default();
+            let elements: Vec<Vec<u8>> = body.chunks(chunk_size).flat_map(|chunk| splitter.push(chunk)).collect();
+            assert_eq!(elements.len(), 2, "chunk size {chunk_size}");
+
+            let first: SessionMessage = serde_json::from_slice(&elements[0]).unwrap();
+            assert_eq!(first.info.id, "msg_1");
+            assert_eq!(first.text(), "a ] } \" [");
+            let second: SessionMessage = serde_json::from_slice(&elements[1]).unwrap();
+            assert_eq!(second.info.role, "assistant");
+        }
+    }
+
+    #[test]
+    fn test_title_length_limit() {
+        let bad_request = reqwest::StatusCode::BAD_REQUEST;
+        let zod = r#"{"error":[{"code":"too_big","maximum":50,"type":"string","path":["title"]}]}"#;
+        assert_eq!(title_length_limit(bad_request, zod), Some(50));
+        assert_eq!(title_length_limit(bad_request, "title too long"), Some(FALLBACK_TITLE_CHARS));
+        assert_eq!(title_length_limit(bad_request, "invalid session"), None);
+        assert_eq!(title_length_limit(reqwest::StatusCode::NOT_FOUND, zod), None);
+
+        assert_eq!(truncate_title("short", 10), "short");
+        assert_eq!(truncate_title("Fix the flaky deploy", 9), "Fix the…");
+    }
+
+    #[test]
+    fn test_is_supported_version() {
+        let pool = OpenCodeServerPool::new("opencode", PermissionProfiles::default(), 1);
+        assert!(pool.is_supported_version("0.1.0"));
+
+        let pool = pool.with_supported_versions(semver::VersionReq::parse(">=1.1.0, <2").unwrap());
+        assert!(pool.is_supported_version("1.1.36"));
+        assert!(pool.is_supported_version("v1.2.0"));
+        assert!(!pool.is_supported_version("1.0.9"));
+        assert!(!pool.is_supported_version("2.0.0"));
+        assert!(!pool.is_supported_version("local"));
+    }
+}
@@ -0,0 +1,452 @@
+//! Channel-to-session bookkeeping for OpenCode.
+//!
+//! The persisted channel → session mapping lives in SQLite (`ChannelStore`);
+//! `resume_active_session` ties it to a live server so stale sessions aren't
+//! reused after a restart, and `ensure_session` is the entry point for getting
+//! a usable one. `SessionRegistry` tracks the in-memory session tree,
+//! so events from sub-agent child sessions resolve to the right channel.
+
+use crate::conversation::channels::ChannelStore;
+use crate::conversation::history::ConversationLogger;
+use crate::opencode::server::OpenCodeServer;
+use crate::opencode::types::{Session, SseEvent};
+use crate::ChannelId;
+
+use futures::StreamExt as _;
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+/// Parent chains deeper than this are treated as cycles.
+const MAX_SESSION_DEPTH: usize = 16;
+
+/// Look up the session recorded for a channel and confirm OpenCode still has it.
+///
+/// Returns `None` if the channel has no recorded session, or if OpenCode no
+/// longer knows it — in which case the stale mapping is cleared so the caller
+/// starts a fresh session. A resumed session's history is reconciled into
+/// `logger`; failing that is logged, not fatal.
+pub async fn resume_active_session(
+    store: &ChannelStore,
+    logger: &ConversationLogger,
+    server: &OpenCodeServer,
+    channel_id: &ChannelId,
+) -> anyhow::Result<Option<Session>> {
+    let Some(session_id) = store.get_active_session(channel_id).await? else {
+        return Ok(None);
+    };
+
+    match server.get_session(&session_id).await? {
+        Some(session) => {
+            match reconcile_session_history(logger, server, channel_id, &session_id).await {
+                Ok(0) => {}
+                Ok(backfilled) => {
+                    tracing::info!(%channel_id, %session_id, backfilled, "backfilled messages from OpenCode");
+                }
+                Err(error) => {
+                    tracing::warn!(%error, %channel_id, %session_id, "failed to reconcile session history");
+                }
+            }
+            Ok(Some(session))
+        }
+        None => {
+            tracing::info!(
+                %channel_id,
+                %session_id,
+                "recorded OpenCode session no longer exists, clearing"
+            );
+            store.clear_active_session(channel_id).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// The channel's session, reused if OpenCode still has it, otherwise created
+/// (with `title`) and recorded as the channel's active session.
+///
+/// Safe to call before every prompt: a session lost to an OpenCode restart
+/// is replaced instead of failing with "session not found".
+pub async fn ensure_session(
+    store: &ChannelStore,
+    logger: &ConversationLogger,
+    server: &OpenCodeServer,
+    channel_id: &ChannelId,
+    title: Option<String>,
+) -> anyhow::Result<Session> {
+    if let Some(session) = resume_active_session(store, logger, server, channel_id).await? {
+        return Ok(session);
+    }
+
+    let session = server.create_session(title).await?;
+    store.set_active_session(channel_id, &session.id).await?;
+    if let Some(title) = &session.title {
+        store.set_session_title(channel_id, &session.id, title).await?;
+    }
+    tracing::info!(%channel_id, session_id = %session.id, "created OpenCode session for channel");
+    Ok(session)
+}
+
+/// Copy messages OpenCode has but the local history doesn't into
+/// `conversation_messages`. Returns how many were backfilled.
+///
+/// "Missing" means newer than the channel's newest local message; older
+/// server messages are assumed mirrored already. A message already
+/// backfilled, by OpenCode message ID, is skipped, so overlapping runs don't
+/// duplicate it. Messages made only of
+/// injected context are stored as synthetic; messages without text
+/// (tool-only steps) are skipped. History is streamed, so long sessions
+/// aren't held in memory.
+pub async fn reconcile_session_history(
+    logger: &ConversationLogger,
+    server: &OpenCodeServer,
+    channel_id: &ChannelId,
+    session_id: &str,
+) -> anyhow::Result<usize> {
+    let cutoff = logger.latest_message_time(channel_id).await?;
+    let mut messages = server.stream_session_messages(session_id).await?;
+
+    let mut backfilled = 0;
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let Some(created_at) = message.created_at() else {
+            continue;
+        };
+        if cutoff.is_some_and(|cutoff| created_at <= cutoff) {
+            continue;
+        }
+        let (text, is_synthetic) = match message.text() {
+            text if !text.is_empty() => (text, false),
+            _ => (message.synthetic_text(), true),
+        };
+        if text.is_empty() {
+            continue;
+        }
+        if logger
+            .backfill_message(channel_id, &message.info, &text, is_synthetic, created_at)
+            .await?
+        {
+            backfilled += 1;
+        }
+    }
+
+    Ok(backfilled)
+}
+
+/// Rename a channel's session in OpenCode and record the title OpenCode
+/// accepted (which may be truncated) against the channel.
+pub async fn apply_session_title(
+    store: &ChannelStore,
+    server: &OpenCodeServer,
+    channel_id: &str,
+    session_id: &str,
+    title: &str,
+) -> anyhow::Result<Session> {
+    let session = server.set_session_title(session_id, title).await?;
+    let accepted = session.title.as_deref().unwrap_or(title);
+    store.set_session_title(channel_id, session_id, accepted).await?;
+    Ok(session)
+}
+
+/// Maps every known session ID back to the channel that started it.
+///
+/// Root sessions are registered with their channel when created. Child
+/// sessions (spawned by OpenCode's `task` tool) are learned from
+/// `session.created` events and resolve through their `parent_id` chain.
+#[derive(Debug, Default)]
+pub struct SessionRegistry {
+    sessions: RwLock<HashMap<String, SessionEntry>>,
+}
+
+/// Where an event for a session belongs, from `SessionRegistry::route`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionRoute {
+    /// A top-level session, started by `channel_id` (`None` for workers
+    /// without a channel).
+    Root { channel_id: Option<ChannelId> },
+    /// A sub-agent session under `root_id`.
+    Child { root_id: String, channel_id: Option<ChannelId> },
+    /// No session we know of: a stale event after a reset, or another
+    /// client's session on a shared server. Nothing should act on it.
+    Orphan,
+}
+
+#[derive(Debug, Clone)]
+struct SessionEntry {
+    channel_id: Option<ChannelId>,
+    parent_id: Option<String>,
+}
+
+impl SessionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a top-level session started on behalf of a channel.
+    pub fn register_root(&self, session_id: impl Into<String>, channel_id: Option<ChannelId>) {
+        self.insert(session_id.into(), SessionEntry { channel_id, parent_id: None });
+    }
+
+    /// Register a child session under its parent.
+    pub fn register_child(&self, session_id: impl Into<String>, parent_id: impl Into<String>) {
+        self.insert(
+            session_id.into(),
+            SessionEntry {
+                channel_id: None,
+                parent_id: Some(parent_id.into()),
+            },
+        );
+    }
+
+    /// Learn about child sessions from the event stream. Returns `true` if the
+    /// event registered a new child of a known session.
+    pub fn observe(&self, event: &SseEvent) -> bool {
+        let SseEvent::SessionCreated { info } = event else {
+            return false;
+        };
+        let Some(parent_id) = &info.parent_id else {
+            return false;
+        };
+        if !self.contains(parent_id) {
+            return false;
+        }
+        self.register_child(info.id.clone(), parent_id.clone());
+        true
+    }
+
+    pub fn contains(&self, session_id: &str) -> bool {
+        self.read().contains_key(session_id)
+    }
+
+    /// The top-level session a session descends from (itself for roots).
+    pub fn root_of(&self, session_id: &str) -> Option<String> {
+        let sessions = self.read();
+        let mut current = session_id;
+        for _ in 0..MAX_SESSION_DEPTH {
+            let entry = sessions.get(current)?;
+            match &entry.parent_id {
+                Some(parent_id) => current = parent_id,
+                None => return Some(current.to_string()),
+            }
+        }
+        tracing::warn!(%session_id, "session parent chain too deep, ignoring");
+        None
+    }
+
+    /// The channel that originated a session, following the parent chain.
+    pub fn resolve_channel(&self, session_id: &str) -> Option<ChannelId> {
+        let root = self.root_of(session_id)?;
+        self.read().get(&root)?.channel_id.clone()
+    }
+
+    /// Where events for `session_id` should go. Never fails: sessions that
+    /// don't resolve, including ones caught in a parent cycle, are orphans.
+    pub fn route(&self, session_id: &str) -> SessionRoute {
+        let Some(root_id) = self.root_of(session_id) else {
+            return SessionRoute::Orphan;
+        };
+        let channel_id = self.read().get(&root_id).and_then(|entry| entry.channel_id.clone());
+        if root_id == session_id {
+            SessionRoute::Root { channel_id }
+        } else {
+            SessionRoute::Child { root_id, channel_id }
+        }
+    }
+
+    /// Whether a session is a known descendant of `root_id` (not `root_id` itself).
+    pub fn is_descendant_of(&self, session_id: &str, root_id: &str) -> bool {
+        session_id != root_id && self.root_of(session_id).as_deref() == Some(root_id)
+    }
+
+    /// Forget a session and every session descending from it.
+    pub fn remove_tree(&self, root_id: &str) {
+        let descendants: Vec<String> = {
+            let sessions = self.read();
+            sessions
+                .keys()
+                .filter(|id| self.is_descendant_of_locked(&sessions, id, root_id))
+                .cloned()
+                .collect()
+        };
+        let mut sessions = self.sessions.write().unwrap_or_else(|e| e.into_inner());
+        sessions.remove(root_id);
+        for id in descendants {
+            sessions.remove(&id);
+        }
+    }
+
+    fn is_descendant_of_locked(
+        &self,
+        sessions: &HashMap<String, SessionEntry>,
+        session_id: &str,
+        root_id: &str,
+    ) -> bool {
+        let mut current = session_id;
+        for _ in 0..MAX_SESSION_DEPTH {
+            match sessions.get(current).and_then(|e| e.parent_id.as_deref()) {
+                Some(parent_id) if parent_id == root_id => return true,
+                Some(parent_id) => current = parent_id,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    fn insert(&self, session_id: String, entry: SessionEntry) {
+        self.sessions
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(session_id, entry);
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<String, SessionEntry>> {
+        self.sessions.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opencode::server::tests::mock_server;
+    use crate::db::connect_in_memory;
+
+    fn created(id: &str, parent_id: Option<&str>) -> SseEvent {
+        SseEvent::SessionCreated {
+            info: Session {
+                id: id.into(),
+                title: None,
+                parent_id: parent_id.map(String::from),
+            },
+        }
+    }
+
+    #[test]
+    fn test_child_sessions_resolve_to_parent_channel() {
+        let registry = SessionRegistry::new();
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        registry.register_root("ses_root", Some(channel_id.clone()));
+
+        assert!(registry.observe(&created("ses_child", Some("ses_root"))));
+        assert!(registry.observe(&created("ses_grandchild", Some("ses_child"))));
+        // Children of sessions we don't own are ignored.
+        assert!(!registry.observe(&created("ses_other", Some("ses_unknown"))));
+        assert!(!registry.observe(&created("ses_new_root", None)));
+
+        assert_eq!(registry.resolve_channel("ses_grandchild"), Some(channel_id.clone()));
+        assert_eq!(registry.root_of("ses_child").as_deref(), Some("ses_root"));
+        assert!(registry.is_descendant_of("ses_grandchild", "ses_root"));
+        assert!(!registry.is_descendant_of("ses_root", "ses_root"));
+        assert_eq!(registry.resolve_channel("ses_other"), None);
+
+        registry.remove_tree("ses_root");
+        assert!(!registry.contains("ses_child"));
+        assert!(!registry.contains("ses_grandchild"));
+        assert_eq!(registry.resolve_channel("ses_root"), None);
+    }
+
+    #[test]
+    fn test_route_unknown_and_child_sessions() {
+        let registry = SessionRegistry::new();
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        registry.register_root("ses_root", Some(channel_id.clone()));
+        registry.observe(&created("ses_child", Some("ses_root")));
+
+        assert_eq!(registry.route("ses_root"), SessionRoute::Root { channel_id: Some(channel_id.clone()) });
+        assert_eq!(
+            registry.route("ses_child"),
+            SessionRoute::Child { root_id: "ses_root".into(), channel_id: Some(channel_id) }
+        );
+        assert_eq!(registry.route("ses_stale"), SessionRoute::Orphan);
+
+        // A child whose parent was forgotten by a reset is an orphan too.
+        registry.register_child("ses_lost", "ses_gone");
+        assert_eq!(registry.route("ses_lost"), SessionRoute::Orphan);
+        registry.remove_tree("ses_root");
+        assert_eq!(registry.route("ses_child"), SessionRoute::Orphan);
+    }
+
+    #[test]
+    fn test_parent_cycle_does_not_hang() {
+        let registry = SessionRegistry::new();
+        registry.register_child("ses_a", "ses_b");
+        registry.register_child("ses_b", "ses_a");
+        assert_eq!(registry.root_of("ses_a"), None);
+    }
+
+    #[tokio::test]
+    async fn test_ensure_session_replaces_stale_session() {
+        let app = axum::Router::new()
+            .route(
+                "/session",
+                axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+                    axum::Json(serde_json::json!({ "id": "ses_new", "title": body["title"] }))
+                }),
+            )
+            .route(
+                "/session/{id}",
+                axum::routing::get(|axum::extract::Path(id): axum::extract::Path<String>| async move {
+                    match id.as_str() {
+                        "ses_new" => Ok(axum::Json(serde_json::json!({ "id": "ses_new" }))),
+                        _ => Err(axum::http::StatusCode::NOT_FOUND),
+                    }
+                }),
+            )
+            .route(
+                "/session/{id}/message",
+                axum::routing::get(|| async { axum::Json(serde_json::json!([])) }),
+            );
+        let server = mock_server(app).await;
+        let pool = connect_in_memory().await;
+        let store = ChannelStore::new(pool.clone());
+        let logger = ConversationLogger::new(pool);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        // The recorded session is gone (e.g. OpenCode's storage was wiped).
+        store.set_active_session(&channel_id, "ses_stale").await.unwrap();
+        let session = ensure_session(&store, &logger, &server, &channel_id, Some("Fix CI".into()))
+            .await
+            .unwrap();
+        assert_eq!(session.id, "ses_new");
+        assert_eq!(store.get_active_session(&channel_id).await.unwrap().as_deref(), Some("ses_new"));
+        assert_eq!(store.get_session_title(&channel_id).await.unwrap().as_deref(), Some("Fix CI"));
+
+        // Once recorded, the same session is reused.
+        let session = ensure_session(&store, &logger, &server, &channel_id, None).await.unwrap();
+        assert_eq!(session.id, "ses_new");
+    }
+
+    #[tokio::test]
+    async fn test_reconcile_backfills_each_message_once() {
+        let message = |id: &str, role: &str, start: u64, text: &str| {
+            serde_json::json!({
+                "info": { "id": id, "role": role, "sessionID": "ses_1", "time": { "start": start } },
+                "parts": [{ "type": "text", "id": format!("prt_{id}"), "sessionID": "ses_1", "messageID": id, "text": text }],
+            })
+        };
+        let messages = serde_json::json!([
+            message("msg_1", "user", 1_770_927_523_000, "fix the tests"),
+            message("msg_2", "assistant", 1_770_927_528_000, "done"),
+        ]);
+        let app = axum::Router::new().route(
+            "/session/{id}/message",
+            axum::routing::get(move || async move { axum::Json(messages) }),
+        );
+        let server = mock_server(app).await;
+        let pool = connect_in_memory().await;
+        let logger = ConversationLogger::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        // Two runs racing on the same cutoff still store each message once.
+        let (first, second) = tokio::join!(
+            reconcile_session_history(&logger, &server, &channel_id, "ses_1"),
+            reconcile_session_history(&logger, &server, &channel_id, "ses_1"),
+        );
+        assert_eq!(first.unwrap() + second.unwrap(), 2);
+        assert_eq!(reconcile_session_history(&logger, &server, &channel_id, "ses_1").await.unwrap(), 0);
+
+        let (rows,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM conversation_messages WHERE channel_id = ?")
+            .bind(channel_id.as_ref())
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(rows, 2);
+    }
+}
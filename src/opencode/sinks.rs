@@ -0,0 +1,233 @@
+//! Forwarding finished assistant replies to external systems.
+//!
+//! A `ReplySink` receives every reply an OpenCode worker finishes, once its
+//! session goes idle: another chat, a log pipeline, an analytics store.
+//! `ReplySinks` fans a reply out to each configured sink in its own task, so
+//! a slow or failing sink never holds up the others or the worker. The
+//! built-in `WebhookReplySink` POSTs the reply as JSON. This is separate from
+//! the session error webhook, which only reports failures.
+
+use crate::conversation::history::ConversationMessage;
+use crate::opencode::types::TurnOutcome;
+use crate::opencode::webhook::post_json;
+use crate::ChannelId;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Longest a sink may take with one reply before it's abandoned.
+const EMIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Somewhere finished replies are sent.
+#[async_trait]
+pub trait ReplySink: Send + Sync + std::fmt::Debug {
+    /// Short name for logs.
+    fn name(&self) -> &str;
+
+    async fn emit(&self, channel_id: &ChannelId, message: &ConversationMessage) -> anyhow::Result<()>;
+}
+
+/// Every sink a worker sends its replies to.
+#[derive(Debug, Clone, Default)]
+pub struct ReplySinks {
+    sinks: Vec<Arc<dyn ReplySink>>,
+}
+
+impl ReplySinks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_sink(mut self, sink: Arc<dyn ReplySink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    /// Send `message` to every sink. Fire-and-forget: each sink runs in its
+    /// own task, and failures and timeouts are logged.
+    pub fn dispatch(&self, channel_id: &ChannelId, message: &ConversationMessage) {
+        for sink in &self.sinks {
+            let sink = sink.clone();
+            let channel_id = channel_id.clone();
+            let message = message.clone();
+            tokio::spawn(async move {
+                match tokio::time::timeout(EMIT_TIMEOUT, sink.emit(&channel_id, &message)).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(error)) => {
+                        tracing::warn!(%error, sink = sink.name(), %channel_id, "failed to emit reply");
+                    }
+                    Err(_) => tracing::warn!(sink = sink.name(), %channel_id, "reply sink timed out"),
+                }
+            });
+        }
+    }
+}
+
+/// The reply a finished turn produced, as the message sinks receive. `None`
+/// if the turn produced no text.
+pub fn reply_message(channel_id: &ChannelId, outcome: &TurnOutcome) -> Option<ConversationMessage> {
+    if outcome.text.trim().is_empty() {
+        return None;
+    }
+    let metadata = outcome.model.as_deref().and_then(|model| {
+        let (provider_id, model_id) = model.split_once('/')?;
+        Some(serde_json::json!({ "provider_id": provider_id, "model_id": model_id }).to_string())
+    });
+    Some(ConversationMessage {
+        id: uuid::Uuid::new_v4().to_string(),
+        channel_id: channel_id.to_string(),
+        role: "assistant".into(),
+        sender_name: None,
+        sender_id: None,
+        content: outcome.text.clone(),
+        metadata,
+        token_count: outcome.tokens.as_ref().map(|tokens| tokens.output as i64),
+        is_synthetic: false,
+        is_hidden: false,
+        created_at: chrono::Utc::now(),
+    })
+}
+
+/// Body POSTed by `WebhookReplySink` for each reply.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplyPayload {
+    pub channel_id: String,
+    pub message_id: String,
+    pub content: String,
+    /// `provider/model` that wrote the reply, if OpenCode reported it.
+    pub model: Option<String>,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// POSTs each reply as JSON to a URL, retrying connection errors, 429s, and
+/// 5xx responses up to three times.
+#[derive(Debug, Clone)]
+pub struct WebhookReplySink {
+    client: reqwest::Client,
+    url: String,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl WebhookReplySink {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            url: url.into(),
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+#[async_trait]
+impl ReplySink for WebhookReplySink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn emit(&self, channel_id: &ChannelId, message: &ConversationMessage) -> anyhow::Result<()> {
+        let payload = ReplyPayload {
+            channel_id: channel_id.to_string(),
+            message_id: message.id.clone(),
+            content: message.content.clone(),
+            model: message.model(),
+            timestamp: message.created_at,
+        };
+        post_json(&self.client, &self.url, &payload, self.max_attempts, self.initial_backoff).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    #[derive(Debug)]
+    struct ChannelSink(mpsc::UnboundedSender<String>);
+
+    #[async_trait]
+    impl ReplySink for ChannelSink {
+        fn name(&self) -> &str {
+            "channel"
+        }
+
+        async fn emit(&self, _channel_id: &ChannelId, message: &ConversationMessage) -> anyhow::Result<()> {
+            let _ = self.0.send(message.content.clone());
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct StuckSink;
+
+    #[async_trait]
+    impl ReplySink for StuckSink {
+        fn name(&self) -> &str {
+            "stuck"
+        }
+
+        async fn emit(&self, _channel_id: &ChannelId, _message: &ConversationMessage) -> anyhow::Result<()> {
+            std::future::pending::<()>().await;
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct FailingSink;
+
+    #[async_trait]
+    impl ReplySink for FailingSink {
+        fn name(&self) -> &str {
+            "failing"
+        }
+
+        async fn emit(&self, _channel_id: &ChannelId, _message: &ConversationMessage) -> anyhow::Result<()> {
+            anyhow::bail!("sink is down")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_fans_out_past_failures() {
+        let (first_tx, mut first_rx) = mpsc::unbounded_channel();
+        let (second_tx, mut second_rx) = mpsc::unbounded_channel();
+        let sinks = ReplySinks::new()
+            .with_sink(Arc::new(StuckSink))
+            .with_sink(Arc::new(ChannelSink(first_tx)))
+            .with_sink(Arc::new(FailingSink))
+            .with_sink(Arc::new(ChannelSink(second_tx)));
+        assert_eq!(sinks.len(), 4);
+
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        let outcome = TurnOutcome {
+            text: "done".into(),
+            finish_reason: None,
+            tool_errored: false,
+            tokens: None,
+            model: Some("anthropic/claude-sonnet-4".into()),
+            duration_ms: None,
+        };
+        let message = reply_message(&channel_id, &outcome).unwrap();
+        assert_eq!(message.model().as_deref(), Some("anthropic/claude-sonnet-4"));
+
+        sinks.dispatch(&channel_id, &message);
+        assert_eq!(first_rx.recv().await.as_deref(), Some("done"));
+        assert_eq!(second_rx.recv().await.as_deref(), Some("done"));
+
+        let empty = TurnOutcome { text: " ".into(), ..outcome };
+        assert!(reply_message(&channel_id, &empty).is_none());
+    }
+}
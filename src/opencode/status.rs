@@ -0,0 +1,240 @@
+//! Self-reported bot status for a channel, behind the `!status` command.
+//!
+//! `status_report` gathers what the bot knows about a channel from OpenCode's
+//! health and pending-request endpoints, the channel → session mapping, and
+//! the conversation history. Each source is queried independently, so an
+//! unreachable OpenCode server shows up in the report instead of failing it.
+
+use crate::conversation::channels::ChannelStore;
+use crate::opencode::breaker::BreakerState;
+use crate::conversation::history::ConversationLogger;
+use crate::opencode::server::OpenCodeServer;
+use crate::ChannelId;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// Whether the channel's OpenCode server answered its health check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OpenCodeHealth {
+    Healthy { version: Option<String> },
+    Unreachable { error: String },
+}
+
+/// A snapshot of the bot's state for one channel. Fields that couldn't be
+/// determined are `None`.
+#[derive(Debug, Clone)]
+pub struct StatusReport {
+    pub opencode: OpenCodeHealth,
+    /// The circuit breaker guarding calls to OpenCode.
+    pub breaker: BreakerState,
+    /// The OpenCode session recorded for the channel.
+    pub active_session: Option<String>,
+    pub messages_since_compaction: Option<usize>,
+    /// Permission requests in the channel's session tree waiting for a reply.
+    pub pending_permissions: Option<usize>,
+    /// Questions in the channel's session tree waiting for an answer.
+    pub pending_questions: Option<usize>,
+    pub uptime: Duration,
+}
+
+impl StatusReport {
+    /// Render the report as a chat message.
+    pub fn render(&self) -> String {
+        let opencode = match &self.opencode {
+            OpenCodeHealth::Healthy { version: Some(version) } => format!("healthy (v{version})"),
+            OpenCodeHealth::Healthy { version: None } => "healthy".to_string(),
+            OpenCodeHealth::Unreachable { error } => format!("unreachable ({error})"),
+        };
+        let pending = match (self.pending_permissions, self.pending_questions) {
+            (Some(permissions), Some(questions)) => format!(
+                "{permissions} permission{}, {questions} question{}",
+                if permissions == 1 { "" } else { "s" },
+                if questions == 1 { "" } else { "s" },
+            ),
+            _ => "unknown".to_string(),
+        };
+
+        let lines = [
+            format!("**OpenCode:** {opencode}"),
+            format!("**Circuit breaker:** {}", self.breaker),
+            format!("**Session:** {}", self.active_session.as_deref().unwrap_or("none")),
+            format!(
+                "**Messages since compaction:** {}",
+                self.messages_since_compaction.map_or("unknown".to_string(), |count| count.to_string())
+            ),
+            format!("**Pending:** {pending}"),
+            format!("**Uptime:** {}", format_uptime(self.uptime)),
+        ];
+        lines.join("\n")
+    }
+}
+
+/// Gather a `StatusReport` for `channel_id`. Never fails: a source that
+/// errors is logged and reported as unknown or unreachable.
+pub async fn status_report(
+    server: &OpenCodeServer,
+    store: &ChannelStore,
+    logger: &ConversationLogger,
+    channel_id: &ChannelId,
+    uptime: Duration,
+) -> StatusReport {
+    let opencode = match server.version().await {
+        Ok(version) => OpenCodeHealth::Healthy { version },
+        Err(error) => OpenCodeHealth::Unreachable { error: format!("{error:#}") },
+    };
+
+    let active_session = store.get_active_session(channel_id).await.unwrap_or_else(|error| {
+        tracing::warn!(%error, %channel_id, "failed to load active session for status");
+        None
+    });
+
+    let messages_since_compaction = match logger.turns_since_last_compaction(channel_id).await {
+        Ok(count) => Some(count),
+        Err(error) => {
+            tracing::warn!(%error, %channel_id, "failed to count messages for status");
+            None
+        }
+    };
+
+    let (pending_permissions, pending_questions) = match (&opencode, &active_session) {
+        (OpenCodeHealth::Unreachable { .. }, _) => (None, None),
+        (OpenCodeHealth::Healthy { .. }, None) => (Some(0), Some(0)),
+        (OpenCodeHealth::Healthy { .. }, Some(session_id)) => {
+            let sessions = session_tree(server, session_id).await;
+            let permissions = match server.list_permissions().await {
+                Ok(requests) => Some(requests.iter().filter(|request| sessions.contains(&request.session_id)).count()),
+                Err(error) => {
+                    tracing::warn!(%error, %channel_id, "failed to list pending permissions for status");
+                    None
+                }
+            };
+            let questions = match server.list_questions().await {
+                Ok(requests) => Some(requests.iter().filter(|request| sessions.contains(&request.session_id)).count()),
+                Err(error) => {
+                    tracing::warn!(%error, %channel_id, "failed to list pending questions for status");
+                    None
+                }
+            };
+            (permissions, questions)
+        }
+    };
+
+    StatusReport {
+        opencode,
+        breaker: server.breaker_state(),
+        active_session,
+        messages_since_compaction,
+        pending_permissions,
+        pending_questions,
+        uptime,
+    }
+}
+
+/// `root_id` and every session descended from it, so requests from
+/// sub-agent sessions count toward the channel. Just `root_id` if the
+/// session list can't be loaded.
+async fn session_tree(server: &OpenCodeServer, root_id: &str) -> HashSet<String> {
+    let mut tree = HashSet::from([root_id.to_string()]);
+    let sessions = match server.list_sessions(true).await {
+        Ok(sessions) => sessions,
+        Err(error) => {
+            tracing::debug!(%error, "failed to list sessions for status; counting the root session only");
+            return tree;
+        }
+    };
+
+    // Sessions may be listed before their parents, so repeat until no new
+    // descendants turn up.
+    loop {
+        let before = tree.len();
+        for session in &sessions {
+            if let Some(parent_id) = &session.parent_id {
+                if tree.contains(parent_id) {
+                    tree.insert(session.id.clone());
+                }
+            }
+        }
+        if tree.len() == before {
+            return tree;
+        }
+    }
+}
+
+/// `3h 12m`, `12m 5s`, or `42s`.
+fn format_uptime(uptime: Duration) -> String {
+    let seconds = uptime.as_secs();
+    let (hours, minutes, seconds) = (seconds / 3600, (seconds % 3600) / 60, seconds % 60);
+    if hours > 0 {
+        format!("{hours}h {minutes}m")
+    } else if minutes > 0 {
+        format!("{minutes}m {seconds}s")
+    } else {
+        format!("{seconds}s")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::opencode::server::tests::mock_server;
+    use crate::db::connect_in_memory;
+
+    #[tokio::test]
+    async fn test_status_report() {
+        let app = axum::Router::new()
+            .route(
+                "/global/health",
+                axum::routing::get(|| async { axum::Json(serde_json::json!({ "healthy": true, "version": "1.1.36" })) }),
+            )
+            .route(
+                "/session",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!([
+                        { "id": "ses_grandchild", "parentID": "ses_child" },
+                        { "id": "ses_child", "parentID": "ses_root" },
+                        { "id": "ses_root" },
+                        { "id": "ses_other" },
+                    ]))
+                }),
+            )
+            .route(
+                "/permission",
+                axum::routing::get(|| async {
+                    axum::Json(serde_json::json!([
+                        { "id": "per_1", "sessionID": "ses_root", "permission": "bash" },
+                        { "id": "per_2", "sessionID": "ses_grandchild", "permission": "edit" },
+                        { "id": "per_3", "sessionID": "ses_other", "permission": "bash" },
+                    ]))
+                }),
+            )
+            .route(
+                "/question",
+                axum::routing::get(|| async { axum::Json(serde_json::json!([{ "id": "que_1", "sessionID": "ses_other" }])) }),
+            );
+        let server = mock_server(app).await;
+        let pool = connect_in_memory().await;
+        let store = ChannelStore::new(pool.clone());
+        let logger = ConversationLogger::new(pool);
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        store.set_active_session(&channel_id, "ses_root").await.unwrap();
+
+        let report = status_report(&server, &store, &logger, &channel_id, Duration::from_secs(3 * 3600 + 120)).await;
+        assert_eq!(report.opencode, OpenCodeHealth::Healthy { version: Some("1.1.36".into()) });
+        assert_eq!(report.active_session.as_deref(), Some("ses_root"));
+        assert_eq!(report.messages_since_compaction, Some(0));
+        assert_eq!((report.pending_permissions, report.pending_questions), (Some(2), Some(0)));
+        let rendered = report.render();
+        assert!(rendered.contains("healthy (v1.1.36)"));
+        assert!(rendered.contains("**Circuit breaker:** closed"));
+        assert!(rendered.contains("2 permissions, 0 questions"));
+        assert!(rendered.contains("3h 2m"));
+
+        let offline = OpenCodeServer::offline("/tmp".into());
+        let report = status_report(&offline, &store, &logger, &channel_id, Duration::from_secs(42)).await;
+        assert!(matches!(report.opencode, OpenCodeHealth::Unreachable { .. }));
+        assert_eq!(report.active_session.as_deref(), Some("ses_root"));
+        assert_eq!(report.pending_permissions, None);
+        assert!(report.render().contains("**Pending:** unknown"));
+    }
+}
@@ -0,0 +1,529 @@
+//! Streaming assistant text into chat.
+//!
+//! OpenCode's `message.part.updated` carries both a `delta` and the full
+//! `text` so far. Deltas can repeat or arrive after a newer snapshot, so
+//! blindly concatenating them double-prints. `TextAccumulator` tracks what has
+//! already been emitted per part and yields only the new suffix.
+//!
+//! `StreamCoordinator` turns that suffix into `OutboundResponse` stream
+//! messages: a placeholder, debounced edits, and a finalize on idle.
+//!
+//! A message edited mid-stream often ends inside a code fence, which chat
+//! clients render as everything after it being code (or not at all).
+//! `MarkdownStreamGuard` tracks fences in the streamed text so each edit is
+//! sent with any open fence closed, and a message split inside a fence is
+//! closed and reopened in the next one.
+
+use crate::OutboundResponse;
+use crate::opencode::types::{Part, SseEvent};
+
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Per-part record of text already handed to the chat.
+#[derive(Debug, Default)]
+pub struct TextAccumulator {
+    parts: HashMap<String, String>,
+}
+
+impl TextAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fold in one update for a text part and return the text to append, if any.
+    ///
+    /// A non-empty `text` is authoritative: the output is whatever it adds on
+    /// top of what was already emitted, and stale snapshots (a prefix of what
+    /// was emitted) are ignored. `delta` is only used when no snapshot came
+    /// with the update. If the snapshot rewrites earlier text, the record is
+    /// reset to it and nothing is emitted, since sent output can't be taken back.
+    pub fn push(&mut self, part_id: &str, text: &str, delta: Option<&str>) -> Option<String> {
+        let emitted = self.parts.entry(part_id.to_string()).or_default();
+
+        if !text.is_empty() {
+            if let Some(suffix) = text.strip_prefix(emitted.as_str()) {
+                let suffix = suffix.to_string();
+                *emitted = text.to_string();
+                return (!suffix.is_empty()).then_some(suffix);
+            }
+            if !emitted.starts_with(text) {
+                *emitted = text.to_string();
+            }
+            return None;
+        }
+
+        let delta = delta.filter(|d| !d.is_empty())?;
+        emitted.push_str(delta);
+        Some(delta.to_string())
+    }
+
+    /// Fold in a `message.part.updated` event for a text part.
+    /// Returns the part ID with the text to append.
+    pub fn push_event(&mut self, event: &SseEvent) -> Option<(String, String)> {
+        let SseEvent::MessagePartUpdated {
+            part: Part::Text { id, text, .. },
+            delta,
+        } = event
+        else {
+            return None;
+        };
+        let output = self.push(id, text, delta.as_deref())?;
+        Some((id.clone(), output))
+    }
+
+    /// Everything emitted so far for a part.
+    pub fn text(&self, part_id: &str) -> Option<&str> {
+        self.parts.get(part_id).map(String::as_str)
+    }
+
+    /// Forget a part, e.g. after `message.part.removed`.
+    pub fn remove(&mut self, part_id: &str) {
+        self.parts.remove(part_id);
+    }
+}
+
+/// A fenced code block's opening line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeFence {
+    /// '`' or '~'.
+    marker: char,
+    /// Length of the marker run; a closing fence needs at least as many.
+    len: usize,
+    /// The opening line, info string included, without indentation.
+    opener: String,
+}
+
+impl CodeFence {
+    /// Parse a line that opens a fence: up to three spaces, then three or
+    /// more backticks or tildes. A backtick fence's info string can't
+    /// contain backticks.
+    fn parse(line: &str) -> Option<Self> {
+        let trimmed = line.trim_start_matches(' ');
+        if line.len() - trimmed.len() > 3 {
+            return None;
+        }
+        let marker = trimmed.chars().next().filter(|c| *c == '`' || *c == '~')?;
+        let len = trimmed.chars().take_while(|c| *c == marker).count();
+        if len < 3 {
+            return None;
+        }
+        let info = &trimmed[len..];
+        if marker == '`' && info.contains('`') {
+            return None;
+        }
+        Some(Self { marker, len, opener: trimmed.trim_end().to_string() })
+    }
+
+    /// Whether `line` closes this fence: the same marker, at least as long,
+    /// and nothing after it but whitespace.
+    fn is_closed_by(&self, line: &str) -> bool {
+        Self::parse(line).is_some_and(|fence| {
+            fence.marker == self.marker && fence.len >= self.len && fence.opener.len() == fence.len
+        })
+    }
+
+    /// The opening line, to reopen the fence in a new message.
+    pub fn opener(&self) -> &str {
+        &self.opener
+    }
+
+    /// A line closing this fence.
+    pub fn closer(&self) -> String {
+        std::iter::repeat_n(self.marker, self.len).collect()
+    }
+}
+
+/// Tracks the code fence left open by streamed Markdown, so partial text can
+/// be closed off before it's shown.
+///
+/// Complete lines are folded in as they arrive. The trailing partial line is
+/// only provisionally considered: `"```"` with no newline yet already closes
+/// a fence as far as a renderer is concerned, but could still grow into
+/// `"```rust"`. Fences are matched the CommonMark way, so a shorter run or
+/// the other marker inside a block (e.g. a ```` ``` ```` example inside a
+/// ```` ```` ```` block) is content, not a close.
+#[derive(Debug, Default, Clone)]
+pub struct MarkdownStreamGuard {
+    /// The fence open after the last complete line.
+    open: Option<CodeFence>,
+    /// Text after the last newline.
+    partial: String,
+}
+
+impl MarkdownStreamGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A guard that has already seen `text`.
+    pub fn from_text(text: &str) -> Self {
+        let mut guard = Self::new();
+        guard.push(text);
+        guard
+    }
+
+    /// Fold in appended text.
+    pub fn push(&mut self, text: &str) {
+        self.partial.push_str(text);
+        while let Some(newline) = self.partial.find('\n') {
+            let line: String = self.partial.drain(..=newline).collect();
+            self.open = Self::step(self.open.take(), line.trim_end_matches(['\n', '\r']));
+        }
+    }
+
+    /// The fence open at the end of the text so far, if any.
+    pub fn open_fence(&self) -> Option<CodeFence> {
+        if self.partial.is_empty() {
+            return self.open.clone();
+        }
+        Self::step(self.open.clone(), &self.partial)
+    }
+
+    /// `text` (the text this guard has seen) with any open fence closed.
+    pub fn render(&self, text: &str) -> String {
+        match self.open_fence() {
+            Some(fence) => close_fence(text, &fence),
+            None => text.to_string(),
+        }
+    }
+
+    fn step(open: Option<CodeFence>, line: &str) -> Option<CodeFence> {
+        match open {
+            Some(fence) if fence.is_closed_by(line) => None,
+            Some(fence) => Some(fence),
+            None => CodeFence::parse(line),
+        }
+    }
+}
+
+/// Append `fence`'s closing line to `text`.
+fn close_fence(text: &str, fence: &CodeFence) -> String {
+    let separator = if text.is_empty() || text.ends_with('\n') { "" } else { "\n" };
+    format!("{text}{separator}{}", fence.closer())
+}
+
+/// Drives a placeholder-and-edit chat reply from streamed text parts.
+///
+/// Edits are debounced: pending text goes out once `edit_interval` has passed
+/// since it started piling up, or immediately once `edit_chars` bytes are
+/// waiting. A message that would exceed `max_message_len` (2000 on Discord) is
+/// finalized and the overflow rolls into a new message. A new text part also
+/// starts a new message. Every chunk sent has its open code fence closed; a
+/// fence cut by a rollover is reopened at the top of the next message.
+#[derive(Debug)]
+pub struct StreamCoordinator {
+    accumulator: TextAccumulator,
+    max_message_len: usize,
+    edit_interval: Duration,
+    edit_chars: usize,
+    /// Text part currently streaming into the open message.
+    part_id: Option<String>,
+    /// Content of the open chat message, if one is open.
+    message: Option<String>,
+    /// Fences in `message`.
+    fences: MarkdownStreamGuard,
+    /// Length of `message` as of the last edit sent.
+    sent_len: usize,
+    /// When pending text is due to be sent.
+    edit_deadline: Option<Instant>,
+}
+
+impl StreamCoordinator {
+    pub fn new(max_message_len: usize, edit_interval: Duration, edit_chars: usize) -> Self {
+        Self {
+            accumulator: TextAccumulator::new(),
+            max_message_len,
+            edit_interval,
+            edit_chars,
+            part_id: None,
+            message: None,
+            fences: MarkdownStreamGuard::new(),
+            sent_len: 0,
+            edit_deadline: None,
+        }
+    }
+
+    /// Fold in a text update. Returns responses to send now.
+    pub fn push(
+        &mut self,
+        part_id: &str,
+        text: &str,
+        delta: Option<&str>,
+        now: Instant,
+    ) -> Vec<OutboundResponse> {
+        let Some(appended) = self.accumulator.push(part_id, text, delta) else {
+            return Vec::new();
+        };
+
+        let mut responses = Vec::new();
+        if self.part_id.as_deref() != Some(part_id) {
+            responses.extend(self.finish());
+            self.part_id = Some(part_id.to_string());
+        }
+        let message = self.message.get_or_insert_with(|| {
+            responses.push(OutboundResponse::StreamStart);
+            String::new()
+        });
+        message.push_str(&appended);
+        self.fences.push(&appended);
+
+        // Roll overflow into fresh messages, leaving room to close a fence.
+        while message.len() > self.max_message_len {
+            let mut split = split_point(message, self.max_message_len);
+            let mut fence = MarkdownStreamGuard::from_text(&message[..split]).open_fence();
+            if let Some(open) = &fence {
+                let reserved = self.max_message_len.saturating_sub(open.closer().len() + 1).max(1);
+                if split > reserved {
+                    split = split_point(message, reserved);
+                    fence = MarkdownStreamGuard::from_text(&message[..split]).open_fence();
+                }
+            }
+            // Reopening must still shrink the message, or this never ends.
+            let fence = fence.filter(|fence| split > fence.opener().len() + 1);
+            let rest = message.split_off(split);
+            let head = std::mem::take(message);
+            let rest = match fence {
+                Some(fence) => {
+                    responses.push(OutboundResponse::StreamChunk(close_fence(&head, &fence)));
+                    let rest = rest.strip_prefix('\n').unwrap_or(&rest);
+                    format!("{}\n{rest}", fence.opener())
+                }
+                None => {
+                    responses.push(OutboundResponse::StreamChunk(head));
+                    rest.trim_start().to_string()
+                }
+            };
+            responses.push(OutboundResponse::StreamEnd);
+            responses.push(OutboundResponse::StreamStart);
+            *message = rest;
+            self.fences = MarkdownStreamGuard::from_text(message);
+            self.sent_len = 0;
+        }
+
+        let pending = message.len() - self.sent_len;
+        if pending >= self.edit_chars {
+            responses.extend(self.send_edit());
+        } else if pending > 0 && self.edit_deadline.is_none() {
+            self.edit_deadline = Some(now + self.edit_interval);
+        }
+        responses
+    }
+
+    /// When pending text is due, if any is waiting.
+    pub fn edit_deadline(&self) -> Option<Instant> {
+        self.edit_deadline
+    }
+
+    /// Send pending text if its deadline has passed.
+    pub fn flush(&mut self, now: Instant) -> Vec<OutboundResponse> {
+        match self.edit_deadline {
+            Some(deadline) if deadline <= now => self.send_edit(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Send any pending text and close the open message. Call on idle.
+    pub fn finish(&mut self) -> Vec<OutboundResponse> {
+        let mut responses = self.send_edit();
+        if self.message.take().is_some() {
+            responses.push(OutboundResponse::StreamEnd);
+        }
+        self.fences = MarkdownStreamGuard::new();
+        self.sent_len = 0;
+        self.part_id = None;
+        responses
+    }
+
+    /// Forget a removed part so a re-sent copy streams again.
+    pub fn remove(&mut self, part_id: &str) {
+        self.accumulator.remove(part_id);
+    }
+
+    fn send_edit(&mut self) -> Vec<OutboundResponse> {
+        self.edit_deadline = None;
+        match &self.message {
+            Some(message) if message.len() > self.sent_len => {
+                self.sent_len = message.len();
+                vec![OutboundResponse::StreamChunk(self.fences.render(message))]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Where to cut `text` so the head fits in `max_len`: the last newline, else
+/// the last space, in the second half of the window; else a hard cut.
+fn split_point(text: &str, max_len: usize) -> usize {
+    let mut end = max_len.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let window = &text[..end];
+    window
+        .rfind('\n')
+        .or_else(|| window.rfind(' '))
+        .filter(|&index| index > end / 2)
+        .unwrap_or(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interleaved_deltas_and_snapshots() {
+        let mut accumulator = TextAccumulator::new();
+
+        assert_eq!(accumulator.push("prt_1", "", Some("Hel")).as_deref(), Some("Hel"));
+        // Snapshot that also covers the delta we already sent.
+        assert_eq!(accumulator.push("prt_1", "Hello", Some("lo")).as_deref(), Some("lo"));
+        // Repeated update.
+        assert_eq!(accumulator.push("prt_1", "Hello", Some("lo")), None);
+        // Stale snapshot arriving late.
+        assert_eq!(accumulator.push("prt_1", "Hel", Some("l")), None);
+        assert_eq!(accumulator.push("prt_1", "Hello, world", None).as_deref(), Some(", world"));
+        assert_eq!(accumulator.text("prt_1"), Some("Hello, world"));
+
+        // Parts are tracked independently.
+        assert_eq!(accumulator.push("prt_2", "Other", None).as_deref(), Some("Other"));
+        assert_eq!(accumulator.text("prt_1"), Some("Hello, world"));
+    }
+
+    #[test]
+    fn test_rewritten_snapshot_resets_without_output() {
+        let mut accumulator = TextAccumulator::new();
+        accumulator.push("prt_1", "draft answer", None);
+        assert_eq!(accumulator.push("prt_1", "final answer", None), None);
+        assert_eq!(accumulator.push("prt_1", "final answer!", None).as_deref(), Some("!"));
+
+        accumulator.remove("prt_1");
+        assert_eq!(accumulator.text("prt_1"), None);
+    }
+
+    #[test]
+    fn test_push_event_ignores_non_text_parts() {
+        let mut accumulator = TextAccumulator::new();
+        let envelope = serde_json::from_str(
+            r#"{"type":"message.part.updated","properties":{"part":{"id":"prt_1","type":"text","text":"hi"},"delta":"hi"}}"#,
+        )
+        .unwrap();
+        let event = SseEvent::from_envelope(envelope);
+        assert_eq!(accumulator.push_event(&event), Some(("prt_1".into(), "hi".into())));
+
+        let idle = SseEvent::SessionIdle { session_id: "ses_1".into() };
+        assert_eq!(accumulator.push_event(&idle), None);
+    }
+
+    fn chunks(responses: &[OutboundResponse]) -> Vec<String> {
+        responses
+            .iter()
+            .map(|response| match response {
+                OutboundResponse::StreamStart => "<start>".to_string(),
+                OutboundResponse::StreamChunk(text) => text.clone(),
+                OutboundResponse::StreamEnd => "<end>".to_string(),
+                other => panic!("unexpected response {other:?}"),
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_debounced_edits_and_finish() {
+        let mut coordinator = StreamCoordinator::new(2000, Duration::from_millis(750), 100);
+        let start = Instant::now();
+
+        let responses = coordinator.push("prt_1", "", Some("Hello"), start);
+        assert_eq!(chunks(&responses), ["<start>"]);
+        assert_eq!(coordinator.edit_deadline(), Some(start + Duration::from_millis(750)));
+
+        assert!(coordinator.push("prt_1", "Hello, wor", None, start).is_empty());
+        assert!(coordinator.flush(start + Duration::from_millis(100)).is_empty());
+
+        let responses = coordinator.flush(start + Duration::from_millis(750));
+        assert_eq!(chunks(&responses), ["Hello, wor"]);
+        assert!(coordinator.edit_deadline().is_none());
+
+        coordinator.push("prt_1", "Hello, world", None, start);
+        assert_eq!(chunks(&coordinator.finish()), ["Hello, world", "<end>"]);
+        assert!(coordinator.finish().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rolls_into_new_message_past_limit() {
+        let mut coordinator = StreamCoordinator::new(20, Duration::from_millis(750), 5);
+        let now = Instant::now();
+
+        let responses = coordinator.push("prt_1", "first line here\nsecond line", None, now);
+        assert_eq!(
+            chunks(&responses),
+            ["<start>", "first line here", "<end>", "<start>", "second line"]
+        );
+
+        // A new text part gets its own message.
+        let responses = coordinator.push("prt_2", "next part", None, now);
+        assert_eq!(chunks(&responses), ["<end>", "<start>", "next part"]);
+    }
+
+    #[test]
+    fn test_markdown_guard_tracks_fences() {
+        let mut guard = MarkdownStreamGuard::new();
+        guard.push("Run this:\n```ba");
+        // The partial line already opens a fence.
+        assert_eq!(guard.render("Run this:\n```ba"), "Run this:\n```ba\n```");
+        guard.push("sh\ncargo test\n``");
+        assert_eq!(guard.open_fence().map(|fence| fence.opener().to_string()).as_deref(), Some("```bash"));
+        // "```" with no newline yet already closes it.
+        guard.push("`");
+        assert_eq!(guard.open_fence(), None);
+        guard.push("\nDone.");
+        assert_eq!(guard.render("x"), "x");
+
+        // A shorter run, or the other marker, inside a longer fence is content.
+        let nested = MarkdownStreamGuard::from_text("````markdown\n```rust\nfn main() {}\n```\n~~~\n");
+        let fence = nested.open_fence().unwrap();
+        assert_eq!(fence.closer(), "````");
+        assert_eq!(nested.render("body\n"), "body\n````");
+        assert_eq!(MarkdownStreamGuard::from_text("````md\n```\n`````\n").open_fence(), None);
+
+        // Not fences: inline code, four-space indentation, backticks in the info string.
+        for text in ["use `x` here\n", "    ```\n", "``` a`b\n", "~~\n"] {
+            assert_eq!(MarkdownStreamGuard::from_text(text).open_fence(), None, "{text:?}");
+        }
+        assert!(MarkdownStreamGuard::from_text("   ~~~\n").open_fence().is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_edits_close_open_fences() {
+        let mut coordinator = StreamCoordinator::new(2000, Duration::from_millis(750), 5);
+        let now = Instant::now();
+
+        let responses = coordinator.push("prt_1", "Here:\n```rust\nfn main", None, now);
+        assert_eq!(chunks(&responses), ["<start>", "Here:\n```rust\nfn main\n```"]);
+        let responses = coordinator.push("prt_1", "Here:\n```rust\nfn main() {}\n```\nDone.", None, now);
+        assert_eq!(chunks(&responses), ["Here:\n```rust\nfn main() {}\n```\nDone."]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rollover_reopens_fence() {
+        let mut coordinator = StreamCoordinator::new(30, Duration::from_millis(750), 5);
+        let now = Instant::now();
+
+        let text = "```rust\nlet a = 1;\nlet b = 2;\nlet c = 3;\n```";
+        let responses = coordinator.push("prt_1", text, None, now);
+        assert_eq!(
+            chunks(&responses),
+            [
+                "<start>",
+                "```rust\nlet a = 1;\n```",
+                "<end>",
+                "<start>",
+                "```rust\nlet b = 2;\n```",
+                "<end>",
+                "<start>",
+                "```rust\nlet c = 3;\n```",
+            ]
+        );
+        assert_eq!(chunks(&coordinator.finish()), ["<end>"]);
+    }
+}
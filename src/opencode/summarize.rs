@@ -0,0 +1,188 @@
+//! `!summarize`: a summary of the channel's recent conversation, on demand.
+//!
+//! Built from the same prompt as compaction, in a throwaway session, but
+//! nothing is saved: no compaction summary is written, no turns are archived,
+//! and the reply isn't logged to the channel's history. The channel's context
+//! is exactly what it was before, so summarizing is always safe to ask for.
+
+use crate::conversation::history::{ConversationLogger, ConversationMessage};
+use crate::opencode::compaction::response_text;
+use crate::opencode::prompt::build_compaction_prompt;
+use crate::opencode::server::OpenCodeServer;
+use crate::opencode::types::{PartInput, SendPromptRequest};
+use crate::ChannelId;
+
+use anyhow::bail;
+
+/// Messages summarized when the command doesn't say how many.
+pub const DEFAULT_MESSAGES: usize = 50;
+/// Most messages one summary covers.
+pub const MAX_MESSAGES: usize = 500;
+
+/// How the summary is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SummaryStyle {
+    /// A few sentences.
+    #[default]
+    Short,
+    /// Several paragraphs, keeping the reasoning behind decisions.
+    Detailed,
+    /// A bulleted list of decisions, open questions, and action items.
+    Bullets,
+}
+
+impl SummaryStyle {
+    fn instructions(self) -> &'static str {
+        match self {
+            Self::Short => "Write it for the people in the channel, in at most four sentences.",
+            Self::Detailed => {
+                "Write it for the people in the channel, in a few paragraphs. Keep the reasoning \
+                 behind decisions and who holds which view."
+            }
+            Self::Bullets => {
+                "Write it for the people in the channel as a bulleted list, grouped under \
+                 Decisions, Open questions, and Action items (with owners). Leave out empty groups."
+            }
+        }
+    }
+}
+
+/// A `!summarize` command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SummarizeCommand {
+    pub style: SummaryStyle,
+    /// How many of the channel's most recent messages to cover.
+    pub messages: usize,
+}
+
+/// Why a `!summarize` was refused.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum SummarizeError {
+    #[error("unknown option `{0}`; use `!summarize [short|detailed|bullets] [messages]`")]
+    UnknownOption(String),
+    #[error("can summarize between 1 and {max} messages, not {0}", max = MAX_MESSAGES)]
+    MessageCount(usize),
+}
+
+impl SummarizeCommand {
+    /// Parse `!summarize [short|detailed|bullets] [messages]`, options in any
+    /// order. Returns `None` for any other message.
+    pub fn parse(text: &str) -> Option<Result<Self, SummarizeError>> {
+        let rest = text.trim().strip_prefix("!summarize")?;
+        if !rest.is_empty() && !rest.starts_with(char::is_whitespace) {
+            return None;
+        }
+        let mut command = Self {
+            style: SummaryStyle::default(),
+            messages: DEFAULT_MESSAGES,
+        };
+        for token in rest.split_whitespace() {
+            match token {
+                "short" => command.style = SummaryStyle::Short,
+                "detailed" => command.style = SummaryStyle::Detailed,
+                "bullets" => command.style = SummaryStyle::Bullets,
+                _ => match token.parse::<usize>() {
+                    Ok(count) if (1..=MAX_MESSAGES).contains(&count) => command.messages = count,
+                    Ok(count) => return Some(Err(SummarizeError::MessageCount(count))),
+                    Err(_) => return Some(Err(SummarizeError::UnknownOption(token.to_string()))),
+                },
+            }
+        }
+        Some(Ok(command))
+    }
+}
+
+/// The compaction prompt for `messages`, with the style's instructions added
+/// after compaction's own. No prior summary is folded in; the summary covers
+/// just these messages.
+pub fn build_summary_prompt(messages: &[ConversationMessage], style: SummaryStyle) -> anyhow::Result<SendPromptRequest> {
+    let mut request = build_compaction_prompt(messages, None)?;
+    if let Some(PartInput::Text { text, .. }) = request.parts.first_mut() {
+        *text = text.replacen("\n\n## Conversation\n", &format!("\n\n{}\n\n## Conversation\n", style.instructions()), 1);
+    }
+    Ok(request)
+}
+
+/// Summarize the channel's most recent messages as `command` asks. Reads
+/// history only; see the module docs. The session the summary is written in
+/// is deleted afterwards, whether or not the prompt succeeded.
+pub async fn summarize_channel(
+    server: &OpenCodeServer,
+    logger: &ConversationLogger,
+    channel_id: &ChannelId,
+    command: &SummarizeCommand,
+) -> anyhow::Result<Option<String>> {
+    let messages = logger.load_recent(channel_id, command.messages as i64, false).await?;
+    if messages.is_empty() {
+        return Ok(None);
+    }
+    let request = build_summary_prompt(&messages, command.style)?;
+
+    let session = server
+        .create_session(Some(format!("spacebot-summary-{channel_id}")))
+        .await?;
+    let response = server.send_prompt(&session.id, &request).await;
+    if let Err(error) = server.delete_session(&session.id).await {
+        tracing::warn!(%channel_id, session_id = %session.id, %error, "failed to delete summary session");
+    }
+    let summary = response_text(&response?);
+    if summary.is_empty() {
+        bail!("summary prompt returned no text");
+    }
+    Ok(Some(summary))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(content: &str) -> ConversationMessage {
+        ConversationMessage {
+            id: uuid::Uuid::new_v4().to_string(),
+            channel_id: "discord:1:2".into(),
+            role: "user".into(),
+            sender_name: Some("alice".into()),
+            sender_id: Some("1".into()),
+            content: content.into(),
+            metadata: None,
+            token_count: None,
+            is_synthetic: false,
+            is_hidden: false,
+            created_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            SummarizeCommand::parse("!summarize"),
+            Some(Ok(SummarizeCommand { style: SummaryStyle::Short, messages: DEFAULT_MESSAGES }))
+        );
+        assert_eq!(
+            SummarizeCommand::parse(" !summarize 200 bullets "),
+            Some(Ok(SummarizeCommand { style: SummaryStyle::Bullets, messages: 200 }))
+        );
+        assert_eq!(SummarizeCommand::parse("!summarize 0"), Some(Err(SummarizeError::MessageCount(0))));
+        assert_eq!(
+            SummarizeCommand::parse("!summarize poem"),
+            Some(Err(SummarizeError::UnknownOption("poem".into())))
+        );
+        assert_eq!(SummarizeCommand::parse("!summarized"), None);
+        assert_eq!(SummarizeCommand::parse("please !summarize"), None);
+    }
+
+    #[test]
+    fn test_prompt_adds_style_before_the_conversation() {
+        let request = build_summary_prompt(&[message("ship it on friday")], SummaryStyle::Bullets).unwrap();
+        let PartInput::Text { text, .. } = &request.parts[0] else {
+            panic!("expected a text part");
+        };
+        let style = text.find("bulleted list").unwrap();
+        let conversation = text.find("## Conversation").unwrap();
+        assert!(text.starts_with("Summarize the conversation below"));
+        assert!(style < conversation);
+        assert!(text.ends_with("alice (user): ship it on friday\n"));
+
+        assert!(build_summary_prompt(&[], SummaryStyle::Short).is_err());
+    }
+}
@@ -0,0 +1,129 @@
+//! Which channels have a turn in flight.
+//!
+//! A worker registers a turn in `ActiveTurns` when it sends a prompt and
+//! completes it when the session goes idle or errors. Anything that needs to
+//! know whether a channel is busy, or to stop its turn early, looks it up
+//! here instead of tracking turns itself.
+
+use crate::ChannelId;
+
+use std::collections::HashMap;
+use std::sync::RwLock;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// One in-flight turn.
+#[derive(Debug, Clone)]
+pub struct TurnHandle {
+    /// Distinguishes this turn from a later one on the same channel.
+    pub turn_id: Uuid,
+    pub session_id: String,
+    /// Cancelled to make the worker abort the turn.
+    pub cancel: CancellationToken,
+    pub started_at: Instant,
+}
+
+/// The in-flight turn of each channel, shared by every worker.
+#[derive(Debug, Default)]
+pub struct ActiveTurns {
+    turns: RwLock<HashMap<ChannelId, TurnHandle>>,
+}
+
+impl ActiveTurns {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark a turn in `session_id` active for `channel_id`, replacing any
+    /// turn already registered there.
+    pub fn register(&self, channel_id: &ChannelId, session_id: impl Into<String>) -> TurnHandle {
+        let handle = TurnHandle {
+            turn_id: Uuid::new_v4(),
+            session_id: session_id.into(),
+            cancel: CancellationToken::new(),
+            started_at: Instant::now(),
+        };
+        let replaced = self
+            .turns
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(channel_id.clone(), handle.clone());
+        if let Some(replaced) = replaced {
+            tracing::debug!(%channel_id, turn_id = %replaced.turn_id, "active turn replaced before it completed");
+        }
+        handle
+    }
+
+    /// The channel's in-flight turn, if it has one.
+    pub fn get(&self, channel_id: &ChannelId) -> Option<TurnHandle> {
+        self.read().get(channel_id).cloned()
+    }
+
+    pub fn is_active(&self, channel_id: &ChannelId) -> bool {
+        self.read().contains_key(channel_id)
+    }
+
+    /// Clear `handle`'s turn. A no-op if the channel has since registered a
+    /// newer turn, so a late completion can't clear it.
+    pub fn complete(&self, channel_id: &ChannelId, handle: &TurnHandle) {
+        let mut turns = self.turns.write().unwrap_or_else(|e| e.into_inner());
+        if turns.get(channel_id).is_some_and(|active| active.turn_id == handle.turn_id) {
+            turns.remove(channel_id);
+        }
+    }
+
+    /// Ask the channel's in-flight turn to stop. Returns whether there was
+    /// one. The turn stays registered until its worker completes it.
+    pub fn cancel(&self, channel_id: &ChannelId) -> bool {
+        match self.get(channel_id) {
+            Some(handle) => {
+                handle.cancel.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Number of channels with a turn in flight.
+    pub fn len(&self) -> usize {
+        self.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.read().is_empty()
+    }
+
+    fn read(&self) -> std::sync::RwLockReadGuard<'_, HashMap<ChannelId, TurnHandle>> {
+        self.turns.read().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_active_turns() {
+        let turns = ActiveTurns::new();
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+        assert!(!turns.is_active(&channel_id));
+        assert!(!turns.cancel(&channel_id));
+
+        let first = turns.register(&channel_id, "ses_1");
+        let second = turns.register(&channel_id, "ses_1");
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns.get(&channel_id).map(|handle| handle.turn_id), Some(second.turn_id));
+
+        // The replaced turn finishing late leaves the newer one in place.
+        turns.complete(&channel_id, &first);
+        assert!(turns.is_active(&channel_id));
+
+        assert!(turns.cancel(&channel_id));
+        assert!(second.cancel.is_cancelled());
+        assert!(!first.cancel.is_cancelled());
+
+        turns.complete(&channel_id, &second);
+        assert!(turns.is_empty());
+    }
+}
@@ -7,7 +7,7 @@
 //! The `properties` content varies per event type.
 
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 // -- Request types --
 
@@ -16,6 +16,16 @@ use std::collections::HashMap;
 pub struct CreateSessionRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub title: Option<String>,
+    /// Makes the new session a child of this one.
+    #[serde(rename = "parentID", skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+}
+
+/// Body for `PATCH /session/{id}`.
+#[derive(Debug, Serialize)]
+pub struct UpdateSessionRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
 }
 
 /// A single part within a message prompt.
@@ -35,14 +45,84 @@ pub enum PartInput {
     },
 }
 
+/// Default cap on files inlined with `PartInput::file_from_bytes`. Matches
+/// the default `attachment_max_bytes`.
+pub const DEFAULT_INLINE_FILE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+/// A file too large to inline as a `data:` URL. Host it and pass its URL in
+/// a `PartInput::File` instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error("file is {size} bytes, over the {max_bytes} byte limit for inline files")]
+pub struct InlineFileTooLarge {
+    pub size: u64,
+    pub max_bytes: u64,
+}
+
+impl PartInput {
+    /// A file part carrying `bytes` inline as a base64 `data:` URL, capped at
+    /// `DEFAULT_INLINE_FILE_MAX_BYTES`.
+    pub fn file_from_bytes(
+        bytes: &[u8],
+        mime: &str,
+        filename: Option<String>,
+    ) -> Result<Self, InlineFileTooLarge> {
+        Self::file_from_bytes_with_limit(bytes, mime, filename, DEFAULT_INLINE_FILE_MAX_BYTES)
+    }
+
+    /// Like `file_from_bytes`, refusing files over `max_bytes`.
+    pub fn file_from_bytes_with_limit(
+        bytes: &[u8],
+        mime: &str,
+        filename: Option<String>,
+        max_bytes: u64,
+    ) -> Result<Self, InlineFileTooLarge> {
+        use base64::Engine as _;
+
+        let size = bytes.len() as u64;
+        if size > max_bytes {
+            return Err(InlineFileTooLarge { size, max_bytes });
+        }
+        let data = base64::engine::general_purpose::STANDARD.encode(bytes);
+        Ok(Self::File {
+            mime: mime.to_string(),
+            url: format!("data:{mime};base64,{data}"),
+            filename,
+        })
+    }
+}
+
 /// Model selection for a prompt.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ModelParam {
+    #[serde(alias = "providerID")]
     pub provider_id: String,
+    #[serde(alias = "modelID")]
     pub model_id: String,
 }
 
+impl std::fmt::Display for ModelParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.provider_id, self.model_id)
+    }
+}
+
+impl std::str::FromStr for ModelParam {
+    type Err = String;
+
+    /// Parse `provider/model`. The model ID may itself contain slashes
+    /// (e.g. `openrouter/anthropic/claude-sonnet-4`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().split_once('/') {
+            Some((provider_id, model_id)) if !provider_id.is_empty() && !model_id.is_empty() => Ok(Self {
+                provider_id: provider_id.to_string(),
+                model_id: model_id.to_string(),
+            }),
+            _ => Err(format!("expected provider/model, got: {s}")),
+        }
+    }
+}
+
 /// Body for `POST /session/{id}/message` (send prompt).
 #[derive(Debug, Serialize)]
 #[serde(rename_all = "camelCase")]
@@ -54,6 +134,10 @@ pub struct SendPromptRequest {
     pub model: Option<ModelParam>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub agent: Option<String>,
+    /// Identifies the logical prompt across resends. Sent as the
+    /// `Idempotency-Key` header, not in the body.
+    #[serde(skip)]
+    pub idempotency_key: Option<String>,
 }
 
 /// Body for `POST /permission/{id}/reply`.
@@ -96,10 +180,59 @@ pub struct Session {
     pub id: String,
     #[serde(default)]
     pub title: Option<String>,
-    #[serde(default)]
+    /// Set on child sessions OpenCode spawns for sub-agents (the `task` tool).
+    #[serde(default, alias = "parentID")]
     pub parent_id: Option<String>,
 }
 
+/// A provider and its models, from `GET /config/providers`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderInfo {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Keyed by model ID.
+    #[serde(default)]
+    pub models: HashMap<String, ProviderModel>,
+}
+
+/// A model a provider offers.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderModel {
+    pub id: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// Response of `GET /config/providers`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProviderList {
+    #[serde(default)]
+    pub providers: Vec<ProviderInfo>,
+    /// Provider ID → its default model ID.
+    #[serde(default)]
+    pub default: HashMap<String, String>,
+}
+
+/// An agent the server can run, from `GET /agent`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AgentInfo {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    /// `"primary"`, `"subagent"`, or `"all"`.
+    #[serde(default)]
+    pub mode: Option<String>,
+}
+
+impl AgentInfo {
+    /// Whether a prompt can be sent to this agent directly. Sub-agents only
+    /// run through the `task` tool.
+    pub fn is_selectable(&self) -> bool {
+        self.mode.as_deref() != Some("subagent")
+    }
+}
+
 /// Health check response from `GET /global/health` or `GET /api/health`.
 #[derive(Debug, Deserialize)]
 pub struct HealthResponse {
@@ -110,14 +243,42 @@ pub struct HealthResponse {
 }
 
 /// Time span for message/part timing.
+///
+/// Parts report `start`/`end`; messages report `created`/`completed`.
 #[derive(Debug, Clone, Deserialize)]
 pub struct TimeSpan {
-    #[serde(default)]
+    #[serde(default, alias = "created")]
     pub start: Option<f64>,
-    #[serde(default)]
+    #[serde(default, alias = "completed")]
     pub end: Option<f64>,
 }
 
+impl TimeSpan {
+    /// Milliseconds from `start` to `end`, or `None` while it's still
+    /// running or if either bound is missing.
+    pub fn duration_ms(&self) -> Option<f64> {
+        Some(self.end? - self.start?)
+    }
+
+    /// Whether the span has ended.
+    pub fn is_complete(&self) -> bool {
+        self.end.is_some()
+    }
+}
+
+/// Render a duration for chat, e.g. "850ms", "3.2s", or "2m 5s".
+pub fn format_duration_ms(ms: f64) -> String {
+    let ms = ms.max(0.0);
+    if ms < 1000.0 {
+        format!("{}ms", ms.round())
+    } else if ms < 60_000.0 {
+        format!("{:.1}s", ms / 1000.0)
+    } else {
+        let secs = (ms / 1000.0).round() as u64;
+        format!("{}m {}s", secs / 60, secs % 60)
+    }
+}
+
 /// A message in a session.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -128,6 +289,64 @@ pub struct MessageInfo {
     pub session_id: Option<String>,
     #[serde(default)]
     pub time: Option<TimeSpan>,
+    /// Assistant messages only, once generation has finished.
+    #[serde(default)]
+    pub tokens: Option<TokenUsage>,
+    /// Provider that produced an assistant message (e.g. "anthropic").
+    #[serde(rename = "providerID", default)]
+    pub provider_id: Option<String>,
+    /// Model that produced an assistant message (e.g. "claude-sonnet-4-5").
+    #[serde(rename = "modelID", default)]
+    pub model_id: Option<String>,
+}
+
+impl MessageInfo {
+    /// `provider/model`, when OpenCode reported both.
+    pub fn model(&self) -> Option<String> {
+        Some(format!("{}/{}", self.provider_id.as_deref()?, self.model_id.as_deref()?))
+    }
+}
+
+/// A message with its parts, as listed by `GET /session/{id}/message`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionMessage {
+    pub info: MessageInfo,
+    #[serde(default)]
+    pub parts: Vec<Part>,
+}
+
+impl SessionMessage {
+    /// The message's visible text: its non-synthetic text parts, joined.
+    pub fn text(&self) -> String {
+        self.joined_text(false)
+    }
+
+    /// Text Spacebot injected (context, summaries): the synthetic text
+    /// parts, joined.
+    pub fn synthetic_text(&self) -> String {
+        self.joined_text(true)
+    }
+
+    fn joined_text(&self, synthetic: bool) -> String {
+        self.parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Text { text, synthetic: is_synthetic, .. } if *is_synthetic == synthetic => {
+                    Some(text.as_str())
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim()
+            .to_string()
+    }
+
+    /// When the message was created, from `info.time`.
+    pub fn created_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        let millis = self.info.time.as_ref()?.start?;
+        chrono::DateTime::from_timestamp_millis(millis as i64)
+    }
 }
 
 // -- SSE Event types --
@@ -137,7 +356,7 @@ pub struct MessageInfo {
 // match on `type` and parse `properties` accordingly.
 
 /// Raw SSE event envelope from OpenCode.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SseEventEnvelope {
     #[serde(rename = "type")]
     pub event_type: String,
@@ -176,11 +395,75 @@ pub enum SseEvent {
     QuestionReplied {
         session_id: String,
         request_id: String,
+        /// The chosen answers, in question order. Empty if OpenCode didn't
+        /// say.
+        answers: Vec<QuestionAnswer>,
+    },
+    /// A session was created. Child sessions carry `parent_id`.
+    SessionCreated {
+        info: Session,
+    },
+    /// OpenCode retracted a message (e.g. after an abort).
+    MessageRemoved {
+        session_id: String,
+        message_id: String,
+    },
+    /// OpenCode retracted a single part of a message.
+    PartRemoved {
+        session_id: String,
+        message_id: String,
+        part_id: String,
+    },
+    /// The server's OpenCode installation changed version (e.g. after an
+    /// update). The running server keeps its old code until restarted.
+    ServerInfo {
+        version: Option<String>,
     },
     Unknown(String),
 }
 
 impl SseEvent {
+    /// The session the event belongs to. `None` for server-wide events, and
+    /// for message events that don't say.
+    pub fn session_id(&self) -> Option<&str> {
+        match self {
+            SseEvent::MessageUpdated { info } => info.as_ref()?.session_id.as_deref(),
+            SseEvent::MessagePartUpdated { part, .. } => part.session_id(),
+            SseEvent::SessionIdle { session_id }
+            | SseEvent::SessionStatus { session_id, .. }
+            | SseEvent::PermissionReplied { session_id, .. }
+            | SseEvent::QuestionReplied { session_id, .. }
+            | SseEvent::MessageRemoved { session_id, .. }
+            | SseEvent::PartRemoved { session_id, .. } => Some(session_id),
+            SseEvent::SessionError { session_id, .. } => session_id.as_deref(),
+            SseEvent::PermissionAsked(permission) => Some(&permission.session_id),
+            SseEvent::QuestionAsked(question) => Some(&question.session_id),
+            SseEvent::SessionCreated { info } => Some(&info.id),
+            SseEvent::ServerInfo { .. } | SseEvent::Unknown(_) => None,
+        }
+    }
+
+    /// OpenCode event type this variant was parsed from. Unrecognized events
+    /// all report `"unknown"` to keep metric label cardinality bounded.
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            SseEvent::MessageUpdated { .. } => "message.updated",
+            SseEvent::MessagePartUpdated { .. } => "message.part.updated",
+            SseEvent::SessionIdle { .. } => "session.idle",
+            SseEvent::SessionError { .. } => "session.error",
+            SseEvent::SessionStatus { .. } => "session.status",
+            SseEvent::PermissionAsked(_) => "permission.asked",
+            SseEvent::PermissionReplied { .. } => "permission.replied",
+            SseEvent::QuestionAsked(_) => "question.asked",
+            SseEvent::QuestionReplied { .. } => "question.replied",
+            SseEvent::SessionCreated { .. } => "session.created",
+            SseEvent::MessageRemoved { .. } => "message.removed",
+            SseEvent::PartRemoved { .. } => "message.part.removed",
+            SseEvent::ServerInfo { .. } => "installation.updated",
+            SseEvent::Unknown(_) => "unknown",
+        }
+    }
+
     /// Parse from an envelope. Returns `Unknown` for unrecognized event types.
     pub fn from_envelope(envelope: SseEventEnvelope) -> Self {
         let props = envelope.properties;
@@ -244,9 +527,33 @@ impl SseEvent {
                 Ok(p) => SseEvent::QuestionReplied {
                     session_id: p.session_id,
                     request_id: p.request_id,
+                    answers: p.answers.into_iter().flat_map(RepliedAnswer::flatten).collect(),
                 },
                 Err(_) => SseEvent::Unknown("question.replied (parse error)".into()),
             },
+            "session.created" => match serde_json::from_value::<SessionInfoProps>(props) {
+                Ok(p) => SseEvent::SessionCreated { info: p.info },
+                Err(_) => SseEvent::Unknown("session.created (parse error)".into()),
+            },
+            "message.removed" => match serde_json::from_value::<MessageRemovedProps>(props) {
+                Ok(p) => SseEvent::MessageRemoved {
+                    session_id: p.session_id,
+                    message_id: p.message_id,
+                },
+                Err(_) => SseEvent::Unknown("message.removed (parse error)".into()),
+            },
+            "message.part.removed" => match serde_json::from_value::<PartRemovedProps>(props) {
+                Ok(p) => SseEvent::PartRemoved {
+                    session_id: p.session_id,
+                    message_id: p.message_id,
+                    part_id: p.part_id,
+                },
+                Err(_) => SseEvent::Unknown("message.part.removed (parse error)".into()),
+            },
+            "installation.updated" => {
+                let p = serde_json::from_value::<ServerInfoProps>(props).unwrap_or_default();
+                SseEvent::ServerInfo { version: p.version }
+            }
             other => SseEvent::Unknown(other.to_string()),
         }
     }
@@ -288,12 +595,14 @@ struct SessionStatusProps {
     status: SessionStatusPayload,
 }
 
+/// OpenCode before 1.1 sent `permissionID` and `response` instead.
 #[derive(Debug, Deserialize)]
 struct PermissionRepliedProps {
     #[serde(rename = "sessionID")]
     session_id: String,
-    #[serde(rename = "requestID")]
+    #[serde(rename = "requestID", alias = "permissionID")]
     request_id: String,
+    #[serde(alias = "response")]
     reply: String,
 }
 
@@ -303,6 +612,58 @@ struct QuestionRepliedProps {
     session_id: String,
     #[serde(rename = "requestID")]
     request_id: String,
+    /// Older OpenCode versions don't send answers.
+    #[serde(default)]
+    answers: Vec<RepliedAnswer>,
+}
+
+/// An answer in `question.replied`. OpenCode sends one list of labels per
+/// question; full answer objects are accepted too.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RepliedAnswer {
+    Label(String),
+    Answer(QuestionAnswer),
+    Group(Vec<RepliedAnswer>),
+}
+
+impl RepliedAnswer {
+    fn flatten(self) -> Vec<QuestionAnswer> {
+        match self {
+            Self::Label(label) => vec![QuestionAnswer { label, description: None }],
+            Self::Answer(answer) => vec![answer],
+            Self::Group(answers) => answers.into_iter().flat_map(Self::flatten).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SessionInfoProps {
+    info: Session,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageRemovedProps {
+    #[serde(rename = "sessionID")]
+    session_id: String,
+    #[serde(rename = "messageID")]
+    message_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PartRemovedProps {
+    #[serde(rename = "sessionID")]
+    session_id: String,
+    #[serde(rename = "messageID")]
+    message_id: String,
+    #[serde(rename = "partID")]
+    part_id: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ServerInfoProps {
+    #[serde(default)]
+    version: Option<String>,
 }
 
 // -- Part types --
@@ -322,6 +683,9 @@ pub enum Part {
         text: String,
         #[serde(default)]
         time: Option<TimeSpan>,
+        /// Set on text the client injected rather than the user typed.
+        #[serde(default)]
+        synthetic: bool,
     },
     #[serde(rename = "tool")]
     Tool {
@@ -337,7 +701,9 @@ pub enum Part {
         tool: Option<String>,
         /// Tool execution state. This is a tagged object with `status` as discriminant,
         /// not a simple enum. Contains `input`, `output`, `title`, `time`, etc.
-        #[serde(default)]
+        /// A state that doesn't parse becomes `ToolState::Unknown` rather than
+        /// failing the whole part.
+        #[serde(default, deserialize_with = "deserialize_tool_state")]
         state: Option<ToolState>,
     },
     #[serde(rename = "step-start")]
@@ -345,14 +711,21 @@ pub enum Part {
         id: String,
         #[serde(rename = "sessionID", default)]
         session_id: Option<String>,
+        #[serde(rename = "messageID", default)]
+        message_id: Option<String>,
     },
     #[serde(rename = "step-finish")]
     StepFinish {
         id: String,
         #[serde(rename = "sessionID", default)]
         session_id: Option<String>,
+        #[serde(rename = "messageID", default)]
+        message_id: Option<String>,
         #[serde(default)]
         reason: Option<String>,
+        /// Tokens spent on this step. Absent on older OpenCode versions.
+        #[serde(default)]
+        tokens: Option<TokenUsage>,
     },
     /// Catch-all for part types we don't process (reasoning, file, subtask, snapshot, etc.)
     #[serde(other)]
@@ -392,6 +765,8 @@ pub enum ToolState {
         title: Option<String>,
         #[serde(default)]
         metadata: Option<HashMap<String, serde_json::Value>>,
+        #[serde(default)]
+        time: Option<TimeSpan>,
     },
     #[serde(rename = "error")]
     Error {
@@ -399,10 +774,44 @@ pub enum ToolState {
         input: Option<serde_json::Value>,
         #[serde(default)]
         error: Option<String>,
+        #[serde(default)]
+        time: Option<TimeSpan>,
     },
+    /// A status we don't model yet, or a known status whose fields have an
+    /// unexpected shape. `raw` is the whole state object. Only produced by
+    /// `ToolState::from_value`.
+    #[serde(skip_deserializing)]
+    Unknown {
+        status: String,
+        raw: serde_json::Value,
+    },
+}
+
+/// `Part::Tool::state`, parsed with `ToolState::from_value`.
+fn deserialize_tool_state<'de, D>(deserializer: D) -> Result<Option<ToolState>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<serde_json::Value>::deserialize(deserializer)?.map(ToolState::from_value))
 }
 
 impl ToolState {
+    /// Parse a state object, falling back to `Unknown` instead of failing.
+    pub fn from_value(raw: serde_json::Value) -> Self {
+        match serde_json::from_value::<ToolState>(raw.clone()) {
+            Ok(state) => state,
+            Err(error) => {
+                let status = raw
+                    .get("status")
+                    .and_then(|status| status.as_str())
+                    .unwrap_or_default()
+                    .to_string();
+                tracing::debug!(%error, %status, "unrecognized tool state");
+                ToolState::Unknown { status, raw }
+            }
+        }
+    }
+
     /// Check if this is a running state.
     pub fn is_running(&self) -> bool {
         matches!(self, ToolState::Running { .. })
@@ -418,13 +827,359 @@ impl ToolState {
         matches!(self, ToolState::Error { .. })
     }
 
-    /// Get a display-friendly status string.
-    pub fn status_str(&self) -> &'static str {
+    /// Get a display-friendly status string. For `Unknown`, the status as
+    /// OpenCode sent it.
+    pub fn status_str(&self) -> &str {
         match self {
             ToolState::Pending { .. } => "pending",
             ToolState::Running { .. } => "running",
             ToolState::Completed { .. } => "completed",
             ToolState::Error { .. } => "error",
+            ToolState::Unknown { status, .. } => status,
+        }
+    }
+
+    /// Raw tool input, present in every state.
+    pub fn input(&self) -> Option<&serde_json::Value> {
+        match self {
+            ToolState::Pending { input }
+            | ToolState::Running { input, .. }
+            | ToolState::Completed { input, .. }
+            | ToolState::Error { input, .. } => input.as_ref(),
+            ToolState::Unknown { raw, .. } => raw.get("input"),
+        }
+    }
+
+    /// Title OpenCode gave a running or completed call (e.g. the file read).
+    pub fn title(&self) -> Option<&str> {
+        match self {
+            ToolState::Running { title, .. } | ToolState::Completed { title, .. } => title.as_deref(),
+            ToolState::Unknown { raw, .. } => raw.get("title").and_then(|title| title.as_str()),
+            ToolState::Pending { .. } | ToolState::Error { .. } => None,
+        }
+    }
+
+    /// Output of a completed call, or the error message of a failed one.
+    /// For `Unknown`, a string `output` field if there is one.
+    pub fn output(&self) -> Option<&str> {
+        match self {
+            ToolState::Completed { output, .. } => output.as_deref(),
+            ToolState::Error { error, .. } => error.as_deref(),
+            ToolState::Unknown { raw, .. } => raw.get("output").and_then(|output| output.as_str()),
+            ToolState::Pending { .. } | ToolState::Running { .. } => None,
+        }
+    }
+
+    /// Output trimmed to roughly `max_chars` for display in chat.
+    ///
+    /// Long output keeps whole lines from its head and tail around a
+    /// "… N lines omitted …" marker. A code fence cut open by the omission is
+    /// closed before the marker and reopened after it, so the preview still
+    /// renders as Markdown. The full output stays in `tool_invocations`; see
+    /// `ConversationLogger::load_tool_output`.
+    pub fn output_preview(&self, max_chars: usize) -> String {
+        preview_text(self.output().unwrap_or_default(), max_chars)
+    }
+
+    /// When the call ran, for states that report it.
+    pub fn time(&self) -> Option<&TimeSpan> {
+        match self {
+            ToolState::Completed { time, .. } | ToolState::Error { time, .. } => time.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// How long a finished call took, in milliseconds. `None` while it's
+    /// pending or running, or if OpenCode didn't report timing.
+    pub fn duration_ms(&self) -> Option<f64> {
+        match self {
+            ToolState::Completed { .. } | ToolState::Error { .. } => self.time()?.duration_ms(),
+            _ => None,
+        }
+    }
+
+    /// Command string of a `bash` tool call, from `input["command"]`.
+    ///
+    /// Returns `None` if the input is missing or `command` isn't a string.
+    pub fn bash_command(&self) -> Option<&str> {
+        self.input()?.get("command")?.as_str()
+    }
+
+    /// Exit code of a completed `bash` tool call.
+    ///
+    /// OpenCode reports it as `metadata["exit"]`; `exitCode` is accepted as a
+    /// fallback. Returns `None` until the call completes, or if neither field
+    /// holds an integer (e.g. the process was killed).
+    pub fn bash_exit_code(&self) -> Option<i64> {
+        let ToolState::Completed { metadata, .. } = self else {
+            return None;
+        };
+        let metadata = metadata.as_ref()?;
+        metadata
+            .get("exit")
+            .or_else(|| metadata.get("exitCode"))
+            .and_then(|v| v.as_i64())
+    }
+
+    /// Parse the diff OpenCode attaches to a completed `edit` tool call.
+    ///
+    /// The state doesn't know which tool produced it, so this only checks
+    /// that the metadata looks like an edit result. Prefer `Part::edit_diff`,
+    /// which also checks the tool name. Returns `None` if the tool hasn't
+    /// completed or the metadata carries no diff.
+    pub fn edit_diff(&self) -> Option<EditToolMetadata> {
+        let ToolState::Completed { input, metadata, .. } = self else {
+            return None;
+        };
+        let metadata = metadata.as_ref()?;
+        let diff = metadata.get("diff")?.as_str()?.to_string();
+
+        // `filediff` carries the counts when OpenCode computed them. Fall back
+        // to counting diff lines ourselves.
+        let filediff = metadata.get("filediff");
+        let count = |key: &str| filediff.and_then(|f| f.get(key)).and_then(|v| v.as_u64());
+        let (counted_additions, counted_removals) = count_diff_lines(&diff);
+
+        let filename = filediff
+            .and_then(|f| f.get("file"))
+            .and_then(|v| v.as_str())
+            .or_else(|| {
+                input
+                    .as_ref()
+                    .and_then(|i| i.get("filePath"))
+                    .and_then(|v| v.as_str())
+            })
+            .map(|s| s.to_string());
+
+        Some(EditToolMetadata {
+            filename,
+            additions: count("additions").unwrap_or(counted_additions),
+            removals: count("deletions").unwrap_or(counted_removals),
+            diff,
+        })
+    }
+}
+
+impl Part {
+    /// The session the part belongs to, if OpenCode said.
+    pub fn session_id(&self) -> Option<&str> {
+        match self {
+            Part::Text { session_id, .. }
+            | Part::Tool { session_id, .. }
+            | Part::StepStart { session_id, .. }
+            | Part::StepFinish { session_id, .. } => session_id.as_deref(),
+            Part::Other => None,
+        }
+    }
+
+    /// The part's ID. `None` for `Other`.
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Part::Text { id, .. } | Part::Tool { id, .. } | Part::StepStart { id, .. } | Part::StepFinish { id, .. } => {
+                Some(id)
+            }
+            Part::Other => None,
+        }
+    }
+
+    /// The message the part belongs to, if OpenCode said.
+    pub fn message_id(&self) -> Option<&str> {
+        match self {
+            Part::Text { message_id, .. }
+            | Part::Tool { message_id, .. }
+            | Part::StepStart { message_id, .. }
+            | Part::StepFinish { message_id, .. } => message_id.as_deref(),
+            Part::Other => None,
+        }
+    }
+
+    /// Typed diff for a completed `edit` tool part. `None` for anything else.
+    pub fn edit_diff(&self) -> Option<EditToolMetadata> {
+        match self {
+            Part::Tool { tool, state: Some(state), .. } if tool.as_deref() == Some("edit") => {
+                state.edit_diff()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Diff summary for a completed `edit` tool call.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditToolMetadata {
+    /// Path of the edited file, if OpenCode reported it.
+    pub filename: Option<String>,
+    pub additions: u64,
+    pub removals: u64,
+    /// Unified diff text.
+    pub diff: String,
+}
+
+fn preview_text(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_string();
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let budget = max_chars / 2;
+    let width = |line: &str| line.chars().count() + 1;
+
+    let mut head = 0;
+    let mut used = 0;
+    while head < lines.len() && used + width(lines[head]) <= budget {
+        used += width(lines[head]);
+        head += 1;
+    }
+    let mut tail = lines.len();
+    used = 0;
+    while tail > head && used + width(lines[tail - 1]) <= budget {
+        used += width(lines[tail - 1]);
+        tail -= 1;
+    }
+
+    if head == 0 && tail == lines.len() {
+        // Not even one line fits (e.g. a single huge line): cut by characters.
+        let total = text.chars().count();
+        let cut: String = text.chars().take(budget).collect();
+        let mut preview = cut.clone();
+        if open_fence(cut.lines()).is_some() {
+            preview.push_str("\n```");
+        }
+        preview.push_str(&format!("\n… {} characters omitted …", total - budget));
+        return preview;
+    }
+
+    let mut preview: Vec<String> = lines[..head].iter().map(|line| line.to_string()).collect();
+    if open_fence(lines[..head].iter().copied()).is_some() {
+        preview.push("```".to_string());
+    }
+    preview.push(format!("… {} lines omitted …", tail - head));
+    if let Some(fence) = open_fence(lines[..tail].iter().copied()) {
+        preview.push(fence.to_string());
+    }
+    preview.extend(lines[tail..].iter().map(|line| line.to_string()));
+    preview.join("\n")
+}
+
+/// The opening line of the code fence still open after `lines`, if any.
+fn open_fence<'a>(lines: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    let mut open = None;
+    for line in lines {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            open = match open {
+                Some(_) => None,
+                None => Some(trimmed),
+            };
+        }
+    }
+    open
+}
+
+/// Count added and removed lines in a unified diff, skipping file headers.
+fn count_diff_lines(diff: &str) -> (u64, u64) {
+    let mut additions = 0;
+    let mut removals = 0;
+    for line in diff.lines() {
+        if line.starts_with("+++") || line.starts_with("---") {
+            continue;
+        }
+        if line.starts_with('+') {
+            additions += 1;
+        } else if line.starts_with('-') {
+            removals += 1;
+        }
+    }
+    (additions, removals)
+}
+
+/// Token counts for one step, or summed over a turn.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    #[serde(default)]
+    pub input: u64,
+    #[serde(default)]
+    pub output: u64,
+    #[serde(default)]
+    pub reasoning: u64,
+    #[serde(default)]
+    pub cache: CacheTokenUsage,
+}
+
+/// Prompt-cache token counts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CacheTokenUsage {
+    #[serde(default)]
+    pub read: u64,
+    #[serde(default)]
+    pub write: u64,
+}
+
+impl std::ops::AddAssign for TokenUsage {
+    fn add_assign(&mut self, other: Self) {
+        self.input += other.input;
+        self.output += other.output;
+        self.reasoning += other.reasoning;
+        self.cache.read += other.cache.read;
+        self.cache.write += other.cache.write;
+    }
+}
+
+/// Why the model stopped generating, from the last `step-finish` part.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FinishReason {
+    /// Finished normally.
+    Stop,
+    /// Hit the output token limit; the reply is truncated.
+    Length,
+    /// Stopped to call tools and never resumed.
+    ToolCalls,
+    /// Cut off by the provider's content filter.
+    ContentFilter,
+    Other(String),
+}
+
+impl From<&str> for FinishReason {
+    fn from(reason: &str) -> Self {
+        match reason {
+            "stop" => Self::Stop,
+            "length" => Self::Length,
+            "tool-calls" | "tool_calls" => Self::ToolCalls,
+            "content-filter" | "content_filter" => Self::ContentFilter,
+            other => Self::Other(other.to_string()),
+        }
+    }
+}
+
+/// Summary of a finished turn, produced when the session goes idle.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TurnOutcome {
+    /// The final assistant text.
+    pub text: String,
+    /// `None` if OpenCode never reported a finished step.
+    pub finish_reason: Option<FinishReason>,
+    /// Whether any tool call in the turn (including sub-agents') errored.
+    pub tool_errored: bool,
+    /// Tokens summed over every step, if OpenCode reported any.
+    pub tokens: Option<TokenUsage>,
+    /// `provider/model` that produced the reply, if OpenCode reported it.
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Milliseconds from the first assistant message starting to the last
+    /// one finishing, if OpenCode reported timing.
+    #[serde(default)]
+    pub duration_ms: Option<u64>,
+}
+
+impl TurnOutcome {
+    /// A short warning for the channel when the turn didn't finish cleanly.
+    pub fn warning(&self) -> Option<&'static str> {
+        match self.finish_reason {
+            Some(FinishReason::Length) => Some("⚠️ Response was truncated: the model hit its output limit."),
+            Some(FinishReason::ToolCalls) => Some("⚠️ Response stopped while waiting on a tool call."),
+            Some(FinishReason::ContentFilter) => Some("⚠️ Response was cut off by the provider's content filter."),
+            _ => None,
         }
     }
 }
@@ -443,6 +1198,102 @@ pub enum SessionStatusPayload {
     },
 }
 
+/// What went wrong in a `session.error` event, as far as the bot needs to know.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionErrorKind {
+    /// The provider is throttling requests (HTTP 429 or equivalent).
+    RateLimited,
+    /// Missing or rejected provider credentials.
+    Auth,
+    /// The provider is down or overloaded (5xx, 529, connection failures).
+    ProviderUnavailable,
+    /// The prompt plus history no longer fits the model's context window.
+    ContextLengthExceeded,
+    Unknown,
+}
+
+impl std::fmt::Display for SessionErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::RateLimited => write!(f, "rate_limited"),
+            Self::Auth => write!(f, "auth"),
+            Self::ProviderUnavailable => write!(f, "provider_unavailable"),
+            Self::ContextLengthExceeded => write!(f, "context_length_exceeded"),
+            Self::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Classify a `session.error` payload.
+///
+/// OpenCode wraps errors as `{ "name": "APIError", "data": { "message", "statusCode", ... } }`;
+/// provider errors passed through verbatim may instead carry `type`/`code`
+/// fields (e.g. `context_length_exceeded`). The error name is checked first,
+/// then the status code, then well-known phrases in the message.
+pub fn classify_session_error(value: &serde_json::Value) -> SessionErrorKind {
+    let data = value.get("data").unwrap_or(value);
+    let name = value.get("name").and_then(|v| v.as_str()).unwrap_or_default();
+
+    match name {
+        "ProviderAuthError" => return SessionErrorKind::Auth,
+        "ContextOverflowError" => return SessionErrorKind::ContextLengthExceeded,
+        _ => {}
+    }
+
+    let text = [
+        session_error_message(value),
+        data.get("type").and_then(|v| v.as_str()),
+        data.get("code").and_then(|v| v.as_str()),
+        data.get("responseBody").and_then(|v| v.as_str()),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ")
+    .to_lowercase();
+
+    // Context overflows come back as 400s, so check the text before the status.
+    const CONTEXT_PHRASES: &[&str] = &[
+        "context_length_exceeded",
+        "context length",
+        "context window",
+        "prompt is too long",
+        "too many tokens",
+        "maximum context",
+    ];
+    if CONTEXT_PHRASES.iter().any(|phrase| text.contains(phrase)) {
+        return SessionErrorKind::ContextLengthExceeded;
+    }
+
+    match data.get("statusCode").and_then(|v| v.as_u64()) {
+        Some(429) => return SessionErrorKind::RateLimited,
+        Some(401 | 403) => return SessionErrorKind::Auth,
+        Some(500..=599) => return SessionErrorKind::ProviderUnavailable,
+        _ => {}
+    }
+
+    if text.contains("rate limit") || text.contains("rate_limit") || text.contains("too many requests") {
+        SessionErrorKind::RateLimited
+    } else if text.contains("api key") || text.contains("unauthorized") || text.contains("authentication") {
+        SessionErrorKind::Auth
+    } else if text.contains("overloaded") || text.contains("unavailable") || text.contains("econnrefused") {
+        SessionErrorKind::ProviderUnavailable
+    } else {
+        SessionErrorKind::Unknown
+    }
+}
+
+/// Human-readable message from a `session.error` payload, from either
+/// `data.message` (OpenCode's wrapper) or a top-level `message`.
+pub fn session_error_message(value: &serde_json::Value) -> Option<&str> {
+    value
+        .get("data")
+        .and_then(|data| data.get("message"))
+        .or_else(|| value.get("message"))
+        .and_then(|v| v.as_str())
+}
+
 /// Permission request from OpenCode.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -454,10 +1305,36 @@ pub struct PermissionRequest {
     pub permission: Option<String>,
     #[serde(default)]
     pub patterns: Vec<String>,
+    /// Patterns an `Always` reply approves from then on (e.g. `rm *` for
+    /// `rm -rf target`).
+    #[serde(default)]
+    pub always: Vec<String>,
     #[serde(default)]
     pub metadata: HashMap<String, serde_json::Value>,
 }
 
+/// A permission request as shown to the channel in a grouped prompt.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionPrompt {
+    pub permission_id: String,
+    pub description: String,
+    pub patterns: Vec<String>,
+}
+
+impl From<&PermissionRequest> for PermissionPrompt {
+    fn from(request: &PermissionRequest) -> Self {
+        Self {
+            permission_id: request.id.clone(),
+            description: format!(
+                "{}: {}",
+                request.permission.as_deref().unwrap_or("unknown"),
+                request.patterns.join(", ")
+            ),
+            patterns: request.patterns.clone(),
+        }
+    }
+}
+
 /// Question request from OpenCode.
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -498,6 +1375,61 @@ pub struct OpenCodeEnvConfig {
     pub lsp: bool,
     pub formatter: bool,
     pub permission: OpenCodePermissions,
+    /// Provider ID (e.g. "anthropic") → credentials OpenCode uses for it.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub provider: HashMap<String, OpenCodeProvider>,
+}
+
+/// Credentials for one model provider, so OpenCode can reach it without
+/// being configured out of band. Serialized as OpenCode's
+/// `provider.<id>.options`. `Debug` redacts the API key.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct OpenCodeProvider {
+    pub api_key: Option<String>,
+    /// Overrides the provider's API endpoint (e.g. a proxy).
+    pub base_url: Option<String>,
+}
+
+impl std::fmt::Debug for OpenCodeProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OpenCodeProvider")
+            .field("api_key", &self.api_key.as_ref().map(|_| "[redacted]"))
+            .field("base_url", &self.base_url)
+            .finish()
+    }
+}
+
+impl Serialize for OpenCodeProvider {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Provider<'a> {
+            options: Options<'a>,
+        }
+
+        #[derive(Serialize)]
+        struct Options<'a> {
+            #[serde(rename = "apiKey", skip_serializing_if = "Option::is_none")]
+            api_key: Option<&'a str>,
+            #[serde(rename = "baseURL", skip_serializing_if = "Option::is_none")]
+            base_url: Option<&'a str>,
+        }
+
+        Provider {
+            options: Options {
+                api_key: self.api_key.as_deref(),
+                base_url: self.base_url.as_deref(),
+            },
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Reject providers with a blank ID, which OpenCode would never match.
+pub fn validate_providers(providers: &HashMap<String, OpenCodeProvider>) -> anyhow::Result<()> {
+    if providers.keys().any(|id| id.trim().is_empty()) {
+        anyhow::bail!("OpenCode provider IDs must not be empty");
+    }
+    Ok(())
 }
 
 /// Permission settings for headless OpenCode operation.
@@ -507,30 +1439,457 @@ pub struct OpenCodePermissions {
     pub bash: String,
     #[serde(default = "default_webfetch_permission")]
     pub webfetch: String,
+    /// Any other OpenCode permission by name (e.g. "read", "task"),
+    /// serialized next to the keys above.
+    #[serde(flatten, default)]
+    pub tools: BTreeMap<String, String>,
+}
+
+/// What an interactive worker does when a follow-up arrives mid-turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FollowUpMode {
+    /// Let the running prompt finish, then send the follow-up (default).
+    #[default]
+    Queue,
+    /// Abort the running prompt and send the follow-up immediately.
+    Abort,
+}
+
+impl std::fmt::Display for FollowUpMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Queue => write!(f, "queue"),
+            Self::Abort => write!(f, "abort"),
+        }
+    }
+}
+
+impl std::str::FromStr for FollowUpMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "queue" => Ok(Self::Queue),
+            "abort" => Ok(Self::Abort),
+            _ => Err(format!("unknown follow-up mode: {}", s)),
+        }
+    }
+}
+
+/// How workers answer OpenCode permission prompts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PermissionMode {
+    /// Approve every request once (default). Relies on the configured
+    /// permissions to keep prompts rare.
+    #[default]
+    Auto,
+    /// Post requests to the channel, batched, and wait for a reply.
+    /// Only interactive workers can ask; others fall back to `Auto`.
+    Ask,
+}
+
+impl std::fmt::Display for PermissionMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::Ask => write!(f, "ask"),
+        }
+    }
+}
+
+impl std::str::FromStr for PermissionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "ask" => Ok(Self::Ask),
+            _ => Err(format!("unknown permission mode: {}", s)),
+        }
+    }
 }
 
 fn default_webfetch_permission() -> String {
     "allow".to_string()
 }
 
+/// Values OpenCode accepts for a permission setting.
+const PERMISSION_VALUES: [&str; 3] = ["allow", "ask", "deny"];
+
+impl OpenCodePermissions {
+    /// Build a validated permission set.
+    pub fn new(
+        edit: impl Into<String>,
+        bash: impl Into<String>,
+        webfetch: impl Into<String>,
+    ) -> anyhow::Result<Self> {
+        let permissions = Self {
+            edit: edit.into(),
+            bash: bash.into(),
+            webfetch: webfetch.into(),
+            tools: BTreeMap::new(),
+        };
+        permissions.validate()?;
+        Ok(permissions)
+    }
+
+    /// Add per-tool entries, replacing any already set for the same tool.
+    pub fn with_tools(mut self, tools: impl IntoIterator<Item = (String, String)>) -> Self {
+        self.tools.extend(tools);
+        self
+    }
+
+    /// Reject anything other than `allow`, `ask`, or `deny`, and per-tool
+    /// entries that are blank or repeat `edit`, `bash`, or `webfetch`.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let fixed = [("edit", &self.edit), ("bash", &self.bash), ("webfetch", &self.webfetch)];
+        for name in self.tools.keys() {
+            if name.trim().is_empty() {
+                anyhow::bail!("OpenCode tool permission names must not be empty");
+            }
+            if fixed.iter().any(|(fixed, _)| fixed == name) {
+                anyhow::bail!("set the OpenCode {name} permission directly, not under tools");
+            }
+        }
+        let tools = self.tools.iter().map(|(name, value)| (name.as_str(), value));
+        for (name, value) in fixed.into_iter().chain(tools) {
+            if !PERMISSION_VALUES.contains(&value.as_str()) {
+                anyhow::bail!(
+                    "invalid OpenCode {name} permission '{value}', expected one of: {}",
+                    PERMISSION_VALUES.join(", ")
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Permission sets selected per channel.
+///
+/// Channels listed in `channels` use the named profile; everything else gets
+/// `default`. Each profile runs on its own OpenCode server, since permissions
+/// are fixed when the server process starts.
+#[derive(Debug, Clone, Default)]
+pub struct PermissionProfiles {
+    pub default: OpenCodePermissions,
+    /// Profile name → permissions, e.g. "readonly".
+    pub profiles: HashMap<String, OpenCodePermissions>,
+    /// Channel ID → profile name.
+    pub channels: HashMap<String, String>,
+}
+
+impl PermissionProfiles {
+    /// Check every profile's values and that channels only name known profiles.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        self.default.validate()?;
+        for (name, permissions) in &self.profiles {
+            permissions
+                .validate()
+                .map_err(|error| anyhow::anyhow!("permission profile '{name}': {error}"))?;
+        }
+        for (channel_id, profile) in &self.channels {
+            if !self.profiles.contains_key(profile) {
+                anyhow::bail!("channel '{channel_id}' uses unknown permission profile '{profile}'");
+            }
+        }
+        Ok(())
+    }
+
+    /// The profile name (None for the default) and permissions for a channel.
+    pub fn resolve(&self, channel_id: Option<&str>) -> (Option<&str>, &OpenCodePermissions) {
+        channel_id
+            .and_then(|id| self.channels.get(id))
+            .and_then(|name| {
+                self.profiles
+                    .get_key_value(name)
+                    .map(|(name, permissions)| (Some(name.as_str()), permissions))
+            })
+            .unwrap_or((None, &self.default))
+    }
+}
+
 impl Default for OpenCodePermissions {
     fn default() -> Self {
         Self {
             edit: "allow".to_string(),
             bash: "allow".to_string(),
             webfetch: "allow".to_string(),
+            tools: BTreeMap::new(),
         }
     }
 }
 
 impl OpenCodeEnvConfig {
     /// Build the config JSON that gets passed as `OPENCODE_CONFIG_CONTENT`.
-    pub fn new(permissions: &OpenCodePermissions) -> Self {
-        Self {
+    ///
+    /// Takes the permissions already resolved for the channel's profile and
+    /// the providers to configure, and rejects values OpenCode wouldn't
+    /// understand.
+    pub fn new(
+        permissions: &OpenCodePermissions,
+        providers: &HashMap<String, OpenCodeProvider>,
+    ) -> anyhow::Result<Self> {
+        permissions.validate()?;
+        validate_providers(providers)?;
+        Ok(Self {
             schema: "https://opencode.ai/config.json".to_string(),
             lsp: false,
             formatter: false,
             permission: permissions.clone(),
-        }
+            provider: providers.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::Engine as _;
+
+    #[test]
+    fn test_file_from_bytes_inlines_data_url() {
+        // PNG signature plus the start of an IHDR chunk.
+        let png: &[u8] = &[
+            0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, b'I', b'H', b'D', b'R',
+        ];
+        let part = PartInput::file_from_bytes(png, "image/png", Some("dot.png".into())).unwrap();
+        let PartInput::File { mime, url, filename } = part else {
+            panic!("expected a file part");
+        };
+        assert_eq!(mime, "image/png");
+        assert_eq!(filename.as_deref(), Some("dot.png"));
+        let data = url.strip_prefix("data:image/png;base64,").expect("data URL");
+        assert_eq!(base64::engine::general_purpose::STANDARD.decode(data).unwrap(), png);
+
+        assert_eq!(
+            PartInput::file_from_bytes_with_limit(png, "image/png", None, 8).unwrap_err(),
+            InlineFileTooLarge { size: 16, max_bytes: 8 }
+        );
+    }
+
+    #[test]
+    fn test_permission_profiles_resolve_and_validate() {
+        let readonly = OpenCodePermissions::new("deny", "deny", "allow").unwrap();
+        let mut profiles = PermissionProfiles {
+            profiles: HashMap::from([("readonly".to_string(), readonly)]),
+            channels: HashMap::from([("discord:1:2".to_string(), "readonly".to_string())]),
+            ..Default::default()
+        };
+        profiles.validate().unwrap();
+
+        let (name, permissions) = profiles.resolve(Some("discord:1:2"));
+        assert_eq!(name, Some("readonly"));
+        assert_eq!(permissions.edit, "deny");
+        let (name, permissions) = profiles.resolve(Some("discord:1:3"));
+        assert_eq!(name, None);
+        assert_eq!(permissions.edit, "allow");
+
+        profiles.channels.insert("slack:T1:C1".into(), "missing".into());
+        assert!(profiles.validate().is_err());
+    }
+
+    #[test]
+    fn test_time_span_duration() {
+        let span: TimeSpan = serde_json::from_value(serde_json::json!({ "created": 1000.0, "completed": 4200.0 })).unwrap();
+        assert!(span.is_complete());
+        assert_eq!(span.duration_ms(), Some(3200.0));
+        assert_eq!(format_duration_ms(span.duration_ms().unwrap()), "3.2s");
+
+        let running = TimeSpan { start: Some(1000.0), end: None };
+        assert!(!running.is_complete());
+        assert_eq!(running.duration_ms(), None);
+
+        assert_eq!(format_duration_ms(850.0), "850ms");
+        assert_eq!(format_duration_ms(125_000.0), "2m 5s");
+    }
+
+    #[test]
+    fn test_unknown_permission_value_is_rejected() {
+        assert!(OpenCodePermissions::new("allow", "sometimes", "allow").is_err());
+
+        let permissions = OpenCodePermissions {
+            edit: "Allow".into(),
+            ..Default::default()
+        };
+        assert!(OpenCodeEnvConfig::new(&permissions, &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_tool_permissions() {
+        let global = OpenCodePermissions::default()
+            .with_tools([("read".to_string(), "allow".to_string()), ("task".to_string(), "ask".to_string())]);
+        // A profile's entry for a tool replaces the global one.
+        let profile = global.clone().with_tools([("task".to_string(), "deny".to_string())]);
+        let config = OpenCodeEnvConfig::new(&profile, &HashMap::new()).unwrap();
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(
+            json["permission"],
+            serde_json::json!({ "edit": "allow", "bash": "allow", "webfetch": "allow", "read": "allow", "task": "deny" })
+        );
+
+        let invalid = global.clone().with_tools([("read".to_string(), "never".to_string())]);
+        assert!(invalid.validate().is_err());
+        let duplicate = global.clone().with_tools([("bash".to_string(), "deny".to_string())]);
+        assert!(duplicate.validate().is_err());
+        let blank = global.with_tools([(" ".to_string(), "deny".to_string())]);
+        assert!(blank.validate().is_err());
+    }
+
+    #[test]
+    fn test_env_config_providers() {
+        let providers = HashMap::from([(
+            "anthropic".to_string(),
+            OpenCodeProvider { api_key: Some("sk-ant-secret".into()), base_url: None },
+        )]);
+        let config = OpenCodeEnvConfig::new(&OpenCodePermissions::default(), &providers).unwrap();
+
+        let json = serde_json::to_value(&config).unwrap();
+        assert_eq!(json["provider"]["anthropic"], serde_json::json!({ "options": { "apiKey": "sk-ant-secret" } }));
+        assert!(!format!("{config:?}").contains("sk-ant-secret"));
+
+        let unconfigured = OpenCodeEnvConfig::new(&OpenCodePermissions::default(), &HashMap::new()).unwrap();
+        assert!(serde_json::to_value(&unconfigured).unwrap().get("provider").is_none());
+
+        let blank = HashMap::from([(" ".to_string(), OpenCodeProvider::default())]);
+        assert!(OpenCodeEnvConfig::new(&OpenCodePermissions::default(), &blank).is_err());
+    }
+
+    #[test]
+    fn test_classify_session_error() {
+        let classify = |value: serde_json::Value| classify_session_error(&value);
+
+        assert_eq!(
+            classify(serde_json::json!({ "name": "ProviderAuthError", "data": { "providerID": "anthropic" } })),
+            SessionErrorKind::Auth
+        );
+        assert_eq!(
+            classify(serde_json::json!({ "name": "APIError", "data": { "message": "Too Many Requests", "statusCode": 429 } })),
+            SessionErrorKind::RateLimited
+        );
+        assert_eq!(
+            classify(serde_json::json!({ "name": "APIError", "data": { "message": "Overloaded", "statusCode": 529 } })),
+            SessionErrorKind::ProviderUnavailable
+        );
+        assert_eq!(
+            classify(serde_json::json!({
+                "name": "APIError",
+                "data": { "message": "prompt is too long: 210000 tokens > 200000 maximum", "statusCode": 400 }
+            })),
+            SessionErrorKind::ContextLengthExceeded
+        );
+        assert_eq!(
+            classify(serde_json::json!({ "code": "context_length_exceeded", "message": "This model's maximum context length is 128000 tokens" })),
+            SessionErrorKind::ContextLengthExceeded
+        );
+        assert_eq!(
+            classify(serde_json::json!({ "name": "UnknownError", "data": { "message": "something broke" } })),
+            SessionErrorKind::Unknown
+        );
+    }
+
+    #[test]
+    fn test_step_finish_parses_reason_and_tokens() {
+        let part: Part = serde_json::from_value(serde_json::json!({
+            "type": "step-finish",
+            "id": "prt_1",
+            "sessionID": "ses_1",
+            "reason": "length",
+            "cost": 0.01,
+            "tokens": { "input": 1200, "output": 4096, "reasoning": 0, "cache": { "read": 800, "write": 0 } }
+        }))
+        .unwrap();
+
+        let Part::StepFinish { reason, tokens, .. } = part else {
+            panic!("expected step-finish part");
+        };
+        assert_eq!(reason.as_deref().map(FinishReason::from), Some(FinishReason::Length));
+        let tokens = tokens.unwrap();
+        assert_eq!(tokens.output, 4096);
+        assert_eq!(tokens.cache.read, 800);
+    }
+
+    #[test]
+    fn test_question_replied_answers() {
+        let replied = |properties: serde_json::Value| {
+            let event = SseEvent::from_envelope(SseEventEnvelope { event_type: "question.replied".into(), properties });
+            let SseEvent::QuestionReplied { answers, .. } = event else {
+                panic!("expected QuestionReplied, got {event:?}");
+            };
+            answers.into_iter().map(|answer| answer.label).collect::<Vec<_>>()
+        };
+
+        let labels = replied(serde_json::json!({
+            "sessionID": "ses_1",
+            "requestID": "que_1",
+            "answers": [["pnpm"], [{ "label": "yes", "description": "Run it" }, "later"]]
+        }));
+        assert_eq!(labels, ["pnpm", "yes", "later"]);
+
+        // Older servers leave the answers out.
+        assert!(replied(serde_json::json!({ "sessionID": "ses_1", "requestID": "que_1" })).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_tool_state_keeps_the_part() {
+        let part: Part = serde_json::from_value(serde_json::json!({
+            "id": "prt_1",
+            "sessionID": "ses_1",
+            "callID": "call_1",
+            "type": "tool",
+            "tool": "bash",
+            "state": { "status": "paused", "input": { "command": "cargo test" }, "reason": "waiting" }
+        }))
+        .unwrap();
+        let Part::Tool { call_id, state: Some(state), .. } = part else {
+            panic!("expected Part::Tool with a state");
+        };
+        assert_eq!(call_id.as_deref(), Some("call_1"));
+        assert!(matches!(state, ToolState::Unknown { ref status, .. } if status == "paused"));
+        assert_eq!(state.status_str(), "paused");
+        assert_eq!(state.bash_command(), Some("cargo test"));
+
+        // A known status with a field of the wrong type is kept too.
+        let state = ToolState::from_value(serde_json::json!({ "status": "completed", "output": 42 }));
+        assert_eq!(state.status_str(), "completed");
+        assert!(!state.is_completed());
+        assert_eq!(state.output(), None);
+    }
+
+    #[test]
+    fn test_output_preview() {
+        let completed = |output: String| ToolState::Completed {
+            input: None,
+            output: Some(output),
+            title: None,
+            metadata: None,
+            time: None,
+        };
+
+        assert_eq!(completed("short".into()).output_preview(100), "short");
+        assert_eq!(ToolState::Pending { input: None }.output_preview(100), "");
+
+        let numbered = (1..=100).map(|n| format!("line {n}")).collect::<Vec<_>>().join("\n");
+        let preview = completed(numbered).output_preview(60);
+        assert!(preview.starts_with("line 1\n"));
+        assert!(preview.ends_with("line 100"));
+        assert!(preview.contains("… 93 lines omitted …"));
+
+        // A fence cut open by the omission is closed and reopened.
+        let fenced = format!(
+            "```rust\n{}\n```",
+            (1..=50).map(|n| format!("let x{n} = {n};")).collect::<Vec<_>>().join("\n")
+        );
+        let preview = completed(fenced).output_preview(120);
+        let (head, tail) = preview.split_once(" lines omitted …").unwrap();
+        assert!(head.starts_with("```rust\n"));
+        assert!(head.ends_with("let x4 = 4;\n```\n… 42"));
+        assert!(tail.starts_with("\n```rust\n"));
+        assert!(tail.ends_with("```"));
+
+        let single_line = "x".repeat(500);
+        let preview = completed(single_line).output_preview(100);
+        assert!(preview.starts_with(&"x".repeat(50)));
+        assert!(preview.ends_with("… 450 characters omitted …"));
     }
 }
@@ -0,0 +1,180 @@
+//! Outbound webhook for OpenCode session errors, for ops alerting.
+
+use crate::opencode::types::SessionErrorKind;
+
+use anyhow::bail;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Body POSTed to the webhook for each `session.error`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionErrorPayload {
+    pub channel_id: Option<String>,
+    pub session_id: Option<String>,
+    pub kind: SessionErrorKind,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+}
+
+/// POSTs a JSON payload to a configured URL whenever a session errors.
+///
+/// `notify` is fire-and-forget: delivery runs in its own task with retry and
+/// backoff, so a slow or failing webhook never holds up event processing.
+#[derive(Debug, Clone)]
+pub struct SessionErrorNotifier {
+    client: reqwest::Client,
+    url: String,
+    max_attempts: u32,
+    initial_backoff: Duration,
+}
+
+impl SessionErrorNotifier {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .unwrap_or_default(),
+            url: url.into(),
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Report a session error. Fire-and-forget.
+    pub fn notify(&self, channel_id: Option<&str>, session_id: Option<&str>, kind: SessionErrorKind) {
+        let notifier = self.clone();
+        let payload = SessionErrorPayload {
+            channel_id: channel_id.map(str::to_string),
+            session_id: session_id.map(str::to_string),
+            kind,
+            timestamp: chrono::Utc::now(),
+        };
+
+        tokio::spawn(async move {
+            if let Err(error) = notifier.deliver(&payload).await {
+                tracing::warn!(%error, url = %notifier.url, "failed to deliver session error webhook");
+            }
+        });
+    }
+
+    async fn deliver(&self, payload: &SessionErrorPayload) -> anyhow::Result<()> {
+        post_json(&self.client, &self.url, payload, self.max_attempts, self.initial_backoff).await
+    }
+}
+
+/// POST `payload` as JSON to `url`, retrying with exponential backoff on
+/// connection errors, 429, and 5xx. Other client errors fail immediately.
+pub(crate) async fn post_json(
+    client: &reqwest::Client,
+    url: &str,
+    payload: &impl Serialize,
+    max_attempts: u32,
+    initial_backoff: Duration,
+) -> anyhow::Result<()> {
+    let mut backoff = initial_backoff;
+    let mut attempt = 1;
+    loop {
+        let retryable = match client.post(url).json(payload).send().await {
+            Ok(response) if response.status().is_success() => return Ok(()),
+            Ok(response) => {
+                let status = response.status();
+                if !(status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS) {
+                    bail!("webhook rejected payload ({status})");
+                }
+                anyhow::anyhow!("webhook returned {status}")
+            }
+            Err(error) => anyhow::Error::new(error),
+        };
+
+        if attempt >= max_attempts {
+            return Err(retryable.context(format!("gave up after {attempt} attempts")));
+        }
+        tracing::debug!(error = %retryable, attempt, %url, "webhook delivery failed, retrying");
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Json;
+    use axum::extract::State;
+    use axum::http::StatusCode;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use tokio::sync::mpsc;
+
+    /// Mock webhook that fails the first `failures` requests with a 503 and
+    /// forwards every body it receives.
+    async fn mock_webhook(failures: u32) -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let (body_tx, body_rx) = mpsc::unbounded_channel();
+        let calls = Arc::new(AtomicU32::new(0));
+        let app = axum::Router::new()
+            .route(
+                "/hook",
+                axum::routing::post(
+                    move |State(calls): State<Arc<AtomicU32>>, Json(body): Json<serde_json::Value>| {
+                        let body_tx = body_tx.clone();
+                        async move {
+                            let _ = body_tx.send(body);
+                            if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                                StatusCode::SERVICE_UNAVAILABLE
+                            } else {
+                                StatusCode::OK
+                            }
+                        }
+                    },
+                ),
+            )
+            .with_state(calls);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, app).await.unwrap();
+        });
+        (format!("http://{address}/hook"), body_rx)
+    }
+
+    fn notifier(url: String) -> SessionErrorNotifier {
+        SessionErrorNotifier {
+            initial_backoff: Duration::from_millis(10),
+            ..SessionErrorNotifier::new(url)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_notify_posts_payload() {
+        let (url, mut bodies) = mock_webhook(0).await;
+
+        notifier(url).notify(Some("discord:1:2"), Some("ses_1"), SessionErrorKind::RateLimited);
+
+        let body = bodies.recv().await.unwrap();
+        assert_eq!(body["channel_id"], "discord:1:2");
+        assert_eq!(body["session_id"], "ses_1");
+        assert_eq!(body["kind"], "rate_limited");
+        let timestamp = body["timestamp"].as_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(timestamp).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_deliver_retries_server_errors() {
+        let (url, mut bodies) = mock_webhook(2).await;
+        let payload = SessionErrorPayload {
+            channel_id: None,
+            session_id: Some("ses_1".into()),
+            kind: SessionErrorKind::ContextLengthExceeded,
+            timestamp: chrono::Utc::now(),
+        };
+
+        notifier(url).deliver(&payload).await.unwrap();
+
+        let mut attempts = 0;
+        while bodies.try_recv().is_ok() {
+            attempts += 1;
+        }
+        assert_eq!(attempts, 3);
+    }
+}
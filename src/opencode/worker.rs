@@ -4,15 +4,45 @@
 //! delegates to an OpenCode subprocess that has its own codebase exploration,
 //! context management, and tool suite. Communication happens over HTTP + SSE.
 
-use crate::opencode::server::OpenCodeServerPool;
+use crate::conversation::channels::ChannelStore;
+use crate::conversation::history::ConversationLogger;
+use crate::opencode::metrics;
+use crate::opencode::audit::{PermissionAuditLog, ReplySource};
+use crate::opencode::compaction::{CompactionScheduler, SummaryLimits, compact_channel};
+use crate::opencode::grants::{GrantDecision, PermissionGrants};
+use crate::opencode::handler::{DefaultEventHandler, EventContext, EventHandler};
+use crate::opencode::idempotency::{Claim, PromptLedger, idempotency_key, prompt_key};
+use crate::opencode::indicator::{IndicatorState, TypingIndicator};
+use crate::opencode::limits::{FittedPrompt, PromptLimit};
+use crate::opencode::pending::PendingRegistry;
+use crate::opencode::retries::{RetryTracker, RetryUpdate};
+use crate::opencode::permissions::{PermissionBatcher, PermissionTimeouts};
+use crate::opencode::prompt::{ChannelContext, ChannelPreferences, TurnPromptBuilder};
+use crate::opencode::questions::{PendingQuestions, QuestionDefault};
+use crate::opencode::queue::{PromptQueue, strip_priority};
+use crate::opencode::recorder::SseRecorder;
+use crate::opencode::render::render_final_message;
+use crate::opencode::replay::read_recording;
+use crate::opencode::server::{OpenCodeServer, OpenCodeServerPool};
+use crate::opencode::sessions::{SessionRegistry, SessionRoute, ensure_session};
+use crate::opencode::sinks::{ReplySinks, reply_message};
+use crate::opencode::stream::StreamCoordinator;
+use crate::opencode::turns::{ActiveTurns, TurnHandle};
+use crate::opencode::webhook::SessionErrorNotifier;
 use crate::opencode::types::*;
-use crate::{AgentId, ChannelId, ProcessEvent, WorkerId};
+use crate::{AgentId, ChannelId, OutboundResponse, ProcessEvent, WorkerId, WorkerInput};
 
 use anyhow::{Context as _, bail};
 use futures::StreamExt as _;
-use std::path::PathBuf;
+use futures::stream::BoxStream;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{broadcast, mpsc, Mutex};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::Instrument as _;
 use uuid::Uuid;
 
 /// An OpenCode-backed worker that drives a coding session via subprocess.
@@ -25,17 +55,112 @@ pub struct OpenCodeWorker {
     pub server_pool: Arc<OpenCodeServerPool>,
     pub event_tx: broadcast::Sender<ProcessEvent>,
     /// Input channel for interactive follow-ups (permissions, questions, user messages).
-    pub input_rx: Option<mpsc::Receiver<String>>,
-    /// System prompt injected into each OpenCode prompt.
-    pub system_prompt: Option<String>,
-    /// Model override (provider/model format like "anthropic/claude-sonnet-4-20250514").
-    pub model: Option<String>,
+    pub input_rx: Option<mpsc::Receiver<WorkerInput>>,
+    /// System prompt, model ("provider/model"), and OpenCode agent sent with
+    /// each prompt. Unset ones use the server's defaults.
+    pub preferences: ChannelPreferences,
+    /// Channel metadata sent ahead of each prompt as a synthetic part. The
+    /// current time is refreshed per prompt.
+    pub channel_context: Option<ChannelContext>,
+    /// Whether a follow-up arriving mid-turn waits or aborts the running prompt.
+    pub follow_up_mode: FollowUpMode,
+    /// How long to wait for more follow-ups to merge into a prompt. Zero
+    /// sends each follow-up as its own turn.
+    pub prompt_coalesce_window: Duration,
+    /// Largest merged follow-up prompt, in characters.
+    pub prompt_coalesce_max_chars: usize,
+    /// Shortest gap between two refreshes of an unchanged typing indicator.
+    pub indicator_debounce: Duration,
+    /// Provider retries in one turn before the channel is warned it may fail.
+    /// Zero never warns.
+    pub retry_warning_attempts: u32,
+    /// Whether permission prompts are auto-approved or asked in the channel.
+    pub permission_mode: PermissionMode,
+    /// How long to collect permission requests before asking, in `Ask` mode.
+    pub permission_batch_window: Duration,
+    /// How long asked permissions wait for a reply before being rejected.
+    pub permission_timeouts: PermissionTimeouts,
+    /// Audit trail for every permission reply sent.
+    pub permission_audit: Option<PermissionAuditLog>,
+    /// "Always" replies kept per channel, answered without asking in later sessions.
+    pub permission_grants: Option<PermissionGrants>,
+    /// How default answers to questions are picked.
+    pub question_default: QuestionDefault,
+    /// How long asked questions wait for a reply before the default is sent.
+    pub question_timeout: Option<Duration>,
+    /// Used to persist partial replies from aborted turns and tool calls.
+    pub conversation_logger: Option<ConversationLogger>,
+    /// Records the channel's session, so later workers resume it.
+    pub channel_store: Option<ChannelStore>,
+    /// Session tree used to attribute sub-agent (child session) activity.
+    pub sessions: Arc<SessionRegistry>,
+    /// Where to stream assistant text as an editable chat message.
+    pub reply_stream: Option<mpsc::Sender<OutboundResponse>>,
+    /// Compacts channel history and retries once on context-length errors.
+    pub auto_compaction: Option<AutoCompaction>,
+    /// Truncates, rejects, or attaches messages over the prompt limit.
+    pub prompt_guard: Option<PromptGuard>,
+    /// Compacts channel history in the background after turns, once enough
+    /// have piled up.
+    pub compaction_scheduler: Option<Arc<CompactionScheduler>>,
+    /// Where each prompt's turn is registered while in flight, so it can be
+    /// looked up or cancelled from outside the worker.
+    pub active_turns: Option<Arc<ActiveTurns>>,
+    /// Where asked permissions and questions are listed until answered.
+    pub pending: Option<Arc<PendingRegistry>>,
+    /// Keeps the raw SSE envelopes of the channel's turns for debugging.
+    pub sse_recorder: Option<Arc<SseRecorder>>,
+    /// Where the recorded envelopes are written when a turn fails.
+    pub sse_debug_pool: Option<sqlx::SqlitePool>,
+    /// Alerts an external webhook on session errors.
+    pub error_notifier: Option<SessionErrorNotifier>,
+    /// Where finished replies are forwarded. Needs a channel.
+    pub reply_sinks: ReplySinks,
+    /// Build and echo prompts instead of sending them to OpenCode.
+    pub dry_run: bool,
+    /// File parts (chat attachments) sent along with the initial task.
+    pub files: Vec<PartInput>,
+    /// Hooks run on session events ahead of the built-in chat behavior.
+    /// Defaults to overriding nothing.
+    pub event_handler: Arc<dyn EventHandler>,
+    /// Records prompt sends so a repeat of one is skipped.
+    pub prompt_ledger: Option<PromptLedger>,
+    /// Idempotency key of the user message the task was spawned for. The
+    /// task's prompt key is derived from it.
+    pub message_key: Option<String>,
+    /// Record every part of the turn's assistant messages to
+    /// `message_parts`. Needs a conversation logger and a channel.
+    pub persist_message_parts: bool,
+    /// How often a streamed reply is written to history while it grows, so
+    /// a crash mid-turn keeps what was said. Zero logs it only once the turn
+    /// ends.
+    pub reply_checkpoint_interval: Duration,
+}
+
+/// Where auto-compaction archives transcripts and posts its notice, and
+/// what its summaries are checked against.
+#[derive(Debug, Clone)]
+pub struct AutoCompaction {
+    pub archives_dir: PathBuf,
+    pub notice_tx: mpsc::Sender<OutboundResponse>,
+    pub summary_limits: SummaryLimits,
+}
+
+/// The prompt limit a worker enforces, and where it tells the user about
+/// messages it changed or rejected.
+#[derive(Debug, Clone)]
+pub struct PromptGuard {
+    pub limit: PromptLimit,
+    pub notice_tx: mpsc::Sender<OutboundResponse>,
 }
 
 /// Result of an OpenCode worker run.
 pub struct OpenCodeWorkerResult {
     pub session_id: String,
     pub result_text: String,
+    /// How the initial task's turn ended. Follow-up turns are reported as
+    /// `ProcessEvent::WorkerTurnOutcome` only.
+    pub outcome: TurnOutcome,
 }
 
 impl OpenCodeWorker {
@@ -57,8 +182,40 @@ impl OpenCodeWorker {
             server_pool,
             event_tx,
             input_rx: None,
-            system_prompt: None,
-            model: None,
+            preferences: ChannelPreferences::default(),
+            channel_context: None,
+            follow_up_mode: FollowUpMode::default(),
+            prompt_coalesce_window: Duration::ZERO,
+            prompt_coalesce_max_chars: 4000,
+            indicator_debounce: Duration::from_secs(5),
+            retry_warning_attempts: 3,
+            permission_mode: PermissionMode::default(),
+            permission_batch_window: Duration::from_millis(300),
+            permission_timeouts: PermissionTimeouts::default(),
+            permission_audit: None,
+            permission_grants: None,
+            question_default: QuestionDefault::default(),
+            question_timeout: None,
+            conversation_logger: None,
+            channel_store: None,
+            sessions: Arc::new(SessionRegistry::new()),
+            reply_stream: None,
+            auto_compaction: None,
+            prompt_guard: None,
+            compaction_scheduler: None,
+            active_turns: None,
+            pending: None,
+            sse_recorder: None,
+            sse_debug_pool: None,
+            error_notifier: None,
+            reply_sinks: ReplySinks::default(),
+            dry_run: false,
+            files: Vec::new(),
+            event_handler: Arc::new(DefaultEventHandler),
+            prompt_ledger: None,
+            message_key: None,
+            persist_message_parts: false,
+            reply_checkpoint_interval: Duration::ZERO,
         }
     }
 
@@ -70,7 +227,7 @@ impl OpenCodeWorker {
         directory: PathBuf,
         server_pool: Arc<OpenCodeServerPool>,
         event_tx: broadcast::Sender<ProcessEvent>,
-    ) -> (Self, mpsc::Sender<String>) {
+    ) -> (Self, mpsc::Sender<WorkerInput>) {
         let (input_tx, input_rx) = mpsc::channel(32);
         let mut worker = Self::new(channel_id, agent_id, task, directory, server_pool, event_tx);
         worker.input_rx = Some(input_rx);
@@ -79,24 +236,246 @@ impl OpenCodeWorker {
 
     /// Set the system prompt injected into OpenCode prompts.
     pub fn with_system_prompt(mut self, prompt: impl Into<String>) -> Self {
-        self.system_prompt = Some(prompt.into());
+        self.preferences.system = Some(prompt.into());
         self
     }
 
     /// Set the model to use for this worker.
     pub fn with_model(mut self, model: impl Into<String>) -> Self {
-        self.model = Some(model.into());
+        self.preferences.model = Some(model.into());
+        self
+    }
+
+    /// Run prompts with this OpenCode agent instead of the server's default.
+    pub fn with_agent(mut self, agent: impl Into<String>) -> Self {
+        self.preferences.agent = Some(agent.into());
+        self
+    }
+
+    /// Send prompts with a channel's resolved preferences (see
+    /// `ChannelPreferences::resolve`).
+    pub fn with_preferences(mut self, preferences: ChannelPreferences) -> Self {
+        self.preferences = preferences;
+        self
+    }
+
+    /// Tell the model where each prompt comes from (see `ChannelContext`).
+    pub fn with_channel_context(mut self, context: ChannelContext) -> Self {
+        self.channel_context = Some(context);
+        self
+    }
+
+    /// Set how follow-ups that arrive mid-turn are handled.
+    pub fn with_follow_up_mode(mut self, mode: FollowUpMode) -> Self {
+        self.follow_up_mode = mode;
+        self
+    }
+
+    /// Merge follow-ups that arrive within `window` of each other (or queue
+    /// up mid-turn) into one prompt of at most `max_chars`.
+    pub fn with_prompt_coalescing(mut self, window: Duration, max_chars: usize) -> Self {
+        self.prompt_coalesce_window = window;
+        self.prompt_coalesce_max_chars = max_chars;
+        self
+    }
+
+    /// Refresh an unchanged typing indicator at most once per `debounce`.
+    pub fn with_indicator_debounce(mut self, debounce: Duration) -> Self {
+        self.indicator_debounce = debounce;
+        self
+    }
+
+    /// Warn the channel once a turn's provider retries go past `attempts`.
+    /// Zero never warns.
+    pub fn with_retry_warning(mut self, attempts: u32) -> Self {
+        self.retry_warning_attempts = attempts;
+        self
+    }
+
+    /// Set how permission prompts are answered, and the batching window for `Ask`.
+    pub fn with_permission_mode(mut self, mode: PermissionMode, batch_window: Duration) -> Self {
+        self.permission_mode = mode;
+        self.permission_batch_window = batch_window;
+        self
+    }
+
+    /// Reject asked permissions nobody answers within these timeouts.
+    pub fn with_permission_timeouts(mut self, timeouts: PermissionTimeouts) -> Self {
+        self.permission_timeouts = timeouts;
+        self
+    }
+
+    /// Record every permission reply in an audit trail.
+    pub fn with_permission_audit(mut self, audit: PermissionAuditLog) -> Self {
+        self.permission_audit = Some(audit);
+        self
+    }
+
+    /// Persist "always" replies for the channel, and in `Ask` mode answer
+    /// requests they cover without prompting. Needs a channel; otherwise
+    /// it's a no-op.
+    pub fn with_permission_grants(mut self, grants: PermissionGrants) -> Self {
+        self.permission_grants = Some(grants);
+        self
+    }
+
+    /// Set how default answers are picked, and how long `Ask` mode waits for
+    /// a human before sending them (`None` waits forever).
+    pub fn with_question_defaults(mut self, default: QuestionDefault, timeout: Option<Duration>) -> Self {
+        self.question_default = default;
+        self.question_timeout = timeout;
+        self
+    }
+
+    /// Set the logger used to persist partial replies from aborted turns and tool calls.
+    pub fn with_conversation_logger(mut self, logger: ConversationLogger) -> Self {
+        self.conversation_logger = Some(logger);
+        self
+    }
+
+    /// Reuse the channel's recorded session if OpenCode still has it, and
+    /// record the sessions this worker creates so later workers can resume
+    /// them. Needs a conversation logger and a channel; otherwise every run
+    /// starts a fresh session.
+    pub fn with_channel_store(mut self, store: ChannelStore) -> Self {
+        self.channel_store = Some(store);
+        self
+    }
+
+    /// Share a session registry with other workers.
+    pub fn with_session_registry(mut self, sessions: Arc<SessionRegistry>) -> Self {
+        self.sessions = sessions;
+        self
+    }
+
+    /// Stream assistant text into the channel as it's generated, editing a
+    /// placeholder message in place.
+    pub fn with_reply_stream(mut self, response_tx: mpsc::Sender<OutboundResponse>) -> Self {
+        self.reply_stream = Some(response_tx);
+        self
+    }
+
+    /// When a prompt overflows the context window, summarize the channel's
+    /// history, archive the raw turns to `archives_dir`, and resend the prompt
+    /// in a fresh session with the summary. A notice is posted to `notice_tx`.
+    /// A summary failing `summary_limits` fails the prompt instead.
+    /// Needs a conversation logger and a channel; otherwise it's a no-op.
+    pub fn with_auto_compaction(
+        mut self,
+        archives_dir: PathBuf,
+        notice_tx: mpsc::Sender<OutboundResponse>,
+        summary_limits: SummaryLimits,
+    ) -> Self {
+        self.auto_compaction = Some(AutoCompaction { archives_dir, notice_tx, summary_limits });
+        self
+    }
+
+    /// Check each message against `limit` before sending it, posting what
+    /// was done about oversized ones to `notice_tx`.
+    pub fn with_prompt_limit(mut self, limit: PromptLimit, notice_tx: mpsc::Sender<OutboundResponse>) -> Self {
+        self.prompt_guard = Some(PromptGuard { limit, notice_tx });
+        self
+    }
+
+    /// After each completed turn, let `scheduler` compact the channel's
+    /// history in the background. Needs a conversation logger and a channel;
+    /// otherwise it's a no-op.
+    pub fn with_compaction_scheduler(mut self, scheduler: Arc<CompactionScheduler>) -> Self {
+        self.compaction_scheduler = Some(scheduler);
+        self
+    }
+
+    /// Register each prompt's turn in `turns` until the session goes idle or
+    /// errors. Needs a channel; otherwise it's a no-op.
+    pub fn with_active_turns(mut self, turns: Arc<ActiveTurns>) -> Self {
+        self.active_turns = Some(turns);
+        self
+    }
+
+    /// List each asked permission and question in `pending` until it's
+    /// answered or its turn ends. Needs a channel; otherwise it's a no-op.
+    pub fn with_pending_registry(mut self, pending: Arc<PendingRegistry>) -> Self {
+        self.pending = Some(pending);
+        self
+    }
+
+    /// Record the raw SSE envelopes of each turn in `recorder`, and write
+    /// them to `persist_to` when a turn fails. Needs a channel; otherwise
+    /// it's a no-op.
+    pub fn with_sse_recorder(mut self, recorder: Arc<SseRecorder>, persist_to: Option<sqlx::SqlitePool>) -> Self {
+        self.sse_recorder = Some(recorder);
+        self.sse_debug_pool = persist_to;
+        self
+    }
+
+    /// POST every session error to a webhook.
+    pub fn with_error_notifier(mut self, notifier: SessionErrorNotifier) -> Self {
+        self.error_notifier = Some(notifier);
+        self
+    }
+
+    /// Forward every finished reply to these sinks.
+    pub fn with_reply_sinks(mut self, sinks: ReplySinks) -> Self {
+        self.reply_sinks = sinks;
+        self
+    }
+
+    /// Echo each prompt back instead of sending it, for testing context
+    /// assembly without model cost.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Run `handler`'s hooks on session events, ahead of the built-in
+    /// chat behavior.
+    pub fn with_event_handler(mut self, handler: Arc<dyn EventHandler>) -> Self {
+        self.event_handler = handler;
+        self
+    }
+
+    /// Skip prompt sends the ledger has already seen.
+    pub fn with_prompt_ledger(mut self, ledger: PromptLedger) -> Self {
+        self.prompt_ledger = Some(ledger);
+        self
+    }
+
+    /// Key the task's prompt by the user message it was spawned for.
+    pub fn with_message_key(mut self, message_key: Option<String>) -> Self {
+        self.message_key = message_key;
+        self
+    }
+
+    /// Record each part of the assistant's messages, in order, as it streams.
+    pub fn with_message_parts(mut self, persist: bool) -> Self {
+        self.persist_message_parts = persist;
+        self
+    }
+
+    /// Write streamed replies to history every `interval` while they grow.
+    pub fn with_reply_checkpoints(mut self, interval: Duration) -> Self {
+        self.reply_checkpoint_interval = interval;
+        self
+    }
+
+    /// Attach files to the initial task's prompt, after its text.
+    pub fn with_files(mut self, files: Vec<PartInput>) -> Self {
+        self.files = files;
         self
     }
 
     /// Run the worker: spawn/reuse an OpenCode server, create a session,
     /// send the task, monitor via SSE, and return the result.
     pub async fn run(mut self) -> anyhow::Result<OpenCodeWorkerResult> {
+        if self.dry_run {
+            return self.run_dry().await;
+        }
+
         self.send_status("starting OpenCode server");
 
         // Get or create server for this directory
         let server = self.server_pool
-            .get_or_create(&self.directory)
+            .get_or_create_for_channel(&self.directory, self.channel_id.as_deref())
             .await
             .with_context(|| format!(
                 "failed to get OpenCode server for '{}'",
@@ -105,84 +484,78 @@ impl OpenCodeWorker {
 
         self.send_status("creating session");
 
-        // Create a session
+        // Resume the channel's session if there is one, otherwise create one
         let session = {
             let guard = server.lock().await;
-            guard.create_session(Some(format!("spacebot-worker-{}", self.id))).await?
+            let title = Some(format!("spacebot-worker-{}", self.id));
+            match (&self.channel_store, &self.conversation_logger, &self.channel_id) {
+                (Some(store), Some(logger), Some(channel_id)) => {
+                    ensure_session(store, logger, &guard, channel_id, title).await?
+                }
+                _ => guard.create_session(title).await?,
+            }
         };
-        let session_id = session.id.clone();
+        let mut session_id = session.id.clone();
 
         tracing::info!(
             worker_id = %self.id,
             session_id = %session_id,
             directory = %self.directory.display(),
-            "OpenCode session created"
+            "OpenCode session ready"
         );
+        self.sessions.register_root(session_id.clone(), self.channel_id.clone());
 
-        // Subscribe to SSE events before sending the prompt
-        let event_response = {
-            let guard = server.lock().await;
-            guard.subscribe_events().await?
-        };
-
-        // Build the prompt request
-        let model_param = self.model.as_ref().and_then(|m| parse_model_param(m));
-        let prompt_request = SendPromptRequest {
-            parts: vec![PartInput::Text {
-                text: self.task.clone(),
-                synthetic: None,
-            }],
-            system: self.system_prompt.clone(),
-            model: model_param,
-            agent: None,
-        };
+        let mut input_rx = self.input_rx.take();
+        // Follow-ups waiting for the next turn, including those that arrived
+        // mid-turn while the worker was listening for permission replies.
+        let mut queue = PromptQueue::new(self.prompt_coalesce_window, self.prompt_coalesce_max_chars);
 
-        // Send prompt async so we can process SSE events while it runs
         self.send_status("sending task to OpenCode");
+        let files = std::mem::take(&mut self.files);
+        let task = WorkerInput { text: self.task.clone(), message_key: self.message_key.clone() };
+        let outcome = match self
+            .run_turn(&server, &mut session_id, task, files, input_rx.as_mut(), &mut queue)
+            .await
         {
-            let guard = server.lock().await;
-            guard.send_prompt_async(&session_id, &prompt_request).await?;
-        }
-
-        // Process SSE events until session goes idle or errors
-        let result_text = self.process_events(
-            event_response,
-            &session_id,
-            &server,
-        ).await?;
+            Ok(outcome) => outcome,
+            Err(error) => {
+                self.sessions.remove_tree(&session_id);
+                return Err(error);
+            }
+        };
 
         // Interactive follow-up loop
-        if let Some(mut input_rx) = self.input_rx.take() {
+        if let Some(mut input_rx) = input_rx {
             self.send_status("waiting for follow-up");
 
-            while let Some(follow_up) = input_rx.recv().await {
-                self.send_status("processing follow-up");
-
-                // Subscribe to fresh events for the follow-up
-                let event_response = {
-                    let guard = server.lock().await;
-                    guard.subscribe_events().await?
-                };
-
-                let follow_up_request = SendPromptRequest {
-                    parts: vec![PartInput::Text {
-                        text: follow_up,
-                        synthetic: None,
-                    }],
-                    system: self.system_prompt.clone(),
-                    model: self.model.as_ref().and_then(|m| parse_model_param(m)),
-                    agent: None,
+            loop {
+                if queue.is_empty() {
+                    match input_rx.recv().await {
+                        Some(follow_up) => queue.push(follow_up),
+                        None => break,
+                    }
+                }
+                collect_follow_ups(&mut input_rx, &mut queue).await;
+                let Some(batch) = queue.pop_batch() else {
+                    break;
                 };
-
-                {
-                    let guard = server.lock().await;
-                    guard.send_prompt_async(&session_id, &follow_up_request).await?;
+                self.send_status("processing follow-up");
+                if batch.messages.len() > 1 {
+                    tracing::debug!(worker_id = %self.id, messages = batch.messages.len(), "coalesced follow-ups");
                 }
+                let (follow_up, parts) = batch.into_prompt();
 
-                match self.process_events(event_response, &session_id, &server).await {
-                    Ok(_) => {
-                        self.send_status("waiting for follow-up");
-                    }
+                match self
+                    .run_turn(&server, &mut session_id, follow_up, parts, Some(&mut input_rx), &mut queue)
+                    .await
+                {
+                    Ok(outcome) => match outcome.duration_ms {
+                        Some(ms) => self.send_status(&format!(
+                            "waiting for follow-up (last turn took {})",
+                            format_duration_ms(ms as f64)
+                        )),
+                        None => self.send_status("waiting for follow-up"),
+                    },
                     Err(error) => {
                         tracing::error!(
                             worker_id = %self.id,
@@ -196,6 +569,7 @@ impl OpenCodeWorker {
             }
         }
 
+        self.sessions.remove_tree(&session_id);
         self.send_status("completed");
 
         tracing::info!(
@@ -206,156 +580,1069 @@ impl OpenCodeWorker {
 
         Ok(OpenCodeWorkerResult {
             session_id,
-            result_text,
+            result_text: outcome.text.clone(),
+            outcome,
         })
     }
 
-    /// Process SSE events from the OpenCode event stream until the session
-    /// goes idle or encounters an error.
-    async fn process_events(
+    /// Send a prompt and drive it until the session goes idle.
+    ///
+    /// In `FollowUpMode::Abort`, a message arriving on `input_rx` mid-turn
+    /// aborts the running prompt and is sent in its place. Returns the outcome
+    /// of the last prompt sent, which is also emitted as a `WorkerTurnOutcome`.
+    ///
+    /// With auto-compaction, a context-length error is retried once in a new
+    /// session, which replaces `session_id` for the rest of the run.
+    ///
+    /// `files` (and any follow-ups coalesced with `text`) go out with `text`
+    /// and its compaction retry, but not with a follow-up that interrupts it.
+    ///
+    /// Runs inside an `opencode_turn` span carrying the channel, session, and
+    /// a fresh turn ID, so every log line from the turn can be correlated.
+    async fn run_turn(
         &self,
-        response: reqwest::Response,
-        session_id: &str,
         server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
-    ) -> anyhow::Result<String> {
-        let mut stream = response.bytes_stream();
-        let mut buffer = String::new();
-        let mut last_text = String::new();
-        let mut current_tool: Option<String> = None;
-        // Guards: don't treat session.idle as completion until we've seen real work
-        let mut has_received_event = false;
-        let mut has_assistant_message = false;
+        session_id: &mut String,
+        prompt: WorkerInput,
+        files: Vec<PartInput>,
+        input_rx: Option<&mut mpsc::Receiver<WorkerInput>>,
+        queue: &mut PromptQueue,
+    ) -> anyhow::Result<TurnOutcome> {
+        let span = tracing::info_span!(
+            "opencode_turn",
+            worker_id = %self.id,
+            channel_id = self.channel_id.as_deref().unwrap_or_default(),
+            session_id = session_id.as_str(),
+            turn_id = %Uuid::new_v4(),
+        );
+        self.drive_turn(server, session_id, prompt, files, input_rx, queue)
+            .instrument(span)
+            .await
+    }
 
+    async fn drive_turn(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        session_id: &mut String,
+        prompt: WorkerInput,
+        mut files: Vec<PartInput>,
+        mut input_rx: Option<&mut mpsc::Receiver<WorkerInput>>,
+        queue: &mut PromptQueue,
+    ) -> anyhow::Result<TurnOutcome> {
+        let mut message_key = prompt.message_key;
+        let mut text = match self.apply_prompt_limit(prompt.text).await {
+            Ok(fitted) => {
+                files.extend(fitted.files);
+                fitted.text
+            }
+            Err(outcome) => return Ok(outcome),
+        };
+        let mut request = self.build_prompt(&text, prompt_key(message_key.as_deref(), session_id, &text));
+        request.parts.extend(files.iter().cloned());
+        let mut compacted = false;
         loop {
-            let chunk = tokio::select! {
-                chunk = stream.next() => chunk,
-                _ = tokio::time::sleep(std::time::Duration::from_secs(600)) => {
-                    bail!("OpenCode session timed out after 10 minutes of inactivity");
+            let events = self.send_prompt(server, session_id, &request).await?;
+            let turn = self.register_turn(session_id);
+            let cancel = turn.as_ref().map(|turn| turn.cancel.clone()).unwrap_or_default();
+            let mut checkpoint = self.reply_checkpoint();
+            let end = self
+                .process_events(events, session_id, server, input_rx.as_deref_mut(), queue, &cancel, &mut checkpoint)
+                .await;
+            self.complete_turn(turn.as_ref());
+            // Anything but a completed turn leaves the streamed reply cut short.
+            let persisted = self.finalize_checkpoint(checkpoint.take(), true).await;
+            if end.is_err() {
+                self.persist_sse_debug().await;
+            }
+            if let Some(pending) = &self.pending {
+                let dropped = pending.clear_session(session_id);
+                if dropped > 0 {
+                    tracing::debug!(worker_id = %self.id, dropped, "turn ended with requests still pending");
                 }
-            };
+            }
 
-            let Some(chunk) = chunk else {
-                // Stream ended -- if we have results, return them
-                if has_assistant_message && !last_text.is_empty() {
-                    return Ok(last_text);
+            match end? {
+                TurnEnd::Completed(outcome) => {
+                    tracing::info!(
+                        finish_reason = ?outcome.finish_reason,
+                        tool_errored = outcome.tool_errored,
+                        duration_ms = ?outcome.duration_ms,
+                        "turn completed"
+                    );
+                    self.emit_turn_outcome(&outcome);
+                    self.forward_reply(&outcome);
+                    self.schedule_compaction(server).await;
+                    return Ok(outcome);
                 }
-                bail!("OpenCode event stream ended before session completed");
-            };
-
-            let bytes = chunk.context("failed to read SSE chunk")?;
-            buffer.push_str(&String::from_utf8_lossy(&bytes));
-
-            // Parse SSE lines from buffer
-            while let Some(event) = extract_sse_event(&mut buffer) {
-                match self.handle_sse_event(
-                    &event,
-                    session_id,
-                    server,
-                    &mut last_text,
-                    &mut current_tool,
-                    &mut has_received_event,
-                    &mut has_assistant_message,
-                ).await {
-                    EventAction::Continue => {}
-                    EventAction::Complete => return Ok(last_text.clone()),
-                    EventAction::Error(message) => bail!("OpenCode session error: {message}"),
+                TurnEnd::Cancelled { partial_text } => {
+                    self.interrupt_turn(server, session_id, &partial_text, persisted, "turn cancelled").await;
+                    return Ok(TurnOutcome {
+                        text: partial_text,
+                        finish_reason: None,
+                        tool_errored: false,
+                        tokens: None,
+                        model: None,
+                        duration_ms: None,
+                    });
+                }
+                TurnEnd::Interrupted { partial_text, next_message } => {
+                    self.interrupt_turn(server, session_id, &partial_text, persisted, "interrupted by new message").await;
+                    let next_text = match strip_priority(&next_message.text) {
+                        Some(text) => text.to_string(),
+                        None => next_message.text,
+                    };
+                    let fitted = match self.apply_prompt_limit(next_text).await {
+                        Ok(fitted) => fitted,
+                        Err(outcome) => return Ok(outcome),
+                    };
+                    text = fitted.text;
+                    files = fitted.files;
+                    message_key = next_message.message_key;
+                    request = self.build_prompt(&text, prompt_key(message_key.as_deref(), session_id, &text));
+                    request.parts.extend(files.iter().cloned());
+                }
+                TurnEnd::ContextOverflow(message) => {
+                    // Only one automatic retry per turn.
+                    let retry = if compacted {
+                        None
+                    } else {
+                        compacted = true;
+                        self.compact_and_reopen(server, session_id, &text)
+                            .await
+                            .with_context(|| format!("context length exceeded ({message}) and compaction failed"))?
+                    };
+                    let Some(mut retry) = retry else {
+                        bail!("OpenCode session error: context_length_exceeded: {message}");
+                    };
+                    retry.parts.extend(files.iter().cloned());
+                    // Keyed to the new session, so it's distinct from the
+                    // prompt that overflowed.
+                    retry.idempotency_key = Some(prompt_key(message_key.as_deref(), session_id, &text));
+                    request = retry;
+                    tracing::Span::current().record("session_id", session_id.as_str());
                 }
             }
         }
     }
 
-    /// Handle a single SSE event. Returns whether to continue, complete, or error.
-    async fn handle_sse_event(
-        &self,
-        event: &SseEvent,
-        session_id: &str,
-        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
-        last_text: &mut String,
-        current_tool: &mut Option<String>,
-        has_received_event: &mut bool,
-        has_assistant_message: &mut bool,
-    ) -> EventAction {
-        match event {
-            SseEvent::MessageUpdated { info } => {
-                *has_received_event = true;
-                // Track assistant messages for idle guard
-                if let Some(msg) = info {
-                    if msg.role == "assistant" {
-                        if let Some(sid) = &msg.session_id {
-                            if sid == session_id {
-                                *has_assistant_message = true;
-                            }
-                        }
-                    }
-                }
-                EventAction::Continue
+    /// Fit `text` to the worker's prompt limit, posting a notice if it had to
+    /// be changed. A rejected message is never sent; `Err` is the turn's
+    /// outcome instead, carrying the guidance posted to the channel.
+    async fn apply_prompt_limit(&self, text: String) -> Result<FittedPrompt, TurnOutcome> {
+        let Some(guard) = &self.prompt_guard else {
+            return Ok(FittedPrompt { text, files: Vec::new(), notice: None });
+        };
+        let (fitted, notice) = match guard.limit.fit(&text) {
+            Ok(fitted) => {
+                let notice = fitted.notice.clone();
+                (Ok(fitted), notice)
             }
+            Err(guidance) => (Err(guidance.clone()), Some(guidance)),
+        };
 
-            SseEvent::MessagePartUpdated { part, .. } => {
-                *has_received_event = true;
-                match part {
-                    Part::Text { text, session_id: part_session, .. } => {
-                        if let Some(sid) = part_session {
-                            if sid != session_id {
-                                return EventAction::Continue;
-                            }
-                        }
-                        *has_assistant_message = true;
-                        *last_text = text.clone();
-                    }
-                    Part::Tool { tool, state, session_id: part_session, .. } => {
-                        if let Some(sid) = part_session {
-                            if sid != session_id {
-                                return EventAction::Continue;
-                            }
-                        }
-                        *has_assistant_message = true;
-                        if let Some(tool_name) = tool {
-                            if let Some(tool_state) = state {
-                                match tool_state {
-                                    ToolState::Running { title, .. } => {
-                                        *current_tool = Some(tool_name.clone());
-                                        let label = title.as_deref().unwrap_or(tool_name.as_str());
-                                        self.send_status(&format!("running: {label}"));
-                                    }
-                                    ToolState::Completed { .. } => {
-                                        if current_tool.as_deref() == Some(tool_name.as_str()) {
-                                            *current_tool = None;
-                                        }
-                                        self.send_status("working");
-                                    }
-                                    ToolState::Error { error, .. } => {
-                                        let description = error.as_deref().unwrap_or("unknown");
-                                        self.send_status(&format!("tool error: {tool_name}: {description}"));
-                                    }
-                                    ToolState::Pending { .. } => {
-                                        // Tool queued, no status update needed
-                                    }
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-                EventAction::Continue
+        if let Some(notice) = notice {
+            tracing::info!(
+                worker_id = %self.id,
+                chars = text.chars().count(),
+                max_chars = guard.limit.max_chars,
+                overflow = ?guard.limit.overflow,
+                "message over the prompt limit"
+            );
+            if guard.notice_tx.send(OutboundResponse::Text(notice)).await.is_err() {
+                tracing::debug!(worker_id = %self.id, "prompt limit notice channel closed");
             }
+        }
 
-            SseEvent::SessionIdle { session_id: event_session_id } => {
-                if event_session_id != session_id {
-                    return EventAction::Continue;
-                }
+        fitted.map_err(|guidance| TurnOutcome {
+            text: guidance,
+            finish_reason: None,
+            tool_errored: false,
+            tokens: None,
+            model: None,
+            duration_ms: None,
+        })
+    }
 
-                // Guard: don't complete until we've seen actual work.
-                // OpenCode can send an early idle event before the prompt is processed.
-                if !*has_received_event || !*has_assistant_message {
-                    tracing::trace!(
-                        worker_id = %self.id,
-                        has_received_event,
-                        has_assistant_message,
-                        "ignoring early session.idle"
-                    );
-                    return EventAction::Continue;
-                }
+    /// The request `send_prompt` would POST for `text`: the message as the
+    /// only part, plus the worker's system prompt and model, under
+    /// `idempotency_key`.
+    pub fn build_prompt(&self, text: &str, idempotency_key: String) -> SendPromptRequest {
+        let mut request = SendPromptRequest {
+            parts: vec![PartInput::Text {
+                text: text.to_string(),
+                synthetic: None,
+            }],
+            system: self.preferences.system.clone(),
+            model: self.preferences.model.as_ref().and_then(|m| parse_model_param(m)),
+            agent: self.preferences.agent.clone(),
+            idempotency_key: Some(idempotency_key),
+        };
+        if let Some(context) = self.channel_context() {
+            context.prepend_to(&mut request);
+        }
+        request
+    }
+
+    /// The channel context for a prompt sent now.
+    fn channel_context(&self) -> Option<ChannelContext> {
+        self.channel_context.as_ref().map(|context| ChannelContext {
+            now: chrono::Utc::now(),
+            ..context.clone()
+        })
+    }
+
+    /// Hand the channel to the compaction scheduler, if there is one.
+    async fn schedule_compaction(&self, server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>) {
+        let (Some(scheduler), Some(logger), Some(channel_id)) =
+            (&self.compaction_scheduler, &self.conversation_logger, &self.channel_id)
+        else {
+            return;
+        };
+        let handle = server.lock().await.handle();
+        scheduler.after_turn(handle, logger.clone(), channel_id.clone());
+    }
+
+    /// Mark the prompt just sent as the channel's active turn.
+    fn register_turn(&self, session_id: &str) -> Option<TurnHandle> {
+        let (Some(turns), Some(channel_id)) = (&self.active_turns, &self.channel_id) else {
+            return None;
+        };
+        Some(turns.register(channel_id, session_id))
+    }
+
+    fn complete_turn(&self, turn: Option<&TurnHandle>) {
+        if let (Some(turns), Some(channel_id), Some(turn)) = (&self.active_turns, &self.channel_id, turn) {
+            turns.complete(channel_id, turn);
+        }
+    }
+
+    fn emit_tool_error(&self, tool: &str, call_id: Option<&str>, error: &str) {
+        let _ = self.event_tx.send(ProcessEvent::WorkerToolError {
+            agent_id: self.agent_id.clone(),
+            worker_id: self.id,
+            channel_id: self.channel_id.clone(),
+            tool: tool.to_string(),
+            call_id: call_id.unwrap_or_default().to_string(),
+            error: error.to_string(),
+        });
+    }
+
+    /// Hand a finished turn's reply to the reply sinks.
+    fn forward_reply(&self, outcome: &TurnOutcome) {
+        let Some(channel_id) = &self.channel_id else {
+            return;
+        };
+        if self.reply_sinks.is_empty() {
+            return;
+        }
+        if let Some(message) = reply_message(channel_id, outcome) {
+            self.reply_sinks.dispatch(channel_id, &message);
+        }
+    }
+
+    fn emit_turn_outcome(&self, outcome: &TurnOutcome) {
+        let _ = self.event_tx.send(ProcessEvent::WorkerTurnOutcome {
+            agent_id: self.agent_id.clone(),
+            worker_id: self.id,
+            channel_id: self.channel_id.clone(),
+            outcome: outcome.clone(),
+        });
+    }
+
+    /// Drive a recorded event stream (see `opencode::replay`) through the
+    /// live event handler, as if it arrived for a prompt in `session_id`.
+    /// No server is contacted, so permission and question replies fail and
+    /// are logged. Returns the turn's outcome, or the error the live loop
+    /// would have raised.
+    pub async fn replay_from_file(&self, path: &Path, session_id: &str) -> anyhow::Result<TurnOutcome> {
+        let events = read_recording(path)?;
+        let server = Arc::new(Mutex::new(OpenCodeServer::offline(self.directory.clone())));
+        let mut queue = PromptQueue::default();
+
+        self.sessions.register_root(session_id, self.channel_id.clone());
+        let end = self
+            .process_events(
+                futures::stream::iter(events.into_iter().map(Ok)).boxed(),
+                session_id,
+                &server,
+                None,
+                &mut queue,
+                &CancellationToken::new(),
+                &mut None,
+            )
+            .await;
+        self.sessions.remove_tree(session_id);
+
+        match end? {
+            TurnEnd::Completed(outcome) => Ok(outcome),
+            TurnEnd::ContextOverflow(message) => {
+                bail!("OpenCode session error: context_length_exceeded: {message}")
+            }
+            // Interruptions come from follow-ups, and a replay has no input
+            // or registered turn.
+            TurnEnd::Interrupted { .. } | TurnEnd::Cancelled { .. } => bail!("replay was interrupted"),
+        }
+    }
+
+    /// Run without OpenCode: build each prompt, log it, and echo it back in
+    /// place of a model reply. No server is started and nothing is POSTed.
+    async fn run_dry(mut self) -> anyhow::Result<OpenCodeWorkerResult> {
+        self.send_status("dry run: prompts are not sent to OpenCode");
+        let files = std::mem::take(&mut self.files);
+        let outcome = self.dry_run_turn(&self.task, &files).await?;
+
+        if let Some(mut input_rx) = self.input_rx.take() {
+            self.send_status("waiting for follow-up");
+            while let Some(follow_up) = input_rx.recv().await {
+                self.dry_run_turn(&follow_up.text, &[]).await?;
+                self.send_status("waiting for follow-up");
+            }
+        }
+
+        self.send_status("completed");
+        Ok(OpenCodeWorkerResult {
+            session_id: String::new(),
+            result_text: outcome.text.clone(),
+            outcome,
+        })
+    }
+
+    async fn dry_run_turn(&self, text: &str, files: &[PartInput]) -> anyhow::Result<TurnOutcome> {
+        let mut request = self.build_prompt(text, idempotency_key());
+        // Inline file data would swamp the echo; show its size instead.
+        request.parts.extend(files.iter().cloned().map(|part| match part {
+            PartInput::File { mime, url, filename } if url.starts_with("data:") => PartInput::File {
+                url: format!("data:{mime};base64,<{} chars elided>", url.len()),
+                mime,
+                filename,
+            },
+            part => part,
+        }));
+        let request = serde_json::to_string_pretty(&request).context("failed to serialize prompt")?;
+        tracing::info!(worker_id = %self.id, %request, "dry run, prompt not sent");
+
+        let text = format!("[dry run] OpenCode prompt:\n```json\n{request}\n```");
+        if let Some(response_tx) = &self.reply_stream {
+            if response_tx.send(OutboundResponse::Text(text.clone())).await.is_err() {
+                tracing::debug!(worker_id = %self.id, "reply stream closed");
+            }
+        }
+
+        let outcome = TurnOutcome {
+            text,
+            finish_reason: None,
+            tool_errored: false,
+            tokens: None,
+            model: None,
+            duration_ms: None,
+        };
+        self.emit_turn_outcome(&outcome);
+        Ok(outcome)
+    }
+
+    /// Compact the channel's history, move the worker onto a fresh session,
+    /// and build a prompt for `text` carrying the new summary.
+    ///
+    /// Returns `None` without doing anything if auto-compaction isn't set up.
+    async fn compact_and_reopen(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        session_id: &mut String,
+        text: &str,
+    ) -> anyhow::Result<Option<SendPromptRequest>> {
+        let (Some(auto_compaction), Some(logger), Some(channel_id)) =
+            (&self.auto_compaction, &self.conversation_logger, &self.channel_id)
+        else {
+            return Ok(None);
+        };
+
+        tracing::info!(worker_id = %self.id, session_id = %session_id, "context length exceeded, compacting");
+        self.send_status("context too long, compacting history");
+        let notice = "⚠️ The conversation got too long for the model's context, so I summarized the earlier history and am retrying.";
+        // Recorded so the channel's history matches what was posted, but kept
+        // out of the transcript, exports, and the next compaction window.
+        logger.log_synthetic_message(channel_id, "assistant", notice);
+        if auto_compaction.notice_tx.send(OutboundResponse::Text(notice.into())).await.is_err() {
+            tracing::debug!(worker_id = %self.id, "compaction notice channel closed");
+        }
+
+        // Through a handle, so the pool's lock isn't held while the model
+        // writes the summary.
+        let handle = server.lock().await.handle();
+        let compaction = compact_channel(
+            &handle,
+            logger,
+            channel_id,
+            &auto_compaction.archives_dir,
+            &auto_compaction.summary_limits,
+        )
+        .await?;
+        let session = {
+            let guard = server.lock().await;
+            guard.create_session(Some(format!("spacebot-worker-{}", self.id))).await?
+        };
+        tracing::info!(
+            worker_id = %self.id,
+            turns_covered = compaction.turns_covered,
+            archive = %compaction.archive_path.display(),
+            new_session_id = %session.id,
+            "compacted channel history, retrying in a new session"
+        );
+
+        if let Some(store) = &self.channel_store {
+            if let Err(error) = store.set_active_session(channel_id, &session.id).await {
+                tracing::warn!(%error, worker_id = %self.id, "failed to record compacted session");
+            }
+        }
+
+        self.sessions.remove_tree(session_id);
+        *session_id = session.id;
+        self.sessions.register_root(session_id.clone(), self.channel_id.clone());
+        self.send_status("retrying with compacted history");
+
+        let mut builder = TurnPromptBuilder::new(text)
+            .summary(compaction.summary)
+            .preferences(self.preferences.clone());
+        if let Some(context) = self.channel_context() {
+            builder = builder.context(context);
+        }
+        Ok(Some(builder.build()))
+    }
+
+    /// Subscribe to SSE events, then send `request` as an async prompt.
+    /// Subscribing first means we can't miss events from a fast reply.
+    ///
+    /// With a prompt ledger, a request it has already seen (by key, or by
+    /// content within the dedup window) is not sent again and fails instead.
+    async fn send_prompt(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        session_id: &str,
+        request: &SendPromptRequest,
+    ) -> anyhow::Result<BoxStream<'static, anyhow::Result<SseEvent>>> {
+        let event_response = {
+            let guard = server.lock().await;
+            guard.subscribe_events().await?
+        };
+
+        let claimed = self.claim_prompt(session_id, request).await?;
+        let sent = {
+            let guard = server.lock().await;
+            guard.send_prompt_async(session_id, request).await
+        };
+        if let Err(error) = sent {
+            // Only a transport error leaves us unsure whether OpenCode got
+            // the prompt. Anything else was refused, so it may go again.
+            let unsure = error.chain().any(|cause| cause.is::<reqwest::Error>());
+            if let (Some(ledger), Some(key), false) = (&self.prompt_ledger, claimed, unsure) {
+                if let Err(error) = ledger.release(key).await {
+                    tracing::warn!(worker_id = %self.id, %error, "failed to release prompt idempotency key");
+                }
+            }
+            return Err(error);
+        }
+        metrics::global().record_prompt_sent(self.channel_id.as_deref());
+        tracing::info!(session_id, parts = request.parts.len(), "prompt sent");
+
+        let recorder = match (&self.sse_recorder, &self.channel_id) {
+            (Some(recorder), Some(channel_id)) if recorder.is_enabled() => {
+                Some((recorder.clone(), channel_id.clone()))
+            }
+            _ => None,
+        };
+        Ok(sse_events(event_response, recorder))
+    }
+
+    /// Record `request` in the prompt ledger before it's sent. Returns the
+    /// claimed key, or an error if the prompt was already sent.
+    async fn claim_prompt<'a>(&self, session_id: &str, request: &'a SendPromptRequest) -> anyhow::Result<Option<&'a str>> {
+        let (Some(ledger), Some(key)) = (&self.prompt_ledger, request.idempotency_key.as_deref()) else {
+            return Ok(None);
+        };
+        // Synthetic parts (the channel context) change with the clock, so
+        // only the user's text counts as the prompt's content.
+        let text: Vec<&str> = request
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                PartInput::Text { text, synthetic: None | Some(false) } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        match ledger.claim(key, self.channel_id.as_ref(), session_id, &text.join("\n")).await {
+            Ok(Claim::New) => Ok(Some(key)),
+            Ok(claim) => {
+                tracing::warn!(worker_id = %self.id, session_id, idempotency_key = key, ?claim, "skipping duplicate prompt");
+                bail!("this prompt was already sent to the OpenCode session; not sending it again")
+            }
+            Err(error) => {
+                // Better a possible duplicate than a dropped prompt.
+                tracing::warn!(worker_id = %self.id, %error, "failed to check prompt ledger, sending anyway");
+                Ok(None)
+            }
+        }
+    }
+
+    /// Write the channel's recorded SSE envelopes to the `sse_debug` table.
+    async fn persist_sse_debug(&self) {
+        let (Some(recorder), Some(pool), Some(channel_id)) =
+            (&self.sse_recorder, &self.sse_debug_pool, &self.channel_id)
+        else {
+            return;
+        };
+        match recorder.persist(pool, channel_id).await {
+            Ok(count) => tracing::debug!(worker_id = %self.id, count, "persisted SSE debug envelopes"),
+            Err(error) => tracing::warn!(worker_id = %self.id, %error, "failed to persist SSE debug envelopes"),
+        }
+    }
+
+    /// Abort the running prompt and persist whatever the assistant had
+    /// written so far, flagged as interrupted, unless its checkpoint already
+    /// was (`persisted`). `reason` is posted as the worker's status.
+    async fn interrupt_turn(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        session_id: &str,
+        partial_text: &str,
+        persisted: bool,
+        reason: &str,
+    ) {
+        tracing::info!(worker_id = %self.id, session_id, reason, "aborting prompt mid-turn");
+        self.send_status(reason);
+
+        {
+            let guard = server.lock().await;
+            if let Err(error) = guard.abort_session(session_id).await {
+                tracing::warn!(worker_id = %self.id, %error, "failed to abort OpenCode prompt");
+            }
+        }
+
+        if partial_text.is_empty() || persisted {
+            return;
+        }
+        if let (Some(logger), Some(channel_id)) = (&self.conversation_logger, &self.channel_id) {
+            logger.log_interrupted_bot_message(channel_id, partial_text);
+        }
+    }
+
+    /// Process SSE events from the OpenCode event stream until the session
+    /// goes idle or encounters an error.
+    ///
+    /// `input_rx` is read mid-turn in two cases: in `FollowUpMode::Abort`,
+    /// where a message ends the turn early, and while `Ask`-mode permission
+    /// requests await a reply. Messages that are neither a permission reply
+    /// nor an interruption are pushed onto `queue`. Cancelling `cancel`
+    /// ends the turn early too.
+    ///
+    /// The streamed reply is written to `checkpoint` as it grows. A turn that
+    /// completes finalizes it and leaves `None`; any other ending leaves it
+    /// for the caller to finalize as interrupted.
+    #[allow(clippy::too_many_arguments)]
+    async fn process_events(
+        &self,
+        mut events: BoxStream<'static, anyhow::Result<SseEvent>>,
+        session_id: &str,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        mut input_rx: Option<&mut mpsc::Receiver<WorkerInput>>,
+        queue: &mut PromptQueue,
+        cancel: &CancellationToken,
+        checkpoint: &mut Option<ReplyCheckpoint>,
+    ) -> anyhow::Result<TurnEnd> {
+        let interruptible = self.follow_up_mode == FollowUpMode::Abort;
+        // Asking needs somewhere for replies to come from.
+        let ask = self.permission_mode == PermissionMode::Ask && input_rx.is_some();
+        let mut permissions = PermissionBatcher::new(self.permission_batch_window)
+            .with_timeouts(self.permission_timeouts.clone());
+        let mut questions = PendingQuestions::new(self.question_timeout);
+        let mut streamer = self.reply_stream.as_ref().map(|_| {
+            StreamCoordinator::new(2000, Duration::from_millis(750), 200)
+        });
+        // Assistant messages in this session. The prompt comes back as a text
+        // part too, so only their text is streamed or counts as the first token.
+        let mut assistant_messages = HashSet::new();
+        // Text and tool parts of those messages, in arrival order, for the
+        // finalized reply.
+        let mut reply_parts: Vec<Part> = Vec::new();
+        // Ordinal and revision of every part recorded to `message_parts`.
+        let mut part_positions = PartPositions::default();
+        let mut first_token_seen = false;
+        let started = Instant::now();
+
+        let mut last_text = String::new();
+        let mut tool_calls = ToolCallTracker::default();
+        // Guards: don't treat session.idle as completion until we've seen real work
+        let mut has_received_event = false;
+        let mut has_assistant_message = false;
+        // Assistant messages already counted toward the latency histogram.
+        // OpenCode re-sends message.updated after completion (token counts etc).
+        let mut timed_messages = HashSet::new();
+        let mut stats = TurnStats::default();
+        let mut indicator = TypingIndicator::new(self.indicator_debounce);
+        let mut retries = RetryTracker::new(self.retry_warning_attempts);
+
+        loop {
+            let waiting_on_human = permissions.has_awaiting() || questions.has_pending();
+            let listening = interruptible || waiting_on_human;
+            let event = tokio::select! {
+                event = events.next() => event,
+                Some(message) = next_input(&mut input_rx), if listening => {
+                    if permissions.has_awaiting() {
+                        if let Some(replies) = permissions.apply_reply(&message.text) {
+                            self.send_permission_replies(server, replies).await;
+                            continue;
+                        }
+                    }
+                    if let Some((question, answers)) = questions.apply_reply(&message.text) {
+                        self.answer_question(server, &question, answers).await;
+                        self.send_status("working");
+                        continue;
+                    }
+                    if interruptible {
+                        self.finish_reply_stream(&mut streamer).await;
+                        return Ok(TurnEnd::Interrupted {
+                            partial_text: last_text,
+                            next_message: message,
+                        });
+                    }
+                    queue.push(message);
+                    continue;
+                }
+                _ = cancel.cancelled() => {
+                    self.finish_reply_stream(&mut streamer).await;
+                    return Ok(TurnEnd::Cancelled { partial_text: last_text });
+                }
+                _ = sleep_until(streamer.as_ref().and_then(StreamCoordinator::edit_deadline)) => {
+                    if let Some(streamer) = &mut streamer {
+                        let responses = streamer.flush(Instant::now());
+                        self.send_reply_stream(responses).await;
+                    }
+                    continue;
+                }
+                _ = sleep_until(permissions.deadline()) => {
+                    self.ask_permissions(permissions.flush());
+                    continue;
+                }
+                _ = sleep_until(permissions.next_expiry()) => {
+                    let expired = permissions.take_expired(Instant::now());
+                    self.reject_expired_permissions(server, expired).await;
+                    continue;
+                }
+                _ = sleep_until(questions.next_expiry()) => {
+                    for question in questions.take_expired(Instant::now()) {
+                        let answers = self.question_default.answers(&question.questions);
+                        let labels: Vec<&str> = answers.iter().map(|a| a.label.as_str()).collect();
+                        self.send_status(&format!(
+                            "no answer to question, using default: {}",
+                            labels.join(", ")
+                        ));
+                        self.answer_question(server, &question, answers).await;
+                    }
+                    continue;
+                }
+                // Waiting on a human isn't inactivity.
+                _ = tokio::time::sleep(Duration::from_secs(600)), if !waiting_on_human => {
+                    bail!("OpenCode session timed out after 10 minutes of inactivity");
+                }
+            };
+
+            let Some(event) = event else {
+                self.finish_reply_stream(&mut streamer).await;
+                // Stream ended -- if we have results, return them
+                if has_assistant_message && !last_text.is_empty() {
+                    return Ok(TurnEnd::Completed(stats.into_outcome(last_text)));
+                }
+                bail!("OpenCode event stream ended before session completed");
+            };
+
+            let event = event?;
+            if self.sessions.observe(&event) {
+                tracing::debug!(worker_id = %self.id, "OpenCode sub-agent session started");
+            }
+            stats.observe(&event, session_id, &self.sessions);
+            if let (Some(pending), Some(channel_id)) = (&self.pending, &self.channel_id) {
+                pending.observe(channel_id, session_id, &event);
+            }
+            if let Some(state) = indicator.observe(&event, session_id, Instant::now()) {
+                self.send_indicator(state);
+            }
+            if let Some(update) = retries.observe(&event, session_id) {
+                self.report_retry(&update);
+            }
+            let metrics = metrics::global();
+            // The stream carries every session on the server; only count
+            // this turn's session and its sub-agents.
+            if event
+                .session_id()
+                .is_some_and(|id| id == session_id || self.sessions.is_descendant_of(id, session_id))
+            {
+                metrics.record_event(&event, self.channel_id.as_deref());
+            }
+            if let SseEvent::MessageUpdated { info: Some(info) } = &event {
+                if info.session_id.as_deref() == Some(session_id)
+                    && !timed_messages.contains(&info.id)
+                    && metrics.observe_assistant_message(info, self.channel_id.as_deref())
+                {
+                    timed_messages.insert(info.id.clone());
+                }
+            }
+
+            match &event {
+                SseEvent::MessageUpdated { info: Some(info) }
+                    if info.role == "assistant" && info.session_id.as_deref() == Some(session_id) =>
+                {
+                    assistant_messages.insert(info.id.clone());
+                }
+                SseEvent::MessagePartUpdated {
+                    part: Part::Text { session_id: Some(part_session), message_id: Some(message_id), .. },
+                    ..
+                } if !first_token_seen && part_session == session_id && assistant_messages.contains(message_id) => {
+                    first_token_seen = true;
+                    tracing::info!(elapsed_ms = started.elapsed().as_millis() as u64, "first token");
+                }
+                _ => {}
+            }
+            if let SseEvent::MessagePartUpdated { part, .. } = &event {
+                let ids = match part {
+                    Part::Text { id, message_id: Some(message_id), .. }
+                    | Part::Tool { id, message_id: Some(message_id), .. } => Some((id, message_id)),
+                    _ => None,
+                };
+                if let Some((id, message_id)) = ids {
+                    if part.session_id() == Some(session_id) && assistant_messages.contains(message_id) {
+                        let existing = reply_parts.iter_mut().find(|seen| match seen {
+                            Part::Text { id: seen_id, .. } | Part::Tool { id: seen_id, .. } => seen_id == id,
+                            _ => false,
+                        });
+                        match existing {
+                            Some(seen) => *seen = part.clone(),
+                            None => reply_parts.push(part.clone()),
+                        }
+                        if let Some(checkpoint) = checkpoint.as_mut() {
+                            self.checkpoint_reply(checkpoint, &reply_parts).await;
+                        }
+                    }
+                }
+                self.log_message_part(part, session_id, &assistant_messages, &mut part_positions);
+            }
+
+            // The event handler goes first, and can take over permissions
+            // and questions.
+            let context = self.event_context(session_id);
+            match &event {
+                SseEvent::MessagePartUpdated { part, delta } if part.session_id() == Some(session_id) => {
+                    self.event_handler.on_message_part(&context, part, delta.as_deref()).await;
+                }
+                SseEvent::PermissionAsked(permission) if permission.session_id == session_id => {
+                    if let Some(reply) = self.event_handler.on_permission(&context, permission).await {
+                        has_received_event = true;
+                        self.reply_from_handler(server, permission, reply).await;
+                        continue;
+                    }
+                }
+                SseEvent::QuestionAsked(question) if question.session_id == session_id => {
+                    if let Some(answers) = self.event_handler.on_question(&context, question).await {
+                        has_received_event = true;
+                        tracing::info!(worker_id = %self.id, question_id = %question.id, "question answered by event handler");
+                        self.answer_question(server, question, answers).await;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+
+            if let Some(streamer) = &mut streamer {
+                match &event {
+                    SseEvent::MessagePartUpdated {
+                        part: Part::Text { id, session_id: Some(part_session), message_id: Some(message_id), text, .. },
+                        delta,
+                    } if part_session == session_id && assistant_messages.contains(message_id) => {
+                        let responses = streamer.push(id, text, delta.as_deref(), Instant::now());
+                        self.send_reply_stream(responses).await;
+                    }
+                    SseEvent::PartRemoved { part_id, .. } => streamer.remove(part_id),
+                    _ => {}
+                }
+            }
+
+            if ask {
+                match &event {
+                    SseEvent::PermissionAsked(permission) if permission.session_id == session_id => {
+                        has_received_event = true;
+                        if !self.reply_from_grants(server, permission).await {
+                            permissions.push(permission.clone());
+                        }
+                        continue;
+                    }
+                    SseEvent::PermissionReplied { request_id, .. } => {
+                        permissions.remove(request_id);
+                    }
+                    SseEvent::QuestionAsked(question) if question.session_id == session_id => {
+                        has_received_event = true;
+                        self.announce_question(question, true);
+                        self.send_status("waiting for an answer");
+                        questions.push(question.clone());
+                        continue;
+                    }
+                    SseEvent::QuestionReplied { session_id: replied_session, request_id, answers } => {
+                        questions.remove(request_id);
+                        if replied_session == session_id && !answers.is_empty() {
+                            self.emit_question_answered(request_id, answers);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
+            match self.handle_sse_event(
+                &event,
+                session_id,
+                server,
+                &mut last_text,
+                &mut tool_calls,
+                &mut has_received_event,
+                &mut has_assistant_message,
+            ).await {
+                EventAction::Continue => {}
+                EventAction::Complete => {
+                    tracing::info!(elapsed_ms = started.elapsed().as_millis() as u64, "session idle");
+                    self.finish_reply_stream(&mut streamer).await;
+                    self.log_final_message(&reply_parts, checkpoint.take()).await;
+                    let outcome = stats.into_outcome(last_text);
+                    self.event_handler.on_session_idle(&context, &outcome).await;
+                    return Ok(TurnEnd::Completed(outcome));
+                }
+                EventAction::Error { kind, message } => {
+                    self.finish_reply_stream(&mut streamer).await;
+                    if kind == SessionErrorKind::ContextLengthExceeded {
+                        return Ok(TurnEnd::ContextOverflow(message));
+                    }
+                    bail!("OpenCode session error: {kind}: {message}");
+                }
+            }
+        }
+    }
+
+    /// Forward streamed reply updates to the channel.
+    async fn send_reply_stream(&self, responses: Vec<OutboundResponse>) {
+        let Some(response_tx) = &self.reply_stream else {
+            return;
+        };
+        for response in responses {
+            if response_tx.send(response).await.is_err() {
+                tracing::debug!(worker_id = %self.id, "reply stream closed");
+                return;
+            }
+        }
+    }
+
+    /// Persist a streamed reply once its turn completes, rendered without tool
+    /// noise. Only replies this worker streamed itself are logged here; a
+    /// channel that relays the worker's result logs its own reply. A reply
+    /// already checkpointed is finalized in place.
+    async fn log_final_message(&self, parts: &[Part], checkpoint: Option<ReplyCheckpoint>) {
+        if self.reply_stream.is_none() || self.dry_run {
+            return;
+        }
+        let (Some(logger), Some(channel_id)) = (&self.conversation_logger, &self.channel_id) else {
+            return;
+        };
+        let content = render_final_message(parts);
+        if let Some(mut checkpoint) = checkpoint.filter(ReplyCheckpoint::is_written) {
+            checkpoint.content = content;
+            self.finalize_checkpoint(Some(checkpoint), false).await;
+        } else if !content.is_empty() {
+            logger.log_bot_message(channel_id, &content);
+        }
+    }
+
+    /// A checkpoint for this turn's streamed reply, if replies are logged
+    /// and checkpoints are on.
+    fn reply_checkpoint(&self) -> Option<ReplyCheckpoint> {
+        let enabled = self.reply_stream.is_some()
+            && !self.dry_run
+            && !self.reply_checkpoint_interval.is_zero()
+            && self.conversation_logger.is_some()
+            && self.channel_id.is_some();
+        enabled.then(|| ReplyCheckpoint::new(self.reply_checkpoint_interval))
+    }
+
+    /// Note the reply's latest content, and write it if a checkpoint is due.
+    async fn checkpoint_reply(&self, checkpoint: &mut ReplyCheckpoint, parts: &[Part]) {
+        let (Some(logger), Some(channel_id)) = (&self.conversation_logger, &self.channel_id) else {
+            return;
+        };
+        let Some(content) = checkpoint.update(render_final_message(parts), Instant::now()).map(str::to_string) else {
+            return;
+        };
+        if let Err(error) = logger.checkpoint_bot_message(channel_id, &checkpoint.id, &content).await {
+            tracing::warn!(worker_id = %self.id, %error, "failed to checkpoint streamed reply");
+        }
+    }
+
+    /// Write a checkpointed reply's last content and mark it finished.
+    /// Returns whether there was a written reply to finalize.
+    async fn finalize_checkpoint(&self, checkpoint: Option<ReplyCheckpoint>, interrupted: bool) -> bool {
+        let (Some(checkpoint), Some(logger), Some(channel_id)) =
+            (checkpoint.filter(ReplyCheckpoint::is_written), &self.conversation_logger, &self.channel_id)
+        else {
+            return false;
+        };
+        if let Err(error) = logger
+            .finalize_bot_message(channel_id, &checkpoint.id, &checkpoint.content, interrupted)
+            .await
+        {
+            tracing::warn!(worker_id = %self.id, %error, "failed to finalize streamed reply");
+        }
+        true
+    }
+
+    /// Flush pending text and finalize the streamed message for this turn.
+    async fn finish_reply_stream(&self, streamer: &mut Option<StreamCoordinator>) {
+        if let Some(streamer) = streamer {
+            let responses = streamer.finish();
+            self.send_reply_stream(responses).await;
+        }
+    }
+
+    /// Handle a single SSE event. Returns whether to continue, complete, or error.
+    async fn handle_sse_event(
+        &self,
+        event: &SseEvent,
+        session_id: &str,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        last_text: &mut String,
+        tool_calls: &mut ToolCallTracker,
+        has_received_event: &mut bool,
+        has_assistant_message: &mut bool,
+    ) -> EventAction {
+        match event {
+            SseEvent::MessageUpdated { info } => {
+                *has_received_event = true;
+                // Track assistant messages for idle guard
+                if let Some(msg) = info {
+                    if msg.role == "assistant" {
+                        if let Some(sid) = &msg.session_id {
+                            if sid == session_id {
+                                *has_assistant_message = true;
+                            }
+                        }
+                    }
+                }
+                EventAction::Continue
+            }
+
+            SseEvent::MessagePartUpdated { part, .. } => {
+                *has_received_event = true;
+                match part {
+                    Part::Text { text, session_id: part_session, .. } => {
+                        if let Some(sid) = part_session {
+                            if sid != session_id {
+                                return EventAction::Continue;
+                            }
+                        }
+                        *has_assistant_message = true;
+                        *last_text = text.clone();
+                    }
+                    Part::Tool { tool, state, session_id: part_session, call_id, .. } => {
+                        self.log_tool_invocation(
+                            part_session.as_deref(),
+                            session_id,
+                            call_id.as_deref(),
+                            tool.as_deref(),
+                            state.as_ref(),
+                        );
+                        if let Some(sid) = part_session {
+                            if sid != session_id {
+                                // Sub-agent tool activity shows nested under the main turn.
+                                if self.sessions.is_descendant_of(sid, session_id) {
+                                    if let (Some(tool_name), Some(ToolState::Running { title, .. })) = (tool, state) {
+                                        let label = title.as_deref().unwrap_or(tool_name.as_str());
+                                        self.send_status(&format!("↳ running: {label}"));
+                                    }
+                                }
+                                return EventAction::Continue;
+                            }
+                        }
+                        *has_assistant_message = true;
+                        if let Some(tool_name) = tool {
+                            if let Some(tool_state) = state {
+                                match tool_state {
+                                    ToolState::Running { title, .. } => {
+                                        if tool_calls.running(tool_name) {
+                                            tracing::info!(tool = %tool_name, call_id = ?call_id, "tool call started");
+                                        }
+                                        let label = title.as_deref().unwrap_or(tool_name.as_str());
+                                        self.send_status(&format!("running: {label}"));
+                                    }
+                                    ToolState::Completed { .. } => {
+                                        let duration_ms = tool_state.duration_ms();
+                                        tracing::info!(tool = %tool_name, call_id = ?call_id, ?duration_ms, "tool call completed");
+                                        tool_calls.finished(tool_name);
+                                        match duration_ms {
+                                            Some(ms) => self.send_status(&format!(
+                                                "working ({tool_name} took {})",
+                                                format_duration_ms(ms)
+                                            )),
+                                            None => self.send_status("working"),
+                                        }
+                                    }
+                                    ToolState::Error { error, .. } => {
+                                        let description = error.as_deref().unwrap_or("unknown");
+                                        tool_calls.finished(tool_name);
+                                        if tool_calls.failed(call_id.as_deref()) {
+                                            let duration_ms = tool_state.duration_ms();
+                                            tracing::info!(tool = %tool_name, call_id = ?call_id, error = description, ?duration_ms, "tool call failed");
+                                            self.emit_tool_error(tool_name, call_id.as_deref(), description);
+                                        }
+                                        self.send_status(&format!("tool error: {tool_name}: {description}"));
+                                    }
+                                    ToolState::Pending { .. } => {
+                                        // Tool queued, no status update needed
+                                    }
+                                    ToolState::Unknown { .. } => {
+                                        // A status we don't model; logged when parsed
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+                EventAction::Continue
+            }
+
+            SseEvent::SessionIdle { session_id: event_session_id } => {
+                if event_session_id != session_id {
+                    // Only our own session going idle ends the turn. A
+                    // sub-agent finishing is routine; an unknown session is
+                    // a stale event from before a reset, or someone else's.
+                    match self.sessions.route(event_session_id) {
+                        SessionRoute::Orphan => tracing::debug!(
+                            worker_id = %self.id,
+                            session_id = %event_session_id,
+                            "ignoring session.idle for unknown session"
+                        ),
+                        SessionRoute::Child { .. } => tracing::trace!(
+                            worker_id = %self.id,
+                            session_id = %event_session_id,
+                            "sub-agent session idle"
+                        ),
+                        SessionRoute::Root { .. } => {}
+                    }
+                    return EventAction::Continue;
+                }
+
+                // Guard: don't complete until we've seen actual work.
+                // OpenCode can send an early idle event before the prompt is processed.
+                if !*has_received_event || !*has_assistant_message {
+                    tracing::trace!(
+                        worker_id = %self.id,
+                        has_received_event,
+                        has_assistant_message,
+                        "ignoring early session.idle"
+                    );
+                    return EventAction::Continue;
+                }
 
                 EventAction::Complete
             }
@@ -364,12 +1651,16 @@ impl OpenCodeWorker {
                 if event_session_id.as_deref() != Some(session_id) {
                     return EventAction::Continue;
                 }
+                let kind = error.as_ref().map_or(SessionErrorKind::Unknown, classify_session_error);
+                if let Some(notifier) = &self.error_notifier {
+                    notifier.notify(self.channel_id.as_deref(), Some(session_id), kind);
+                }
                 let message = error
                     .as_ref()
-                    .and_then(|e| e.get("message").and_then(|v| v.as_str()))
+                    .and_then(session_error_message)
                     .unwrap_or("unknown error")
                     .to_string();
-                EventAction::Error(message)
+                EventAction::Error { kind, message }
             }
 
             SseEvent::PermissionAsked(permission) => {
@@ -398,96 +1689,375 @@ impl OpenCodeWorker {
                     patterns: permission.patterns.clone(),
                 });
 
-                // Auto-allow (OPENCODE_CONFIG_CONTENT should prevent most prompts)
-                let guard = server.lock().await;
-                if let Err(error) = guard.reply_permission(&permission.id, PermissionReply::Once).await {
+                // Auto-allow (OPENCODE_CONFIG_CONTENT should prevent most prompts)
+                self.audit_permission(permission, &PermissionReply::Once, ReplySource::Auto);
+                let guard = server.lock().await;
+                if let Err(error) = guard.reply_permission(&permission.id, PermissionReply::Once).await {
+                    tracing::warn!(
+                        worker_id = %self.id,
+                        permission_id = %permission.id,
+                        %error,
+                        "failed to auto-reply permission"
+                    );
+                }
+
+                EventAction::Continue
+            }
+
+            SseEvent::QuestionAsked(question) => {
+                if question.session_id != session_id {
+                    return EventAction::Continue;
+                }
+
+                self.announce_question(question, false);
+
+                // Auto-answer with the configured defaults
+                let answers = self.question_default.answers(&question.questions);
+                self.answer_question(server, question, answers).await;
+
+                EventAction::Continue
+            }
+
+            SseEvent::SessionStatus { session_id: event_session_id, status } => {
+                if event_session_id != session_id {
+                    return EventAction::Continue;
+                }
+                match status {
+                    SessionStatusPayload::Busy => {
+                        self.send_status("working");
+                    }
+                    // Retries are reported by the turn's `RetryTracker`.
+                    SessionStatusPayload::Retry { .. } | SessionStatusPayload::Idle => {}
+                }
+                EventAction::Continue
+            }
+
+            SseEvent::ServerInfo { version: Some(version) } => {
+                if !self.server_pool.is_supported_version(version) {
+                    tracing::warn!(
+                        worker_id = %self.id,
+                        %version,
+                        "OpenCode was updated to an unsupported version"
+                    );
+                    self.send_status(&format!(
+                        "warning: OpenCode updated to unsupported version {version}"
+                    ));
+                }
+                EventAction::Continue
+            }
+
+            _ => EventAction::Continue,
+        }
+    }
+
+    /// Post a batch of permission requests to the channel.
+    fn ask_permissions(&self, prompts: Vec<PermissionPrompt>) {
+        tracing::info!(
+            worker_id = %self.id,
+            count = prompts.len(),
+            "OpenCode requesting permissions"
+        );
+        self.send_status(&format!("waiting for permission ({} pending)", prompts.len()));
+        let _ = self.event_tx.send(ProcessEvent::WorkerPermissionBatch {
+            agent_id: self.agent_id.clone(),
+            worker_id: self.id,
+            channel_id: self.channel_id.clone(),
+            permissions: prompts,
+        });
+    }
+
+    /// Send one reply per permission request. `Always` and `Reject` replies
+    /// are also remembered as channel grants.
+    async fn send_permission_replies(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        replies: Vec<(PermissionRequest, PermissionReply)>,
+    ) {
+        let mut remembered = Vec::new();
+        let guard = server.lock().await;
+        for (request, reply) in replies {
+            self.audit_permission(&request, &reply, ReplySource::User);
+            let decision = GrantDecision::from_reply(&reply);
+            if let Err(error) = guard.reply_permission(&request.id, reply).await {
+                tracing::warn!(
+                    worker_id = %self.id,
+                    permission_id = %request.id,
+                    %error,
+                    "failed to reply to permission"
+                );
+            } else if let Some(decision) = decision {
+                remembered.push((request, decision));
+            }
+        }
+        drop(guard);
+
+        if let (Some(grants), Some(channel_id)) = (&self.permission_grants, &self.channel_id) {
+            for (request, decision) in &remembered {
+                if let Err(error) = grants.remember(channel_id, request, *decision).await {
                     tracing::warn!(
                         worker_id = %self.id,
-                        permission_id = %permission.id,
+                        permission_id = %request.id,
                         %error,
-                        "failed to auto-reply permission"
+                        "failed to store permission grant"
                     );
                 }
+            }
+        }
+        self.send_status("working");
+    }
 
-                EventAction::Continue
+    /// Answer `request` with the channel's remembered choice for it, if any.
+    /// Returns `false` if it still needs asking.
+    async fn reply_from_grants(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        request: &PermissionRequest,
+    ) -> bool {
+        let (Some(grants), Some(channel_id)) = (&self.permission_grants, &self.channel_id) else {
+            return false;
+        };
+        let decision = match grants.decide(channel_id, request).await {
+            Ok(Some(decision)) => decision,
+            Ok(None) => return false,
+            Err(error) => {
+                tracing::warn!(worker_id = %self.id, %error, "failed to check permission grants");
+                return false;
             }
+        };
 
-            SseEvent::QuestionAsked(question) => {
-                if question.session_id != session_id {
-                    return EventAction::Continue;
-                }
+        tracing::info!(
+            worker_id = %self.id,
+            permission_id = %request.id,
+            permission = ?request.permission,
+            decision = decision.as_str(),
+            "permission answered by a stored grant"
+        );
+        self.audit_permission(request, &decision.reply(), ReplySource::Grant);
+        let guard = server.lock().await;
+        if let Err(error) = guard.reply_permission(&request.id, decision.reply()).await {
+            tracing::warn!(
+                worker_id = %self.id,
+                permission_id = %request.id,
+                %error,
+                "failed to reply to granted permission"
+            );
+            return false;
+        }
+        drop(guard);
+        let verb = match decision {
+            GrantDecision::Always => "granted",
+            GrantDecision::Reject => "rejected",
+        };
+        self.send_status(&format!("{verb}: {}", PermissionPrompt::from(request).description));
+        true
+    }
 
-                tracing::info!(
-                    worker_id = %self.id,
-                    question_id = %question.id,
-                    question_count = question.questions.len(),
-                    "OpenCode asking question"
-                );
+    /// Send the event handler's reply to a permission request.
+    async fn reply_from_handler(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        request: &PermissionRequest,
+        reply: PermissionReply,
+    ) {
+        tracing::info!(
+            worker_id = %self.id,
+            permission_id = %request.id,
+            permission = ?request.permission,
+            ?reply,
+            "permission answered by event handler"
+        );
+        self.audit_permission(request, &reply, ReplySource::Handler);
+        let guard = server.lock().await;
+        if let Err(error) = guard.reply_permission(&request.id, reply).await {
+            tracing::warn!(
+                worker_id = %self.id,
+                permission_id = %request.id,
+                %error,
+                "failed to reply to permission"
+            );
+        }
+    }
 
-                let _ = self.event_tx.send(ProcessEvent::WorkerQuestion {
-                    agent_id: self.agent_id.clone(),
-                    worker_id: self.id,
-                    channel_id: self.channel_id.clone(),
-                    question_id: question.id.clone(),
-                    questions: question.questions.iter().map(|q| {
-                        QuestionInfo {
-                            question: q.question.clone(),
-                            header: q.header.clone(),
-                            options: q.options.clone(),
-                        }
-                    }).collect(),
-                });
+    fn event_context<'a>(&'a self, session_id: &'a str) -> EventContext<'a> {
+        EventContext {
+            worker_id: self.id,
+            channel_id: self.channel_id.as_ref(),
+            session_id,
+        }
+    }
 
-                // Auto-select first option
-                let answers: Vec<QuestionAnswer> = question.questions.iter().map(|q| {
-                    if let Some(first_option) = q.options.first() {
-                        QuestionAnswer {
-                            label: first_option.label.clone(),
-                            description: first_option.description.clone(),
-                        }
-                    } else {
-                        QuestionAnswer {
-                            label: "continue".to_string(),
-                            description: None,
-                        }
-                    }
-                }).collect();
+    /// Tell the channel about a question. `awaiting_reply` is set when the
+    /// worker waits for a routed answer rather than answering it itself.
+    fn announce_question(&self, question: &QuestionRequest, awaiting_reply: bool) {
+        tracing::info!(
+            worker_id = %self.id,
+            question_id = %question.id,
+            question_count = question.questions.len(),
+            awaiting_reply,
+            "OpenCode asking question"
+        );
 
-                let guard = server.lock().await;
-                if let Err(error) = guard.reply_question(&question.id, answers).await {
-                    tracing::warn!(
-                        worker_id = %self.id,
-                        question_id = %question.id,
-                        %error,
-                        "failed to auto-reply question"
-                    );
-                }
+        let _ = self.event_tx.send(ProcessEvent::WorkerQuestion {
+            agent_id: self.agent_id.clone(),
+            worker_id: self.id,
+            channel_id: self.channel_id.clone(),
+            question_id: question.id.clone(),
+            questions: question.questions.clone(),
+            awaiting_reply,
+        });
+    }
 
-                EventAction::Continue
-            }
+    fn emit_question_answered(&self, question_id: &str, answers: &[QuestionAnswer]) {
+        let _ = self.event_tx.send(ProcessEvent::WorkerQuestionAnswered {
+            agent_id: self.agent_id.clone(),
+            worker_id: self.id,
+            channel_id: self.channel_id.clone(),
+            question_id: question_id.to_string(),
+            answers: answers.to_vec(),
+        });
+    }
 
-            SseEvent::SessionStatus { session_id: event_session_id, status } => {
-                if event_session_id != session_id {
-                    return EventAction::Continue;
-                }
-                match status {
-                    SessionStatusPayload::Retry { attempt, message, .. } => {
-                        let description = message.as_deref().unwrap_or("rate limited");
-                        self.send_status(&format!("retry attempt {attempt}: {description}"));
-                    }
-                    SessionStatusPayload::Busy => {
-                        self.send_status("working");
-                    }
-                    SessionStatusPayload::Idle => {}
-                }
-                EventAction::Continue
+    async fn answer_question(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        question: &QuestionRequest,
+        answers: Vec<QuestionAnswer>,
+    ) {
+        let guard = server.lock().await;
+        if let Err(error) = guard.reply_question(&question.id, answers).await {
+            tracing::warn!(
+                worker_id = %self.id,
+                question_id = %question.id,
+                %error,
+                "failed to reply to question"
+            );
+        }
+    }
+
+    /// Reject permission requests nobody answered in time.
+    async fn reject_expired_permissions(
+        &self,
+        server: &Arc<Mutex<crate::opencode::server::OpenCodeServer>>,
+        expired: Vec<PermissionRequest>,
+    ) {
+        let guard = server.lock().await;
+        for request in &expired {
+            tracing::info!(
+                worker_id = %self.id,
+                permission_id = %request.id,
+                permission = ?request.permission,
+                "permission request timed out, rejecting"
+            );
+            self.audit_permission(request, &PermissionReply::Reject, ReplySource::Timeout);
+            if let Err(error) = guard
+                .reply_permission_with_message(
+                    &request.id,
+                    PermissionReply::Reject,
+                    Some("timed out waiting for approval".to_string()),
+                )
+                .await
+            {
+                tracing::warn!(
+                    worker_id = %self.id,
+                    permission_id = %request.id,
+                    %error,
+                    "failed to reject timed-out permission"
+                );
+            }
+        }
+        drop(guard);
+        if let Some(request) = expired.first() {
+            let label = PermissionPrompt::from(request).description;
+            let more = expired.len() - 1;
+            if more == 0 {
+                self.send_status(&format!("permission timed out: {label}"));
+            } else {
+                self.send_status(&format!("permission timed out: {label} (+{more} more)"));
             }
+        }
+    }
 
-            _ => EventAction::Continue,
+    /// Persist a tool call from this worker's session tree. Calls from
+    /// unrelated sessions on the same server are ignored.
+    fn log_message_part(
+        &self,
+        part: &Part,
+        session_id: &str,
+        assistant_messages: &HashSet<String>,
+        positions: &mut PartPositions,
+    ) {
+        if !self.persist_message_parts || self.dry_run {
+            return;
+        }
+        let (Some(logger), Some(channel_id)) = (&self.conversation_logger, &self.channel_id) else {
+            return;
+        };
+        let (Some(part_id), Some(message_id)) = (part.id(), part.message_id()) else {
+            return;
+        };
+        if part.session_id() != Some(session_id) || !assistant_messages.contains(message_id) {
+            return;
+        }
+        let (ordinal, revision) = positions.next(message_id, part_id);
+        logger.log_message_part(channel_id, session_id, ordinal, revision, part);
+    }
+
+    fn log_tool_invocation(
+        &self,
+        part_session: Option<&str>,
+        session_id: &str,
+        call_id: Option<&str>,
+        tool: Option<&str>,
+        state: Option<&ToolState>,
+    ) {
+        let (Some(logger), Some(channel_id)) = (&self.conversation_logger, &self.channel_id) else {
+            return;
+        };
+        let (Some(call_id), Some(tool), Some(state)) = (call_id, tool, state) else {
+            return;
+        };
+        let part_session = part_session.unwrap_or(session_id);
+        if part_session != session_id && !self.sessions.is_descendant_of(part_session, session_id) {
+            return;
+        }
+        logger.log_tool_invocation(channel_id, part_session, call_id, tool, state);
+    }
+
+    fn audit_permission(&self, request: &PermissionRequest, reply: &PermissionReply, source: ReplySource) {
+        if let Some(audit) = &self.permission_audit {
+            audit.record(self.id, self.channel_id.as_ref(), request, reply, source);
         }
     }
 
     /// Send a status update via the process event bus.
+    fn report_retry(&self, update: &RetryUpdate) {
+        tracing::info!(
+            worker_id = %self.id,
+            attempt = update.attempt,
+            message = ?update.message,
+            "OpenCode retrying provider call"
+        );
+        self.send_status(&update.status());
+        if update.warn {
+            let _ = self.event_tx.send(ProcessEvent::WorkerRetryWarning {
+                agent_id: self.agent_id.clone(),
+                worker_id: self.id,
+                channel_id: self.channel_id.clone(),
+                attempt: update.attempt,
+                message: update.message.clone(),
+            });
+        }
+    }
+
+    fn send_indicator(&self, state: IndicatorState) {
+        let _ = self.event_tx.send(ProcessEvent::WorkerIndicator {
+            agent_id: self.agent_id.clone(),
+            worker_id: self.id,
+            channel_id: self.channel_id.clone(),
+            state,
+        });
+    }
+
     fn send_status(&self, status: &str) {
         let _ = self.event_tx.send(ProcessEvent::WorkerStatus {
             agent_id: self.agent_id.clone(),
@@ -498,16 +2068,271 @@ impl OpenCodeWorker {
     }
 }
 
+/// How a single prompt's event loop ended.
+enum TurnEnd {
+    /// The session went idle.
+    Completed(TurnOutcome),
+    /// A newer message arrived before the prompt finished.
+    Interrupted {
+        partial_text: String,
+        next_message: WorkerInput,
+    },
+    /// The turn was cancelled through `ActiveTurns`.
+    Cancelled { partial_text: String },
+    /// The prompt failed with a context-length error. Carries the provider's message.
+    ContextOverflow(String),
+}
+
+/// Tool calls seen during one prompt.
+#[derive(Debug, Default)]
+struct ToolCallTracker {
+    /// The tool most recently seen running.
+    current: Option<String>,
+    /// Calls already reported as failed. OpenCode re-sends a part on every
+    /// update, so an errored call can arrive more than once.
+    failed: HashSet<String>,
+}
+
+impl ToolCallTracker {
+    /// Note `tool` running. Returns whether it just started.
+    fn running(&mut self, tool: &str) -> bool {
+        if self.current.as_deref() == Some(tool) {
+            return false;
+        }
+        self.current = Some(tool.to_string());
+        true
+    }
+
+    /// Note `tool` completed or errored.
+    fn finished(&mut self, tool: &str) {
+        if self.current.as_deref() == Some(tool) {
+            self.current = None;
+        }
+    }
+
+    /// Note a call errored. Returns whether this is the first time it's seen
+    /// failing. Calls without an ID can't be deduplicated and always count.
+    fn failed(&mut self, call_id: Option<&str>) -> bool {
+        call_id.is_none_or(|call_id| self.failed.insert(call_id.to_string()))
+    }
+}
+
+/// Where each part of a turn's messages sits, for `message_parts`.
+#[derive(Debug, Default)]
+struct PartPositions {
+    /// Part ID → (ordinal within its message, updates seen so far).
+    parts: HashMap<String, (i64, i64)>,
+    /// Message ID → parts seen in it.
+    counts: HashMap<String, i64>,
+}
+
+impl PartPositions {
+    /// The ordinal and revision for the next write of a part. A new part
+    /// goes after the ones already seen in its message.
+    fn next(&mut self, message_id: &str, part_id: &str) -> (i64, i64) {
+        if let Some((ordinal, revision)) = self.parts.get_mut(part_id) {
+            *revision += 1;
+            return (*ordinal, *revision);
+        }
+        let count = self.counts.entry(message_id.to_string()).or_default();
+        let ordinal = *count;
+        *count += 1;
+        self.parts.insert(part_id.to_string(), (ordinal, 0));
+        (ordinal, 0)
+    }
+}
+
+/// A streamed reply's row in history, written every `interval` while the
+/// reply grows.
+#[derive(Debug)]
+struct ReplyCheckpoint {
+    /// Message ID of the row.
+    id: String,
+    interval: Duration,
+    /// The reply as of the latest part, rendered for history.
+    content: String,
+    /// When the row was last written. `None` until the first write.
+    written_at: Option<Instant>,
+}
+
+impl ReplyCheckpoint {
+    fn new(interval: Duration) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            interval,
+            content: String::new(),
+            written_at: None,
+        }
+    }
+
+    /// Take the reply's latest `content`. Returns it if it should be written
+    /// now: the first non-empty content at once, later content once
+    /// `interval` has passed since the last write.
+    fn update(&mut self, content: String, now: Instant) -> Option<&str> {
+        if content == self.content {
+            return None;
+        }
+        self.content = content;
+        let due = self.written_at.is_none_or(|at| now.duration_since(at) >= self.interval);
+        if !due || self.content.is_empty() {
+            return None;
+        }
+        self.written_at = Some(now);
+        Some(&self.content)
+    }
+
+    fn is_written(&self) -> bool {
+        self.written_at.is_some()
+    }
+}
+
+/// Finish reason, tool errors, token usage, and model seen during one prompt.
+#[derive(Default)]
+struct TurnStats {
+    finish_reason: Option<FinishReason>,
+    tool_errored: bool,
+    model: Option<String>,
+    /// Earliest start and latest end of the turn's assistant messages, in
+    /// epoch milliseconds.
+    started_at: Option<f64>,
+    finished_at: Option<f64>,
+    /// Keyed by part ID, since OpenCode can re-send a part when it updates.
+    step_tokens: HashMap<String, TokenUsage>,
+}
+
+impl TurnStats {
+    fn observe(&mut self, event: &SseEvent, session_id: &str, sessions: &SessionRegistry) {
+        if let SseEvent::MessageUpdated { info: Some(info) } = event {
+            if info.role == "assistant" && info.session_id.as_deref() == Some(session_id) {
+                if let Some(model) = info.model() {
+                    self.model = Some(model);
+                }
+                if let Some(time) = &info.time {
+                    if let Some(start) = time.start {
+                        self.started_at = Some(self.started_at.map_or(start, |earliest| earliest.min(start)));
+                    }
+                    if let Some(end) = time.end {
+                        self.finished_at = Some(self.finished_at.map_or(end, |latest| latest.max(end)));
+                    }
+                }
+            }
+            return;
+        }
+        let SseEvent::MessagePartUpdated { part, .. } = event else {
+            return;
+        };
+        match part {
+            Part::StepFinish { id, session_id: Some(part_session), reason, tokens, .. } if part_session == session_id => {
+                if let Some(reason) = reason {
+                    self.finish_reason = Some(FinishReason::from(reason.as_str()));
+                }
+                if let Some(tokens) = tokens {
+                    self.step_tokens.insert(id.clone(), *tokens);
+                }
+            }
+            Part::Tool { session_id: Some(part_session), state: Some(state), .. }
+                if state.is_error()
+                    && (part_session == session_id || sessions.is_descendant_of(part_session, session_id)) =>
+            {
+                self.tool_errored = true;
+            }
+            _ => {}
+        }
+    }
+
+    fn into_outcome(self, text: String) -> TurnOutcome {
+        let tokens = (!self.step_tokens.is_empty()).then(|| {
+            self.step_tokens.into_values().fold(TokenUsage::default(), |mut total, step| {
+                total += step;
+                total
+            })
+        });
+        let span = TimeSpan { start: self.started_at, end: self.finished_at };
+        TurnOutcome {
+            text,
+            finish_reason: self.finish_reason,
+            tool_errored: self.tool_errored,
+            tokens,
+            model: self.model,
+            duration_ms: span.duration_ms().filter(|ms| *ms >= 0.0).map(|ms| ms.round() as u64),
+        }
+    }
+}
+
+/// Move follow-ups already waiting on `input_rx` into `queue`, then wait out
+/// its coalescing window for more. Stops early once waiting can't change the
+/// next batch, or the input closes.
+async fn collect_follow_ups(input_rx: &mut mpsc::Receiver<WorkerInput>, queue: &mut PromptQueue) {
+    while let Ok(follow_up) = input_rx.try_recv() {
+        queue.push(follow_up);
+    }
+    let deadline = Instant::now() + queue.window();
+    while !queue.is_full() {
+        match tokio::time::timeout_at(deadline, input_rx.recv()).await {
+            Ok(Some(follow_up)) => queue.push(follow_up),
+            Ok(None) | Err(_) => break,
+        }
+    }
+}
+
+/// Wait for the next follow-up message. Pends forever without an input channel.
+
+async fn next_input(input_rx: &mut Option<&mut mpsc::Receiver<WorkerInput>>) -> Option<WorkerInput> {
+    match input_rx {
+        Some(input_rx) => input_rx.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Sleep until `deadline`. Pends forever without one.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
 /// Result of processing a single SSE event.
 enum EventAction {
     Continue,
     Complete,
-    Error(String),
+    Error {
+        kind: SessionErrorKind,
+        message: String,
+    },
+}
+
+/// Decode an SSE response body into events, handing each raw envelope to
+/// the recorder first, if there is one.
+fn sse_events(
+    response: reqwest::Response,
+    recorder: Option<(Arc<SseRecorder>, ChannelId)>,
+) -> BoxStream<'static, anyhow::Result<SseEvent>> {
+    let chunks = Box::pin(response.bytes_stream());
+    futures::stream::unfold((chunks, String::new(), recorder), |(mut chunks, mut buffer, recorder)| async move {
+        loop {
+            if let Some(envelope) = extract_sse_envelope(&mut buffer) {
+                if let Some((recorder, channel_id)) = &recorder {
+                    recorder.record(channel_id, &envelope);
+                }
+                let event = SseEvent::from_envelope(envelope);
+                return Some((Ok(event), (chunks, buffer, recorder)));
+            }
+            match chunks.next().await? {
+                Ok(bytes) => buffer.push_str(&String::from_utf8_lossy(&bytes)),
+                Err(error) => {
+                    let error = anyhow::Error::new(error).context("failed to read SSE chunk");
+                    return Some((Err(error), (chunks, buffer, recorder)));
+                }
+            }
+        }
+    })
+    .boxed()
 }
 
-/// Parse an SSE event from a buffer. Parses the `{ type, properties }` envelope
-/// and converts to our `SseEvent` enum. Returns None if no complete event is available.
-fn extract_sse_event(buffer: &mut String) -> Option<SseEvent> {
+/// Parse an SSE event's `{ type, properties }` envelope from a buffer.
+/// Returns None if no complete event is available.
+fn extract_sse_envelope(buffer: &mut String) -> Option<SseEventEnvelope> {
     // SSE format: lines starting with "data: " followed by JSON, terminated by
     // a blank line. We may also see "event:" and "id:" lines which we ignore.
     loop {
@@ -536,7 +2361,7 @@ fn extract_sse_event(buffer: &mut String) -> Option<SseEvent> {
 
         // Parse the envelope first, then convert to our event type
         match serde_json::from_str::<SseEventEnvelope>(&json_str) {
-            Ok(envelope) => return Some(SseEvent::from_envelope(envelope)),
+            Ok(envelope) => return Some(envelope),
             Err(error) => {
                 tracing::trace!(
                     %error,
@@ -550,10 +2375,297 @@ fn extract_sse_event(buffer: &mut String) -> Option<SseEvent> {
 }
 
 /// Parse a model string like "anthropic/claude-sonnet-4-20250514" into a ModelParam.
-fn parse_model_param(model: &str) -> Option<ModelParam> {
+pub(crate) fn parse_model_param(model: &str) -> Option<ModelParam> {
     let (provider, model_id) = model.split_once('/')?;
     Some(ModelParam {
         provider_id: provider.to_string(),
         model_id: model_id.to_string(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn worker() -> OpenCodeWorker {
+        let (event_tx, _) = broadcast::channel(8);
+        OpenCodeWorker::new(
+            Some(ChannelId::new("discord:1:2").unwrap()),
+            Arc::from("main"),
+            "fix the build",
+            PathBuf::from("/tmp"),
+            Arc::new(OpenCodeServerPool::new("opencode", PermissionProfiles::default(), 1)),
+            event_tx,
+        )
+    }
+
+    #[test]
+    fn test_build_prompt() {
+        let worker = worker()
+            .with_system_prompt("be terse")
+            .with_model("anthropic/claude-sonnet-4-20250514");
+
+        let request = serde_json::to_value(worker.build_prompt("run the tests", "key-1".into())).unwrap();
+        assert_eq!(request["parts"][0]["type"], "text");
+        assert_eq!(request["parts"][0]["text"], "run the tests");
+        assert_eq!(request["system"], "be terse");
+        assert_eq!(request["model"]["providerId"], "anthropic");
+        assert_eq!(request["model"]["modelId"], "claude-sonnet-4-20250514");
+    }
+
+    #[test]
+    fn test_build_prompt_with_channel_context() {
+        let worker = worker().with_channel_context(ChannelContext::new(Some("#infra".into()), vec!["alice".into()]));
+
+        let request = worker.build_prompt("run the tests", "key-1".into());
+        assert_eq!(request.parts.len(), 2);
+        assert!(matches!(
+            &request.parts[0],
+            PartInput::Text { text, synthetic: Some(true) } if text.contains("Channel: #infra")
+        ));
+        assert!(matches!(&request.parts[1], PartInput::Text { text, synthetic: None } if text == "run the tests"));
+    }
+
+    #[tokio::test]
+    async fn test_replay_from_file() {
+        let (event_tx, mut event_rx) = broadcast::channel(64);
+        let mut worker = worker();
+        worker.event_tx = event_tx;
+        let recording = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay/turn.jsonl"));
+
+        let outcome = worker.replay_from_file(recording, "ses_3b1f6c2a8ffe").await.unwrap();
+        assert_eq!(outcome.text, "The tests pass now.");
+        assert_eq!(outcome.finish_reason, Some(FinishReason::Stop));
+        assert_eq!(outcome.tokens.map(|tokens| tokens.output), Some(388));
+        assert_eq!(outcome.model.as_deref(), Some("anthropic/claude-sonnet-4-5"));
+        assert!(!outcome.tool_errored);
+
+        let mut statuses = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            if let ProcessEvent::WorkerStatus { status, .. } = event {
+                statuses.push(status);
+            }
+        }
+        assert!(statuses.iter().any(|status| status == "running: Run the test suite"));
+
+        // Replaying it for another session never sees the turn finish.
+        assert!(worker.replay_from_file(recording, "ses_other").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_foreign_session_events_are_not_counted() {
+        let mut worker = worker();
+        // A channel of its own, since the metrics are process-wide.
+        worker.channel_id = Some(ChannelId::new("test:foreign-session-metrics").unwrap());
+        let fixture = std::fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay/turn.jsonl")).unwrap();
+        let (turn, idle) = fixture.trim_end().rsplit_once('\n').unwrap();
+        let foreign = r#"{"type":"session.status","properties":{"sessionID":"ses_other","status":{"type":"busy"}}}"#;
+        let recording = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(recording.path(), format!("{turn}\n{foreign}\n{idle}\n")).unwrap();
+
+        worker.replay_from_file(recording.path(), "ses_3b1f6c2a8ffe").await.unwrap();
+        let rendered = metrics::global().render();
+        assert!(rendered.contains(
+            "spacebot_opencode_events_total{event=\"session.status\",channel=\"test:foreign-session-metrics\"} 1"
+        ), "{rendered}");
+        assert!(!rendered.contains(
+            "spacebot_opencode_events_total{event=\"unknown\",channel=\"test:foreign-session-metrics\"}"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_follow_up_interrupts_the_turn() {
+        let aborted = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let app = axum::Router::new().route(
+            "/session/{id}/abort",
+            axum::routing::post({
+                let aborted = aborted.clone();
+                move || async move {
+                    aborted.store(true, std::sync::atomic::Ordering::SeqCst);
+                    axum::Json(true)
+                }
+            }),
+        );
+        let server = Arc::new(Mutex::new(crate::opencode::server::tests::mock_server(app).await));
+        let logger = ConversationLogger::new(crate::db::connect_in_memory().await);
+        let worker = worker()
+            .with_follow_up_mode(FollowUpMode::Abort)
+            .with_conversation_logger(logger.clone());
+        let channel_id = worker.channel_id.clone().unwrap();
+
+        // The turn streams its reply up to the text part, then stalls. The
+        // correction arrives only once every event has been handled.
+        let recording = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay/turn.jsonl"));
+        let events: Vec<anyhow::Result<SseEvent>> = read_recording(recording).unwrap().into_iter().take(7).map(Ok).collect();
+        let (input_tx, mut input_rx) = mpsc::channel(1);
+        let correction = futures::stream::once(async move {
+            input_tx.send(WorkerInput::from("only run the unit tests")).await.unwrap();
+        })
+        .filter_map(|()| async { None });
+        let events = futures::stream::iter(events).chain(correction).chain(futures::stream::pending()).boxed();
+
+        worker.sessions.register_root("ses_3b1f6c2a8ffe", worker.channel_id.clone());
+        let end = worker
+            .process_events(
+                events,
+                "ses_3b1f6c2a8ffe",
+                &server,
+                Some(&mut input_rx),
+                &mut PromptQueue::default(),
+                &CancellationToken::new(),
+                &mut None,
+            )
+            .await
+            .unwrap();
+        let TurnEnd::Interrupted { partial_text, next_message } = end else {
+            panic!("expected the turn to be interrupted");
+        };
+        assert_eq!(partial_text, "The tests pass now.");
+        assert_eq!(next_message.text, "only run the unit tests");
+
+        // The prompt is aborted, and the partial reply kept, flagged as interrupted.
+        worker
+            .interrupt_turn(&server, "ses_3b1f6c2a8ffe", &partial_text, false, "interrupted by new message")
+            .await;
+        assert!(aborted.load(std::sync::atomic::Ordering::SeqCst));
+        let mut messages = Vec::new();
+        for _ in 0..50 {
+            messages = logger.load_recent(&channel_id, 10, true).await.unwrap();
+            if !messages.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "The tests pass now.");
+        assert!(messages[0].metadata.as_deref().unwrap().contains("\"interrupted\":true"));
+    }
+
+    #[tokio::test]
+    async fn test_handler_answers_permission_before_the_built_in_flow() {
+        let replies = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let app = axum::Router::new().route(
+            "/permission/{id}/reply",
+            axum::routing::post({
+                let replies = replies.clone();
+                move |axum::extract::Path(id): axum::extract::Path<String>,
+                      axum::Json(body): axum::Json<serde_json::Value>| async move {
+                    replies.lock().unwrap().push((id, body["reply"].as_str().unwrap_or_default().to_string()));
+                    axum::Json(true)
+                }
+            }),
+        );
+        let server = Arc::new(Mutex::new(crate::opencode::server::tests::mock_server(app).await));
+        let (event_tx, mut event_rx) = broadcast::channel(64);
+        let mut worker = worker().with_event_handler(Arc::new(crate::opencode::handler::tests::Locked));
+        worker.event_tx = event_tx;
+
+        // Left to the worker, the default mode would approve this once.
+        let permission: PermissionRequest = serde_json::from_value(serde_json::json!({
+            "id": "per_1",
+            "sessionID": "ses_3b1f6c2a8ffe",
+            "permission": "bash",
+            "patterns": ["rm -rf build"],
+        }))
+        .unwrap();
+        let recording = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay/turn.jsonl"));
+        let mut events = read_recording(recording).unwrap();
+        events.insert(4, SseEvent::PermissionAsked(permission));
+        let events = futures::stream::iter(events.into_iter().map(Ok)).boxed();
+
+        worker.sessions.register_root("ses_3b1f6c2a8ffe", worker.channel_id.clone());
+        worker
+            .process_events(
+                events,
+                "ses_3b1f6c2a8ffe",
+                &server,
+                None,
+                &mut PromptQueue::default(),
+                &CancellationToken::new(),
+                &mut None,
+            )
+            .await
+            .unwrap();
+
+        // The handler's reply is the only one sent, and the permission is
+        // never announced to the channel.
+        assert_eq!(*replies.lock().unwrap(), [("per_1".to_string(), "reject".to_string())]);
+        while let Ok(event) = event_rx.try_recv() {
+            assert!(!matches!(event, ProcessEvent::WorkerPermission { .. }));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_error_is_reported_once_per_call() {
+        let (event_tx, mut event_rx) = broadcast::channel(64);
+        let mut worker = worker();
+        worker.event_tx = event_tx;
+        let recording = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay/tool-error.jsonl"));
+
+        let outcome = worker.replay_from_file(recording, "ses_3b1f6c2a8ffe").await.unwrap();
+        assert!(outcome.tool_errored);
+
+        let mut errors = Vec::new();
+        while let Ok(event) = event_rx.try_recv() {
+            if let ProcessEvent::WorkerToolError { tool, call_id, error, .. } = event {
+                errors.push((tool, call_id, error));
+            }
+        }
+        assert_eq!(
+            errors,
+            [("read".to_string(), "toolu_01Rd7mKp".to_string(), "Error: File not found: /code/app/missing.rs".to_string())]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idle_for_other_sessions_does_not_end_turn() {
+        let worker = worker();
+        let recording = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/replay/orphan-idle.jsonl"));
+
+        // Idle events for a stale session and for a sub-agent arrive
+        // mid-turn; only the root session's idle completes it.
+        let outcome = worker.replay_from_file(recording, "ses_3b1f6c2a8ffe").await.unwrap();
+        assert_eq!(outcome.text, "The sub-agent fixed the build.");
+    }
+
+    #[test]
+    fn test_part_positions_follow_arrival_order() {
+        let mut positions = PartPositions::default();
+        assert_eq!(positions.next("msg_1", "prt_a"), (0, 0));
+        assert_eq!(positions.next("msg_1", "prt_b"), (1, 0));
+        // Updates keep the part's place and bump its revision.
+        assert_eq!(positions.next("msg_1", "prt_a"), (0, 1));
+        assert_eq!(positions.next("msg_2", "prt_c"), (0, 0));
+        assert_eq!(positions.next("msg_1", "prt_d"), (2, 0));
+    }
+
+    #[test]
+    fn test_reply_checkpoints_are_throttled() {
+        let start = Instant::now();
+        let mut checkpoint = ReplyCheckpoint::new(Duration::from_secs(2));
+        assert_eq!(checkpoint.update(String::new(), start), None);
+        assert!(!checkpoint.is_written());
+
+        // The first text is written at once, then at most every interval.
+        assert_eq!(checkpoint.update("Looking".into(), start), Some("Looking"));
+        assert_eq!(checkpoint.update("Looking at".into(), start + Duration::from_secs(1)), None);
+        assert_eq!(
+            checkpoint.update("Looking at it".into(), start + Duration::from_secs(3)),
+            Some("Looking at it")
+        );
+        // Unchanged content is never rewritten.
+        assert_eq!(checkpoint.update("Looking at it".into(), start + Duration::from_secs(9)), None);
+        // What was held back is kept for the final write.
+        assert_eq!(checkpoint.update("Looking at it now".into(), start + Duration::from_secs(10)), Some("Looking at it now"));
+        assert_eq!(checkpoint.update("Looking at it now.".into(), start + Duration::from_secs(11)), None);
+        assert_eq!(checkpoint.content, "Looking at it now.");
+        assert!(checkpoint.is_written());
+    }
+
+    #[tokio::test]
+    async fn test_dry_run_echoes_prompt_without_a_server() {
+        let result = worker().with_dry_run(true).run().await.unwrap();
+        assert!(result.result_text.starts_with("[dry run]"));
+        assert!(result.result_text.contains("fix the build"));
+    }
+}
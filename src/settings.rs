@@ -1,5 +1,7 @@
 //! Key-value settings storage.
 
+pub mod channels;
 pub mod store;
 
+pub use channels::ChannelSettings;
 pub use store::{SettingsStore, WorkerLogMode, WORKER_LOG_MODE_KEY};
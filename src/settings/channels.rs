@@ -0,0 +1,189 @@
+//! Per-channel settings (SQLite).
+//!
+//! A generic key-value store for anything a channel configures about
+//! itself, with JSON values read and written through typed getters and
+//! setters. Reads are cached in memory, misses included, so a setting
+//! checked on every message costs one query per channel. Clones share the
+//! cache; writes through any clone keep it current.
+
+use crate::error::{Result, SettingsError};
+use crate::ChannelId;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use sqlx::SqlitePool;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+/// Cached values by channel, then key. `None` caches a missing setting.
+type Cache = HashMap<String, HashMap<String, Option<serde_json::Value>>>;
+
+/// Reads and writes settings for any channel.
+#[derive(Debug, Clone)]
+pub struct ChannelSettings {
+    pool: SqlitePool,
+    cache: Arc<RwLock<Cache>>,
+}
+
+impl ChannelSettings {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// The channel's value for `key`, or `None` if it isn't set.
+    pub async fn get_setting<T: DeserializeOwned>(&self, channel_id: &ChannelId, key: &str) -> Result<Option<T>> {
+        let Some(value) = self.get_value(channel_id, key).await? else {
+            return Ok(None);
+        };
+        let value = serde_json::from_value(value).map_err(|error| SettingsError::ReadFailed {
+            key: key.to_string(),
+            details: error.to_string(),
+        })?;
+        Ok(Some(value))
+    }
+
+    /// Set the channel's value for `key`, replacing any earlier one.
+    pub async fn set_setting<T: Serialize>(&self, channel_id: &ChannelId, key: &str, value: &T) -> Result<()> {
+        let write_failed = |details: String| SettingsError::WriteFailed {
+            key: key.to_string(),
+            details,
+        };
+        let value = serde_json::to_value(value).map_err(|error| write_failed(error.to_string()))?;
+        sqlx::query(
+            "INSERT INTO channel_settings (channel_id, key, value, updated_at) \
+             VALUES (?, ?, ?, CURRENT_TIMESTAMP) \
+             ON CONFLICT(channel_id, key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP",
+        )
+        .bind(channel_id.as_ref())
+        .bind(key)
+        .bind(value.to_string())
+        .execute(&self.pool)
+        .await
+        .map_err(|error| write_failed(error.to_string()))?;
+
+        self.cache_value(channel_id, key, Some(value));
+        Ok(())
+    }
+
+    /// Unset `key` for the channel. Returns whether it was set.
+    pub async fn clear_setting(&self, channel_id: &ChannelId, key: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM channel_settings WHERE channel_id = ? AND key = ?")
+            .bind(channel_id.as_ref())
+            .bind(key)
+            .execute(&self.pool)
+            .await
+            .map_err(|error| SettingsError::WriteFailed {
+                key: key.to_string(),
+                details: error.to_string(),
+            })?;
+
+        self.cache_value(channel_id, key, None);
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Every setting of the channel, by key. Read from the database, not
+    /// the cache.
+    pub async fn list_settings(&self, channel_id: &ChannelId) -> Result<Vec<(String, serde_json::Value)>> {
+        let rows: Vec<(String, String)> =
+            sqlx::query_as("SELECT key, value FROM channel_settings WHERE channel_id = ? ORDER BY key")
+                .bind(channel_id.as_ref())
+                .fetch_all(&self.pool)
+                .await
+                .map_err(|error| SettingsError::Other(format!("failed to list channel settings: {error}")))?;
+
+        Ok(rows
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, serde_json::from_str(&value).ok()?)))
+            .collect())
+    }
+
+    async fn get_value(&self, channel_id: &ChannelId, key: &str) -> Result<Option<serde_json::Value>> {
+        let cached = self
+            .cache
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(channel_id.as_ref())
+            .and_then(|settings| settings.get(key))
+            .cloned();
+        if let Some(value) = cached {
+            return Ok(value);
+        }
+
+        let read_failed = |details: String| SettingsError::ReadFailed {
+            key: key.to_string(),
+            details,
+        };
+        let raw: Option<String> =
+            sqlx::query_scalar("SELECT value FROM channel_settings WHERE channel_id = ? AND key = ?")
+                .bind(channel_id.as_ref())
+                .bind(key)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(|error| read_failed(error.to_string()))?;
+        let value = raw
+            .map(|raw| serde_json::from_str(&raw))
+            .transpose()
+            .map_err(|error| read_failed(error.to_string()))?;
+
+        self.cache_value(channel_id, key, value.clone());
+        Ok(value)
+    }
+
+    fn cache_value(&self, channel_id: &ChannelId, key: &str, value: Option<serde_json::Value>) {
+        self.cache
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(channel_id.to_string())
+            .or_default()
+            .insert(key.to_string(), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::connect_in_memory;
+
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Limits {
+        max_chars: usize,
+        overflow: String,
+    }
+
+    #[tokio::test]
+    async fn test_typed_settings_round_trip() {
+        let pool = connect_in_memory().await;
+        let settings = ChannelSettings::new(pool.clone());
+        let channel_id: ChannelId = "discord:1:2".parse().unwrap();
+
+        assert_eq!(settings.get_setting::<String>(&channel_id, "model").await.unwrap(), None);
+        settings.set_setting(&channel_id, "model", &"anthropic/claude-sonnet-4-5").await.unwrap();
+        let limits = Limits { max_chars: 8000, overflow: "reject".into() };
+        settings.set_setting(&channel_id, "limits", &limits).await.unwrap();
+
+        // A fresh store reads what the first wrote.
+        let reopened = ChannelSettings::new(pool);
+        assert_eq!(
+            reopened.get_setting::<String>(&channel_id, "model").await.unwrap().as_deref(),
+            Some("anthropic/claude-sonnet-4-5")
+        );
+        assert_eq!(reopened.get_setting::<Limits>(&channel_id, "limits").await.unwrap(), Some(limits));
+        // Asking for the wrong type is an error, not a silent default.
+        assert!(reopened.get_setting::<u64>(&channel_id, "model").await.is_err());
+
+        let keys: Vec<String> = reopened.list_settings(&channel_id).await.unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, ["limits", "model"]);
+
+        // Clones share the cache, so a cleared setting reads as unset everywhere.
+        let clone = reopened.clone();
+        assert!(clone.clear_setting(&channel_id, "model").await.unwrap());
+        assert!(!clone.clear_setting(&channel_id, "model").await.unwrap());
+        assert_eq!(reopened.get_setting::<String>(&channel_id, "model").await.unwrap(), None);
+
+        let other: ChannelId = "discord:1:3".parse().unwrap();
+        assert_eq!(reopened.get_setting::<Limits>(&other, "limits").await.unwrap(), None);
+    }
+}
@@ -1,6 +1,6 @@
 //! Cron job management tool for creating, listing, and deleting scheduled tasks.
 
-use crate::cron::scheduler::{CronConfig, Scheduler};
+use crate::cron::scheduler::{CronConfig, Scheduler, cron_channel_id};
 use crate::cron::store::CronStore;
 use rig::completion::ToolDefinition;
 use rig::tool::Tool;
@@ -91,7 +91,7 @@ impl Tool for CronTool {
                     },
                     "id": {
                         "type": "string",
-                        "description": "For 'create': a short unique ID (e.g. 'check-email', 'daily-summary')."
+                        "description": "For 'create': a short unique ID of ASCII letters, digits and '_-.@' (e.g. 'check-email', 'daily-summary')."
                     },
                     "prompt": {
                         "type": "string",
@@ -140,6 +140,7 @@ impl Tool for CronTool {
 impl CronTool {
     async fn create(&self, args: CronArgs) -> Result<CronOutput, CronError> {
         let id = args.id.ok_or_else(|| CronError("'id' is required for create".into()))?;
+        cron_channel_id(&id).map_err(|error| CronError(format!("invalid cron job ID '{id}': {error}")))?;
         let prompt = args
             .prompt
             .ok_or_else(|| CronError("'prompt' is required for create".into()))?;
@@ -185,7 +185,9 @@ impl Tool for MemorySaveTool {
         }
 
         if let Some(channel_id) = args.channel_id {
-            memory = memory.with_channel_id(Arc::from(channel_id.as_str()));
+            let channel_id = crate::ChannelId::new(&channel_id)
+                .map_err(|e| MemorySaveError(format!("Invalid channel_id: {e}")))?;
+            memory = memory.with_channel_id(channel_id);
         }
 
         // Save to SQLite database
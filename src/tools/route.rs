@@ -1,7 +1,7 @@
 //! Route tool for sending follow-ups to active workers.
 
-use crate::agent::channel::ChannelState;
-use crate::WorkerId;
+use crate::agent::channel::{ChannelState, attribute_prompt};
+use crate::{WorkerId, WorkerInput};
 use rig::completion::ToolDefinition;
 use rig::tool::Tool;
 use schemars::JsonSchema;
@@ -86,8 +86,18 @@ impl Tool for RouteTool {
             .clone();
         drop(inputs);
 
-        // Deliver the message
-        input_tx.send(args.message).await
+        // Builtin workers are listed in `active_workers`; the rest are
+        // OpenCode workers, whose prompts carry sender attribution.
+        let is_builtin = self.state.active_workers.read().await.contains_key(&worker_id);
+        let message = if is_builtin {
+            args.message
+        } else {
+            attribute_prompt(&self.state, args.message).await
+        };
+
+        // Deliver the message, keyed by the user message that prompted it
+        let message_key = self.state.pending_message_key.read().await.clone();
+        input_tx.send(WorkerInput { text: message, message_key }).await
             .map_err(|_| RouteError(format!(
                 "Worker {worker_id} has stopped accepting input (channel closed)"
             )))?;
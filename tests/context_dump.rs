@@ -164,7 +164,7 @@ async fn dump_channel_context() {
     // Build the actual channel tool server with real tools registered
     let conversation_logger = spacebot::conversation::ConversationLogger::new(deps.sqlite_pool.clone());
     let channel_store = spacebot::conversation::ChannelStore::new(deps.sqlite_pool.clone());
-    let channel_id: spacebot::ChannelId = Arc::from("test-channel");
+    let channel_id: spacebot::ChannelId = "test-channel".parse().unwrap();
     let status_block = Arc::new(tokio::sync::RwLock::new(spacebot::agent::status::StatusBlock::new()));
     let (response_tx, _response_rx) = tokio::sync::mpsc::channel(16);
 
@@ -352,7 +352,7 @@ async fn dump_all_contexts() {
     // ── Channel ──
     let channel_prompt = build_channel_system_prompt(rc);
 
-    let channel_id: spacebot::ChannelId = Arc::from("test-channel");
+    let channel_id: spacebot::ChannelId = "test-channel".parse().unwrap();
     let (response_tx, _response_rx) = tokio::sync::mpsc::channel(16);
     let state = spacebot::agent::channel::ChannelState {
         channel_id,
@@ -0,0 +1,206 @@
+//! Parse golden OpenCode SSE payloads from `tests/fixtures/opencode`.
+//!
+//! Each fixture is one event envelope as a live OpenCode server sends it,
+//! named `<event type>[.<variant>].json`. When OpenCode changes a payload
+//! shape, capture the new one here and fix `types.rs` until this passes.
+
+use spacebot::opencode::types::*;
+
+use std::path::Path;
+
+fn fixtures_dir() -> &'static Path {
+    Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/opencode"))
+}
+
+fn fixture(name: &str) -> SseEvent {
+    let path = fixtures_dir().join(format!("{name}.json"));
+    let json = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", path.display()));
+    let envelope: SseEventEnvelope = serde_json::from_str(&json)
+        .unwrap_or_else(|e| panic!("failed to parse envelope {}: {e}", path.display()));
+    SseEvent::from_envelope(envelope)
+}
+
+fn part(name: &str) -> Part {
+    match fixture(name) {
+        SseEvent::MessagePartUpdated { part, .. } => part,
+        other => panic!("{name}: expected MessagePartUpdated, got {other:?}"),
+    }
+}
+
+fn tool_state(name: &str) -> ToolState {
+    match part(name) {
+        Part::Tool { state: Some(state), .. } => state,
+        other => panic!("{name}: expected Part::Tool with a state, got {other:?}"),
+    }
+}
+
+#[test]
+fn every_fixture_parses_into_its_event_type() {
+    let mut names: Vec<String> = std::fs::read_dir(fixtures_dir())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .filter_map(|file| file.strip_suffix(".json").map(str::to_string))
+        .collect();
+    names.sort();
+    assert!(!names.is_empty());
+
+    for name in names {
+        let event = fixture(&name);
+        if let SseEvent::Unknown(ref raw) = event {
+            assert!(!raw.contains("parse error"), "{name}: {raw}");
+            assert_eq!(raw, &name, "{name}: only unmodeled events may be Unknown");
+            continue;
+        }
+        // `message.part.updated.tool-completed` -> `message.part.updated`
+        let expected_type = event.event_type();
+        assert!(
+            name == expected_type || name.starts_with(&format!("{expected_type}.")),
+            "{name}: parsed as {expected_type}"
+        );
+    }
+}
+
+#[test]
+fn message_updated_fixtures() {
+    let SseEvent::MessageUpdated { info: Some(user) } = fixture("message.updated.user") else {
+        panic!("expected user message info");
+    };
+    assert_eq!(user.role, "user");
+    assert_eq!(user.session_id.as_deref(), Some("ses_3b1f6c2a8ffe"));
+    assert_eq!(user.time.as_ref().and_then(|t| t.start), Some(1770927523031.0));
+    assert_eq!(user.model(), None);
+
+    let SseEvent::MessageUpdated { info: Some(assistant) } = fixture("message.updated.assistant") else {
+        panic!("expected assistant message info");
+    };
+    assert_eq!(assistant.role, "assistant");
+    let time = assistant.time.unwrap();
+    assert_eq!(time.start, Some(1770927523033.0));
+    assert_eq!(time.end, Some(1770927531870.0));
+    assert_eq!(assistant.tokens.map(|tokens| tokens.output), Some(388));
+    assert_eq!(assistant.model().as_deref(), Some("anthropic/claude-sonnet-4-5"));
+}
+
+#[test]
+fn part_fixtures() {
+    let SseEvent::MessagePartUpdated { part, delta } = fixture("message.part.updated.text") else {
+        panic!("expected MessagePartUpdated");
+    };
+    assert_eq!(delta.as_deref(), Some("now."));
+    let Part::Text { text, session_id, message_id, time, .. } = part else {
+        panic!("expected Part::Text");
+    };
+    assert_eq!(text, "The tests pass now.");
+    assert_eq!(session_id.as_deref(), Some("ses_3b1f6c2a8ffe"));
+    assert_eq!(message_id.as_deref(), Some("msg_c4e0936a1001"));
+    assert_eq!(time.and_then(|t| t.end), Some(1770927531850.0));
+
+    assert!(matches!(part("message.part.updated.reasoning"), Part::Other));
+    assert!(matches!(part("message.part.updated.step-start"), Part::StepStart { .. }));
+
+    let Part::StepFinish { reason, tokens, .. } = part("message.part.updated.step-finish") else {
+        panic!("expected Part::StepFinish");
+    };
+    assert_eq!(reason.as_deref().map(FinishReason::from), Some(FinishReason::Stop));
+    let tokens = tokens.unwrap();
+    assert_eq!((tokens.input, tokens.output, tokens.cache.read), (1203, 388, 11520));
+}
+
+#[test]
+fn tool_state_fixtures() {
+    let Part::Tool { call_id, tool, .. } = part("message.part.updated.tool-pending") else {
+        panic!("expected Part::Tool");
+    };
+    assert_eq!(call_id.as_deref(), Some("toolu_01Xb4nTq"));
+    assert_eq!(tool.as_deref(), Some("bash"));
+
+    assert_eq!(tool_state("message.part.updated.tool-pending").status_str(), "pending");
+
+    let running = tool_state("message.part.updated.tool-running");
+    assert!(running.is_running());
+    assert_eq!(running.bash_command(), Some("cargo test"));
+
+    let completed = tool_state("message.part.updated.tool-completed");
+    assert!(completed.is_completed());
+    assert_eq!(completed.output(), Some("test result: ok. 12 passed; 0 failed\n"));
+    assert_eq!(completed.bash_exit_code(), Some(0));
+
+    let errored = tool_state("message.part.updated.tool-error");
+    assert!(errored.is_error());
+    assert_eq!(errored.output(), Some("Error: File not found: /code/app/missing.rs"));
+}
+
+#[test]
+fn session_fixtures() {
+    let SseEvent::SessionCreated { info } = fixture("session.created") else {
+        panic!("expected SessionCreated");
+    };
+    assert_eq!(info.id, "ses_3b1f5e7d0ffe");
+    assert_eq!(info.parent_id.as_deref(), Some("ses_3b1f6c2a8ffe"));
+
+    assert!(matches!(
+        fixture("session.status.busy"),
+        SseEvent::SessionStatus { status: SessionStatusPayload::Busy, .. }
+    ));
+    assert!(matches!(
+        fixture("session.status.idle"),
+        SseEvent::SessionStatus { status: SessionStatusPayload::Idle, .. }
+    ));
+    let SseEvent::SessionStatus { status: SessionStatusPayload::Retry { attempt, message }, .. } =
+        fixture("session.status.retry")
+    else {
+        panic!("expected a retry status");
+    };
+    assert_eq!(attempt, 2);
+    assert_eq!(message.as_deref(), Some("Overloaded"));
+
+    let SseEvent::SessionError { session_id, error } = fixture("session.error") else {
+        panic!("expected SessionError");
+    };
+    assert_eq!(session_id.as_deref(), Some("ses_3b1f6c2a8ffe"));
+    let error = error.unwrap();
+    assert_eq!(classify_session_error(&error), SessionErrorKind::RateLimited);
+    assert_eq!(session_error_message(&error), Some("Rate limit exceeded"));
+}
+
+#[test]
+fn permission_and_question_fixtures() {
+    let SseEvent::PermissionAsked(request) = fixture("permission.asked") else {
+        panic!("expected PermissionAsked");
+    };
+    assert_eq!(request.id, "per_c4e097b10001");
+    assert_eq!(request.permission.as_deref(), Some("bash"));
+    assert_eq!(request.patterns, ["rm -rf target"]);
+    assert_eq!(request.always, ["rm *"]);
+
+    for (name, expected_reply) in [("permission.replied", "once"), ("permission.replied.legacy", "always")] {
+        let SseEvent::PermissionReplied { request_id, reply, .. } = fixture(name) else {
+            panic!("{name}: expected PermissionReplied");
+        };
+        assert_eq!(request_id, "per_c4e097b10001", "{name}");
+        assert_eq!(reply, expected_reply, "{name}");
+    }
+
+    let SseEvent::QuestionAsked(request) = fixture("question.asked") else {
+        panic!("expected QuestionAsked");
+    };
+    assert_eq!(request.questions.len(), 1);
+    let labels: Vec<_> = request.questions[0].options.iter().map(|o| o.label.as_str()).collect();
+    assert_eq!(labels, ["pnpm", "npm"]);
+
+    let SseEvent::QuestionReplied { request_id, answers, .. } = fixture("question.replied") else {
+        panic!("expected QuestionReplied");
+    };
+    assert_eq!(request_id, "que_c4e097c20001");
+    let labels: Vec<_> = answers.iter().map(|a| a.label.as_str()).collect();
+    assert_eq!(labels, ["pnpm"]);
+}
+
+#[test]
+fn installation_updated_fixture() {
+    let SseEvent::ServerInfo { version } = fixture("installation.updated") else {
+        panic!("expected ServerInfo");
+    };
+    assert_eq!(version.as_deref(), Some("1.1.36"));
+}
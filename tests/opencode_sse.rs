@@ -311,3 +311,125 @@ fn parse_tool_with_part_level_metadata() {
         other => panic!("expected MessagePartUpdated, got {other:?}"),
     }
 }
+
+#[test]
+fn parse_completed_edit_tool_diff() {
+    let event = parse_sse_line(
+        r#"data: {"type":"message.part.updated","properties":{"part":{"id":"prt_e","sessionID":"ses_y","messageID":"msg_z","type":"tool","callID":"call_2","tool":"edit","state":{"status":"completed","input":{"filePath":"/tmp/src/main.rs","oldString":"a","newString":"b"},"output":"","title":"src/main.rs","metadata":{"diff":"--- /tmp/src/main.rs\n+++ /tmp/src/main.rs\n@@ -1,2 +1,2 @@\n-a\n+b\n+c\n","filediff":{"file":"/tmp/src/main.rs","additions":2,"deletions":1}},"time":{"start":1770927526652,"end":1770927526700}}}}}"#,
+    );
+    let SseEvent::MessagePartUpdated { part, .. } = event else {
+        panic!("expected MessagePartUpdated");
+    };
+    let edit = part.edit_diff().expect("expected edit diff");
+    assert_eq!(edit.filename.as_deref(), Some("/tmp/src/main.rs"));
+    assert_eq!(edit.additions, 2);
+    assert_eq!(edit.removals, 1);
+    assert!(edit.diff.starts_with("--- /tmp/src/main.rs"));
+}
+
+#[test]
+fn edit_diff_counts_lines_without_filediff() {
+    let event = parse_sse_line(
+        r#"data: {"type":"message.part.updated","properties":{"part":{"id":"prt_e","sessionID":"ses_y","messageID":"msg_z","type":"tool","callID":"call_2","tool":"edit","state":{"status":"completed","input":{"filePath":"/tmp/a.txt"},"output":"","metadata":{"diff":"--- a.txt\n+++ a.txt\n-old\n-older\n+new\n"}}}}}"#,
+    );
+    let SseEvent::MessagePartUpdated { part, .. } = event else {
+        panic!("expected MessagePartUpdated");
+    };
+    let edit = part.edit_diff().expect("expected edit diff");
+    assert_eq!(edit.filename.as_deref(), Some("/tmp/a.txt"));
+    assert_eq!(edit.additions, 1);
+    assert_eq!(edit.removals, 2);
+}
+
+#[test]
+fn edit_diff_ignores_other_tools() {
+    let event = parse_sse_line(
+        r#"data: {"type":"message.part.updated","properties":{"part":{"id":"prt_x","sessionID":"ses_y","messageID":"msg_z","type":"tool","callID":"call_1","tool":"bash","state":{"status":"completed","input":{"command":"ls"},"output":"a\n","metadata":{"diff":"+a\n"}}}}}"#,
+    );
+    let SseEvent::MessagePartUpdated { part, .. } = event else {
+        panic!("expected MessagePartUpdated");
+    };
+    assert!(part.edit_diff().is_none());
+}
+
+#[test]
+fn parse_completed_bash_command_and_exit_code() {
+    let event = parse_sse_line(
+        r#"data: {"type":"message.part.updated","properties":{"part":{"id":"prt_b","sessionID":"ses_y","messageID":"msg_z","type":"tool","callID":"call_3","tool":"bash","state":{"status":"completed","input":{"command":"cargo test","description":"Run tests"},"output":"error: test failed\n","title":"cargo test","metadata":{"output":"error: test failed\n","exit":101,"description":"Run tests"},"time":{"start":1770927526652,"end":1770927529000}}}}}"#,
+    );
+    let SseEvent::MessagePartUpdated { part: Part::Tool { state, .. }, .. } = event else {
+        panic!("expected tool part");
+    };
+    let state = state.expect("expected state");
+    assert_eq!(state.bash_command(), Some("cargo test"));
+    assert_eq!(state.bash_exit_code(), Some(101));
+}
+
+#[test]
+fn bash_accessors_handle_missing_fields() {
+    let running: ToolState = serde_json::from_str(
+        r#"{"status":"running","input":{"command":"sleep 5"},"time":{"start":1}}"#,
+    )
+    .unwrap();
+    assert_eq!(running.bash_command(), Some("sleep 5"));
+    assert_eq!(running.bash_exit_code(), None);
+
+    let odd_input: ToolState = serde_json::from_str(
+        r#"{"status":"completed","input":{"command":["ls","-la"]},"output":"","metadata":{"exitCode":0}}"#,
+    )
+    .unwrap();
+    assert_eq!(odd_input.bash_command(), None);
+    assert_eq!(odd_input.bash_exit_code(), Some(0));
+
+    let no_input: ToolState = serde_json::from_str(r#"{"status":"pending"}"#).unwrap();
+    assert_eq!(no_input.bash_command(), None);
+}
+
+#[test]
+fn parse_message_removed() {
+    let event = parse_sse_line(
+        r#"data: {"type":"message.removed","properties":{"sessionID":"ses_456","messageID":"msg_789"}}"#,
+    );
+    match event {
+        SseEvent::MessageRemoved { session_id, message_id } => {
+            assert_eq!(session_id, "ses_456");
+            assert_eq!(message_id, "msg_789");
+        }
+        other => panic!("expected MessageRemoved, got {other:?}"),
+    }
+}
+
+#[test]
+fn parse_part_removed() {
+    let event = parse_sse_line(
+        r#"data: {"type":"message.part.removed","properties":{"sessionID":"ses_456","messageID":"msg_789","partID":"prt_abc"}}"#,
+    );
+    match event {
+        SseEvent::PartRemoved { session_id, message_id, part_id } => {
+            assert_eq!(session_id, "ses_456");
+            assert_eq!(message_id, "msg_789");
+            assert_eq!(part_id, "prt_abc");
+        }
+        other => panic!("expected PartRemoved, got {other:?}"),
+    }
+}
+
+#[test]
+fn parse_message_removed_missing_ids() {
+    let event = parse_sse_line(r#"data: {"type":"message.removed","properties":{"sessionID":"ses_456"}}"#);
+    assert!(matches!(event, SseEvent::Unknown(ref s) if s == "message.removed (parse error)"));
+}
+
+#[test]
+fn parse_child_session_created() {
+    let event = parse_sse_line(
+        r#"data: {"type":"session.created","properties":{"info":{"id":"ses_child","title":"explore","parentID":"ses_root"}}}"#,
+    );
+    match event {
+        SseEvent::SessionCreated { info } => {
+            assert_eq!(info.id, "ses_child");
+            assert_eq!(info.parent_id.as_deref(), Some("ses_root"));
+        }
+        other => panic!("expected SessionCreated, got {other:?}"),
+    }
+}